@@ -3,4 +3,85 @@ CREATE TABLE IF NOT EXISTS rust_dump (
     value TEXT
 );
 
-DELETE FROM rust_dump;
\ No newline at end of file
+-- created by the bot on startup as well
+CREATE TABLE IF NOT EXISTS csfloat_listings (
+    id TEXT PRIMARY KEY,
+    market_hash_name TEXT NOT NULL,
+    price BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS steam_analysis (
+    market_name TEXT PRIMARY KEY,
+    analysis TEXT,
+    order_book TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS sticker_prices (
+    name TEXT PRIMARY KEY,
+    price BIGINT NOT NULL
+);
+
+-- ledger of autobuy attempts, never cleared by the bot
+CREATE TABLE IF NOT EXISTS purchases (
+    id BIGSERIAL PRIMARY KEY,
+    listing_id TEXT NOT NULL,
+    market_hash_name TEXT NOT NULL,
+    paid_price BIGINT NOT NULL,
+    expected_steam_price BIGINT NOT NULL,
+    expected_profit BIGINT NOT NULL,
+    profit_pct DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    is_success BOOLEAN NOT NULL,
+    outcome TEXT NOT NULL,
+    is_paper BOOLEAN NOT NULL DEFAULT false,
+    -- paper purchases: still listed on the next refresh
+    is_available BOOLEAN,
+    -- listed on Steam by the steam seller, the price is after the Steam fee
+    steam_asset_id TEXT,
+    steam_list_price BIGINT,
+    steam_listed_at TIMESTAMPTZ,
+    -- float of the bought item, matches it in the Steam inventory
+    float_value DOUBLE PRECISION
+);
+
+-- manual sale entries, see /sold Telegram command
+CREATE TABLE IF NOT EXISTS sales (
+    id BIGSERIAL PRIMARY KEY,
+    market_hash_name TEXT NOT NULL,
+    price BIGINT NOT NULL,
+    sold_at TIMESTAMPTZ NOT NULL
+);
+
+-- notification rules, see /watch Telegram command
+CREATE TABLE IF NOT EXISTS watch_rules (
+    id BIGSERIAL PRIMARY KEY,
+    market_name TEXT NOT NULL,
+    max_price BIGINT NOT NULL,
+    max_float DOUBLE PRECISION
+);
+
+-- sellers blacklisted via the notification buttons
+CREATE TABLE IF NOT EXISTS seller_blacklist (steam_id TEXT PRIMARY KEY);
+
+CREATE TABLE IF NOT EXISTS item_mutes (
+    id BIGSERIAL PRIMARY KEY,
+    market_name TEXT,
+    listing_id TEXT,
+    until TIMESTAMPTZ
+);
+
+-- write-ahead log of primary events, replayed on startup
+CREATE TABLE IF NOT EXISTS event_log (
+    seq BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    event TEXT NOT NULL
+);
+
+DELETE FROM rust_dump;
+DELETE FROM csfloat_listings;
+DELETE FROM steam_analysis;
+DELETE FROM sticker_prices;
+DELETE FROM event_log;
\ No newline at end of file
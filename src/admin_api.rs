@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::{
+    config::{AppConfig, SharedConfig},
+    csfloat::{CsfloatScheduler, QueuedListing},
+    dashboard::{get_dashboard, get_events, get_status, DealFeed},
+    models::CsfloatListingStruct,
+    queues::QueueSenders,
+    stats::{Stats, StatsSnapshot},
+    stats_history::{load_buckets, Bucket, StatsPoint},
+    steam_analyzer::AnalysisResult,
+    steam_orders::SteamOrderBook,
+    storages::{CsfloatEngine, SteamEngine},
+    types::MarketName,
+};
+
+const SCHEDULER_QUEUE_LIMIT: usize = 100;
+const STATS_HISTORY_DAYS: i64 = 14;
+
+// Read-only views of the in-memory engines, plus config replacement and the dashboard.
+// Requests need `Authorization: Bearer <ADMIN_API_TOKEN>` when the token is set,
+// or `?token=<ADMIN_API_TOKEN>` since the dashboard's EventSource can't send headers.
+#[derive(Clone)]
+pub struct AdminState {
+    pub csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    pub steam_engine: Arc<Mutex<SteamEngine>>,
+    pub csfloat_scheduler: Arc<Mutex<CsfloatScheduler>>,
+    pub stats: Arc<Mutex<Stats>>,
+    pub config: SharedConfig,
+    pub pool: Pool<Postgres>,
+    pub deal_feed: DealFeed,
+    pub queues: QueueSenders,
+    pub token: Option<String>,
+}
+
+pub fn build_router(state: AdminState) -> Router {
+    Router::new()
+        .route("/", get(get_dashboard))
+        .route("/events", get(get_events))
+        .route("/status", get(get_status))
+        .route("/listings", get(get_listings))
+        .route("/analysis/:market_name", get(get_analysis))
+        .route("/scheduler/queue", get(get_scheduler_queue))
+        .route("/stats", get(get_stats))
+        .route("/stats/history", get(get_stats_history))
+        .route("/config", post(post_config))
+        .layer(middleware::from_fn_with_state(state.clone(), check_token))
+        .with_state(state)
+}
+
+async fn check_token(State(state): State<AdminState>, request: Request, next: Next) -> Response {
+    if let Some(token) = &state.token {
+        let expected = format!("Bearer {}", token);
+        let is_authorized = request
+            .headers()
+            .get(AUTHORIZATION)
+            .is_some_and(|x| x.as_bytes() == expected.as_bytes())
+            || get_query_token(request.uri().query()) == Some(token.as_str());
+        if !is_authorized {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+    next.run(request).await
+}
+
+fn get_query_token(query: Option<&str>) -> Option<&str> {
+    query?
+        .split('&')
+        .find_map(|x| x.strip_prefix("token="))
+        .filter(|x| !x.is_empty())
+}
+
+#[derive(Deserialize)]
+pub struct ListingsQuery {
+    pub name: MarketName,
+}
+
+// Listings of the market name, cheapest first
+pub async fn get_listings(
+    State(state): State<AdminState>,
+    Query(query): Query<ListingsQuery>,
+) -> Json<Vec<CsfloatListingStruct>> {
+    let csfloat_engine_locked = state.csfloat_engine.lock().await;
+    let mut listings: Vec<CsfloatListingStruct> = csfloat_engine_locked
+        .get_listings_by_name(&query.name)
+        .into_iter()
+        .cloned()
+        .collect();
+    listings.sort_by_key(|x| x.get_price_value());
+    Json(listings)
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnalysisResponse {
+    pub analysis: Option<AnalysisResult>,
+    pub order_book: Option<SteamOrderBook>,
+}
+
+pub async fn get_analysis(
+    State(state): State<AdminState>,
+    Path(market_name): Path<String>,
+) -> Result<Json<AnalysisResponse>, StatusCode> {
+    let steam_engine_locked = state.steam_engine.lock().await;
+    let response = AnalysisResponse {
+        analysis: steam_engine_locked.hm.get(market_name.as_str()).cloned(),
+        order_book: steam_engine_locked
+            .order_books
+            .get(market_name.as_str())
+            .cloned(),
+    };
+    if response.analysis.is_none() && response.order_book.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(response))
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchedulerQueueResponse {
+    pub size: usize,
+    pub queue: Vec<QueuedListing>,
+}
+
+pub async fn get_scheduler_queue(State(state): State<AdminState>) -> Json<SchedulerQueueResponse> {
+    let csfloat_scheduler_locked = state.csfloat_scheduler.lock().await;
+    Json(SchedulerQueueResponse {
+        size: csfloat_scheduler_locked.get_size(),
+        queue: csfloat_scheduler_locked.get_queue(SCHEDULER_QUEUE_LIMIT),
+    })
+}
+
+pub async fn get_stats(State(state): State<AdminState>) -> Json<StatsSnapshot> {
+    Json(state.stats.lock().await.get_snapshot())
+}
+
+#[derive(Deserialize)]
+pub struct StatsHistoryQuery {
+    // e.g. "processed." or "counter.StrategyBuys"
+    #[serde(default)]
+    pub prefix: String,
+    pub days: Option<i64>,
+    pub bucket: Option<Bucket>,
+}
+
+// Flushed stats of the last days, see `stats_history::load_buckets`
+pub async fn get_stats_history(
+    State(state): State<AdminState>,
+    Query(query): Query<StatsHistoryQuery>,
+) -> Result<Json<Vec<StatsPoint>>, StatusCode> {
+    let since = Utc::now() - chrono::Duration::days(query.days.unwrap_or(STATS_HISTORY_DAYS));
+    let bucket = query.bucket.unwrap_or(Bucket::Day);
+    match load_buckets(&state.pool, &query.prefix, since, bucket).await {
+        Ok(points) => Ok(Json(points)),
+        Err(err) => {
+            warn!("Failed to load stats history: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// TOML of the whole config, env overrides still apply on top of it.
+// It's replaced again once the config file changes.
+pub async fn post_config(State(state): State<AdminState>, body: String) -> Response {
+    match AppConfig::from_toml_with_env(&body) {
+        Ok(new_config) => {
+            state.config.store(Arc::new(new_config));
+            warn!("Config replaced via the admin API");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        steam_analyzer::{Smoothing, Trend},
+        storages::{CsfloatEngineTrait, SteamEngineTrait},
+        tests::fixtures::get_listing,
+    };
+    use sqlx::postgres::PgPoolOptions;
+    use tokio::sync::mpsc;
+
+    fn get_state() -> AdminState {
+        // the handlers under test don't touch the db
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/test")
+            .unwrap();
+        AdminState {
+            csfloat_engine: Arc::new(Mutex::new(CsfloatEngine::new())),
+            steam_engine: Arc::new(Mutex::new(SteamEngine::new())),
+            csfloat_scheduler: Arc::new(Mutex::new(CsfloatScheduler::new())),
+            stats: Arc::new(Mutex::new(Stats::new())),
+            config: AppConfig::default().into_shared(),
+            pool,
+            deal_feed: DealFeed::new(),
+            queues: QueueSenders {
+                primary: mpsc::channel(1).0,
+                secondary: mpsc::channel(1).0,
+                csfloat: mpsc::channel(1).0,
+                steam: mpsc::channel(1).0,
+            },
+            token: None,
+        }
+    }
+
+    #[test]
+    fn test_get_query_token() {
+        assert_eq!(get_query_token(Some("token=abc")), Some("abc"));
+        assert_eq!(get_query_token(Some("name=x&token=abc")), Some("abc"));
+        assert_eq!(get_query_token(Some("token=")), None);
+        assert_eq!(get_query_token(Some("name=x")), None);
+        assert_eq!(get_query_token(None), None);
+    }
+
+    #[tokio::test]
+    async fn test_admin_api_handlers() {
+        let state = get_state();
+        {
+            let mut csfloat_engine_locked = state.csfloat_engine.lock().await;
+            let redline = "AK-47 | Redline (Field-Tested)";
+            csfloat_engine_locked.update_listing(&get_listing("1", redline, 12_00));
+            csfloat_engine_locked.update_listing(&get_listing("2", redline, 10_00));
+            csfloat_engine_locked.update_listing(&get_listing("3", "Kilowatt Case", 5_00));
+        }
+        let Json(listings) = get_listings(
+            State(state.clone()),
+            Query(ListingsQuery {
+                name: "AK-47 | Redline (Field-Tested)".into(),
+            }),
+        )
+        .await;
+        assert_eq!(
+            listings
+                .iter()
+                .map(|x| x.id.to_string())
+                .collect::<Vec<_>>(),
+            vec!["2", "1"]
+        );
+
+        let analysis = get_analysis(State(state.clone()), Path("Kilowatt Case".to_string())).await;
+        assert_eq!(analysis.unwrap_err(), StatusCode::NOT_FOUND);
+        state.steam_engine.lock().await.update(
+            &"Kilowatt Case".into(),
+            AnalysisResult {
+                rsd: Some(0.01),
+                is_stable: Some(true),
+                sold_per_week: Some(500),
+                percentiles: vec![(60, 1_00)],
+                percentiles_no_fee: vec![(60, 87)],
+                weighted_percentiles: vec![],
+                rejected_outliers: 0,
+                trend: Trend::Flat,
+                analyzed_at: None,
+                sell_listings: None,
+                expected_days_to_sell: None,
+                smoothing: Smoothing::Sma,
+                seasonality: None,
+            },
+        );
+        let analysis = get_analysis(State(state.clone()), Path("Kilowatt Case".to_string())).await;
+        assert!(analysis.unwrap().0.analysis.is_some());
+
+        let response = post_config(
+            State(state.clone()),
+            "[reporting]\nenabled = false".to_string(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!state.config.load().reporting.enabled);
+        let response =
+            post_config(State(state.clone()), "[reporting]\nenabled = 1".to_string()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!state.config.load().reporting.enabled);
+    }
+}
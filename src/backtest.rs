@@ -0,0 +1,235 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use sqlx::{Pool, Postgres, Row};
+use tracing::info;
+
+use crate::{
+    business_logic::{get_buy_cost, is_need_to_autobuy},
+    cli::parse_time,
+    clock::{Clock, MockClock},
+    config::AppConfig,
+    csfloat::CsfloatScheduler,
+    currency::ExchangeRates,
+    event_processors::{
+        process_csfloat_listings_response, process_steam_response, process_updated_csfloat_listing,
+    },
+    events::{CsfloatResponseEvent, Event, PrimEvent, SecEvent, SteamResponseEvent},
+    filters::ListingFilters,
+    response_archive::get_archive_table,
+    risk::RiskManager,
+    storages::{CsfloatEngine, SteamEngine},
+    types::ListingId,
+    watchlist::Watchlist,
+};
+
+// Steam sell histories are loaded from that long before the range start,
+// so listings at the beginning of the range can be priced
+const STEAM_WARMUP: Duration = Duration::hours(24);
+
+// `backtest <from> <to>`, e.g. backtest 2024-02-19T00:00:00Z 2024-02-20T00:00:00Z
+#[derive(Debug, PartialEq, clap::Args)]
+pub struct BacktestArgs {
+    #[arg(value_parser = parse_time, help = "RFC 3339")]
+    pub from: DateTime<Utc>,
+    #[arg(value_parser = parse_time, help = "RFC 3339")]
+    pub to: DateTime<Utc>,
+}
+
+impl BacktestArgs {
+    pub fn check(&self) -> Result<(), String> {
+        if self.from >= self.to {
+            return Err("<from> should be before <to>".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct BacktestResult {
+    pub csfloat_responses: usize,
+    pub steam_responses: usize,
+    // unique listings
+    pub found: usize,
+    pub bought: usize,
+    // steam price minus fee minus paid price of bought listings, in cents
+    pub simulated_profit: i64,
+}
+
+enum ArchivedResponse {
+    Csfloat(String),
+    Steam(DateTime<Utc>, String),
+}
+
+async fn load_responses(
+    db: &Pool<Postgres>,
+    table: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<(NaiveDateTime, String)>, sqlx::Error> {
+    // old responses are moved to the archive table, see `response_archive`
+    let query = format!(
+        "SELECT timestamp, response FROM (
+            SELECT timestamp, response FROM {} UNION ALL SELECT timestamp, response FROM {}
+        ) AS x
+        WHERE timestamp >= $1 AND timestamp < $2 ORDER BY timestamp",
+        table,
+        get_archive_table(table)
+    );
+    let rows = sqlx::query(&query)
+        .bind(from.naive_utc())
+        .bind(to.naive_utc())
+        .fetch_all(db)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|x| (x.get("timestamp"), x.get("response")))
+        .collect())
+}
+
+// Replays archived responses of the range in their order through the event processors,
+// with fresh engines and the current strategy. Virtual time is the response timestamp.
+pub async fn run_backtest(
+    db: &Pool<Postgres>,
+    args: &BacktestArgs,
+    config: &AppConfig,
+) -> Result<BacktestResult, sqlx::Error> {
+    let csfloat = load_responses(db, "csfloat_responses", args.from, args.to).await?;
+    let steam = load_responses(db, "steam_responses", args.from - STEAM_WARMUP, args.to).await?;
+    info!(
+        "Backtest: {} csfloat and {} steam responses loaded",
+        csfloat.len(),
+        steam.len()
+    );
+
+    let mut result = BacktestResult {
+        csfloat_responses: csfloat.len(),
+        steam_responses: steam.len(),
+        ..BacktestResult::default()
+    };
+    let mut responses: Vec<(NaiveDateTime, ArchivedResponse)> = steam
+        .into_iter()
+        .map(|(ts, response)| (ts, ArchivedResponse::Steam(ts.and_utc(), response)))
+        .chain(
+            csfloat
+                .into_iter()
+                .map(|(ts, response)| (ts, ArchivedResponse::Csfloat(response))),
+        )
+        .collect();
+    // stable, so a steam response goes before a csfloat one of the same timestamp
+    responses.sort_by_key(|(ts, _)| *ts);
+
+    // would-be purchases are counted regardless of `autobuy.enabled`
+    let mut config = config.clone();
+    config.autobuy.paper_trading = true;
+    // analyses are as old as the archived responses, not as the current time
+    config.steam_analyzer.max_age_secs = 0;
+
+    // virtual time, moved to the timestamp of each replayed response
+    let clock = Arc::new(MockClock::new(args.from));
+    let mut csfloat_engine = CsfloatEngine::with_clock(clock.clone());
+    let mut steam_engine = SteamEngine::new();
+    let mut csfloat_scheduler = CsfloatScheduler::with_clock(clock.clone());
+    let mut risk_manager = RiskManager::new();
+    // user rules only notify, they don't affect the result
+    let watchlist = Watchlist::new();
+    // the strategy is replayed without mutes
+    let mut listing_filters = ListingFilters::new();
+    // past exchange rates aren't known, archived responses are expected in USD
+    let rates = ExchangeRates::new();
+    let mut found: HashSet<ListingId> = HashSet::new();
+    let mut bought: HashSet<ListingId> = HashSet::new();
+
+    for (ts, response) in responses {
+        let now = ts.and_utc();
+        clock.set(now);
+        let mut queue: VecDeque<PrimEvent> = VecDeque::from([match response {
+            ArchivedResponse::Csfloat(response) => {
+                PrimEvent::CsfloatListingsResponse(CsfloatResponseEvent {
+                    timestamp: clock.instant(),
+                    response,
+                    log_seq: None,
+                })
+            }
+            ArchivedResponse::Steam(timestamp, response) => {
+                PrimEvent::SteamResponse(SteamResponseEvent {
+                    timestamp,
+                    response,
+                    sell_listings: None,
+                    log_seq: None,
+                })
+            }
+        }]);
+
+        while let Some(event) = queue.pop_front() {
+            let new_events = match event {
+                PrimEvent::CsfloatListingsResponse(ref e) => {
+                    process_csfloat_listings_response(
+                        &mut csfloat_engine,
+                        &mut csfloat_scheduler,
+                        e,
+                        &config,
+                    )
+                    .await
+                }
+                PrimEvent::SteamResponse(ref e) => {
+                    process_steam_response(&mut steam_engine, &csfloat_engine, e, &rates, &config)
+                        .await
+                }
+                PrimEvent::UpdatedCsfloatListings(ref e) => {
+                    process_updated_csfloat_listing(
+                        &mut steam_engine,
+                        &mut csfloat_engine,
+                        &mut csfloat_scheduler,
+                        &watchlist,
+                        &mut listing_filters,
+                        e,
+                        &config,
+                    )
+                    .await
+                }
+                // listings are not refreshed one by one in the archive
+                _ => vec![],
+            };
+
+            for new_event in new_events {
+                match new_event {
+                    Event::Primary(prim_event) => queue.push_back(prim_event),
+                    Event::Secondary(SecEvent::ProfitableListing(e)) => {
+                        found.insert(e.listing_id.clone());
+                        if bought.contains(&e.listing_id)
+                            || !is_need_to_autobuy(&e, &config, &risk_manager, None, now)
+                        {
+                            continue;
+                        }
+                        let cost = get_buy_cost(e.venue, e.csfloat_price, &config);
+                        risk_manager.register_purchase(&e.market_name, cost, now);
+                        bought.insert(e.listing_id.clone());
+                        result.simulated_profit += e.steam_no_fee as i64 - cost as i64;
+                    }
+                    Event::Secondary(_) => {}
+                }
+            }
+        }
+    }
+
+    result.found = found.len();
+    result.bought = bought.len();
+    Ok(result)
+}
+
+pub fn format_result(args: &BacktestArgs, result: &BacktestResult) -> String {
+    format!(
+        "Backtest {} - {}\nReplayed {} csfloat and {} steam responses\nDeals found: {} | bought: {} | simulated profit: ${:.2}",
+        args.from,
+        args.to,
+        result.csfloat_responses,
+        result.steam_responses,
+        result.found,
+        result.bought,
+        result.simulated_profit as f64 / 100.0,
+    )
+}
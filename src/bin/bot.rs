@@ -0,0 +1,2048 @@
+use chrono::Utc;
+use clap::Parser;
+use dotenvy::dotenv;
+use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use std::collections::HashSet;
+use std::env;
+use std::future::IntoFuture;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use teloxide::Bot;
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    Mutex,
+};
+use tokio::task::JoinHandle;
+use tracing::{error, info, info_span, level_filters::LevelFilter, trace, warn, Instrument, Span};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{self, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use steam_csfloat_rust::{
+    admin_api::{build_router, AdminState},
+    backtest,
+    cli::{Cli, Command},
+    config::{
+        config_modified_at, config_path, AppConfig, LoggingConfig, SharedConfig, StateBackend,
+    },
+    consts::{
+        ADMIN_API_SHUTDOWN_TIMEOUT, CSFLOAT_EXPIRY_INTERVAL, PROXY_POOL_SUMMARY_INTERVAL,
+        QUEUE_MONITOR_INTERVAL, STATS_SAMPLE_INTERVAL, STEAM_EVICTION_INTERVAL,
+    },
+    csfloat::CsfloatScheduler,
+    csfloat_autobuy::CsfloatAutobuy,
+    csfloat_client::CsfloatClient,
+    csfloat_fetcher::{fetch_listing, CsfloatFetcher, ListingFetch},
+    currency::{fetch_exchange_rates, ExchangeRates, SharedRates},
+    dashboard::DealFeed,
+    dmarket::{DmarketClient, DmarketEngine},
+    dry_run,
+    event_log::EventLog,
+    event_processors::{
+        parse_steam_orders_response, process_alert, process_auction_opportunity,
+        process_buy_requested, process_csfloat_listings_response,
+        process_csfloat_one_listing_response, process_dmarket_listings_response,
+        process_paper_purchase, process_paper_purchase_checked, process_profitable_listing,
+        process_purchase_confirmed, process_skinport_listings_response,
+        process_steam_analysis_ready, process_steam_analysis_requested,
+        process_updated_csfloat_listing, refresh_balance,
+    },
+    events::{
+        CsfloatOneListingResponseEvent, CsfloatResponseEvent, DmarketResponseEvent, Event,
+        EventPriority, Pipeline, PrimEvent, SecEvent, SkinportResponseEvent,
+        SteamOrdersResponseEvent, SteamResponseEvent,
+    },
+    filters::{self, ListingFilters},
+    leadership::{self, Leadership},
+    ledger,
+    market_floors::{fetch_floors, MarketFloors},
+    notify::{NotificationKind, Notifications},
+    pending_purchases::PendingPurchases,
+    price_history::spawn_price_history_writer,
+    prices::PriceValueTrait,
+    proxy_pool::ProxyPool,
+    queues::{get_fill_pct, is_need_to_shed, try_send_event, DropRateMonitor, QueueSenders},
+    realtime_importer::{RealtimeImporter, ResponsesListener},
+    reporting::spawn_reporter,
+    response_archive::spawn_response_archiver,
+    risk::RiskManager,
+    sharding,
+    shutdown::{self, Shutdown, ShutdownSignal},
+    skinport::SkinportEngine,
+    state_store::AnyStateStore,
+    stats::{Stats, StatsCounter, StatsGauge, StatsKind},
+    stats_history::spawn_stats_flusher,
+    steam_fetcher::SteamFetcher,
+    steam_inventory::{poll_inventory, InventoryTracker},
+    steam_parser::SteamParserPool,
+    steam_repricer::{reprice_listings, RelistBudget},
+    steam_seller::{list_purchased_items, SteamSeller},
+    storages::{
+        self, CsfloatEngine, CsfloatEngineTrait, DbSerializable, SteamEngine, SteamEngineTrait,
+    },
+    telegram_commands::spawn_telegram_commands,
+    types::{ListingId, MarketName},
+    watchdog::{Component, Heartbeats, Watchdog},
+    watchlist::{self, Watchlist},
+};
+
+// Routes primary events to the pipeline of their engine
+fn spawn_primary_event_dispatcher(
+    mut prim_rx: Receiver<PrimEvent>,
+    csfloat_tx: Sender<PrimEvent>,
+    steam_tx: Sender<PrimEvent>,
+    stats: Arc<Mutex<Stats>>,
+    event_log: Arc<EventLog>,
+    heartbeats: Heartbeats,
+) {
+    tokio::spawn(async move {
+        while let Some(event) = prim_rx.recv().await {
+            let tx = match event.get_pipeline() {
+                Pipeline::Csfloat => &csfloat_tx,
+                Pipeline::Steam => &steam_tx,
+            };
+            // only low priority events are dropped, the others wait for room in the pipeline
+            let result = match event.get_priority() {
+                EventPriority::Low => try_send_event(tx, event, &stats).await,
+                EventPriority::High => tx.send(event).await.map_err(|err| {
+                    error!(
+                        "Queue is closed, {:?} event is dropped",
+                        StatsKind::from(&err.0)
+                    );
+                    err.0
+                }),
+            };
+            if let Err(event) = result {
+                event_log.mark_processed(&event).await;
+            }
+            heartbeats.beat(Component::PrimaryDispatcher);
+        }
+    });
+}
+
+async fn send_new_events(
+    new_events: Vec<Event>,
+    prim_tx: &Sender<PrimEvent>,
+    sec_tx: &Sender<SecEvent>,
+    stats: &Mutex<Stats>,
+) {
+    for new_event in new_events {
+        match new_event {
+            Event::Primary(prim_event) => {
+                let _ = try_send_event(prim_tx, prim_event, stats).await;
+            }
+            Event::Secondary(sec_event) => {
+                let _ = try_send_event(sec_tx, sec_event, stats).await;
+            }
+        };
+    }
+}
+
+// Low priority events are skipped while the pipeline queue `tx` is filling up
+async fn try_shed_event(
+    event: &PrimEvent,
+    tx: &Sender<PrimEvent>,
+    stats: &Mutex<Stats>,
+    event_log: &EventLog,
+    config: &AppConfig,
+) -> bool {
+    if !is_need_to_shed(event, get_fill_pct(tx), &config.queues) {
+        return false;
+    }
+    stats
+        .lock()
+        .await
+        .increment(StatsCounter::Shed(StatsKind::from(event)));
+    event_log.mark_processed(event).await;
+    true
+}
+
+// Logs written while the event is processed carry its listing and market name,
+// so a listing can be followed from ingestion to notification and purchase
+fn get_event_span(
+    kind: StatsKind,
+    listing_id: Option<&ListingId>,
+    market_name: Option<&MarketName>,
+) -> Span {
+    info_span!(
+        "event",
+        kind = ?kind,
+        listing_id = listing_id.map(|x| &**x),
+        market_name = market_name.map(|x| &**x),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_csfloat_pipeline(
+    prim_tx: Sender<PrimEvent>,
+    sec_tx: Sender<SecEvent>,
+    csfloat_tx: Sender<PrimEvent>,
+    mut csfloat_rx: Receiver<PrimEvent>,
+    stats: Arc<Mutex<Stats>>,
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    csfloat_scheduler: Arc<Mutex<CsfloatScheduler>>,
+    watchlist: Arc<Mutex<Watchlist>>,
+    listing_filters: Arc<Mutex<ListingFilters>>,
+    event_log: Arc<EventLog>,
+    heartbeats: Heartbeats,
+    config: SharedConfig,
+) {
+    tokio::spawn(async move {
+        while let Some(event) = csfloat_rx.recv().await {
+            heartbeats.beat(Component::CsfloatPipeline);
+            let _start = Instant::now();
+            let current_config = config.load();
+
+            if try_shed_event(&event, &csfloat_tx, &stats, &event_log, &current_config).await {
+                continue;
+            }
+
+            let span = get_event_span(
+                StatsKind::from(&event),
+                event.get_listing_id(),
+                event.get_market_name(),
+            );
+            let mut csfloat_engine_locked = csfloat_engine.lock().await;
+            let new_events = async {
+                match event {
+                    PrimEvent::CsfloatListingsResponse(ref e) => {
+                        process_csfloat_listings_response(
+                            &mut csfloat_engine_locked,
+                            &mut *csfloat_scheduler.lock().await,
+                            e,
+                            &current_config,
+                        )
+                        .await
+                    }
+                    PrimEvent::CsfloatOneListingResponse(ref e) => {
+                        process_csfloat_one_listing_response(
+                            &mut csfloat_engine_locked,
+                            &mut *csfloat_scheduler.lock().await,
+                            e,
+                            &current_config,
+                        )
+                        .await
+                    }
+                    PrimEvent::PaperPurchase(ref e) => {
+                        process_paper_purchase(
+                            &mut csfloat_engine_locked,
+                            &mut *csfloat_scheduler.lock().await,
+                            e,
+                        )
+                        .await
+                    }
+                    PrimEvent::UpdatedCsfloatListings(ref e) => {
+                        // the only event which needs both engines
+                        let mut steam_engine_locked = steam_engine.lock().await;
+                        let mut csfloat_scheduler_locked = csfloat_scheduler.lock().await;
+                        let watchlist_locked = watchlist.lock().await;
+                        let mut listing_filters_locked = listing_filters.lock().await;
+                        process_updated_csfloat_listing(
+                            &mut steam_engine_locked,
+                            &mut csfloat_engine_locked,
+                            &mut csfloat_scheduler_locked,
+                            &watchlist_locked,
+                            &mut listing_filters_locked,
+                            e,
+                            &current_config,
+                        )
+                        .await
+                    }
+                    PrimEvent::SteamResponse(_)
+                    | PrimEvent::SteamOrdersResponse(_)
+                    | PrimEvent::SteamAnalysisRequested(_)
+                    | PrimEvent::SteamAnalysisReady(_)
+                    | PrimEvent::SkinportListingsResponse(_)
+                    | PrimEvent::DmarketListingsResponse(_) => {
+                        error!(
+                            "{:?} is routed to the csfloat pipeline",
+                            StatsKind::from(&event)
+                        );
+                        vec![]
+                    }
+                }
+            }
+            .instrument(span)
+            .await;
+            drop(csfloat_engine_locked);
+
+            send_new_events(new_events, &prim_tx, &sec_tx, &stats).await;
+
+            let _duration = _start.elapsed();
+            stats
+                .lock()
+                .await
+                .register_duration(StatsKind::from(&event), _duration);
+            event_log.mark_processed(&event).await;
+        }
+    });
+}
+
+// Steam responses are parsed by `SteamParserPool`, the engine is locked only to apply them
+#[allow(clippy::too_many_arguments)]
+fn spawn_steam_pipeline(
+    prim_tx: Sender<PrimEvent>,
+    sec_tx: Sender<SecEvent>,
+    steam_tx: Sender<PrimEvent>,
+    mut steam_rx: Receiver<PrimEvent>,
+    stats: Arc<Mutex<Stats>>,
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    skinport_engine: Arc<Mutex<SkinportEngine>>,
+    dmarket_engine: Arc<Mutex<DmarketEngine>>,
+    event_log: Arc<EventLog>,
+    rates: SharedRates,
+    heartbeats: Heartbeats,
+    config: SharedConfig,
+) {
+    tokio::spawn(async move {
+        let steam_parser = SteamParserPool::new(
+            config.load().steam_analyzer.parse_workers,
+            prim_tx.clone(),
+            stats.clone(),
+            event_log.clone(),
+            rates.clone(),
+        );
+        while let Some(event) = steam_rx.recv().await {
+            heartbeats.beat(Component::SteamPipeline);
+            let _start = Instant::now();
+            let current_config = config.load();
+
+            if try_shed_event(&event, &steam_tx, &stats, &event_log, &current_config).await {
+                continue;
+            }
+
+            let span = get_event_span(
+                StatsKind::from(&event),
+                event.get_listing_id(),
+                event.get_market_name(),
+            );
+            let event = match event {
+                PrimEvent::SteamResponse(e) => {
+                    // it's processed once its `SteamAnalysisReady` is applied
+                    steam_parser
+                        .parse(e, config.load_full())
+                        .instrument(span)
+                        .await;
+                    continue;
+                }
+                event => event,
+            };
+
+            let new_events = async {
+                match event {
+                    PrimEvent::SteamAnalysisReady(ref e) => {
+                        // in the order of the csfloat pipeline, which locks both too
+                        let csfloat_engine_locked = csfloat_engine.lock().await;
+                        process_steam_analysis_ready(
+                            &mut *steam_engine.lock().await,
+                            &csfloat_engine_locked,
+                            e,
+                        )
+                        .await
+                    }
+                    PrimEvent::SteamOrdersResponse(ref e) => {
+                        if let Some(order_book) = parse_steam_orders_response(e) {
+                            steam_engine
+                                .lock()
+                                .await
+                                .update_order_book(&e.market_name, order_book);
+                        }
+                        vec![]
+                    }
+                    PrimEvent::SteamAnalysisRequested(ref e) => {
+                        process_steam_analysis_requested(&mut *steam_engine.lock().await, e).await
+                    }
+                    PrimEvent::SkinportListingsResponse(ref e) => {
+                        let mut steam_engine_locked = steam_engine.lock().await;
+                        process_skinport_listings_response(
+                            &mut *skinport_engine.lock().await,
+                            &mut steam_engine_locked,
+                            e,
+                            &rates.load(),
+                            &current_config,
+                        )
+                        .await
+                    }
+                    PrimEvent::DmarketListingsResponse(ref e) => {
+                        let mut steam_engine_locked = steam_engine.lock().await;
+                        process_dmarket_listings_response(
+                            &mut *dmarket_engine.lock().await,
+                            &mut steam_engine_locked,
+                            e,
+                            &current_config,
+                        )
+                        .await
+                    }
+                    // sent to the parser above
+                    PrimEvent::SteamResponse(_) => vec![],
+                    PrimEvent::CsfloatListingsResponse(_)
+                    | PrimEvent::CsfloatOneListingResponse(_)
+                    | PrimEvent::UpdatedCsfloatListings(_)
+                    | PrimEvent::PaperPurchase(_) => {
+                        error!(
+                            "{:?} is routed to the steam pipeline",
+                            StatsKind::from(&event)
+                        );
+                        vec![]
+                    }
+                }
+            }
+            .instrument(span)
+            .await;
+
+            send_new_events(new_events, &prim_tx, &sec_tx, &stats).await;
+
+            let _duration = _start.elapsed();
+            stats
+                .lock()
+                .await
+                .register_duration(StatsKind::from(&event), _duration);
+            event_log.mark_processed(&event).await;
+        }
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_secondary_event_dispatcher(
+    prim_tx: Sender<PrimEvent>,
+    sec_tx: Sender<SecEvent>,
+    mut sec_rx: Receiver<SecEvent>,
+    notifications: Notifications,
+    pool: Pool<Postgres>,
+    stats: Arc<Mutex<Stats>>,
+    csfloat_autobuy: Arc<Mutex<CsfloatAutobuy>>,
+    risk_manager: Arc<Mutex<RiskManager>>,
+    listing_filters: Arc<Mutex<ListingFilters>>,
+    deal_feed: DealFeed,
+    market_floors: Arc<Mutex<MarketFloors>>,
+    leadership: Leadership,
+    heartbeats: Heartbeats,
+    config: SharedConfig,
+) {
+    tokio::spawn(async move {
+        let mut pending_purchases = PendingPurchases::new();
+        // the risk state of startup is current until a standby takes over
+        let mut is_risk_loaded = leadership.is_leader();
+        while let Some(event) = sec_rx.recv().await {
+            heartbeats.beat(Component::SecondaryDispatcher);
+            // followers only keep the engines warm, purchases are made by the leader
+            if !leadership.is_leader() {
+                is_risk_loaded = false;
+                if let SecEvent::ProfitableListing(ref e) = event {
+                    deal_feed.publish(e);
+                }
+                continue;
+            }
+            let _start = Instant::now();
+            let current_config = config.load();
+            // the previous leader's purchases and kill-switch are in the ledger,
+            // nothing is bought until they're loaded
+            if !is_risk_loaded {
+                match ledger::load_risk_manager(&pool, &current_config.autobuy, Utc::now()).await {
+                    Ok(loaded) => {
+                        info!(
+                            "Risk state reloaded from the ledger: {}",
+                            loaded.summary(Utc::now())
+                        );
+                        *risk_manager.lock().await = loaded;
+                        is_risk_loaded = true;
+                    }
+                    Err(err) => {
+                        error!(
+                            "Failed to reload the risk state, event is skipped: {:?}",
+                            err
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            let span = get_event_span(
+                StatsKind::from(&event),
+                event.get_listing_id(),
+                event.get_market_name(),
+            );
+            // Dispatch events to their respective processing functions,
+            // only the autobuy stays locked during a purchase
+            let new_events = async {
+                match event {
+                    SecEvent::ProfitableListing(ref e) => {
+                        deal_feed.publish(e);
+                        process_profitable_listing(
+                            &notifications,
+                            &pool,
+                            &stats,
+                            &mut *csfloat_autobuy.lock().await,
+                            &risk_manager,
+                            &listing_filters,
+                            &mut pending_purchases,
+                            &market_floors,
+                            e,
+                            &current_config,
+                        )
+                        .await
+                    }
+                    SecEvent::PurchaseConfirmed(ref e) => {
+                        process_purchase_confirmed(
+                            &notifications,
+                            &pool,
+                            &stats,
+                            &mut *csfloat_autobuy.lock().await,
+                            &risk_manager,
+                            &mut pending_purchases,
+                            e,
+                            &current_config,
+                        )
+                        .await
+                    }
+                    SecEvent::BuyRequested(ref e) => {
+                        process_buy_requested(
+                            &notifications,
+                            &pool,
+                            &stats,
+                            &mut *csfloat_autobuy.lock().await,
+                            &risk_manager,
+                            e,
+                            &current_config,
+                        )
+                        .await
+                    }
+                    SecEvent::Alert(ref e) => {
+                        process_alert(&notifications, e, &current_config).await
+                    }
+                    SecEvent::PaperPurchaseChecked(ref e) => {
+                        process_paper_purchase_checked(&notifications, &pool, e, &current_config)
+                            .await
+                    }
+                    SecEvent::AuctionOpportunity(ref e) => {
+                        process_auction_opportunity(
+                            &notifications,
+                            &pool,
+                            &mut *csfloat_autobuy.lock().await,
+                            &risk_manager,
+                            e,
+                            &current_config,
+                        )
+                        .await
+                    }
+                }
+            }
+            .instrument(span)
+            .await;
+
+            send_new_events(new_events, &prim_tx, &sec_tx, &stats).await;
+
+            let _duration = _start.elapsed();
+
+            stats
+                .lock()
+                .await
+                .register_duration(StatsKind::from(&event), _duration);
+        }
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_importer(
+    pool: Pool<Postgres>,
+    tx: Sender<PrimEvent>,
+    stats: Arc<Mutex<Stats>>,
+    event_log: Arc<EventLog>,
+    heartbeats: Heartbeats,
+    shard: sharding::Shard,
+    leadership: Leadership,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let max_catchup = config.load().importer.max_catchup();
+        let mut ri = RealtimeImporter::load(&pool, shard, max_catchup).await;
+        let mut listener = ResponsesListener::new();
+        loop {
+            let (
+                poll_interval,
+                batch_size,
+                importer_config,
+                is_skinport_enabled,
+                is_csfloat_fetched,
+                is_steam_fetched,
+                event_log_config,
+            ) = {
+                let current_config = config.load();
+                (
+                    current_config.intervals.importer_poll(),
+                    current_config.queues.importer_batch_size,
+                    current_config.importer.clone(),
+                    current_config.skinport.enabled,
+                    current_config.csfloat_fetcher.enabled,
+                    current_config.steam_fetcher.enabled,
+                    current_config.event_log.clone(),
+                )
+            };
+            // the backlog is imported without waiting, unless the queue is full
+            let poll_interval = match ri.is_catching_up() && tx.capacity() > 0 {
+                true => Duration::ZERO,
+                false => poll_interval,
+            };
+            tokio::select! {
+                _ = listener.wait(&pool, poll_interval, importer_config.listen) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            // nothing to import is a progress too
+            let mut imported = 0;
+            let is_importing = !is_csfloat_fetched || !is_steam_fetched || is_skinport_enabled;
+
+            // listings come from the csfloat fetcher in standalone mode
+            if !is_csfloat_fetched {
+                for csfloat_response in ri
+                    .get_csfloat_new(&pool, batch_size, tx.capacity(), &importer_config)
+                    .await
+                {
+                    let csfloat_response_event = CsfloatResponseEvent {
+                        timestamp: Instant::now(),
+                        response: csfloat_response,
+                        log_seq: None,
+                    };
+                    let mut event = PrimEvent::CsfloatListingsResponse(csfloat_response_event);
+                    imported += 1;
+                    event_log.append(&mut event, &event_log_config).await;
+                    tx.send(event).await.expect("Error sending event");
+                }
+                let lag = ri.get_csfloat_lag_secs(Utc::now().naive_utc());
+                let next_size = ri.get_csfloat_batch().get_size(batch_size);
+                let mut stats = stats.lock().await;
+                stats.set_gauge(StatsGauge::ImporterLagSecs("csfloat"), lag);
+                stats.set_gauge(StatsGauge::ImporterBatchSize("csfloat"), next_size as u64);
+            }
+
+            // sell histories come from the steam fetcher in standalone mode
+            if !is_steam_fetched {
+                for steam_response in ri
+                    .get_steam_new(&pool, batch_size, tx.capacity(), &importer_config)
+                    .await
+                {
+                    let steam_response_event = SteamResponseEvent {
+                        timestamp: Utc::now(),
+                        response: steam_response,
+                        sell_listings: None,
+                        log_seq: None,
+                    };
+                    let mut event = PrimEvent::SteamResponse(steam_response_event);
+                    imported += 1;
+                    event_log.append(&mut event, &event_log_config).await;
+                    tx.send(event).await.expect("Error sending event");
+                }
+                let lag = ri.get_steam_lag_secs(Utc::now().naive_utc());
+                let next_size = ri.get_steam_batch().get_size(batch_size);
+                let mut stats = stats.lock().await;
+                stats.set_gauge(StatsGauge::ImporterLagSecs("steam"), lag);
+                stats.set_gauge(StatsGauge::ImporterBatchSize("steam"), next_size as u64);
+            }
+
+            if is_skinport_enabled {
+                for skinport_response in ri
+                    .get_skinport_new(&pool, batch_size, tx.capacity(), &importer_config)
+                    .await
+                {
+                    let skinport_response_event = SkinportResponseEvent {
+                        timestamp: Instant::now(),
+                        response: skinport_response,
+                        log_seq: None,
+                    };
+                    let mut event = PrimEvent::SkinportListingsResponse(skinport_response_event);
+                    imported += 1;
+                    event_log.append(&mut event, &event_log_config).await;
+                    tx.send(event).await.expect("Error sending event");
+                }
+                let lag = ri.get_skinport_lag_secs(Utc::now().naive_utc());
+                let next_size = ri.get_skinport_batch().get_size(batch_size);
+                let mut stats = stats.lock().await;
+                stats.set_gauge(StatsGauge::ImporterLagSecs("skinport"), lag);
+                stats.set_gauge(StatsGauge::ImporterBatchSize("skinport"), next_size as u64);
+            }
+
+            stats
+                .lock()
+                .await
+                .set_gauge(StatsGauge::ImporterCatchingUp, ri.is_catching_up() as u64);
+            if imported > 0 || !is_importing {
+                heartbeats.beat(Component::Importer);
+            }
+            // a follower's cursors would skip the responses its leader hasn't imported yet
+            if leadership.is_leader() {
+                ri.save_cursors(&pool).await;
+            }
+        }
+    })
+}
+
+fn spawn_csfloat_fetcher(
+    tx: Sender<PrimEvent>,
+    stats: Arc<Mutex<Stats>>,
+    event_log: Arc<EventLog>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut fetcher = CsfloatFetcher::from_env(&config.load().csfloat_fetcher);
+        loop {
+            let fetcher_config = config.load().csfloat_fetcher.clone();
+            tokio::select! {
+                _ = tokio::time::sleep(fetcher_config.poll_interval()) => {}
+                _ = shutdown.changed() => break,
+            }
+            if !fetcher_config.enabled {
+                continue;
+            }
+
+            // oldest page first, so listings are processed in creation order
+            for response in fetcher.fetch_new(&fetcher_config).await.into_iter().rev() {
+                let csfloat_response_event = CsfloatResponseEvent {
+                    timestamp: Instant::now(),
+                    response,
+                    log_seq: None,
+                };
+                let mut event = PrimEvent::CsfloatListingsResponse(csfloat_response_event);
+                event_log.append(&mut event, &config.load().event_log).await;
+                if let Err(event) = try_send_event(&tx, event, &stats).await {
+                    event_log.mark_processed(&event).await;
+                }
+            }
+        }
+    })
+}
+
+// Newest DMarket offers within the listing price band, already seen ones are skipped
+// by `DmarketEngine`
+fn spawn_dmarket_fetcher(
+    tx: Sender<PrimEvent>,
+    stats: Arc<Mutex<Stats>>,
+    event_log: Arc<EventLog>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = DmarketClient::from_env(&config.load().dmarket);
+        if client.is_none() && config.load().dmarket.enabled {
+            error!("DMARKET_PUBLIC_KEY and DMARKET_SECRET_KEY must be set for dmarket fetcher");
+        }
+        loop {
+            let current_config = config.load_full();
+            tokio::select! {
+                _ = tokio::time::sleep(current_config.dmarket.poll_interval()) => {}
+                _ = shutdown.changed() => break,
+            }
+            let (true, Some(client)) = (current_config.dmarket.enabled, &client) else {
+                continue;
+            };
+
+            let Some(response) = client
+                .fetch_market_items(
+                    current_config.strategy.listing_min_price,
+                    current_config.strategy.listing_max_price,
+                    &current_config.dmarket,
+                )
+                .await
+            else {
+                continue;
+            };
+            let dmarket_response_event = DmarketResponseEvent {
+                timestamp: Instant::now(),
+                response,
+                log_seq: None,
+            };
+            let mut event = PrimEvent::DmarketListingsResponse(dmarket_response_event);
+            event_log
+                .append(&mut event, &current_config.event_log)
+                .await;
+            if let Err(event) = try_send_event(&tx, event, &stats).await {
+                event_log.mark_processed(&event).await;
+            }
+        }
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_steam_fetcher(
+    tx: Sender<PrimEvent>,
+    stats: Arc<Mutex<Stats>>,
+    event_log: Arc<EventLog>,
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut fetcher = SteamFetcher::from_env(&config.load().steam_fetcher);
+        loop {
+            let fetcher_config = config.load().steam_fetcher.clone();
+            let next = match fetcher_config.enabled {
+                true => {
+                    let csfloat_engine_locked = csfloat_engine.lock().await;
+                    let steam_engine_locked = steam_engine.lock().await;
+                    let market_names: HashSet<MarketName> = csfloat_engine_locked
+                        .hm
+                        .values()
+                        .map(|listing| listing.item.market_hash_name.clone())
+                        .collect();
+                    fetcher.pick_next(
+                        &market_names,
+                        &steam_engine_locked,
+                        fetcher_config.stale_after(),
+                    )
+                }
+                false => None,
+            };
+
+            let Some(market_name) = next else {
+                tokio::select! {
+                    _ = tokio::time::sleep(fetcher_config.poll_interval()) => {}
+                    _ = shutdown.changed() => break,
+                }
+                continue;
+            };
+
+            let sell_listings = match fetcher_config.fetch_sell_listings {
+                true => tokio::select! {
+                    sell_listings = fetcher.fetch_sell_listings(&market_name, &fetcher_config) => sell_listings,
+                    _ = shutdown.changed() => break,
+                },
+                false => None,
+            };
+            let response = tokio::select! {
+                response = fetcher.fetch(&market_name, &fetcher_config) => response,
+                _ = shutdown.changed() => break,
+            };
+            let Some(response) = response else {
+                continue;
+            };
+            let steam_response_event = SteamResponseEvent {
+                timestamp: Utc::now(),
+                response,
+                sell_listings,
+                log_seq: None,
+            };
+            let mut event = PrimEvent::SteamResponse(steam_response_event);
+            event_log.append(&mut event, &config.load().event_log).await;
+            if let Err(event) = try_send_event(&tx, event, &stats).await {
+                event_log.mark_processed(&event).await;
+            }
+
+            if !fetcher_config.fetch_order_book {
+                continue;
+            }
+            let response = tokio::select! {
+                response = fetcher.fetch_order_histogram(&market_name, &fetcher_config) => response,
+                _ = shutdown.changed() => break,
+            };
+            if let Some(response) = response {
+                let steam_orders_response_event = SteamOrdersResponseEvent {
+                    timestamp: Utc::now(),
+                    market_name,
+                    response,
+                    log_seq: None,
+                };
+                let mut event = PrimEvent::SteamOrdersResponse(steam_orders_response_event);
+                event_log.append(&mut event, &config.load().event_log).await;
+                if let Err(event) = try_send_event(&tx, event, &stats).await {
+                    event_log.mark_processed(&event).await;
+                }
+            }
+        }
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_csfloat_refresher(
+    tx: Sender<PrimEvent>,
+    sec_tx: Sender<SecEvent>,
+    event_log: Arc<EventLog>,
+    stats: Arc<Mutex<Stats>>,
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    csfloat_scheduler: Arc<Mutex<CsfloatScheduler>>,
+    heartbeats: Heartbeats,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut proxy_pool = ProxyPool::new(&config.load().proxy_pool, stats.clone(), sec_tx);
+        info!("Csfloat refresher uses {} proxies", proxy_pool.get_size());
+        let mut last_summary = Instant::now();
+
+        loop {
+            // least recently used proxy which is not disabled
+            let (proxy_idx, ready_at) = proxy_pool.next_ready();
+            tokio::select! {
+                _ = tokio::time::sleep_until(ready_at) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            if last_summary.elapsed() > PROXY_POOL_SUMMARY_INTERVAL {
+                info!("Proxy pool:\n{}", proxy_pool.summary());
+                last_summary = Instant::now();
+            }
+
+            let req_interval = config.load().intervals.csfloat_one_listing_req();
+            let next: Option<ListingId>;
+            {
+                let mut csfloat_scheduler_locked = csfloat_scheduler.lock().await;
+                next = csfloat_scheduler_locked.get_next();
+                if let Some(listing_id) = &next {
+                    trace!(
+                        "csfloat_scheduler size: {} | next was: {:?} | proxy: {}",
+                        csfloat_scheduler_locked.get_size(),
+                        *listing_id,
+                        proxy_idx
+                    );
+                }
+            }
+
+            let Some(listing_id) = next else {
+                // nothing to refresh
+                heartbeats.beat(Component::Refresher);
+                tokio::select! {
+                    _ = tokio::time::sleep(req_interval) => {}
+                    _ = shutdown.changed() => break,
+                }
+                continue;
+            };
+            // slows down the proxy when its remaining quota won't last until the limit resets
+            let client = proxy_pool.acquire(proxy_idx, req_interval);
+            let text = match fetch_listing(client, &listing_id).await {
+                ListingFetch::Fetched(text) => text,
+                ListingFetch::RateLimited => {
+                    let backoff = proxy_pool.report_rate_limited(proxy_idx);
+                    warn!(
+                        "Csfloat rate limited proxy {}, pausing it for {:?}",
+                        proxy_idx, backoff
+                    );
+                    csfloat_scheduler.lock().await.report_failure(&listing_id);
+                    continue;
+                }
+                ListingFetch::NotFound => {
+                    csfloat_engine.lock().await.remove_listing(&listing_id);
+                    csfloat_scheduler.lock().await.remove_listing(&listing_id);
+                    stats
+                        .lock()
+                        .await
+                        .increment(StatsCounter::CsfloatListingNotFound);
+                    continue;
+                }
+                ListingFetch::Failed => {
+                    proxy_pool.report_failure(proxy_idx);
+                    csfloat_scheduler.lock().await.report_failure(&listing_id);
+                    continue;
+                }
+                ListingFetch::Rejected(_) => continue,
+            };
+            proxy_pool.report_success(proxy_idx);
+            csfloat_scheduler.lock().await.report_success(&listing_id);
+            heartbeats.beat(Component::Refresher);
+
+            let csfloat_response_event = CsfloatOneListingResponseEvent {
+                timestamp: Instant::now(),
+                response: text,
+                log_seq: None,
+            };
+            let mut new_event = PrimEvent::CsfloatOneListingResponse(csfloat_response_event);
+            event_log
+                .append(&mut new_event, &config.load().event_log)
+                .await;
+            if let Err(new_event) = try_send_event(&tx, new_event, &stats).await {
+                event_log.mark_processed(&new_event).await;
+            }
+        }
+    })
+}
+
+async fn truncate_event_log(event_log: &EventLog, watermark: i64) {
+    match event_log.truncate(watermark).await {
+        Ok(removed) => trace!("Removed {} saved events from the event log", removed),
+        Err(err) => error!("Failed to truncate the event log: {:?}", err),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_db_saver(
+    state_store: Arc<AnyStateStore>,
+    stats: Arc<Mutex<Stats>>,
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    event_log: Arc<EventLog>,
+    leadership: Leadership,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.load().intervals.db_save());
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.changed() => break,
+            }
+
+            {
+                let stats_locked = stats.lock().await;
+                stats_locked.print();
+            }
+            // the saved state of the shard is written by its leader only
+            if !leadership.is_leader() {
+                continue;
+            }
+
+            let _start = Instant::now();
+            let watermark = event_log.get_watermark().await;
+            // engines are locked only while the changed entries are copied
+            let csfloat_saved = storages::save_engine(&csfloat_engine, &*state_store).await;
+            let steam_saved = storages::save_engine(&steam_engine, &*state_store).await;
+
+            let _duration = _start.elapsed();
+
+            info!(
+                "Dumped state to DB in {:?} | csfloat changed {} | steam changed {}",
+                _duration,
+                csfloat_saved.as_ref().unwrap_or(&0),
+                steam_saved.as_ref().unwrap_or(&0)
+            );
+            if let (Some(watermark), Ok(_), Ok(_)) = (watermark, &csfloat_saved, &steam_saved) {
+                truncate_event_log(&event_log, watermark).await;
+            }
+
+            let csfloat_size = csfloat_engine.lock().await.hm.len();
+            let steam_size = steam_engine.lock().await.hm.len();
+
+            info!(
+                "Data saved to the database at {:?} | csfloat size {} | steam size {}",
+                Utc::now(),
+                csfloat_size,
+                steam_size
+            );
+        }
+    })
+}
+
+// Evicts analyses older than `steam_analyzer.evict_after_secs` and asks the steam fetcher
+// to refresh the stale ones of listed items first
+// Listings loaded on startup get the whole TTL to be refreshed, as the update time
+// of not changed listings isn't saved
+fn spawn_csfloat_expiry(
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    csfloat_scheduler: Arc<Mutex<CsfloatScheduler>>,
+    stats: Arc<Mutex<Stats>>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let started_at = Utc::now();
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(CSFLOAT_EXPIRY_INTERVAL) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let Some(listing_ttl) = config.load().scheduler.listing_ttl() else {
+                continue;
+            };
+            let deadline = Utc::now() - listing_ttl;
+            if deadline < started_at {
+                continue;
+            }
+            let expired = csfloat_engine
+                .lock()
+                .await
+                .expire_refreshed_before(deadline);
+            if expired.is_empty() {
+                continue;
+            }
+            let mut csfloat_scheduler_locked = csfloat_scheduler.lock().await;
+            for listing_id in expired.iter() {
+                csfloat_scheduler_locked.remove_listing(listing_id);
+            }
+            drop(csfloat_scheduler_locked);
+
+            stats
+                .lock()
+                .await
+                .increment_by(StatsCounter::CsfloatListingExpired, expired.len() as u64);
+            info!("Csfloat listings: {} expired", expired.len());
+        }
+    })
+}
+
+fn spawn_steam_evictor(
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(STEAM_EVICTION_INTERVAL) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let analyzer_config = config.load().steam_analyzer.clone();
+            let now = Utc::now();
+            let market_names: HashSet<MarketName> = csfloat_engine
+                .lock()
+                .await
+                .hm
+                .values()
+                .map(|listing| listing.item.market_hash_name.clone())
+                .collect();
+            let mut steam_engine_locked = steam_engine.lock().await;
+
+            let evicted = match analyzer_config.evict_after() {
+                Some(evict_after) => steam_engine_locked.evict_analyzed_before(now - evict_after),
+                None => 0,
+            };
+            let mut requested = 0;
+            if let Some(max_age) = analyzer_config.max_age() {
+                for market_name in market_names.iter() {
+                    if steam_engine_locked.is_stale(market_name, now, max_age) {
+                        steam_engine_locked.request_refresh(market_name);
+                        requested += 1;
+                    }
+                }
+            }
+            info!(
+                "Steam analysis: {} evicted | {} stale requested to refresh",
+                evicted, requested
+            );
+        }
+    })
+}
+
+fn spawn_steam_seller(
+    notifications: Notifications,
+    pool: Pool<Postgres>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    leadership: Leadership,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let seller = SteamSeller::from_env();
+        loop {
+            let poll_interval = config.load().steam_seller.poll_interval();
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let current_config = config.load();
+            if !current_config.steam_seller.enabled || !leadership.is_leader() {
+                continue;
+            }
+            if let Err(err) = list_purchased_items(
+                &seller,
+                &pool,
+                &steam_engine,
+                &notifications,
+                &current_config,
+            )
+            .await
+            {
+                warn!("Failed to list purchased items on Steam: {}", err);
+            }
+        }
+    })
+}
+
+fn spawn_steam_repricer(
+    notifications: Notifications,
+    pool: Pool<Postgres>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    leadership: Leadership,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let seller = SteamSeller::from_env();
+        let mut budget = RelistBudget::new();
+        loop {
+            let poll_interval = config.load().repricer.poll_interval();
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let current_config = config.load();
+            if !current_config.repricer.enabled || !leadership.is_leader() {
+                continue;
+            }
+            if let Err(err) = reprice_listings(
+                &seller,
+                &pool,
+                &steam_engine,
+                &mut budget,
+                &notifications,
+                &current_config,
+            )
+            .await
+            {
+                warn!("Failed to reprice Steam listings: {}", err);
+            }
+        }
+    })
+}
+
+// Notifies about ended trade holds on each poll and sends the stock summary
+// every `inventory.summary_interval_secs`
+fn spawn_inventory_tracker(
+    notifications: Notifications,
+    pool: Pool<Postgres>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    inventory: Arc<Mutex<InventoryTracker>>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let seller = SteamSeller::from_env();
+        let mut last_summary_at = Instant::now();
+        loop {
+            let poll_interval = config.load().inventory.poll_interval();
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let current_config = config.load();
+            if !current_config.inventory.enabled {
+                continue;
+            }
+            let released =
+                match poll_inventory(&seller, &pool, &steam_engine, &inventory, &current_config)
+                    .await
+                {
+                    Ok(released) => released,
+                    Err(err) => {
+                        warn!("Failed to poll Steam inventory: {}", err);
+                        continue;
+                    }
+                };
+            for stock_item in released {
+                let text = format!("{} is tradable now", stock_item.item.market_name);
+                notifications.notify(NotificationKind::Inventory, text, &current_config);
+            }
+
+            let Some(summary_interval) = current_config.inventory.summary_interval() else {
+                continue;
+            };
+            if last_summary_at.elapsed() >= summary_interval {
+                last_summary_at = Instant::now();
+                let text = inventory.lock().await.summary(Utc::now());
+                notifications.notify(NotificationKind::Inventory, text, &current_config);
+            }
+        }
+    })
+}
+
+// Queue depths and engine sizes, printed and exposed with the rest of `Stats`
+#[allow(clippy::too_many_arguments)]
+fn spawn_stats_sampler(
+    stats: Arc<Mutex<Stats>>,
+    queues: QueueSenders,
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    csfloat_scheduler: Arc<Mutex<CsfloatScheduler>>,
+    skinport_engine: Arc<Mutex<SkinportEngine>>,
+    dmarket_engine: Arc<Mutex<DmarketEngine>>,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(STATS_SAMPLE_INTERVAL) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            // each one is locked on its own, so the sizes may be slightly apart
+            let (csfloat_size, rejections) = {
+                let mut csfloat_engine_locked = csfloat_engine.lock().await;
+                let rejections = csfloat_engine_locked.prefilter_rejections.take();
+                (csfloat_engine_locked.hm.len(), rejections)
+            };
+            let steam_size = steam_engine.lock().await.hm.len();
+            let scheduler_size = csfloat_scheduler.lock().await.get_size();
+            let skinport_size = skinport_engine.lock().await.hm.len();
+            let dmarket_size = dmarket_engine.lock().await.hm.len();
+
+            let mut stats_locked = stats.lock().await;
+            for depth in queues.get_depths() {
+                stats_locked.set_gauge(StatsGauge::QueueDepth(depth.name), depth.queued as u64);
+            }
+            stats_locked.set_gauge(StatsGauge::CsfloatEngineSize, csfloat_size as u64);
+            stats_locked.set_gauge(StatsGauge::SteamEngineSize, steam_size as u64);
+            stats_locked.set_gauge(StatsGauge::SchedulerSize, scheduler_size as u64);
+            stats_locked.set_gauge(StatsGauge::SkinportEngineSize, skinport_size as u64);
+            stats_locked.set_gauge(StatsGauge::DmarketEngineSize, dmarket_size as u64);
+            for (name, count) in rejections {
+                stats_locked.increment_by(StatsCounter::PrefilterRejected(name), count);
+            }
+        }
+    })
+}
+
+// Alerts when more than `queues.drop_alert_per_min` events are dropped in a minute
+fn spawn_queue_monitor(
+    notifications: Notifications,
+    stats: Arc<Mutex<Stats>>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut monitor = DropRateMonitor::new();
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(QUEUE_MONITOR_INTERVAL) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let current_config = config.load();
+            let dropped_total = stats.lock().await.get_dropped_total();
+            let threshold = current_config.queues.drop_alert_per_min;
+            if let Some(dropped) = monitor.check(dropped_total, threshold) {
+                let text = format!(
+                    "{} events are dropped in the last minute because of full queues",
+                    dropped
+                );
+                warn!("{}", text);
+                notifications.notify(NotificationKind::Alert, text, &current_config);
+            }
+        }
+    })
+}
+
+// The balance is also refreshed after each purchase
+fn spawn_balance_refresher(
+    notifications: Notifications,
+    csfloat_autobuy: Arc<Mutex<CsfloatAutobuy>>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let refresh_interval = config.load().intervals.balance_refresh();
+            tokio::select! {
+                _ = tokio::time::sleep(refresh_interval) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let current_config = config.load();
+            let mut csfloat_autobuy_locked = csfloat_autobuy.lock().await;
+            refresh_balance(&notifications, &mut csfloat_autobuy_locked, &current_config).await;
+        }
+    })
+}
+
+// Rejected keys are retired and alerted, the ones which work again are revived
+fn spawn_auth_probe(
+    client: CsfloatClient,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let probe_interval = config.load().intervals.auth_probe();
+            tokio::select! {
+                _ = tokio::time::sleep(probe_interval) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            client.probe_keys().await;
+            let (current, alive, count) = client.get_key_counts();
+            info!(
+                "Csfloat API keys: {} of {} work, #{} is used",
+                alive,
+                count,
+                current + 1
+            );
+        }
+    })
+}
+
+fn spawn_admin_api(
+    state: AdminState,
+    bind_addr: String,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let listener = match tokio::net::TcpListener::bind(&bind_addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to bind the admin API to {}: {:?}", bind_addr, err);
+                return;
+            }
+        };
+        if state.token.is_none() {
+            warn!("ADMIN_API_TOKEN is not set, the admin API is not protected");
+        }
+        info!("Admin API listens on {}", bind_addr);
+        let mut shutdown_timeout = shutdown.clone();
+        let server =
+            axum::serve(listener, build_router(state)).with_graceful_shutdown(async move {
+                let _ = shutdown.changed().await;
+            });
+        tokio::select! {
+            result = server.into_future() => {
+                if let Err(err) = result {
+                    error!("Admin API failed: {:?}", err);
+                }
+            }
+            // open dashboards keep their event streams forever
+            _ = async {
+                let _ = shutdown_timeout.changed().await;
+                tokio::time::sleep(ADMIN_API_SHUTDOWN_TIMEOUT).await;
+            } => {
+                warn!("Admin API connections are still open, closing them");
+            }
+        }
+    })
+}
+
+// Each feed replaces the floors of its source, a failed one keeps the previous floors
+// until they're older than `market_floors.max_age_secs`
+fn spawn_market_floors_refresher(
+    market_floors: Arc<Mutex<MarketFloors>>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        loop {
+            let current_config = config.load_full();
+            if current_config.market_floors.enabled {
+                for feed in current_config.market_floors.feeds.iter() {
+                    let timeout = current_config.market_floors.request_timeout();
+                    match fetch_floors(&client, feed.source, &feed.url, timeout).await {
+                        Ok(floors) => {
+                            info!("Fetched {} {} floors", floors.len(), feed.source);
+                            market_floors.lock().await.update_source(
+                                feed.source,
+                                floors,
+                                Utc::now(),
+                            );
+                        }
+                        Err(err) => warn!("Failed to fetch {} floors: {}", feed.source, err),
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(current_config.market_floors.refresh_interval()) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+    })
+}
+
+// Non-USD prices are skipped until the first fetch, so it's done right away
+fn spawn_exchange_rates_refresher(
+    rates: SharedRates,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        loop {
+            let current_config = config.load_full();
+            if current_config.currency.enabled {
+                match fetch_exchange_rates(&client, &current_config.currency.rates_url).await {
+                    Ok(fetched) => {
+                        info!(
+                            "Fetched {} exchange rates updated at {:?}",
+                            fetched.rates.len(),
+                            fetched.updated_at
+                        );
+                        rates.store(Arc::new(fetched));
+                    }
+                    Err(err) => warn!("Failed to fetch exchange rates: {}", err),
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(current_config.currency.refresh_interval()) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+    })
+}
+
+fn spawn_config_watcher(config: SharedConfig) {
+    tokio::spawn(async move {
+        let path = config_path();
+        let mut last_modified = config_modified_at(&path);
+        loop {
+            let reload_interval = config.load().intervals.config_reload();
+            tokio::time::sleep(reload_interval).await;
+
+            let modified = config_modified_at(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            match AppConfig::load() {
+                Ok(new_config) => {
+                    config.store(Arc::new(new_config));
+                    warn!("Config reloaded from {}", path);
+                }
+                Err(err) => {
+                    error!("Failed to reload config, keeping the previous one: {}", err);
+                }
+            }
+        }
+    });
+}
+
+fn init_logging(config: &LoggingConfig) -> Result<WorkerGuard, Box<dyn std::error::Error>> {
+    fn get_filter() -> Result<EnvFilter, Box<dyn std::error::Error>> {
+        Ok(EnvFilter::builder()
+            .with_default_directive(LevelFilter::DEBUG.into())
+            .from_env()?)
+    }
+
+    let file_appender = tracing_appender::rolling::hourly("logs/", "prefix.log");
+    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
+    // tracing_subscriber::fmt().with_writer(file_appender).with_writer(non_blocking).init();
+    let file_filter = get_filter()?;
+    let console_filter = get_filter()?;
+    let file_layer = tracing_subscriber::fmt::layer()
+        .with_writer(file_appender)
+        .with_ansi(false);
+    let console_layer = tracing_subscriber::fmt::layer().with_writer(non_blocking);
+    let (file_layer, console_layer) = match config.json {
+        true => (
+            file_layer.json().with_span_list(true).boxed(),
+            console_layer
+                .with_ansi(false)
+                .json()
+                .with_span_list(true)
+                .boxed(),
+        ),
+        false => (file_layer.boxed(), console_layer.with_ansi(true).boxed()),
+    };
+    tracing_subscriber::registry()
+        .with(file_layer.with_filter(file_filter))
+        .with(console_layer.with_filter(console_filter))
+        .init();
+
+    Ok(guard)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let command = Cli::parse().command;
+    dotenv().ok();
+    // the log format is configured, so the config is loaded with a plain console logger
+    let config =
+        tracing::subscriber::with_default(tracing_subscriber::fmt().finish(), AppConfig::load)?
+            .into_shared();
+    let startup_config = config.load_full();
+
+    // dry runs print JSON to stdout, the logs would get in the way
+    if let Some(Command::Analyze(analyze_args)) = &command {
+        println!("{}", dry_run::run_analyze(analyze_args, &startup_config)?);
+        return Ok(());
+    }
+
+    let _guard = init_logging(&startup_config.logging)?;
+
+    info!("Starting the program...");
+
+    // the state store only picks where the engines are saved, the rest always needs Postgres
+    let database_url = env::var("DATABASE_URL").expect(
+        "DATABASE_URL must be set, the ledger, watchlist and event log are kept in Postgres",
+    );
+    info!("Database URL is {}", database_url);
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await?;
+
+    // Create an asynchronous channels for event communication
+    let (prim_tx, prim_rx) = mpsc::channel::<PrimEvent>(startup_config.queues.primary_size);
+    let (sec_tx, sec_rx) = mpsc::channel::<SecEvent>(startup_config.queues.secondary_size);
+    // primary events are routed to these by their engine
+    let (csfloat_tx, csfloat_rx) = mpsc::channel::<PrimEvent>(startup_config.queues.primary_size);
+    let (steam_tx, steam_rx) = mpsc::channel::<PrimEvent>(startup_config.queues.primary_size);
+
+    storages::create_tables(&pool).await?;
+
+    match &command {
+        Some(Command::Backtest(backtest_args)) => {
+            backtest_args.check()?;
+            let result = backtest::run_backtest(&pool, backtest_args, &startup_config).await?;
+            info!("{}", backtest::format_result(backtest_args, &result));
+            return Ok(());
+        }
+        Some(Command::Evaluate(evaluate_args)) => {
+            println!(
+                "{}",
+                dry_run::run_evaluate(&pool, evaluate_args, &startup_config).await?
+            );
+            return Ok(());
+        }
+        Some(Command::Analyze(_)) | None => {}
+    }
+
+    let shard = startup_config.sharding.get_shard()?;
+    let is_electing = startup_config.leadership.enabled;
+    // dropped at the end of main, which releases the shard. With leader election
+    // instances of the same shard run together and the leader lock is used instead.
+    let _shard_lock = if shard.is_sharded() && !is_electing {
+        let shard_lock = sharding::claim_shard(&database_url, shard).await?;
+        info!("Running as shard {}", shard);
+        Some(shard_lock)
+    } else {
+        None
+    };
+
+    let state_store =
+        Arc::new(AnyStateStore::connect(&startup_config.state_store, shard, &pool).await?);
+    let mut csfloat_engine_itself = CsfloatEngine::deserialize(&*state_store).await;
+    csfloat_engine_itself.shard = shard;
+    let steam_engine_itself = SteamEngine::deserialize(&*state_store).await;
+    let mut csfloat_scheduler_itself = CsfloatScheduler::new();
+    for listing in csfloat_engine_itself.get_listing_ids_by_update_time() {
+        csfloat_scheduler_itself.upsert_listing(&listing);
+    }
+
+    assert_eq!(
+        csfloat_engine_itself.get_size(),
+        csfloat_scheduler_itself.get_size()
+    );
+
+    info!(
+        "Loaded state for CsfloatEngine: {} | SteamEngine: {}",
+        csfloat_engine_itself.hm.len(),
+        steam_engine_itself.hm.len()
+    );
+    let csfloat_engine = Arc::new(Mutex::new(csfloat_engine_itself));
+    let steam_engine = Arc::new(Mutex::new(steam_engine_itself));
+    let csfloat_scheduler = Arc::new(Mutex::new(csfloat_scheduler_itself));
+    let skinport_engine = Arc::new(Mutex::new(SkinportEngine::new()));
+    let dmarket_engine = Arc::new(Mutex::new(DmarketEngine::new()));
+    let stats = Arc::new(Mutex::new(Stats::new()));
+    let rates = ExchangeRates::new().into_shared();
+
+    let mut csfloat_autobuy =
+        CsfloatAutobuy::from_env(config.clone(), stats.clone(), sec_tx.clone());
+    csfloat_autobuy.dmarket = DmarketClient::from_env(&startup_config.dmarket);
+    let auth_probe_client = csfloat_autobuy.client.clone();
+    let csfloat_autobuy = Arc::new(Mutex::new(csfloat_autobuy));
+    let risk_manager =
+        ledger::load_risk_manager(&pool, &startup_config.autobuy, Utc::now()).await?;
+    info!(
+        "Risk state restored from the ledger: {}",
+        risk_manager.summary(Utc::now())
+    );
+    let risk_manager = Arc::new(Mutex::new(risk_manager));
+    let watchlist = watchlist::load_watch_rules(&pool).await?;
+    info!("Watchlist loaded: {}", watchlist.summary());
+    let watchlist = Arc::new(Mutex::new(watchlist));
+    let listing_filters = match filters::load_listing_filters(&pool).await {
+        Ok(listing_filters) => listing_filters,
+        Err(err) => {
+            error!("Failed to load listing filters: {:?}", err);
+            ListingFilters::new()
+        }
+    };
+    let listing_filters = Arc::new(Mutex::new(listing_filters));
+    let inventory = Arc::new(Mutex::new(InventoryTracker::new()));
+    let shutdown = Shutdown::new();
+    let leadership = match is_electing {
+        true => Leadership::follower(),
+        false => Leadership::always(),
+    };
+    let event_log = Arc::new(EventLog::new(pool.clone(), shard, leadership.clone()));
+    csfloat_autobuy
+        .lock()
+        .await
+        .set_leadership(leadership.clone());
+    let leader_election = is_electing.then(|| {
+        leadership::spawn_leader_election(
+            database_url.clone(),
+            shard,
+            leadership.clone(),
+            startup_config.leadership.retry_interval(),
+            shutdown.subscribe(),
+        )
+    });
+
+    let bot = Bot::from_env();
+    let notifications = Notifications::new(bot.clone(), leadership.clone(), config.clone());
+
+    {
+        let mut csfloat_autobuy_locked = csfloat_autobuy.lock().await;
+        csfloat_autobuy_locked
+            .refresh_balance(startup_config.autobuy.low_balance_alert)
+            .await?;
+        let balance = csfloat_autobuy_locked.get_cached_balance().unwrap_or(0);
+        warn!("Csfloat balance is ${}", balance.to_usd());
+    }
+
+    // Start the event dispatchers
+    let heartbeats = Heartbeats::new();
+    spawn_primary_event_dispatcher(
+        prim_rx,
+        csfloat_tx.clone(),
+        steam_tx.clone(),
+        stats.clone(),
+        event_log.clone(),
+        heartbeats.clone(),
+    );
+    spawn_csfloat_pipeline(
+        prim_tx.clone(),
+        sec_tx.clone(),
+        csfloat_tx.clone(),
+        csfloat_rx,
+        stats.clone(),
+        csfloat_engine.clone(),
+        steam_engine.clone(),
+        csfloat_scheduler.clone(),
+        watchlist.clone(),
+        listing_filters.clone(),
+        event_log.clone(),
+        heartbeats.clone(),
+        config.clone(),
+    );
+    spawn_steam_pipeline(
+        prim_tx.clone(),
+        sec_tx.clone(),
+        steam_tx.clone(),
+        steam_rx,
+        stats.clone(),
+        csfloat_engine.clone(),
+        steam_engine.clone(),
+        skinport_engine.clone(),
+        dmarket_engine.clone(),
+        event_log.clone(),
+        rates.clone(),
+        heartbeats.clone(),
+        config.clone(),
+    );
+
+    let deal_feed = DealFeed::new();
+    let market_floors = Arc::new(Mutex::new(MarketFloors::new()));
+    spawn_secondary_event_dispatcher(
+        prim_tx.clone(),
+        sec_tx.clone(),
+        sec_rx,
+        notifications.clone(),
+        pool.clone(),
+        stats.clone(),
+        csfloat_autobuy.clone(),
+        risk_manager.clone(),
+        listing_filters.clone(),
+        deal_feed.clone(),
+        market_floors.clone(),
+        leadership.clone(),
+        heartbeats.clone(),
+        config.clone(),
+    );
+
+    // replayed events go before the new ones
+    let event_log_config = config.load().event_log.clone();
+    if event_log_config.enabled && event_log_config.replay_on_startup {
+        if let Err(err) = event_log.replay(&prim_tx).await {
+            error!("Failed to replay the event log: {:?}", err);
+        }
+    }
+
+    let mut producers = vec![
+        spawn_csfloat_fetcher(
+            prim_tx.clone(),
+            stats.clone(),
+            event_log.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_dmarket_fetcher(
+            prim_tx.clone(),
+            stats.clone(),
+            event_log.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_steam_fetcher(
+            prim_tx.clone(),
+            stats.clone(),
+            event_log.clone(),
+            csfloat_engine.clone(),
+            steam_engine.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_db_saver(
+            state_store.clone(),
+            stats.clone(),
+            csfloat_engine.clone(),
+            steam_engine.clone(),
+            event_log.clone(),
+            leadership.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_csfloat_expiry(
+            csfloat_engine.clone(),
+            csfloat_scheduler.clone(),
+            stats.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_steam_evictor(
+            csfloat_engine.clone(),
+            steam_engine.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_telegram_commands(
+            bot.clone(),
+            csfloat_autobuy.clone(),
+            risk_manager.clone(),
+            watchlist.clone(),
+            listing_filters.clone(),
+            inventory.clone(),
+            sec_tx.clone(),
+            pool.clone(),
+            leadership.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_steam_seller(
+            notifications.clone(),
+            pool.clone(),
+            steam_engine.clone(),
+            leadership.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_steam_repricer(
+            notifications.clone(),
+            pool.clone(),
+            steam_engine.clone(),
+            leadership.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_inventory_tracker(
+            notifications.clone(),
+            pool.clone(),
+            steam_engine.clone(),
+            inventory.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_queue_monitor(
+            notifications.clone(),
+            stats.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_stats_sampler(
+            stats.clone(),
+            QueueSenders {
+                primary: prim_tx.clone(),
+                secondary: sec_tx.clone(),
+                csfloat: csfloat_tx.clone(),
+                steam: steam_tx.clone(),
+            },
+            csfloat_engine.clone(),
+            steam_engine.clone(),
+            csfloat_scheduler.clone(),
+            skinport_engine.clone(),
+            dmarket_engine.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_balance_refresher(
+            notifications.clone(),
+            csfloat_autobuy.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_auth_probe(auth_probe_client, config.clone(), shutdown.subscribe()),
+        spawn_exchange_rates_refresher(rates.clone(), config.clone(), shutdown.subscribe()),
+        spawn_market_floors_refresher(market_floors.clone(), config.clone(), shutdown.subscribe()),
+        spawn_reporter(
+            notifications.clone(),
+            pool.clone(),
+            csfloat_engine.clone(),
+            steam_engine.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_stats_flusher(
+            stats.clone(),
+            pool.clone(),
+            shard.to_string(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_response_archiver(
+            pool.clone(),
+            leadership.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+        spawn_price_history_writer(
+            csfloat_engine.clone(),
+            steam_engine.clone(),
+            pool.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        ),
+    ];
+    // the refresher and the importer are restarted by the watchdog when they stall
+    let mut watchdog = Watchdog::new(
+        heartbeats.clone(),
+        vec![
+            Component::PrimaryDispatcher,
+            Component::CsfloatPipeline,
+            Component::SteamPipeline,
+            Component::SecondaryDispatcher,
+            Component::Database,
+        ],
+    );
+    {
+        let (prim_tx, sec_tx, event_log, stats) = (
+            prim_tx.clone(),
+            sec_tx.clone(),
+            event_log.clone(),
+            stats.clone(),
+        );
+        let (csfloat_engine, csfloat_scheduler) =
+            (csfloat_engine.clone(), csfloat_scheduler.clone());
+        let (heartbeats, config, shutdown) =
+            (heartbeats.clone(), config.clone(), shutdown.subscribe());
+        watchdog.supervise(Component::Refresher, move || {
+            spawn_csfloat_refresher(
+                prim_tx.clone(),
+                sec_tx.clone(),
+                event_log.clone(),
+                stats.clone(),
+                csfloat_engine.clone(),
+                csfloat_scheduler.clone(),
+                heartbeats.clone(),
+                config.clone(),
+                shutdown.clone(),
+            )
+        });
+    }
+    // responses saved to Postgres by external scrapers, they're imported only with the
+    // postgres backend
+    if startup_config.state_store.backend == StateBackend::Postgres {
+        let (pool, prim_tx, stats, event_log) = (
+            pool.clone(),
+            prim_tx.clone(),
+            stats.clone(),
+            event_log.clone(),
+        );
+        let (heartbeats, leadership, config, shutdown) = (
+            heartbeats.clone(),
+            leadership.clone(),
+            config.clone(),
+            shutdown.subscribe(),
+        );
+        watchdog.supervise(Component::Importer, move || {
+            spawn_importer(
+                pool.clone(),
+                prim_tx.clone(),
+                stats.clone(),
+                event_log.clone(),
+                heartbeats.clone(),
+                shard,
+                leadership.clone(),
+                config.clone(),
+                shutdown.clone(),
+            )
+        });
+    } else {
+        warn!("Realtime importer is disabled with the non-Postgres state store");
+    }
+    producers.push(watchdog.spawn(
+        notifications.clone(),
+        pool.clone(),
+        QueueSenders {
+            primary: prim_tx.clone(),
+            secondary: sec_tx.clone(),
+            csfloat: csfloat_tx.clone(),
+            steam: steam_tx.clone(),
+        },
+        config.clone(),
+        shutdown.subscribe(),
+    ));
+    if startup_config.admin_api.enabled {
+        let admin_state = AdminState {
+            csfloat_engine: csfloat_engine.clone(),
+            steam_engine: steam_engine.clone(),
+            csfloat_scheduler: csfloat_scheduler.clone(),
+            stats: stats.clone(),
+            config: config.clone(),
+            pool: pool.clone(),
+            deal_feed: deal_feed.clone(),
+            queues: QueueSenders {
+                primary: prim_tx.clone(),
+                secondary: sec_tx.clone(),
+                csfloat: csfloat_tx.clone(),
+                steam: steam_tx.clone(),
+            },
+            token: env::var("ADMIN_API_TOKEN").ok().filter(|x| !x.is_empty()),
+        };
+        producers.push(spawn_admin_api(
+            admin_state,
+            startup_config.admin_api.bind_addr.clone(),
+            shutdown.subscribe(),
+        ));
+    }
+
+    spawn_config_watcher(config.clone());
+
+    shutdown::wait_for_signal().await;
+    warn!("Shutting down: stopping importer, refresher and db saver...");
+    shutdown.trigger();
+    for producer in producers {
+        if let Err(err) = producer.await {
+            error!("Task failed during shutdown: {:?}", err);
+        }
+    }
+
+    let drain_timeout = config.load().intervals.shutdown_drain_timeout();
+    if shutdown::drain_queues(&[prim_tx, csfloat_tx, steam_tx], &sec_tx, drain_timeout).await {
+        info!("Event queues are drained");
+    }
+
+    // the leader lock is kept until the final state is saved
+    let _leader_lock = match leader_election {
+        Some(leader_election) => leader_election.await.ok().flatten(),
+        None => None,
+    };
+    if !leadership.is_leader() {
+        info!("Not the leader, the final state isn't saved");
+        return Ok(());
+    }
+
+    // wait for an in-flight purchase (if any) before exiting
+    let _csfloat_autobuy_locked = csfloat_autobuy.lock().await;
+    let watermark = event_log.get_watermark().await;
+    let mut csfloat_engine_locked = csfloat_engine.lock().await;
+    let mut steam_engine_locked = steam_engine.lock().await;
+    let _start = Instant::now();
+    let csfloat_saved = csfloat_engine_locked.serialize(&*state_store).await;
+    let steam_saved = steam_engine_locked.serialize(&*state_store).await;
+    if let (Some(watermark), Ok(()), Ok(())) = (watermark, csfloat_saved, steam_saved) {
+        truncate_event_log(&event_log, watermark).await;
+    }
+    info!(
+        "Final state dumped to DB in {:?} | csfloat size {} | steam size {}",
+        _start.elapsed(),
+        csfloat_engine_locked.hm.len(),
+        steam_engine_locked.hm.len()
+    );
+
+    Ok(())
+}
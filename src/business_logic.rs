@@ -1,70 +1,535 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
 use crate::{
-    consts::{
-        AUTOBUY_FROM_PROFIT_PCT, LISTING_MAX_PRICE, LISTING_MIN_PRICE, MIN_SOLD_PER_WEEK, PHASE_4,
-        TG_NOTIFY_MIN_PROFIT_PCT,
-    },
-    events::{ProfitableListingEvent, ProfitableListingKind},
-    models::CsfloatListingStruct,
-    prices::PriceValue,
+    config::{AppConfig, SellPriceSource},
+    csfloat::PriorityTier,
+    events::{AppliedValue, PriceSource, ProfitableListingEvent, ProfitableListingKind, Venue},
+    fee::{CsfloatFee, SteamFee},
+    models::{CsfloatListingItem, CsfloatListingStruct, CsfloatSeller, CsfloatSticker},
+    patterns::{find_pattern_tier, PatternTier},
+    phases::{find_phase_price, PhasePrice},
+    prices::{PriceValue, PriceValueTrait},
+    pricing::ItemCategory,
+    risk::{RiskManager, RiskRejection},
+    steam_analyzer::{AnalysisResult, Trend},
+    stickers::StickerPriceTable,
+    storages::SteamEngine,
+    strategies::StrategyName,
+    types::{ListingId, MarketName},
 };
 
+// Souvenirs are never in the band unless `strategy.souvenirs_enabled`
 #[inline]
-pub fn prefilter_listing(listing: &CsfloatListingStruct) -> bool {
-    // true - listing is allowed
-    // false - skip listing
-
-    // Skip souvenir listings
-    if listing.item.is_souvenir {
+pub fn is_price_in_band(price: PriceValue, category: ItemCategory, config: &AppConfig) -> bool {
+    let strategy = &config.strategy;
+    if category == ItemCategory::Souvenir && !strategy.souvenirs_enabled {
         return false;
     }
+    match strategy
+        .category_price_bands
+        .iter()
+        .find(|band| band.category == category)
+    {
+        Some(band) => band.min_price <= price && price <= band.max_price,
+        None => strategy.listing_min_price <= price && price <= strategy.listing_max_price,
+    }
+}
 
-    // Skip too cheap or rich items
-    if listing.price < LISTING_MIN_PRICE || listing.price > LISTING_MAX_PRICE {
-        return false;
+// Err is the name of the check which skipped the listing, they're counted by it
+#[inline]
+pub fn prefilter_listing<'a>(
+    listing: &CsfloatListingStruct,
+    config: &'a AppConfig,
+) -> Result<(), &'a str> {
+    // Skip too cheap or rich items and souvenirs when they're not enabled
+    if !is_price_in_band(listing.price, listing.item.get_category(), config) {
+        return Err("price_band");
+    }
+
+    // Skip unreliable sellers
+    if let Some(seller) = &listing.seller {
+        if !is_reliable_seller(seller, config) {
+            return Err("seller");
+        }
     }
 
-    true
+    config.prefilter.check(listing)
 }
 
-pub fn is_good_glock_phase_listing(listing: &CsfloatListingStruct) -> bool {
-    if listing.item.phase.is_none() {
+pub fn is_reliable_seller(seller: &CsfloatSeller, config: &AppConfig) -> bool {
+    if !config.seller.enabled {
+        return true;
+    }
+    if seller.statistics.total_verified_trades < config.seller.min_verified_trades {
         return false;
     }
+    match seller.get_failed_trades_pct() {
+        Some(failed_pct) => failed_pct <= config.seller.max_failed_trades_pct,
+        None => true,
+    }
+}
 
-    let phase = listing.item.phase.as_ref().unwrap();
+// None when the item sells too rarely to be priced
+pub fn get_sell_percentile(sold_per_week: Option<i32>, config: &AppConfig) -> Option<u8> {
+    let tiers = &config.strategy.liquidity_tiers;
+    if tiers.is_empty() {
+        return Some(config.strategy.desired_percentile);
+    }
 
-    if phase.as_str() == PHASE_4 {
-        const FACTORY_NEW: &str = "Glock-18 | Gamma Doppler (Factory New)";
-        const MINIMAL_WEAR: &str = "Glock-18 | Gamma Doppler (Minimal Wear)";
-        const FIELD_TESTED: &str = "Glock-18 | Gamma Doppler (Field-Tested)";
+    let sold_per_week = sold_per_week?.max(0) as u64;
+    tiers
+        .iter()
+        .filter(|tier| sold_per_week >= tier.min_sold_per_week)
+        .max_by_key(|tier| tier.min_sold_per_week)
+        .map(|tier| tier.percentile)
+}
 
-        const FACTORY_NEW_PRICE: PriceValue = 60_00 as PriceValue; // $60
-        const MINIMAL_WEAR_PRICE: PriceValue = 45_00 as PriceValue; // $45
-        const FIELD_TESTED_PRICE: PriceValue = 35_00 as PriceValue; // $35
+// Items without enough sales at the sell hour are left as is
+fn adjust_to_sell_hour(
+    price: PriceValue,
+    analysis: &AnalysisResult,
+    config: &AppConfig,
+) -> PriceValue {
+    match &analysis.seasonality {
+        Some(seasonality) if config.strategy.adjust_to_sell_hour => {
+            price.multiply_by_percent(seasonality.sell_hour_factor)
+        }
+        _ => price,
+    }
+}
 
-        let price = listing.price;
+// Steam price (with fee) we expect to sell the item for, according to the strategy
+pub fn estimate_steam_sell_price(
+    market_name: &MarketName,
+    steam_engine: &SteamEngine,
+    config: &AppConfig,
+) -> Option<PriceValue> {
+    match config.strategy.sell_price_source {
+        SellPriceSource::Percentile => {
+            let analysis = steam_engine.hm.get(market_name)?;
+            let percentile = get_sell_percentile(analysis.sold_per_week, config)?;
+            let price = analysis.get_price_by_percentile(percentile)?;
+            Some(adjust_to_sell_hour(price, analysis, config))
+        }
+        SellPriceSource::WeightedPercentile => {
+            let analysis = steam_engine.hm.get(market_name)?;
+            let percentile = get_sell_percentile(analysis.sold_per_week, config)?;
+            let price = analysis.get_weighted_price_by_percentile(percentile)?;
+            Some(adjust_to_sell_hour(price, analysis, config))
+        }
+        SellPriceSource::HighestBuyOrder => {
+            steam_engine.order_books.get(market_name)?.highest_buy_order
+        }
+    }
+}
 
-        return match listing.item.market_hash_name.as_str() {
-            FACTORY_NEW => price <= FACTORY_NEW_PRICE,
-            MINIMAL_WEAR => price <= MINIMAL_WEAR_PRICE,
-            FIELD_TESTED => price <= FIELD_TESTED_PRICE,
-            _ => false,
-        };
+// Secondary estimate for items without enough Steam sales to be analyzed.
+// `predicted_price` is CSFloat's reference price of the listing.
+pub fn estimate_fallback_sell_price(
+    market_name: &MarketName,
+    predicted_price: Option<PriceValue>,
+    steam_engine: &SteamEngine,
+    config: &AppConfig,
+) -> Option<(PriceValue, PriceSource)> {
+    let fallback = &config.fallback_pricing;
+    if !fallback.enabled || steam_engine.hm.contains_key(market_name) {
+        return None;
     }
 
-    false
+    let haircut = |price: PriceValue, pct: f64| price.multiply_by_percent(1.0 - pct / 100.0);
+    let buy_order = steam_engine
+        .order_books
+        .get(market_name)
+        .and_then(|order_book| order_book.highest_buy_order);
+    let estimate = match (buy_order, predicted_price) {
+        (Some(price), _) => (
+            haircut(price, fallback.buy_order_haircut_pct),
+            PriceSource::BuyOrderFallback,
+        ),
+        (None, Some(price)) => (
+            haircut(price, fallback.predicted_price_haircut_pct),
+            PriceSource::PredictedPriceFallback,
+        ),
+        (None, None) => return None,
+    };
+    // nothing would be left after the Steam fee
+    Some(estimate).filter(|(price, _)| *price >= SteamFee::get_min_total())
 }
 
-pub fn is_need_notify_via_telegram(event: &ProfitableListingEvent) -> bool {
-    if event.kind == ProfitableListingKind::GoodPhase {
-        return true;
+// `steam_no_fee` is None when the item is not analyzed yet
+pub fn get_refresh_tier(
+    market_name: &MarketName,
+    price: PriceValue,
+    steam_no_fee: Option<PriceValue>,
+    config: &AppConfig,
+) -> PriorityTier {
+    let scheduler = &config.scheduler;
+    if scheduler.watched_market_names.contains(market_name) {
+        return PriorityTier::Watched;
+    }
+
+    let is_near_profit = steam_no_fee.is_some_and(|steam_no_fee| {
+        price.multiply_by_percent(1.0 - scheduler.near_profit_pct / 100.0) < steam_no_fee
+    });
+    if is_near_profit || price >= scheduler.high_price_from {
+        return PriorityTier::High;
+    }
+    if price < scheduler.low_price_below {
+        return PriorityTier::Low;
+    }
+    PriorityTier::Normal
+}
+
+// Entry of the phase pricing table the listing can be bought by
+pub fn find_phase_deal<'a>(
+    listing: &CsfloatListingStruct,
+    config: &'a AppConfig,
+) -> Option<&'a PhasePrice> {
+    let phase = listing.item.phase.as_ref()?;
+    find_phase_price(
+        &listing.item.market_hash_name,
+        phase,
+        listing.get_price_value(),
+        &config.phases.prices,
+    )
+}
+
+// Tier of the rare pattern table the listing is worth reporting by
+pub fn find_rare_pattern_deal<'a>(
+    listing: &CsfloatListingStruct,
+    steam_engine: &SteamEngine,
+    config: &'a AppConfig,
+) -> Option<&'a PatternTier> {
+    let paint_seed = listing.item.paint_seed?;
+    let market_name = &listing.item.market_hash_name;
+    let steam_price = estimate_steam_sell_price(market_name, steam_engine, config)?;
+    find_pattern_tier(
+        market_name,
+        paint_seed,
+        listing.get_price_value(),
+        steam_price,
+        &config.patterns.tiers,
+    )
+}
+
+// Falling items need `falling_trend_extra_profit_pct` more to cover the further drop
+pub fn get_trend_adjusted_profit_pct(
+    min_profit_pct: f64,
+    event: &ProfitableListingEvent,
+    config: &AppConfig,
+) -> f64 {
+    match event.trend {
+        Trend::Falling(_) => min_profit_pct + config.strategy.falling_trend_extra_profit_pct,
+        Trend::Rising(_) | Trend::Flat => min_profit_pct,
+    }
+}
+
+pub fn is_need_notify_via_telegram(event: &ProfitableListingEvent, config: &AppConfig) -> bool {
+    check_notify_via_telegram(event, config).is_ok()
+}
+
+// Err is the first check the deal failed
+pub fn check_notify_via_telegram(
+    event: &ProfitableListingEvent,
+    config: &AppConfig,
+) -> Result<(), &'static str> {
+    if matches!(
+        event.kind,
+        ProfitableListingKind::Phase(_)
+            | ProfitableListingKind::RarePattern(_)
+            | ProfitableListingKind::Watchlist(_)
+            | ProfitableListingKind::LowFloat
+            | ProfitableListingKind::Sticker
+    ) {
+        return Ok(());
+    }
+    if !is_undercutting_floor(event, config) {
+        return Err("doesn't undercut the floor by strategy.notify_min_floor_undercut_pct");
+    }
+    if !is_above_min_profit_abs(event, config) {
+        return Err("profit is below strategy.min_profit_abs");
+    }
+
+    let min_profit_pct = match event.price_source {
+        PriceSource::Steam => {
+            get_trend_adjusted_profit_pct(config.strategy.tg_notify_min_profit_pct, event, config)
+        }
+        _ => config.strategy.tg_notify_min_profit_pct,
+    };
+    if event.price_source == PriceSource::Steam {
+        if !event.is_stable {
+            return Err("Steam price isn't stable");
+        }
+        if event.sold_per_week < config.strategy.min_sold_per_week {
+            return Err("sold per week is below strategy.min_sold_per_week");
+        }
+        if !is_within_max_days_to_sell(event, config) {
+            return Err("expected days to sell are above strategy.max_days_to_sell");
+        }
+    }
+    if event.profit_pct <= min_profit_pct {
+        return Err("profit is below strategy.tg_notify_min_profit_pct");
+    }
+    Ok(())
+}
+
+// `strategy.min_profit_abs` or the one of the highest band the price falls into
+pub fn get_min_profit_abs(price: PriceValue, config: &AppConfig) -> PriceValue {
+    config
+        .strategy
+        .min_profit_abs_bands
+        .iter()
+        .filter(|band| price >= band.min_price)
+        .max_by_key(|band| band.min_price)
+        .map_or(config.strategy.min_profit_abs, |band| band.min_profit_abs)
+}
+
+pub fn is_above_min_profit_abs(event: &ProfitableListingEvent, config: &AppConfig) -> bool {
+    let cost = get_buy_cost(event.venue, event.csfloat_price, config);
+    event.steam_no_fee.saturating_sub(cost) >= get_min_profit_abs(event.csfloat_price, config)
+}
+
+// Deals without the estimate are allowed
+pub fn is_within_max_days_to_sell(event: &ProfitableListingEvent, config: &AppConfig) -> bool {
+    let max_days = config.strategy.max_days_to_sell;
+    max_days <= 0.0 || event.expected_days_to_sell.is_none_or(|x| x <= max_days)
+}
+
+// What buying a listing costs in total, CSFloat adds its buyer fee to the price
+pub fn get_buy_cost(venue: Venue, price: PriceValue, config: &AppConfig) -> PriceValue {
+    match venue {
+        Venue::Csfloat => CsfloatFee::add_fee(price, &config.csfloat_fee),
+        Venue::Skinport | Venue::Dmarket => price,
+    }
+}
+
+// What is paid for each listing of the event, the marketplace fee included
+pub fn get_purchase_costs(
+    event: &ProfitableListingEvent,
+    config: &AppConfig,
+) -> Vec<(ListingId, PriceValue)> {
+    event
+        .get_purchases()
+        .into_iter()
+        .map(|(listing_id, price)| (listing_id, get_buy_cost(event.venue, price, config)))
+        .collect()
+}
+
+// Venues the autobuy can buy from, Skinport sales are only notified
+pub fn is_autobuy_venue(venue: Venue, config: &AppConfig) -> bool {
+    match venue {
+        Venue::Csfloat => true,
+        Venue::Dmarket => config.dmarket.autobuy,
+        Venue::Skinport => false,
     }
+}
+
+// Listings which are the only ones of their item have no floor and are allowed
+pub fn is_undercutting_floor(event: &ProfitableListingEvent, config: &AppConfig) -> bool {
+    let min_pct = config.strategy.notify_min_floor_undercut_pct;
+    min_pct <= 0.0 || event.floor_undercut_pct.is_none_or(|x| x >= min_pct)
+}
+
+// Listings without CSFloat reference can't be checked and are allowed
+pub fn is_below_predicted_price(event: &ProfitableListingEvent, config: &AppConfig) -> bool {
+    let ratio = config.autobuy.max_predicted_price_ratio;
+    match event.predicted_price {
+        Some(predicted_price) if ratio > 0.0 => {
+            (event.csfloat_price as f64) < predicted_price as f64 * ratio
+        }
+        _ => true,
+    }
+}
+
+// Open positions of the market name the autobuy may hold, commodities are bought in bulk
+pub fn get_max_positions(event: &ProfitableListingEvent, config: &AppConfig) -> u32 {
+    match event.strategy {
+        Some(StrategyName::Commodity) => config.strategies.commodity.max_positions_per_market,
+        _ => config.autobuy.max_positions_per_market,
+    }
+}
+
+// `balance` is the cached balance of the venue, not checked while unknown or in paper trading
+pub fn is_need_to_autobuy(
+    event: &ProfitableListingEvent,
+    config: &AppConfig,
+    risk_manager: &RiskManager,
+    balance: Option<PriceValue>,
+    now: DateTime<Utc>,
+) -> bool {
+    check_autobuy(event, config, risk_manager, balance, now).is_ok()
+}
 
-    event.is_stable
-        && event.sold_per_week >= MIN_SOLD_PER_WEEK
-        && event.profit_pct > TG_NOTIFY_MIN_PROFIT_PCT
+// The first check a deal failed to be bought
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutobuyRejection {
+    Rule(&'static str),
+    Risk(RiskRejection),
 }
 
-pub fn is_need_to_autobuy(event: &ProfitableListingEvent) -> bool {
-    event.kind == ProfitableListingKind::Profitable && event.profit_pct > AUTOBUY_FROM_PROFIT_PCT
+impl fmt::Display for AutobuyRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutobuyRejection::Rule(rule) => write!(f, "{}", rule),
+            AutobuyRejection::Risk(err) => write!(f, "risk limits: {:?}", err),
+        }
+    }
+}
+
+pub fn check_autobuy(
+    event: &ProfitableListingEvent,
+    config: &AppConfig,
+    risk_manager: &RiskManager,
+    balance: Option<PriceValue>,
+    now: DateTime<Utc>,
+) -> Result<(), AutobuyRejection> {
+    let prices: Vec<PriceValue> = get_purchase_costs(event, config)
+        .into_iter()
+        .map(|(_, x)| x)
+        .collect();
+    let is_affordable = config.autobuy.paper_trading
+        || balance.is_none_or(|balance| prices.iter().sum::<PriceValue>() <= balance);
+    let rejection = if !config.autobuy.enabled && !config.autobuy.paper_trading {
+        "autobuy is disabled"
+    } else if !is_affordable {
+        "balance is too low"
+    } else if !is_autobuy_venue(event.venue, config) {
+        "the venue isn't bought from"
+    } else if event.kind != ProfitableListingKind::Profitable {
+        "only profitable deals are bought"
+    } else if event.price_source != PriceSource::Steam {
+        "the price isn't from Steam"
+    } else if !is_below_predicted_price(event, config) {
+        "price is above autobuy.max_predicted_price_ratio of the predicted price"
+    } else if !is_above_min_profit_abs(event, config) {
+        "profit is below strategy.min_profit_abs"
+    } else if !is_within_max_days_to_sell(event, config) {
+        "expected days to sell are above strategy.max_days_to_sell"
+    } else if event.profit_pct
+        <= get_trend_adjusted_profit_pct(config.autobuy.from_profit_pct, event, config)
+    {
+        "profit is below autobuy.from_profit_pct"
+    } else {
+        return risk_manager
+            .check_batch(
+                &event.market_name,
+                &prices,
+                get_max_positions(event, config),
+                &config.autobuy,
+                now,
+            )
+            .map_err(AutobuyRejection::Risk);
+    };
+    Err(AutobuyRejection::Rule(rejection))
+}
+
+// Deals which are notified but not profitable enough to be bought without the operator
+pub fn is_need_to_confirm_buy(event: &ProfitableListingEvent, config: &AppConfig) -> bool {
+    config.autobuy.confirm_enabled
+        && event.venue == Venue::Csfloat
+        && event.kind == ProfitableListingKind::Profitable
+        && event.price_source == PriceSource::Steam
+        && is_below_predicted_price(event, config)
+        && is_above_min_profit_abs(event, config)
+        && is_within_max_days_to_sell(event, config)
+        && event.profit_pct
+            > get_trend_adjusted_profit_pct(config.strategy.tg_notify_min_profit_pct, event, config)
+        && event.profit_pct
+            <= get_trend_adjusted_profit_pct(config.autobuy.from_profit_pct, event, config)
+}
+
+// The highest bid which still leaves `auction.min_profit_pct`
+pub fn get_max_auction_bid(steam_no_fee: PriceValue, config: &AppConfig) -> PriceValue {
+    steam_no_fee.divide_by(1.0 + config.auction.min_profit_pct / 100.0)
+}
+
+// Compounded `decay_per_day_pct` for each day the item can't be resold
+pub fn apply_trade_hold_decay(
+    price: PriceValue,
+    trade_hold_days: u32,
+    config: &AppConfig,
+) -> PriceValue {
+    if trade_hold_days == 0 || config.trade_hold.decay_per_day_pct <= 0.0 {
+        return price;
+    }
+    let factor = (1.0 - config.trade_hold.decay_per_day_pct / 100.0).powi(trade_hold_days as i32);
+    price.multiply_by_percent(factor.max(0.0))
+}
+
+// `multiplier` part of the total price, the reference price of a listing is preferred
+fn estimate_applied_price(
+    applied: &[CsfloatSticker],
+    sticker_prices: &StickerPriceTable,
+    multiplier: f64,
+    max_wear: f64,
+) -> PriceValue {
+    if multiplier <= 0.0 {
+        return 0;
+    }
+
+    let total: PriceValue = applied
+        .iter()
+        .filter(|sticker| sticker.wear.unwrap_or(0.0) <= max_wear)
+        .filter_map(|sticker| {
+            sticker
+                .reference
+                .as_ref()
+                .map(|reference| reference.price)
+                .or_else(|| sticker_prices.get_price(&sticker.name))
+        })
+        .sum();
+    total.multiply_by_percent(multiplier)
+}
+
+// Part of stickers price that is expected to be paid by a buyer on top of the skin price.
+// Scraped stickers above `max_wear` are worth nothing.
+pub fn estimate_stickers_value(
+    item: &CsfloatListingItem,
+    sticker_prices: &StickerPriceTable,
+    config: &AppConfig,
+) -> PriceValue {
+    estimate_applied_price(
+        &item.stickers,
+        sticker_prices,
+        config.stickers.value_multiplier,
+        config.stickers.max_wear,
+    )
+}
+
+// Full price of the stickers which aren't scraped above `max_wear`
+pub fn estimate_raw_stickers_value(
+    item: &CsfloatListingItem,
+    sticker_prices: &StickerPriceTable,
+    config: &AppConfig,
+) -> PriceValue {
+    estimate_applied_price(
+        &item.stickers,
+        sticker_prices,
+        1.0,
+        config.stickers.max_wear,
+    )
+}
+
+pub fn estimate_applied_value(
+    item: &CsfloatListingItem,
+    sticker_prices: &StickerPriceTable,
+    config: &AppConfig,
+) -> AppliedValue {
+    AppliedValue {
+        stickers: estimate_stickers_value(item, sticker_prices, config),
+        keychains: estimate_applied_price(
+            &item.keychains,
+            sticker_prices,
+            config.stickers.keychain_multiplier,
+            f64::MAX,
+        ),
+        patches: estimate_applied_price(
+            &item.patches,
+            sticker_prices,
+            config.stickers.patch_multiplier,
+            f64::MAX,
+        ),
+    }
 }
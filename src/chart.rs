@@ -0,0 +1,219 @@
+use crate::{
+    price_history::{PriceKind, PricePoint},
+    prices::PriceValue,
+};
+
+// Small PNG line charts for Telegram. There is no text on them, the caption of the
+// photo tells the series and their ranges, see `price_history::summarize`.
+const WIDTH: usize = 640;
+const HEIGHT: usize = 320;
+const MARGIN: usize = 16;
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+type Rgb = [u8; 3];
+
+const WHITE: Rgb = [255, 255, 255];
+const GRID: Rgb = [225, 225, 225];
+const AXIS: Rgb = [120, 120, 120];
+const FLOOR: Rgb = [30, 100, 220];
+const LISTING: Rgb = [150, 190, 240];
+const STEAM: Rgb = [40, 160, 60];
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Canvas {
+            width,
+            height,
+            pixels: vec![WHITE; width * height],
+        }
+    }
+
+    fn set(&mut self, x: i64, y: i64, color: Rgb) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        self.pixels[y as usize * self.width + x as usize] = color;
+    }
+
+    // Bresenham's line, 2 pixels thick
+    fn line(&mut self, (x0, y0): (i64, i64), (x1, y1): (i64, i64), color: Rgb) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+        loop {
+            self.set(x, y, color);
+            self.set(x, y + 1, color);
+            if x == x1 && y == y1 {
+                return;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn dot(&mut self, x: i64, y: i64, color: Rgb) {
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            self.set(x + dx, y + dy, color);
+        }
+    }
+
+    fn to_png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width) {
+            // no filter
+            raw.push(0);
+            raw.extend(row.iter().flatten());
+        }
+        encode_png(self.width as u32, self.height as u32, &raw)
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// 8-bit RGB, `raw` are the rows, each prefixed with its filter type
+fn encode_png(width: u32, height: u32, raw: &[u8]) -> Vec<u8> {
+    let mut header = vec![];
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // bit depth, RGB, deflate, adaptive filtering, no interlace
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(
+        &mut png,
+        b"IDAT",
+        &miniz_oxide::deflate::compress_to_vec_zlib(raw, 6),
+    );
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// CSFloat floor and Steam as lines, prices of single listings as dots. None without points.
+pub fn render_price_chart(points: &[PricePoint]) -> Option<Vec<u8>> {
+    let min_ts = points.iter().map(|x| x.created_at.timestamp()).min()?;
+    let max_ts = points.iter().map(|x| x.created_at.timestamp()).max()?;
+    let min_price = points.iter().map(|x| x.price).min()?;
+    let max_price = points.iter().map(|x| x.price).max()?;
+    // a flat series is drawn in the middle
+    let pad = ((max_price - min_price) / 10).max(1);
+    let (min_price, max_price) = (min_price.saturating_sub(pad), max_price + pad);
+
+    let (left, top) = (MARGIN as i64, MARGIN as i64);
+    let (right, bottom) = ((WIDTH - MARGIN) as i64, (HEIGHT - MARGIN) as i64);
+    let to_x = |ts: i64| match max_ts > min_ts {
+        true => left + (ts - min_ts) * (right - left) / (max_ts - min_ts),
+        false => (left + right) / 2,
+    };
+    let to_y = |price: PriceValue| {
+        bottom - ((price - min_price) as i64 * (bottom - top) / (max_price - min_price) as i64)
+    };
+
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    for i in 1..4 {
+        let y = top + (bottom - top) * i / 4;
+        canvas.line((left, y), (right, y), GRID);
+    }
+    canvas.line((left, bottom), (right, bottom), AXIS);
+    canvas.line((left, top), (left, bottom), AXIS);
+
+    for point in points.iter().filter(|x| x.kind == PriceKind::Listing) {
+        canvas.dot(
+            to_x(point.created_at.timestamp()),
+            to_y(point.price),
+            LISTING,
+        );
+    }
+    for (kind, color) in [(PriceKind::Floor, FLOOR), (PriceKind::Steam, STEAM)] {
+        let series: Vec<(i64, i64)> = points
+            .iter()
+            .filter(|x| x.kind == kind)
+            .map(|x| (to_x(x.created_at.timestamp()), to_y(x.price)))
+            .collect();
+        // a step line, the price holds until the next change
+        for pair in series.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            canvas.line(from, (to.0, from.1), color);
+            canvas.line((to.0, from.1), to, color);
+        }
+        if let Some(&(x, y)) = series.last() {
+            canvas.line((x, y), (right, y), color);
+        }
+    }
+    Some(canvas.to_png())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_render_price_chart() {
+        assert_eq!(render_price_chart(&[]), None);
+
+        let now = Utc::now();
+        let point = |hours, kind, price| PricePoint {
+            created_at: now - Duration::hours(hours),
+            market_name: "AK-47 | Redline (Field-Tested)".into(),
+            listing_id: None,
+            kind,
+            price,
+        };
+        let png = render_price_chart(&[
+            point(48, PriceKind::Floor, 12_00),
+            point(24, PriceKind::Listing, 12_50),
+            point(12, PriceKind::Floor, 11_00),
+            point(6, PriceKind::Steam, 15_00),
+        ])
+        .unwrap();
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], (WIDTH as u32).to_be_bytes());
+        assert_eq!(png[20..24], (HEIGHT as u32).to_be_bytes());
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // a single point is fine too
+        assert!(render_price_chart(&[point(1, PriceKind::Steam, 15_00)]).is_some());
+    }
+}
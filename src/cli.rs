@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+
+use crate::{
+    backtest::BacktestArgs,
+    dry_run::{AnalyzeArgs, EvaluateArgs},
+};
+
+// Without a subcommand the bot is started
+#[derive(Debug, Parser)]
+#[command(about = "Buys CSFloat listings which sell on Steam with a profit")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum Command {
+    #[command(about = "Replay archived responses of the range through the event processors")]
+    Backtest(BacktestArgs),
+    #[command(about = "Print the Steam analysis of a saved listings page as JSON")]
+    Analyze(AnalyzeArgs),
+    #[command(about = "Print whether the deals of a saved CSFloat listing would be bought")]
+    Evaluate(EvaluateArgs),
+}
+
+// RFC 3339, e.g. 2024-02-19T00:00:00Z
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|x| x.with_timezone(&Utc))
+        .map_err(|err| format!("Can't parse {:?}: {}", value, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Command>, clap::Error> {
+        Cli::try_parse_from([&["bot"], args].concat()).map(|x| x.command)
+    }
+
+    #[test]
+    fn test_parse_without_subcommand() {
+        assert_eq!(parse(&[]).unwrap(), None);
+        assert!(parse(&["unknown"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_backtest_args() {
+        let Some(Command::Backtest(args)) = parse(&[
+            "backtest",
+            "2024-02-19T00:00:00Z",
+            "2024-02-20T00:00:00+00:00",
+        ])
+        .unwrap() else {
+            panic!("not a backtest");
+        };
+        assert_eq!(args.to - args.from, Duration::days(1));
+        assert!(args.check().is_ok());
+
+        assert!(parse(&["backtest", "2024-02-19T00:00:00Z"]).is_err());
+        assert!(parse(&["backtest", "2024-02-19", "2024-02-20"]).is_err());
+        let Some(Command::Backtest(args)) =
+            parse(&["backtest", "2024-02-20T00:00:00Z", "2024-02-19T00:00:00Z"]).unwrap()
+        else {
+            panic!("not a backtest");
+        };
+        assert!(args.check().is_err());
+    }
+
+    #[test]
+    fn test_parse_analyze_args() {
+        assert_eq!(
+            parse(&["analyze", "page.html"]).unwrap(),
+            Some(Command::Analyze(AnalyzeArgs {
+                path: "page.html".to_string(),
+                at: None
+            }))
+        );
+        assert_eq!(
+            parse(&["analyze", "page.html", "2024-02-19T00:00:00Z"]).unwrap(),
+            Some(Command::Analyze(AnalyzeArgs {
+                path: "page.html".to_string(),
+                at: Some(Utc.with_ymd_and_hms(2024, 2, 19, 0, 0, 0).unwrap())
+            }))
+        );
+        assert!(parse(&["analyze"]).is_err());
+        assert!(parse(&["analyze", "page.html", "2024-02-19"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_evaluate_args() {
+        assert_eq!(
+            parse(&["evaluate", "listing.json"]).unwrap(),
+            Some(Command::Evaluate(EvaluateArgs {
+                path: "listing.json".to_string()
+            }))
+        );
+        assert!(parse(&["evaluate", "a.json", "b.json"]).is_err());
+    }
+}
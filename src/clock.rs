@@ -0,0 +1,95 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+// Source of the current time for the time-dependent logic, so tests and the backtest
+// can control it. `now` and `instant` of a clock move together.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+    fn instant(&self) -> Instant;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+pub fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+// Stands still until it's moved by `set` or `advance`. Instants before the start
+// aren't representable, they're clamped to the start.
+#[derive(Debug)]
+pub struct MockClock {
+    start: DateTime<Utc>,
+    start_instant: Instant,
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl MockClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        MockClock {
+            start,
+            start_instant: Instant::now(),
+            now: Mutex::new(start),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+
+    fn instant(&self) -> Instant {
+        let elapsed = (self.now() - self.start).to_std().unwrap_or_default();
+        self.start_instant + elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let start = Utc::now();
+        let clock = MockClock::new(start);
+        let instant = clock.instant();
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::seconds(90));
+        assert_eq!(clock.now(), start + Duration::seconds(90));
+        assert_eq!(
+            clock.instant() - instant,
+            std::time::Duration::from_secs(90)
+        );
+
+        clock.set(start - Duration::hours(1));
+        assert_eq!(clock.now(), start - Duration::hours(1));
+        assert_eq!(clock.instant(), instant);
+    }
+}
@@ -45,6 +45,27 @@ pub const DB_SAVE_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_
 pub const CSFLOAT_ONE_LISTING_REQ_INTERVAL: std::time::Duration =
     tokio::time::Duration::from_secs(3);
 
+// Pause of the one-listing refresher after 429 from csfloat.com,
+// doubled for each next 429 in a row.
+pub const CSFLOAT_RATE_LIMITED_BACKOFF: std::time::Duration = std::time::Duration::from_secs(60);
+pub const CSFLOAT_RATE_LIMITED_MAX_BACKOFF: std::time::Duration =
+    std::time::Duration::from_secs(15 * 60);
+// Per-proxy requests stats of the refresher are logged that often
+pub const PROXY_POOL_SUMMARY_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(10 * 60);
+// Old steam analyses are evicted and stale ones are requested to be refreshed that often
+pub const STEAM_EVICTION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+// Csfloat listings not refreshed for `scheduler.listing_ttl_secs` are removed that often
+pub const CSFLOAT_EXPIRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+// Dropped events are checked against `queues.drop_alert_per_min` that often
+pub const QUEUE_MONITOR_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+// Queue depths and engine sizes in `Stats` are sampled that often
+pub const STATS_SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+// Connections of the admin API left after the shutdown are closed after that, e.g. of the dashboard
+pub const ADMIN_API_SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+// Paper purchases whose listing isn't refreshed in that time are never checked, e.g. of other shards
+pub const PAPER_CHECK_TTL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
 // my Telegram ID
 // removed
 pub const MY_TG_ID: ChatId = ChatId(0);
@@ -1,55 +1,318 @@
-use std::collections::HashSet;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    time::{Duration, Instant},
+};
 
-use crate::types::ListingId;
+use serde::Serialize;
 
+use crate::{
+    clock::{system_clock, SharedClock},
+    types::ListingId,
+};
+
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+// after that the listing is refreshed only in its regular turn
+const RETRY_MAX_ATTEMPTS: u32 = 5;
+
+// base * 2^(attempt - 1), but not more than max
+pub fn backoff_delay(base: Duration, attempt: u32, max: Duration) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    base.saturating_mul(factor).min(max)
+}
+
+// How often a listing is refreshed relative to others:
+// a tier is refreshed twice as often as the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PriorityTier {
+    Low,
+    Normal,
+    High,
+    Watched,
+}
+
+impl PriorityTier {
+    // in virtual time units of the scheduler
+    fn refresh_period(&self) -> u64 {
+        match self {
+            PriorityTier::Low => 8,
+            PriorityTier::Normal => 4,
+            PriorityTier::High => 2,
+            PriorityTier::Watched => 1,
+        }
+    }
+}
+
+// Position of a listing in the queue, see `CsfloatScheduler::get_queue`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueuedListing {
+    pub listing_id: ListingId,
+    pub tier: PriorityTier,
+    // in virtual time units of the scheduler
+    pub due_in: u64,
+    pub failed_attempts: u32,
+}
+
+struct ScheduledListing {
+    tier: PriorityTier,
+    // entries of the heap with other `seq` are outdated
+    seq: u64,
+}
+
+// Listings are ordered by next due (virtual) time, so with the same request budget
+// higher tiers are refreshed more often than lower ones.
 pub struct CsfloatScheduler {
-    // for fast existance check
-    hs: HashSet<ListingId>,
-    // contains ListingId in ordered way
-    v: Vec<ListingId>,
-    // pointer to a item
-    idx: usize,
-    // mb also add Vec for temporary failed listings
+    listings: HashMap<ListingId, ScheduledListing>,
+    // (due time, seq) of each scheduled refresh, stale entries are skipped lazily
+    heap: BinaryHeap<Reverse<(u64, u64, ListingId)>>,
+    // due time of the last returned listing
+    now: u64,
+    next_seq: u64,
+    // temporary failed listings, retried before regular ones once due
+    retries: BinaryHeap<Reverse<(Instant, ListingId)>>,
+    failed_attempts: HashMap<ListingId, u32>,
+    // due times of the retries
+    clock: SharedClock,
+}
+
+impl Default for CsfloatScheduler {
+    fn default() -> Self {
+        CsfloatScheduler::new()
+    }
 }
 
 impl CsfloatScheduler {
     pub fn new() -> Self {
+        CsfloatScheduler::with_clock(system_clock())
+    }
+
+    pub fn with_clock(clock: SharedClock) -> Self {
         CsfloatScheduler {
-            hs: HashSet::new(),
-            v: Vec::<ListingId>::new(),
-            idx: 0,
+            listings: HashMap::new(),
+            heap: BinaryHeap::new(),
+            now: 0,
+            next_seq: 0,
+            retries: BinaryHeap::new(),
+            failed_attempts: HashMap::new(),
+            clock,
         }
     }
 
     pub fn get_size(&self) -> usize {
-        self.v.len()
+        self.listings.len()
+    }
+
+    fn schedule(&mut self, listing_id: &ListingId, tier: PriorityTier) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.listings
+            .insert(listing_id.clone(), ScheduledListing { tier, seq });
+        self.heap.push(Reverse((
+            self.now + tier.refresh_period(),
+            seq,
+            listing_id.clone(),
+        )));
+
+        // drop stale entries once they take most of the heap
+        if self.heap.len() > 2 * self.listings.len() + 1024 {
+            let listings = &self.listings;
+            self.heap
+                .retain(|Reverse((_, seq, id))| listings.get(id).is_some_and(|x| x.seq == *seq));
+        }
     }
 
     pub fn upsert_listing(&mut self, listing_id: &ListingId) {
-        if !self.hs.contains(listing_id) {
-            self.hs.insert(listing_id.clone());
-            self.v.push(listing_id.clone());
+        if !self.listings.contains_key(listing_id) {
+            self.schedule(listing_id, PriorityTier::Normal);
+        }
+    }
+
+    pub fn set_priority(&mut self, listing_id: &ListingId, tier: PriorityTier) {
+        match self.listings.get(listing_id) {
+            Some(scheduled) if scheduled.tier != tier => self.schedule(listing_id, tier),
+            _ => {}
         }
     }
 
     pub fn remove_listing(&mut self, listing_id: &ListingId) {
-        if self.hs.contains(listing_id) {
-            self.hs.remove(listing_id);
-            self.v.retain(|x| *x != *listing_id);
+        self.listings.remove(listing_id);
+        self.failed_attempts.remove(listing_id);
+    }
+
+    pub fn report_success(&mut self, listing_id: &ListingId) {
+        self.failed_attempts.remove(listing_id);
+    }
+
+    pub fn report_failure(&mut self, listing_id: &ListingId) {
+        if !self.listings.contains_key(listing_id) {
+            return;
         }
+        let attempts = self.failed_attempts.entry(listing_id.clone()).or_insert(0);
+        *attempts += 1;
+        if *attempts > RETRY_MAX_ATTEMPTS {
+            self.failed_attempts.remove(listing_id);
+            return;
+        }
+
+        let delay = backoff_delay(RETRY_BASE_DELAY, *attempts, RETRY_MAX_DELAY);
+        self.retries
+            .push(Reverse((self.clock.instant() + delay, listing_id.clone())));
+    }
+
+    // The first `limit` listings in the order of their regular refresh,
+    // retries of failed listings may go before them
+    pub fn get_queue(&self, limit: usize) -> Vec<QueuedListing> {
+        let mut queue: Vec<_> = self
+            .heap
+            .iter()
+            .filter_map(|Reverse((due, seq, listing_id))| {
+                let scheduled = self.listings.get(listing_id)?;
+                (scheduled.seq == *seq).then_some((*due, *seq, listing_id, scheduled.tier))
+            })
+            .collect();
+        queue.sort_unstable_by_key(|(due, seq, _, _)| (*due, *seq));
+        queue
+            .into_iter()
+            .take(limit)
+            .map(|(due, _, listing_id, tier)| QueuedListing {
+                listing_id: listing_id.clone(),
+                tier,
+                due_in: due.saturating_sub(self.now),
+                failed_attempts: self.failed_attempts.get(listing_id).copied().unwrap_or(0),
+            })
+            .collect()
     }
 
     pub fn get_next(&mut self) -> Option<ListingId> {
-        if self.idx == 0 && self.v.is_empty() {
-            return None;
+        let now = self.clock.instant();
+        while let Some(Reverse((retry_at, _))) = self.retries.peek() {
+            if *retry_at > now {
+                break;
+            }
+            let Reverse((_, listing_id)) = self.retries.pop().unwrap();
+            // listing may be removed or refreshed successfully in its regular turn
+            if self.failed_attempts.contains_key(&listing_id) {
+                return Some(listing_id);
+            }
+        }
+
+        while let Some(Reverse((due, seq, listing_id))) = self.heap.pop() {
+            let tier = match self.listings.get(&listing_id) {
+                Some(scheduled) if scheduled.seq == seq => scheduled.tier,
+                _ => continue,
+            };
+
+            self.now = self.now.max(due);
+            self.schedule(&listing_id, tier);
+            return Some(listing_id);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use chrono::Utc;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_round_robin_within_tier() {
+        let mut scheduler = CsfloatScheduler::new();
+        for id in ["1", "2", "3"] {
+            scheduler.upsert_listing(&id.into());
         }
+        scheduler.remove_listing(&"2".into());
 
-        if self.idx >= self.v.len() {
-            self.idx = 0;
+        let order: Vec<ListingId> = (0..4).filter_map(|_| scheduler.get_next()).collect();
+        assert_eq!(order, vec!["1", "3", "1", "3"]);
+        assert_eq!(scheduler.get_size(), 2);
+    }
+
+    #[test]
+    fn test_higher_tier_is_refreshed_more_often() {
+        let mut scheduler = CsfloatScheduler::new();
+        let (low, high) = (ListingId::from("low"), ListingId::from("high"));
+        scheduler.upsert_listing(&low);
+        scheduler.upsert_listing(&high);
+        scheduler.set_priority(&low, PriorityTier::Low);
+        scheduler.set_priority(&high, PriorityTier::High);
+
+        let picked: Vec<ListingId> = (0..40).filter_map(|_| scheduler.get_next()).collect();
+        let high_count = picked.iter().filter(|x| **x == high).count();
+        assert_eq!(high_count, 32);
+    }
+
+    #[test]
+    fn test_failed_listing_is_retried_with_backoff() {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let mut scheduler = CsfloatScheduler::with_clock(clock.clone());
+        let (failed, other) = (ListingId::from("failed"), ListingId::from("other"));
+        scheduler.upsert_listing(&failed);
+        scheduler.upsert_listing(&other);
+        let now = clock.instant();
+
+        assert_eq!(scheduler.get_next(), Some(failed.clone()));
+        scheduler.report_failure(&failed);
+        assert_eq!(scheduler.get_next(), Some(other.clone()));
+        clock.advance(chrono::Duration::seconds(RETRY_BASE_DELAY.as_secs() as i64));
+        assert_eq!(scheduler.get_next(), Some(failed.clone()));
+
+        // the second retry waits twice longer
+        scheduler.report_failure(&failed);
+        assert_eq!(
+            scheduler.retries.peek(),
+            Some(&Reverse((now + 3 * RETRY_BASE_DELAY, failed.clone())))
+        );
+
+        // refreshed in the regular turn, the retry is dropped
+        scheduler.report_success(&failed);
+        clock.advance(chrono::Duration::seconds(RETRY_MAX_DELAY.as_secs() as i64));
+        scheduler.get_next();
+        assert!(scheduler.retries.is_empty());
+        assert!(scheduler.failed_attempts.is_empty());
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let base = Duration::from_secs(1);
+        let max = Duration::from_secs(10);
+        assert_eq!(backoff_delay(base, 1, max), base);
+        assert_eq!(backoff_delay(base, 3, max), Duration::from_secs(4));
+        assert_eq!(backoff_delay(base, 40, max), max);
+    }
+
+    #[test]
+    fn test_get_queue() {
+        let mut scheduler = CsfloatScheduler::new();
+        for id in ["1", "2", "3"] {
+            scheduler.upsert_listing(&id.into());
         }
-        let result = self.v.get(self.idx).unwrap();
-        self.idx += 1;
+        scheduler.set_priority(&"3".into(), PriorityTier::Watched);
+        scheduler.remove_listing(&"2".into());
+
+        let queue = scheduler.get_queue(10);
+        assert_eq!(
+            queue
+                .iter()
+                .map(|x| (x.listing_id.to_string(), x.tier, x.due_in))
+                .collect::<Vec<_>>(),
+            vec![
+                ("3".to_string(), PriorityTier::Watched, 1),
+                ("1".to_string(), PriorityTier::Normal, 4),
+            ]
+        );
+        assert_eq!(scheduler.get_queue(1).len(), 1);
+    }
 
-        Some(result.to_string())
+    #[test]
+    fn test_empty_scheduler() {
+        let mut scheduler = CsfloatScheduler::new();
+        assert_eq!(scheduler.get_next(), None);
+        scheduler.upsert_listing(&"1".into());
+        scheduler.remove_listing(&"1".into());
+        assert_eq!(scheduler.get_next(), None);
     }
 }
@@ -1,13 +1,26 @@
-use std::{env, time::Duration};
+use std::{collections::HashMap, env, fmt, sync::Arc, time::Duration};
 
 use chrono::{DateTime, Utc};
-use reqwest::{
-    header::{HeaderMap, HeaderValue, AUTHORIZATION},
-    Client, Proxy,
-};
-use tracing::{error, warn};
+use reqwest::{Proxy, StatusCode};
+use serde::Serialize;
+use tokio::sync::{mpsc::Sender, Mutex};
+use tracing::{debug, error, warn};
 
-use crate::{prices::PriceValue, types::ListingId};
+use crate::{
+    clock::{system_clock, SharedClock},
+    config::{SharedConfig, SimilarListingsConfig},
+    csfloat_client::{
+        is_key_rejected, parse_rate_limit_headers, ApiKeys, CsfloatApiError, CsfloatClient,
+    },
+    csfloat_fetcher::{split_listings_page, RateLimiter},
+    dmarket::DmarketClient,
+    events::SecEvent,
+    leadership::Leadership,
+    models::{CsfloatListingState, CsfloatListingStruct},
+    prices::PriceValue,
+    stats::Stats,
+    types::{ListingId, MarketName},
+};
 
 // #[derive(Debug, PartialEq)]
 // pub enum CsfloatBuyResult {
@@ -19,15 +32,326 @@ use crate::{prices::PriceValue, types::ListingId};
 //     async fn buy_listing(&self, listing_id: &ListingId, price: PriceValue) -> CsfloatBuyResult;
 // }
 
+// What happened to a purchase attempt, stored in the ledger as JSON
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuyOutcome {
+    pub is_success: bool,
+    // None when no request was sent
+    pub status: Option<u16>,
+    pub response: serde_json::Value,
+}
+
+impl BuyOutcome {
+    pub fn failed(err: &CsfloatBuyError) -> BuyOutcome {
+        BuyOutcome {
+            is_success: false,
+            status: None,
+            response: serde_json::json!({ "error": err }),
+        }
+    }
+
+    // paper-trading purchases always succeed, availability is checked later
+    pub fn paper() -> BuyOutcome {
+        BuyOutcome {
+            is_success: true,
+            status: None,
+            response: serde_json::json!({ "paper_trading": true }),
+        }
+    }
+}
+
+// Why a purchase was aborted before the buy request, the listing could be sold
+// or repriced since it was found
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyError {
+    NotListed(CsfloatListingState),
+    PriceChanged {
+        expected: PriceValue,
+        actual: PriceValue,
+    },
+    // the listing couldn't be fetched or parsed
+    Unavailable(String),
+    // another CSFloat listing of the item is cheaper, the Steam analysis could be stale
+    AboveFloor {
+        price: PriceValue,
+        floor: PriceValue,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::NotListed(state) => write!(f, "listing is {}", state),
+            VerifyError::PriceChanged { expected, actual } => {
+                write!(f, "price changed from {} to {}", expected, actual)
+            }
+            VerifyError::Unavailable(reason) => write!(f, "listing is unavailable: {}", reason),
+            VerifyError::AboveFloor { price, floor } => {
+                write!(f, "price {} is above the CSFloat floor {}", price, floor)
+            }
+        }
+    }
+}
+
+// Why `buy_listing` failed, parsed from the CSFloat error body
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsfloatBuyError {
+    InsufficientBalance,
+    // seconds until the next purchase can be tried
+    RateLimited { retry_after: Option<u64> },
+    AlreadySold,
+    PriceChanged,
+    AuthExpired,
+    // CSFloat error code, the HTTP status when the body has none
+    Api { code: i64, message: String },
+    // the listing didn't pass the check before the purchase
+    Aborted(VerifyError),
+    // the request clearly wasn't executed, e.g. the connection failed or a 5xx without a body,
+    // the purchase is retried after `autobuy.retry_delay_ms`
+    Request(String),
+    // the request was sent, but whether it was executed is unknown, e.g. it timed out or the
+    // answer can't be read; it's never retried, see `CsfloatAutobuy::reconcile_purchase`
+    Unknown(String),
+    // purchases are held after repeated failures, see `CircuitBreaker`
+    CircuitOpen { until: DateTime<Utc> },
+    // the leader lock is lost, another instance buys, see `Leadership::confirm`
+    NotLeader,
+}
+
+impl CsfloatBuyError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, CsfloatBuyError::Request(_))
+    }
+
+    // Errors which fail every following purchase too
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            CsfloatBuyError::InsufficientBalance | CsfloatBuyError::AuthExpired
+        )
+    }
+
+    // Failures of the account or CSFloat, not of the listing, trip the circuit breaker
+    pub fn is_breaker_failure(&self) -> bool {
+        matches!(
+            self,
+            CsfloatBuyError::InsufficientBalance
+                | CsfloatBuyError::AuthExpired
+                | CsfloatBuyError::Api { .. }
+                | CsfloatBuyError::Request(_)
+                | CsfloatBuyError::Unknown(_)
+        )
+    }
+}
+
+impl fmt::Display for CsfloatBuyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsfloatBuyError::InsufficientBalance => write!(f, "insufficient balance"),
+            CsfloatBuyError::RateLimited {
+                retry_after: Some(secs),
+            } => write!(f, "rate limited for {}s", secs),
+            CsfloatBuyError::RateLimited { retry_after: None } => write!(f, "rate limited"),
+            CsfloatBuyError::AlreadySold => write!(f, "already sold"),
+            CsfloatBuyError::PriceChanged => write!(f, "price changed"),
+            CsfloatBuyError::AuthExpired => write!(f, "API key is expired or revoked"),
+            CsfloatBuyError::Api { code, message } => write!(f, "API error {}: {}", code, message),
+            CsfloatBuyError::Aborted(err) => write!(f, "aborted, {}", err),
+            CsfloatBuyError::Request(err) => write!(f, "request failed: {}", err),
+            CsfloatBuyError::Unknown(err) => write!(f, "unknown result: {}", err),
+            CsfloatBuyError::CircuitOpen { until } => {
+                write!(f, "autobuy is held after repeated failures until {}", until)
+            }
+            CsfloatBuyError::NotLeader => write!(f, "not the leader anymore"),
+        }
+    }
+}
+
+// The balance is unknown, a zero one would skip every deal
+#[derive(Debug)]
+pub enum BalanceError {
+    Request(reqwest::Error),
+    // the response has no `user.balance`
+    Malformed(String),
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BalanceError::Request(err) => write!(f, "request failed: {}", err),
+            BalanceError::Malformed(body) => write!(f, "no balance in the response: {}", body),
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
+impl From<reqwest::Error> for BalanceError {
+    fn from(err: reqwest::Error) -> Self {
+        BalanceError::Request(err)
+    }
+}
+
+// CSFloat answers with {"code": 4, "message": "..."}, the kind of the error is
+// only told by the message
+pub fn parse_buy_error(
+    status: StatusCode,
+    body: &str,
+    retry_after: Option<u64>,
+) -> CsfloatBuyError {
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return CsfloatBuyError::RateLimited { retry_after };
+    }
+    // the key is retired by the client already, so the purchase is retried with the next one
+    if is_key_rejected(status, body) {
+        return CsfloatBuyError::AuthExpired;
+    }
+
+    let api_error = CsfloatApiError::parse(body);
+    let message = api_error
+        .as_ref()
+        .map_or(body, |x| x.message.as_str())
+        .to_string();
+    let text = message.to_lowercase();
+    if api_error.as_ref().is_some_and(|x| x.is_auth_error()) {
+        CsfloatBuyError::AuthExpired
+    } else if text.contains("balance") || text.contains("insufficient") {
+        CsfloatBuyError::InsufficientBalance
+    } else if text.contains("sold") || text.contains("not listed") || text.contains("no longer") {
+        CsfloatBuyError::AlreadySold
+    } else if text.contains("price") {
+        CsfloatBuyError::PriceChanged
+    } else {
+        CsfloatBuyError::Api {
+            code: api_error.map_or(status.as_u16() as i64, |x| x.code),
+            message,
+        }
+    }
+}
+
+pub fn check_listing(listing: &CsfloatListingStruct, price: PriceValue) -> Result<(), VerifyError> {
+    if listing.state != CsfloatListingState::Listed {
+        return Err(VerifyError::NotListed(listing.state.clone()));
+    }
+    if listing.get_price_value() != price {
+        return Err(VerifyError::PriceChanged {
+            expected: price,
+            actual: listing.get_price_value(),
+        });
+    }
+    Ok(())
+}
+
+// `listings` are the cheapest ones of the market name, the candidate itself is among them
+// when it's still listed
+pub fn check_similar_listings(
+    listing_id: &ListingId,
+    price: PriceValue,
+    listings: &[CsfloatListingStruct],
+    max_above_floor_pct: f64,
+) -> Result<(), VerifyError> {
+    let floor = listings
+        .iter()
+        .filter(|x| &x.id != listing_id && x.state == CsfloatListingState::Listed)
+        .map(|x| x.get_price_value())
+        .min();
+    match floor {
+        Some(floor) if price as f64 > floor as f64 * (1.0 + max_above_floor_pct / 100.0) => {
+            Err(VerifyError::AboveFloor { price, floor })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Holds the purchases for `autobuy.breaker_cooldown_secs` after `autobuy.breaker_failures`
+// failed ones in a row, or until /reset_autobuy. 0 failures disables it.
+// The thresholds are read from the live config, so a reload applies to the next purchase
+pub struct CircuitBreaker {
+    config: SharedConfig,
+    failures: u32,
+    open_until: Option<DateTime<Utc>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: SharedConfig) -> Self {
+        CircuitBreaker {
+            config,
+            failures: 0,
+            open_until: None,
+        }
+    }
+
+    // None while purchases are allowed
+    pub fn get_open_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.open_until.filter(|until| *until > now)
+    }
+
+    // Returns true when the breaker has just tripped
+    pub fn record(
+        &mut self,
+        result: &Result<BuyOutcome, CsfloatBuyError>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        match result {
+            Ok(outcome) if outcome.is_success => self.failures = 0,
+            Err(err) if err.is_breaker_failure() => self.failures += 1,
+            _ => return false,
+        }
+        let config = &self.config.load().autobuy;
+        if config.breaker_failures == 0 || self.failures < config.breaker_failures {
+            return false;
+        }
+        self.failures = 0;
+        self.open_until = Some(now + config.breaker_cooldown());
+        true
+    }
+
+    // Returns true when it was open
+    pub fn reset(&mut self, now: DateTime<Utc>) -> bool {
+        let is_open = self.get_open_until(now).is_some();
+        self.failures = 0;
+        self.open_until = None;
+        is_open
+    }
+}
+
 pub struct CsfloatAutobuy {
     // pub api_key: String,
     pub next_call: DateTime<Utc>,
-    pub client: Client,
+    pub client: CsfloatClient,
+    buy_cooldown: Duration,
+    verify_before_buy: bool,
+    // budget of the similar listings requests, separate from the purchases
+    similar_limiter: RateLimiter,
+    // None until the first refresh
+    balance: Option<PriceValue>,
+    is_low_balance: bool,
+    // buys DMarket deals, None without its keys
+    pub dmarket: Option<DmarketClient>,
+    breaker: CircuitBreaker,
+    // auctions bid on by their end, each one is bid once
+    bids: HashMap<ListingId, DateTime<Utc>>,
+    // only the leader buys, see `set_leadership`
+    leadership: Leadership,
+    // time of the purchase cooldown and the circuit breaker
+    clock: SharedClock,
 }
 
 impl CsfloatAutobuy {
-    pub fn from_env() -> CsfloatAutobuy {
-        let api_key = env::var("CSFLOAT_API_KEY").expect("CSFLOAT_API_KEY must be set");
+    pub fn from_env(
+        config: SharedConfig,
+        stats: Arc<Mutex<Stats>>,
+        alert_tx: Sender<SecEvent>,
+    ) -> CsfloatAutobuy {
+        // the next key is used when CSFloat rejects the current one
+        let api_keys = [env::var("CSFLOAT_API_KEYS"), env::var("CSFLOAT_API_KEY")]
+            .into_iter()
+            .flatten()
+            .map(|x| ApiKeys::parse(&x))
+            .find(|x| !x.is_empty())
+            .expect("CSFLOAT_API_KEYS or CSFLOAT_API_KEY must be set");
         let proxy = match env::var("CSFLOAT_PROXY") {
             Ok(val) => Some(val),
             Err(e) => {
@@ -35,57 +359,183 @@ impl CsfloatAutobuy {
                 None
             }
         };
-        CsfloatAutobuy::new(api_key, proxy)
+        CsfloatAutobuy::new(api_keys, proxy, config, stats, alert_tx)
     }
 
-    pub fn new(api_key: String, proxy: Option<String>) -> CsfloatAutobuy {
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(api_key.as_str()).unwrap(),
-        );
-
+    pub fn new(
+        api_keys: ApiKeys,
+        proxy: Option<String>,
+        config: SharedConfig,
+        stats: Arc<Mutex<Stats>>,
+        alert_tx: Sender<SecEvent>,
+    ) -> CsfloatAutobuy {
         let mut client = reqwest::Client::builder();
 
         if let Some(proxy_value) = proxy {
             client = client
                 .proxy(Proxy::http(proxy_value).expect("Failed to parse proxy provided from env!"))
         }
+        let autobuy_config = &config.load().autobuy;
 
         let client = client
             // .proxy(proxy)
-            .timeout(Duration::from_secs(10))
-            .default_headers(headers)
+            .timeout(autobuy_config.request_timeout())
             .build()
             .expect("Failed to build client for csfloat autobuy");
+        let client = CsfloatClient::new(client, stats, alert_tx);
+        client.set_api_keys(api_keys);
 
+        let clock = system_clock();
         CsfloatAutobuy {
             // api_key,
-            next_call: Utc::now(),
+            next_call: clock.now(),
             client,
+            buy_cooldown: autobuy_config.buy_cooldown(),
+            verify_before_buy: autobuy_config.verify_before_buy,
+            similar_limiter: RateLimiter::new(Duration::ZERO),
+            balance: None,
+            is_low_balance: false,
+            dmarket: None,
+            breaker: CircuitBreaker::new(config.clone()),
+            bids: HashMap::new(),
+            leadership: Leadership::always(),
+            clock,
         }
     }
 
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.next_call = clock.now();
+        self.clock = clock;
+    }
+
+    pub fn set_leadership(&mut self, leadership: Leadership) {
+        self.leadership = leadership;
+    }
+
+    // Purchases are held by the breaker and made only while the leader lock is held
+    async fn check_allowed(&self) -> Result<(), CsfloatBuyError> {
+        if let Some(until) = self.get_breaker_open_until() {
+            return Err(CsfloatBuyError::CircuitOpen { until });
+        }
+        if !self.leadership.confirm().await {
+            return Err(CsfloatBuyError::NotLeader);
+        }
+        Ok(())
+    }
+
     pub async fn buy_listing(
         &mut self,
         listing_id: &ListingId,
         price: PriceValue,
-    ) -> Result<bool, reqwest::Error> {
-        const BUY_NEXT_CALL: Duration = Duration::from_secs(10);
-        let now = Utc::now();
+    ) -> Result<BuyOutcome, CsfloatBuyError> {
+        self.buy_listings(&[(listing_id.clone(), price)]).await
+    }
+
+    pub fn get_breaker_open_until(&self) -> Option<DateTime<Utc>> {
+        self.breaker.get_open_until(self.clock.now())
+    }
+
+    // Returns true when the breaker was open
+    pub fn reset_breaker(&mut self) -> bool {
+        self.breaker.reset(self.clock.now())
+    }
+
+    // All listings are bought in one request or none of them, the first one names the purchase
+    pub async fn buy_listings(
+        &mut self,
+        listings: &[(ListingId, PriceValue)],
+    ) -> Result<BuyOutcome, CsfloatBuyError> {
+        self.check_allowed().await?;
+        let previous_call = self.next_call;
+        let mut result = self.send_buy(listings).await;
+        // the rejected key is retired by the client, the next one is tried right away
+        for _ in 1..self.client.get_key_counts().2 {
+            let (_, alive, _) = self.client.get_key_counts();
+            if result != Err(CsfloatBuyError::AuthExpired) || alive == 0 {
+                break;
+            }
+            self.next_call = previous_call;
+            result = self.send_buy(listings).await;
+        }
+        self.record_breaker(&result);
+        result
+    }
+
+    // DMarket purchases share the breaker, its failures hold the CSFloat ones too
+    pub async fn buy_dmarket_offer(
+        &mut self,
+        offer_id: &ListingId,
+        price: PriceValue,
+    ) -> Result<BuyOutcome, CsfloatBuyError> {
+        self.check_allowed().await?;
+        let result = match &self.dmarket {
+            Some(dmarket) => dmarket.buy_offer(offer_id, price).await,
+            None => {
+                return Err(CsfloatBuyError::Request(
+                    "DMARKET_PUBLIC_KEY and DMARKET_SECRET_KEY are not set".to_string(),
+                ))
+            }
+        };
+        self.record_breaker(&result);
+        result
+    }
+
+    fn record_breaker(&mut self, result: &Result<BuyOutcome, CsfloatBuyError>) {
+        if !self.breaker.record(result, self.clock.now()) {
+            return;
+        }
+        let err = result
+            .as_ref()
+            .err()
+            .map_or(String::new(), |x| x.to_string());
+        warn!(
+            "Autobuy circuit breaker is tripped, the last error: {}",
+            err
+        );
+        self.client.alert(format!(
+            "Autobuy is held until {} after repeated failures, the last one: {}. \
+            Use /reset_autobuy to resume earlier",
+            self.get_breaker_open_until().unwrap_or_default(),
+            err
+        ));
+    }
+
+    async fn send_buy(
+        &mut self,
+        listings: &[(ListingId, PriceValue)],
+    ) -> Result<BuyOutcome, CsfloatBuyError> {
+        let Some((listing_id, _)) = listings.first() else {
+            return Err(CsfloatBuyError::Request("nothing to buy".to_string()));
+        };
+        let price: PriceValue = listings.iter().map(|(_, price)| price).sum();
+        let now = self.clock.now();
         if self.next_call > now {
             warn!(
                 "Locally rate-limited: next call {}  | now {}",
                 self.next_call, now
             );
-            return Ok(false);
+            let retry_after = (self.next_call - now).num_seconds().max(1) as u64;
+            return Err(CsfloatBuyError::RateLimited {
+                retry_after: Some(retry_after),
+            });
+        }
+
+        let previous_call = self.next_call;
+        self.next_call = now + self.buy_cooldown;
+        if self.verify_before_buy {
+            for (listing_id, price) in listings {
+                if let Err(err) = self.verify_listing(listing_id, *price).await {
+                    warn!("Purchase of listing {} is aborted: {}", listing_id, err);
+                    return Err(CsfloatBuyError::Aborted(err));
+                }
+            }
         }
 
-        self.next_call = now + BUY_NEXT_CALL;
-        let url = "https://csfloat.com/api/v1/listings/buy";
+        let url = self.client.url("/listings/buy");
+        let contract_ids: Vec<String> = listings.iter().map(|(id, _)| id.to_string()).collect();
         let body = serde_json::json!({
             "total_price": price,
-            "contract_ids": [listing_id.to_string()]
+            "contract_ids": contract_ids
         });
         // let mut headers = HeaderMap::new();
         // let api_key = env::var("CSFLOAT_API_KEY").expect("CSFLOAT_API_KEY must be set");
@@ -96,33 +546,568 @@ impl CsfloatAutobuy {
         //     .timeout(Duration::from_secs(10))
         //     .build()?;
 
-        let response = self
+        let response = match self
             .client
-            .post(url)
-            // .headers(self.headers)
-            .json(&body)
-            .send()
-            .await?;
+            .send(
+                self.client
+                    .post(url)
+                    // .headers(self.headers)
+                    .json(&body),
+            )
+            .await
+        {
+            Ok(response) => response,
+            // CSFloat never got it, so the retry isn't held by the cooldown
+            Err(err) if err.is_connect() => {
+                self.next_call = previous_call;
+                return Err(CsfloatBuyError::Request(err.to_string()));
+            }
+            Err(err) => return Err(CsfloatBuyError::Unknown(err.to_string())),
+        };
+        let status = response.status();
+        let rate_limit = parse_rate_limit_headers(response.headers(), now);
+        let text = response.text().await.unwrap_or_default();
+        // e.g. of a proxy or a load balancer in front of CSFloat
+        if status.is_server_error() && text.trim().is_empty() {
+            warn!("Failed to buy listing {}: {}", listing_id, status);
+            self.next_call = previous_call;
+            return Err(CsfloatBuyError::Request(status.to_string()));
+        }
+        if !status.is_success() {
+            warn!("Failed to buy listing {}: {} {}", listing_id, status, text);
+            self.client.report_error(status, &text).await;
+            let retry_after = rate_limit
+                .reset_at
+                .map(|reset_at| (reset_at - now).num_seconds().max(0) as u64);
+            let err = parse_buy_error(status, &text, retry_after);
+            if let CsfloatBuyError::RateLimited {
+                retry_after: Some(secs),
+            } = err
+            {
+                self.next_call = self
+                    .next_call
+                    .max(now + chrono::Duration::seconds(secs as i64));
+            }
+            return Err(err);
+        }
 
         // {
         //     let data = response.text().await?;
         //     debug!("Response: {}", data);
         // }
 
-        let response_json: serde_json::Value = response.json().await?;
+        let Ok(response_json) = serde_json::from_str::<serde_json::Value>(&text) else {
+            return Err(CsfloatBuyError::Unknown(format!("{} {}", status, text)));
+        };
+        if response_json["message"] != "all listings purchased" {
+            return Err(parse_buy_error(status, &text, None));
+        }
+        // until the next refresh
+        self.balance = self.balance.map(|balance| balance.saturating_sub(price));
+        Ok(BuyOutcome {
+            is_success: true,
+            status: Some(status.as_u16()),
+            response: response_json,
+        })
+    }
+
+    // Refetches the listing right before the purchase
+    pub async fn verify_listing(
+        &mut self,
+        listing_id: &ListingId,
+        price: PriceValue,
+    ) -> Result<(), VerifyError> {
+        let listing = self.fetch_listing(listing_id).await?;
+        check_listing(&listing, price)
+    }
+
+    // Tells whether a purchase of `Unknown` result went through: it didn't when a listing is
+    // still listed, it did when they're sold and the balance dropped by their price since
+    // `balance_before`. Otherwise the error is kept and the purchase is left to the operator.
+    pub async fn reconcile_purchase(
+        &mut self,
+        listings: &[(ListingId, PriceValue)],
+        balance_before: Option<PriceValue>,
+        err: CsfloatBuyError,
+    ) -> Result<BuyOutcome, CsfloatBuyError> {
+        let mut states = vec![];
+        for (listing_id, _) in listings {
+            match self.fetch_listing(listing_id).await {
+                Ok(listing) => states.push(listing.state),
+                Err(fetch_err) => {
+                    warn!(
+                        "Failed to reconcile purchase of {}: {}",
+                        listing_id, fetch_err
+                    );
+                    return Err(err);
+                }
+            }
+        }
+        // all listings are bought in one request or none of them
+        if states.contains(&CsfloatListingState::Listed) {
+            return Err(CsfloatBuyError::Request(format!("{}, still listed", err)));
+        }
+        let price: PriceValue = listings.iter().map(|(_, price)| price).sum();
+        let balance = match self.get_balance().await {
+            Ok(balance) => balance,
+            Err(balance_err) => {
+                warn!("Failed to reconcile purchase: {}", balance_err);
+                return Err(err);
+            }
+        };
+        self.balance = Some(balance);
+        match balance_before {
+            Some(before) if before.saturating_sub(balance) >= price => Ok(BuyOutcome {
+                is_success: true,
+                status: None,
+                response: serde_json::json!({ "reconciled": err }),
+            }),
+            _ => Err(err),
+        }
+    }
 
-        Ok(response_json["message"] == "all listings purchased")
+    async fn fetch_listing(
+        &mut self,
+        listing_id: &ListingId,
+    ) -> Result<CsfloatListingStruct, VerifyError> {
+        let url = self.client.url(&format!("/listings/{}", listing_id));
+        let response = self
+            .client
+            .send(self.client.get(&url))
+            .await
+            .map_err(|err| VerifyError::Unavailable(err.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            self.client.report_error(status, &text).await;
+            return Err(VerifyError::Unavailable(status.to_string()));
+        }
+        response
+            .json::<CsfloatListingStruct>()
+            .await
+            .map_err(|err| VerifyError::Unavailable(err.to_string()))
     }
 
-    pub async fn get_balance(&mut self) -> Result<PriceValue, reqwest::Error> {
-        let url = "https://csfloat.com/api/v1/me";
-        let response = self.client.get(url).send().await?;
+    // Compares the candidate with the cheapest CSFloat listings of the item. Skipped, not
+    // failed, when the requests are over their budget or CSFloat doesn't answer.
+    pub async fn verify_similar_listings(
+        &mut self,
+        market_name: &MarketName,
+        listing_id: &ListingId,
+        price: PriceValue,
+        config: &SimilarListingsConfig,
+    ) -> Result<(), VerifyError> {
+        if !config.enabled {
+            return Ok(());
+        }
+        self.similar_limiter.set_interval(config.min_interval());
+        if !self.similar_limiter.try_acquire() {
+            debug!(
+                "Similar listings check of {} is skipped: over the budget",
+                listing_id
+            );
+            return Ok(());
+        }
+
+        let limit = config.limit.to_string();
+        let request = self.client.get(self.client.url("/listings")).query(&[
+            ("market_hash_name", market_name.as_ref()),
+            ("sort_by", "lowest_price"),
+            ("type", "buy_now"),
+            ("limit", limit.as_str()),
+        ]);
+        let response = match self.client.send(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(
+                    "Failed to fetch similar listings of {}: {}",
+                    market_name, err
+                );
+                return Ok(());
+            }
+        };
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            warn!(
+                "Failed to fetch similar listings of {}: {} {}",
+                market_name, status, text
+            );
+            self.client.report_error(status, &text).await;
+            return Ok(());
+        }
+        let listings = split_listings_page(&text).and_then(|(listings, _)| {
+            serde_json::from_value::<Vec<CsfloatListingStruct>>(listings).ok()
+        });
+        let Some(listings) = listings else {
+            warn!("Unexpected similar listings response: {}", text);
+            return Ok(());
+        };
+        check_similar_listings(listing_id, price, &listings, config.max_above_floor_pct)
+    }
+
+    pub fn has_bid(&self, listing_id: &ListingId) -> bool {
+        let now = self.clock.now();
+        self.bids
+            .get(listing_id)
+            .is_some_and(|expires_at| *expires_at > now)
+    }
+
+    // CSFloat raises the bid automatically up to `max_price` when outbid. The bid is sent
+    // once per auction whatever its result, the next refreshes of the listing skip it.
+    pub async fn place_bid(
+        &mut self,
+        listing_id: &ListingId,
+        max_price: PriceValue,
+        expires_at: DateTime<Utc>,
+    ) -> Result<BuyOutcome, CsfloatBuyError> {
+        self.check_allowed().await?;
+        let now = self.clock.now();
+        self.bids.retain(|_, expires_at| *expires_at > now);
+        self.bids.insert(listing_id.clone(), expires_at);
+
+        let url = self.client.url(&format!("/listings/{}/bid", listing_id));
+        let body = serde_json::json!({ "max_price": max_price });
+        let result = match self.client.send(self.client.post(&url).json(&body)).await {
+            Err(err) if err.is_connect() => Err(CsfloatBuyError::Request(err.to_string())),
+            Err(err) => Err(CsfloatBuyError::Unknown(err.to_string())),
+            Ok(response) => {
+                let status = response.status();
+                let text = response.text().await.unwrap_or_default();
+                if status.is_success() {
+                    Ok(BuyOutcome {
+                        is_success: true,
+                        status: Some(status.as_u16()),
+                        response: serde_json::from_str(&text)
+                            .unwrap_or(serde_json::Value::String(text)),
+                    })
+                } else {
+                    warn!(
+                        "Failed to bid on listing {}: {} {}",
+                        listing_id, status, text
+                    );
+                    self.client.report_error(status, &text).await;
+                    Err(parse_buy_error(status, &text, None))
+                }
+            }
+        };
+        self.record_breaker(&result);
+        result
+    }
+
+    pub fn get_cached_balance(&self) -> Option<PriceValue> {
+        self.balance
+    }
+
+    // Returns true when the balance has just dropped below `low_balance_alert`
+    pub fn set_balance(&mut self, balance: PriceValue, low_balance_alert: PriceValue) -> bool {
+        self.balance = Some(balance);
+        let is_low_balance = balance < low_balance_alert;
+        let is_dropped = is_low_balance && !self.is_low_balance;
+        self.is_low_balance = is_low_balance;
+        is_dropped
+    }
+
+    pub async fn refresh_balance(
+        &mut self,
+        low_balance_alert: PriceValue,
+    ) -> Result<bool, BalanceError> {
+        let balance = self.get_balance().await?;
+        Ok(self.set_balance(balance, low_balance_alert))
+    }
+
+    pub async fn get_balance(&mut self) -> Result<PriceValue, BalanceError> {
+        let url = self.client.url("/me");
+        let response = self.client.send(self.client.get(&url)).await?;
+        if !response.status().is_success() {
+            warn!("Failed to get csfloat balance: {}", response.status());
+        }
+        // a failed request must not look like an empty balance
+        let response = response.error_for_status()?;
 
         let data = response.json::<serde_json::Value>().await?;
-        let balance = data["user"]["balance"].as_u64().unwrap_or(0);
-        Ok(balance)
+        data["user"]["balance"]
+            .as_u64()
+            .ok_or_else(|| BalanceError::Malformed(data.to_string()))
     }
 }
 
-// it's recommended to use this crate
-// https://github.com/lipanski/mockito
+// requests are tested against a mockito server in `tests::csfloat_http`
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, MockClock},
+        config::{AppConfig, AutobuyConfig},
+        tests::fixtures::get_listing,
+    };
+
+    #[test]
+    fn test_check_listing() {
+        let mut listing = get_listing(
+            "679718648830624407",
+            "AK-47 | Redline (Field-Tested)",
+            10_00,
+        );
+        assert_eq!(check_listing(&listing, 10_00), Ok(()));
+        assert_eq!(
+            check_listing(&listing, 9_00),
+            Err(VerifyError::PriceChanged {
+                expected: 9_00,
+                actual: 10_00
+            })
+        );
+
+        listing.state = CsfloatListingState::Sold;
+        assert_eq!(
+            check_listing(&listing, 10_00),
+            Err(VerifyError::NotListed(CsfloatListingState::Sold))
+        );
+        let err = CsfloatBuyError::Aborted(VerifyError::NotListed(CsfloatListingState::Sold));
+        assert_eq!(
+            serde_json::to_value(BuyOutcome::failed(&err)).unwrap()["response"],
+            serde_json::json!({ "error": { "aborted": { "not_listed": "sold" } } })
+        );
+    }
+
+    #[test]
+    fn test_check_similar_listings() {
+        let redline = "AK-47 | Redline (Field-Tested)";
+        let mut listings = vec![
+            get_listing("1", redline, 9_00),
+            get_listing("2", redline, 9_50),
+            get_listing("3", redline, 10_00),
+        ];
+        listings[1].state = CsfloatListingState::Sold;
+        // the candidate is the floor itself
+        assert_eq!(
+            check_similar_listings(&"1".into(), 9_00, &listings, 0.0),
+            Ok(())
+        );
+        assert_eq!(
+            check_similar_listings(&"3".into(), 10_00, &listings, 0.0),
+            Err(VerifyError::AboveFloor {
+                price: 10_00,
+                floor: 9_00
+            })
+        );
+        assert_eq!(
+            check_similar_listings(&"3".into(), 10_00, &listings, 15.0),
+            Ok(())
+        );
+        assert_eq!(check_similar_listings(&"3".into(), 10_00, &[], 0.0), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_local_rate_limit() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let stats = Arc::new(Mutex::new(Stats::new()));
+        let mut autobuy = CsfloatAutobuy::new(
+            ApiKeys::new(vec!["key".to_string()]),
+            None,
+            AppConfig::default().into_shared(),
+            stats,
+            tx,
+        );
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        autobuy.set_clock(clock.clone());
+        autobuy.next_call = clock.now() + chrono::Duration::seconds(30);
+
+        clock.advance(chrono::Duration::seconds(20));
+        assert_eq!(
+            autobuy.buy_listing(&"1".into(), 10_00).await,
+            Err(CsfloatBuyError::RateLimited {
+                retry_after: Some(10)
+            })
+        );
+        assert_eq!(
+            autobuy.buy_listings(&[]).await,
+            Err(CsfloatBuyError::Request("nothing to buy".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let config = AppConfig {
+            autobuy: AutobuyConfig {
+                breaker_failures: 2,
+                breaker_cooldown_secs: 600,
+                ..AutobuyConfig::default()
+            },
+            ..AppConfig::default()
+        }
+        .into_shared();
+        let mut breaker = CircuitBreaker::new(config.clone());
+        let now = Utc::now();
+        let failed = Err(CsfloatBuyError::Api {
+            code: 500,
+            message: "internal error".to_string(),
+        });
+
+        assert!(!breaker.record(&failed, now));
+        // a success starts the count over
+        assert!(!breaker.record(&Ok(BuyOutcome::paper()), now));
+        assert!(!breaker.record(&failed, now));
+        // missed listings aren't failures of the autobuy
+        assert!(!breaker.record(&Err(CsfloatBuyError::AlreadySold), now));
+        assert_eq!(breaker.get_open_until(now), None);
+
+        assert!(breaker.record(&Err(CsfloatBuyError::AuthExpired), now));
+        let until = now + chrono::Duration::minutes(10);
+        assert_eq!(breaker.get_open_until(now), Some(until));
+        assert_eq!(breaker.get_open_until(until), None);
+
+        assert!(breaker.reset(now));
+        assert!(!breaker.reset(now));
+        assert_eq!(breaker.get_open_until(now), None);
+
+        // a reloaded config applies to the next failure
+        let mut reloaded = AppConfig::clone(&config.load());
+        reloaded.autobuy.breaker_failures = 0;
+        config.store(Arc::new(reloaded.clone()));
+        for _ in 0..5 {
+            assert!(!breaker.record(&failed, now));
+        }
+        reloaded.autobuy.breaker_failures = 1;
+        reloaded.autobuy.breaker_cooldown_secs = 60;
+        config.store(Arc::new(reloaded));
+        assert!(breaker.record(&failed, now));
+        assert_eq!(
+            breaker.get_open_until(now),
+            Some(now + chrono::Duration::minutes(1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_breaker_holds_dmarket() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let stats = Arc::new(Mutex::new(Stats::new()));
+        let config = AppConfig {
+            autobuy: AutobuyConfig {
+                breaker_failures: 1,
+                ..AutobuyConfig::default()
+            },
+            ..AppConfig::default()
+        };
+        let mut autobuy = CsfloatAutobuy::new(
+            ApiKeys::new(vec!["key".to_string()]),
+            None,
+            config.into_shared(),
+            stats,
+            tx,
+        );
+        assert!(matches!(
+            autobuy.buy_dmarket_offer(&"1".into(), 10_00).await,
+            Err(CsfloatBuyError::Request(_))
+        ));
+
+        autobuy.record_breaker(&Err(CsfloatBuyError::AuthExpired));
+        let until = autobuy.get_breaker_open_until().unwrap();
+        assert_eq!(
+            autobuy.buy_dmarket_offer(&"1".into(), 10_00).await,
+            Err(CsfloatBuyError::CircuitOpen { until })
+        );
+    }
+
+    #[test]
+    fn test_low_balance_alert() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let stats = Arc::new(Mutex::new(Stats::new()));
+        let mut autobuy = CsfloatAutobuy::new(
+            ApiKeys::new(vec!["key".to_string()]),
+            None,
+            AppConfig::default().into_shared(),
+            stats,
+            tx,
+        );
+        assert_eq!(autobuy.get_cached_balance(), None);
+
+        assert!(!autobuy.set_balance(50_00, 20_00));
+        assert!(autobuy.set_balance(15_00, 20_00));
+        assert!(!autobuy.set_balance(10_00, 20_00));
+        assert_eq!(autobuy.get_cached_balance(), Some(10_00));
+        assert!(!autobuy.set_balance(30_00, 20_00));
+        assert!(autobuy.set_balance(5_00, 20_00));
+    }
+
+    #[test]
+    fn test_parse_buy_error() {
+        assert_eq!(
+            parse_buy_error(StatusCode::TOO_MANY_REQUESTS, "", Some(30)),
+            CsfloatBuyError::RateLimited {
+                retry_after: Some(30)
+            }
+        );
+        assert_eq!(
+            parse_buy_error(StatusCode::UNAUTHORIZED, "", None),
+            CsfloatBuyError::AuthExpired
+        );
+        assert_eq!(
+            parse_buy_error(
+                StatusCode::FORBIDDEN,
+                r#"{"code": 1, "message": "forbidden"}"#,
+                None
+            ),
+            CsfloatBuyError::AuthExpired
+        );
+        // a Cloudflare page isn't about the key
+        assert_eq!(
+            parse_buy_error(StatusCode::FORBIDDEN, "<html>Access denied</html>", None),
+            CsfloatBuyError::Api {
+                code: 403,
+                message: "<html>Access denied</html>".to_string()
+            }
+        );
+        assert_eq!(
+            parse_buy_error(
+                StatusCode::BAD_REQUEST,
+                r#"{"code": 12, "message": "Insufficient balance to purchase"}"#,
+                None
+            ),
+            CsfloatBuyError::InsufficientBalance
+        );
+        assert_eq!(
+            parse_buy_error(
+                StatusCode::BAD_REQUEST,
+                r#"{"code": 4, "message": "the listing is no longer available"}"#,
+                None
+            ),
+            CsfloatBuyError::AlreadySold
+        );
+        assert_eq!(
+            parse_buy_error(
+                StatusCode::BAD_REQUEST,
+                r#"{"code": 4, "message": "total_price does not match"}"#,
+                None
+            ),
+            CsfloatBuyError::PriceChanged
+        );
+        assert_eq!(
+            parse_buy_error(
+                StatusCode::BAD_REQUEST,
+                r#"{"code": 1, "message": "Invalid API key"}"#,
+                None
+            ),
+            CsfloatBuyError::AuthExpired
+        );
+        assert_eq!(
+            parse_buy_error(
+                StatusCode::BAD_REQUEST,
+                r#"{"code": 20, "message": "listing is reserved"}"#,
+                None
+            ),
+            CsfloatBuyError::Api {
+                code: 20,
+                message: "listing is reserved".to_string()
+            }
+        );
+        assert_eq!(
+            parse_buy_error(StatusCode::BAD_GATEWAY, "upstream error", None),
+            CsfloatBuyError::Api {
+                code: 502,
+                message: "upstream error".to_string()
+            }
+        );
+    }
+}
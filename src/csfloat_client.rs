@@ -0,0 +1,559 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::{
+    header::{HeaderMap, AUTHORIZATION},
+    Client, IntoUrl, RequestBuilder, Response, ResponseBuilderExt, StatusCode,
+};
+use serde::Deserialize;
+use tokio::sync::{mpsc::Sender, Mutex};
+use tracing::{error, info, warn};
+
+use crate::{
+    events::{AlertEvent, SecEvent},
+    stats::{Stats, StatsCounter},
+};
+
+pub const API_URL: &str = "https://csfloat.com/api/v1";
+
+const LIMIT_HEADER: &str = "x-ratelimit-limit";
+const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+const RESET_HEADER: &str = "x-ratelimit-reset";
+
+// 403s which don't reject the key, e.g. of Cloudflare, are alerted at most that often
+const FORBIDDEN_ALERT_INTERVAL_MINS: i64 = 30;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RateLimitInfo {
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    pub reset_at: Option<DateTime<Utc>>,
+}
+
+pub fn parse_rate_limit_headers(headers: &HeaderMap, now: DateTime<Utc>) -> RateLimitInfo {
+    let get_u64 = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.trim().parse::<u64>().ok())
+    };
+
+    // either unix timestamp or seconds until reset
+    let reset_at = get_u64(RESET_HEADER).and_then(|reset| match reset > 1_000_000_000 {
+        true => Utc.timestamp_opt(reset as i64, 0).single(),
+        false => Some(now + chrono::Duration::seconds(reset as i64)),
+    });
+
+    RateLimitInfo {
+        limit: get_u64(LIMIT_HEADER),
+        remaining: get_u64(REMAINING_HEADER),
+        reset_at,
+    }
+}
+
+// Error body of csfloat.com, e.g. `{"code": 4, "message": "the listing is no longer available"}`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CsfloatApiError {
+    #[serde(default)]
+    pub code: i64,
+    pub message: String,
+}
+
+impl CsfloatApiError {
+    // None when it's not an API error, e.g. an HTML page of a proxy or Cloudflare
+    pub fn parse(body: &str) -> Option<Self> {
+        serde_json::from_str(body).ok()
+    }
+
+    // The API key is missing, expired or revoked
+    pub fn is_auth_error(&self) -> bool {
+        let text = self.message.to_lowercase();
+        text.contains("api key") || text.contains("unauthorized")
+    }
+}
+
+// CSFloat rejected the API key of the request. A 403 of Cloudflare or a proxy is an HTML page,
+// it isn't the key's fault
+pub fn is_key_rejected(status: StatusCode, body: &str) -> bool {
+    match status {
+        StatusCode::UNAUTHORIZED => true,
+        StatusCode::FORBIDDEN => CsfloatApiError::parse(body).is_some(),
+        _ => false,
+    }
+}
+
+// Spreads the remaining quota evenly until the window resets,
+// but never goes below `min_interval`.
+pub fn throttled_interval(
+    info: &RateLimitInfo,
+    min_interval: Duration,
+    now: DateTime<Utc>,
+) -> Duration {
+    let (Some(remaining), Some(reset_at)) = (info.remaining, info.reset_at) else {
+        return min_interval;
+    };
+    let until_reset = (reset_at - now).to_std().unwrap_or(Duration::ZERO);
+    let interval = match remaining {
+        0 => until_reset,
+        _ => until_reset / remaining.min(u32::MAX as u64) as u32,
+    };
+    interval.max(min_interval)
+}
+
+#[derive(Debug, Clone)]
+struct ApiKey {
+    value: String,
+    is_alive: bool,
+}
+
+// API keys the requests are authorized with, the current one is used until CSFloat
+// rejects it (see `is_key_rejected`), then the next live one takes over.
+// A rejected key is revived when the auth probe succeeds with it.
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: Vec<ApiKey>,
+    current: usize,
+}
+
+impl ApiKeys {
+    pub fn new(keys: Vec<String>) -> Self {
+        ApiKeys {
+            keys: keys
+                .into_iter()
+                .map(|value| ApiKey {
+                    value,
+                    is_alive: true,
+                })
+                .collect(),
+            current: 0,
+        }
+    }
+
+    // comma separated, e.g. `CSFLOAT_API_KEYS`
+    pub fn parse(keys: &str) -> Self {
+        ApiKeys::new(
+            keys.split(',')
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect(),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn get_current(&self) -> Option<&str> {
+        self.keys.get(self.current).map(|x| x.value.as_str())
+    }
+
+    pub fn get_current_idx(&self) -> usize {
+        self.current
+    }
+
+    pub fn get_alive_count(&self) -> usize {
+        self.keys.iter().filter(|x| x.is_alive).count()
+    }
+
+    // "#2 (...f3a1)", the key itself must not get into the logs and alerts
+    pub fn label(&self, idx: usize) -> String {
+        let value = self.keys.get(idx).map_or("", |x| x.value.as_str());
+        let tail = value.get(value.len().saturating_sub(4)..).unwrap_or("");
+        format!("#{} (...{})", idx + 1, tail)
+    }
+
+    fn find(&self, value: &str) -> Option<usize> {
+        self.keys.iter().position(|x| x.value == value)
+    }
+
+    // Returns false when the key was already retired. The current key moves to the next
+    // live one, with none left it stays so the requests fail visibly.
+    pub fn retire(&mut self, idx: usize) -> bool {
+        let Some(key) = self.keys.get_mut(idx) else {
+            return false;
+        };
+        if !key.is_alive {
+            return false;
+        }
+        key.is_alive = false;
+        if idx == self.current {
+            let count = self.keys.len();
+            if let Some(next) = (1..count)
+                .map(|offset| (idx + offset) % count)
+                .find(|x| self.keys[*x].is_alive)
+            {
+                self.current = next;
+            }
+        }
+        true
+    }
+
+    // Returns false when the key wasn't retired
+    pub fn revive(&mut self, idx: usize) -> bool {
+        let Some(key) = self.keys.get_mut(idx) else {
+            return false;
+        };
+        if key.is_alive {
+            return false;
+        }
+        key.is_alive = true;
+        if !self.keys[self.current].is_alive {
+            self.current = idx;
+        }
+        true
+    }
+}
+
+// reqwest client for csfloat.com that tracks rate-limit headers of the responses
+// and reports 403/429 and API errors to stats and Telegram.
+// With API keys set the requests of `get` and `post` are authorized with the current one.
+// Clones share the keys and the rate limit, so the auth probe doesn't wait for the autobuy.
+#[derive(Clone)]
+pub struct CsfloatClient {
+    client: Client,
+    // `API_URL`, a local mock server in tests
+    base_url: String,
+    rate_limit: Arc<std::sync::Mutex<RateLimitInfo>>,
+    keys: Arc<std::sync::Mutex<ApiKeys>>,
+    forbidden_alerted_at: Arc<std::sync::Mutex<Option<DateTime<Utc>>>>,
+    stats: Arc<Mutex<Stats>>,
+    alert_tx: Sender<SecEvent>,
+}
+
+impl CsfloatClient {
+    pub fn new(client: Client, stats: Arc<Mutex<Stats>>, alert_tx: Sender<SecEvent>) -> Self {
+        CsfloatClient {
+            client,
+            base_url: API_URL.to_string(),
+            rate_limit: Arc::new(std::sync::Mutex::new(RateLimitInfo::default())),
+            keys: Arc::new(std::sync::Mutex::new(ApiKeys::default())),
+            forbidden_alerted_at: Arc::new(std::sync::Mutex::new(None)),
+            stats,
+            alert_tx,
+        }
+    }
+
+    pub fn set_base_url(&mut self, base_url: &str) {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+    }
+
+    // `path` starts with a slash, e.g. "/listings/buy"
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    pub fn set_api_keys(&self, keys: ApiKeys) {
+        *self.keys.lock().unwrap() = keys;
+    }
+
+    // (current, live, all)
+    pub fn get_key_counts(&self) -> (usize, usize, usize) {
+        let keys = self.keys.lock().unwrap();
+        (keys.get_current_idx(), keys.get_alive_count(), keys.len())
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.keys.lock().unwrap().get_current() {
+            Some(key) => request.header(AUTHORIZATION, key),
+            None => request,
+        }
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.authorize(self.client.get(url))
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.authorize(self.client.post(url))
+    }
+
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let request = request.build()?;
+        let key = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| self.keys.lock().unwrap().find(x));
+        let response = self.client.execute(request).await?;
+        self.observe(&response).await;
+        let status = response.status();
+        let Some(idx) =
+            key.filter(|_| matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN))
+        else {
+            return Ok(response);
+        };
+        // the body tells whether the key is rejected, the response is rebuilt for the caller
+        let url = response.url().clone();
+        let mut builder = http::Response::builder()
+            .status(status)
+            .version(response.version())
+            .url(url.clone());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = response.headers().clone();
+        }
+        let body = response.bytes().await?;
+        if is_key_rejected(status, &String::from_utf8_lossy(&body)) {
+            self.retire_key(idx, status);
+        } else {
+            self.alert_forbidden(format!("Csfloat answered {} for {}", status, url.path()));
+        }
+        let response = builder
+            .body(body)
+            .expect("the parts of a received response are valid");
+        Ok(Response::from(response))
+    }
+
+    fn retire_key(&self, idx: usize, status: StatusCode) {
+        let mut keys = self.keys.lock().unwrap();
+        if !keys.retire(idx) {
+            return;
+        }
+        let next = match keys.get_alive_count() {
+            0 => "no working keys are left".to_string(),
+            _ => format!("switched to {}", keys.label(keys.get_current_idx())),
+        };
+        let text = format!(
+            "Csfloat rejected the API key {} with {}, {}",
+            keys.label(idx),
+            status,
+            next
+        );
+        drop(keys);
+        warn!("{}", text);
+        self.alert(text);
+    }
+
+    // Calls `/me` with every key. Rejected keys are retired by `send`,
+    // the retired ones which work again are revived.
+    pub async fn probe_keys(&self) {
+        let values: Vec<String> = {
+            let keys = self.keys.lock().unwrap();
+            keys.keys.iter().map(|x| x.value.clone()).collect()
+        };
+        for (idx, value) in values.iter().enumerate() {
+            let request = self
+                .client
+                .get(self.url("/me"))
+                .header(AUTHORIZATION, value);
+            let response = match self.send(request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    warn!("Auth probe failed: {}", err);
+                    continue;
+                }
+            };
+            if !response.status().is_success() {
+                continue;
+            }
+            let mut keys = self.keys.lock().unwrap();
+            if keys.revive(idx) {
+                let text = format!("Csfloat API key {} works again", keys.label(idx));
+                drop(keys);
+                info!("{}", text);
+                self.alert(text);
+            }
+        }
+    }
+
+    pub fn get_rate_limit(&self) -> RateLimitInfo {
+        self.rate_limit.lock().unwrap().clone()
+    }
+
+    pub fn throttled_interval(&self, min_interval: Duration) -> Duration {
+        throttled_interval(&self.get_rate_limit(), min_interval, Utc::now())
+    }
+
+    // Should be called with the body of a failed response, counts the error code
+    // and alerts when the API key is rejected (403, and 401 of the rotated keys,
+    // are already alerted by `send`).
+    pub async fn report_error(&self, status: StatusCode, body: &str) -> Option<CsfloatApiError> {
+        let api_error = CsfloatApiError::parse(body);
+        if let Some(api_error) = &api_error {
+            self.stats
+                .lock()
+                .await
+                .increment(StatsCounter::CsfloatApiError(api_error.code));
+        }
+        let is_auth_error = status == StatusCode::UNAUTHORIZED
+            || api_error.as_ref().is_some_and(|x| x.is_auth_error());
+        let is_rotated =
+            status == StatusCode::UNAUTHORIZED && !self.keys.lock().unwrap().is_empty();
+        if is_auth_error && status != StatusCode::FORBIDDEN && !is_rotated {
+            let message = api_error.as_ref().map_or(body, |x| x.message.as_str());
+            self.alert(format!(
+                "Csfloat rejected the API key: {} {}",
+                status, message
+            ));
+        }
+        api_error
+    }
+
+    // Sent to Telegram by the secondary dispatcher
+    pub fn alert(&self, text: String) {
+        if self
+            .alert_tx
+            .try_send(SecEvent::Alert(AlertEvent { text }))
+            .is_err()
+        {
+            error!("Failed to sent new event in the queue!");
+        }
+    }
+
+    // Returns whether it's alerted, the others are only counted and logged
+    fn alert_forbidden(&self, text: String) -> bool {
+        let now = Utc::now();
+        let mut alerted_at = self.forbidden_alerted_at.lock().unwrap();
+        let interval = chrono::Duration::minutes(FORBIDDEN_ALERT_INTERVAL_MINS);
+        if alerted_at.is_some_and(|x| now - x < interval) {
+            return false;
+        }
+        *alerted_at = Some(now);
+        drop(alerted_at);
+        self.alert(format!(
+            "{}, the next ones are alerted in {} minutes at the earliest",
+            text, FORBIDDEN_ALERT_INTERVAL_MINS
+        ));
+        true
+    }
+
+    async fn observe(&self, response: &Response) {
+        let info = parse_rate_limit_headers(response.headers(), Utc::now());
+        if info.remaining.is_some() {
+            *self.rate_limit.lock().unwrap() = info;
+        }
+
+        match response.status() {
+            StatusCode::TOO_MANY_REQUESTS => {
+                self.stats
+                    .lock()
+                    .await
+                    .increment(StatsCounter::CsfloatRateLimited);
+            }
+            StatusCode::FORBIDDEN => {
+                self.stats
+                    .lock()
+                    .await
+                    .increment(StatsCounter::CsfloatForbidden);
+                warn!("Csfloat answered 403 for {}", response.url());
+                // the requests with a key are alerted by `send`
+                if self.keys.lock().unwrap().is_empty() {
+                    self.alert_forbidden(format!(
+                        "Csfloat answered 403 for {}",
+                        response.url().path()
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_parse_rate_limit_headers() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(LIMIT_HEADER, HeaderValue::from_static("50000"));
+        headers.insert(REMAINING_HEADER, HeaderValue::from_static("120"));
+        headers.insert(RESET_HEADER, HeaderValue::from_static("1700000600"));
+
+        let info = parse_rate_limit_headers(&headers, now);
+        assert_eq!(info.limit, Some(50000));
+        assert_eq!(info.remaining, Some(120));
+        assert_eq!(info.reset_at, Some(now + chrono::Duration::seconds(600)));
+
+        headers.insert(RESET_HEADER, HeaderValue::from_static("60"));
+        let info = parse_rate_limit_headers(&headers, now);
+        assert_eq!(info.reset_at, Some(now + chrono::Duration::seconds(60)));
+
+        assert_eq!(
+            parse_rate_limit_headers(&HeaderMap::new(), now),
+            RateLimitInfo::default()
+        );
+    }
+
+    #[test]
+    fn test_parse_api_error() {
+        let api_error = CsfloatApiError::parse(
+            r#"{"code": 4, "message": "the listing is no longer available"}"#,
+        )
+        .unwrap();
+        assert_eq!(api_error.code, 4);
+        assert!(!api_error.is_auth_error());
+
+        let api_error = CsfloatApiError::parse(r#"{"message": "Invalid API key"}"#).unwrap();
+        assert_eq!(api_error.code, 0);
+        assert!(api_error.is_auth_error());
+
+        assert_eq!(CsfloatApiError::parse("<html>Bad gateway</html>"), None);
+        // a listing is not an error
+        assert_eq!(
+            CsfloatApiError::parse(r#"{"id": "1", "price": 1000}"#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_throttled_interval() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let min_interval = Duration::from_secs(1);
+        let mut info = RateLimitInfo {
+            limit: Some(50000),
+            remaining: Some(120),
+            reset_at: Some(now + chrono::Duration::seconds(600)),
+        };
+        assert_eq!(
+            throttled_interval(&info, min_interval, now),
+            Duration::from_secs(5)
+        );
+
+        info.remaining = Some(0);
+        assert_eq!(
+            throttled_interval(&info, min_interval, now),
+            Duration::from_secs(600)
+        );
+
+        info.remaining = Some(100_000);
+        assert_eq!(throttled_interval(&info, min_interval, now), min_interval);
+        assert_eq!(
+            throttled_interval(&RateLimitInfo::default(), min_interval, now),
+            min_interval
+        );
+    }
+
+    #[test]
+    fn test_api_key_rotation() {
+        let mut keys = ApiKeys::parse(" first-key, ,second-key,third-key ");
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys.get_current(), Some("first-key"));
+        assert_eq!(keys.label(1), "#2 (...-key)");
+
+        assert!(keys.retire(0));
+        assert!(!keys.retire(0));
+        assert_eq!(keys.get_current(), Some("second-key"));
+        // a key which isn't used doesn't move the current one
+        assert!(keys.retire(2));
+        assert_eq!(keys.get_current(), Some("second-key"));
+
+        // with none left the last one stays
+        assert!(keys.retire(1));
+        assert_eq!(keys.get_alive_count(), 0);
+        assert_eq!(keys.get_current(), Some("second-key"));
+
+        assert!(keys.revive(2));
+        assert!(!keys.revive(2));
+        assert_eq!(keys.get_current(), Some("third-key"));
+        assert!(keys.revive(0));
+        assert_eq!(keys.get_current(), Some("third-key"));
+
+        assert!(ApiKeys::parse("").is_empty());
+    }
+}
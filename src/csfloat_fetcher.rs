@@ -0,0 +1,256 @@
+use std::{collections::HashSet, env, time::Duration};
+
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Client, StatusCode,
+};
+use tokio::time::Instant;
+use tracing::{error, warn};
+
+use crate::{config::CsfloatFetcherConfig, csfloat_client::CsfloatClient, types::ListingId};
+
+pub const LISTINGS_URL: &str = "https://csfloat.com/api/v1/listings";
+
+// Spreads requests evenly: every call to `wait` returns not earlier than `interval`
+// after the previous one.
+pub struct RateLimiter {
+    interval: Duration,
+    next_call: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        RateLimiter {
+            interval,
+            next_call: Instant::now(),
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    // postpones the next call, e.g. after the server asked us to slow down
+    pub fn pause(&mut self, duration: Duration) {
+        self.next_call = self.next_call.max(Instant::now() + duration);
+    }
+
+    // Takes the call only when it's due, without waiting
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_call {
+            return false;
+        }
+        self.next_call = now + self.interval;
+        true
+    }
+
+    pub async fn wait(&mut self) {
+        tokio::time::sleep_until(self.next_call).await;
+        self.next_call = Instant::now() + self.interval;
+    }
+}
+
+pub struct CsfloatFetcher {
+    client: Client,
+    rate_limiter: RateLimiter,
+    // ids returned by the previous cycle, used to detect that we caught up
+    last_seen: HashSet<ListingId>,
+}
+
+impl CsfloatFetcher {
+    pub fn from_env(config: &CsfloatFetcherConfig) -> CsfloatFetcher {
+        let mut headers = HeaderMap::new();
+        match env::var("CSFLOAT_API_KEY") {
+            Ok(api_key) => {
+                headers.insert(
+                    AUTHORIZATION,
+                    HeaderValue::from_str(api_key.as_str()).unwrap(),
+                );
+            }
+            Err(e) => error!("CSFLOAT_API_KEY is not set for csfloat fetcher! {:?}", e),
+        };
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .default_headers(headers)
+            .build()
+            .expect("Failed to build client for csfloat fetcher");
+
+        CsfloatFetcher {
+            client,
+            rate_limiter: RateLimiter::new(config.request_interval()),
+            last_seen: HashSet::new(),
+        }
+    }
+
+    // Returns raw JSON arrays of listings (one per page), newest first.
+    // Stops paginating as soon as a page contains a listing seen in the previous cycle.
+    pub async fn fetch_new(&mut self, config: &CsfloatFetcherConfig) -> Vec<String> {
+        self.rate_limiter.set_interval(config.request_interval());
+
+        let mut result = vec![];
+        let mut seen_now: HashSet<ListingId> = HashSet::new();
+        let mut cursor: Option<String> = None;
+        let page_size = config.page_size.to_string();
+
+        for _ in 0..config.max_pages {
+            self.rate_limiter.wait().await;
+
+            let mut request = self
+                .client
+                .get(LISTINGS_URL)
+                .query(&[("sort_by", "most_recent"), ("limit", page_size.as_str())]);
+            if let Some(cursor) = &cursor {
+                request = request.query(&[("cursor", cursor.as_str())]);
+            }
+
+            let page = match request.send().await {
+                Ok(response) if response.status().is_success() => response.text().await.ok(),
+                Ok(response) => {
+                    warn!("Csfloat listings request failed: {}", response.status());
+                    None
+                }
+                Err(err) => {
+                    warn!("Csfloat listings request failed: {:?}", err);
+                    None
+                }
+            };
+            let Some(page) = page else {
+                break;
+            };
+
+            let Some((listings, next_cursor)) = split_listings_page(&page) else {
+                warn!("Unexpected csfloat listings response: {}", page);
+                break;
+            };
+
+            let ids: Vec<ListingId> = listings
+                .as_array()
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter_map(|item| item["id"].as_str().map(ListingId::from))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let is_caught_up = ids.is_empty() || ids.iter().any(|id| self.last_seen.contains(id));
+            seen_now.extend(ids);
+            result.push(listings.to_string());
+
+            if is_caught_up || next_cursor.is_none() {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        if !seen_now.is_empty() {
+            self.last_seen = seen_now;
+        }
+        result
+    }
+}
+
+// Result of a request of the one-listing refresher
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListingFetch {
+    // the JSON of the listing
+    Fetched(String),
+    // delisted without us seeing it
+    NotFound,
+    RateLimited,
+    // worth a retry: a server error, a timeout or a broken connection
+    Failed,
+    // other 4xx, a retry won't help
+    Rejected(StatusCode),
+}
+
+pub async fn fetch_listing(client: &CsfloatClient, listing_id: &ListingId) -> ListingFetch {
+    let url = client.url(&format!("/listings/{}", listing_id));
+    let response = match client.send(client.get(&url)).await {
+        Ok(response) => response,
+        Err(err) => {
+            warn!("Csfloat listing {} request failed: {:?}", listing_id, err);
+            return ListingFetch::Failed;
+        }
+    };
+    let status = response.status();
+    if status.is_success() {
+        return match response.text().await {
+            Ok(text) => ListingFetch::Fetched(text),
+            Err(err) => {
+                warn!("Csfloat listing {} request failed: {:?}", listing_id, err);
+                ListingFetch::Failed
+            }
+        };
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    // rate limit and auth errors aren't malformed listings
+    match client.report_error(status, &body).await {
+        Some(api_error) => warn!(
+            "Csfloat listing {} request failed: {} (code {}: {})",
+            listing_id, status, api_error.code, api_error.message
+        ),
+        None => warn!("Csfloat listing {} request failed: {}", listing_id, status),
+    }
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => ListingFetch::RateLimited,
+        StatusCode::NOT_FOUND => ListingFetch::NotFound,
+        _ if status.is_server_error() => ListingFetch::Failed,
+        _ => ListingFetch::Rejected(status),
+    }
+}
+
+// The endpoint returns either a bare array or `{"data": [...], "cursor": "..."}`
+pub fn split_listings_page(page: &str) -> Option<(serde_json::Value, Option<String>)> {
+    let mut parsed = serde_json::from_str::<serde_json::Value>(page).ok()?;
+    if parsed.is_array() {
+        return Some((parsed, None));
+    }
+
+    let cursor = parsed["cursor"].as_str().map(|x| x.to_string());
+    let listings = parsed.get_mut("data")?.take();
+    match listings.is_array() {
+        true => Some((listings, cursor)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_try_acquire() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(60));
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+
+        limiter.set_interval(Duration::ZERO);
+        limiter.next_call = Instant::now();
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_split_listings_page_with_cursor() {
+        let (listings, cursor) =
+            split_listings_page(r#"{"data": [{"id": "1"}, {"id": "2"}], "cursor": "abc"}"#)
+                .unwrap();
+        assert_eq!(listings.as_array().unwrap().len(), 2);
+        assert_eq!(cursor, Some("abc".to_string()));
+    }
+
+    #[test]
+    fn test_split_listings_page_bare_array() {
+        let (listings, cursor) = split_listings_page(r#"[{"id": "1"}]"#).unwrap();
+        assert_eq!(listings.as_array().unwrap().len(), 1);
+        assert_eq!(cursor, None);
+    }
+
+    #[test]
+    fn test_split_listings_page_error_body() {
+        assert!(split_listings_page(r#"{"code": 4, "message": "rate limited"}"#).is_none());
+    }
+}
@@ -0,0 +1,210 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
+
+use arc_swap::ArcSwap;
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::prices::PriceValue;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Currency {
+    #[default]
+    Usd,
+    Eur,
+    Gbp,
+    Cny,
+    Rub,
+    Pln,
+    Brl,
+    Uah,
+}
+
+impl Currency {
+    pub const ALL: [Currency; 8] = [
+        Currency::Usd,
+        Currency::Eur,
+        Currency::Gbp,
+        Currency::Cny,
+        Currency::Rub,
+        Currency::Pln,
+        Currency::Brl,
+        Currency::Uah,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+            Currency::Gbp => "GBP",
+            Currency::Cny => "CNY",
+            Currency::Rub => "RUB",
+            Currency::Pln => "PLN",
+            Currency::Brl => "BRL",
+            Currency::Uah => "UAH",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Currency> {
+        Currency::ALL
+            .into_iter()
+            .find(|x| x.code().eq_ignore_ascii_case(code))
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Currency::parse(s).ok_or_else(|| format!("unknown currency {}", s))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+// Price in cents (or the smallest unit) of any currency. Everything past the
+// parsing is compared in USD `PriceValue`, so money is converted right away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Money {
+    pub amount: PriceValue,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: PriceValue, currency: Currency) -> Self {
+        Money { amount, currency }
+    }
+
+    // None while the rate of the currency is unknown
+    pub fn to_usd(self, rates: &ExchangeRates) -> Option<PriceValue> {
+        let rate = rates.get_usd_rate(self.currency)?;
+        Some((self.amount as f64 * rate).round() as PriceValue)
+    }
+}
+
+// Units of each currency per 1 USD
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExchangeRates {
+    pub rates: HashMap<Currency, f64>,
+    // None until the rates are fetched for the first time
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+pub type SharedRates = Arc<ArcSwap<ExchangeRates>>;
+
+impl ExchangeRates {
+    pub fn new() -> Self {
+        ExchangeRates::default()
+    }
+
+    pub fn into_shared(self) -> SharedRates {
+        Arc::new(ArcSwap::from_pointee(self))
+    }
+
+    // Multiplier from the currency to USD
+    pub fn get_usd_rate(&self, currency: Currency) -> Option<f64> {
+        if currency == Currency::Usd {
+            return Some(1.0);
+        }
+        self.rates
+            .get(&currency)
+            .filter(|x| **x > 0.0)
+            .map(|x| 1.0 / x)
+    }
+}
+
+#[derive(Deserialize)]
+struct ExchangeRatesResponse {
+    result: String,
+    base_code: String,
+    rates: HashMap<String, f64>,
+    time_last_update_unix: i64,
+}
+
+// Response of open.er-api.com (ExchangeRate-API) with USD as the base currency,
+// currencies we don't know are skipped
+pub fn parse_exchange_rates(body: &str) -> Option<ExchangeRates> {
+    let response = serde_json::from_str::<ExchangeRatesResponse>(body).ok()?;
+    if response.result != "success" || Currency::parse(&response.base_code) != Some(Currency::Usd) {
+        return None;
+    }
+    Some(ExchangeRates {
+        rates: response
+            .rates
+            .iter()
+            .filter_map(|(code, rate)| Some((Currency::parse(code)?, *rate)))
+            .collect(),
+        updated_at: Utc
+            .timestamp_opt(response.time_last_update_unix, 0)
+            .single(),
+    })
+}
+
+#[derive(Debug)]
+pub enum ExchangeRatesError {
+    Http(reqwest::Error),
+    // not a successful response with USD as the base currency
+    Unexpected(String),
+}
+
+impl fmt::Display for ExchangeRatesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeRatesError::Http(err) => write!(f, "http: {}", err),
+            ExchangeRatesError::Unexpected(body) => write!(f, "unexpected response: {}", body),
+        }
+    }
+}
+
+pub async fn fetch_exchange_rates(
+    client: &Client,
+    url: &str,
+) -> Result<ExchangeRates, ExchangeRatesError> {
+    let body = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|x| x.error_for_status())
+        .map_err(ExchangeRatesError::Http)?
+        .text()
+        .await
+        .map_err(ExchangeRatesError::Http)?;
+    parse_exchange_rates(&body).ok_or(ExchangeRatesError::Unexpected(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_exchange_rates() {
+        let rates = parse_exchange_rates(
+            r#"{
+                "result": "success",
+                "time_last_update_unix": 1704067200,
+                "base_code": "USD",
+                "rates": {"USD": 1, "EUR": 0.8, "CNY": 7.25, "JPY": 141.5}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(rates.rates.len(), 3);
+        assert_eq!(
+            rates.updated_at,
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+        );
+        assert_eq!(Money::new(8_00, Currency::Eur).to_usd(&rates), Some(10_00));
+        assert_eq!(Money::new(72_50, Currency::Cny).to_usd(&rates), Some(10_00));
+        assert_eq!(Money::new(10_00, Currency::Usd).to_usd(&rates), Some(10_00));
+        assert_eq!(Money::new(10_00, Currency::Rub).to_usd(&rates), None);
+
+        assert!(
+            parse_exchange_rates(r#"{"result": "error", "error-type": "unsupported-code"}"#)
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::State,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html,
+    },
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+use tracing::error;
+
+use crate::{
+    admin_api::AdminState,
+    event_processors::get_kind_description,
+    events::{ProfitableListingEvent, Venue},
+    ledger::{load_purchases_since, PurchaseRecord},
+    prices::PriceValue,
+    queues::QueueDepth,
+    strategies::StrategyName,
+    types::{ListingId, MarketName},
+};
+
+const DEAL_FEED_SIZE: usize = 256;
+const PURCHASES_LIMIT: usize = 20;
+const PURCHASES_DAYS: i64 = 7;
+
+// Deal as it's shown on the dashboard
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DealView {
+    pub found_at: DateTime<Utc>,
+    pub market_name: MarketName,
+    pub listing_id: ListingId,
+    pub venue: Venue,
+    pub kind: String,
+    pub strategy: Option<StrategyName>,
+    pub price: PriceValue,
+    pub steam_no_fee: PriceValue,
+    pub profit_pct: f64,
+    pub float: Option<f64>,
+}
+
+impl DealView {
+    pub fn new(event: &ProfitableListingEvent, found_at: DateTime<Utc>) -> Self {
+        DealView {
+            found_at,
+            market_name: event.market_name.clone(),
+            listing_id: event.listing_id.clone(),
+            venue: event.venue,
+            kind: get_kind_description(&event.kind),
+            strategy: event.strategy,
+            price: event.csfloat_price,
+            steam_no_fee: event.steam_no_fee,
+            profit_pct: event.profit_pct,
+            float: event.float,
+        }
+    }
+}
+
+// Live stream of the found deals. Nothing is kept while no dashboard is open,
+// a slow dashboard skips the deals it has fallen behind on.
+#[derive(Clone)]
+pub struct DealFeed {
+    tx: broadcast::Sender<DealView>,
+}
+
+impl Default for DealFeed {
+    fn default() -> Self {
+        DealFeed::new()
+    }
+}
+
+impl DealFeed {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(DEAL_FEED_SIZE);
+        DealFeed { tx }
+    }
+
+    pub fn publish(&self, event: &ProfitableListingEvent) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        // fails only when the last dashboard has just disconnected
+        let _ = self.tx.send(DealView::new(event, Utc::now()));
+    }
+
+    pub fn subscribe(&self) -> BroadcastStream<DealView> {
+        BroadcastStream::new(self.tx.subscribe())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EngineSizes {
+    pub csfloat_listings: usize,
+    pub steam_analyses: usize,
+    pub steam_order_books: usize,
+    pub scheduler: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DashboardStatus {
+    pub engines: EngineSizes,
+    pub queues: Vec<QueueDepth>,
+    // autobuy history, newest first
+    pub purchases: Vec<PurchaseRecord>,
+}
+
+pub async fn get_dashboard() -> Html<&'static str> {
+    Html(include_str!("dashboard.html"))
+}
+
+fn to_sse_event(deal: Result<DealView, BroadcastStreamRecvError>) -> Option<Event> {
+    Event::default().event("deal").json_data(deal.ok()?).ok()
+}
+
+pub async fn get_events(
+    State(state): State<AdminState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = state
+        .deal_feed
+        .subscribe()
+        .filter_map(|x| to_sse_event(x).map(Ok));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub async fn get_status(State(state): State<AdminState>) -> Json<DashboardStatus> {
+    let csfloat_listings = state.csfloat_engine.lock().await.hm.len();
+    let (steam_analyses, steam_order_books) = {
+        let steam_engine_locked = state.steam_engine.lock().await;
+        (
+            steam_engine_locked.hm.len(),
+            steam_engine_locked.order_books.len(),
+        )
+    };
+    let scheduler = state.csfloat_scheduler.lock().await.get_size();
+
+    let since = Utc::now() - Duration::days(PURCHASES_DAYS);
+    let purchases = match load_purchases_since(&state.pool, since).await {
+        Ok(purchases) => purchases.into_iter().rev().take(PURCHASES_LIMIT).collect(),
+        Err(err) => {
+            error!("Failed to load purchases for the dashboard: {:?}", err);
+            vec![]
+        }
+    };
+
+    Json(DashboardStatus {
+        engines: EngineSizes {
+            csfloat_listings,
+            steam_analyses,
+            steam_order_books,
+            scheduler,
+        },
+        queues: state.queues.get_depths(),
+        purchases,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{events::ProfitableListingKind, tests::fixtures::get_profitable_listing_event};
+
+    fn get_event(listing_id: &str) -> ProfitableListingEvent {
+        ProfitableListingEvent {
+            kind: ProfitableListingKind::LowFloat,
+            listing_id: listing_id.into(),
+            float: Some(0.15),
+            strategy: Some(StrategyName::LowFloat),
+            ..get_profitable_listing_event()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deal_feed() {
+        let feed = DealFeed::new();
+        // nobody is watching
+        feed.publish(&get_event("1"));
+
+        let mut stream = feed.subscribe();
+        feed.publish(&get_event("2"));
+        let deal = stream.next().await.unwrap().unwrap();
+        assert_eq!(deal.listing_id, "2");
+        assert_eq!(deal.kind, "Low float");
+        assert_eq!(deal.strategy, Some(StrategyName::LowFloat));
+
+        let json = serde_json::to_value(&deal).unwrap();
+        assert_eq!(json["venue"], "Csfloat");
+        assert_eq!(json["strategy"], "low_float");
+        assert_eq!(json["price"], 10_00);
+    }
+}
@@ -0,0 +1,389 @@
+use std::{collections::HashMap, env, time::Duration};
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signer, SigningKey};
+use reqwest::{Client, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{
+    config::DmarketConfig,
+    csfloat_autobuy::{BuyOutcome, CsfloatBuyError},
+    names::deserialize_market_name,
+    prices::PriceValue,
+    types::{ListingId, MarketName},
+};
+
+const API_URL: &str = "https://api.dmarket.com";
+const MARKET_ITEMS_PATH: &str = "/exchange/v1/market/items";
+const OFFERS_BUY_PATH: &str = "/exchange/v1/offers-buy";
+// DMarket's id of CS2
+const CS2_GAME_ID: &str = "a8db";
+
+// Prices are strings of USD cents, e.g. {"USD": "1250"}
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct DmarketPrice {
+    #[serde(rename = "USD", default)]
+    pub usd: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DmarketItemExtra {
+    // what is bought, the item id changes when the item is relisted
+    #[serde(default)]
+    pub offer_id: String,
+    #[serde(default)]
+    pub float_value: Option<f64>,
+}
+
+// One offer of the market items response
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DmarketItem {
+    pub item_id: String,
+    // market_hash_name
+    #[serde(deserialize_with = "deserialize_market_name")]
+    pub title: MarketName,
+    pub price: DmarketPrice,
+    #[serde(default)]
+    pub extra: DmarketItemExtra,
+}
+
+impl DmarketItem {
+    pub fn get_price(&self) -> Option<PriceValue> {
+        self.price.usd.parse().ok()
+    }
+
+    pub fn get_float(&self) -> Option<f64> {
+        self.extra.float_value.filter(|x| *x > 0.0)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DmarketItemsResponse {
+    #[serde(default)]
+    pub objects: Vec<DmarketItem>,
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+pub enum DmarketEngineDecision {
+    New,
+    NotChanged,
+    Updated,
+}
+
+// DMarket offers are only seen while they're among the newest ones, so the engine
+// is kept only in memory and offers are forgotten after `dmarket.listing_ttl_secs`
+#[derive(Debug, Default)]
+pub struct DmarketEngine {
+    pub hm: HashMap<ListingId, (DmarketItem, DateTime<Utc>)>,
+}
+
+impl DmarketEngine {
+    pub fn new() -> Self {
+        DmarketEngine::default()
+    }
+
+    pub fn update_item(&mut self, item: &DmarketItem, now: DateTime<Utc>) -> DmarketEngineDecision {
+        let offer_id = ListingId::from(item.extra.offer_id.as_str());
+        match self.hm.insert(offer_id, (item.clone(), now)) {
+            Some((old_item, _)) if old_item.price == item.price => {
+                DmarketEngineDecision::NotChanged
+            }
+            Some(_) => DmarketEngineDecision::Updated,
+            None => DmarketEngineDecision::New,
+        }
+    }
+
+    // Returns how many offers are forgotten
+    pub fn evict_older_than(&mut self, deadline: DateTime<Utc>) -> usize {
+        let size = self.hm.len();
+        self.hm.retain(|_, (_, seen_at)| *seen_at >= deadline);
+        size - self.hm.len()
+    }
+}
+
+// Request of the DMarket trading API signed with the Ed25519 key pair of the account:
+// `X-Request-Sign` is the signature of method + path with query + body + `X-Sign-Date`.
+pub struct DmarketClient {
+    client: Client,
+    public_key: String,
+    signing_key: SigningKey,
+}
+
+impl DmarketClient {
+    // None without DMARKET_PUBLIC_KEY and DMARKET_SECRET_KEY
+    pub fn from_env(config: &DmarketConfig) -> Option<DmarketClient> {
+        let (Ok(public_key), Ok(secret_key)) = (
+            env::var("DMARKET_PUBLIC_KEY"),
+            env::var("DMARKET_SECRET_KEY"),
+        ) else {
+            return None;
+        };
+        match DmarketClient::new(public_key, &secret_key, config.request_timeout()) {
+            Ok(client) => Some(client),
+            Err(err) => {
+                error!("DMARKET_SECRET_KEY is invalid: {}", err);
+                None
+            }
+        }
+    }
+
+    // The secret key is hex of 64 bytes (seed and public key) as given by DMarket,
+    // or of the 32 bytes seed alone
+    pub fn new(
+        public_key: String,
+        secret_key: &str,
+        timeout: Duration,
+    ) -> Result<DmarketClient, String> {
+        let bytes = hex::decode(secret_key.trim()).map_err(|err| err.to_string())?;
+        let seed: [u8; 32] = bytes
+            .get(..32)
+            .and_then(|x| x.try_into().ok())
+            .ok_or_else(|| format!("expected 32 or 64 bytes, got {}", bytes.len()))?;
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build client for dmarket");
+        Ok(DmarketClient {
+            client,
+            public_key,
+            signing_key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    pub fn sign(
+        &self,
+        method: &Method,
+        path_with_query: &str,
+        body: &str,
+        timestamp: i64,
+    ) -> String {
+        let message = format!(
+            "{}{}{}{}",
+            method.as_str(),
+            path_with_query,
+            body,
+            timestamp
+        );
+        let signature = self.signing_key.sign(message.as_bytes());
+        format!("dmar ed25519 {}", hex::encode(signature.to_bytes()))
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path_with_query: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<(StatusCode, String), reqwest::Error> {
+        let body = body.map(|x| x.to_string()).unwrap_or_default();
+        let timestamp = Utc::now().timestamp();
+        let signature = self.sign(&method, path_with_query, &body, timestamp);
+        let mut request = self
+            .client
+            .request(method, format!("{}{}", API_URL, path_with_query))
+            .header("X-Api-Key", &self.public_key)
+            .header("X-Sign-Date", timestamp.to_string())
+            .header("X-Request-Sign", signature);
+        if !body.is_empty() {
+            request = request
+                .header("Content-Type", "application/json")
+                .body(body);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        Ok((status, text))
+    }
+
+    // Raw JSON of the newest offers priced within the band, see `DmarketItemsResponse`
+    pub async fn fetch_market_items(
+        &self,
+        min_price: PriceValue,
+        max_price: PriceValue,
+        config: &DmarketConfig,
+    ) -> Option<String> {
+        let path = format!(
+            "{}?gameId={}&currency=USD&orderBy=updated&orderDir=desc&limit={}&priceFrom={}&priceTo={}",
+            MARKET_ITEMS_PATH, CS2_GAME_ID, config.page_size, min_price, max_price
+        );
+        match self.send(Method::GET, &path, None).await {
+            Ok((status, text)) if status.is_success() => Some(text),
+            Ok((status, text)) => {
+                warn!("Dmarket market items request failed: {} {}", status, text);
+                None
+            }
+            Err(err) => {
+                warn!("Dmarket market items request failed: {:?}", err);
+                None
+            }
+        }
+    }
+
+    pub async fn buy_offer(
+        &self,
+        offer_id: &ListingId,
+        price: PriceValue,
+    ) -> Result<BuyOutcome, CsfloatBuyError> {
+        let body = serde_json::json!({
+            "offers": [{
+                "offerId": offer_id.to_string(),
+                "price": { "amount": price.to_string(), "currency": "USD" },
+                "type": "dmarket",
+            }]
+        });
+        let (status, text) = self
+            .send(Method::PATCH, OFFERS_BUY_PATH, Some(&body))
+            .await
+            .map_err(|err| match err.is_connect() {
+                true => CsfloatBuyError::Request(err.to_string()),
+                // DMarket could have executed it
+                false => CsfloatBuyError::Unknown(err.to_string()),
+            })?;
+        if !status.is_success() {
+            warn!(
+                "Failed to buy dmarket offer {}: {} {}",
+                offer_id, status, text
+            );
+            return Err(parse_buy_error(status, &text));
+        }
+        // the offer may be bought, it's left to the operator as a pending purchase
+        let Ok(response) = serde_json::from_str::<serde_json::Value>(&text) else {
+            warn!(
+                "Unexpected answer to the purchase of dmarket offer {}: {}",
+                offer_id, text
+            );
+            return Err(CsfloatBuyError::Unknown(format!("{} {}", status, text)));
+        };
+        if response["status"] == "TxFailed" {
+            return Err(CsfloatBuyError::AlreadySold);
+        }
+        Ok(BuyOutcome {
+            is_success: true,
+            status: Some(status.as_u16()),
+            response,
+        })
+    }
+}
+
+// DMarket answers with {"code": "...", "message": "..."}, mapped onto the CSFloat errors,
+// so the purchase is reported and stops the autobuy the same way
+pub fn parse_buy_error(status: StatusCode, body: &str) -> CsfloatBuyError {
+    let response: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+    let message = response["message"].as_str().unwrap_or(body).to_string();
+    let text = format!("{} {}", response["code"].as_str().unwrap_or(""), message).to_lowercase();
+    match status {
+        StatusCode::UNAUTHORIZED => CsfloatBuyError::AuthExpired,
+        StatusCode::TOO_MANY_REQUESTS => CsfloatBuyError::RateLimited { retry_after: None },
+        _ if text.contains("insufficient") || text.contains("not enough") => {
+            CsfloatBuyError::InsufficientBalance
+        }
+        _ if text.contains("not found") || text.contains("sold") => CsfloatBuyError::AlreadySold,
+        _ if text.contains("price") => CsfloatBuyError::PriceChanged,
+        _ => CsfloatBuyError::Api {
+            code: status.as_u16() as i64,
+            message,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature, Verifier};
+
+    use super::*;
+
+    const RESPONSE: &str = r#"{
+        "cursor": "MjAw",
+        "objects": [{
+            "itemId": "c7b2e0a4-8d2b-5b5d-9d1e-2a0a3e0f6e11",
+            "title": "AK-47 | Redline (Field-Tested)",
+            "price": {"USD": "1250"},
+            "extra": {"offerId": "0f5b1c2e-3c4d-4e5f-8a9b-0c1d2e3f4a5b", "floatValue": 0.2312}
+        }, {
+            "itemId": "d1e2f3a4-0000-4000-8000-000000000001",
+            "title": "Operation Breakout Weapon Case",
+            "price": {"USD": "95"},
+            "extra": {"offerId": "a1b2c3d4-0000-4000-8000-000000000002", "floatValue": 0}
+        }]
+    }"#;
+
+    #[test]
+    fn test_update_item_lifecycle() {
+        let parsed = serde_json::from_str::<DmarketItemsResponse>(RESPONSE).unwrap();
+        assert_eq!(parsed.objects.len(), 2);
+        let item = &parsed.objects[0];
+        assert_eq!(item.get_price(), Some(12_50));
+        assert_eq!(item.get_float(), Some(0.2312));
+        assert_eq!(parsed.objects[1].get_float(), None);
+
+        let now = Utc::now();
+        let mut engine = DmarketEngine::new();
+        assert!(matches!(
+            engine.update_item(item, now),
+            DmarketEngineDecision::New
+        ));
+        assert!(matches!(
+            engine.update_item(item, now),
+            DmarketEngineDecision::NotChanged
+        ));
+        let mut repriced = item.clone();
+        repriced.price.usd = "1100".to_string();
+        assert!(matches!(
+            engine.update_item(&repriced, now),
+            DmarketEngineDecision::Updated
+        ));
+
+        engine.update_item(&parsed.objects[1], now + chrono::Duration::hours(1));
+        assert_eq!(
+            engine.evict_older_than(now + chrono::Duration::minutes(30)),
+            1
+        );
+        assert_eq!(engine.hm.len(), 1);
+    }
+
+    #[test]
+    fn test_sign() {
+        let secret_key = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+        let client =
+            DmarketClient::new("public".to_string(), secret_key, Duration::from_secs(1)).unwrap();
+        let header = client.sign(&Method::GET, "/account/v1/balance", "", 1_700_000_000);
+        let signature = header.strip_prefix("dmar ed25519 ").unwrap();
+        let signature = Signature::from_slice(&hex::decode(signature).unwrap()).unwrap();
+        client
+            .signing_key
+            .verifying_key()
+            .verify(b"GET/account/v1/balance1700000000", &signature)
+            .unwrap();
+
+        assert!(DmarketClient::new("public".to_string(), "abcd", Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn test_parse_buy_error() {
+        assert_eq!(
+            parse_buy_error(StatusCode::UNAUTHORIZED, ""),
+            CsfloatBuyError::AuthExpired
+        );
+        assert_eq!(
+            parse_buy_error(
+                StatusCode::BAD_REQUEST,
+                r#"{"code": "InsufficientFunds", "message": "Insufficient funds"}"#
+            ),
+            CsfloatBuyError::InsufficientBalance
+        );
+        assert_eq!(
+            parse_buy_error(
+                StatusCode::BAD_REQUEST,
+                r#"{"code": "OfferNotFound", "message": "offer not found"}"#
+            ),
+            CsfloatBuyError::AlreadySold
+        );
+        assert!(matches!(
+            parse_buy_error(StatusCode::INTERNAL_SERVER_ERROR, "oops"),
+            CsfloatBuyError::Api { code: 500, .. }
+        ));
+    }
+}
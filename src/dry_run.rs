@@ -0,0 +1,371 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use sqlx::{Pool, Postgres};
+
+use crate::{
+    business_logic::{check_autobuy, check_notify_via_telegram, prefilter_listing},
+    cli::parse_time,
+    config::AppConfig,
+    csfloat::CsfloatScheduler,
+    currency::ExchangeRates,
+    event_processors::{
+        get_kind_description, parse_steam_response, process_updated_csfloat_listing,
+    },
+    events::{Event, PrimEvent, SecEvent, SteamResponseEvent, UpdatedCsfloatListingsEvent},
+    filters::{load_listing_filters, ListingFilters},
+    ledger::load_risk_manager,
+    models::{CsfloatListingState, CsfloatListingStruct},
+    prices::PriceValue,
+    risk::RiskManager,
+    sharding::Shard,
+    state_store::AnyStateStore,
+    steam_analyzer::{AnalysisResult, REL_STD_MAX},
+    storages::{CsfloatEngine, CsfloatEngineTrait, DbSerializable, SteamEngine},
+    strategies::StrategyName,
+    types::{ListingId, MarketName},
+    watchlist::{load_watch_rules, Watchlist},
+};
+
+// Subcommands which run a part of the pipeline on a saved input and print the result
+// as JSON, without starting the tasks of the bot
+
+// `analyze <file.html> [<at>]`, e.g. analyze page.html 2024-02-19T00:00:00Z
+#[derive(Debug, PartialEq, clap::Args)]
+pub struct AnalyzeArgs {
+    #[arg(value_name = "file.html")]
+    pub path: String,
+    // end of the analyzed window, the sales of a saved page are older than now
+    #[arg(value_parser = parse_time, help = "RFC 3339, now by default")]
+    pub at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnalyzeOutput {
+    pub market_name: MarketName,
+    pub at: DateTime<Utc>,
+    pub window_days: i64,
+    // `analysis.rsd` below it is stable
+    pub max_rsd: f64,
+    pub analysis: AnalysisResult,
+}
+
+// The same analysis as of a Steam response of the pipeline. The page is expected
+// in USD unless `currency.steam_wallet_currency` says otherwise.
+pub fn analyze_steam_page(
+    html: String,
+    at: DateTime<Utc>,
+    config: &AppConfig,
+) -> Result<AnalyzeOutput, String> {
+    let wallet_currency = config.currency.steam_wallet_currency;
+    // rates are fetched by the running bot only
+    if ExchangeRates::new().get_usd_rate(wallet_currency).is_none() {
+        return Err(format!(
+            "Pages in {} can't be analyzed offline, set currency.steam_wallet_currency = \"USD\"",
+            wallet_currency
+        ));
+    }
+    let event = SteamResponseEvent {
+        timestamp: at,
+        response: html,
+        sell_listings: None,
+        log_seq: None,
+    };
+    let (market_name, analysis) = parse_steam_response(&event, &ExchangeRates::new(), config)
+        .ok_or("No market name or sell history in the page")?;
+    Ok(AnalyzeOutput {
+        market_name,
+        at,
+        window_days: config.steam_analyzer.window_days,
+        max_rsd: REL_STD_MAX,
+        analysis,
+    })
+}
+
+pub fn run_analyze(args: &AnalyzeArgs, config: &AppConfig) -> Result<String, String> {
+    let html = std::fs::read_to_string(&args.path)
+        .map_err(|err| format!("Can't read {}: {}", args.path, err))?;
+    let output = analyze_steam_page(html, args.at.unwrap_or_else(Utc::now), config)?;
+    serde_json::to_string_pretty(&output).map_err(|err| err.to_string())
+}
+
+// `evaluate <listing.json>`, a listing as returned by the CSFloat API
+#[derive(Debug, PartialEq, clap::Args)]
+pub struct EvaluateArgs {
+    #[arg(value_name = "listing.json")]
+    pub path: String,
+}
+
+// What the secondary dispatcher would do with a deal, the rejections are
+// the first failed check
+#[derive(Debug, Serialize)]
+pub struct DealEvaluation {
+    pub kind: String,
+    pub strategy: Option<StrategyName>,
+    pub csfloat_price: PriceValue,
+    pub steam_price: PriceValue,
+    pub steam_no_fee: PriceValue,
+    pub profit_pct: f64,
+    pub is_notified: bool,
+    pub notify_rejection: Option<String>,
+    pub is_bought: bool,
+    pub autobuy_rejection: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListingEvaluation {
+    pub listing_id: ListingId,
+    pub market_name: MarketName,
+    // name of the prefilter rule which dropped the listing
+    pub prefiltered_by: Option<String>,
+    // sold, delisted or refunded listings are only removed
+    pub is_listed: bool,
+    // the strategies needing a Steam price were skipped
+    pub is_steam_analysis_missing: bool,
+    pub is_muted: bool,
+    pub steam_analysis: Option<AnalysisResult>,
+    pub deals: Vec<DealEvaluation>,
+    pub auction: Option<AuctionEvaluation>,
+}
+
+// see `AuctionOpportunityEvent`
+#[derive(Debug, Serialize)]
+pub struct AuctionEvaluation {
+    pub next_bid: PriceValue,
+    pub max_bid: PriceValue,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Runs the listing through `process_updated_csfloat_listing` as if it was just fetched.
+// The engines are changed by it, they're expected to be loaded for this run only.
+#[allow(clippy::too_many_arguments)]
+pub async fn evaluate_listing(
+    listing: &CsfloatListingStruct,
+    steam_engine: &mut SteamEngine,
+    csfloat_engine: &mut CsfloatEngine,
+    watchlist: &Watchlist,
+    listing_filters: &mut ListingFilters,
+    risk_manager: &RiskManager,
+    config: &AppConfig,
+    now: DateTime<Utc>,
+) -> ListingEvaluation {
+    let market_name = listing.item.market_hash_name.clone();
+    let mut evaluation = ListingEvaluation {
+        listing_id: listing.id.clone(),
+        market_name: market_name.clone(),
+        prefiltered_by: prefilter_listing(listing, config)
+            .err()
+            .map(|x| x.to_string()),
+        is_listed: listing.state == CsfloatListingState::Listed,
+        is_steam_analysis_missing: false,
+        is_muted: listing_filters.is_muted(&market_name, &listing.id, now),
+        steam_analysis: steam_engine.hm.get(&market_name).cloned(),
+        deals: vec![],
+        auction: None,
+    };
+    if evaluation.prefiltered_by.is_some() || !evaluation.is_listed {
+        return evaluation;
+    }
+
+    csfloat_engine.update_listing(listing);
+    let event = UpdatedCsfloatListingsEvent {
+        listing_ids: vec![listing.id.clone()],
+    };
+    let events = process_updated_csfloat_listing(
+        steam_engine,
+        csfloat_engine,
+        // refresh tiers don't matter here
+        &mut CsfloatScheduler::new(),
+        watchlist,
+        listing_filters,
+        &event,
+        config,
+    )
+    .await;
+
+    for event in events {
+        match event {
+            Event::Secondary(SecEvent::ProfitableListing(e)) => {
+                let is_filtered = listing_filters.is_filtered(&e, now);
+                let notify = match is_filtered {
+                    true => Err("snoozed item or blacklisted seller"),
+                    false => check_notify_via_telegram(&e, config),
+                };
+                let autobuy = match is_filtered {
+                    true => Err("snoozed item or blacklisted seller".to_string()),
+                    false => check_autobuy(&e, config, risk_manager, None, now)
+                        .map_err(|x| x.to_string()),
+                };
+                evaluation.deals.push(DealEvaluation {
+                    kind: get_kind_description(&e.kind),
+                    strategy: e.strategy,
+                    csfloat_price: e.csfloat_price,
+                    steam_price: e.steam_price,
+                    steam_no_fee: e.steam_no_fee,
+                    profit_pct: e.profit_pct,
+                    is_notified: notify.is_ok(),
+                    notify_rejection: notify.err().map(|x| x.to_string()),
+                    is_bought: autobuy.is_ok(),
+                    autobuy_rejection: autobuy.err(),
+                });
+            }
+            Event::Secondary(SecEvent::AuctionOpportunity(e)) => {
+                evaluation.auction = Some(AuctionEvaluation {
+                    next_bid: e.next_bid,
+                    max_bid: e.max_bid,
+                    expires_at: e.expires_at,
+                });
+            }
+            Event::Primary(PrimEvent::SteamAnalysisRequested(_)) => {
+                evaluation.is_steam_analysis_missing = true;
+            }
+            _ => {}
+        }
+    }
+    evaluation
+}
+
+// Loads the saved engines, the risk state, the watchlist and the filters like the bot
+// does on startup. Nothing is saved back.
+pub async fn run_evaluate(
+    db: &Pool<Postgres>,
+    args: &EvaluateArgs,
+    config: &AppConfig,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let json = std::fs::read_to_string(&args.path)
+        .map_err(|err| format!("Can't read {}: {}", args.path, err))?;
+    let listing: CsfloatListingStruct = serde_json::from_str(&json)
+        .map_err(|err| format!("Can't parse the listing of {}: {}", args.path, err))?;
+
+    let state_store = AnyStateStore::connect(&config.state_store, Shard::default(), db).await?;
+    let mut csfloat_engine = CsfloatEngine::deserialize(&state_store).await;
+    let mut steam_engine = SteamEngine::deserialize(&state_store).await;
+    let now = Utc::now();
+    let risk_manager = load_risk_manager(db, &config.autobuy, now).await?;
+    let watchlist = load_watch_rules(db).await?;
+    let mut listing_filters = load_listing_filters(db).await?;
+
+    let evaluation = evaluate_listing(
+        &listing,
+        &mut steam_engine,
+        &mut csfloat_engine,
+        &watchlist,
+        &mut listing_filters,
+        &risk_manager,
+        config,
+        now,
+    )
+    .await;
+    Ok(serde_json::to_string_pretty(&evaluation)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        steam_analyzer::{Smoothing, Trend},
+        storages::SteamEngineTrait,
+        tests::fixtures::get_listing_json,
+    };
+    use chrono::TimeZone;
+
+    async fn evaluate(
+        listing: &CsfloatListingStruct,
+        steam_engine: &mut SteamEngine,
+        config: &AppConfig,
+    ) -> ListingEvaluation {
+        evaluate_listing(
+            listing,
+            steam_engine,
+            &mut CsfloatEngine::new(),
+            &Watchlist::new(),
+            &mut ListingFilters::new(),
+            &RiskManager::new(),
+            config,
+            Utc::now(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_listing() {
+        let market_name = MarketName::from("Glock-18 | Wasteland Rebel (Minimal Wear)");
+        let listing = |state: &str| -> CsfloatListingStruct {
+            let mut listing = get_listing_json("1", &market_name, 1_00);
+            listing["state"] = state.into();
+            serde_json::from_value(listing).unwrap()
+        };
+        let mut config = AppConfig::default();
+        config.strategy.desired_percentile = 60;
+
+        // without an analysis it's requested
+        let mut steam_engine = SteamEngine::new();
+        let evaluation = evaluate(&listing("listed"), &mut steam_engine, &config).await;
+        assert!(evaluation.is_steam_analysis_missing);
+        assert!(evaluation.deals.is_empty());
+
+        steam_engine.update(
+            &market_name,
+            AnalysisResult {
+                rsd: Some(0.01),
+                is_stable: Some(true),
+                sold_per_week: Some(500),
+                percentiles: vec![(60, 13_00)],
+                percentiles_no_fee: vec![(60, 11_30)],
+                weighted_percentiles: vec![],
+                rejected_outliers: 0,
+                trend: Trend::Flat,
+                analyzed_at: Some(Utc::now()),
+                sell_listings: None,
+                expected_days_to_sell: None,
+                smoothing: Smoothing::Sma,
+                seasonality: None,
+            },
+        );
+        let evaluation = evaluate(&listing("listed"), &mut steam_engine, &config).await;
+        assert!(!evaluation.is_steam_analysis_missing);
+        assert!(evaluation.steam_analysis.is_some());
+        let deal = &evaluation.deals[0];
+        assert!(deal.is_notified);
+        assert!(!deal.is_bought);
+        assert_eq!(
+            deal.autobuy_rejection.as_deref(),
+            Some("autobuy is disabled")
+        );
+
+        config.autobuy.paper_trading = true;
+        let evaluation = evaluate(&listing("listed"), &mut steam_engine, &config).await;
+        assert!(evaluation.deals[0].is_bought);
+
+        // sold listings are only removed
+        let evaluation = evaluate(&listing("sold"), &mut steam_engine, &config).await;
+        assert!(!evaluation.is_listed);
+        assert!(evaluation.deals.is_empty());
+    }
+
+    #[test]
+    fn test_run_analyze() {
+        let args = AnalyzeArgs {
+            path: "src/test_data/Kilowatt Case.html".to_string(),
+            at: Some(Utc.with_ymd_and_hms(2024, 2, 19, 0, 0, 0).unwrap()),
+        };
+        let output = run_analyze(&args, &AppConfig::default()).unwrap();
+        let output: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(output["market_name"], "Kilowatt Case");
+        assert_eq!(output["window_days"], 7);
+        assert_eq!(output["analysis"]["is_stable"], false);
+        assert_eq!(output["analysis"]["sold_per_week"], 604_240);
+
+        let args = AnalyzeArgs {
+            path: "src/test_data/missing.html".to_string(),
+            at: None,
+        };
+        assert!(run_analyze(&args, &AppConfig::default()).is_err());
+        assert!(analyze_steam_page(
+            "<html></html>".to_string(),
+            Utc::now(),
+            &AppConfig::default()
+        )
+        .is_err());
+    }
+}
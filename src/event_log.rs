@@ -0,0 +1,324 @@
+use std::{collections::BTreeSet, time::Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use tokio::sync::{mpsc::Sender, Mutex};
+use tracing::{error, info};
+
+use crate::{
+    config::EventLogConfig,
+    events::{
+        CsfloatOneListingResponseEvent, CsfloatResponseEvent, DmarketResponseEvent, PrimEvent,
+        SkinportResponseEvent, SteamOrdersResponseEvent, SteamResponseEvent,
+    },
+    leadership::Leadership,
+    sharding::Shard,
+    types::MarketName,
+};
+
+// Primary events which come from outside, the rest of them are produced again
+// by processing these. Variants are named after `PrimEvent` ones.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LoggedEvent {
+    CsfloatListingsResponse {
+        response: String,
+    },
+    CsfloatOneListingResponse {
+        response: String,
+    },
+    SteamResponse {
+        timestamp: DateTime<Utc>,
+        response: String,
+        #[serde(default)]
+        sell_listings: Option<u32>,
+    },
+    SteamOrdersResponse {
+        timestamp: DateTime<Utc>,
+        market_name: MarketName,
+        response: String,
+    },
+    SkinportListingsResponse {
+        response: String,
+    },
+    DmarketListingsResponse {
+        response: String,
+    },
+}
+
+impl LoggedEvent {
+    pub fn from_event(event: &PrimEvent) -> Option<LoggedEvent> {
+        let logged_event = match event {
+            PrimEvent::CsfloatListingsResponse(e) => LoggedEvent::CsfloatListingsResponse {
+                response: e.response.clone(),
+            },
+            PrimEvent::CsfloatOneListingResponse(e) => LoggedEvent::CsfloatOneListingResponse {
+                response: e.response.clone(),
+            },
+            PrimEvent::SteamResponse(e) => LoggedEvent::SteamResponse {
+                timestamp: e.timestamp,
+                response: e.response.clone(),
+                sell_listings: e.sell_listings,
+            },
+            PrimEvent::SteamOrdersResponse(e) => LoggedEvent::SteamOrdersResponse {
+                timestamp: e.timestamp,
+                market_name: e.market_name.clone(),
+                response: e.response.clone(),
+            },
+            PrimEvent::SkinportListingsResponse(e) => LoggedEvent::SkinportListingsResponse {
+                response: e.response.clone(),
+            },
+            PrimEvent::DmarketListingsResponse(e) => LoggedEvent::DmarketListingsResponse {
+                response: e.response.clone(),
+            },
+            PrimEvent::UpdatedCsfloatListings(_)
+            | PrimEvent::PaperPurchase(_)
+            | PrimEvent::SteamAnalysisRequested(_)
+            | PrimEvent::SteamAnalysisReady(_) => return None,
+        };
+        Some(logged_event)
+    }
+
+    // `Instant` timestamps can't be restored, they're set to the replay time
+    pub fn into_event(self, log_seq: i64) -> PrimEvent {
+        let log_seq = Some(log_seq);
+        match self {
+            LoggedEvent::CsfloatListingsResponse { response } => {
+                PrimEvent::CsfloatListingsResponse(CsfloatResponseEvent {
+                    timestamp: Instant::now(),
+                    response,
+                    log_seq,
+                })
+            }
+            LoggedEvent::CsfloatOneListingResponse { response } => {
+                PrimEvent::CsfloatOneListingResponse(CsfloatOneListingResponseEvent {
+                    timestamp: Instant::now(),
+                    response,
+                    log_seq,
+                })
+            }
+            LoggedEvent::SteamResponse {
+                timestamp,
+                response,
+                sell_listings,
+            } => PrimEvent::SteamResponse(SteamResponseEvent {
+                timestamp,
+                response,
+                sell_listings,
+                log_seq,
+            }),
+            LoggedEvent::SteamOrdersResponse {
+                timestamp,
+                market_name,
+                response,
+            } => PrimEvent::SteamOrdersResponse(SteamOrdersResponseEvent {
+                timestamp,
+                market_name,
+                response,
+                log_seq,
+            }),
+            LoggedEvent::SkinportListingsResponse { response } => {
+                PrimEvent::SkinportListingsResponse(SkinportResponseEvent {
+                    timestamp: Instant::now(),
+                    response,
+                    log_seq,
+                })
+            }
+            LoggedEvent::DmarketListingsResponse { response } => {
+                PrimEvent::DmarketListingsResponse(DmarketResponseEvent {
+                    timestamp: Instant::now(),
+                    response,
+                    log_seq,
+                })
+            }
+        }
+    }
+}
+
+// Sequence numbers of logged events which aren't processed yet
+#[derive(Debug, Default)]
+pub struct InFlightEvents {
+    seqs: BTreeSet<i64>,
+    last_seq: Option<i64>,
+}
+
+impl InFlightEvents {
+    pub fn add(&mut self, seq: i64) {
+        self.seqs.insert(seq);
+        self.last_seq = self.last_seq.max(Some(seq));
+    }
+
+    pub fn remove(&mut self, seq: i64) {
+        self.seqs.remove(&seq);
+    }
+
+    // Events up to it are processed, None when nothing is logged yet
+    pub fn get_watermark(&self) -> Option<i64> {
+        match self.seqs.first() {
+            Some(seq) => Some(seq - 1),
+            None => self.last_seq,
+        }
+    }
+}
+
+// Write-ahead log of primary events. Events are appended before they're queued,
+// and removed once the engines with them applied are saved, so the events left
+// in the log on startup are the ones lost by a crash. Each shard has its own log,
+// written by its leader only, as only the leader saves the engines.
+pub struct EventLog {
+    db: Pool<Postgres>,
+    shard: Shard,
+    leadership: Leadership,
+    // also held while appending, so a new event can't be missed by the watermark
+    in_flight: Mutex<InFlightEvents>,
+}
+
+impl EventLog {
+    pub fn new(db: Pool<Postgres>, shard: Shard, leadership: Leadership) -> Self {
+        EventLog {
+            db,
+            shard,
+            leadership,
+            in_flight: Mutex::new(InFlightEvents::default()),
+        }
+    }
+
+    // Sets `log_seq` of the event when `event_log.enabled`, failures are only logged
+    pub async fn append(&self, event: &mut PrimEvent, config: &EventLogConfig) {
+        if !config.enabled || !self.leadership.is_leader() {
+            return;
+        }
+        let Some(logged_event) = LoggedEvent::from_event(event) else {
+            return;
+        };
+        let text = serde_json::to_string(&logged_event).unwrap_or_default();
+
+        let mut in_flight_locked = self.in_flight.lock().await;
+        let result = sqlx::query_scalar(
+            "INSERT INTO event_log (shard, event) VALUES ($1, $2) RETURNING seq",
+        )
+        .bind(self.shard.to_string())
+        .bind(text)
+        .fetch_one(&self.db)
+        .await;
+        match result {
+            Ok(seq) => {
+                in_flight_locked.add(seq);
+                event.set_log_seq(seq);
+            }
+            Err(err) => error!("Failed to append event to the event log: {:?}", err),
+        }
+    }
+
+    pub async fn mark_processed(&self, event: &PrimEvent) {
+        self.mark_processed_seq(event.get_log_seq()).await;
+    }
+
+    pub async fn mark_processed_seq(&self, seq: Option<i64>) {
+        if let Some(seq) = seq {
+            self.in_flight.lock().await.remove(seq);
+        }
+    }
+
+    // Should be taken before the engines are saved
+    pub async fn get_watermark(&self) -> Option<i64> {
+        self.in_flight.lock().await.get_watermark()
+    }
+
+    // Called after the engines are saved
+    pub async fn truncate(&self, watermark: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM event_log WHERE shard = $1 AND seq <= $2")
+            .bind(self.shard.to_string())
+            .bind(watermark)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    // Queues the events left in the log, should be called before the producers start.
+    // Returns the number of replayed events.
+    pub async fn replay(&self, tx: &Sender<PrimEvent>) -> Result<usize, sqlx::Error> {
+        let rows = sqlx::query("SELECT seq, event FROM event_log WHERE shard = $1 ORDER BY seq")
+            .bind(self.shard.to_string())
+            .fetch_all(&self.db)
+            .await?;
+
+        let mut replayed = 0;
+        for row in rows {
+            let seq: i64 = row.get("seq");
+            let logged_event: LoggedEvent = match serde_json::from_str(row.get::<&str, _>("event"))
+            {
+                Ok(logged_event) => logged_event,
+                Err(err) => {
+                    error!("Failed to parse logged event #{}: {:?}", seq, err);
+                    continue;
+                }
+            };
+            self.in_flight.lock().await.add(seq);
+            if tx.send(logged_event.into_event(seq)).await.is_err() {
+                error!("Failed to replay event #{}, the queue is closed", seq);
+                break;
+            }
+            replayed += 1;
+        }
+        info!("Replayed {} events from the event log", replayed);
+        Ok(replayed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logged_event_roundtrip() {
+        let event = PrimEvent::SteamOrdersResponse(SteamOrdersResponseEvent {
+            timestamp: Utc::now(),
+            market_name: "AK-47 | Redline (Field-Tested)".into(),
+            response: "{}".to_string(),
+            log_seq: None,
+        });
+        let logged_event = LoggedEvent::from_event(&event).unwrap();
+        let text = serde_json::to_string(&logged_event).unwrap();
+        let parsed: LoggedEvent = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed, logged_event);
+
+        let PrimEvent::SteamOrdersResponse(expected) = event else {
+            unreachable!()
+        };
+        let replayed = parsed.into_event(7);
+        assert_eq!(replayed.get_log_seq(), Some(7));
+        assert_eq!(
+            replayed,
+            PrimEvent::SteamOrdersResponse(SteamOrdersResponseEvent {
+                log_seq: Some(7),
+                ..expected
+            })
+        );
+
+        let event = PrimEvent::SteamAnalysisRequested(crate::events::SteamAnalysisRequestedEvent {
+            market_name: "AK-47 | Redline (Field-Tested)".into(),
+        });
+        assert_eq!(LoggedEvent::from_event(&event), None);
+    }
+
+    #[test]
+    fn test_in_flight_watermark() {
+        let mut in_flight = InFlightEvents::default();
+        assert_eq!(in_flight.get_watermark(), None);
+
+        in_flight.add(1);
+        in_flight.add(2);
+        in_flight.add(3);
+        assert_eq!(in_flight.get_watermark(), Some(0));
+        // processed out of order, 1 is still in the queue
+        in_flight.remove(2);
+        assert_eq!(in_flight.get_watermark(), Some(0));
+        in_flight.remove(1);
+        assert_eq!(in_flight.get_watermark(), Some(2));
+        in_flight.remove(3);
+        assert_eq!(in_flight.get_watermark(), Some(3));
+    }
+}
@@ -1,32 +1,58 @@
 use std::time::Duration;
 
+use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use regex::Regex;
-use teloxide::{requests::Requester, types::Recipient, Bot};
-use tracing::{error, warn};
+use sqlx::{Pool, Postgres};
+use teloxide::utils::markdown::{escape, link};
+use tokio::sync::Mutex;
+use tracing::{error, info, info_span, trace, warn};
 
 use crate::{
     business_logic::{
-        is_good_glock_phase_listing, is_need_notify_via_telegram, is_need_to_autobuy,
-        prefilter_listing,
+        estimate_applied_value, get_buy_cost, get_max_auction_bid, get_max_positions,
+        get_purchase_costs, get_refresh_tier, is_need_notify_via_telegram, is_need_to_autobuy,
+        is_need_to_confirm_buy, is_price_in_band, prefilter_listing,
     },
-    consts::{DESIRED_PERCENTILE, IS_AUTOBUY_ALLOWED, MY_TG_ID},
-    csfloat::CsfloatScheduler,
-    csfloat_autobuy::CsfloatAutobuy,
+    config::AppConfig,
+    csfloat::{CsfloatScheduler, PriorityTier},
+    csfloat_autobuy::{BuyOutcome, CsfloatAutobuy, CsfloatBuyError},
+    currency::ExchangeRates,
+    dmarket::{DmarketEngine, DmarketEngineDecision, DmarketItemsResponse},
     events::{
-        CsfloatOneListingResponseEvent, CsfloatResponseEvent, Event, PrimEvent,
-        ProfitableListingEvent, ProfitableListingKind, SecEvent, SteamResponseEvent,
-        UpdatedCsfloatListingsEvent,
+        AlertEvent, AppliedValue, AuctionOpportunityEvent, BuyRequestedEvent,
+        CsfloatOneListingResponseEvent, CsfloatResponseEvent, DmarketResponseEvent, Event,
+        PaperPurchaseCheckedEvent, PaperPurchaseEvent, PrimEvent, ProfitableListingEvent,
+        ProfitableListingKind, PurchaseConfirmedEvent, SecEvent, SkinportResponseEvent,
+        SteamAnalysisReadyEvent, SteamAnalysisRequestedEvent, SteamOrdersResponseEvent,
+        SteamResponseEvent, UpdatedCsfloatListingsEvent, Venue,
     },
     fee::SteamFee,
-    models::CsfloatListingStruct,
+    filters::ListingFilters,
+    ledger::{record_purchase, save_kill_switch, set_paper_availability, PurchaseRecord},
+    market_floors::MarketFloors,
+    models::{CsfloatListingState, CsfloatListingStruct},
+    names::canonicalize,
+    notify::{DealSummary, Notification, NotificationKind, Notifications},
+    pending_purchases::PendingPurchases,
     prices::{PriceValue, PriceValueTrait},
-    steam_analyzer::analyze_steam_sell_history,
+    pricing::ItemCategory,
+    risk::RiskManager,
+    skinport::{SkinportEngine, SkinportEngineDecision, SkinportFeedResponse},
+    stats::{Stats, StatsCounter},
+    steam_analyzer::{analyze_steam_sell_history, AnalysisResult, Trend},
+    steam_fetcher::get_listings_url,
+    steam_orders::{parse_order_histogram, SteamOrderBook},
     storages::{
         CsfloatEngine, CsfloatEngineListingDecision, CsfloatEngineTrait, SteamEngine,
         SteamEngineTrait,
     },
-    types::ListingId,
+    strategies::{
+        build_profitable_listing_event, estimate_steam_price, evaluate_strategies, StrategyContext,
+    },
+    telegram_commands::ListingAction,
+    types::{ListingId, MarketName},
+    watchlist::Watchlist,
 };
 
 lazy_static! {
@@ -37,107 +63,284 @@ lazy_static! {
 fn extract_market_hash_name(input: &str) -> Option<String> {
     if let Some(captures) = MARKET_HASH_NAME_REGEX.captures(input) {
         if let Some(market_hash_name) = captures.get(1) {
-            return Some(market_hash_name.as_str().to_string());
+            return Some(canonicalize(market_hash_name.as_str()));
         }
     }
 
     None
 }
 
+// Parsing is the slow part, the steam pipeline does it before locking the engine
+pub fn parse_steam_response(
+    event: &SteamResponseEvent,
+    rates: &ExchangeRates,
+    config: &AppConfig,
+) -> Option<(MarketName, AnalysisResult)> {
+    let Some(market_name) = extract_market_hash_name(&event.response) else {
+        warn!("Failed to extract market_hash_name for {}", event.response);
+        return None;
+    };
+    let wallet_currency = config.currency.steam_wallet_currency;
+    let Some(usd_rate) = rates.get_usd_rate(wallet_currency) else {
+        warn!(
+            "No {} exchange rate yet, Steam response of {} is skipped",
+            wallet_currency, market_name
+        );
+        return None;
+    };
+    let mut result = analyze_steam_sell_history(
+        &event.response,
+        event.timestamp,
+        usd_rate,
+        &config.steam_analyzer,
+    )?;
+    if let Some(sell_listings) = event.sell_listings {
+        result.set_sell_listings(sell_listings);
+    }
+    Some((market_name.into(), result))
+}
+
+pub fn parse_steam_orders_response(event: &SteamOrdersResponseEvent) -> Option<SteamOrderBook> {
+    let order_book = parse_order_histogram(&event.response, event.timestamp);
+    if order_book.is_none() {
+        warn!(
+            "Failed to parse order histogram for {}: {}",
+            event.market_name, event.response
+        );
+    }
+    order_book
+}
+
+// Responses are parsed in parallel, so an older one may be parsed last
+// Stored listings of the market name are evaluated again with its new analysis,
+// otherwise they'd wait for their own change
+fn reevaluate_listings(csfloat_engine: &CsfloatEngine, market_name: &MarketName) -> Vec<Event> {
+    let listing_ids = csfloat_engine.get_listing_ids_by_name(market_name);
+    if listing_ids.is_empty() {
+        return vec![];
+    }
+    vec![Event::Primary(PrimEvent::UpdatedCsfloatListings(
+        UpdatedCsfloatListingsEvent { listing_ids },
+    ))]
+}
+
+pub async fn process_steam_analysis_ready(
+    steam_engine: &mut SteamEngine,
+    csfloat_engine: &CsfloatEngine,
+    event: &SteamAnalysisReadyEvent,
+) -> Vec<Event> {
+    let analyzed_at = steam_engine
+        .hm
+        .get(&event.market_name)
+        .and_then(|x| x.analyzed_at);
+    if analyzed_at > event.result.analyzed_at {
+        return vec![];
+    }
+    steam_engine.update(&event.market_name, event.result.clone());
+
+    reevaluate_listings(csfloat_engine, &event.market_name)
+}
+
 pub async fn process_steam_response(
     steam_engine: &mut SteamEngine,
+    csfloat_engine: &CsfloatEngine,
     event: &SteamResponseEvent,
+    rates: &ExchangeRates,
+    config: &AppConfig,
 ) -> Vec<Event> {
-    let market_name = extract_market_hash_name(&event.response);
-    if market_name.is_none() {
-        warn!("Failed to extract market_hash_name for {}", event.response);
+    let Some((market_name, result)) = parse_steam_response(event, rates, config) else {
         return vec![];
-    }
-    let market_name = market_name.unwrap();
+    };
+    steam_engine.update(&market_name, result);
+
+    reevaluate_listings(csfloat_engine, &market_name)
+}
 
-    if let Some(res_uw) = analyze_steam_sell_history(&event.response, event.timestamp) {
-        steam_engine.update(&market_name, res_uw);
+// Stale analyses are not acted on, the steam fetcher is asked to refresh them instead
+fn is_stale_analysis(
+    steam_engine: &SteamEngine,
+    market_name: &MarketName,
+    now: DateTime<Utc>,
+    config: &AppConfig,
+) -> bool {
+    match config.steam_analyzer.max_age() {
+        Some(max_age) => steam_engine.is_stale(market_name, now, max_age),
+        None => false,
     }
+}
 
-    vec![]
+// Once per market name, names already waiting for the steam fetcher are skipped
+fn request_steam_analysis(
+    requested: &mut Vec<MarketName>,
+    steam_engine: &SteamEngine,
+    market_name: &MarketName,
+) {
+    if !requested.contains(market_name) && !steam_engine.refresh_requests.contains(market_name) {
+        requested.push(market_name.clone());
+    }
 }
 
 pub async fn process_updated_csfloat_listing(
     steam_engine: &mut SteamEngine,
     csfloat_engine: &mut CsfloatEngine,
+    csfloat_scheduler: &mut CsfloatScheduler,
+    watchlist: &Watchlist,
+    listing_filters: &mut ListingFilters,
     event: &UpdatedCsfloatListingsEvent,
+    config: &AppConfig,
 ) -> Vec<Event> {
     let mut result: Vec<Event> = vec![];
+    let mut requested: Vec<MarketName> = vec![];
+    let now = csfloat_engine.now();
+    let ctx = StrategyContext {
+        steam_engine,
+        sticker_prices: &csfloat_engine.sticker_prices,
+        aggregates: &csfloat_engine.aggregates,
+        now,
+        config,
+    };
 
     for listing_id in event.listing_ids.iter() {
-        let csfloat_item = csfloat_engine.hm.get(listing_id);
-        if csfloat_item.is_none() {
+        let Some(csfloat_item) = csfloat_engine.hm.get(listing_id) else {
             continue;
-        }
-        let csfloat_item = csfloat_item.unwrap();
+        };
         let market_name = &csfloat_item.item.market_hash_name;
-        let steam_analysis = steam_engine.hm.get(market_name);
-        if steam_analysis.is_none() {
-            continue;
+        let _span = info_span!(
+            "listing",
+            listing_id = &**listing_id,
+            market_name = &**market_name
+        )
+        .entered();
+        let is_stale = is_stale_analysis(steam_engine, market_name, now, config);
+        if is_stale || !steam_engine.hm.contains_key(market_name) {
+            request_steam_analysis(&mut requested, steam_engine, market_name);
         }
-        let steam_analysis = steam_analysis.unwrap();
 
-        let steam_price = steam_analysis.get_price_by_percentile(DESIRED_PERCENTILE);
-        if steam_price.is_none() {
-            continue;
+        if !is_stale {
+            let auction_details = match csfloat_item.is_auction() {
+                true => csfloat_item.auction_details.as_ref(),
+                false => None,
+            };
+            let csfloat_price = match auction_details {
+                Some(auction_details) => auction_details.get_next_bid(),
+                None => csfloat_item.get_price_value(),
+            };
+            let applied_value =
+                estimate_applied_value(&csfloat_item.item, &csfloat_engine.sticker_prices, config);
+            let steam_price = estimate_steam_price(
+                steam_engine,
+                market_name,
+                csfloat_item.item.float_value,
+                csfloat_item.get_predicted_price(),
+                applied_value,
+                csfloat_item.item.get_days_until_tradable(now),
+                config,
+            )
+            .map(|(steam_price, _)| steam_price);
+            let steam_no_fee = steam_price.map(SteamFee::subtract_fee);
+            csfloat_scheduler.set_priority(
+                listing_id,
+                get_refresh_tier(market_name, csfloat_price, steam_no_fee, config),
+            );
+
+            if let (Some(auction_details), Some(steam_price)) = (auction_details, steam_price) {
+                let steam_no_fee = SteamFee::subtract_fee(steam_price);
+                let max_bid = get_max_auction_bid(steam_no_fee, config);
+                if csfloat_price <= max_bid && auction_details.expires_at > now {
+                    result.push(Event::Secondary(SecEvent::AuctionOpportunity(
+                        AuctionOpportunityEvent {
+                            market_name: market_name.clone(),
+                            listing_id: listing_id.clone(),
+                            next_bid: csfloat_price,
+                            max_bid,
+                            steam_price,
+                            steam_no_fee,
+                            expires_at: auction_details.expires_at,
+                        },
+                    )));
+                }
+            }
         }
-        let steam_price = steam_price.unwrap();
-        let csfloat_price = csfloat_item.get_price_value();
-        let steam_no_fee = SteamFee::subtract_fee(steam_price);
-        let sold_per_week = steam_analysis.sold_per_week.unwrap_or(0) as u64;
-        let is_stable = steam_analysis.is_stable.unwrap_or(false);
-        let profit_pct = ((steam_no_fee as f64 / csfloat_price as f64) - 1.0) * 100.0;
-        if csfloat_price < steam_no_fee {
+
+        result.extend(
+            evaluate_strategies(csfloat_item, &ctx, is_stale)
+                .into_iter()
+                .map(|signal| Event::Secondary(SecEvent::ProfitableListing(signal.event))),
+        );
+
+        if let Some(watch_rule) = watchlist.find_matching(csfloat_item) {
+            let csfloat_price = csfloat_item.get_price_value();
+            // there's no profit to show for an unpriced item
+            let Some((steam_price, price_source)) = estimate_steam_price(
+                steam_engine,
+                market_name,
+                csfloat_item.item.float_value,
+                csfloat_item.get_predicted_price(),
+                AppliedValue::default(),
+                csfloat_item.item.get_days_until_tradable(now),
+                config,
+            ) else {
+                continue;
+            };
+            let steam_no_fee = SteamFee::subtract_fee(steam_price);
+            let cost = get_buy_cost(Venue::Csfloat, csfloat_price, config);
+            let profit_pct = match steam_no_fee > cost {
+                true => ((steam_no_fee as f64 / cost as f64) - 1.0) * 100.0,
+                false => 0.0,
+            };
+
             result.push(Event::Secondary(SecEvent::ProfitableListing(
                 ProfitableListingEvent {
-                    kind: ProfitableListingKind::Profitable,
+                    kind: ProfitableListingKind::Watchlist(watch_rule.clone()),
+                    venue: Venue::Csfloat,
                     market_name: market_name.clone(),
                     listing_id: listing_id.clone(),
                     csfloat_price,
                     steam_price,
                     steam_no_fee,
-                    sold_per_week,
-                    is_stable,
+                    price_source,
+                    predicted_price: csfloat_item.get_predicted_price(),
+                    applied_value: AppliedValue::default(),
+                    sold_per_week: steam_engine
+                        .hm
+                        .get(market_name)
+                        .and_then(|x| x.sold_per_week)
+                        .unwrap_or(0) as u64,
+                    is_stable: false,
+                    trend: Trend::Flat,
+                    expected_days_to_sell: None,
                     profit_pct,
                     float: csfloat_item.item.float_value,
+                    trade_hold_days: csfloat_item.item.get_days_until_tradable(now),
+                    seller_id: csfloat_item.get_seller_id(),
+                    strategy: None,
+                    floor_undercut_pct: csfloat_engine.aggregates.get_floor_undercut_pct(
+                        market_name,
+                        listing_id,
+                        csfloat_price,
+                    ),
+                    batch: vec![],
                 },
             )));
         }
     }
 
-    for listing_id in event.listing_ids.iter() {
-        let csfloat_item = csfloat_engine.hm.get(listing_id);
-        if csfloat_item.is_none() {
-            continue;
+    // muted items are still priced above, so their refresh tiers stay up to date
+    result.retain(|x| match x {
+        Event::Secondary(SecEvent::ProfitableListing(e)) => {
+            !listing_filters.is_muted(&e.market_name, &e.listing_id, now)
         }
-
-        let csfloat_item = csfloat_item.unwrap();
-        if is_good_glock_phase_listing(csfloat_item) {
-            let csfloat_price = csfloat_item.get_price_value();
-            const EMPTY_PRICE: PriceValue = 0 as PriceValue;
-
-            result.push(Event::Secondary(SecEvent::ProfitableListing(
-                ProfitableListingEvent {
-                    kind: ProfitableListingKind::GoodPhase,
-                    market_name: csfloat_item.item.market_hash_name.clone(),
-                    listing_id: listing_id.clone(),
-                    csfloat_price,
-                    steam_price: EMPTY_PRICE,
-                    steam_no_fee: EMPTY_PRICE,
-                    sold_per_week: 0,
-                    is_stable: false,
-                    profit_pct: 0.0,
-                    float: csfloat_item.item.float_value,
-                },
-            )));
+        Event::Secondary(SecEvent::AuctionOpportunity(e)) => {
+            !listing_filters.is_muted(&e.market_name, &e.listing_id, now)
         }
-    }
+        _ => true,
+    });
 
+    result.extend(requested.into_iter().map(|market_name| {
+        Event::Primary(PrimEvent::SteamAnalysisRequested(
+            SteamAnalysisRequestedEvent { market_name },
+        ))
+    }));
     result
 }
 
@@ -145,6 +348,7 @@ pub async fn process_csfloat_one_listing_response(
     csfloat_engine: &mut CsfloatEngine,
     csfloat_scheduler: &mut CsfloatScheduler,
     event: &CsfloatOneListingResponseEvent,
+    config: &AppConfig,
 ) -> Vec<Event> {
     if event.timestamp.elapsed() > Duration::from_micros(100) {
         warn!(
@@ -158,6 +362,7 @@ pub async fn process_csfloat_one_listing_response(
             vec![parsed_item],
             csfloat_engine,
             csfloat_scheduler,
+            config,
         );
     } else {
         // Handle the case when an item is malformed (e.g., print an error message)
@@ -171,6 +376,7 @@ pub async fn process_csfloat_listings_response(
     csfloat_engine: &mut CsfloatEngine,
     csfloat_scheduler: &mut CsfloatScheduler,
     event: &CsfloatResponseEvent,
+    config: &AppConfig,
 ) -> Vec<Event> {
     if event.timestamp.elapsed() > Duration::from_micros(100) {
         warn!(
@@ -180,7 +386,12 @@ pub async fn process_csfloat_listings_response(
     }
 
     if let Ok(parsed_items) = serde_json::from_str::<Vec<CsfloatListingStruct>>(&event.response) {
-        return process_parsed_csfloat_listings(parsed_items, csfloat_engine, csfloat_scheduler);
+        return process_parsed_csfloat_listings(
+            parsed_items,
+            csfloat_engine,
+            csfloat_scheduler,
+            config,
+        );
     } else {
         // Handle the case when an item is malformed (e.g., print an error message)
         warn!("Error parsing item");
@@ -193,12 +404,38 @@ fn process_parsed_csfloat_listings(
     parsed_items: Vec<CsfloatListingStruct>,
     csfloat_engine: &mut CsfloatEngine,
     csfloat_scheduler: &mut CsfloatScheduler,
+    config: &AppConfig,
 ) -> Vec<Event> {
+    let mut new_events: Vec<Event> = parsed_items
+        .iter()
+        .filter(|listing| csfloat_engine.paper_checks.remove(&listing.id).is_some())
+        .map(|listing| {
+            Event::Secondary(SecEvent::PaperPurchaseChecked(PaperPurchaseCheckedEvent {
+                listing_id: listing.id.clone(),
+                is_available: listing.state == CsfloatListingState::Listed,
+            }))
+        })
+        .collect();
+
+    let shard = csfloat_engine.shard;
+    let mut rejected = vec![];
     let listing_ids: Vec<ListingId> = parsed_items
         .iter()
-        .filter(|listing| prefilter_listing(listing))
+        .filter(|listing| shard.contains(&listing.id))
+        .filter(|listing| match prefilter_listing(listing, config) {
+            Ok(()) => true,
+            Err(name) => {
+                rejected.push(name);
+                false
+            }
+        })
         .filter_map(|listing| match csfloat_engine.update_listing(listing) {
             CsfloatEngineListingDecision::New | CsfloatEngineListingDecision::Updated => {
+                trace!(
+                    listing_id = &*listing.id,
+                    market_name = &*listing.item.market_hash_name,
+                    "Listing is new or updated"
+                );
                 csfloat_scheduler.upsert_listing(&listing.id);
                 assert_eq!(
                     csfloat_engine.get_size(),
@@ -209,6 +446,11 @@ fn process_parsed_csfloat_listings(
             }
             CsfloatEngineListingDecision::NotChanged => None,
             CsfloatEngineListingDecision::Removed => {
+                trace!(
+                    listing_id = &*listing.id,
+                    market_name = &*listing.item.market_hash_name,
+                    "Listing is removed"
+                );
                 csfloat_scheduler.remove_listing(&listing.id);
                 assert_eq!(
                     csfloat_engine.get_size(),
@@ -219,68 +461,872 @@ fn process_parsed_csfloat_listings(
             }
         })
         .collect();
+    for name in rejected {
+        csfloat_engine.prefilter_rejections.add(name);
+    }
 
-    match listing_ids.is_empty() {
-        true => vec![],
-        false => vec![Event::Primary(PrimEvent::UpdatedCsfloatListings(
+    if !listing_ids.is_empty() {
+        new_events.push(Event::Primary(PrimEvent::UpdatedCsfloatListings(
             UpdatedCsfloatListingsEvent { listing_ids },
-        ))],
+        )));
+    }
+    new_events
+}
+
+pub async fn process_steam_analysis_requested(
+    steam_engine: &mut SteamEngine,
+    event: &SteamAnalysisRequestedEvent,
+) -> Vec<Event> {
+    steam_engine.request_refresh(&event.market_name);
+    vec![]
+}
+
+pub async fn process_paper_purchase(
+    csfloat_engine: &mut CsfloatEngine,
+    csfloat_scheduler: &mut CsfloatScheduler,
+    event: &PaperPurchaseEvent,
+) -> Vec<Event> {
+    csfloat_engine.add_paper_check(&event.listing_id);
+    // refresh it as soon as possible
+    csfloat_scheduler.set_priority(&event.listing_id, PriorityTier::Watched);
+    vec![]
+}
+
+pub async fn process_paper_purchase_checked(
+    notifications: &Notifications,
+    db: &Pool<Postgres>,
+    event: &PaperPurchaseCheckedEvent,
+    config: &AppConfig,
+) -> Vec<Event> {
+    let listing_id = event.listing_id.clone();
+    let is_available = event.is_available;
+    let db_cloned = db.clone();
+    tokio::spawn(async move {
+        if let Err(err) = set_paper_availability(&db_cloned, &listing_id, is_available).await {
+            error!(
+                "Failed to record availability of paper purchase {}: {:?}",
+                listing_id, err
+            );
+        }
+    });
+
+    let text = format!(
+        "Paper purchase {} would have succeeded: {}",
+        event.listing_id, event.is_available
+    );
+    notifications.notify(NotificationKind::PaperTrading, text, config);
+
+    vec![]
+}
+
+pub async fn process_skinport_listings_response(
+    skinport_engine: &mut SkinportEngine,
+    steam_engine: &mut SteamEngine,
+    event: &SkinportResponseEvent,
+    rates: &ExchangeRates,
+    config: &AppConfig,
+) -> Vec<Event> {
+    let parsed = match serde_json::from_str::<SkinportFeedResponse>(&event.response) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            warn!("Error parsing skinport response: {}", err);
+            return vec![];
+        }
+    };
+
+    parsed
+        .sales
+        .iter()
+        // sales in a currency without a known rate can't be compared
+        .filter_map(|sale| Some((sale, sale.get_money().to_usd(rates)?)))
+        .filter(|(sale, price)| {
+            let category = ItemCategory::from_market_name(&sale.market_hash_name);
+            is_price_in_band(*price, category, config)
+        })
+        .filter(|(sale, _)| {
+            matches!(
+                skinport_engine.update_sale(&parsed.event_type, sale),
+                SkinportEngineDecision::New | SkinportEngineDecision::Updated
+            )
+        })
+        .filter_map(|(sale, price)| {
+            build_profitable_listing_event(
+                steam_engine,
+                Venue::Skinport,
+                &sale.market_hash_name,
+                &sale.sale_id.to_string().into(),
+                price,
+                sale.wear,
+                None,
+                AppliedValue::default(),
+                0,
+                None,
+                None,
+                config,
+            )
+            .map(|x| Event::Secondary(SecEvent::ProfitableListing(x)))
+        })
+        .collect()
+}
+
+pub async fn process_dmarket_listings_response(
+    dmarket_engine: &mut DmarketEngine,
+    steam_engine: &mut SteamEngine,
+    event: &DmarketResponseEvent,
+    config: &AppConfig,
+) -> Vec<Event> {
+    let parsed = match serde_json::from_str::<DmarketItemsResponse>(&event.response) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            warn!("Error parsing dmarket response: {}", err);
+            return vec![];
+        }
+    };
+
+    let now = Utc::now();
+    let evicted = dmarket_engine.evict_older_than(now - config.dmarket.listing_ttl());
+    if evicted > 0 {
+        trace!("Forgot {} dmarket offers", evicted);
+    }
+
+    parsed
+        .objects
+        .iter()
+        .filter(|item| !item.extra.offer_id.is_empty())
+        .filter_map(|item| Some((item, item.get_price()?)))
+        .filter(|(item, price)| {
+            is_price_in_band(*price, ItemCategory::from_market_name(&item.title), config)
+        })
+        .filter(|(item, _)| {
+            matches!(
+                dmarket_engine.update_item(item, now),
+                DmarketEngineDecision::New | DmarketEngineDecision::Updated
+            )
+        })
+        .filter_map(|(item, price)| {
+            build_profitable_listing_event(
+                steam_engine,
+                Venue::Dmarket,
+                &item.title,
+                &item.extra.offer_id.as_str().into(),
+                price,
+                item.get_float(),
+                None,
+                AppliedValue::default(),
+                0,
+                None,
+                None,
+                config,
+            )
+            .map(|x| Event::Secondary(SecEvent::ProfitableListing(x)))
+        })
+        .collect()
+}
+
+pub async fn process_alert(
+    notifications: &Notifications,
+    event: &AlertEvent,
+    config: &AppConfig,
+) -> Vec<Event> {
+    notifications.notify(NotificationKind::Alert, event.text.clone(), config);
+
+    vec![]
+}
+
+pub async fn process_auction_opportunity(
+    notifications: &Notifications,
+    db: &Pool<Postgres>,
+    csfloat_autobuy: &mut CsfloatAutobuy,
+    risk_manager: &Mutex<RiskManager>,
+    event: &AuctionOpportunityEvent,
+    config: &AppConfig,
+) -> Vec<Event> {
+    if config.auction.notify {
+        let text = format!(
+            "Auction {} : next bid ${} | max bid ${} | steam minus fee ${} | steam ${} \n id: {} \n expires at: {}",
+            event.market_name,
+            event.next_bid.to_usd(),
+            event.max_bid.to_usd(),
+            event.steam_no_fee.to_usd(),
+            event.steam_price.to_usd(),
+            event.listing_id,
+            event.expires_at,
+        );
+        notifications.notify(NotificationKind::Auction, text, config);
+    }
+
+    let bid = event.max_bid.min(config.auction.max_bid);
+    let is_allowed = config.auction.auto_bid
+        && config.autobuy.enabled
+        && !config.autobuy.paper_trading
+        && event.next_bid <= bid
+        && !csfloat_autobuy.has_bid(&event.listing_id)
+        && csfloat_autobuy.get_breaker_open_until().is_none()
+        && risk_manager
+            .lock()
+            .await
+            .check(&event.market_name, bid, &config.autobuy, Utc::now())
+            .is_ok();
+    if !is_allowed {
+        return vec![];
     }
+
+    let result = csfloat_autobuy
+        .place_bid(&event.listing_id, bid, event.expires_at)
+        .await;
+    if result == Err(CsfloatBuyError::NotLeader) {
+        warn!(
+            "Bid on listing {} is skipped, not the leader",
+            event.listing_id
+        );
+        return vec![];
+    }
+    let outcome = match &result {
+        Ok(outcome) => outcome.clone(),
+        Err(err) => {
+            warn!(
+                "Failed to bid on listing_id {} for ${} because {:?}",
+                event.listing_id,
+                bid.to_usd(),
+                err
+            );
+            BuyOutcome::failed(err)
+        }
+    };
+    // a bid of unknown result may be placed, so it counts to the limits too
+    if outcome.is_success || matches!(result, Err(CsfloatBuyError::Unknown(_))) {
+        risk_manager
+            .lock()
+            .await
+            .register_purchase(&event.market_name, bid, Utc::now());
+    }
+    let record = PurchaseRecord::from_bid(event, bid, &outcome, Utc::now());
+    let db_cloned = db.clone();
+    tokio::spawn(async move {
+        if let Err(err) = record_purchase(&db_cloned, &record).await {
+            error!("Failed to record bid {:?}: {:?}", record, err);
+        }
+    });
+
+    let text = match result {
+        Ok(_) => format!(
+            "Placed a bid on {} up to ${}",
+            event.listing_id,
+            bid.to_usd()
+        ),
+        Err(err) => format!(
+            "Failed to bid on {} up to ${}: {}",
+            event.listing_id,
+            bid.to_usd(),
+            err
+        ),
+    };
+    notifications.notify(NotificationKind::Autobuy, text, config);
+    vec![]
 }
 
+// MarkdownV2 message with market links and action buttons for Telegram,
+// other channels get `text` as is
+pub fn build_listing_notification(
+    event: &ProfitableListingEvent,
+    kind: &str,
+    text: String,
+) -> Notification {
+    let mut lines = vec![
+        format!(
+            "*{}* {}",
+            escape(&format!("{:.2}%", event.profit_pct)),
+            escape(&event.market_name)
+        ),
+        escape(&format!(
+            "${} | steam minus fee ${} | steam ${}",
+            event.csfloat_price.to_usd(),
+            event.steam_no_fee.to_usd(),
+            event.steam_price.to_usd()
+        )),
+    ];
+    if event.applied_value.total() > 0 {
+        lines.push(escape(&format!(
+            "stickers ${}, charms ${}, patches ${}",
+            event.applied_value.stickers.to_usd(),
+            event.applied_value.keychains.to_usd(),
+            event.applied_value.patches.to_usd()
+        )));
+    }
+    if let Some(float) = event.float {
+        lines.push(escape(&format!("float: {}", float)));
+    }
+    if event.trade_hold_days > 0 {
+        lines.push(escape(&format!(
+            "trade hold: {} days",
+            event.trade_hold_days
+        )));
+    }
+    if let Some(floor_undercut_pct) = event.floor_undercut_pct {
+        lines.push(escape(&format!(
+            "below CSFloat floor: {:.2}%",
+            floor_undercut_pct
+        )));
+    }
+    lines.push(escape(&format!(
+        "sold per week: {} | stable: {} | trend: {:?} | price source: {:?}",
+        event.sold_per_week, event.is_stable, event.trend, event.price_source
+    )));
+    if let Some(days) = event.expected_days_to_sell {
+        lines.push(escape(&format!("expected days to sell: {:.1}", days)));
+    }
+    let mut kind_line = format!("kind: {} | venue: {:?}", kind, event.venue);
+    if let Some(strategy) = event.strategy {
+        kind_line.push_str(&format!(" | strategy: {}", strategy));
+    }
+    lines.push(escape(&kind_line));
+
+    let steam_link = link(get_listings_url(&event.market_name).as_str(), "Steam");
+    let mut buttons = vec![];
+    match event.venue {
+        Venue::Csfloat => {
+            let csfloat_url = format!("https://csfloat.com/item/{}", event.listing_id);
+            lines.push(format!(
+                "{} \\| {}",
+                link(&csfloat_url, "CSFloat"),
+                steam_link
+            ));
+            buttons.push(("Buy now", ListingAction::Buy(event.listing_id.clone())));
+        }
+        Venue::Skinport => lines.push(steam_link),
+        Venue::Dmarket => {
+            let dmarket_url = format!(
+                "https://dmarket.com/ingame-items/item-list/csgo-skins?userOfferId={}",
+                event.listing_id
+            );
+            lines.push(format!(
+                "{} \\| {}",
+                link(&dmarket_url, "DMarket"),
+                steam_link
+            ));
+        }
+    }
+    buttons.push((
+        "Snooze item",
+        ListingAction::Snooze(event.listing_id.clone()),
+    ));
+    if event.seller_id.is_some() {
+        buttons.push((
+            "Blacklist seller",
+            ListingAction::BlacklistSeller(event.listing_id.clone()),
+        ));
+    }
+
+    Notification {
+        text,
+        markdown: Some(lines.join("\n")),
+        buttons: buttons
+            .into_iter()
+            .map(|(label, action)| (label.to_string(), action.to_callback_data()))
+            .collect(),
+        deal: Some(DealSummary {
+            listing_id: event.listing_id.clone(),
+            market_name: event.market_name.clone(),
+            price: event.csfloat_price,
+            profit_pct: event.profit_pct,
+        }),
+    }
+}
+
+// Human readable kind of the deal, shown in notifications and the dashboard
+pub fn get_kind_description(kind: &ProfitableListingKind) -> String {
+    match kind {
+        ProfitableListingKind::Profitable => "Profitable".to_string(),
+        ProfitableListingKind::Phase(phase_price) => format!(
+            "{} (max buy ${}, target ${:?})",
+            phase_price.phase,
+            phase_price.max_buy_price.to_usd(),
+            phase_price.target_sell_price.map(|x| x.to_usd()),
+        ),
+        ProfitableListingKind::RarePattern(pattern_tier) => format!(
+            "{:?} {} (max premium {:.0}%)",
+            pattern_tier.kind, pattern_tier.name, pattern_tier.max_premium_pct,
+        ),
+        ProfitableListingKind::Watchlist(watch_rule) => {
+            format!(
+                "watchlist rule #{} {}",
+                watch_rule.id, watch_rule.market_name
+            )
+        }
+        ProfitableListingKind::LowFloat => "Low float".to_string(),
+        ProfitableListingKind::Sticker => "Stickers".to_string(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn process_profitable_listing(
-    bot: &Bot,
+    notifications: &Notifications,
+    db: &Pool<Postgres>,
+    stats: &Mutex<Stats>,
     csfloat_autobuy: &mut CsfloatAutobuy,
+    risk_manager: &Mutex<RiskManager>,
+    listing_filters: &Mutex<ListingFilters>,
+    pending_purchases: &mut PendingPurchases,
+    market_floors: &Mutex<MarketFloors>,
     event: &ProfitableListingEvent,
+    config: &AppConfig,
 ) -> Vec<Event> {
-    let text = format!(
-        "Found item {:.2}% {} : ${} | steam minus fee ${} | steam ${} \n stable: {} \n sold per week: {} \n id: {} \n float: {:?} \n kind: {:?}",
+    // the filters and floors are shared with the pipelines, so they aren't locked during a purchase
+    // snoozed items and blacklisted sellers are neither notified nor bought
+    if listing_filters.lock().await.is_filtered(event, Utc::now()) {
+        return vec![];
+    }
+    if let Some(strategy) = event.strategy {
+        stats
+            .lock()
+            .await
+            .increment(StatsCounter::StrategySignals(strategy));
+    }
+
+    let kind = get_kind_description(&event.kind);
+    let floors = market_floors.lock().await.compare(
+        &event.market_name,
+        event.csfloat_price,
+        Utc::now(),
+        config.market_floors.max_age(),
+    );
+    let mut text = format!(
+        "Found item {:.2}% {} : ${} | steam minus fee ${} | steam ${} (stickers ${}, charms ${}, patches ${}) \n trade hold: {} days \n price source: {:?} \n stable: {} \n trend: {:?} \n sold per week: {} \n expected days to sell: {} \n id: {} \n float: {:?} \n kind: {} \n venue: {:?} \n strategy: {}",
         event.profit_pct,
         event.market_name,
         event.csfloat_price.to_usd(),
         event.steam_no_fee.to_usd(),
         event.steam_price.to_usd(),
+        event.applied_value.stickers.to_usd(),
+        event.applied_value.keychains.to_usd(),
+        event.applied_value.patches.to_usd(),
+        event.trade_hold_days,
+        event.price_source,
         event.is_stable,
+        event.trend,
         event.sold_per_week,
+        event
+            .expected_days_to_sell
+            .map_or("unknown".to_string(), |x| format!("{:.1}", x)),
         event.listing_id,
         event.float,
-        event.kind,
+        kind,
+        event.venue,
+        event
+            .strategy
+            .map_or("none".to_string(), |x| x.to_string()),
     );
+    if !event.batch.is_empty() {
+        text.push_str(&format!(
+            " \n batch: {} listings for ${}",
+            event.batch.len() + 1,
+            event.get_total_price().to_usd()
+        ));
+    }
+    let floors_line = floors.describe();
+    if let Some(line) = &floors_line {
+        text.push_str(&format!(" \n {}", line));
+    }
 
-    if is_need_notify_via_telegram(event) {
-        let bot_cloned = bot.clone();
-        tokio::spawn(async move {
-            let _ = bot_cloned
-                .send_message(Recipient::Id(MY_TG_ID), text.clone())
-                .await;
-        });
+    // repeated deals are dropped by `Notifications::notify`
+    if is_need_notify_via_telegram(event, config) {
+        let notification_kind = match event.kind {
+            ProfitableListingKind::Profitable => NotificationKind::Profitable,
+            ProfitableListingKind::Phase(_) => NotificationKind::Phase,
+            ProfitableListingKind::RarePattern(_) => NotificationKind::RarePattern,
+            ProfitableListingKind::Watchlist(_) => NotificationKind::Watchlist,
+            ProfitableListingKind::LowFloat => NotificationKind::LowFloat,
+            ProfitableListingKind::Sticker => NotificationKind::Sticker,
+        };
+        listing_filters.lock().await.remember(event);
+        let mut notification = build_listing_notification(event, &kind, text);
+        if let (Some(markdown), Some(line)) = (notification.markdown.as_mut(), &floors_line) {
+            markdown.push_str(&format!("\n{}", escape(line)));
+        }
+        if is_need_to_confirm_buy(event, config) {
+            pending_purchases.add(event, Utc::now() + config.autobuy.confirm_timeout());
+            let action = ListingAction::ConfirmBuy(event.listing_id.clone());
+            notification
+                .buttons
+                .insert(0, ("Confirm buy".to_string(), action.to_callback_data()));
+        }
+        notifications.notify(notification_kind, notification, config);
     }
 
-    if IS_AUTOBUY_ALLOWED && is_need_to_autobuy(event) {
-        let listing_id = event.listing_id.to_string();
-        let price = event.csfloat_price as PriceValue;
-        let result = match csfloat_autobuy.buy_listing(&listing_id, price).await {
-            Ok(is_success) => is_success,
+    let balance = match event.venue {
+        Venue::Csfloat => csfloat_autobuy.get_cached_balance(),
+        Venue::Skinport | Venue::Dmarket => None,
+    };
+    let risk_manager_locked = risk_manager.lock().await;
+    let is_allowed = is_need_to_autobuy(event, config, &risk_manager_locked, balance, Utc::now());
+    drop(risk_manager_locked);
+    if !is_allowed {
+        return vec![];
+    }
+    if let Some(floor) = floors
+        .cheaper
+        .filter(|_| config.market_floors.block_above_floor)
+    {
+        info!(
+            "Autobuy of {} is skipped, {} floor ${} is lower",
+            event.listing_id,
+            floor.source,
+            floor.price.to_usd()
+        );
+        return vec![];
+    }
+    if let (false, Some(until)) = (
+        config.autobuy.paper_trading,
+        csfloat_autobuy.get_breaker_open_until(),
+    ) {
+        info!(
+            "Autobuy of {} is skipped, the circuit breaker is open until {}",
+            event.listing_id, until
+        );
+        return vec![];
+    }
+    buy_profitable_listing(
+        notifications,
+        db,
+        stats,
+        csfloat_autobuy,
+        risk_manager,
+        event,
+        config,
+    )
+    .await
+}
+
+// Buys the listing, or records it as a paper purchase, and reports the result
+async fn buy_profitable_listing(
+    notifications: &Notifications,
+    db: &Pool<Postgres>,
+    stats: &Mutex<Stats>,
+    csfloat_autobuy: &mut CsfloatAutobuy,
+    risk_manager: &Mutex<RiskManager>,
+    event: &ProfitableListingEvent,
+    config: &AppConfig,
+) -> Vec<Event> {
+    let mut new_events = vec![];
+    let listing_id = event.listing_id.clone();
+    // of the whole batch, see `strategies.commodity`
+    let purchases = event.get_purchases();
+    let costs = get_purchase_costs(event, config);
+    let price = event.get_total_price();
+    let is_paper = config.autobuy.paper_trading;
+    let is_csfloat = event.venue == Venue::Csfloat;
+    let result = match (is_paper, event.venue) {
+        (true, _) => Ok(BuyOutcome::paper()),
+        (false, Venue::Csfloat) => match csfloat_autobuy
+            .verify_similar_listings(
+                &event.market_name,
+                &listing_id,
+                event.csfloat_price,
+                &config.similar_listings,
+            )
+            .await
+        {
             Err(err) => {
-                warn!(
-                    "Failed to buy listing_id {} for ${} because {:?}",
-                    listing_id, price, err
-                );
-                false
+                warn!("Purchase of listing {} is aborted: {}", listing_id, err);
+                Err(CsfloatBuyError::Aborted(err))
             }
-        };
+            Ok(()) => {
+                let balance_before = csfloat_autobuy.get_cached_balance();
+                let mut result = csfloat_autobuy.buy_listings(&purchases).await;
+                if config.autobuy.retry_delay_ms > 0
+                    && matches!(&result, Err(err) if err.is_retryable())
+                {
+                    tokio::time::sleep(config.autobuy.retry_delay()).await;
+                    result = csfloat_autobuy.buy_listings(&purchases).await;
+                }
+                // a purchase which may have gone through is checked instead of retried
+                if let Err(err @ CsfloatBuyError::Unknown(_)) = result {
+                    warn!("Purchase of listing {} is unknown: {}", listing_id, err);
+                    tokio::time::sleep(config.autobuy.retry_delay()).await;
+                    result = csfloat_autobuy
+                        .reconcile_purchase(&purchases, balance_before, err)
+                        .await;
+                }
+                result
+            }
+        },
+        (false, Venue::Dmarket) => csfloat_autobuy.buy_dmarket_offer(&listing_id, price).await,
+        (false, Venue::Skinport) => Err(CsfloatBuyError::Request(
+            "Skinport sales can't be bought".to_string(),
+        )),
+    };
+    // the ledger is the new leader's now
+    if result == Err(CsfloatBuyError::NotLeader) {
+        warn!(
+            "Purchase of listing {} is skipped, not the leader",
+            listing_id
+        );
+        return new_events;
+    }
+    let outcome = match &result {
+        Ok(outcome) => outcome.clone(),
+        Err(err) => {
+            warn!(
+                "Failed to buy listing_id {} for ${} because {:?}",
+                listing_id,
+                price.to_usd(),
+                err
+            );
+            BuyOutcome::failed(err)
+        }
+    };
+    // a purchase of unknown result counts to the limits until the operator checks it
+    let is_pending = matches!(result, Err(CsfloatBuyError::Unknown(_)));
+    if outcome.is_success || is_pending {
+        let mut risk_manager_locked = risk_manager.lock().await;
+        for (_, cost) in costs.iter() {
+            risk_manager_locked.register_purchase(&event.market_name, *cost, Utc::now());
+        }
+    }
+    if outcome.is_success {
+        if let Some(strategy) = event.strategy {
+            stats
+                .lock()
+                .await
+                .increment(StatsCounter::StrategyBuys(strategy));
+        }
+    }
+    // the balance and paper purchases are of CSFloat only
+    if !is_paper && is_csfloat {
+        refresh_balance(notifications, csfloat_autobuy, config).await;
+    }
+    if is_paper && is_csfloat {
+        for (listing_id, _) in purchases.iter() {
+            new_events.push(Event::Primary(PrimEvent::PaperPurchase(
+                PaperPurchaseEvent {
+                    listing_id: listing_id.clone(),
+                },
+            )));
+        }
+    }
 
-        let bot_cloned = bot.clone();
-        tokio::spawn(async move {
+    let records: Vec<PurchaseRecord> = costs
+        .iter()
+        .map(|(listing_id, cost)| {
+            let cost = *cost;
+            let mut record = PurchaseRecord::new(event, cost, &outcome, is_paper, Utc::now());
+            if listing_id != &event.listing_id {
+                record.listing_id = listing_id.clone();
+                record.profit_pct =
+                    ((event.steam_no_fee as f64 / cost.max(1) as f64) - 1.0) * 100.0;
+                // only the found listing's float is known
+                record.float = None;
+            }
+            record
+        })
+        .collect();
+    let db_cloned = db.clone();
+    tokio::spawn(async move {
+        for record in records {
+            if let Err(err) = record_purchase(&db_cloned, &record).await {
+                error!("Failed to record purchase {:?}: {:?}", record, err);
+            }
+        }
+    });
+
+    let batch_text = match purchases.len() {
+        1 => String::new(),
+        count => format!(" and {} more", count - 1),
+    };
+    match result {
+        Ok(_) => {
+            let text = format!(
+                "Bought {}{} for ${}{}",
+                listing_id,
+                batch_text,
+                price.to_usd(),
+                if is_paper { " (paper trading)" } else { "" },
+            );
+            notifications.notify(NotificationKind::Autobuy, text, config);
+        }
+        // retrying won't help until the operator fixes it
+        Err(err) if err.is_fatal() => {
+            risk_manager.lock().await.set_kill_switch(true);
+            if let Err(err) = save_kill_switch(db, true).await {
+                error!("Failed to save the kill-switch: {:?}", err);
+            }
+            let text = format!(
+                "Autobuy is stopped, failed to buy {} for ${}: {}. Use /resume when it's fixed",
+                listing_id,
+                price.to_usd(),
+                err
+            );
+            notifications.notify(NotificationKind::Alert, text, config);
+        }
+        Err(err @ CsfloatBuyError::Unknown(_)) => {
+            let text = format!(
+                "Purchase of {}{} for ${} may have gone through, check it on {:?}: {}",
+                listing_id,
+                batch_text,
+                price.to_usd(),
+                event.venue,
+                err
+            );
+            notifications.notify(NotificationKind::Alert, text, config);
+        }
+        Err(
+            err @ (CsfloatBuyError::AlreadySold
+            | CsfloatBuyError::PriceChanged
+            | CsfloatBuyError::Aborted(_)),
+        ) => {
+            let text = format!("Missed {} for ${}: {}", listing_id, price.to_usd(), err);
+            notifications.notify(NotificationKind::Autobuy, text, config);
+        }
+        Err(err) => {
             let text = format!(
-                "Tried to buy {} for ${}: {:?}",
+                "Failed to buy {} for ${}: {}",
                 listing_id,
                 price.to_usd(),
-                result,
+                err
             );
-            let _ = bot_cloned.send_message(Recipient::Id(MY_TG_ID), text).await;
-        });
+            notifications.notify(NotificationKind::Autobuy, text, config);
+        }
     }
+    new_events
+}
 
-    vec![]
+// Alerts when the balance has just dropped below `autobuy.low_balance_alert`
+pub async fn refresh_balance(
+    notifications: &Notifications,
+    csfloat_autobuy: &mut CsfloatAutobuy,
+    config: &AppConfig,
+) {
+    match csfloat_autobuy
+        .refresh_balance(config.autobuy.low_balance_alert)
+        .await
+    {
+        Ok(true) => {
+            let balance = csfloat_autobuy.get_cached_balance().unwrap_or(0);
+            let text = format!("Csfloat balance is low: ${}", balance.to_usd());
+            notifications.notify(NotificationKind::Alert, text, config);
+        }
+        Ok(false) => {}
+        Err(err) => warn!("Failed to refresh csfloat balance: {:?}", err),
+    }
+}
+
+// Risk limits, the kill-switch and the balance still apply to confirmed purchases
+#[allow(clippy::too_many_arguments)]
+pub async fn process_purchase_confirmed(
+    notifications: &Notifications,
+    db: &Pool<Postgres>,
+    stats: &Mutex<Stats>,
+    csfloat_autobuy: &mut CsfloatAutobuy,
+    risk_manager: &Mutex<RiskManager>,
+    pending_purchases: &mut PendingPurchases,
+    event: &PurchaseConfirmedEvent,
+    config: &AppConfig,
+) -> Vec<Event> {
+    let Some(listing) = pending_purchases.take(&event.listing_id, Utc::now()) else {
+        let text = format!(
+            "Confirmation of {} is expired, the listing is not bought",
+            event.listing_id
+        );
+        notifications.notify(NotificationKind::Autobuy, text, config);
+        return vec![];
+    };
+    buy_requested_listing(
+        notifications,
+        db,
+        stats,
+        csfloat_autobuy,
+        risk_manager,
+        &listing,
+        config,
+    )
+    .await
+}
+
+pub async fn process_buy_requested(
+    notifications: &Notifications,
+    db: &Pool<Postgres>,
+    stats: &Mutex<Stats>,
+    csfloat_autobuy: &mut CsfloatAutobuy,
+    risk_manager: &Mutex<RiskManager>,
+    event: &BuyRequestedEvent,
+    config: &AppConfig,
+) -> Vec<Event> {
+    buy_requested_listing(
+        notifications,
+        db,
+        stats,
+        csfloat_autobuy,
+        risk_manager,
+        &event.listing,
+        config,
+    )
+    .await
+}
+
+// Purchase asked by the operator: the deal rules are skipped, the risk limits, the kill-switch,
+// the balance and the circuit breaker still apply
+async fn buy_requested_listing(
+    notifications: &Notifications,
+    db: &Pool<Postgres>,
+    stats: &Mutex<Stats>,
+    csfloat_autobuy: &mut CsfloatAutobuy,
+    risk_manager: &Mutex<RiskManager>,
+    listing: &ProfitableListingEvent,
+    config: &AppConfig,
+) -> Vec<Event> {
+    let now = Utc::now();
+    let prices: Vec<PriceValue> = get_purchase_costs(listing, config)
+        .into_iter()
+        .map(|(_, cost)| cost)
+        .collect();
+    if let Err(rejection) = risk_manager.lock().await.check_batch(
+        &listing.market_name,
+        &prices,
+        get_max_positions(listing, config),
+        &config.autobuy,
+        now,
+    ) {
+        let text = format!(
+            "Purchase of {} is rejected: {:?}",
+            listing.listing_id, rejection
+        );
+        notifications.notify(NotificationKind::Autobuy, text, config);
+        return vec![];
+    }
+    let balance = match listing.venue {
+        Venue::Csfloat => csfloat_autobuy.get_cached_balance(),
+        Venue::Skinport | Venue::Dmarket => None,
+    };
+    if let Some(balance) = balance {
+        if !config.autobuy.paper_trading && prices.iter().sum::<PriceValue>() > balance {
+            let text = format!(
+                "Purchase of {} is rejected: balance ${} is too low",
+                listing.listing_id,
+                balance.to_usd()
+            );
+            notifications.notify(NotificationKind::Autobuy, text, config);
+            return vec![];
+        }
+    }
+    if let (false, Some(until)) = (
+        config.autobuy.paper_trading,
+        csfloat_autobuy.get_breaker_open_until(),
+    ) {
+        let text = format!(
+            "Purchase of {} is rejected: autobuy is held until {}, see /reset_autobuy",
+            listing.listing_id, until
+        );
+        notifications.notify(NotificationKind::Autobuy, text, config);
+        return vec![];
+    }
+    buy_profitable_listing(
+        notifications,
+        db,
+        stats,
+        csfloat_autobuy,
+        risk_manager,
+        listing,
+        config,
+    )
+    .await
 }
@@ -1,28 +1,69 @@
 use std::time::Instant;
 
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 
 use crate::{
+    patterns::PatternTier,
+    phases::PhasePrice,
     prices::PriceValue,
+    steam_analyzer::{AnalysisResult, Trend},
+    strategies::StrategyName,
     types::{ListingId, MarketName},
+    watchlist::WatchRule,
 };
 
 #[derive(Debug, PartialEq)]
 pub struct CsfloatResponseEvent {
     pub timestamp: Instant,
     pub response: String,
+    // sequence number in the event log, None when it's not logged
+    pub log_seq: Option<i64>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct CsfloatOneListingResponseEvent {
     pub timestamp: Instant,
     pub response: String,
+    // sequence number in the event log, None when it's not logged
+    pub log_seq: Option<i64>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct SteamResponseEvent {
     pub timestamp: DateTime<Utc>,
     pub response: String,
+    // number of the item's listings by search/render, None when it's not fetched
+    pub sell_listings: Option<u32>,
+    // sequence number in the event log, None when it's not logged
+    pub log_seq: Option<i64>,
+}
+
+// itemordershistogram JSON, doesn't contain the market name itself
+#[derive(Debug, PartialEq)]
+pub struct SteamOrdersResponseEvent {
+    pub timestamp: DateTime<Utc>,
+    pub market_name: MarketName,
+    pub response: String,
+    // sequence number in the event log, None when it's not logged
+    pub log_seq: Option<i64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SkinportResponseEvent {
+    pub timestamp: Instant,
+    pub response: String,
+    // sequence number in the event log, None when it's not logged
+    pub log_seq: Option<i64>,
+}
+
+// `DmarketItemsResponse` JSON of the dmarket fetcher
+#[derive(Debug, PartialEq)]
+pub struct DmarketResponseEvent {
+    pub timestamp: Instant,
+    pub response: String,
+    // sequence number in the event log, None when it's not logged
+    pub log_seq: Option<i64>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -37,35 +78,326 @@ pub enum PrimEvent {
     CsfloatListingsResponse(CsfloatResponseEvent),
     SteamResponse(SteamResponseEvent),
     UpdatedCsfloatListings(UpdatedCsfloatListingsEvent),
+    SkinportListingsResponse(SkinportResponseEvent),
+    DmarketListingsResponse(DmarketResponseEvent),
+    SteamOrdersResponse(SteamOrdersResponseEvent),
+    PaperPurchase(PaperPurchaseEvent),
+    SteamAnalysisRequested(SteamAnalysisRequestedEvent),
+    SteamAnalysisReady(SteamAnalysisReadyEvent),
     // secondary events
 }
 
-#[derive(Debug, PartialEq)]
+// Low priority events are shed first when the primary queue is filling up
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EventPriority {
+    Low,
+    High,
+}
+
+// Primary events are processed by the pipeline of the engine they update, so
+// slow Steam parsing doesn't hold back CSFloat listings
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Pipeline {
+    Csfloat,
+    // Skinport and DMarket listings are priced by the steam engine
+    Steam,
+}
+
+impl PrimEvent {
+    pub fn get_pipeline(&self) -> Pipeline {
+        match self {
+            PrimEvent::CsfloatOneListingResponse(_)
+            | PrimEvent::CsfloatListingsResponse(_)
+            | PrimEvent::UpdatedCsfloatListings(_)
+            | PrimEvent::PaperPurchase(_) => Pipeline::Csfloat,
+            PrimEvent::SteamResponse(_)
+            | PrimEvent::SteamOrdersResponse(_)
+            | PrimEvent::SteamAnalysisRequested(_)
+            | PrimEvent::SteamAnalysisReady(_)
+            | PrimEvent::SkinportListingsResponse(_)
+            | PrimEvent::DmarketListingsResponse(_) => Pipeline::Steam,
+        }
+    }
+
+    // Responses with new listings and prices lead to deals. Re-evaluated listings
+    // and order books are redone by their next refresh.
+    pub fn get_priority(&self) -> EventPriority {
+        match self {
+            PrimEvent::UpdatedCsfloatListings(_)
+            | PrimEvent::SteamOrdersResponse(_)
+            | PrimEvent::SteamAnalysisRequested(_) => EventPriority::Low,
+            PrimEvent::CsfloatOneListingResponse(_)
+            | PrimEvent::CsfloatListingsResponse(_)
+            | PrimEvent::SteamResponse(_)
+            | PrimEvent::SteamAnalysisReady(_)
+            | PrimEvent::SkinportListingsResponse(_)
+            | PrimEvent::DmarketListingsResponse(_)
+            | PrimEvent::PaperPurchase(_) => EventPriority::High,
+        }
+    }
+
+    pub fn get_log_seq(&self) -> Option<i64> {
+        match self {
+            PrimEvent::CsfloatOneListingResponse(e) => e.log_seq,
+            PrimEvent::CsfloatListingsResponse(e) => e.log_seq,
+            PrimEvent::SteamResponse(e) => e.log_seq,
+            PrimEvent::SkinportListingsResponse(e) => e.log_seq,
+            PrimEvent::DmarketListingsResponse(e) => e.log_seq,
+            PrimEvent::SteamOrdersResponse(e) => e.log_seq,
+            PrimEvent::SteamAnalysisReady(e) => e.log_seq,
+            PrimEvent::UpdatedCsfloatListings(_)
+            | PrimEvent::PaperPurchase(_)
+            | PrimEvent::SteamAnalysisRequested(_) => None,
+        }
+    }
+
+    // Ignored by events which aren't logged, see `LoggedEvent`
+    pub fn set_log_seq(&mut self, log_seq: i64) {
+        let log_seq = Some(log_seq);
+        match self {
+            PrimEvent::CsfloatOneListingResponse(e) => e.log_seq = log_seq,
+            PrimEvent::CsfloatListingsResponse(e) => e.log_seq = log_seq,
+            PrimEvent::SteamResponse(e) => e.log_seq = log_seq,
+            PrimEvent::SkinportListingsResponse(e) => e.log_seq = log_seq,
+            PrimEvent::DmarketListingsResponse(e) => e.log_seq = log_seq,
+            PrimEvent::SteamOrdersResponse(e) => e.log_seq = log_seq,
+            PrimEvent::UpdatedCsfloatListings(_)
+            | PrimEvent::PaperPurchase(_)
+            | PrimEvent::SteamAnalysisRequested(_)
+            | PrimEvent::SteamAnalysisReady(_) => {}
+        }
+    }
+
+    // Listing the event is about, carried by the tracing span of its processing.
+    // Responses with many listings get a span per listing where they're evaluated.
+    pub fn get_listing_id(&self) -> Option<&ListingId> {
+        match self {
+            PrimEvent::PaperPurchase(e) => Some(&e.listing_id),
+            PrimEvent::CsfloatOneListingResponse(_)
+            | PrimEvent::CsfloatListingsResponse(_)
+            | PrimEvent::SteamResponse(_)
+            | PrimEvent::UpdatedCsfloatListings(_)
+            | PrimEvent::SkinportListingsResponse(_)
+            | PrimEvent::DmarketListingsResponse(_)
+            | PrimEvent::SteamOrdersResponse(_)
+            | PrimEvent::SteamAnalysisRequested(_)
+            | PrimEvent::SteamAnalysisReady(_) => None,
+        }
+    }
+
+    pub fn get_market_name(&self) -> Option<&MarketName> {
+        match self {
+            PrimEvent::SteamOrdersResponse(e) => Some(&e.market_name),
+            PrimEvent::SteamAnalysisRequested(e) => Some(&e.market_name),
+            PrimEvent::SteamAnalysisReady(e) => Some(&e.market_name),
+            PrimEvent::CsfloatOneListingResponse(_)
+            | PrimEvent::CsfloatListingsResponse(_)
+            | PrimEvent::SteamResponse(_)
+            | PrimEvent::UpdatedCsfloatListings(_)
+            | PrimEvent::SkinportListingsResponse(_)
+            | PrimEvent::DmarketListingsResponse(_)
+            | PrimEvent::PaperPurchase(_) => None,
+        }
+    }
+}
+
+// Marketplace where the listing can be bought
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub enum Venue {
+    Csfloat,
+    Skinport,
+    Dmarket,
+}
+
+// Where the Steam sell price comes from
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PriceSource {
+    // according to `strategy.sell_price_source`
+    Steam,
+    // thin sell history, see `FallbackPricingConfig`
+    BuyOrderFallback,
+    PredictedPriceFallback,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ProfitableListingKind {
     Profitable,
-    GoodPhase,
+    // bought by the phase pricing table, Steam prices are the target sell price
+    Phase(PhasePrice),
+    // rare pattern priced below the tier premium, Steam prices are of the regular item
+    RarePattern(PatternTier),
+    // matched a user-defined rule, Steam prices are 0 when unknown
+    Watchlist(WatchRule),
+    // see `strategies.low_float`, Steam prices are of the regular item
+    LowFloat,
+    // see `strategies.sticker`, Steam prices are of the regular item
+    Sticker,
 }
 
-#[derive(Debug, PartialEq)]
+// Estimated value of things applied to the item, paid by a buyer on top of the skin price
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AppliedValue {
+    pub stickers: PriceValue,
+    pub keychains: PriceValue,
+    pub patches: PriceValue,
+}
+
+impl AppliedValue {
+    pub fn total(&self) -> PriceValue {
+        self.stickers + self.keychains + self.patches
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ProfitableListingEvent {
     pub kind: ProfitableListingKind,
+    pub venue: Venue,
     pub market_name: MarketName,
     pub listing_id: ListingId,
     pub csfloat_price: PriceValue,
     pub steam_price: PriceValue,
     pub steam_no_fee: PriceValue,
+    pub price_source: PriceSource,
+    // CSFloat's own estimate, None for other venues
+    pub predicted_price: Option<PriceValue>,
+    // already included into steam_price
+    pub applied_value: AppliedValue,
     pub sold_per_week: u64,
     pub is_stable: bool,
+    pub trend: Trend,
+    // see `AnalysisResult::set_sell_listings`, None when the listings aren't fetched
+    pub expected_days_to_sell: Option<f64>,
     pub profit_pct: f64,
     pub float: Option<f64>,
+    // already applied to steam_price
+    pub trade_hold_days: u32,
+    // Steam ID of the CSFloat seller, None for other venues
+    pub seller_id: Option<String>,
+    // what found the deal, None for other venues and the watchlist
+    pub strategy: Option<StrategyName>,
+    // how much cheaper it is than other CSFloat listings of the market name,
+    // see `MarketAggregates`, None for other venues or when it's the only one
+    pub floor_undercut_pct: Option<f64>,
+    // other CSFloat listings of the market name bought together with it, see
+    // `strategies.commodity`, empty for the rest
+    pub batch: Vec<(ListingId, PriceValue)>,
+}
+
+impl ProfitableListingEvent {
+    // The listing itself first
+    pub fn get_purchases(&self) -> Vec<(ListingId, PriceValue)> {
+        let mut purchases = vec![(self.listing_id.clone(), self.csfloat_price)];
+        purchases.extend(self.batch.iter().cloned());
+        purchases
+    }
+
+    pub fn get_total_price(&self) -> PriceValue {
+        self.csfloat_price
+            + self
+                .batch
+                .iter()
+                .map(|(_, price)| price)
+                .sum::<PriceValue>()
+    }
+}
+
+// Auction which can be won with the profit, `max_bid` still leaves `auction.min_profit_pct`
+#[derive(Debug, PartialEq)]
+pub struct AuctionOpportunityEvent {
+    pub market_name: MarketName,
+    pub listing_id: ListingId,
+    pub next_bid: PriceValue,
+    pub max_bid: PriceValue,
+    pub steam_price: PriceValue,
+    pub steam_no_fee: PriceValue,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Something that needs attention of the operator, sent to Telegram as is
+#[derive(Debug, PartialEq)]
+pub struct AlertEvent {
+    pub text: String,
+}
+
+// Listed item with stale or missing Steam analysis, the steam fetcher refreshes it first
+#[derive(Debug, PartialEq)]
+pub struct SteamAnalysisRequestedEvent {
+    pub market_name: MarketName,
+}
+
+// Steam response parsed by the steam parser workers, see `SteamParserPool`
+#[derive(Debug, PartialEq)]
+pub struct SteamAnalysisReadyEvent {
+    pub market_name: MarketName,
+    pub result: AnalysisResult,
+    // of the parsed `SteamResponseEvent`, it's processed once the result is applied
+    pub log_seq: Option<i64>,
+}
+
+// Would-be purchase in paper-trading mode, the listing is checked by its next refresh
+#[derive(Debug, PartialEq)]
+pub struct PaperPurchaseEvent {
+    pub listing_id: ListingId,
 }
 
+#[derive(Debug, PartialEq)]
+pub struct PaperPurchaseCheckedEvent {
+    pub listing_id: ListingId,
+    // still listed on the next refresh, so the purchase would have succeeded
+    pub is_available: bool,
+}
+
+// "Confirm buy" is pressed in Telegram, see `PendingPurchases`
+#[derive(Debug, PartialEq)]
+pub struct PurchaseConfirmedEvent {
+    pub listing_id: ListingId,
+}
+
+// "Buy" is pressed in Telegram on a notified listing, it's bought by the autobuy checks
+#[derive(Debug, PartialEq)]
+pub struct BuyRequestedEvent {
+    pub listing: ProfitableListingEvent,
+}
+
+// profitable listings are most of the secondary events, boxing them doesn't pay off
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq)]
 pub enum SecEvent {
     // secondary events
     ProfitableListing(ProfitableListingEvent),
+    Alert(AlertEvent),
+    PaperPurchaseChecked(PaperPurchaseCheckedEvent),
+    AuctionOpportunity(AuctionOpportunityEvent),
+    PurchaseConfirmed(PurchaseConfirmedEvent),
+    BuyRequested(BuyRequestedEvent),
+}
+
+impl SecEvent {
+    pub fn get_listing_id(&self) -> Option<&ListingId> {
+        match self {
+            SecEvent::ProfitableListing(e) => Some(&e.listing_id),
+            SecEvent::PaperPurchaseChecked(e) => Some(&e.listing_id),
+            SecEvent::AuctionOpportunity(e) => Some(&e.listing_id),
+            SecEvent::PurchaseConfirmed(e) => Some(&e.listing_id),
+            SecEvent::BuyRequested(e) => Some(&e.listing.listing_id),
+            SecEvent::Alert(_) => None,
+        }
+    }
+
+    pub fn get_market_name(&self) -> Option<&MarketName> {
+        match self {
+            SecEvent::ProfitableListing(e) => Some(&e.market_name),
+            SecEvent::AuctionOpportunity(e) => Some(&e.market_name),
+            SecEvent::BuyRequested(e) => Some(&e.listing.market_name),
+            SecEvent::PaperPurchaseChecked(_)
+            | SecEvent::PurchaseConfirmed(_)
+            | SecEvent::Alert(_) => None,
+        }
+    }
 }
 
+// moved once into a queue, so boxing the bigger secondary events doesn't pay off
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq)]
 pub enum Event {
     Primary(PrimEvent),
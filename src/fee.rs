@@ -1,44 +1,137 @@
-use crate::prices::{PriceValue, PriceValueTrait};
+use crate::{
+    config::CsfloatFeeConfig,
+    prices::{PriceValue, PriceValueTrait},
+};
 
-pub struct SteamFee;
+pub const CS2_APP_ID: u32 = 730;
+pub const DOTA2_APP_ID: u32 = 570;
+pub const TF2_APP_ID: u32 = 440;
 
-const WALLET_FEE_PERCENT: f64 = 0.05;
-const DEFAULT_PUBLISHER_FEE: f64 = 0.1;
-// 1 + WALLET_FEE_PERCENT + DEFAULT_PUBLISHER_FEE
-const DIVIDER: f64 = 1.15;
+// Steam market fee of a game: the Steam wallet fee plus the publisher fee,
+// each of them is rounded down but not below its minimum in cents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeSchedule {
+    pub wallet_pct: f64,
+    pub publisher_pct: f64,
+    pub min_wallet_fee: PriceValue,
+    pub min_publisher_fee: PriceValue,
+}
 
-impl SteamFee {
+impl FeeSchedule {
+    // Valve takes 10% as the publisher of its own games
+    pub const VALVE: FeeSchedule = FeeSchedule {
+        wallet_pct: 5.0,
+        publisher_pct: 10.0,
+        min_wallet_fee: 1,
+        min_publisher_fee: 1,
+    };
+
+    pub fn for_appid(appid: u32) -> FeeSchedule {
+        match appid {
+            CS2_APP_ID | DOTA2_APP_ID | TF2_APP_ID => FeeSchedule::VALVE,
+            // other publishers may set their own fee, Steam's default one is the same 10%
+            _ => FeeSchedule::VALVE,
+        }
+    }
+
+    // Price with fee divided by the payload, ignoring the minimums
     #[inline]
-    pub fn add_fee(payload: PriceValue) -> PriceValue {
+    pub fn get_divider(&self) -> f64 {
+        1.0 + (self.wallet_pct + self.publisher_pct) / 100.0
+    }
+
+    #[inline]
+    pub fn add_fee(&self, payload: PriceValue) -> PriceValue {
         if payload < 1 {
             panic!("Unexpected input");
         }
-        let steam_fee = payload.multiply_by_percent(WALLET_FEE_PERCENT).max(1);
-        let game_fee = payload.multiply_by_percent(DEFAULT_PUBLISHER_FEE).max(1);
+        let steam_fee = payload
+            .multiply_by_percent(self.wallet_pct / 100.0)
+            .max(self.min_wallet_fee);
+        let game_fee = payload
+            .multiply_by_percent(self.publisher_pct / 100.0)
+            .max(self.min_publisher_fee);
 
         payload + steam_fee + game_fee
     }
 
+    // The smallest total the fee can be subtracted from, a cent is left after it
     #[inline]
-    pub fn subtract_fee(total: PriceValue) -> PriceValue {
-        if total < 3 {
-            panic!("Unexpected input");
+    pub fn get_min_total(&self) -> PriceValue {
+        1 + self.min_wallet_fee + self.min_publisher_fee
+    }
+
+    // Nothing is left after the fee below `get_min_total`
+    #[inline]
+    pub fn subtract_fee(&self, total: PriceValue) -> PriceValue {
+        self.try_subtract_fee(total).unwrap_or(0)
+    }
+
+    // None below `get_min_total`
+    #[inline]
+    pub fn try_subtract_fee(&self, total: PriceValue) -> Option<PriceValue> {
+        if total < self.get_min_total() {
+            return None;
         }
         const MAX_STEPS: i32 = 4;
         const START_ADDITION_CENTS: u64 = 2;
 
-        let predicted_payload = total.divide_by(DIVIDER);
+        let predicted_payload = total.divide_by(self.get_divider());
         let mut payload = predicted_payload + START_ADDITION_CENTS;
 
         for _ in 0..MAX_STEPS {
-            let calculated_total = SteamFee::add_fee(payload);
+            let calculated_total = self.add_fee(payload);
             if calculated_total <= total {
                 break;
             }
             payload -= 1;
         }
 
-        payload
+        Some(payload)
+    }
+}
+
+// Fee of CS2 items, which are the only ones traded so far
+pub struct SteamFee;
+
+impl SteamFee {
+    #[inline]
+    pub fn add_fee(payload: PriceValue) -> PriceValue {
+        FeeSchedule::for_appid(CS2_APP_ID).add_fee(payload)
+    }
+
+    #[inline]
+    pub fn subtract_fee(total: PriceValue) -> PriceValue {
+        FeeSchedule::for_appid(CS2_APP_ID).subtract_fee(total)
+    }
+
+    #[inline]
+    pub fn try_subtract_fee(total: PriceValue) -> Option<PriceValue> {
+        FeeSchedule::for_appid(CS2_APP_ID).try_subtract_fee(total)
+    }
+
+    #[inline]
+    pub fn get_min_total() -> PriceValue {
+        FeeSchedule::for_appid(CS2_APP_ID).get_min_total()
+    }
+}
+
+pub struct CsfloatFee;
+
+impl CsfloatFee {
+    #[inline]
+    pub fn get_fee(price: PriceValue, config: &CsfloatFeeConfig) -> PriceValue {
+        if config.buyer_fee_pct <= 0.0 {
+            return 0;
+        }
+        let fee = (price as f64 * config.buyer_fee_pct / 100.0).round() as PriceValue;
+        fee.max(config.min_buyer_fee)
+    }
+
+    // All-in cost of a listing
+    #[inline]
+    pub fn add_fee(price: PriceValue, config: &CsfloatFeeConfig) -> PriceValue {
+        price + CsfloatFee::get_fee(price, config)
     }
 }
 
@@ -48,6 +141,6 @@ mod tests {
 
     #[test]
     fn test_divider_is_strict_value() {
-        assert_eq!(DIVIDER, 1.15);
+        assert_eq!(FeeSchedule::for_appid(CS2_APP_ID).get_divider(), 1.15);
     }
 }
@@ -0,0 +1,348 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::Write,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres, Row};
+
+use crate::{
+    events::ProfitableListingEvent,
+    types::{ListingId, MarketName},
+};
+
+// "Buy now" works for that many last notified listings
+const NOTIFIED_LISTINGS_SIZE: usize = 500;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MuteTarget {
+    MarketName(MarketName),
+    Listing(ListingId),
+}
+
+// Suppressed item, forever without `until`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemMute {
+    pub id: i64,
+    pub target: MuteTarget,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl ItemMute {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+
+    pub fn is_matching(&self, market_name: &str, listing_id: &str) -> bool {
+        match &self.target {
+            MuteTarget::MarketName(x) => x == market_name,
+            MuteTarget::Listing(x) => x == listing_id,
+        }
+    }
+}
+
+// Lists edited via Telegram commands and the buttons of listing notifications.
+// Filtered listings are neither notified nor bought. Mutes and the seller
+// blacklist are kept in the DB.
+#[derive(Debug, Default)]
+pub struct ListingFilters {
+    mutes: Vec<ItemMute>,
+    blacklisted_sellers: HashSet<String>,
+    notified: VecDeque<ProfitableListingEvent>,
+}
+
+impl ListingFilters {
+    pub fn new() -> Self {
+        ListingFilters::default()
+    }
+
+    pub fn add_mute(&mut self, mute: ItemMute) {
+        self.mutes.push(mute);
+    }
+
+    pub fn remove_mute(&mut self, id: i64) -> bool {
+        let size = self.mutes.len();
+        self.mutes.retain(|x| x.id != id);
+        self.mutes.len() != size
+    }
+
+    pub fn blacklist_seller(&mut self, seller_id: &str) -> bool {
+        self.blacklisted_sellers.insert(seller_id.to_string())
+    }
+
+    // Expired mutes are dropped on the way, they stay in the DB until the next start
+    pub fn is_muted(&mut self, market_name: &str, listing_id: &str, now: DateTime<Utc>) -> bool {
+        self.mutes.retain(|x| x.is_active(now));
+        self.mutes
+            .iter()
+            .any(|x| x.is_matching(market_name, listing_id))
+    }
+
+    pub fn is_filtered(&mut self, event: &ProfitableListingEvent, now: DateTime<Utc>) -> bool {
+        let is_blacklisted = match &event.seller_id {
+            Some(seller_id) => self.blacklisted_sellers.contains(seller_id),
+            None => false,
+        };
+        is_blacklisted || self.is_muted(&event.market_name, &event.listing_id, now)
+    }
+
+    pub fn remember(&mut self, event: &ProfitableListingEvent) {
+        if self.notified.len() >= NOTIFIED_LISTINGS_SIZE {
+            self.notified.pop_front();
+        }
+        self.notified.push_back(event.clone());
+    }
+
+    pub fn find_notified(&self, listing_id: &ListingId) -> Option<&ProfitableListingEvent> {
+        self.notified
+            .iter()
+            .rev()
+            .find(|x| &x.listing_id == listing_id)
+    }
+
+    pub fn summary(&self, now: DateTime<Utc>) -> String {
+        let mutes: Vec<_> = self.mutes.iter().filter(|x| x.is_active(now)).collect();
+        if mutes.is_empty() {
+            return "No muted items".to_string();
+        }
+
+        let mut text = String::from("Muted:");
+        for mute in mutes {
+            match &mute.target {
+                MuteTarget::MarketName(market_name) => {
+                    write!(text, "\n#{} {}", mute.id, market_name)
+                }
+                MuteTarget::Listing(listing_id) => {
+                    write!(text, "\n#{} listing {}", mute.id, listing_id)
+                }
+            }
+            .unwrap();
+            if let Some(until) = mute.until {
+                write!(text, " until {}", until.format("%Y-%m-%d %H:%M UTC")).unwrap();
+            }
+        }
+        text
+    }
+}
+
+// Mutes with a duration can't be longer than that
+const MAX_MUTE_DAYS: i64 = 365;
+
+// "12h", "90m" or "7d", None when it isn't a duration, e.g. the "10" of a market name
+fn parse_mute_duration(value: &str) -> Option<Result<Duration, String>> {
+    let unit_secs = match value.chars().last()? {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return None,
+    };
+    let amount = &value[..value.len() - 1];
+    if amount.is_empty() || !amount.chars().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let duration = amount
+        .parse::<i64>()
+        .ok()
+        .and_then(|x| x.checked_mul(unit_secs))
+        .and_then(Duration::try_seconds)
+        .filter(|x| *x > Duration::zero() && *x <= Duration::days(MAX_MUTE_DAYS));
+    Some(duration.ok_or(format!(
+        "Duration should be between 1m and {}d",
+        MAX_MUTE_DAYS
+    )))
+}
+
+// "12h AK-47 | Redline (Field-Tested)" -> muted for 12 hours, the duration is optional.
+// Digits only are a listing id. Returns a mute without id.
+pub fn parse_mute_args(args: &str, now: DateTime<Utc>) -> Result<ItemMute, String> {
+    const USAGE: &str = "Usage: /mute [duration, e.g. 12h, 90m or 7d] <market name or listing id>";
+
+    let args = args.trim();
+    let (duration, target) = match args.split_once(' ') {
+        Some((first, rest)) => match parse_mute_duration(first) {
+            Some(duration) => (Some(duration?), rest.trim()),
+            None => (None, args),
+        },
+        None => (None, args),
+    };
+    if target.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    let target = match target.chars().all(|x| x.is_ascii_digit()) {
+        true => MuteTarget::Listing(target.into()),
+        false => MuteTarget::MarketName(target.into()),
+    };
+    Ok(ItemMute {
+        id: 0,
+        target,
+        until: duration.map(|duration| now + duration),
+    })
+}
+
+pub async fn load_listing_filters(db: &Pool<Postgres>) -> Result<ListingFilters, sqlx::Error> {
+    let rows = sqlx::query("SELECT steam_id FROM seller_blacklist")
+        .fetch_all(db)
+        .await?;
+    let blacklisted_sellers = rows.into_iter().map(|row| row.get("steam_id")).collect();
+
+    let rows = sqlx::query(
+        "SELECT id, market_name, listing_id, until FROM item_mutes
+        WHERE until IS NULL OR until > NOW() ORDER BY id",
+    )
+    .fetch_all(db)
+    .await?;
+    let mutes = rows
+        .into_iter()
+        .map(|row| ItemMute {
+            id: row.get("id"),
+            target: match row.get::<Option<ListingId>, _>("listing_id") {
+                Some(listing_id) => MuteTarget::Listing(listing_id),
+                None => MuteTarget::MarketName(row.get("market_name")),
+            },
+            until: row.get("until"),
+        })
+        .collect();
+
+    Ok(ListingFilters {
+        mutes,
+        blacklisted_sellers,
+        ..ListingFilters::default()
+    })
+}
+
+// Saves the mute and returns it with the assigned id
+pub async fn insert_item_mute(
+    db: &Pool<Postgres>,
+    mute: &ItemMute,
+) -> Result<ItemMute, sqlx::Error> {
+    let (market_name, listing_id) = match &mute.target {
+        MuteTarget::MarketName(market_name) => (Some(market_name), None),
+        MuteTarget::Listing(listing_id) => (None, Some(listing_id)),
+    };
+    let row = sqlx::query(
+        "INSERT INTO item_mutes (market_name, listing_id, until) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(market_name)
+    .bind(listing_id)
+    .bind(mute.until)
+    .fetch_one(db)
+    .await?;
+    Ok(ItemMute {
+        id: row.get("id"),
+        ..mute.clone()
+    })
+}
+
+pub async fn delete_item_mute(db: &Pool<Postgres>, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM item_mutes WHERE id = $1")
+        .bind(id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_blacklisted_seller(
+    db: &Pool<Postgres>,
+    seller_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO seller_blacklist (steam_id) VALUES ($1) ON CONFLICT DO NOTHING")
+        .bind(seller_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures::get_profitable_listing_event;
+    use chrono::Duration;
+
+    fn get_event(market_name: &str, seller_id: Option<&str>) -> ProfitableListingEvent {
+        ProfitableListingEvent {
+            market_name: market_name.into(),
+            seller_id: seller_id.map(|x| x.to_string()),
+            ..get_profitable_listing_event()
+        }
+    }
+
+    #[test]
+    fn test_listing_filters() {
+        let now = Utc::now();
+        let mut filters = ListingFilters::new();
+        let event = get_event("A", Some("1"));
+        assert!(!filters.is_filtered(&event, now));
+
+        filters.add_mute(ItemMute {
+            id: 1,
+            target: MuteTarget::MarketName("A".into()),
+            until: Some(now + Duration::hours(1)),
+        });
+        assert!(filters.is_filtered(&event, now));
+        assert!(!filters.is_filtered(&get_event("B", Some("1")), now));
+        assert!(!filters.is_filtered(&event, now + Duration::hours(2)));
+
+        assert!(filters.blacklist_seller("1"));
+        assert!(!filters.blacklist_seller("1"));
+        assert!(filters.is_filtered(&get_event("B", Some("1")), now));
+        assert!(!filters.is_filtered(&get_event("B", None), now));
+    }
+
+    #[test]
+    fn test_item_mutes() {
+        let now = Utc::now();
+        let mut filters = ListingFilters::new();
+        let mute = parse_mute_args("AK-47 | Redline (Field-Tested)", now).unwrap();
+        assert_eq!(
+            mute.target,
+            MuteTarget::MarketName("AK-47 | Redline (Field-Tested)".into())
+        );
+        assert_eq!(mute.until, None);
+        filters.add_mute(ItemMute { id: 1, ..mute });
+
+        let mute = parse_mute_args("90m 679718648830624407", now).unwrap();
+        assert_eq!(
+            mute.target,
+            MuteTarget::Listing("679718648830624407".into())
+        );
+        assert_eq!(mute.until, Some(now + Duration::minutes(90)));
+        filters.add_mute(ItemMute { id: 2, ..mute });
+
+        assert!(filters.is_muted("M4A4 | Howl (Field-Tested)", "679718648830624407", now));
+        assert!(!filters.is_muted(
+            "M4A4 | Howl (Field-Tested)",
+            "679718648830624407",
+            now + Duration::hours(2)
+        ));
+        assert!(filters.is_muted(
+            "AK-47 | Redline (Field-Tested)",
+            "1",
+            now + Duration::days(30)
+        ));
+        assert!(filters.remove_mute(1));
+        assert!(!filters.remove_mute(2));
+        assert_eq!(filters.summary(now), "No muted items");
+
+        assert!(parse_mute_args(" ", now).is_err());
+        assert!(parse_mute_args("0h AK-47 | Redline (Field-Tested)", now).is_err());
+        assert!(parse_mute_args("400d AK-47 | Redline (Field-Tested)", now).is_err());
+        assert!(parse_mute_args("99999999999999999999h x", now).is_err());
+        assert!(parse_mute_args("9223372036854775807d x", now).is_err());
+        assert_eq!(
+            parse_mute_args("7d x", now).unwrap().until,
+            Some(now + Duration::days(7))
+        );
+
+        // not a duration, so a part of the name
+        for args in ["1e20 x", "inf x", "-1 x", "1.5 x"] {
+            let mute = parse_mute_args(args, now).unwrap();
+            assert_eq!(mute.until, None);
+            assert_eq!(mute.target, MuteTarget::MarketName(args.into()));
+        }
+        let mute = parse_mute_args("10 Year Birthday Sticker Capsule", now).unwrap();
+        assert_eq!(
+            mute.target,
+            MuteTarget::MarketName("10 Year Birthday Sticker Capsule".into())
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use sqlx::{Connection, PgConnection};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::warn;
+
+use crate::{sharding::Shard, shutdown::ShutdownSignal};
+
+// Advisory lock ids of the leaders are this plus the shard index
+const LEADER_LOCK_KEY: i64 = 0x5343_4c44_0000_0000;
+
+// Whether this instance is the single writer of its shard: only the leader buys, sends
+// notifications, lists on Steam and saves the engines. Followers run the same pipelines,
+// so their engines are warm when they take over.
+#[derive(Debug, Clone)]
+pub struct Leadership {
+    is_leader: Arc<AtomicBool>,
+    // the connection holding the lock, None without leader election
+    lock: Option<Arc<Mutex<Option<PgConnection>>>>,
+}
+
+impl Leadership {
+    // without leader election every instance is the leader
+    pub fn always() -> Leadership {
+        Leadership {
+            is_leader: Arc::new(AtomicBool::new(true)),
+            lock: None,
+        }
+    }
+
+    // until the lock is taken by `spawn_leader_election`
+    pub fn follower() -> Leadership {
+        Leadership {
+            is_leader: Arc::new(AtomicBool::new(false)),
+            lock: Some(Arc::new(Mutex::new(None))),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::Relaxed)
+    }
+
+    // Pings the held lock right before a purchase, so a leader which has lost it stops buying
+    // at once instead of on the next check of `spawn_leader_election`
+    pub async fn confirm(&self) -> bool {
+        let Some(lock) = &self.lock else {
+            return self.is_leader();
+        };
+        let mut conn = lock.lock().await;
+        let Some(held) = conn.as_mut() else {
+            return false;
+        };
+        if let Err(err) = held.ping().await {
+            warn!("Lost the leader lock, purchases are stopped: {}", err);
+            *conn = None;
+            self.set_leader(false);
+            return false;
+        }
+        self.is_leader()
+    }
+
+    // Returns whether the role has changed
+    fn set_leader(&self, is_leader: bool) -> bool {
+        self.is_leader.swap(is_leader, Ordering::Relaxed) != is_leader
+    }
+}
+
+// The lock is a session advisory lock held on its own connection, like the shard lock.
+// A leader which dies or loses the connection releases it, and one of the followers takes
+// it on its next retry.
+async fn try_lead(
+    database_url: &str,
+    shard: Shard,
+    conn: &mut Option<PgConnection>,
+) -> Result<bool, sqlx::Error> {
+    if let Some(held) = conn.as_mut() {
+        if let Err(err) = held.ping().await {
+            *conn = None;
+            return Err(err);
+        }
+        return Ok(true);
+    }
+    let mut new_conn = PgConnection::connect(database_url).await?;
+    let is_locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+        .bind(LEADER_LOCK_KEY + shard.index as i64)
+        .fetch_one(&mut new_conn)
+        .await?;
+    if is_locked {
+        *conn = Some(new_conn);
+    }
+    Ok(is_locked)
+}
+
+// Retries to take the lock every `retry_interval` while being a follower and checks
+// the held connection while being the leader. On shutdown the held connection is returned,
+// so the lock can be kept until the final save.
+pub fn spawn_leader_election(
+    database_url: String,
+    shard: Shard,
+    leadership: Leadership,
+    retry_interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<Option<PgConnection>> {
+    let lock = leadership
+        .lock
+        .clone()
+        .expect("Leader election needs a follower leadership");
+    tokio::spawn(async move {
+        loop {
+            let result = try_lead(&database_url, shard, &mut *lock.lock().await).await;
+            let is_leader = match result {
+                Ok(is_leader) => is_leader,
+                Err(err) => {
+                    warn!("Failed to check the leader lock: {}", err);
+                    false
+                }
+            };
+            if leadership.set_leader(is_leader) {
+                match is_leader {
+                    true => warn!("Became the leader of shard {}", shard),
+                    false => warn!(
+                        "Lost the leadership of shard {}, running as a standby",
+                        shard
+                    ),
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(retry_interval) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+        let conn = lock.lock().await.take();
+        conn
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leadership() {
+        assert!(Leadership::always().is_leader());
+
+        let leadership = Leadership::follower();
+        let shared = leadership.clone();
+        assert!(!shared.is_leader());
+        assert!(leadership.set_leader(true));
+        assert!(shared.is_leader());
+        assert!(!leadership.set_leader(true));
+        assert!(leadership.set_leader(false));
+        assert!(!shared.is_leader());
+    }
+
+    #[tokio::test]
+    async fn test_confirm() {
+        assert!(Leadership::always().confirm().await);
+
+        // a leader without the held lock doesn't buy
+        let leadership = Leadership::follower();
+        assert!(!leadership.confirm().await);
+        leadership.set_leader(true);
+        assert!(!leadership.confirm().await);
+    }
+}
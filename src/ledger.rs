@@ -0,0 +1,372 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+
+use crate::{
+    config::AutobuyConfig,
+    csfloat_autobuy::BuyOutcome,
+    events::{AuctionOpportunityEvent, ProfitableListingEvent},
+    prices::PriceValue,
+    risk::RiskManager,
+    strategies::StrategyName,
+    types::{ListingId, MarketName},
+};
+
+// One `buy_listing` call, successful or not
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PurchaseRecord {
+    pub listing_id: ListingId,
+    pub market_name: MarketName,
+    pub paid_price: PriceValue,
+    pub expected_steam_price: PriceValue,
+    // steam price minus fee minus paid price, in cents
+    pub expected_profit: i64,
+    pub profit_pct: f64,
+    pub timestamp: DateTime<Utc>,
+    pub is_success: bool,
+    pub outcome: serde_json::Value,
+    // would-be purchase of paper-trading mode
+    pub is_paper: bool,
+    // for paper purchases: whether the listing was still listed on the next refresh
+    pub is_available: Option<bool>,
+    // matches the bought item in the Steam inventory, see `steam_inventory`
+    pub float: Option<f64>,
+    // None for purchases made before strategies were tracked
+    pub strategy: Option<StrategyName>,
+}
+
+impl PurchaseRecord {
+    // `paid_price` is with the marketplace fee, see `get_buy_cost`
+    pub fn new(
+        event: &ProfitableListingEvent,
+        paid_price: PriceValue,
+        outcome: &BuyOutcome,
+        is_paper: bool,
+        timestamp: DateTime<Utc>,
+    ) -> PurchaseRecord {
+        PurchaseRecord {
+            listing_id: event.listing_id.clone(),
+            market_name: event.market_name.clone(),
+            paid_price,
+            expected_steam_price: event.steam_price,
+            expected_profit: event.steam_no_fee as i64 - paid_price as i64,
+            profit_pct: event.profit_pct,
+            timestamp,
+            is_success: outcome.is_success,
+            outcome: serde_json::to_value(outcome).unwrap_or_default(),
+            is_paper,
+            is_available: None,
+            float: event.float,
+            strategy: event.strategy,
+        }
+    }
+
+    // A placed bid holds the budget at its max until the auction ends, as if it's won
+    pub fn from_bid(
+        event: &AuctionOpportunityEvent,
+        bid: PriceValue,
+        outcome: &BuyOutcome,
+        timestamp: DateTime<Utc>,
+    ) -> PurchaseRecord {
+        let expected_profit = event.steam_no_fee as i64 - bid as i64;
+        PurchaseRecord {
+            listing_id: event.listing_id.clone(),
+            market_name: event.market_name.clone(),
+            paid_price: bid,
+            expected_steam_price: event.steam_price,
+            expected_profit,
+            profit_pct: expected_profit as f64 / bid.max(1) as f64 * 100.0,
+            timestamp,
+            is_success: outcome.is_success,
+            outcome: serde_json::to_value(outcome).unwrap_or_default(),
+            is_paper: false,
+            is_available: None,
+            float: None,
+            strategy: None,
+        }
+    }
+}
+
+pub async fn set_paper_availability(
+    db: &Pool<Postgres>,
+    listing_id: &ListingId,
+    is_available: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE purchases SET is_available = $2 WHERE listing_id = $1 AND is_paper")
+        .bind(listing_id)
+        .bind(is_available)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+// Successful purchase which isn't listed on Steam yet, see `steam_seller`.
+// It's either in the Steam inventory or sold manually.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnlistedPurchase {
+    pub listing_id: ListingId,
+    pub market_name: MarketName,
+    pub paid_price: PriceValue,
+    pub float: Option<f64>,
+}
+
+pub async fn load_unlisted_purchases(
+    db: &Pool<Postgres>,
+    since: DateTime<Utc>,
+) -> Result<Vec<UnlistedPurchase>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT listing_id, market_hash_name, paid_price, float_value FROM purchases
+        WHERE is_success AND NOT is_paper AND steam_asset_id IS NULL AND created_at >= $1
+        ORDER BY created_at",
+    )
+    .bind(since)
+    .fetch_all(db)
+    .await?;
+
+    let purchases = rows
+        .into_iter()
+        .map(|row| UnlistedPurchase {
+            listing_id: row.get("listing_id"),
+            market_name: row.get("market_hash_name"),
+            paid_price: row.get::<i64, _>("paid_price") as PriceValue,
+            float: row.get("float_value"),
+        })
+        .collect();
+    Ok(purchases)
+}
+
+pub async fn load_listed_asset_ids(db: &Pool<Postgres>) -> Result<HashSet<String>, sqlx::Error> {
+    let rows = sqlx::query("SELECT steam_asset_id FROM purchases WHERE steam_asset_id IS NOT NULL")
+        .fetch_all(db)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| row.get("steam_asset_id"))
+        .collect())
+}
+
+// What was paid for each listed item with the marketplace fee, by Steam asset id
+pub async fn load_listed_cost_basis(
+    db: &Pool<Postgres>,
+) -> Result<HashMap<String, PriceValue>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT steam_asset_id, paid_price FROM purchases WHERE steam_asset_id IS NOT NULL",
+    )
+    .fetch_all(db)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get("steam_asset_id"),
+                row.get::<i64, _>("paid_price") as PriceValue,
+            )
+        })
+        .collect())
+}
+
+// `price` is what the seller receives after the Steam fee
+pub async fn set_steam_listing(
+    db: &Pool<Postgres>,
+    listing_id: &ListingId,
+    asset_id: &str,
+    price: PriceValue,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE purchases SET steam_asset_id = $2, steam_list_price = $3, steam_listed_at = now()
+        WHERE listing_id = $1 AND is_success AND NOT is_paper",
+    )
+    .bind(listing_id)
+    .bind(asset_id)
+    .bind(price as i64)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+// After the listing is repriced, `price` is after the Steam fee
+pub async fn update_steam_list_price(
+    db: &Pool<Postgres>,
+    asset_id: &str,
+    price: PriceValue,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE purchases SET steam_list_price = $2, steam_listed_at = now()
+        WHERE steam_asset_id = $1",
+    )
+    .bind(asset_id)
+    .bind(price as i64)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+// The purchase is listed by `steam_seller` again
+pub async fn clear_steam_listing(db: &Pool<Postgres>, asset_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE purchases SET steam_asset_id = NULL, steam_list_price = NULL, steam_listed_at = NULL
+        WHERE steam_asset_id = $1",
+    )
+    .bind(asset_id)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+// Manually entered sale of a bought item
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaleRecord {
+    pub market_name: MarketName,
+    // received after the marketplace fee
+    pub price: PriceValue,
+    pub timestamp: DateTime<Utc>,
+}
+
+pub async fn record_purchase(
+    db: &Pool<Postgres>,
+    record: &PurchaseRecord,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO purchases (listing_id, market_hash_name, paid_price, expected_steam_price,
+            expected_profit, profit_pct, created_at, is_success, outcome, is_paper, is_available,
+            float_value, strategy)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+    )
+    .bind(&record.listing_id)
+    .bind(&record.market_name)
+    .bind(record.paid_price as i64)
+    .bind(record.expected_steam_price as i64)
+    .bind(record.expected_profit)
+    .bind(record.profit_pct)
+    .bind(record.timestamp)
+    .bind(record.is_success)
+    .bind(record.outcome.to_string())
+    .bind(record.is_paper)
+    .bind(record.is_available)
+    .bind(record.float)
+    .bind(record.strategy.map(|x| x.as_str()))
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn load_purchases_since(
+    db: &Pool<Postgres>,
+    since: DateTime<Utc>,
+) -> Result<Vec<PurchaseRecord>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT listing_id, market_hash_name, paid_price, expected_steam_price,
+            expected_profit, profit_pct, created_at, is_success, outcome, is_paper, is_available,
+            float_value, strategy
+        FROM purchases WHERE created_at >= $1 ORDER BY created_at",
+    )
+    .bind(since)
+    .fetch_all(db)
+    .await?;
+
+    let records = rows
+        .into_iter()
+        .map(|row| PurchaseRecord {
+            listing_id: row.get("listing_id"),
+            market_name: row.get("market_hash_name"),
+            paid_price: row.get::<i64, _>("paid_price") as PriceValue,
+            expected_steam_price: row.get::<i64, _>("expected_steam_price") as PriceValue,
+            expected_profit: row.get("expected_profit"),
+            profit_pct: row.get("profit_pct"),
+            timestamp: row.get("created_at"),
+            is_success: row.get("is_success"),
+            outcome: serde_json::from_str(row.get::<&str, _>("outcome")).unwrap_or_default(),
+            is_paper: row.get("is_paper"),
+            is_available: row.get("is_available"),
+            float: row.get("float_value"),
+            strategy: row
+                .get::<Option<&str>, _>("strategy")
+                .and_then(StrategyName::parse),
+        })
+        .collect();
+    Ok(records)
+}
+
+// Spend of today, positions and the last purchase of each market name from the successful
+// purchases of the current trading mode within `autobuy.positions_lookback_days`.
+// The sales entered since then close positions.
+pub async fn load_risk_manager(
+    db: &Pool<Postgres>,
+    config: &AutobuyConfig,
+    now: DateTime<Utc>,
+) -> Result<RiskManager, sqlx::Error> {
+    let today = now.date_naive().and_time(NaiveTime::MIN).and_utc();
+    let since = today.min(now - config.positions_lookback());
+    let mut risk_manager = RiskManager::new();
+    // oldest first, so the spend ends up of the day of the last purchase
+    for purchase in load_purchases_since(db, since)
+        .await?
+        .iter()
+        .filter(|x| x.is_success && x.is_paper == config.paper_trading)
+    {
+        risk_manager.register_purchase(
+            &purchase.market_name,
+            purchase.paid_price,
+            purchase.timestamp,
+        );
+    }
+    for sale in load_sales_since(db, since).await? {
+        risk_manager.register_sale(&sale.market_name);
+    }
+    risk_manager.set_kill_switch(load_kill_switch(db).await?);
+    Ok(risk_manager)
+}
+
+// The kill-switch outlives restarts, it's restored by `load_risk_manager`
+pub async fn save_kill_switch(db: &Pool<Postgres>, is_on: bool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO autobuy_state (name, value) VALUES ('kill_switch', $1)
+        ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value",
+    )
+    .bind(is_on.to_string())
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn load_kill_switch(db: &Pool<Postgres>) -> Result<bool, sqlx::Error> {
+    let value: Option<String> =
+        sqlx::query_scalar("SELECT value FROM autobuy_state WHERE name = 'kill_switch'")
+            .fetch_optional(db)
+            .await?;
+    Ok(value.is_some_and(|x| x == "true"))
+}
+
+pub async fn record_sale(db: &Pool<Postgres>, record: &SaleRecord) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO sales (market_hash_name, price, sold_at) VALUES ($1, $2, $3)")
+        .bind(&record.market_name)
+        .bind(record.price as i64)
+        .bind(record.timestamp)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn load_sales_since(
+    db: &Pool<Postgres>,
+    since: DateTime<Utc>,
+) -> Result<Vec<SaleRecord>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT market_hash_name, price, sold_at FROM sales WHERE sold_at >= $1 ORDER BY sold_at",
+    )
+    .bind(since)
+    .fetch_all(db)
+    .await?;
+
+    let records = rows
+        .into_iter()
+        .map(|row| SaleRecord {
+            market_name: row.get("market_hash_name"),
+            price: row.get::<i64, _>("price") as PriceValue,
+            timestamp: row.get("sold_at"),
+        })
+        .collect();
+    Ok(records)
+}
@@ -0,0 +1,67 @@
+// Engines, analyzers, fees and event types of the bot, the bot itself is `bin/bot.rs`
+pub mod admin_api;
+pub mod backtest;
+pub mod business_logic;
+pub mod chart;
+pub mod cli;
+pub mod clock;
+pub mod config;
+pub mod consts;
+pub mod csfloat;
+pub mod csfloat_autobuy;
+pub mod csfloat_client;
+pub mod csfloat_fetcher;
+pub mod currency;
+pub mod dashboard;
+pub mod dmarket;
+pub mod dry_run;
+pub mod event_log;
+pub mod event_processors;
+pub mod events;
+pub mod fee;
+pub mod filters;
+pub mod leadership;
+pub mod ledger;
+pub mod market_aggregates;
+pub mod market_floors;
+pub mod models;
+pub mod names;
+pub mod notify;
+pub mod patterns;
+pub mod pending_purchases;
+pub mod phases;
+pub mod prefilter;
+pub mod price_history;
+pub mod prices;
+pub mod pricing;
+pub mod proxy_pool;
+pub mod queues;
+pub mod realtime_importer;
+pub mod reporting;
+pub mod response_archive;
+pub mod risk;
+pub mod sharding;
+pub mod shutdown;
+pub mod skinport;
+pub mod snapshot_codec;
+pub mod state_store;
+pub mod stats;
+pub mod stats_history;
+pub mod steam_analyzer;
+pub mod steam_fetcher;
+pub mod steam_inventory;
+pub mod steam_orders;
+pub mod steam_parser;
+pub mod steam_repricer;
+pub mod steam_seller;
+pub mod stickers;
+pub mod storages;
+pub mod strategies;
+pub mod telegram_commands;
+pub mod types;
+pub mod utils;
+pub mod watchdog;
+pub mod watchlist;
+
+#[cfg(test)]
+mod tests;
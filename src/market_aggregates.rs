@@ -0,0 +1,150 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    models::CsfloatListingStruct,
+    prices::PriceValue,
+    types::{ListingId, MarketName},
+};
+
+// Buy now listings of each market name ordered by price, kept in sync with
+// `CsfloatEngine::hm`. Auctions are left out, their price is only the next bid.
+#[derive(Debug, Default)]
+pub struct MarketAggregates {
+    by_market_name: HashMap<MarketName, BTreeSet<(PriceValue, ListingId)>>,
+    // what the listing is indexed by, so it can be removed after its price changed
+    entries: HashMap<ListingId, (MarketName, PriceValue)>,
+}
+
+impl MarketAggregates {
+    pub fn new() -> Self {
+        MarketAggregates::default()
+    }
+
+    pub fn update(&mut self, listing: &CsfloatListingStruct) {
+        self.remove(&listing.id);
+        if listing.is_auction() {
+            return;
+        }
+        let market_name = listing.item.market_hash_name.clone();
+        let price = listing.get_price_value();
+        self.by_market_name
+            .entry(market_name.clone())
+            .or_default()
+            .insert((price, listing.id.clone()));
+        self.entries
+            .insert(listing.id.clone(), (market_name, price));
+    }
+
+    pub fn remove(&mut self, listing_id: &ListingId) {
+        let Some((market_name, price)) = self.entries.remove(listing_id) else {
+            return;
+        };
+        if let Some(listings) = self.by_market_name.get_mut(&market_name) {
+            listings.remove(&(price, listing_id.clone()));
+            if listings.is_empty() {
+                self.by_market_name.remove(&market_name);
+            }
+        }
+    }
+
+    // Floor price and the number of listings of each market name
+    pub fn iter_floors(&self) -> impl Iterator<Item = (&MarketName, PriceValue, usize)> {
+        self.by_market_name
+            .iter()
+            .filter_map(|(market_name, listings)| {
+                let (floor, _) = listings.first()?;
+                Some((market_name, *floor, listings.len()))
+            })
+    }
+
+    // Listings of the market name, the cheapest first
+    pub fn iter_listings(
+        &self,
+        market_name: &MarketName,
+    ) -> impl Iterator<Item = (PriceValue, &ListingId)> {
+        self.by_market_name
+            .get(market_name)
+            .into_iter()
+            .flatten()
+            .map(|(price, id)| (*price, id))
+    }
+
+    // The cheapest listing of the market name other than `listing_id`
+    pub fn get_floor_except(
+        &self,
+        market_name: &MarketName,
+        listing_id: Option<&ListingId>,
+    ) -> Option<(PriceValue, &ListingId)> {
+        self.by_market_name
+            .get(market_name)?
+            .iter()
+            .find(|(_, id)| Some(id) != listing_id)
+            .map(|(price, id)| (*price, id))
+    }
+
+    // How much cheaper the listing is than the rest of its market name on CSFloat,
+    // negative when it's not the cheapest one. None when there are no other listings.
+    pub fn get_floor_undercut_pct(
+        &self,
+        market_name: &MarketName,
+        listing_id: &ListingId,
+        price: PriceValue,
+    ) -> Option<f64> {
+        let (floor, _) = self.get_floor_except(market_name, Some(listing_id))?;
+        Some((1.0 - price as f64 / floor.max(1) as f64) * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures::get_listing_json;
+
+    fn listing(id: &str, price: PriceValue, listing_type: &str) -> CsfloatListingStruct {
+        let mut listing = get_listing_json(id, "AK-47 | Redline (Field-Tested)", price);
+        listing["type"] = listing_type.into();
+        serde_json::from_value(listing).unwrap()
+    }
+
+    #[test]
+    fn test_market_aggregates() {
+        let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let mut aggregates = MarketAggregates::new();
+        aggregates.update(&listing("1", 10_00, "buy_now"));
+        aggregates.update(&listing("2", 15_00, "buy_now"));
+        aggregates.update(&listing("3", 5_00, "auction"));
+        assert_eq!(aggregates.entries.len(), 2);
+        assert_eq!(
+            aggregates.iter_floors().collect::<Vec<_>>(),
+            vec![(&market_name, 10_00, 2)]
+        );
+        assert_eq!(
+            aggregates.get_floor_except(&market_name, None),
+            Some((10_00, &ListingId::from("1")))
+        );
+        assert_eq!(
+            aggregates.get_floor_undercut_pct(&market_name, &"1".into(), 7_50),
+            Some(50.0)
+        );
+        assert_eq!(
+            aggregates.get_floor_undercut_pct(&market_name, &"2".into(), 15_00),
+            Some(-50.0)
+        );
+
+        // the price dropped below the other one
+        aggregates.update(&listing("2", 8_00, "buy_now"));
+        assert_eq!(
+            aggregates.get_floor_except(&market_name, None),
+            Some((8_00, &ListingId::from("2")))
+        );
+
+        aggregates.remove(&"2".into());
+        aggregates.remove(&"1".into());
+        assert_eq!(aggregates.get_floor_except(&market_name, None), None);
+        assert_eq!(
+            aggregates.get_floor_undercut_pct(&market_name, &"1".into(), 9_00),
+            None
+        );
+        assert!(aggregates.by_market_name.is_empty());
+    }
+}
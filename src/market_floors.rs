@@ -0,0 +1,262 @@
+use std::{collections::HashMap, fmt, time::Duration as StdDuration};
+
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    names::deserialize_market_name,
+    prices::{PriceValue, PriceValueTrait},
+    types::MarketName,
+};
+
+// Marketplace whose floor prices are pulled by the price feed, the deals themselves
+// come only from the venues
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum FloorSource {
+    Csmoney,
+    Bitskins,
+}
+
+impl fmt::Display for FloorSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FloorSource::Csmoney => write!(f, "CS.MONEY"),
+            FloorSource::Bitskins => write!(f, "Bitskins"),
+        }
+    }
+}
+
+// {"list": [{"name": "...", "price_min": 12340, "quantity": 3}]}, prices are in 1/1000 USD
+#[derive(Debug, Deserialize)]
+struct BitskinsInSellResponse {
+    list: Vec<BitskinsInSellItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitskinsInSellItem {
+    #[serde(deserialize_with = "deserialize_market_name")]
+    name: MarketName,
+    price_min: u64,
+}
+
+// [{"market_hash_name": "...", "price": 12.34}], prices are in USD
+#[derive(Debug, Deserialize)]
+struct CsmoneyPriceItem {
+    #[serde(deserialize_with = "deserialize_market_name")]
+    market_hash_name: MarketName,
+    price: f64,
+}
+
+// Floor price of each market name in a response of the source, broken prices are skipped
+pub fn parse_floors(
+    source: FloorSource,
+    body: &str,
+) -> Result<Vec<(MarketName, PriceValue)>, serde_json::Error> {
+    let floors = match source {
+        FloorSource::Bitskins => serde_json::from_str::<BitskinsInSellResponse>(body)?
+            .list
+            .into_iter()
+            .filter(|x| x.price_min > 0)
+            .map(|x| (x.name, x.price_min.div_ceil(10) as PriceValue))
+            .collect(),
+        FloorSource::Csmoney => serde_json::from_str::<Vec<CsmoneyPriceItem>>(body)?
+            .into_iter()
+            .filter(|x| x.price.is_finite() && x.price > 0.0)
+            .map(|x| (x.market_hash_name, (x.price * 100.0).round() as PriceValue))
+            .filter(|(_, price)| *price > 0)
+            .collect(),
+    };
+    Ok(floors)
+}
+
+pub async fn fetch_floors(
+    client: &Client,
+    source: FloorSource,
+    url: &str,
+    timeout: StdDuration,
+) -> Result<Vec<(MarketName, PriceValue)>, String> {
+    let response = client
+        .get(url)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("unexpected status {}", response.status()));
+    }
+    let body = response.text().await.map_err(|err| err.to_string())?;
+    parse_floors(source, &body).map_err(|err| err.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketFloor {
+    pub source: FloorSource,
+    pub price: PriceValue,
+    pub updated_at: DateTime<Utc>,
+}
+
+// How a deal compares with the floors of the other marketplaces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloorComparison {
+    // the venue of the deal and the sources with a fresh floor
+    pub markets: usize,
+    // the cheapest floor below the deal price
+    pub cheaper: Option<MarketFloor>,
+}
+
+impl FloorComparison {
+    // None when no other marketplace has a fresh floor
+    pub fn describe(&self) -> Option<String> {
+        match self.cheaper {
+            Some(floor) => Some(format!(
+                "{} floor is lower: ${}",
+                floor.source,
+                floor.price.to_usd()
+            )),
+            None if self.markets > 1 => Some(format!("cheapest across {} markets", self.markets)),
+            None => None,
+        }
+    }
+}
+
+// Floor prices of other marketplaces by market name, each source is replaced
+// as a whole by its next pull. Kept only in memory.
+#[derive(Debug, Default)]
+pub struct MarketFloors {
+    hm: HashMap<MarketName, Vec<MarketFloor>>,
+}
+
+impl MarketFloors {
+    pub fn new() -> Self {
+        MarketFloors::default()
+    }
+
+    pub fn update_source(
+        &mut self,
+        source: FloorSource,
+        floors: Vec<(MarketName, PriceValue)>,
+        now: DateTime<Utc>,
+    ) {
+        self.hm.retain(|_, entries| {
+            entries.retain(|x| x.source != source);
+            !entries.is_empty()
+        });
+        for (market_name, price) in floors {
+            self.hm.entry(market_name).or_default().push(MarketFloor {
+                source,
+                price,
+                updated_at: now,
+            });
+        }
+    }
+
+    pub fn compare(
+        &self,
+        market_name: &MarketName,
+        price: PriceValue,
+        now: DateTime<Utc>,
+        max_age: Duration,
+    ) -> FloorComparison {
+        let fresh: Vec<&MarketFloor> = self
+            .hm
+            .get(market_name)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter(|x| now - x.updated_at <= max_age)
+                    .collect()
+            })
+            .unwrap_or_default();
+        FloorComparison {
+            markets: fresh.len() + 1,
+            cheaper: fresh
+                .into_iter()
+                .filter(|x| x.price < price)
+                .min_by_key(|x| x.price)
+                .copied(),
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.hm.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_floors() {
+        let body = r#"{"list": [{"name": "AK-47 | Redline (Field-Tested)", "price_min": 12345, "quantity": 3}]}"#;
+        let floors = parse_floors(FloorSource::Bitskins, body).unwrap();
+        assert_eq!(
+            floors,
+            vec![("AK-47 | Redline (Field-Tested)".into(), 12_35)]
+        );
+
+        let body = r#"[{"market_hash_name": "AK-47 | Redline (Field-Tested)", "price": 11.9}]"#;
+        let floors = parse_floors(FloorSource::Csmoney, body).unwrap();
+        assert_eq!(
+            floors,
+            vec![("AK-47 | Redline (Field-Tested)".into(), 11_90)]
+        );
+
+        // zero and negative prices aren't floors
+        let body = r#"[
+            {"market_hash_name": "AK-47 | Redline (Field-Tested)", "price": 0},
+            {"market_hash_name": "AWP | Asiimov (Field-Tested)", "price": -5.5},
+            {"market_hash_name": "M4A4 | Howl (Field-Tested)", "price": 0.001},
+            {"market_hash_name": "Glock-18 | Fade (Factory New)", "price": 1e-3}
+        ]"#;
+        assert_eq!(parse_floors(FloorSource::Csmoney, body).unwrap(), vec![]);
+        let body = r#"{"list": [{"name": "AK-47 | Redline (Field-Tested)", "price_min": 0}]}"#;
+        assert_eq!(parse_floors(FloorSource::Bitskins, body).unwrap(), vec![]);
+
+        assert!(parse_floors(FloorSource::Csmoney, "<html>").is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let now = Utc::now();
+        let max_age = Duration::hours(1);
+        let market_name: MarketName = "AK-47 | Redline (Field-Tested)".into();
+        let mut floors = MarketFloors::new();
+        assert_eq!(
+            floors.compare(&market_name, 12_00, now, max_age).describe(),
+            None
+        );
+
+        floors.update_source(
+            FloorSource::Bitskins,
+            vec![(market_name.clone(), 12_35)],
+            now,
+        );
+        floors.update_source(
+            FloorSource::Csmoney,
+            vec![(market_name.clone(), 11_90)],
+            now,
+        );
+        let comparison = floors.compare(&market_name, 11_50, now, max_age);
+        assert_eq!(comparison.markets, 3);
+        assert_eq!(comparison.describe().unwrap(), "cheapest across 3 markets");
+
+        let comparison = floors.compare(&market_name, 12_00, now, max_age);
+        assert_eq!(comparison.cheaper.unwrap().source, FloorSource::Csmoney);
+        assert_eq!(
+            comparison.describe().unwrap(),
+            "CS.MONEY floor is lower: $11.9"
+        );
+
+        // stale floors are ignored, the next pull replaces the source
+        let later = now + Duration::hours(2);
+        assert_eq!(
+            floors.compare(&market_name, 12_00, later, max_age).markets,
+            1
+        );
+        floors.update_source(FloorSource::Csmoney, vec![], later);
+        assert_eq!(floors.get_size(), 1);
+    }
+}
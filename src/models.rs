@@ -1,11 +1,13 @@
 use core::fmt;
 use std::fmt::{Display, Formatter};
 
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::names::{deserialize_market_name, ItemType};
 use crate::prices::PriceValue;
-use crate::types::MarketName;
+use crate::pricing::ItemCategory;
+use crate::types::{ListingId, MarketName};
 use crate::utils::{naive_datetime_from_timestamp, naive_datetime_to_timestamp};
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -23,21 +25,228 @@ impl Display for CsfloatListingState {
     }
 }
 
+// `type` of CSFloat items
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum CsfloatItemKind {
+    // guns, knives and gloves
+    Skin,
+    Sticker,
+    Agent,
+    // cases, capsules and souvenir packages
+    #[serde(alias = "case")]
+    Container,
+    // charms
+    Keychain,
+    Patch,
+    Graffiti,
+    MusicKit,
+    #[serde(other)]
+    Other,
+}
+
+impl CsfloatItemKind {
+    // For listings saved before the kind was kept, agents can't be told by the name
+    pub fn from_market_name(market_name: &str) -> CsfloatItemKind {
+        let prefixes = [
+            ("Sticker | ", CsfloatItemKind::Sticker),
+            ("Charm | ", CsfloatItemKind::Keychain),
+            ("Patch | ", CsfloatItemKind::Patch),
+            ("Sealed Graffiti | ", CsfloatItemKind::Graffiti),
+            ("Music Kit | ", CsfloatItemKind::MusicKit),
+            ("StatTrak™ Music Kit | ", CsfloatItemKind::MusicKit),
+        ];
+        if let Some((_, kind)) = prefixes.iter().find(|(x, _)| market_name.starts_with(x)) {
+            return *kind;
+        }
+        match ItemType::from_market_name(market_name) {
+            ItemType::Knife | ItemType::Gloves | ItemType::Weapon => CsfloatItemKind::Skin,
+            ItemType::Sticker => CsfloatItemKind::Sticker,
+            ItemType::Other
+                if market_name.ends_with(" Case")
+                    || market_name.contains("Capsule")
+                    || market_name.ends_with("Souvenir Package") =>
+            {
+                CsfloatItemKind::Container
+            }
+            ItemType::Other => CsfloatItemKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CsfloatStickerReference {
+    pub price: PriceValue,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CsfloatSticker {
+    pub name: String,
+    #[serde(default)]
+    pub slot: u8,
+    // None means the sticker is not scraped
+    #[serde(default)]
+    pub wear: Option<f64>,
+    #[serde(default)]
+    pub reference: Option<CsfloatStickerReference>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CsfloatListingItem {
+    #[serde(deserialize_with = "deserialize_market_name")]
     pub market_hash_name: MarketName,
     #[serde(default)]
     pub is_souvenir: bool,
     #[serde(default)]
+    pub is_stattrak: bool,
+    #[serde(default)]
     pub float_value: Option<f64>,
     #[serde(default)]
     pub phase: Option<String>,
+    #[serde(default)]
+    pub def_index: Option<u32>,
+    #[serde(default)]
+    pub paint_index: Option<u32>,
+    #[serde(default)]
+    pub paint_seed: Option<u32>,
+    #[serde(default)]
+    pub stickers: Vec<CsfloatSticker>,
+    // charms and agent patches come in the same shape as stickers, without wear
+    #[serde(default)]
+    pub keychains: Vec<CsfloatSticker>,
+    #[serde(default)]
+    pub patches: Vec<CsfloatSticker>,
+    // end of the trade hold, `tradable` is 0 for most listings regardless of it
+    #[serde(default)]
+    pub tradable_after: Option<DateTime<Utc>>,
+    // 1 consumer grade .. 6 covert, 7 contraband
+    #[serde(default)]
+    pub rarity: Option<u8>,
+    // e.g. "The Recoil Collection", None for items outside of collections
+    #[serde(default)]
+    pub collection: Option<String>,
+    // None for listings saved before it was kept, see `get_kind`
+    #[serde(rename = "type", default)]
+    pub kind: Option<CsfloatItemKind>,
+    // e.g. "Field-Tested", None for items without a wear
+    #[serde(default)]
+    pub wear_name: Option<String>,
+}
+
+impl CsfloatListingItem {
+    pub fn get_kind(&self) -> CsfloatItemKind {
+        self.kind
+            .unwrap_or_else(|| CsfloatItemKind::from_market_name(&self.market_hash_name))
+    }
+
+    pub fn get_category(&self) -> ItemCategory {
+        if self.is_souvenir {
+            ItemCategory::Souvenir
+        } else if self.is_stattrak {
+            ItemCategory::Stattrak
+        } else {
+            // listings saved before the flag was kept
+            ItemCategory::from_market_name(&self.market_hash_name)
+        }
+    }
+
+    // Whole days left of the trade hold, rounded up: minutes of a hold are a day of waiting
+    pub fn get_days_until_tradable(&self, now: DateTime<Utc>) -> u32 {
+        match self.tradable_after {
+            Some(tradable_after) if tradable_after > now => {
+                ((tradable_after - now).num_seconds() + 86399) as u32 / 86400
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CsfloatSellerStatistics {
+    #[serde(default)]
+    pub total_verified_trades: u64,
+    #[serde(default)]
+    pub total_failed_trades: u64,
+    #[serde(default)]
+    pub total_avoided_trades: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CsfloatSeller {
+    #[serde(default)]
+    pub statistics: CsfloatSellerStatistics,
+    #[serde(default)]
+    pub verification_mode: Option<String>,
+    #[serde(default)]
+    pub steam_id: Option<String>,
+}
+
+impl CsfloatSeller {
+    // failed and avoided trades among all the finished ones, None without trades
+    pub fn get_failed_trades_pct(&self) -> Option<f64> {
+        let stats = &self.statistics;
+        let failed = stats.total_failed_trades + stats.total_avoided_trades;
+        let total = stats.total_verified_trades + failed;
+        if total == 0 {
+            return None;
+        }
+        Some(failed as f64 / total as f64 * 100.0)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CsfloatListingType {
+    #[default]
+    BuyNow,
+    Auction,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CsfloatAuctionBid {
+    pub price: PriceValue,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CsfloatAuctionDetails {
+    #[serde(default)]
+    pub reserve_price: PriceValue,
+    #[serde(default)]
+    pub top_bid: Option<CsfloatAuctionBid>,
+    #[serde(default)]
+    pub min_next_bid: Option<PriceValue>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl CsfloatAuctionDetails {
+    // the lowest price the auction can be won for now
+    pub fn get_next_bid(&self) -> PriceValue {
+        self.min_next_bid
+            .or(self.top_bid.as_ref().map(|bid| bid.price))
+            .unwrap_or(self.reserve_price)
+    }
+}
+
+// CSFloat's own price estimate of the item
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CsfloatListingReference {
+    #[serde(default)]
+    pub base_price: Option<PriceValue>,
+    #[serde(default)]
+    pub predicted_price: Option<PriceValue>,
+    #[serde(default)]
+    pub float_factor: Option<f64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CsfloatListingStruct {
-    pub id: String,
+    pub id: ListingId,
+    // for auctions it's the starting price, see `auction_details`
     pub price: u64,
+    #[serde(rename = "type", default)]
+    pub listing_type: CsfloatListingType,
+    #[serde(default)]
+    pub auction_details: Option<CsfloatAuctionDetails>,
     pub state: CsfloatListingState,
     #[serde(
         deserialize_with = "naive_datetime_from_timestamp",
@@ -45,6 +254,10 @@ pub struct CsfloatListingStruct {
     )]
     pub created_at: NaiveDateTime,
     pub item: CsfloatListingItem,
+    #[serde(default)]
+    pub seller: Option<CsfloatSeller>,
+    #[serde(default)]
+    pub reference: Option<CsfloatListingReference>,
 }
 
 impl CsfloatListingStruct {
@@ -52,6 +265,18 @@ impl CsfloatListingStruct {
         self.price as PriceValue
     }
 
+    pub fn get_predicted_price(&self) -> Option<PriceValue> {
+        self.reference.as_ref()?.predicted_price
+    }
+
+    pub fn get_seller_id(&self) -> Option<String> {
+        self.seller.as_ref()?.steam_id.clone()
+    }
+
+    pub fn is_auction(&self) -> bool {
+        self.listing_type == CsfloatListingType::Auction
+    }
+
     pub fn has_any_important_changes(&self, listing_struct: &CsfloatListingStruct) -> bool {
         if self.price != listing_struct.price {
             return true;
@@ -59,6 +284,255 @@ impl CsfloatListingStruct {
         if self.state != listing_struct.state {
             return true;
         }
+        let next_bid =
+            |x: &CsfloatListingStruct| x.auction_details.as_ref().map(|x| x.get_next_bid());
+        if next_bid(self) != next_bid(listing_struct) {
+            return true;
+        }
         false
     }
 }
+
+// Frozen layout of `CsfloatListingItem` saved before `is_stattrak` was kept
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CsfloatListingItemV1 {
+    pub market_hash_name: MarketName,
+    pub is_souvenir: bool,
+    pub float_value: Option<f64>,
+    pub phase: Option<String>,
+    pub def_index: Option<u32>,
+    pub paint_index: Option<u32>,
+    pub paint_seed: Option<u32>,
+    pub stickers: Vec<CsfloatSticker>,
+    pub keychains: Vec<CsfloatSticker>,
+    pub patches: Vec<CsfloatSticker>,
+    pub tradable_after: Option<DateTime<Utc>>,
+}
+
+// Frozen layout of `CsfloatListingStruct` with `CsfloatListingItemV1`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CsfloatListingStructV1 {
+    pub id: ListingId,
+    pub price: u64,
+    pub listing_type: CsfloatListingType,
+    pub auction_details: Option<CsfloatAuctionDetails>,
+    pub state: CsfloatListingState,
+    #[serde(
+        deserialize_with = "naive_datetime_from_timestamp",
+        serialize_with = "naive_datetime_to_timestamp"
+    )]
+    pub created_at: NaiveDateTime,
+    pub item: CsfloatListingItemV1,
+    pub seller: Option<CsfloatSeller>,
+    pub reference: Option<CsfloatListingReference>,
+}
+
+impl From<CsfloatListingStructV1> for CsfloatListingStructV2 {
+    fn from(value: CsfloatListingStructV1) -> Self {
+        let item = value.item;
+        CsfloatListingStructV2 {
+            id: value.id,
+            price: value.price,
+            listing_type: value.listing_type,
+            auction_details: value.auction_details,
+            state: value.state,
+            created_at: value.created_at,
+            item: CsfloatListingItemV2 {
+                is_stattrak: item.market_hash_name.contains("StatTrak™"),
+                market_hash_name: item.market_hash_name,
+                is_souvenir: item.is_souvenir,
+                float_value: item.float_value,
+                phase: item.phase,
+                def_index: item.def_index,
+                paint_index: item.paint_index,
+                paint_seed: item.paint_seed,
+                stickers: item.stickers,
+                keychains: item.keychains,
+                patches: item.patches,
+                tradable_after: item.tradable_after,
+            },
+            seller: value.seller,
+            reference: value.reference,
+        }
+    }
+}
+
+// Frozen layout of `CsfloatListingItem` saved before the rarity and the collection were kept
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CsfloatListingItemV2 {
+    pub market_hash_name: MarketName,
+    pub is_souvenir: bool,
+    pub is_stattrak: bool,
+    pub float_value: Option<f64>,
+    pub phase: Option<String>,
+    pub def_index: Option<u32>,
+    pub paint_index: Option<u32>,
+    pub paint_seed: Option<u32>,
+    pub stickers: Vec<CsfloatSticker>,
+    pub keychains: Vec<CsfloatSticker>,
+    pub patches: Vec<CsfloatSticker>,
+    pub tradable_after: Option<DateTime<Utc>>,
+}
+
+// Frozen layout of `CsfloatListingStruct` with `CsfloatListingItemV2`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CsfloatListingStructV2 {
+    pub id: ListingId,
+    pub price: u64,
+    pub listing_type: CsfloatListingType,
+    pub auction_details: Option<CsfloatAuctionDetails>,
+    pub state: CsfloatListingState,
+    #[serde(
+        deserialize_with = "naive_datetime_from_timestamp",
+        serialize_with = "naive_datetime_to_timestamp"
+    )]
+    pub created_at: NaiveDateTime,
+    pub item: CsfloatListingItemV2,
+    pub seller: Option<CsfloatSeller>,
+    pub reference: Option<CsfloatListingReference>,
+}
+
+impl From<CsfloatListingStructV2> for CsfloatListingStructV3 {
+    fn from(value: CsfloatListingStructV2) -> Self {
+        let item = value.item;
+        CsfloatListingStructV3 {
+            id: value.id,
+            price: value.price,
+            listing_type: value.listing_type,
+            auction_details: value.auction_details,
+            state: value.state,
+            created_at: value.created_at,
+            item: CsfloatListingItemV3 {
+                market_hash_name: item.market_hash_name,
+                is_souvenir: item.is_souvenir,
+                is_stattrak: item.is_stattrak,
+                float_value: item.float_value,
+                phase: item.phase,
+                def_index: item.def_index,
+                paint_index: item.paint_index,
+                paint_seed: item.paint_seed,
+                stickers: item.stickers,
+                keychains: item.keychains,
+                patches: item.patches,
+                tradable_after: item.tradable_after,
+                rarity: None,
+                collection: None,
+            },
+            seller: value.seller,
+            reference: value.reference,
+        }
+    }
+}
+
+// Frozen layout of `CsfloatListingItem` saved before the kind and the wear name were kept
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CsfloatListingItemV3 {
+    pub market_hash_name: MarketName,
+    pub is_souvenir: bool,
+    pub is_stattrak: bool,
+    pub float_value: Option<f64>,
+    pub phase: Option<String>,
+    pub def_index: Option<u32>,
+    pub paint_index: Option<u32>,
+    pub paint_seed: Option<u32>,
+    pub stickers: Vec<CsfloatSticker>,
+    pub keychains: Vec<CsfloatSticker>,
+    pub patches: Vec<CsfloatSticker>,
+    pub tradable_after: Option<DateTime<Utc>>,
+    pub rarity: Option<u8>,
+    pub collection: Option<String>,
+}
+
+// Frozen layout of `CsfloatListingStruct` with `CsfloatListingItemV3`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CsfloatListingStructV3 {
+    pub id: ListingId,
+    pub price: u64,
+    pub listing_type: CsfloatListingType,
+    pub auction_details: Option<CsfloatAuctionDetails>,
+    pub state: CsfloatListingState,
+    #[serde(
+        deserialize_with = "naive_datetime_from_timestamp",
+        serialize_with = "naive_datetime_to_timestamp"
+    )]
+    pub created_at: NaiveDateTime,
+    pub item: CsfloatListingItemV3,
+    pub seller: Option<CsfloatSeller>,
+    pub reference: Option<CsfloatListingReference>,
+}
+
+impl From<CsfloatListingStructV3> for CsfloatListingStruct {
+    fn from(value: CsfloatListingStructV3) -> Self {
+        let item = value.item;
+        CsfloatListingStruct {
+            id: value.id,
+            price: value.price,
+            listing_type: value.listing_type,
+            auction_details: value.auction_details,
+            state: value.state,
+            created_at: value.created_at,
+            item: CsfloatListingItem {
+                market_hash_name: item.market_hash_name,
+                is_souvenir: item.is_souvenir,
+                is_stattrak: item.is_stattrak,
+                float_value: item.float_value,
+                phase: item.phase,
+                def_index: item.def_index,
+                paint_index: item.paint_index,
+                paint_seed: item.paint_seed,
+                stickers: item.stickers,
+                keychains: item.keychains,
+                patches: item.patches,
+                tradable_after: item.tradable_after,
+                rarity: item.rarity,
+                collection: item.collection,
+                kind: None,
+                wear_name: None,
+            },
+            seller: value.seller,
+            reference: value.reference,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_kind() {
+        let kind = |value: &str| serde_json::from_value::<CsfloatItemKind>(value.into()).unwrap();
+        assert_eq!(kind("agent"), CsfloatItemKind::Agent);
+        assert_eq!(kind("case"), CsfloatItemKind::Container);
+        assert_eq!(kind("music_kit"), CsfloatItemKind::MusicKit);
+        assert_eq!(kind("something_new"), CsfloatItemKind::Other);
+
+        let names = [
+            ("AK-47 | Redline (Field-Tested)", CsfloatItemKind::Skin),
+            ("★ Karambit", CsfloatItemKind::Skin),
+            ("Sticker | Crown (Foil)", CsfloatItemKind::Sticker),
+            ("Recoil Case", CsfloatItemKind::Container),
+            (
+                "Paris 2023 Legends Sticker Capsule",
+                CsfloatItemKind::Container,
+            ),
+            (
+                "Sealed Graffiti | Lambda (Blood Red)",
+                CsfloatItemKind::Graffiti,
+            ),
+            ("Charm | Die-cast AK", CsfloatItemKind::Keychain),
+            (
+                "Sir Bloody Miami Darryl | The Professionals",
+                CsfloatItemKind::Other,
+            ),
+        ];
+        for (name, expected) in names {
+            assert_eq!(
+                CsfloatItemKind::from_market_name(name),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::borrow::Cow;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::types::MarketName;
+
+// Engines are keyed by Steam's market_hash_name. Other sources spell some names differently:
+// HTML entities of the Steam page title, "StatTrak" without ™, knives without ★ or with
+// the phase in the name, so they're brought to the Steam spelling before any lookup.
+
+// weapon part of knives and gloves, which Steam prefixes with ★
+const STAR_WEAPONS: [&str; 5] = ["Knife", "Bayonet", "Karambit", "Daggers", "Gloves"];
+const STAR: &str = "★ ";
+const STATTRAK: &str = "StatTrak™ ";
+const WEARS: [&str; 5] = [
+    "(Factory New)",
+    "(Minimal Wear)",
+    "(Field-Tested)",
+    "(Well-Worn)",
+    "(Battle-Scarred)",
+];
+
+lazy_static! {
+    static ref STATTRAK_REGEX: Regex = Regex::new(r#"StatTrak(?:™|\(TM\))?\s*"#).unwrap();
+    static ref PHASE_REGEX: Regex = Regex::new(
+        r#"^(?P<weapon>.+\| (?:Gamma )?Doppler)(?: (?P<phase>Phase [1-4]|Ruby|Sapphire|Black Pearl|Emerald))?(?P<wear> \([^)]+\))?(?: - (?P<suffix>Phase [1-4]|Ruby|Sapphire|Black Pearl|Emerald))?$"#
+    )
+    .unwrap();
+}
+
+fn decode_html_entities(raw: &str) -> Cow<'_, str> {
+    if !raw.contains('&') {
+        return Cow::Borrowed(raw);
+    }
+    Cow::Owned(
+        raw.replace("&#39;", "'")
+            .replace("&quot;", "\"")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
+
+fn is_star_weapon(name: &str) -> bool {
+    let weapon = name.split(" | ").next().unwrap_or(name);
+    weapon != "Sticker"
+        && (STAR_WEAPONS.iter().any(|x| weapon.contains(x)) || weapon.ends_with("Hand Wraps"))
+}
+
+// Steam spelling of the name, the phase is kept, see `split_phase`
+pub fn canonicalize(raw: &str) -> String {
+    let decoded = decode_html_entities(raw)
+        // UTF-8 read as Latin-1 by some feeds
+        .replace("â˜…", "★")
+        .replace("â„¢", "™");
+    let mut name = decoded.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let is_stattrak = name.contains("StatTrak");
+    if is_stattrak {
+        name = STATTRAK_REGEX.replace_all(&name, "").trim().to_string();
+    }
+    let is_star = name.starts_with('★') || is_star_weapon(&name);
+    if is_star {
+        name = name.trim_start_matches('★').trim_start().to_string();
+    }
+    match (is_star, is_stattrak) {
+        (true, true) => format!("{}{}{}", STAR, STATTRAK, name),
+        (true, false) => format!("{}{}", STAR, name),
+        (false, true) => format!("{}{}", STATTRAK, name),
+        (false, false) => name,
+    }
+}
+
+// Steam names of Doppler skins don't include the phase, some sources add it
+// either after the finish or after the wear: "... | Doppler Phase 2 (Factory New)",
+// "... | Doppler (Factory New) - Phase 2"
+pub fn split_phase(name: &str) -> (String, Option<String>) {
+    let Some(captures) = PHASE_REGEX.captures(name) else {
+        return (name.to_string(), None);
+    };
+    let phase = captures
+        .name("phase")
+        .or_else(|| captures.name("suffix"))
+        .map(|x| x.as_str().to_string());
+    let wear = captures.name("wear").map_or("", |x| x.as_str());
+    (format!("{}{}", &captures["weapon"], wear), phase)
+}
+
+// Kind of the item told by its Steam name, recipients of notifications filter by it
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemType {
+    Knife,
+    Gloves,
+    // guns with a wear
+    Weapon,
+    Sticker,
+    // cases, agents, charms and the rest
+    Other,
+}
+
+impl ItemType {
+    pub fn from_market_name(market_name: &str) -> ItemType {
+        if market_name.starts_with(STAR) {
+            match market_name.contains("Gloves") || market_name.contains("Hand Wraps") {
+                true => ItemType::Gloves,
+                false => ItemType::Knife,
+            }
+        } else if market_name.starts_with("Sticker | ") {
+            ItemType::Sticker
+        } else if WEARS.iter().any(|x| market_name.ends_with(x)) {
+            ItemType::Weapon
+        } else {
+            ItemType::Other
+        }
+    }
+}
+
+// Name the engines key the item by, for names of any source
+pub fn to_market_name(raw: &str) -> MarketName {
+    let (name, _) = split_phase(&canonicalize(raw));
+    MarketName::from(name)
+}
+
+// `deserialize_with` of market names of external responses
+pub fn deserialize_market_name<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<MarketName, D::Error> {
+    let raw = <Cow<'de, str>>::deserialize(deserializer)?;
+    Ok(to_market_name(&raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize() {
+        let names = [
+            (
+                "AK-47 | Redline (Field-Tested)",
+                "AK-47 | Redline (Field-Tested)",
+            ),
+            (
+                "StatTrak AK-47 | Redline (Field-Tested)",
+                "StatTrak™ AK-47 | Redline (Field-Tested)",
+            ),
+            (
+                "  StatTrak(TM)  AK-47 |  Redline (Field-Tested) ",
+                "StatTrak™ AK-47 | Redline (Field-Tested)",
+            ),
+            (
+                "Karambit | Fade (Factory New)",
+                "★ Karambit | Fade (Factory New)",
+            ),
+            (
+                "StatTrak™ ★ Karambit | Fade (Factory New)",
+                "★ StatTrak™ Karambit | Fade (Factory New)",
+            ),
+            (
+                "â˜… Sport Gloves | Vice (Field-Tested)",
+                "★ Sport Gloves | Vice (Field-Tested)",
+            ),
+            (
+                "★ Hand Wraps | Slaughter (Minimal Wear)",
+                "★ Hand Wraps | Slaughter (Minimal Wear)",
+            ),
+            ("Sticker | Kiss&#39;n&#39;Pray", "Sticker | Kiss'n'Pray"),
+            ("Sticker | Knife (Foil)", "Sticker | Knife (Foil)"),
+        ];
+        for (raw, expected) in names {
+            assert_eq!(canonicalize(raw), expected, "{}", raw);
+            assert_eq!(canonicalize(expected), expected);
+        }
+    }
+
+    #[test]
+    fn test_item_type() {
+        let names = [
+            ("★ StatTrak™ Karambit | Fade (Factory New)", ItemType::Knife),
+            ("★ Karambit", ItemType::Knife),
+            ("★ Sport Gloves | Vice (Field-Tested)", ItemType::Gloves),
+            ("★ Hand Wraps | Slaughter (Minimal Wear)", ItemType::Gloves),
+            ("StatTrak™ AK-47 | Redline (Field-Tested)", ItemType::Weapon),
+            ("Sticker | Kiss'n'Pray", ItemType::Sticker),
+            ("Sticker | Knife (Foil)", ItemType::Sticker),
+            ("Recoil Case", ItemType::Other),
+        ];
+        for (name, expected) in names {
+            assert_eq!(ItemType::from_market_name(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_split_phase() {
+        assert_eq!(
+            split_phase("★ Karambit | Doppler Phase 2 (Factory New)"),
+            (
+                "★ Karambit | Doppler (Factory New)".to_string(),
+                Some("Phase 2".to_string())
+            )
+        );
+        assert_eq!(
+            split_phase("Glock-18 | Gamma Doppler (Factory New) - Emerald"),
+            (
+                "Glock-18 | Gamma Doppler (Factory New)".to_string(),
+                Some("Emerald".to_string())
+            )
+        );
+        assert_eq!(
+            split_phase("Glock-18 | Gamma Doppler (Factory New)"),
+            ("Glock-18 | Gamma Doppler (Factory New)".to_string(), None)
+        );
+        // not a phase of other finishes
+        assert_eq!(
+            split_phase("★ Gut Knife | Emerald Web (Factory New)").1,
+            None
+        );
+
+        assert_eq!(
+            to_market_name("Karambit | Doppler Ruby (Factory New)"),
+            "★ Karambit | Doppler (Factory New)"
+        );
+    }
+}
@@ -0,0 +1,718 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, Recipient},
+    Bot, RequestError,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{info, warn};
+
+use crate::{
+    config::{AppConfig, SharedConfig},
+    csfloat_fetcher::RateLimiter,
+    leadership::Leadership,
+    names::ItemType,
+    prices::PriceValue,
+    types::{ListingId, MarketName},
+};
+
+// Discord rejects longer messages
+const DISCORD_MAX_CONTENT_LEN: usize = 2000;
+// and Telegram these
+const TELEGRAM_MAX_MESSAGE_LEN: usize = 4096;
+
+// What a notification is about, channels are subscribed by it
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Profitable,
+    Phase,
+    RarePattern,
+    Watchlist,
+    LowFloat,
+    Sticker,
+    // results of purchases and bids
+    Autobuy,
+    Auction,
+    Alert,
+    PaperTrading,
+    Report,
+    // purchased items listed on Steam
+    SteamListing,
+    // unsold stock and ended trade holds, see `steam_inventory`
+    Inventory,
+}
+
+impl NotificationKind {
+    // Kinds of found items, merged into a digest during bursts
+    pub fn is_deal(&self) -> bool {
+        matches!(
+            self,
+            NotificationKind::Profitable
+                | NotificationKind::Phase
+                | NotificationKind::RarePattern
+                | NotificationKind::Watchlist
+                | NotificationKind::LowFloat
+                | NotificationKind::Sticker
+        )
+    }
+}
+
+// The deal a notification is about, for the filters of the recipients
+#[derive(Debug, Clone, PartialEq)]
+pub struct DealSummary {
+    // a listing found by several kinds or strategies is notified once, see `NotificationDedup`
+    pub listing_id: ListingId,
+    pub market_name: MarketName,
+    pub price: PriceValue,
+    pub profit_pct: f64,
+}
+
+// Telegram gets `markdown` (MarkdownV2) and the buttons when set, other channels `text`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Notification {
+    pub text: String,
+    pub markdown: Option<String>,
+    // (label, callback data), shown as a single row of inline buttons
+    pub buttons: Vec<(String, String)>,
+    // None for notifications which aren't about a deal, they skip the deal filters
+    pub deal: Option<DealSummary>,
+}
+
+impl From<String> for Notification {
+    fn from(text: String) -> Self {
+        Notification {
+            text,
+            ..Notification::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelType {
+    Telegram,
+    // https://discord.com/api/webhooks/...
+    Discord,
+    // POSTs {"kind": ..., "text": ...}
+    Webhook,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct NotifyChannel {
+    #[serde(rename = "type")]
+    pub channel_type: ChannelType,
+    // Telegram only, `telegram.chat_id` by default
+    #[serde(default)]
+    pub chat_id: Option<i64>,
+    // Discord and webhook only
+    #[serde(default)]
+    pub url: String,
+    // empty list means all kinds
+    #[serde(default)]
+    pub kinds: Vec<NotificationKind>,
+    // filters of deals, so a recipient gets e.g. only knives below $500
+    #[serde(default)]
+    pub min_profit_pct: Option<f64>,
+    // cents
+    #[serde(default)]
+    pub max_price: Option<PriceValue>,
+    // empty list means all types
+    #[serde(default)]
+    pub item_types: Vec<ItemType>,
+}
+
+impl NotifyChannel {
+    pub fn is_subscribed(&self, kind: NotificationKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+
+    pub fn accepts(&self, deal: &DealSummary) -> bool {
+        self.min_profit_pct.is_none_or(|x| deal.profit_pct >= x)
+            && self.max_price.is_none_or(|x| deal.price <= x)
+            && (self.item_types.is_empty()
+                || self
+                    .item_types
+                    .contains(&ItemType::from_market_name(&deal.market_name)))
+    }
+}
+
+// Channels subscribed to `kind` whose filters accept the deal. Without configured channels
+// everything goes to `telegram.chat_id`.
+pub fn route(
+    kind: NotificationKind,
+    deal: Option<&DealSummary>,
+    config: &AppConfig,
+) -> Vec<NotifyChannel> {
+    if config.notify.channels.is_empty() {
+        return vec![NotifyChannel {
+            channel_type: ChannelType::Telegram,
+            chat_id: None,
+            url: String::new(),
+            kinds: vec![],
+            min_profit_pct: None,
+            max_price: None,
+            item_types: vec![],
+        }];
+    }
+    config
+        .notify
+        .channels
+        .iter()
+        .filter(|x| x.is_subscribed(kind) && deal.is_none_or(|deal| x.accepts(deal)))
+        .cloned()
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    Telegram(RequestError),
+    Http(reqwest::Error),
+    Status(StatusCode),
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyError::Telegram(err) => write!(f, "telegram: {}", err),
+            NotifyError::Http(err) => write!(f, "http: {}", err),
+            NotifyError::Status(status) => write!(f, "unexpected status {}", status),
+        }
+    }
+}
+
+pub trait Notifier {
+    fn send(
+        &self,
+        kind: NotificationKind,
+        notification: &Notification,
+    ) -> impl Future<Output = Result<(), NotifyError>> + Send;
+}
+
+pub struct TelegramNotifier {
+    bot: Bot,
+    chat_id: ChatId,
+}
+
+impl Notifier for TelegramNotifier {
+    async fn send(
+        &self,
+        _kind: NotificationKind,
+        notification: &Notification,
+    ) -> Result<(), NotifyError> {
+        let text = notification.markdown.as_ref().unwrap_or(&notification.text);
+        let mut request = self.bot.send_message(Recipient::Id(self.chat_id), text);
+        if notification.markdown.is_some() {
+            request = request.parse_mode(ParseMode::MarkdownV2);
+        }
+        if !notification.buttons.is_empty() {
+            let buttons = notification
+                .buttons
+                .iter()
+                .map(|(label, data)| InlineKeyboardButton::callback(label, data));
+            request = request.reply_markup(InlineKeyboardMarkup::new([buttons]));
+        }
+        request.await.map_err(NotifyError::Telegram)?;
+        Ok(())
+    }
+}
+
+pub struct DiscordNotifier {
+    client: Client,
+    url: String,
+}
+
+impl Notifier for DiscordNotifier {
+    async fn send(
+        &self,
+        _kind: NotificationKind,
+        notification: &Notification,
+    ) -> Result<(), NotifyError> {
+        let content: String = notification
+            .text
+            .chars()
+            .take(DISCORD_MAX_CONTENT_LEN)
+            .collect();
+        let body = serde_json::json!({ "content": content });
+        post_json(&self.client, &self.url, &body).await
+    }
+}
+
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+}
+
+impl Notifier for WebhookNotifier {
+    async fn send(
+        &self,
+        kind: NotificationKind,
+        notification: &Notification,
+    ) -> Result<(), NotifyError> {
+        let body = serde_json::json!({ "kind": kind, "text": notification.text });
+        post_json(&self.client, &self.url, &body).await
+    }
+}
+
+async fn post_json(
+    client: &Client,
+    url: &str,
+    body: &serde_json::Value,
+) -> Result<(), NotifyError> {
+    let response = client
+        .post(url)
+        .json(body)
+        .send()
+        .await
+        .map_err(NotifyError::Http)?;
+    match response.status().is_success() {
+        true => Ok(()),
+        false => Err(NotifyError::Status(response.status())),
+    }
+}
+
+// A message waiting in the Telegram queue
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedMessage {
+    pub kind: NotificationKind,
+    pub chat_id: ChatId,
+    pub notification: Notification,
+}
+
+// Merges the deals of each chat into digests when `digest_threshold` or more of them
+// are waiting. A digest takes the place of the first deal of its chat, other messages
+// keep their order. Digests are plain text without buttons.
+pub fn batch_messages(messages: Vec<QueuedMessage>, digest_threshold: usize) -> Vec<QueuedMessage> {
+    let mut deals: HashMap<ChatId, Vec<String>> = HashMap::new();
+    for message in messages.iter().filter(|x| x.kind.is_deal()) {
+        deals
+            .entry(message.chat_id)
+            .or_default()
+            .push(message.notification.text.clone());
+    }
+    deals.retain(|_, texts| digest_threshold > 0 && texts.len() >= digest_threshold);
+    if deals.is_empty() {
+        return messages;
+    }
+
+    let mut result = vec![];
+    let mut digested: Vec<ChatId> = vec![];
+    for message in messages {
+        if !message.kind.is_deal() {
+            result.push(message);
+        } else if let Some(texts) = deals.remove(&message.chat_id) {
+            digested.push(message.chat_id);
+            result.extend(digest_texts(&texts).into_iter().map(|text| QueuedMessage {
+                kind: message.kind,
+                chat_id: message.chat_id,
+                notification: Notification::from(text),
+            }));
+        } else if !digested.contains(&message.chat_id) {
+            result.push(message);
+        }
+    }
+    result
+}
+
+// Texts joined into messages within the Telegram limit
+fn digest_texts(texts: &[String]) -> Vec<String> {
+    let mut chunks: Vec<Vec<&str>> = vec![vec![]];
+    let mut len = 0;
+    for text in texts {
+        let chunk = chunks.last_mut().unwrap();
+        // with some room for the header
+        if !chunk.is_empty() && len + text.len() + 2 > TELEGRAM_MAX_MESSAGE_LEN - 32 {
+            chunks.push(vec![]);
+            len = 0;
+        }
+        len += text.len() + 2;
+        chunks.last_mut().unwrap().push(text);
+    }
+    chunks
+        .into_iter()
+        .map(|chunk| format!("{} deals:\n\n{}", chunk.len(), chunk.join("\n\n")))
+        .collect()
+}
+
+// Sends one message, 429 answers are retried after the delay Telegram asks for
+async fn send_with_retry(
+    notifier: &TelegramNotifier,
+    message: &QueuedMessage,
+    max_retries: u32,
+) -> Result<(), NotifyError> {
+    let mut retries = 0;
+    loop {
+        match notifier.send(message.kind, &message.notification).await {
+            Err(NotifyError::Telegram(RequestError::RetryAfter(delay)))
+                if retries < max_retries =>
+            {
+                warn!("Telegram rate-limited the bot, retrying after {:?}", delay);
+                tokio::time::sleep(delay).await;
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Sends the queued Telegram messages not faster than `telegram.messages_per_sec`.
+// Messages which piled up while sending are batched by `batch_messages`.
+async fn run_telegram_queue(
+    bot: Bot,
+    mut rx: UnboundedReceiver<QueuedMessage>,
+    config: SharedConfig,
+) {
+    let mut rate_limiter = RateLimiter::new(config.load().telegram.send_interval());
+    while let Some(message) = rx.recv().await {
+        let mut pending = vec![message];
+        while let Ok(message) = rx.try_recv() {
+            pending.push(message);
+        }
+        let config = config.load();
+        let count = pending.len();
+        let batched = batch_messages(pending, config.telegram.digest_threshold);
+        if batched.len() < count {
+            info!(
+                "{} queued Telegram messages are sent as {}",
+                count,
+                batched.len()
+            );
+        }
+
+        rate_limiter.set_interval(config.telegram.send_interval());
+        for message in batched {
+            rate_limiter.wait().await;
+            let notifier = TelegramNotifier {
+                bot: bot.clone(),
+                chat_id: message.chat_id,
+            };
+            if let Err(err) =
+                send_with_retry(&notifier, &message, config.telegram.max_retries).await
+            {
+                warn!(
+                    "Failed to send {:?} notification via Telegram: {}",
+                    message.kind, err
+                );
+            }
+        }
+    }
+}
+
+// Sends notifications to the channels of `notify.channels`, which are read on each send,
+// so config reloads apply right away. Followers of the leader election send nothing.
+#[derive(Clone)]
+pub struct Notifications {
+    client: Client,
+    leadership: Leadership,
+    // Telegram messages go through the rate-limited queue
+    telegram_tx: UnboundedSender<QueuedMessage>,
+    // of deals, shared by all senders
+    dedup: Arc<Mutex<NotificationDedup>>,
+}
+
+impl Notifications {
+    pub fn new(bot: Bot, leadership: Leadership, config: SharedConfig) -> Self {
+        let (telegram_tx, telegram_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_telegram_queue(bot, telegram_rx, config));
+        Notifications {
+            client: Client::new(),
+            leadership,
+            telegram_tx,
+            dedup: Arc::new(Mutex::new(NotificationDedup::new())),
+        }
+    }
+
+    async fn send_to(
+        &self,
+        channel: &NotifyChannel,
+        kind: NotificationKind,
+        notification: &Notification,
+    ) -> Result<(), NotifyError> {
+        match channel.channel_type {
+            // queued by `notify`
+            ChannelType::Telegram => Ok(()),
+            ChannelType::Discord => {
+                let notifier = DiscordNotifier {
+                    client: self.client.clone(),
+                    url: channel.url.clone(),
+                };
+                notifier.send(kind, notification).await
+            }
+            ChannelType::Webhook => {
+                let notifier = WebhookNotifier {
+                    client: self.client.clone(),
+                    url: channel.url.clone(),
+                };
+                notifier.send(kind, notification).await
+            }
+        }
+    }
+
+    // Doesn't wait for the delivery, failures are only logged
+    pub fn notify(
+        &self,
+        kind: NotificationKind,
+        notification: impl Into<Notification>,
+        config: &AppConfig,
+    ) {
+        if !self.leadership.is_leader() {
+            return;
+        }
+        let notification = notification.into();
+        if let Some(deal) = &notification.deal {
+            let is_new = self.dedup.lock().unwrap().check(
+                &deal.listing_id,
+                deal.price,
+                Utc::now(),
+                config.notify.dedup_cooldown(),
+            );
+            if !is_new {
+                return;
+            }
+        }
+        let channels = route(kind, notification.deal.as_ref(), config);
+        if channels.is_empty() {
+            return;
+        }
+        let (telegram, channels): (Vec<_>, Vec<_>) = channels
+            .into_iter()
+            .partition(|x| x.channel_type == ChannelType::Telegram);
+        for channel in telegram {
+            let message = QueuedMessage {
+                kind,
+                chat_id: channel
+                    .chat_id
+                    .map(ChatId)
+                    .unwrap_or(config.telegram.chat_id()),
+                notification: notification.clone(),
+            };
+            // the queue lives as long as the process
+            let _ = self.telegram_tx.send(message);
+        }
+        if channels.is_empty() {
+            return;
+        }
+        let notifications = self.clone();
+        tokio::spawn(async move {
+            for channel in channels.iter() {
+                if let Err(err) = notifications.send_to(channel, kind, &notification).await {
+                    warn!(
+                        "Failed to send {:?} notification via {:?}: {}",
+                        kind, channel.channel_type, err
+                    );
+                }
+            }
+        });
+    }
+}
+
+// Skips repeated notifications of still listed items, which come on each refresh.
+// A listing is notified again after the cooldown or right away when its price drops.
+#[derive(Debug, Default)]
+pub struct NotificationDedup {
+    // price and time of the last notification
+    notified: HashMap<ListingId, (PriceValue, DateTime<Utc>)>,
+}
+
+impl NotificationDedup {
+    pub fn new() -> Self {
+        NotificationDedup::default()
+    }
+
+    // Remembers the listing when it should be notified
+    pub fn check(
+        &mut self,
+        listing_id: &ListingId,
+        price: PriceValue,
+        now: DateTime<Utc>,
+        cooldown: Option<Duration>,
+    ) -> bool {
+        let Some(cooldown) = cooldown else {
+            return true;
+        };
+        self.notified.retain(|_, (_, at)| now - *at < cooldown);
+        if let Some((last_price, _)) = self.notified.get(listing_id) {
+            if price >= *last_price {
+                return false;
+            }
+        }
+        self.notified.insert(listing_id.clone(), (price, now));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        let mut config = AppConfig::default();
+        let channels = route(NotificationKind::Phase, None, &config);
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].channel_type, ChannelType::Telegram);
+
+        config = AppConfig::from_toml(
+            r#"
+            [[notify.channels]]
+            type = "telegram"
+
+            [[notify.channels]]
+            type = "discord"
+            url = "https://discord.com/api/webhooks/1/token"
+            kinds = ["phase", "rare_pattern"]
+            "#,
+        )
+        .unwrap();
+        let channels = route(NotificationKind::Phase, None, &config);
+        assert_eq!(channels.len(), 2);
+        let channels = route(NotificationKind::Autobuy, None, &config);
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].channel_type, ChannelType::Telegram);
+
+        // a friend gets only cheap knives
+        config = AppConfig::from_toml(
+            r#"
+            [[notify.channels]]
+            type = "telegram"
+
+            [[notify.channels]]
+            type = "telegram"
+            chat_id = 42
+            kinds = ["profitable"]
+            min_profit_pct = 5.0
+            max_price = 50000
+            item_types = ["knife"]
+            "#,
+        )
+        .unwrap();
+        let mut deal = DealSummary {
+            listing_id: "1".into(),
+            market_name: "★ Karambit | Fade (Factory New)".into(),
+            price: 40_000,
+            profit_pct: 7.0,
+        };
+        let channels = route(NotificationKind::Profitable, Some(&deal), &config);
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[1].chat_id, Some(42));
+        assert_eq!(route(NotificationKind::Alert, None, &config).len(), 1);
+
+        deal.profit_pct = 4.0;
+        assert_eq!(
+            route(NotificationKind::Profitable, Some(&deal), &config).len(),
+            1
+        );
+        deal.profit_pct = 7.0;
+        deal.price = 60_000;
+        assert_eq!(
+            route(NotificationKind::Profitable, Some(&deal), &config).len(),
+            1
+        );
+        deal.price = 40_000;
+        deal.market_name = "AK-47 | Redline (Field-Tested)".into();
+        assert_eq!(
+            route(NotificationKind::Profitable, Some(&deal), &config).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_batch_messages() {
+        let message = |kind, chat_id, text: &str| QueuedMessage {
+            kind,
+            chat_id: ChatId(chat_id),
+            notification: Notification::from(text.to_string()),
+        };
+        let messages = vec![
+            message(NotificationKind::Profitable, 1, "a"),
+            message(NotificationKind::Alert, 1, "alert"),
+            message(NotificationKind::Phase, 1, "b"),
+            message(NotificationKind::Profitable, 2, "c"),
+            message(NotificationKind::Profitable, 1, "d"),
+        ];
+        assert_eq!(batch_messages(messages.clone(), 0), messages);
+        assert_eq!(batch_messages(messages.clone(), 4), messages);
+
+        let batched = batch_messages(messages, 3);
+        assert_eq!(
+            batched,
+            vec![
+                message(NotificationKind::Profitable, 1, "3 deals:\n\na\n\nb\n\nd"),
+                message(NotificationKind::Alert, 1, "alert"),
+                message(NotificationKind::Profitable, 2, "c"),
+            ]
+        );
+
+        // split by the Telegram limit
+        let texts = vec!["x".repeat(3000); 3];
+        let digests = digest_texts(&texts);
+        assert_eq!(digests.len(), 3);
+        assert!(digests[0].starts_with("1 deals:"));
+    }
+
+    #[test]
+    fn test_notification_dedup() {
+        let now = Utc::now();
+        let cooldown = Some(Duration::hours(1));
+        let listing_id = ListingId::from("1");
+        let mut dedup = NotificationDedup::new();
+
+        assert!(dedup.check(&listing_id, 10_00, now, cooldown));
+        assert!(!dedup.check(&listing_id, 10_00, now + Duration::minutes(10), cooldown));
+        assert!(!dedup.check(&listing_id, 11_00, now + Duration::minutes(20), cooldown));
+        assert!(dedup.check(&"2".into(), 10_00, now, cooldown));
+        // price improved
+        assert!(dedup.check(&listing_id, 9_50, now + Duration::minutes(30), cooldown));
+        assert!(!dedup.check(&listing_id, 9_50, now + Duration::minutes(80), cooldown));
+        assert!(dedup.check(&listing_id, 9_50, now + Duration::minutes(91), cooldown));
+
+        assert!(dedup.check(&listing_id, 9_50, now, None));
+    }
+
+    #[test]
+    fn test_deal_is_notified_once() {
+        let (telegram_tx, mut telegram_rx) = mpsc::unbounded_channel();
+        let notifications = Notifications {
+            client: Client::new(),
+            leadership: Leadership::always(),
+            telegram_tx,
+            dedup: Arc::new(Mutex::new(NotificationDedup::new())),
+        };
+        let config = AppConfig::default();
+        let deal = |listing_id: &str| Notification {
+            text: format!("deal {}", listing_id),
+            deal: Some(DealSummary {
+                listing_id: listing_id.into(),
+                market_name: "AK-47 | Redline (Field-Tested)".into(),
+                price: 10_00,
+                profit_pct: 50.0,
+            }),
+            ..Notification::default()
+        };
+
+        // e.g. a profitable listing which is on the watchlist too
+        notifications.notify(NotificationKind::Profitable, deal("1"), &config);
+        notifications.notify(NotificationKind::Watchlist, deal("1"), &config);
+        notifications.notify(NotificationKind::Profitable, deal("2"), &config);
+        // notifications which aren't about a deal are all sent
+        notifications.notify(NotificationKind::Alert, "alert".to_string(), &config);
+        notifications.notify(NotificationKind::Alert, "alert".to_string(), &config);
+
+        let mut texts = vec![];
+        while let Ok(message) = telegram_rx.try_recv() {
+            texts.push(message.notification.text);
+        }
+        assert_eq!(texts, vec!["deal 1", "deal 2", "alert", "alert"]);
+    }
+}
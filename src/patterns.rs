@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prices::PriceValue;
+
+const STAT_TRAK_PREFIX: &str = "StatTrak™ ";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternKind {
+    // Case Hardened with a mostly blue playside
+    BlueGem,
+    // Fade by its fade percentage
+    Fade,
+}
+
+// Paint seeds of a rare pattern, which Steam market names don't include,
+// so these items are sold for a premium over the Steam price of the regular item.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PatternTier {
+    pub kind: PatternKind,
+    // market name without the wear and StatTrak, e.g. "AK-47 | Case Hardened"
+    pub weapon: String,
+    // shown in the notification, e.g. "Tier 1" or "100%"
+    pub name: String,
+    pub seeds: Vec<u32>,
+    // listings priced up to that much above the Steam price are reported
+    pub max_premium_pct: f64,
+}
+
+impl PatternTier {
+    pub fn is_matching(&self, market_name: &str, paint_seed: u32) -> bool {
+        let market_name = market_name
+            .strip_prefix(STAT_TRAK_PREFIX)
+            .unwrap_or(market_name);
+        market_name.starts_with(&format!("{} (", self.weapon)) && self.seeds.contains(&paint_seed)
+    }
+
+    pub fn get_max_price(&self, steam_price: PriceValue) -> PriceValue {
+        (steam_price as f64 * (1.0 + self.max_premium_pct / 100.0)) as PriceValue
+    }
+}
+
+pub fn default_pattern_tiers() -> Vec<PatternTier> {
+    vec![PatternTier {
+        kind: PatternKind::BlueGem,
+        weapon: "AK-47 | Case Hardened".to_string(),
+        name: "Tier 1".to_string(),
+        seeds: vec![151, 179, 321, 387, 555, 661, 670, 760, 809, 828, 868, 955],
+        max_premium_pct: 100.0,
+    }]
+}
+
+// Tier of the table the listing matches, if it's priced below the tier premium.
+// `steam_price` is the Steam price (with fee) of the regular item.
+pub fn find_pattern_tier<'a>(
+    market_name: &str,
+    paint_seed: u32,
+    price: PriceValue,
+    steam_price: PriceValue,
+    pattern_tiers: &'a [PatternTier],
+) -> Option<&'a PatternTier> {
+    pattern_tiers
+        .iter()
+        .find(|x| x.is_matching(market_name, paint_seed))
+        .filter(|x| price <= x.get_max_price(steam_price))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_pattern_tier() {
+        let tiers = default_pattern_tiers();
+        let market_name = "StatTrak™ AK-47 | Case Hardened (Field-Tested)";
+
+        let found = find_pattern_tier(market_name, 661, 20_00, 10_00, &tiers).unwrap();
+        assert_eq!(found.kind, PatternKind::BlueGem);
+        assert!(find_pattern_tier(market_name, 661, 20_01, 10_00, &tiers).is_none());
+        assert!(find_pattern_tier(market_name, 662, 5_00, 10_00, &tiers).is_none());
+        assert!(find_pattern_tier(
+            "Five-SeveN | Case Hardened (Field-Tested)",
+            661,
+            5_00,
+            10_00,
+            &tiers
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::{events::ProfitableListingEvent, types::ListingId};
+
+// Listings waiting for "Confirm buy" in Telegram, see `AutobuyConfig::confirm_enabled`
+#[derive(Debug, Default)]
+pub struct PendingPurchases {
+    // listing and the confirmation deadline
+    pending: HashMap<ListingId, (ProfitableListingEvent, DateTime<Utc>)>,
+}
+
+impl PendingPurchases {
+    pub fn new() -> Self {
+        PendingPurchases::default()
+    }
+
+    pub fn add(&mut self, event: &ProfitableListingEvent, expires_at: DateTime<Utc>) {
+        self.pending
+            .insert(event.listing_id.clone(), (event.clone(), expires_at));
+    }
+
+    // Confirmed listing, None when it has expired or wasn't pending
+    pub fn take(
+        &mut self,
+        listing_id: &ListingId,
+        now: DateTime<Utc>,
+    ) -> Option<ProfitableListingEvent> {
+        self.pending.retain(|_, (_, expires_at)| *expires_at > now);
+        self.pending.remove(listing_id).map(|(event, _)| event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures::get_profitable_listing_event;
+    use chrono::Duration;
+
+    #[test]
+    fn test_pending_purchases() {
+        let now = Utc::now();
+        let event = get_profitable_listing_event();
+        let mut pending = PendingPurchases::new();
+        pending.add(&event, now + Duration::minutes(2));
+
+        assert_eq!(pending.take(&"2".into(), now), None);
+        assert_eq!(pending.take(&"1".into(), now), Some(event.clone()));
+        assert_eq!(pending.take(&"1".into(), now), None);
+
+        pending.add(&event, now + Duration::minutes(2));
+        assert_eq!(pending.take(&"1".into(), now + Duration::minutes(3)), None);
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{consts::PHASE_4, prices::PriceValue, pricing::Exterior};
+
+// Doppler-like skins are priced by phase, which Steam market names don't include,
+// so they're bought by a fixed table instead of the Steam price.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PhasePrice {
+    // market name without the wear, e.g. "★ Karambit | Doppler"
+    pub weapon: String,
+    // as in CSFloat listings, e.g. "Phase 2", "Ruby", "Emerald"
+    pub phase: String,
+    pub wear: Exterior,
+    pub max_buy_price: PriceValue,
+    // None when the expected sell price is unknown
+    #[serde(default)]
+    pub target_sell_price: Option<PriceValue>,
+}
+
+impl PhasePrice {
+    pub fn is_matching(&self, market_name: &str, phase: &str) -> bool {
+        self.phase == phase
+            && market_name.starts_with(&format!("{} (", self.weapon))
+            && Exterior::from_market_name(market_name) == Some(self.wear)
+    }
+}
+
+pub fn default_phase_prices() -> Vec<PhasePrice> {
+    let glock = |wear: Exterior, max_buy_price: PriceValue| PhasePrice {
+        weapon: "Glock-18 | Gamma Doppler".to_string(),
+        phase: PHASE_4.to_string(),
+        wear,
+        max_buy_price,
+        target_sell_price: None,
+    };
+    vec![
+        glock(Exterior::FactoryNew, 60_00),
+        glock(Exterior::MinimalWear, 45_00),
+        glock(Exterior::FieldTested, 35_00),
+    ]
+}
+
+// Entry of the table the listing can be bought by
+pub fn find_phase_price<'a>(
+    market_name: &str,
+    phase: &str,
+    price: PriceValue,
+    phase_prices: &'a [PhasePrice],
+) -> Option<&'a PhasePrice> {
+    phase_prices
+        .iter()
+        .find(|x| x.is_matching(market_name, phase))
+        .filter(|x| price <= x.max_buy_price)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_phase_price() {
+        let prices = default_phase_prices();
+        let market_name = "Glock-18 | Gamma Doppler (Minimal Wear)";
+
+        let found = find_phase_price(market_name, "Phase 4", 45_00, &prices).unwrap();
+        assert_eq!(found.wear, Exterior::MinimalWear);
+        assert!(find_phase_price(market_name, "Phase 4", 45_01, &prices).is_none());
+        assert!(find_phase_price(market_name, "Phase 1", 10_00, &prices).is_none());
+        assert!(find_phase_price(
+            "StatTrak™ Glock-18 | Gamma Doppler (Minimal Wear)",
+            "Phase 4",
+            10_00,
+            &prices
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{CsfloatItemKind, CsfloatListingStruct},
+    names::ItemType,
+    prices::PriceValue,
+    pricing::ItemCategory,
+};
+
+// Rules of `prefilter.rules` are checked in their order, the first matching one decides.
+// Listings matching none get `prefilter.default_action`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PrefilterAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+// Every set condition has to match, empty sets and None bounds match anything.
+// A listing without the rarity, collection or float doesn't match the rules requiring it.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct PrefilterRule {
+    // the rejections are counted by it
+    pub name: String,
+    pub action: PrefilterAction,
+    pub categories: HashSet<ItemCategory>,
+    pub item_types: HashSet<ItemType>,
+    // CSFloat `type` of the item, e.g. "agent" or "container"
+    pub kinds: HashSet<CsfloatItemKind>,
+    // e.g. "AK-47", "Karambit", see `get_weapon`
+    pub weapons: HashSet<String>,
+    pub rarities: HashSet<u8>,
+    pub collections: HashSet<String>,
+    pub min_float: Option<f64>,
+    pub max_float: Option<f64>,
+    pub min_price: Option<PriceValue>,
+    pub max_price: Option<PriceValue>,
+}
+
+// What the rules look at, taken from the listing once for all of them
+#[derive(Debug)]
+pub struct ListingTraits<'a> {
+    pub category: ItemCategory,
+    pub item_type: ItemType,
+    pub kind: CsfloatItemKind,
+    pub weapon: &'a str,
+    pub rarity: Option<u8>,
+    pub collection: Option<&'a str>,
+    pub float: Option<f64>,
+    pub price: PriceValue,
+}
+
+impl<'a> ListingTraits<'a> {
+    pub fn new(listing: &'a CsfloatListingStruct) -> Self {
+        let item = &listing.item;
+        ListingTraits {
+            category: item.get_category(),
+            item_type: ItemType::from_market_name(&item.market_hash_name),
+            kind: item.get_kind(),
+            weapon: get_weapon(&item.market_hash_name),
+            rarity: item.rarity,
+            collection: item.collection.as_deref(),
+            float: item.float_value,
+            price: listing.get_price_value(),
+        }
+    }
+}
+
+// The weapon part of the name without ★, StatTrak™ and Souvenir:
+// "★ StatTrak™ Karambit | Fade (Factory New)" -> "Karambit"
+pub fn get_weapon(market_name: &str) -> &str {
+    let name = market_name
+        .trim_start_matches("★ ")
+        .trim_start_matches("StatTrak™ ")
+        .trim_start_matches("Souvenir ");
+    name.split(" | ").next().unwrap_or(name)
+}
+
+fn matches_set<T: Eq + std::hash::Hash>(set: &HashSet<T>, value: Option<&T>) -> bool {
+    set.is_empty() || value.is_some_and(|x| set.contains(x))
+}
+
+fn matches_range<T: PartialOrd>(min: Option<T>, max: Option<T>, value: Option<T>) -> bool {
+    if min.is_none() && max.is_none() {
+        return true;
+    }
+    let Some(value) = value else {
+        return false;
+    };
+    min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+}
+
+impl PrefilterRule {
+    pub fn matches(&self, traits: &ListingTraits) -> bool {
+        matches_set(&self.categories, Some(&traits.category))
+            && matches_set(&self.item_types, Some(&traits.item_type))
+            && matches_set(&self.kinds, Some(&traits.kind))
+            && (self.weapons.is_empty() || self.weapons.contains(traits.weapon))
+            && matches_set(&self.rarities, traits.rarity.as_ref())
+            && (self.collections.is_empty()
+                || traits
+                    .collection
+                    .is_some_and(|x| self.collections.contains(x)))
+            && matches_range(self.min_float, self.max_float, traits.float)
+            && matches_range(self.min_price, self.max_price, Some(traits.price))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct PrefilterConfig {
+    pub default_action: PrefilterAction,
+    pub rules: Vec<PrefilterRule>,
+}
+
+// name of the rejections by `prefilter.default_action`
+pub const DEFAULT_RULE: &str = "default";
+
+impl PrefilterConfig {
+    // Err is the name of the rule which denied the listing
+    pub fn check(&self, listing: &CsfloatListingStruct) -> Result<(), &str> {
+        if self.rules.is_empty() && self.default_action == PrefilterAction::Allow {
+            return Ok(());
+        }
+        let traits = ListingTraits::new(listing);
+        let (action, name) = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(&traits))
+            .map_or((self.default_action, DEFAULT_RULE), |rule| {
+                (rule.action, rule.name.as_str())
+            });
+        match action {
+            PrefilterAction::Allow => Ok(()),
+            PrefilterAction::Deny => Err(name),
+        }
+    }
+}
+
+// Rejected listings by the name of the check since the last `take`,
+// moved to `StatsCounter::PrefilterRejected` by the stats sampler
+#[derive(Debug, Default)]
+pub struct PrefilterRejections {
+    counts: HashMap<String, u64>,
+}
+
+impl PrefilterRejections {
+    pub fn new() -> Self {
+        PrefilterRejections::default()
+    }
+
+    pub fn add(&mut self, name: &str) {
+        match self.counts.get_mut(name) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts.insert(name.to_string(), 1);
+            }
+        }
+    }
+
+    pub fn take(&mut self) -> HashMap<String, u64> {
+        std::mem::take(&mut self.counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures::get_listing_json;
+
+    fn listing(
+        market_name: &str,
+        price: PriceValue,
+        extra: serde_json::Value,
+    ) -> CsfloatListingStruct {
+        let mut listing = get_listing_json("1", market_name, price);
+        listing["item"]
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(listing).unwrap()
+    }
+
+    #[test]
+    fn test_get_weapon() {
+        assert_eq!(
+            get_weapon("★ StatTrak™ Karambit | Fade (Factory New)"),
+            "Karambit"
+        );
+        assert_eq!(
+            get_weapon("Souvenir AWP | Dragon Lore (Field-Tested)"),
+            "AWP"
+        );
+        assert_eq!(get_weapon("Recoil Case"), "Recoil Case");
+    }
+
+    #[test]
+    fn test_prefilter_rules() {
+        let config: PrefilterConfig = toml::from_str(
+            r#"
+            [[rules]]
+            name = "cheap covert"
+            action = "allow"
+            rarities = [6]
+            max_price = 5000
+
+            [[rules]]
+            name = "no agents"
+            action = "deny"
+            kinds = ["agent", "graffiti"]
+
+            [[rules]]
+            name = "no souvenirs"
+            action = "deny"
+            categories = ["souvenir"]
+
+            [[rules]]
+            name = "worn knives"
+            action = "deny"
+            item_types = ["knife"]
+            min_float = 0.38
+
+            [[rules]]
+            name = "recoil"
+            action = "deny"
+            collections = ["The Recoil Collection"]
+            weapons = ["AK-47", "USP-S"]
+            "#,
+        )
+        .unwrap();
+
+        let redline = "AK-47 | Redline (Field-Tested)";
+        assert_eq!(
+            config.check(&listing(redline, 10_00, serde_json::json!({}))),
+            Ok(())
+        );
+        assert_eq!(
+            config.check(&listing(
+                "Souvenir AWP | Safari Mesh (Field-Tested)",
+                1_00,
+                serde_json::json!({"is_souvenir": true})
+            )),
+            Err("no souvenirs")
+        );
+        // the first matching rule wins
+        assert_eq!(
+            config.check(&listing(
+                "Souvenir AWP | Dragon Lore (Field-Tested)",
+                40_00,
+                serde_json::json!({"is_souvenir": true, "rarity": 6})
+            )),
+            Ok(())
+        );
+
+        assert_eq!(
+            config.check(&listing(
+                "Sir Bloody Miami Darryl | The Professionals",
+                5_00,
+                serde_json::json!({"type": "agent"})
+            )),
+            Err("no agents")
+        );
+        assert_eq!(
+            config.check(&listing(
+                "Sealed Graffiti | Lambda (Blood Red)",
+                5,
+                serde_json::json!({})
+            )),
+            Err("no agents")
+        );
+
+        let knife = "★ Karambit | Fade (Factory New)";
+        assert_eq!(
+            config.check(&listing(
+                knife,
+                100_000,
+                serde_json::json!({"float_value": 0.5})
+            )),
+            Err("worn knives")
+        );
+        assert_eq!(
+            config.check(&listing(
+                knife,
+                100_000,
+                serde_json::json!({"float_value": 0.01})
+            )),
+            Ok(())
+        );
+        // unknown float doesn't match a float range
+        assert_eq!(
+            config.check(&listing(knife, 100_000, serde_json::json!({}))),
+            Ok(())
+        );
+
+        let recoil = serde_json::json!({"collection": "The Recoil Collection"});
+        assert_eq!(
+            config.check(&listing(
+                "AK-47 | Ice Coaled (Field-Tested)",
+                5_00,
+                recoil.clone()
+            )),
+            Err("recoil")
+        );
+        assert_eq!(
+            config.check(&listing(
+                "AWP | Chromatic Aberration (Field-Tested)",
+                5_00,
+                recoil
+            )),
+            Ok(())
+        );
+
+        let deny_all = PrefilterConfig {
+            default_action: PrefilterAction::Deny,
+            rules: vec![],
+        };
+        assert_eq!(
+            deny_all.check(&listing(redline, 10_00, serde_json::json!({}))),
+            Err(DEFAULT_RULE)
+        );
+
+        let mut rejections = PrefilterRejections::new();
+        rejections.add("recoil");
+        rejections.add("recoil");
+        rejections.add(DEFAULT_RULE);
+        let counts = rejections.take();
+        assert_eq!(counts["recoil"], 2);
+        assert_eq!(counts[DEFAULT_RULE], 1);
+        assert!(rejections.take().is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres, Row};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{error, info};
+
+use crate::{
+    config::SharedConfig,
+    prices::{PriceValue, PriceValueTrait},
+    shutdown::ShutdownSignal,
+    storages::{CsfloatEngine, SteamEngine},
+    types::{ListingId, MarketName},
+};
+
+// changes waiting for the writer, newer ones are dropped when it falls behind
+const PENDING_LIMIT: usize = 50_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceKind {
+    // price of a CSFloat listing when it's seen first and when it changes
+    Listing,
+    // the cheapest buy now listing of the market name on CSFloat
+    Floor,
+    // `consts::DESIRED_PERCENTILE` of the Steam analysis
+    Steam,
+}
+
+impl PriceKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PriceKind::Listing => "listing",
+            PriceKind::Floor => "floor",
+            PriceKind::Steam => "steam",
+        }
+    }
+
+    fn from_str(kind: &str) -> Option<PriceKind> {
+        match kind {
+            "listing" => Some(PriceKind::Listing),
+            "floor" => Some(PriceKind::Floor),
+            "steam" => Some(PriceKind::Steam),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricePoint {
+    pub created_at: DateTime<Utc>,
+    pub market_name: MarketName,
+    // only of `PriceKind::Listing`
+    pub listing_id: Option<ListingId>,
+    pub kind: PriceKind,
+    pub price: PriceValue,
+}
+
+// Price changes observed by an engine since the last write, see `spawn_price_history_writer`
+#[derive(Debug, Default)]
+pub struct PriceChanges {
+    points: Vec<PricePoint>,
+}
+
+impl PriceChanges {
+    pub fn new() -> Self {
+        PriceChanges::default()
+    }
+
+    pub fn push(&mut self, point: PricePoint) {
+        if self.points.len() < PENDING_LIMIT {
+            self.points.push(point);
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<PricePoint> {
+        std::mem::take(&mut self.points)
+    }
+}
+
+pub async fn record_points(db: &Pool<Postgres>, points: &[PricePoint]) -> Result<(), sqlx::Error> {
+    if points.is_empty() {
+        return Ok(());
+    }
+    let created_at: Vec<DateTime<Utc>> = points.iter().map(|x| x.created_at).collect();
+    let market_names: Vec<&str> = points.iter().map(|x| x.market_name.as_ref()).collect();
+    let listing_ids: Vec<Option<&str>> = points
+        .iter()
+        .map(|x| x.listing_id.as_ref().map(|id| id.as_ref()))
+        .collect();
+    let kinds: Vec<&str> = points.iter().map(|x| x.kind.as_str()).collect();
+    let prices: Vec<i64> = points.iter().map(|x| x.price as i64).collect();
+    sqlx::query(
+        "INSERT INTO price_history (created_at, market_name, listing_id, kind, price)
+        SELECT * FROM UNNEST($1::timestamptz[], $2::text[], $3::text[], $4::text[], $5::int8[])",
+    )
+    .bind(&created_at)
+    .bind(&market_names)
+    .bind(&listing_ids)
+    .bind(&kinds)
+    .bind(&prices)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_points_before(
+    db: &Pool<Postgres>,
+    before: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM price_history WHERE created_at < $1")
+        .bind(before)
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// Points of the market name since `since`, oldest first
+pub async fn load_points(
+    db: &Pool<Postgres>,
+    market_name: &str,
+    since: DateTime<Utc>,
+) -> Result<Vec<PricePoint>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT created_at, market_name, listing_id, kind, price FROM price_history
+        WHERE market_name = $1 AND created_at >= $2
+        ORDER BY created_at",
+    )
+    .bind(market_name)
+    .bind(since)
+    .fetch_all(db)
+    .await?;
+
+    let points = rows
+        .into_iter()
+        .filter_map(|row| {
+            let kind: &str = row.get("kind");
+            Some(PricePoint {
+                created_at: row.get("created_at"),
+                market_name: MarketName::from(row.get::<String, _>("market_name")),
+                listing_id: row
+                    .get::<Option<String>, _>("listing_id")
+                    .map(ListingId::from),
+                kind: PriceKind::from_str(kind)?,
+                price: row.get::<i64, _>("price") as PriceValue,
+            })
+        })
+        .collect();
+    Ok(points)
+}
+
+// One line per kind: "CSFloat floor: $10.5 .. $12 (last $11)"
+pub fn summarize(market_name: &str, points: &[PricePoint], days: i64) -> String {
+    let mut lines = vec![format!("{}, {} days", market_name, days)];
+    for (kind, title) in [
+        (PriceKind::Floor, "CSFloat floor"),
+        (PriceKind::Listing, "CSFloat listings"),
+        (PriceKind::Steam, "Steam"),
+    ] {
+        let prices: Vec<PriceValue> = points
+            .iter()
+            .filter(|x| x.kind == kind)
+            .map(|x| x.price)
+            .collect();
+        let (Some(min), Some(max), Some(last)) =
+            (prices.iter().min(), prices.iter().max(), prices.last())
+        else {
+            continue;
+        };
+        lines.push(format!(
+            "{}: ${} .. ${} (last ${})",
+            title,
+            min.to_usd(),
+            max.to_usd(),
+            last.to_usd()
+        ));
+    }
+    if lines.len() == 1 {
+        lines.push("No prices recorded".to_string());
+    }
+    lines.join("\n")
+}
+
+// Writes the price changes of the engines every `price_history.flush_interval_secs`
+// and once more on shutdown. With `price_history.enabled` off they're dropped.
+pub fn spawn_price_history_writer(
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    pool: Pool<Postgres>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let flush_interval = config.load().price_history.flush_interval();
+            let is_stopping = tokio::select! {
+                _ = tokio::time::sleep(flush_interval) => false,
+                _ = shutdown.changed() => true,
+            };
+
+            let mut points = csfloat_engine.lock().await.price_changes.take();
+            points.extend(steam_engine.lock().await.price_changes.take());
+            let current_config = config.load();
+            if current_config.price_history.enabled {
+                if let Err(err) = record_points(&pool, &points).await {
+                    error!("Failed to write {} price points: {:?}", points.len(), err);
+                }
+            }
+            if is_stopping {
+                break;
+            }
+
+            let retention_days = current_config.price_history.retention_days;
+            if current_config.price_history.enabled && retention_days > 0 {
+                let before = Utc::now() - Duration::days(retention_days);
+                match delete_points_before(&pool, before).await {
+                    Ok(0) => {}
+                    Ok(deleted) => info!("Deleted {} price points older than {}", deleted, before),
+                    Err(err) => error!("Failed to delete old price points: {:?}", err),
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let now = Utc::now();
+        let point = |kind, price| PricePoint {
+            created_at: now,
+            market_name: "AK-47 | Redline (Field-Tested)".into(),
+            listing_id: None,
+            kind,
+            price,
+        };
+        assert_eq!(
+            summarize("AK-47 | Redline (Field-Tested)", &[], 30),
+            "AK-47 | Redline (Field-Tested), 30 days\nNo prices recorded"
+        );
+
+        let points = [
+            point(PriceKind::Floor, 12_00),
+            point(PriceKind::Steam, 15_00),
+            point(PriceKind::Floor, 10_50),
+            point(PriceKind::Floor, 11_00),
+        ];
+        assert_eq!(
+            summarize("AK-47 | Redline (Field-Tested)", &points, 30),
+            "AK-47 | Redline (Field-Tested), 30 days\n\
+            CSFloat floor: $10.5 .. $12 (last $11)\n\
+            Steam: $15 .. $15 (last $15)"
+        );
+    }
+}
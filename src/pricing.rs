@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::PricingConfig,
+    prices::{PriceValue, PriceValueTrait},
+};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum Exterior {
+    #[serde(rename = "Factory New")]
+    FactoryNew,
+    #[serde(rename = "Minimal Wear")]
+    MinimalWear,
+    #[serde(rename = "Field-Tested")]
+    FieldTested,
+    #[serde(rename = "Well-Worn")]
+    WellWorn,
+    #[serde(rename = "Battle-Scarred")]
+    BattleScarred,
+}
+
+impl Exterior {
+    pub fn from_market_name(market_name: &str) -> Option<Exterior> {
+        const EXTERIORS: [(&str, Exterior); 5] = [
+            ("(Factory New)", Exterior::FactoryNew),
+            ("(Minimal Wear)", Exterior::MinimalWear),
+            ("(Field-Tested)", Exterior::FieldTested),
+            ("(Well-Worn)", Exterior::WellWorn),
+            ("(Battle-Scarred)", Exterior::BattleScarred),
+        ];
+
+        EXTERIORS
+            .iter()
+            .find(|(suffix, _)| market_name.ends_with(suffix))
+            .map(|(_, wear)| *wear)
+    }
+}
+
+// StatTrak and souvenir items are priced in bands of their own, see `CategoryPriceBand`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemCategory {
+    Normal,
+    Stattrak,
+    Souvenir,
+}
+
+impl ItemCategory {
+    // for venues without the flags of CSFloat
+    pub fn from_market_name(market_name: &str) -> ItemCategory {
+        if market_name.starts_with("Souvenir ") {
+            ItemCategory::Souvenir
+        } else if market_name.contains("StatTrak™") {
+            ItemCategory::Stattrak
+        } else {
+            ItemCategory::Normal
+        }
+    }
+}
+
+// Steam price of "X (Factory New)" is the price of an average FN float,
+// so the premium is applied only to floats below a configured breakpoint.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FloatBreakpoint {
+    pub wear: Exterior,
+    pub max_float: f64,
+    pub multiplier: f64,
+}
+
+pub fn float_multiplier(market_name: &str, float: Option<f64>, config: &PricingConfig) -> f64 {
+    let (Some(float), Some(wear)) = (float, Exterior::from_market_name(market_name)) else {
+        return 1.0;
+    };
+
+    // breakpoints are checked from the lowest float, so the strongest premium wins
+    config
+        .float_premiums
+        .iter()
+        .filter(|breakpoint| breakpoint.wear == wear && float < breakpoint.max_float)
+        .min_by(|a, b| a.max_float.total_cmp(&b.max_float))
+        .map(|breakpoint| breakpoint.multiplier)
+        .unwrap_or(1.0)
+}
+
+#[inline]
+pub fn apply_float_premium(
+    price: PriceValue,
+    market_name: &str,
+    float: Option<f64>,
+    config: &PricingConfig,
+) -> PriceValue {
+    let multiplier = float_multiplier(market_name, float, config);
+    if multiplier == 1.0 {
+        return price;
+    }
+    price.multiply_by_percent(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_category() {
+        assert_eq!(
+            ItemCategory::from_market_name("AK-47 | Redline (Field-Tested)"),
+            ItemCategory::Normal
+        );
+        assert_eq!(
+            ItemCategory::from_market_name("★ StatTrak™ Karambit | Fade (Factory New)"),
+            ItemCategory::Stattrak
+        );
+        assert_eq!(
+            ItemCategory::from_market_name("Souvenir AWP | Dragon Lore (Factory New)"),
+            ItemCategory::Souvenir
+        );
+    }
+
+    fn get_config() -> PricingConfig {
+        PricingConfig {
+            float_premiums: vec![
+                FloatBreakpoint {
+                    wear: Exterior::FactoryNew,
+                    max_float: 0.03,
+                    multiplier: 1.05,
+                },
+                FloatBreakpoint {
+                    wear: Exterior::FactoryNew,
+                    max_float: 0.01,
+                    multiplier: 1.2,
+                },
+                FloatBreakpoint {
+                    wear: Exterior::MinimalWear,
+                    max_float: 0.08,
+                    multiplier: 1.1,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_exterior_from_market_name() {
+        assert_eq!(
+            Exterior::from_market_name("AK-47 | Redline (Field-Tested)"),
+            Some(Exterior::FieldTested)
+        );
+        assert_eq!(Exterior::from_market_name("Kilowatt Case"), None);
+    }
+
+    #[test]
+    fn test_lowest_breakpoint_wins() {
+        let config = get_config();
+        let name = "AWP | Asiimov (Factory New)";
+        assert_eq!(float_multiplier(name, Some(0.003), &config), 1.2);
+        assert_eq!(float_multiplier(name, Some(0.02), &config), 1.05);
+        assert_eq!(float_multiplier(name, Some(0.06), &config), 1.0);
+    }
+
+    #[test]
+    fn test_apply_float_premium() {
+        let config = get_config();
+        let name = "Glock-18 | Wasteland Rebel (Minimal Wear)";
+        assert_eq!(apply_float_premium(1000, name, Some(0.071), &config), 1100);
+        assert_eq!(apply_float_premium(1000, name, Some(0.13), &config), 1000);
+        assert_eq!(apply_float_premium(1000, name, None, &config), 1000);
+    }
+}
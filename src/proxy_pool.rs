@@ -0,0 +1,215 @@
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use reqwest::{Client, Proxy};
+use tokio::{
+    sync::{mpsc::Sender, Mutex},
+    time::Instant,
+};
+use tracing::warn;
+
+use crate::{
+    config::ProxyPoolConfig,
+    consts::{CSFLOAT_RATE_LIMITED_BACKOFF, CSFLOAT_RATE_LIMITED_MAX_BACKOFF},
+    csfloat::backoff_delay,
+    csfloat_client::CsfloatClient,
+    events::SecEvent,
+    stats::Stats,
+};
+
+pub type ProxyIdx = usize;
+
+struct ProxyEntry {
+    // None is a direct connection
+    url: Option<String>,
+    client: CsfloatClient,
+    next_call: Instant,
+    // failed requests in a row, the proxy is disabled after `max_failures`
+    failures: u32,
+    // 429 responses in a row
+    rate_limited_streak: u32,
+    requests: u64,
+}
+
+// Each proxy is a separate IP with its own csfloat.com quota, so the total refresh rate
+// grows with the number of healthy proxies. The least recently used proxy is picked.
+pub struct ProxyPool {
+    entries: Vec<ProxyEntry>,
+    max_failures: u32,
+    disable_for: Duration,
+}
+
+impl ProxyPool {
+    pub fn new(
+        config: &ProxyPoolConfig,
+        stats: Arc<Mutex<Stats>>,
+        alert_tx: Sender<SecEvent>,
+    ) -> ProxyPool {
+        let mut urls: Vec<Option<String>> = config.proxies.iter().cloned().map(Some).collect();
+        if config.use_direct || urls.is_empty() {
+            urls.push(None);
+        }
+
+        let now = Instant::now();
+        let entries = urls
+            .into_iter()
+            .map(|url| {
+                let mut builder = Client::builder().timeout(Duration::from_secs(10));
+                if let Some(url) = &url {
+                    builder = builder.proxy(Proxy::all(url).expect("Failed to parse proxy url"));
+                }
+                let client = builder
+                    .build()
+                    .expect("Failed to build client for proxy pool");
+                ProxyEntry {
+                    url,
+                    client: CsfloatClient::new(client, stats.clone(), alert_tx.clone()),
+                    next_call: now,
+                    failures: 0,
+                    rate_limited_streak: 0,
+                    requests: 0,
+                }
+            })
+            .collect();
+
+        ProxyPool {
+            entries,
+            max_failures: config.max_failures,
+            disable_for: config.disable_for(),
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.entries.len()
+    }
+
+    // proxy that is allowed to make a request the earliest and when
+    pub fn next_ready(&self) -> (ProxyIdx, Instant) {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| (idx, entry.next_call))
+            .min_by_key(|(_, next_call)| *next_call)
+            .expect("Proxy pool is never empty")
+    }
+
+    // `interval` is between two requests of the same proxy
+    pub fn acquire(&mut self, idx: ProxyIdx, interval: Duration) -> &CsfloatClient {
+        self.acquire_at(idx, interval, Instant::now())
+    }
+
+    fn acquire_at(&mut self, idx: ProxyIdx, interval: Duration, now: Instant) -> &CsfloatClient {
+        let entry = &mut self.entries[idx];
+        entry.requests += 1;
+        entry.next_call = now + entry.client.throttled_interval(interval);
+        &entry.client
+    }
+
+    pub fn report_success(&mut self, idx: ProxyIdx) {
+        let entry = &mut self.entries[idx];
+        entry.failures = 0;
+        entry.rate_limited_streak = 0;
+    }
+
+    pub fn report_failure(&mut self, idx: ProxyIdx) {
+        self.report_failure_at(idx, Instant::now());
+    }
+
+    fn report_failure_at(&mut self, idx: ProxyIdx, now: Instant) {
+        let entry = &mut self.entries[idx];
+        entry.failures += 1;
+        if entry.failures >= self.max_failures {
+            warn!(
+                "Proxy {:?} failed {} times in a row, disabling for {:?}",
+                entry.url, entry.failures, self.disable_for
+            );
+            entry.failures = 0;
+            entry.next_call = entry.next_call.max(now + self.disable_for);
+        }
+    }
+
+    pub fn report_rate_limited(&mut self, idx: ProxyIdx) -> Duration {
+        self.report_rate_limited_at(idx, Instant::now())
+    }
+
+    fn report_rate_limited_at(&mut self, idx: ProxyIdx, now: Instant) -> Duration {
+        let entry = &mut self.entries[idx];
+        entry.rate_limited_streak += 1;
+        let backoff = backoff_delay(
+            CSFLOAT_RATE_LIMITED_BACKOFF,
+            entry.rate_limited_streak,
+            CSFLOAT_RATE_LIMITED_MAX_BACKOFF,
+        );
+        entry.next_call = entry.next_call.max(now + backoff);
+        backoff
+    }
+
+    pub fn summary(&self) -> String {
+        let mut buffer = String::new();
+        for entry in self.entries.iter() {
+            let remaining = entry.client.get_rate_limit().remaining;
+            writeln!(
+                buffer,
+                "Proxy {:?}: {} requests | remaining quota {:?} | failures {} | 429 streak {}",
+                entry.url.as_deref().unwrap_or("direct"),
+                entry.requests,
+                remaining,
+                entry.failures,
+                entry.rate_limited_streak
+            )
+            .unwrap();
+        }
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    fn get_pool() -> ProxyPool {
+        let config = ProxyPoolConfig {
+            proxies: vec![
+                "http://127.0.0.1:8001".to_string(),
+                "http://127.0.0.1:8002".to_string(),
+            ],
+            use_direct: false,
+            max_failures: 2,
+            disable_secs: 600,
+        };
+        let (tx, _rx) = mpsc::channel(1);
+        ProxyPool::new(&config, Arc::new(Mutex::new(Stats::new())), tx)
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_proxy_is_picked() {
+        let mut pool = get_pool();
+        let now = Instant::now();
+        let interval = Duration::from_secs(3);
+        assert_eq!(pool.get_size(), 2);
+
+        let (first, _) = pool.next_ready();
+        pool.acquire_at(first, interval, now);
+        let (second, ready_at) = pool.next_ready();
+        assert_ne!(first, second);
+        assert!(ready_at <= now);
+
+        pool.acquire_at(second, interval, now + Duration::from_secs(1));
+        assert_eq!(pool.next_ready(), (first, now + interval));
+    }
+
+    #[tokio::test]
+    async fn test_failing_proxy_is_disabled() {
+        let mut pool = get_pool();
+        let now = Instant::now();
+
+        pool.report_failure_at(0, now);
+        assert!(pool.next_ready().1 <= now);
+        pool.report_failure_at(0, now);
+        assert_eq!(pool.next_ready().0, 1);
+
+        let backoff = pool.report_rate_limited_at(1, now);
+        assert_eq!(backoff, CSFLOAT_RATE_LIMITED_BACKOFF);
+        assert_eq!(pool.next_ready(), (1, now + backoff));
+    }
+}
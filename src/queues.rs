@@ -0,0 +1,194 @@
+use std::fmt::Debug;
+
+use serde::Serialize;
+use tokio::sync::{
+    mpsc::{error::TrySendError, Sender},
+    Mutex,
+};
+use tracing::error;
+
+use crate::{
+    config::QueuesConfig,
+    events::{EventPriority, PrimEvent, SecEvent},
+    stats::{Stats, StatsCounter, StatsKind},
+};
+
+// Dropped events are counted by their kind and returned back
+pub async fn try_send_event<T>(tx: &Sender<T>, event: T, stats: &Mutex<Stats>) -> Result<(), T>
+where
+    T: Debug,
+    for<'a> StatsKind: From<&'a T>,
+{
+    match tx.try_send(event) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(event)) => {
+            let kind = StatsKind::from(&event);
+            error!("Queue is full, {:?} event is dropped", kind);
+            stats.lock().await.increment(StatsCounter::Dropped(kind));
+            Err(event)
+        }
+        Err(TrySendError::Closed(event)) => {
+            error!(
+                "Queue is closed, {:?} event is dropped",
+                StatsKind::from(&event)
+            );
+            Err(event)
+        }
+    }
+}
+
+pub fn get_queued<T>(tx: &Sender<T>) -> usize {
+    tx.max_capacity() - tx.capacity()
+}
+
+pub fn get_fill_pct<T>(tx: &Sender<T>) -> f64 {
+    get_queued(tx) as f64 * 100.0 / tx.max_capacity() as f64
+}
+
+// Senders of the event queues, only to see how many events are waiting
+#[derive(Clone)]
+pub struct QueueSenders {
+    pub primary: Sender<PrimEvent>,
+    pub secondary: Sender<SecEvent>,
+    pub csfloat: Sender<PrimEvent>,
+    pub steam: Sender<PrimEvent>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct QueueDepth {
+    pub name: &'static str,
+    pub queued: usize,
+    pub capacity: usize,
+}
+
+impl QueueSenders {
+    pub fn get_depths(&self) -> Vec<QueueDepth> {
+        vec![
+            QueueDepth {
+                name: "primary",
+                queued: get_queued(&self.primary),
+                capacity: self.primary.max_capacity(),
+            },
+            QueueDepth {
+                name: "secondary",
+                queued: get_queued(&self.secondary),
+                capacity: self.secondary.max_capacity(),
+            },
+            QueueDepth {
+                name: "csfloat",
+                queued: get_queued(&self.csfloat),
+                capacity: self.csfloat.max_capacity(),
+            },
+            QueueDepth {
+                name: "steam",
+                queued: get_queued(&self.steam),
+                capacity: self.steam.max_capacity(),
+            },
+        ]
+    }
+}
+
+// Pipelines skip low priority events while their queue is filling up, the oldest
+// of them go first, so the queue has room for the events with new listings
+pub fn is_need_to_shed(event: &PrimEvent, fill_pct: f64, config: &QueuesConfig) -> bool {
+    event.get_priority() == EventPriority::Low && fill_pct >= config.shed_from_pct as f64
+}
+
+// Drops since the previous check, see `queues.drop_alert_per_min`
+#[derive(Debug, Default)]
+pub struct DropRateMonitor {
+    last_total: u64,
+}
+
+impl DropRateMonitor {
+    pub fn new() -> Self {
+        DropRateMonitor::default()
+    }
+
+    // `dropped_total` is since the start, returns the drops when there are more than `threshold`
+    pub fn check(&mut self, dropped_total: u64, threshold: u64) -> Option<u64> {
+        let dropped = dropped_total.saturating_sub(self.last_total);
+        self.last_total = dropped_total;
+        (threshold > 0 && dropped > threshold).then_some(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{SteamAnalysisRequestedEvent, UpdatedCsfloatListingsEvent};
+
+    #[tokio::test]
+    async fn test_dropped_events_are_counted() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let stats = Mutex::new(Stats::new());
+        let event = || {
+            PrimEvent::UpdatedCsfloatListings(UpdatedCsfloatListingsEvent {
+                listing_ids: vec!["1".into()],
+            })
+        };
+
+        assert!(try_send_event(&tx, event(), &stats).await.is_ok());
+        assert_eq!(get_fill_pct(&tx), 100.0);
+        assert_eq!(try_send_event(&tx, event(), &stats).await, Err(event()));
+        assert_eq!(try_send_event(&tx, event(), &stats).await, Err(event()));
+        assert_eq!(stats.lock().await.get_dropped_total(), 2);
+    }
+
+    #[test]
+    fn test_is_need_to_shed() {
+        let config = QueuesConfig::default();
+        let low = PrimEvent::SteamAnalysisRequested(SteamAnalysisRequestedEvent {
+            market_name: "AK-47 | Redline (Field-Tested)".into(),
+        });
+        let high = PrimEvent::PaperPurchase(crate::events::PaperPurchaseEvent {
+            listing_id: "1".into(),
+        });
+        assert!(!is_need_to_shed(&low, 50.0, &config));
+        assert!(is_need_to_shed(&low, 80.0, &config));
+        assert!(!is_need_to_shed(&high, 99.0, &config));
+    }
+
+    #[test]
+    fn test_drop_rate_monitor() {
+        let mut monitor = DropRateMonitor::new();
+        assert_eq!(monitor.check(50, 100), None);
+        assert_eq!(monitor.check(200, 100), Some(150));
+        assert_eq!(monitor.check(250, 100), None);
+        assert_eq!(monitor.check(1000, 0), None);
+    }
+
+    #[tokio::test]
+    async fn test_queue_depths() {
+        let (primary, _primary_rx) = tokio::sync::mpsc::channel(10);
+        let (secondary, _secondary_rx) = tokio::sync::mpsc::channel(5);
+        let (csfloat, _csfloat_rx) = tokio::sync::mpsc::channel(10);
+        let (steam, _steam_rx) = tokio::sync::mpsc::channel(10);
+        let queues = QueueSenders {
+            primary,
+            secondary,
+            csfloat,
+            steam,
+        };
+        queues
+            .primary
+            .send(PrimEvent::SteamAnalysisRequested(
+                SteamAnalysisRequestedEvent {
+                    market_name: "Kilowatt Case".into(),
+                },
+            ))
+            .await
+            .unwrap();
+        let depths = queues.get_depths();
+        assert_eq!(
+            depths[0],
+            QueueDepth {
+                name: "primary",
+                queued: 1,
+                capacity: 10
+            }
+        );
+        assert_eq!(depths[1].queued, 0);
+        assert_eq!(depths[1].capacity, 5);
+    }
+}
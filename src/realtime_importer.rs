@@ -1,81 +1,429 @@
 use chrono::{Duration, NaiveDateTime, Utc};
-use sqlx::{Pool, Postgres, Row};
-use tracing::error;
+use sqlx::{postgres::PgListener, Pool, Postgres, Row};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
 
+use crate::{config::ImporterConfig, sharding::Shard};
+
+pub const CSFLOAT_SOURCE: &str = "csfloat";
+pub const STEAM_SOURCE: &str = "steam";
+pub const SKINPORT_SOURCE: &str = "skinport";
+// the scrapers NOTIFY the channel named as the table after an insert
+const NOTIFY_CHANNELS: [&str; 3] = ["csfloat_responses", "steam_responses", "skinport_responses"];
+const LISTEN_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Size of the next batch of a source. A full batch means a backlog, so the next one
+// is twice as large; while the lag is over `importer.catchup_lag_secs` the batches are the
+// largest until one comes back not full.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AdaptiveBatch {
+    size: u32,
+    is_catching_up: bool,
+}
+
+impl AdaptiveBatch {
+    // `headroom` is the free space of the queue the responses are sent to, 0 skips the fetch
+    pub fn get_limit(&self, base_size: u32, headroom: usize) -> u32 {
+        let headroom = u32::try_from(headroom).unwrap_or(u32::MAX);
+        self.size.max(base_size).min(headroom)
+    }
+
+    pub fn update(
+        &mut self,
+        fetched: usize,
+        limit: u32,
+        lag_secs: u64,
+        base_size: u32,
+        config: &ImporterConfig,
+    ) {
+        let max_size = config.max_batch_size.max(base_size);
+        let is_full = limit > 0 && fetched >= limit as usize;
+        if !is_full {
+            // a limit cut by the queue isn't a sign of the caught up backlog
+            if limit > 0 {
+                *self = AdaptiveBatch::default();
+            }
+            return;
+        }
+        self.is_catching_up = lag_secs >= config.catchup_lag_secs;
+        self.size = match self.is_catching_up {
+            true => max_size,
+            false => limit.saturating_mul(2).clamp(base_size, max_size),
+        };
+    }
+
+    pub fn get_size(&self, base_size: u32) -> u32 {
+        self.size.max(base_size)
+    }
+
+    pub fn is_catching_up(&self) -> bool {
+        self.is_catching_up
+    }
+}
+
+// Cursors are the timestamps of the newest imported responses, they're kept in the
+// importer_cursors table by shard, so responses written while the bot was down are imported
+// after a restart
 pub struct RealtimeImporter {
+    shard: Shard,
     csfloat_last_ts: NaiveDateTime,
     steam_last_ts: NaiveDateTime,
+    skinport_last_ts: NaiveDateTime,
+    // cursors as they were saved last
+    saved: [NaiveDateTime; 3],
+    csfloat_batch: AdaptiveBatch,
+    steam_batch: AdaptiveBatch,
+    skinport_batch: AdaptiveBatch,
+}
+
+impl Default for RealtimeImporter {
+    fn default() -> Self {
+        RealtimeImporter::new()
+    }
 }
 
 impl RealtimeImporter {
     pub fn new() -> RealtimeImporter {
-        RealtimeImporter {
+        let mut ri = RealtimeImporter {
+            shard: Shard::default(),
             csfloat_last_ts: Utc::now().naive_utc(),
             steam_last_ts: Utc::now().naive_utc() - Duration::hours(24),
+            skinport_last_ts: Utc::now().naive_utc(),
+            saved: [NaiveDateTime::MIN; 3],
+            csfloat_batch: AdaptiveBatch::default(),
+            steam_batch: AdaptiveBatch::default(),
+            skinport_batch: AdaptiveBatch::default(),
+        };
+        ri.saved = ri.get_cursors();
+        ri
+    }
+
+    // Continues from the saved cursors, but not from earlier than `max_catchup` ago.
+    // Sources without a saved cursor start as with `new`.
+    pub async fn load(
+        db: &Pool<Postgres>,
+        shard: Shard,
+        max_catchup: Duration,
+    ) -> RealtimeImporter {
+        let mut ri = RealtimeImporter {
+            shard,
+            ..RealtimeImporter::new()
+        };
+        let rows =
+            match sqlx::query("SELECT source, last_ts FROM importer_cursors WHERE shard = $1")
+                .bind(shard.to_string())
+                .fetch_all(db)
+                .await
+            {
+                Ok(rows) => rows,
+                Err(err) => {
+                    error!("Failed to load importer cursors: {:?}", err);
+                    return ri;
+                }
+            };
+        let earliest = Utc::now().naive_utc() - max_catchup;
+        for row in rows {
+            let source: &str = row.get("source");
+            let last_ts: NaiveDateTime = row.get("last_ts");
+            ri.set_cursor(source, last_ts.max(earliest));
+        }
+        info!(
+            "Importer resumes from csfloat {} | steam {} | skinport {}",
+            ri.csfloat_last_ts, ri.steam_last_ts, ri.skinport_last_ts
+        );
+        ri
+    }
+
+    fn get_cursors(&self) -> [NaiveDateTime; 3] {
+        [
+            self.csfloat_last_ts,
+            self.steam_last_ts,
+            self.skinport_last_ts,
+        ]
+    }
+
+    fn set_cursor(&mut self, source: &str, last_ts: NaiveDateTime) {
+        match source {
+            CSFLOAT_SOURCE => self.csfloat_last_ts = last_ts,
+            STEAM_SOURCE => self.steam_last_ts = last_ts,
+            SKINPORT_SOURCE => self.skinport_last_ts = last_ts,
+            _ => error!("Unknown importer cursor {}", source),
         }
     }
 
-    pub async fn get_csfloat_new(&mut self, db: &Pool<Postgres>, size: u32) -> Vec<String> {
-        match sqlx::query(
-            "SELECT timestamp, response FROM csfloat_responses WHERE timestamp > $1 ORDER BY timestamp LIMIT $2",
+    // Saves the cursors which moved since the last save, should be called by the leader only
+    pub async fn save_cursors(&mut self, db: &Pool<Postgres>) {
+        let cursors = self.get_cursors();
+        if cursors == self.saved {
+            return;
+        }
+        let sources = [CSFLOAT_SOURCE, STEAM_SOURCE, SKINPORT_SOURCE];
+        let result = sqlx::query(
+            "INSERT INTO importer_cursors (shard, source, last_ts)
+            SELECT $1, * FROM UNNEST($2::text[], $3::timestamp[])
+            ON CONFLICT (shard, source) DO UPDATE SET last_ts = EXCLUDED.last_ts",
+        )
+        .bind(self.shard.to_string())
+        .bind(&sources[..])
+        .bind(&cursors[..])
+        .execute(db)
+        .await;
+        match result {
+            Ok(_) => self.saved = cursors,
+            Err(err) => error!("Failed to save importer cursors: {:?}", err),
+        }
+    }
+
+    pub async fn get_csfloat_new(
+        &mut self,
+        db: &Pool<Postgres>,
+        base_size: u32,
+        headroom: usize,
+        config: &ImporterConfig,
+    ) -> Vec<String> {
+        get_new_batch(
+            db,
+            "csfloat_responses",
+            &mut self.csfloat_last_ts,
+            &mut self.csfloat_batch,
+            base_size,
+            headroom,
+            config,
         )
-        .bind(self.csfloat_last_ts)
-        .bind(size as i64)
-        .fetch_all(db)
         .await
-        {
-            Ok(resp) => {
-                if let Some(last_row) = resp.last() {
-                    self.csfloat_last_ts = last_row.get("timestamp");
-                }
+    }
 
-                resp.into_iter().map(|x| x.get("response")).collect()
-            }
-            Err(err) => {
-                match err {
-                    sqlx::Error::RowNotFound => {},
-                    _ => {
-                        error!(
-                        "Failed to get last csfloat response: {:?}",
-                         err
-                    );
+    pub async fn get_steam_new(
+        &mut self,
+        db: &Pool<Postgres>,
+        base_size: u32,
+        headroom: usize,
+        config: &ImporterConfig,
+    ) -> Vec<String> {
+        get_new_batch(
+            db,
+            "steam_responses",
+            &mut self.steam_last_ts,
+            &mut self.steam_batch,
+            base_size,
+            headroom,
+            config,
+        )
+        .await
+    }
+
+    pub async fn get_skinport_new(
+        &mut self,
+        db: &Pool<Postgres>,
+        base_size: u32,
+        headroom: usize,
+        config: &ImporterConfig,
+    ) -> Vec<String> {
+        get_new_batch(
+            db,
+            "skinport_responses",
+            &mut self.skinport_last_ts,
+            &mut self.skinport_batch,
+            base_size,
+            headroom,
+            config,
+        )
+        .await
+    }
+
+    pub fn get_csfloat_batch(&self) -> AdaptiveBatch {
+        self.csfloat_batch
+    }
+
+    pub fn get_steam_batch(&self) -> AdaptiveBatch {
+        self.steam_batch
+    }
+
+    pub fn get_skinport_batch(&self) -> AdaptiveBatch {
+        self.skinport_batch
+    }
+
+    // any source is behind, the next poll shouldn't wait
+    pub fn is_catching_up(&self) -> bool {
+        self.csfloat_batch.is_catching_up()
+            || self.steam_batch.is_catching_up()
+            || self.skinport_batch.is_catching_up()
+    }
+
+    pub fn get_csfloat_lag_secs(&self, now: NaiveDateTime) -> u64 {
+        get_lag_secs(self.csfloat_last_ts, now)
+    }
+
+    pub fn get_steam_lag_secs(&self, now: NaiveDateTime) -> u64 {
+        get_lag_secs(self.steam_last_ts, now)
+    }
+
+    pub fn get_skinport_lag_secs(&self, now: NaiveDateTime) -> u64 {
+        get_lag_secs(self.skinport_last_ts, now)
+    }
+}
+
+// Wakes the importer as soon as a scraper NOTIFYs about a new response. The poll
+// interval stays as the fallback for missed notifications and a lost connection.
+#[derive(Default)]
+pub struct ResponsesListener {
+    listener: Option<PgListener>,
+    last_attempt: Option<Instant>,
+}
+
+impl ResponsesListener {
+    pub fn new() -> Self {
+        ResponsesListener::default()
+    }
+
+    async fn connect(db: &Pool<Postgres>) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(db).await?;
+        listener.listen_all(NOTIFY_CHANNELS).await?;
+        Ok(listener)
+    }
+
+    // Waits for a notification or `timeout`, whichever comes first. Without `is_enabled`
+    // the connection is closed and it's a plain sleep.
+    pub async fn wait(
+        &mut self,
+        db: &Pool<Postgres>,
+        timeout: std::time::Duration,
+        is_enabled: bool,
+    ) {
+        if !is_enabled {
+            self.listener = None;
+            tokio::time::sleep(timeout).await;
+            return;
+        }
+        let deadline = Instant::now() + timeout;
+        let is_retry_due = self
+            .last_attempt
+            .is_none_or(|x| x.elapsed() >= LISTEN_RETRY_INTERVAL);
+        if self.listener.is_none() && is_retry_due {
+            self.last_attempt = Some(Instant::now());
+            match ResponsesListener::connect(db).await {
+                Ok(listener) => {
+                    info!("Importer listens to {:?}", NOTIFY_CHANNELS);
+                    self.listener = Some(listener);
                 }
+                Err(err) => warn!("Failed to listen for new responses: {:?}", err),
+            }
+        }
+        let Some(listener) = self.listener.as_mut() else {
+            tokio::time::sleep_until(deadline).await;
+            return;
+        };
+        tokio::select! {
+            result = listener.recv() => {
+                if let Err(err) = result {
+                    warn!("Lost the listener of new responses: {:?}", err);
+                    self.listener = None;
                 }
-
-                vec![]
-            },
+            }
+            _ = tokio::time::sleep_until(deadline) => {}
         }
     }
+}
 
-    pub async fn get_steam_new(&mut self, db: &Pool<Postgres>, size: u32) -> Vec<String> {
-        match sqlx::query(
-            "SELECT timestamp, response FROM steam_responses WHERE timestamp > $1 ORDER BY timestamp LIMIT $2",
-        )
-        .bind(self.steam_last_ts)
+// Time since the newest imported response, the clock of the DB may be a bit ahead
+fn get_lag_secs(last_ts: NaiveDateTime, now: NaiveDateTime) -> u64 {
+    (now - last_ts).num_seconds().max(0) as u64
+}
+
+async fn get_new_batch(
+    db: &Pool<Postgres>,
+    table: &str,
+    last_ts: &mut NaiveDateTime,
+    batch: &mut AdaptiveBatch,
+    base_size: u32,
+    headroom: usize,
+    config: &ImporterConfig,
+) -> Vec<String> {
+    let limit = batch.get_limit(base_size, headroom);
+    if limit == 0 {
+        return vec![];
+    }
+    let responses = get_new_responses(db, table, last_ts, limit).await;
+    let lag_secs = get_lag_secs(*last_ts, Utc::now().naive_utc());
+    batch.update(responses.len(), limit, lag_secs, base_size, config);
+    responses
+}
+
+async fn get_new_responses(
+    db: &Pool<Postgres>,
+    table: &str,
+    last_ts: &mut NaiveDateTime,
+    size: u32,
+) -> Vec<String> {
+    let query = format!(
+        "SELECT timestamp, response FROM {} WHERE timestamp > $1 ORDER BY timestamp LIMIT $2",
+        table
+    );
+    match sqlx::query(&query)
+        .bind(*last_ts)
         .bind(size as i64)
         .fetch_all(db)
         .await
-        {
-            Ok(resp) => {
-                if let Some(last_row) = resp.last() {
-                    self.steam_last_ts = last_row.get("timestamp");
-                }
-
-                resp.into_iter().map(|x| x.get("response")).collect()
+    {
+        Ok(resp) => {
+            if let Some(last_row) = resp.last() {
+                *last_ts = last_row.get("timestamp");
             }
-            Err(err) => {
-                match err {
-                    sqlx::Error::RowNotFound => {},
-                    _ => {
-                        error!(
-                        "Failed to get last steam response: {:?}",
-                         err
-                    );
-                }
+
+            resp.into_iter().map(|x| x.get("response")).collect()
+        }
+        Err(err) => {
+            match err {
+                sqlx::Error::RowNotFound => {}
+                _ => {
+                    error!("Failed to get last response from {}: {:?}", table, err);
                 }
+            }
 
-                vec![]
-            },
+            vec![]
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_batch() {
+        let config = ImporterConfig {
+            max_batch_size: 64,
+            catchup_lag_secs: 60,
+            ..Default::default()
+        };
+        let mut batch = AdaptiveBatch::default();
+        assert_eq!(batch.get_limit(8, 1_000), 8);
+
+        // a full batch without a lag doubles the next one
+        batch.update(8, 8, 5, 8, &config);
+        assert_eq!(batch.get_limit(8, 1_000), 16);
+        assert!(!batch.is_catching_up());
+        batch.update(16, 16, 5, 8, &config);
+        batch.update(32, 32, 5, 8, &config);
+        batch.update(64, 64, 5, 8, &config);
+        assert_eq!(batch.get_limit(8, 1_000), 64);
+
+        // the queue headroom caps the batch, a cut batch doesn't reset it
+        assert_eq!(batch.get_limit(8, 10), 10);
+        assert_eq!(batch.get_limit(8, 0), 0);
+        batch.update(0, 0, 5, 8, &config);
+        assert_eq!(batch.get_size(8), 64);
+
+        // a batch which isn't full is the end of the backlog
+        batch.update(3, 64, 0, 8, &config);
+        assert_eq!(batch, AdaptiveBatch::default());
+
+        // a lag starts the catch-up right away
+        batch.update(8, 8, 3_600, 8, &config);
+        assert!(batch.is_catching_up());
+        assert_eq!(batch.get_limit(8, 1_000), 64);
+        batch.update(20, 64, 30, 8, &config);
+        assert!(!batch.is_catching_up());
+        assert_eq!(batch.get_limit(8, 1_000), 8);
+    }
+}
@@ -0,0 +1,619 @@
+use std::{collections::HashMap, fmt::Write, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc};
+use sqlx::{Pool, Postgres};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{error, info};
+
+use crate::{
+    business_logic::estimate_steam_sell_price,
+    config::{AppConfig, SharedConfig},
+    fee::SteamFee,
+    ledger::{load_purchases_since, load_sales_since, PurchaseRecord, SaleRecord},
+    notify::{NotificationKind, Notifications},
+    prices::{PriceValue, PriceValueTrait},
+    shutdown::ShutdownSignal,
+    storages::{CsfloatEngine, SteamEngine},
+    strategies::StrategyName,
+    types::MarketName,
+};
+
+const REPORTER_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportPeriod {
+    Daily,
+    Weekly,
+}
+
+impl ReportPeriod {
+    fn duration(&self) -> chrono::Duration {
+        match self {
+            ReportPeriod::Daily => chrono::Duration::days(1),
+            ReportPeriod::Weekly => chrono::Duration::days(7),
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            ReportPeriod::Daily => "Daily report",
+            ReportPeriod::Weekly => "Weekly rollup",
+        }
+    }
+}
+
+// Profit values are in cents and can be negative
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PnlReport {
+    pub bought: usize,
+    pub failed: usize,
+    pub spend: PriceValue,
+    pub expected_profit: i64,
+    pub best_deal: Option<PurchaseRecord>,
+    pub sold: usize,
+    pub realized: i64,
+    pub open: usize,
+    // open positions valued by the current Steam price minus fee
+    pub unrealized: i64,
+    // open positions without a known Steam price, not included into `unrealized`
+    pub unpriced: usize,
+}
+
+// Sale price of each purchase, None if it's not sold yet.
+// A sale closes the earliest not sold purchase of the same item bought before it.
+pub fn match_sales(purchases: &[PurchaseRecord], sales: &[SaleRecord]) -> Vec<Option<PriceValue>> {
+    let mut sold_prices = vec![None; purchases.len()];
+    let mut sales: Vec<&SaleRecord> = sales.iter().collect();
+    sales.sort_by_key(|sale| sale.timestamp);
+
+    for sale in sales {
+        let purchase_idx = purchases
+            .iter()
+            .enumerate()
+            .filter(|(idx, purchase)| {
+                purchase.is_success
+                    && sold_prices[*idx].is_none()
+                    && purchase.market_name == sale.market_name
+                    && purchase.timestamp <= sale.timestamp
+            })
+            .min_by_key(|(_, purchase)| purchase.timestamp)
+            .map(|(idx, _)| idx);
+        if let Some(idx) = purchase_idx {
+            sold_prices[idx] = Some(sale.price);
+        }
+    }
+    sold_prices
+}
+
+// `purchases` and `sales` should cover all the history, so sales are matched correctly;
+// only purchases made since `since` are reported.
+// `current_prices` are Steam prices minus fee by market name.
+pub fn build_report(
+    purchases: &[PurchaseRecord],
+    sales: &[SaleRecord],
+    current_prices: &HashMap<MarketName, PriceValue>,
+    since: DateTime<Utc>,
+) -> PnlReport {
+    let sold_prices = match_sales(purchases, sales);
+    collect_report(purchases.iter().zip(sold_prices), current_prices, since)
+}
+
+// Same as `build_report`, split by the strategy which found the listing.
+// Strategies without purchases in the period are left out.
+pub fn build_strategy_reports(
+    purchases: &[PurchaseRecord],
+    sales: &[SaleRecord],
+    current_prices: &HashMap<MarketName, PriceValue>,
+    since: DateTime<Utc>,
+) -> Vec<(StrategyName, PnlReport)> {
+    let sold_prices = match_sales(purchases, sales);
+    StrategyName::ALL
+        .into_iter()
+        .filter_map(|strategy| {
+            let rows = purchases
+                .iter()
+                .zip(sold_prices.iter().copied())
+                .filter(|(purchase, _)| purchase.strategy == Some(strategy));
+            let report = collect_report(rows, current_prices, since);
+            (report.bought + report.failed > 0).then_some((strategy, report))
+        })
+        .collect()
+}
+
+fn collect_report<'a>(
+    rows: impl Iterator<Item = (&'a PurchaseRecord, Option<PriceValue>)>,
+    current_prices: &HashMap<MarketName, PriceValue>,
+    since: DateTime<Utc>,
+) -> PnlReport {
+    let mut report = PnlReport::default();
+
+    for (purchase, sold_price) in rows {
+        if purchase.timestamp < since {
+            continue;
+        }
+        if !purchase.is_success {
+            report.failed += 1;
+            continue;
+        }
+
+        report.bought += 1;
+        report.spend += purchase.paid_price;
+        report.expected_profit += purchase.expected_profit;
+        let is_best = match &report.best_deal {
+            Some(best) => purchase.expected_profit > best.expected_profit,
+            None => true,
+        };
+        if is_best {
+            report.best_deal = Some(purchase.clone());
+        }
+
+        let paid_price = purchase.paid_price as i64;
+        match sold_price {
+            Some(sold_price) => {
+                report.sold += 1;
+                report.realized += sold_price as i64 - paid_price;
+            }
+            None => {
+                report.open += 1;
+                match current_prices.get(&purchase.market_name) {
+                    Some(price) => report.unrealized += *price as i64 - paid_price,
+                    None => report.unpriced += 1,
+                }
+            }
+        }
+    }
+    report
+}
+
+fn cents_to_usd(cents: i64) -> f64 {
+    cents as f64 / 100.0
+}
+
+pub fn format_report(period: ReportPeriod, report: &PnlReport) -> String {
+    let mut text = String::new();
+    writeln!(text, "{}", period.title()).unwrap();
+    writeln!(
+        text,
+        "Bought {} items for ${} ({} failed attempts)",
+        report.bought,
+        report.spend.to_usd(),
+        report.failed
+    )
+    .unwrap();
+    writeln!(
+        text,
+        "Expected profit: ${:.2}",
+        cents_to_usd(report.expected_profit)
+    )
+    .unwrap();
+    if let Some(best) = &report.best_deal {
+        writeln!(
+            text,
+            "Best deal: {} for ${} ({:.2}%, ${:.2})",
+            best.market_name,
+            best.paid_price.to_usd(),
+            best.profit_pct,
+            cents_to_usd(best.expected_profit)
+        )
+        .unwrap();
+    }
+    writeln!(
+        text,
+        "Realized P&L: ${:.2} ({} sold)",
+        cents_to_usd(report.realized),
+        report.sold
+    )
+    .unwrap();
+    write!(
+        text,
+        "Unrealized P&L: ${:.2} ({} open, {} without Steam price)",
+        cents_to_usd(report.unrealized),
+        report.open,
+        report.unpriced
+    )
+    .unwrap();
+    text
+}
+
+pub fn format_strategy_reports(reports: &[(StrategyName, PnlReport)]) -> String {
+    let mut text = "By strategy:".to_string();
+    for (strategy, report) in reports {
+        write!(
+            text,
+            "\n{}: bought {} for ${} | expected ${:.2} | realized ${:.2} ({} sold) | unrealized ${:.2}",
+            strategy,
+            report.bought,
+            report.spend.to_usd(),
+            cents_to_usd(report.expected_profit),
+            cents_to_usd(report.realized),
+            report.sold,
+            cents_to_usd(report.unrealized)
+        )
+        .unwrap();
+    }
+    text
+}
+
+// CSFloat floor of a market name compared with its Steam price
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketSpread {
+    pub market_name: MarketName,
+    pub csfloat_floor: PriceValue,
+    pub csfloat_listings: usize,
+    pub steam_no_fee: PriceValue,
+    pub sold_per_week: u64,
+    pub spread_pct: f64,
+}
+
+// Market names by the spread between CSFloat floor and Steam price minus fee, the widest first.
+// Names without a Steam price (e.g. too illiquid for `strategy.liquidity_tiers`) are skipped.
+pub fn build_market_overview(
+    csfloat_engine: &CsfloatEngine,
+    steam_engine: &SteamEngine,
+    config: &AppConfig,
+    size: usize,
+) -> Vec<MarketSpread> {
+    let mut spreads: Vec<MarketSpread> = csfloat_engine
+        .aggregates
+        .iter_floors()
+        .filter_map(|(market_name, csfloat_floor, csfloat_listings)| {
+            let steam_price = estimate_steam_sell_price(market_name, steam_engine, config)?;
+            let steam_no_fee = SteamFee::subtract_fee(steam_price);
+            Some(MarketSpread {
+                market_name: market_name.clone(),
+                csfloat_floor,
+                csfloat_listings,
+                steam_no_fee,
+                sold_per_week: steam_engine
+                    .hm
+                    .get(market_name)
+                    .and_then(|x| x.sold_per_week)
+                    .unwrap_or(0) as u64,
+                spread_pct: (steam_no_fee as f64 / csfloat_floor.max(1) as f64 - 1.0) * 100.0,
+            })
+        })
+        .collect();
+    spreads.sort_by(|a, b| b.spread_pct.total_cmp(&a.spread_pct));
+    spreads.truncate(size);
+    spreads
+}
+
+pub fn format_market_overview(spreads: &[MarketSpread]) -> String {
+    let mut text = String::new();
+    write!(text, "Market overview, CSFloat floor vs Steam minus fee").unwrap();
+    for spread in spreads {
+        write!(
+            text,
+            "\n{:.2}% {}: ${} ({} listings) vs ${} | sold per week: {}",
+            spread.spread_pct,
+            spread.market_name,
+            spread.csfloat_floor.to_usd(),
+            spread.csfloat_listings,
+            spread.steam_no_fee.to_usd(),
+            spread.sold_per_week
+        )
+        .unwrap();
+    }
+    text
+}
+
+async fn send_market_overview(
+    notifications: &Notifications,
+    csfloat_engine: &Mutex<CsfloatEngine>,
+    steam_engine: &Mutex<SteamEngine>,
+    config: &AppConfig,
+) {
+    let spreads = {
+        let csfloat_engine_locked = csfloat_engine.lock().await;
+        let steam_engine_locked = steam_engine.lock().await;
+        build_market_overview(
+            &csfloat_engine_locked,
+            &steam_engine_locked,
+            config,
+            config.reporting.market_overview_size,
+        )
+    };
+    if spreads.is_empty() {
+        return;
+    }
+    let text = format_market_overview(&spreads);
+    info!("{}", text);
+    notifications.notify(NotificationKind::Report, text, config);
+}
+
+fn get_current_prices(
+    purchases: &[PurchaseRecord],
+    steam_engine: &SteamEngine,
+    config: &AppConfig,
+) -> HashMap<MarketName, PriceValue> {
+    purchases
+        .iter()
+        .filter_map(|purchase| {
+            estimate_steam_sell_price(&purchase.market_name, steam_engine, config)
+                .map(|price| (purchase.market_name.clone(), SteamFee::subtract_fee(price)))
+        })
+        .collect()
+}
+
+async fn send_report(
+    notifications: &Notifications,
+    pool: &Pool<Postgres>,
+    steam_engine: &Mutex<SteamEngine>,
+    config: &AppConfig,
+    period: ReportPeriod,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let purchases = load_purchases_since(pool, DateTime::<Utc>::MIN_UTC).await?;
+    let sales = load_sales_since(pool, DateTime::<Utc>::MIN_UTC).await?;
+    let current_prices = {
+        let steam_engine_locked = steam_engine.lock().await;
+        get_current_prices(&purchases, &steam_engine_locked, config)
+    };
+
+    let since = now - period.duration();
+    let (paper, real): (Vec<PurchaseRecord>, Vec<PurchaseRecord>) =
+        purchases.into_iter().partition(|x| x.is_paper);
+    let report = build_report(&real, &sales, &current_prices, since);
+    let mut text = format_report(period, &report);
+    let strategy_reports = build_strategy_reports(&real, &sales, &current_prices, since);
+    if !strategy_reports.is_empty() {
+        text += &format!("\n{}", format_strategy_reports(&strategy_reports));
+    }
+
+    // paper purchases of listings that were gone on the next refresh wouldn't have succeeded
+    let paper: Vec<PurchaseRecord> = paper
+        .into_iter()
+        .map(|mut x| {
+            x.is_success &= x.is_available != Some(false);
+            x
+        })
+        .collect();
+    if paper.iter().any(|x| x.timestamp >= since) {
+        let paper_report = build_report(&paper, &[], &current_prices, since);
+        text += &format!(
+            "\n\nPaper trading: {}",
+            format_report(period, &paper_report)
+        );
+    }
+    info!("{}", text);
+    notifications.notify(NotificationKind::Report, text, config);
+    Ok(())
+}
+
+// Sends the daily report at `reporting.daily_hour_utc`, on `reporting.weekly_day`
+// the weekly rollup is sent as well, followed by the market overview.
+// Reports missed while the bot was down are not sent.
+pub fn spawn_reporter(
+    notifications: Notifications,
+    pool: Pool<Postgres>,
+    csfloat_engine: Arc<Mutex<CsfloatEngine>>,
+    steam_engine: Arc<Mutex<SteamEngine>>,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let is_due =
+            |now: DateTime<Utc>, config: &AppConfig| now.hour() >= config.reporting.daily_hour_utc;
+        let now = Utc::now();
+        let mut last_sent: Option<NaiveDate> = match is_due(now, &config.load()) {
+            true => Some(now.date_naive()),
+            false => None,
+        };
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(REPORTER_CHECK_INTERVAL) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let current_config = config.load();
+            let now = Utc::now();
+            if !current_config.reporting.enabled
+                || !is_due(now, &current_config)
+                || last_sent == Some(now.date_naive())
+            {
+                continue;
+            }
+            last_sent = Some(now.date_naive());
+
+            let mut periods = vec![ReportPeriod::Daily];
+            if now.weekday() == current_config.reporting.weekly_day {
+                periods.push(ReportPeriod::Weekly);
+            }
+            for period in periods {
+                if let Err(err) = send_report(
+                    &notifications,
+                    &pool,
+                    &steam_engine,
+                    &current_config,
+                    period,
+                    now,
+                )
+                .await
+                {
+                    error!("Failed to build {:?} report: {:?}", period, err);
+                }
+            }
+            if current_config.reporting.market_overview_size > 0 {
+                send_market_overview(
+                    &notifications,
+                    &csfloat_engine,
+                    &steam_engine,
+                    &current_config,
+                )
+                .await;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures::get_listing;
+    use chrono::TimeZone;
+
+    fn get_purchase(market_name: &str, paid_price: PriceValue, day: u32) -> PurchaseRecord {
+        PurchaseRecord {
+            listing_id: format!("{}-{}", market_name, day).into(),
+            market_name: market_name.into(),
+            paid_price,
+            expected_steam_price: paid_price * 2,
+            expected_profit: paid_price as i64 / 2,
+            profit_pct: 50.0,
+            timestamp: Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap(),
+            is_success: true,
+            outcome: serde_json::Value::Null,
+            is_paper: false,
+            is_available: None,
+            float: None,
+            strategy: None,
+        }
+    }
+
+    fn get_sale(market_name: &str, price: PriceValue, day: u32) -> SaleRecord {
+        SaleRecord {
+            market_name: market_name.into(),
+            price,
+            timestamp: Utc.with_ymd_and_hms(2024, 1, day, 18, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_sales_are_matched_fifo() {
+        let purchases = vec![
+            get_purchase("A", 10_00, 1),
+            get_purchase("A", 12_00, 2),
+            get_purchase("B", 5_00, 2),
+        ];
+        // the sale on day 1 can't close the purchase made on day 2
+        let sales = vec![get_sale("A", 15_00, 3), get_sale("B", 6_00, 1)];
+        assert_eq!(
+            match_sales(&purchases, &sales),
+            vec![Some(15_00), None, None]
+        );
+    }
+
+    #[test]
+    fn test_build_report() {
+        let mut failed = get_purchase("C", 10_000, 3);
+        failed.is_success = false;
+        let purchases = vec![
+            get_purchase("A", 10_00, 1),
+            get_purchase("A", 12_00, 2),
+            get_purchase("B", 20_00, 3),
+            get_purchase("D", 3_00, 3),
+            failed,
+        ];
+        let sales = vec![get_sale("A", 15_00, 3)];
+        let current_prices = HashMap::from([("A".into(), 11_00), ("B".into(), 25_00)]);
+        let since = Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap();
+
+        let report = build_report(&purchases, &sales, &current_prices, since);
+        assert_eq!(report.bought, 3);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.spend, 35_00);
+        assert_eq!(report.expected_profit, 17_50);
+        assert_eq!(report.best_deal.unwrap().market_name, "B");
+        // the purchase of the day 1 is sold, but it's out of the period
+        assert_eq!((report.sold, report.realized), (0, 0));
+        assert_eq!(report.open, 3);
+        assert_eq!(report.unrealized, -1_00 + 5_00);
+        assert_eq!(report.unpriced, 1);
+    }
+
+    #[test]
+    fn test_build_strategy_reports() {
+        let mut phase = get_purchase("B", 20_00, 2);
+        phase.strategy = Some(StrategyName::Phase);
+        let purchases = vec![
+            get_purchase("A", 10_00, 1),
+            phase,
+            get_purchase("C", 4_00, 2),
+        ]
+        .into_iter()
+        .map(|mut x| {
+            x.strategy.get_or_insert(StrategyName::SteamArb);
+            x
+        })
+        .collect::<Vec<_>>();
+        let sales = vec![get_sale("A", 15_00, 3), get_sale("B", 18_00, 3)];
+        let current_prices = HashMap::from([("C".into(), 5_00)]);
+        let since = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        let reports = build_strategy_reports(&purchases, &sales, &current_prices, since);
+        assert_eq!(
+            reports
+                .iter()
+                .map(|(strategy, report)| (
+                    *strategy,
+                    report.bought,
+                    report.realized,
+                    report.unrealized
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (StrategyName::SteamArb, 2, 5_00, 1_00),
+                (StrategyName::Phase, 1, -2_00, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_market_overview() {
+        use crate::{
+            steam_analyzer::{AnalysisResult, Smoothing, Trend},
+            storages::{CsfloatEngineTrait, SteamEngineTrait},
+        };
+
+        let mut csfloat_engine = CsfloatEngine::new();
+        for (id, market_name, price) in [
+            ("1", "A", 10_00),
+            ("2", "A", 12_00),
+            ("3", "B", 10_00),
+            ("4", "C", 1_00),
+        ] {
+            csfloat_engine.update_listing(&get_listing(id, market_name, price));
+        }
+        let mut steam_engine = SteamEngine::new();
+        for (market_name, price) in [("A", 20_00), ("B", 12_00)] {
+            steam_engine.update(
+                &market_name.into(),
+                AnalysisResult {
+                    rsd: Some(0.01),
+                    is_stable: Some(true),
+                    sold_per_week: Some(500),
+                    percentiles: vec![(60, price)],
+                    percentiles_no_fee: vec![],
+                    weighted_percentiles: vec![],
+                    rejected_outliers: 0,
+                    trend: Trend::Flat,
+                    analyzed_at: None,
+                    sell_listings: None,
+                    expected_days_to_sell: None,
+                    smoothing: Smoothing::Sma,
+                    seasonality: None,
+                },
+            );
+        }
+        let mut config = AppConfig::default();
+        config.strategy.desired_percentile = 60;
+
+        // C has no Steam price
+        let spreads = build_market_overview(&csfloat_engine, &steam_engine, &config, 10);
+        assert_eq!(
+            spreads
+                .iter()
+                .map(|x| x.market_name.to_string())
+                .collect::<Vec<_>>(),
+            vec!["A", "B"]
+        );
+        assert_eq!(spreads[0].csfloat_floor, 10_00);
+        assert_eq!(spreads[0].csfloat_listings, 2);
+        assert_eq!(spreads[0].steam_no_fee, SteamFee::subtract_fee(20_00));
+        assert_eq!(spreads[0].sold_per_week, 500);
+
+        let spreads = build_market_overview(&csfloat_engine, &steam_engine, &config, 1);
+        assert_eq!(spreads.len(), 1);
+    }
+}
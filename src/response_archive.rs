@@ -0,0 +1,192 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use sqlx::{Pool, Postgres, Row};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::{
+    config::SharedConfig,
+    leadership::Leadership,
+    realtime_importer::{CSFLOAT_SOURCE, STEAM_SOURCE},
+    shutdown::ShutdownSignal,
+};
+
+// Responses of the scrapers older than `archive.retention_days` are moved from the hot
+// tables, polled by the importer, into `<table>_archive` tables partitioned by month.
+// The backtest reads both, see `backtest::load_responses`.
+const ARCHIVED_TABLES: [(&str, &str); 2] = [
+    (CSFLOAT_SOURCE, "csfloat_responses"),
+    (STEAM_SOURCE, "steam_responses"),
+];
+
+pub fn get_archive_table(table: &str) -> String {
+    format!("{}_archive", table)
+}
+
+// Name and bounds of the monthly partition of the archive table holding `ts`
+pub fn get_partition(table: &str, ts: NaiveDateTime) -> (String, NaiveDate, NaiveDate) {
+    let from = NaiveDate::from_ymd_opt(ts.year(), ts.month(), 1).unwrap();
+    let to = match from.month() {
+        12 => NaiveDate::from_ymd_opt(from.year() + 1, 1, 1),
+        month => NaiveDate::from_ymd_opt(from.year(), month + 1, 1),
+    }
+    .unwrap();
+    let name = format!(
+        "{}_{:04}_{:02}",
+        get_archive_table(table),
+        from.year(),
+        from.month()
+    );
+    (name, from, to)
+}
+
+async fn create_partitions(
+    db: &Pool<Postgres>,
+    table: &str,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Result<(), sqlx::Error> {
+    let mut ts = from;
+    while ts <= to {
+        let (name, start, end) = get_partition(table, ts);
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {} PARTITION OF {} FOR VALUES FROM ('{}') TO ('{}')",
+            name,
+            get_archive_table(table),
+            start,
+            end
+        );
+        sqlx::query(&query).execute(db).await?;
+        ts = end.and_hms_opt(0, 0, 0).unwrap();
+    }
+    Ok(())
+}
+
+// Responses the importer of any shard hasn't reached yet are kept in the hot table
+async fn get_cutoff(
+    db: &Pool<Postgres>,
+    source: &str,
+    retention_cutoff: NaiveDateTime,
+) -> Result<NaiveDateTime, sqlx::Error> {
+    let cursor: Option<NaiveDateTime> =
+        sqlx::query_scalar("SELECT MIN(last_ts) FROM importer_cursors WHERE source = $1")
+            .bind(source)
+            .fetch_one(db)
+            .await?;
+    Ok(cursor.map_or(retention_cutoff, |x| x.min(retention_cutoff)))
+}
+
+// Moves responses older than `before` in batches, so the hot table isn't locked for long.
+// Returns the number of moved responses.
+pub async fn archive_table(
+    db: &Pool<Postgres>,
+    table: &str,
+    before: NaiveDateTime,
+    batch_size: u32,
+) -> Result<u64, sqlx::Error> {
+    let oldest: Option<NaiveDateTime> =
+        sqlx::query(&format!("SELECT MIN(timestamp) AS oldest FROM {}", table))
+            .fetch_one(db)
+            .await?
+            .get("oldest");
+    let Some(oldest) = oldest.filter(|x| *x < before) else {
+        return Ok(0);
+    };
+    create_partitions(db, table, oldest, before).await?;
+
+    let query = format!(
+        "WITH moved AS (
+            DELETE FROM {table} WHERE ctid IN (
+                SELECT ctid FROM {table} WHERE timestamp < $1 ORDER BY timestamp LIMIT $2
+            )
+            RETURNING timestamp, response
+        )
+        INSERT INTO {archive} (timestamp, response) SELECT timestamp, response FROM moved",
+        table = table,
+        archive = get_archive_table(table),
+    );
+    let mut moved = 0;
+    loop {
+        let result = sqlx::query(&query)
+            .bind(before)
+            .bind(batch_size as i64)
+            .execute(db)
+            .await?;
+        moved += result.rows_affected();
+        if result.rows_affected() < batch_size as u64 {
+            return Ok(moved);
+        }
+    }
+}
+
+pub async fn archive_responses(db: &Pool<Postgres>, retention_days: i64, batch_size: u32) {
+    let retention_cutoff = Utc::now().naive_utc() - Duration::days(retention_days);
+    for (source, table) in ARCHIVED_TABLES {
+        let result = match get_cutoff(db, source, retention_cutoff).await {
+            Ok(before) => archive_table(db, table, before, batch_size).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(0) => {}
+            Ok(moved) => info!("Archived {} responses of {}", moved, table),
+            Err(err) => error!("Failed to archive {}: {:?}", table, err),
+        }
+    }
+}
+
+// Archives every `archive.interval_secs` on the leader, the hot tables are shared by the shards
+pub fn spawn_response_archiver(
+    pool: Pool<Postgres>,
+    leadership: Leadership,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let interval = config.load().archive.interval();
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let archive_config = config.load().archive.clone();
+            if !archive_config.enabled || !leadership.is_leader() {
+                continue;
+            }
+            archive_responses(
+                &pool,
+                archive_config.retention_days,
+                archive_config.batch_size,
+            )
+            .await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_partition() {
+        let ts = NaiveDate::from_ymd_opt(2024, 2, 19)
+            .unwrap()
+            .and_hms_opt(15, 59, 14)
+            .unwrap();
+        assert_eq!(
+            get_partition("csfloat_responses", ts),
+            (
+                "csfloat_responses_archive_2024_02".to_string(),
+                NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+            )
+        );
+
+        let ts = NaiveDate::from_ymd_opt(2023, 12, 31)
+            .unwrap()
+            .and_hms_opt(23, 59, 59)
+            .unwrap();
+        let (name, _, to) = get_partition("steam_responses", ts);
+        assert_eq!(name, "steam_responses_archive_2023_12");
+        assert_eq!(to, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::{config::AutobuyConfig, prices::PriceValue, types::MarketName};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskRejection {
+    KillSwitch,
+    PriceTooHigh,
+    DailyCapReached { spent_today: PriceValue },
+    TooManyPositions { positions: u32 },
+    MarketCooldown { until: DateTime<Utc> },
+}
+
+// Limits of the autobuy, the kill-switch is toggled via Telegram.
+// Spend, positions and the last purchase of each market name are kept in memory,
+// on startup they're restored from the ledger with the kill-switch, see `ledger::load_risk_manager`.
+#[derive(Debug, Default)]
+pub struct RiskManager {
+    kill_switch: bool,
+    // UTC day of `spent_today`
+    day: Option<NaiveDate>,
+    spent_today: PriceValue,
+    // bought and not sold yet items by market name
+    positions: HashMap<MarketName, u32>,
+    last_purchases: HashMap<MarketName, DateTime<Utc>>,
+}
+
+impl RiskManager {
+    pub fn new() -> Self {
+        RiskManager::default()
+    }
+
+    pub fn set_kill_switch(&mut self, kill_switch: bool) {
+        self.kill_switch = kill_switch;
+    }
+
+    pub fn get_spent_today(&self, now: DateTime<Utc>) -> PriceValue {
+        match self.day == Some(now.date_naive()) {
+            true => self.spent_today,
+            false => 0,
+        }
+    }
+
+    pub fn get_positions(&self, market_name: &str) -> u32 {
+        self.positions.get(market_name).copied().unwrap_or(0)
+    }
+
+    // None while the market name isn't in its `autobuy.market_cooldown_secs`
+    pub fn get_cooldown_until(
+        &self,
+        market_name: &str,
+        config: &AutobuyConfig,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        if config.market_cooldown_secs == 0 {
+            return None;
+        }
+        let until = *self.last_purchases.get(market_name)? + config.market_cooldown();
+        (until > now).then_some(until)
+    }
+
+    pub fn check(
+        &self,
+        market_name: &str,
+        price: PriceValue,
+        config: &AutobuyConfig,
+        now: DateTime<Utc>,
+    ) -> Result<(), RiskRejection> {
+        self.check_batch(
+            market_name,
+            &[price],
+            config.max_positions_per_market,
+            config,
+            now,
+        )
+    }
+
+    // Items of the market name bought in one request, each of them is a position
+    pub fn check_batch(
+        &self,
+        market_name: &str,
+        prices: &[PriceValue],
+        max_positions: u32,
+        config: &AutobuyConfig,
+        now: DateTime<Utc>,
+    ) -> Result<(), RiskRejection> {
+        if self.kill_switch {
+            return Err(RiskRejection::KillSwitch);
+        }
+        if prices
+            .iter()
+            .any(|price| *price > config.max_purchase_price)
+        {
+            return Err(RiskRejection::PriceTooHigh);
+        }
+        if let Some(until) = self.get_cooldown_until(market_name, config, now) {
+            return Err(RiskRejection::MarketCooldown { until });
+        }
+
+        let spent_today = self.get_spent_today(now);
+        if spent_today + prices.iter().sum::<PriceValue>() > config.daily_spend_cap {
+            return Err(RiskRejection::DailyCapReached { spent_today });
+        }
+
+        let positions = self.get_positions(market_name);
+        if positions + prices.len() as u32 > max_positions {
+            return Err(RiskRejection::TooManyPositions { positions });
+        }
+        Ok(())
+    }
+
+    pub fn register_purchase(&mut self, market_name: &str, price: PriceValue, now: DateTime<Utc>) {
+        let today = now.date_naive();
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.spent_today = 0;
+        }
+        self.spent_today += price;
+        *self.positions.entry(market_name.into()).or_insert(0) += 1;
+        let last_purchase = self.last_purchases.entry(market_name.into()).or_insert(now);
+        *last_purchase = now.max(*last_purchase);
+    }
+
+    // returns false if there was no open position
+    pub fn register_sale(&mut self, market_name: &str) -> bool {
+        match self.positions.get_mut(market_name) {
+            Some(positions) => {
+                *positions -= 1;
+                if *positions == 0 {
+                    self.positions.remove(market_name);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn summary(&self, now: DateTime<Utc>) -> String {
+        let mut positions: Vec<String> = self
+            .positions
+            .iter()
+            .map(|(market_name, count)| format!("{} x{}", market_name, count))
+            .collect();
+        positions.sort();
+        format!(
+            "Kill-switch: {} | spent today: {} cents | open positions: {:?}",
+            self.kill_switch,
+            self.get_spent_today(now),
+            positions
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn get_config() -> AutobuyConfig {
+        AutobuyConfig {
+            daily_spend_cap: 10_000,
+            max_purchase_price: 60_00,
+            max_positions_per_market: 1,
+            ..AutobuyConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_risk_limits() {
+        let config = get_config();
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let mut risk = RiskManager::new();
+
+        assert_eq!(risk.check("A", 50_00, &config, now), Ok(()));
+        assert_eq!(
+            risk.check("A", 70_00, &config, now),
+            Err(RiskRejection::PriceTooHigh)
+        );
+
+        risk.register_purchase("A", 50_00, now);
+        assert_eq!(
+            risk.check("A", 10_00, &config, now),
+            Err(RiskRejection::TooManyPositions { positions: 1 })
+        );
+        assert_eq!(
+            risk.check("B", 55_00, &config, now),
+            Err(RiskRejection::DailyCapReached { spent_today: 50_00 })
+        );
+
+        // the next day spend starts from zero
+        let tomorrow = now + chrono::Duration::days(1);
+        assert_eq!(risk.check("B", 55_00, &config, tomorrow), Ok(()));
+
+        assert!(risk.register_sale("A"));
+        assert!(!risk.register_sale("A"));
+        assert_eq!(risk.check("A", 10_00, &config, now), Ok(()));
+
+        risk.set_kill_switch(true);
+        assert_eq!(
+            risk.check("A", 10_00, &config, now),
+            Err(RiskRejection::KillSwitch)
+        );
+    }
+
+    #[test]
+    fn test_batch_limits() {
+        let config = get_config();
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let mut risk = RiskManager::new();
+
+        assert_eq!(
+            risk.check_batch("Case", &[2_00, 2_00, 1_90], 5, &config, now),
+            Ok(())
+        );
+        for _ in 0..3 {
+            risk.register_purchase("Case", 2_00, now);
+        }
+        // a batch is bought whole or not at all
+        assert_eq!(
+            risk.check_batch("Case", &[2_00, 2_00, 2_00], 5, &config, now),
+            Err(RiskRejection::TooManyPositions { positions: 3 })
+        );
+        assert_eq!(
+            risk.check_batch("Case", &[2_00, 2_00], 5, &config, now),
+            Ok(())
+        );
+        assert_eq!(
+            risk.check_batch("Case", &[50_00, 50_00], 5, &config, now),
+            Err(RiskRejection::DailyCapReached { spent_today: 6_00 })
+        );
+    }
+
+    #[test]
+    fn test_market_cooldown() {
+        let config = AutobuyConfig {
+            max_positions_per_market: 3,
+            market_cooldown_secs: 3600,
+            ..get_config()
+        };
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let mut risk = RiskManager::new();
+
+        risk.register_purchase("A", 10_00, now);
+        let until = now + chrono::Duration::hours(1);
+        assert_eq!(
+            risk.check("A", 10_00, &config, now + chrono::Duration::minutes(5)),
+            Err(RiskRejection::MarketCooldown { until })
+        );
+        assert_eq!(risk.check("B", 10_00, &config, now), Ok(()));
+        assert_eq!(risk.check("A", 10_00, &config, until), Ok(()));
+
+        // an older purchase restored from the ledger doesn't shorten it
+        risk.register_purchase("A", 10_00, now - chrono::Duration::hours(5));
+        assert_eq!(risk.get_cooldown_until("A", &config, now), Some(until));
+        // the sale doesn't end the cooldown
+        assert!(risk.register_sale("A"));
+        assert_eq!(risk.get_cooldown_until("A", &config, now), Some(until));
+
+        let disabled = AutobuyConfig {
+            market_cooldown_secs: 0,
+            ..config
+        };
+        assert_eq!(risk.check("A", 10_00, &disabled, now), Ok(()));
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+
+use sqlx::{Connection, PgConnection};
+
+// Advisory lock ids of the shards are this plus the shard index
+const SHARD_LOCK_KEY: i64 = 0x5343_5348_0000_0000;
+
+// Part of the CSFloat listings handled by this instance. Instances sharing one Postgres
+// split the listings by a stable hash of their id, so each listing is refreshed, saved and
+// reported by exactly one of them. Steam analyses are shared by all instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Default for Shard {
+    fn default() -> Self {
+        Shard { index: 0, count: 1 }
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl Shard {
+    pub fn new(index: u32, count: u32) -> Result<Shard, String> {
+        if count == 0 || index >= count {
+            return Err(format!("invalid shard {} of {}", index, count));
+        }
+        Ok(Shard { index, count })
+    }
+
+    pub fn is_sharded(&self) -> bool {
+        self.count > 1
+    }
+
+    pub fn contains(&self, listing_id: &str) -> bool {
+        !self.is_sharded() || get_stable_hash(listing_id) % self.count as u64 == self.index as u64
+    }
+
+    // e.g. `csfloat_engine_shard_0`, keys stay as they are without sharding
+    pub fn get_state_key(&self, key: &str) -> String {
+        if self.is_sharded() {
+            format!("{}_shard_{}", key, self.index)
+        } else {
+            key.to_string()
+        }
+    }
+}
+
+// FNV-1a, `DefaultHasher` isn't guaranteed to be the same across Rust versions
+fn get_stable_hash(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[derive(Debug)]
+pub enum ShardError {
+    Db(sqlx::Error),
+    // another instance runs with the same shard
+    Claimed(Shard),
+}
+
+impl fmt::Display for ShardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShardError::Db(err) => write!(f, "db: {}", err),
+            ShardError::Claimed(shard) => {
+                write!(f, "shard {} is claimed by another instance", shard)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShardError {}
+
+// Takes a session advisory lock of the shard on its own connection, which has to be kept
+// open for the whole run: the lock is released once it's closed, e.g. when the instance dies.
+// All instances have to use the same shard count, otherwise their shards overlap.
+pub async fn claim_shard(database_url: &str, shard: Shard) -> Result<PgConnection, ShardError> {
+    let mut conn = PgConnection::connect(database_url)
+        .await
+        .map_err(ShardError::Db)?;
+    let is_claimed: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+        .bind(SHARD_LOCK_KEY + shard.index as i64)
+        .fetch_one(&mut conn)
+        .await
+        .map_err(ShardError::Db)?;
+    if !is_claimed {
+        return Err(ShardError::Claimed(shard));
+    }
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard() {
+        assert!(Shard::new(2, 2).is_err());
+        assert!(Shard::new(0, 0).is_err());
+
+        let single = Shard::default();
+        assert!(single.contains("123"));
+        assert_eq!(single.get_state_key("csfloat_engine"), "csfloat_engine");
+
+        let shards: Vec<Shard> = (0..3).map(|i| Shard::new(i, 3).unwrap()).collect();
+        assert_eq!(
+            shards[1].get_state_key("csfloat_engine"),
+            "csfloat_engine_shard_1"
+        );
+        let mut sizes = [0; 3];
+        for listing_id in 0..3000 {
+            let listing_id = (700_000_000_000_000_000u64 + listing_id).to_string();
+            let owners: Vec<usize> = (0..3)
+                .filter(|&i| shards[i].contains(&listing_id))
+                .collect();
+            assert_eq!(owners.len(), 1);
+            sizes[owners[0]] += 1;
+        }
+        assert!(sizes.iter().all(|&x| x > 800), "{:?}", sizes);
+        // the same on every instance and run
+        assert_eq!(get_stable_hash("abc"), 0xe71f_a219_0541_574b);
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use tokio::sync::{mpsc::Sender, watch};
+use tracing::{error, info, warn};
+
+use crate::events::{PrimEvent, SecEvent};
+
+// Receivers are handed to producer tasks (importer, refresher, db saver),
+// they stop their loops as soon as the value becomes `true`.
+pub type ShutdownSignal = watch::Receiver<bool>;
+
+pub struct Shutdown {
+    tx: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(false);
+        Shutdown { tx }
+    }
+
+    pub fn subscribe(&self) -> ShutdownSignal {
+        self.tx.subscribe()
+    }
+
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+}
+
+// Resolves on Ctrl-C (SIGINT) or SIGTERM.
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(sigterm) => sigterm,
+            Err(err) => {
+                error!("Failed to install SIGTERM handler: {:?}", err);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+            _ = sigterm.recv() => info!("Received SIGTERM"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Received Ctrl-C");
+    }
+}
+
+// Waits until all queues are empty. Dispatchers may emit new events while processing
+// the last ones, so emptiness has to be observed twice in a row.
+pub async fn drain_queues(
+    prim_txs: &[Sender<PrimEvent>],
+    sec_tx: &Sender<SecEvent>,
+    timeout: Duration,
+) -> bool {
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    let get_prim_left = || {
+        prim_txs
+            .iter()
+            .map(|tx| tx.max_capacity() - tx.capacity())
+            .sum::<usize>()
+    };
+    let is_empty = || get_prim_left() == 0 && sec_tx.capacity() == sec_tx.max_capacity();
+
+    let drained = tokio::time::timeout(timeout, async {
+        let mut empty_checks = 0;
+        while empty_checks < 2 {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            empty_checks = if is_empty() { empty_checks + 1 } else { 0 };
+        }
+    })
+    .await;
+
+    if drained.is_err() {
+        warn!(
+            "Queues are not drained in {:?}: primary {} | secondary {} events left",
+            timeout,
+            get_prim_left(),
+            sec_tx.max_capacity() - sec_tx.capacity(),
+        );
+        return false;
+    }
+    true
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    currency::{Currency, Money},
+    names::deserialize_market_name,
+    prices::PriceValue,
+    types::MarketName,
+};
+
+pub type SkinportSaleId = u64;
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SkinportFeedEventType {
+    Listed,
+    Sold,
+}
+
+// One sale from Skinport's sale feed, prices are in cents of `currency`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SkinportSale {
+    pub sale_id: SkinportSaleId,
+    #[serde(deserialize_with = "deserialize_market_name")]
+    pub market_hash_name: MarketName,
+    pub sale_price: PriceValue,
+    // feeds saved before it was tracked are in USD
+    #[serde(default)]
+    pub currency: Currency,
+    #[serde(default)]
+    pub wear: Option<f64>,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl SkinportSale {
+    pub fn get_money(&self) -> Money {
+        Money::new(self.sale_price, self.currency)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SkinportFeedResponse {
+    pub event_type: SkinportFeedEventType,
+    pub sales: Vec<SkinportSale>,
+}
+
+pub enum SkinportEngineDecision {
+    New,
+    NotChanged,
+    Updated,
+    Removed,
+}
+
+// Skinport sales are short-living, so the engine is kept only in memory
+#[derive(Debug)]
+pub struct SkinportEngine {
+    pub hm: HashMap<SkinportSaleId, SkinportSale>,
+}
+
+impl Default for SkinportEngine {
+    fn default() -> Self {
+        SkinportEngine::new()
+    }
+}
+
+impl SkinportEngine {
+    pub fn new() -> Self {
+        SkinportEngine { hm: HashMap::new() }
+    }
+
+    pub fn update_sale(
+        &mut self,
+        event_type: &SkinportFeedEventType,
+        sale: &SkinportSale,
+    ) -> SkinportEngineDecision {
+        if *event_type == SkinportFeedEventType::Sold {
+            return match self.hm.remove(&sale.sale_id) {
+                Some(_) => SkinportEngineDecision::Removed,
+                None => SkinportEngineDecision::NotChanged,
+            };
+        }
+
+        match self.hm.insert(sale.sale_id, sale.clone()) {
+            Some(old_sale) if old_sale.sale_price == sale.sale_price => {
+                SkinportEngineDecision::NotChanged
+            }
+            Some(_) => SkinportEngineDecision::Updated,
+            None => SkinportEngineDecision::New,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_sale_lifecycle() {
+        let response = r#"{
+            "eventType": "listed",
+            "sales": [{
+                "saleId": 5023146,
+                "marketHashName": "AK-47 | Redline (Field-Tested)",
+                "salePrice": 1250,
+                "wear": 0.2312,
+                "url": "ak-47-redline-field-tested"
+            }]
+        }"#;
+        let parsed = serde_json::from_str::<SkinportFeedResponse>(response).unwrap();
+        let mut engine = SkinportEngine::new();
+        let sale = &parsed.sales[0];
+
+        assert!(matches!(
+            engine.update_sale(&parsed.event_type, sale),
+            SkinportEngineDecision::New
+        ));
+        assert!(matches!(
+            engine.update_sale(&parsed.event_type, sale),
+            SkinportEngineDecision::NotChanged
+        ));
+        assert_eq!(engine.hm.len(), 1);
+
+        assert!(matches!(
+            engine.update_sale(&SkinportFeedEventType::Sold, sale),
+            SkinportEngineDecision::Removed
+        ));
+        assert_eq!(engine.hm.len(), 0);
+    }
+}
@@ -0,0 +1,333 @@
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    models::{
+        CsfloatListingStruct, CsfloatListingStructV1, CsfloatListingStructV2,
+        CsfloatListingStructV3,
+    },
+    steam_analyzer::{AnalysisResult, AnalysisResultV1, AnalysisResultV2, AnalysisResultV3},
+    steam_orders::SteamOrderBook,
+};
+
+// Saved engine entries are bincode compressed with zstd, prefixed by `MAGIC`, the version
+// of this format and the `SnapshotSchema::VERSION` of the saved type. Anything without
+// the prefix is the JSON written before the binary format, it's still decoded.
+// Outdated entries are rewritten by the stores once they're loaded, see `is_outdated`.
+const MAGIC: &[u8; 4] = b"SCSB";
+// 1 had no schema version, everything saved with it is schema version 1
+const FORMAT_V1: u8 = 1;
+const FORMAT_VERSION: u8 = 2;
+const HEADER_SIZE: usize = MAGIC.len() + 1 + 2;
+// fast levels compress the repetitive listings almost as well as the slow ones
+const ZSTD_LEVEL: i32 = 3;
+
+// A type saved by the stores. bincode isn't self-describing, so any change of the saved fields
+// (including the ones of nested structs) needs a bump of `VERSION`, with the previous layout
+// kept as a frozen copy of the struct which `migrate` decodes and converts, see `migrate_from`.
+pub trait SnapshotSchema: Serialize + DeserializeOwned {
+    const VERSION: u16;
+
+    // Decodes bincode of an older `VERSION`
+    fn migrate(version: u16, _encoded: &[u8]) -> Result<Self, CodecError> {
+        Err(CodecError::NoMigration(version))
+    }
+}
+
+impl SnapshotSchema for CsfloatListingStructV1 {
+    const VERSION: u16 = 1;
+}
+
+impl SnapshotSchema for CsfloatListingStructV2 {
+    const VERSION: u16 = 2;
+
+    fn migrate(version: u16, encoded: &[u8]) -> Result<Self, CodecError> {
+        migrate_from::<CsfloatListingStructV1, _>(version, encoded)
+    }
+}
+
+impl SnapshotSchema for CsfloatListingStructV3 {
+    const VERSION: u16 = 3;
+
+    fn migrate(version: u16, encoded: &[u8]) -> Result<Self, CodecError> {
+        migrate_from::<CsfloatListingStructV2, _>(version, encoded)
+    }
+}
+
+impl SnapshotSchema for CsfloatListingStruct {
+    const VERSION: u16 = 4;
+
+    fn migrate(version: u16, encoded: &[u8]) -> Result<Self, CodecError> {
+        migrate_from::<CsfloatListingStructV3, _>(version, encoded)
+    }
+}
+
+impl SnapshotSchema for AnalysisResultV1 {
+    const VERSION: u16 = 1;
+}
+
+impl SnapshotSchema for AnalysisResultV2 {
+    const VERSION: u16 = 2;
+
+    fn migrate(version: u16, encoded: &[u8]) -> Result<Self, CodecError> {
+        migrate_from::<AnalysisResultV1, _>(version, encoded)
+    }
+}
+
+impl SnapshotSchema for AnalysisResultV3 {
+    const VERSION: u16 = 3;
+
+    fn migrate(version: u16, encoded: &[u8]) -> Result<Self, CodecError> {
+        migrate_from::<AnalysisResultV2, _>(version, encoded)
+    }
+}
+
+impl SnapshotSchema for AnalysisResult {
+    const VERSION: u16 = 4;
+
+    fn migrate(version: u16, encoded: &[u8]) -> Result<Self, CodecError> {
+        migrate_from::<AnalysisResultV3, _>(version, encoded)
+    }
+}
+
+impl SnapshotSchema for SteamOrderBook {
+    const VERSION: u16 = 1;
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Io(std::io::Error),
+    Bincode(bincode::Error),
+    Json(serde_json::Error),
+    // written by a newer version of the bot
+    UnknownFormat(u8),
+    NewerSchema(u16),
+    // the layout of the version isn't kept
+    NoMigration(u16),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Io(err) => write!(f, "zstd: {}", err),
+            CodecError::Bincode(err) => write!(f, "bincode: {}", err),
+            CodecError::Json(err) => write!(f, "json: {}", err),
+            CodecError::UnknownFormat(version) => write!(f, "unknown format {}", version),
+            CodecError::NewerSchema(version) => write!(f, "newer schema {}", version),
+            CodecError::NoMigration(version) => {
+                write!(f, "no migration from schema {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+pub fn encode<T: SnapshotSchema>(value: &T) -> Result<Vec<u8>, CodecError> {
+    let encoded = bincode::serialize(value).map_err(CodecError::Bincode)?;
+    let mut data = Vec::with_capacity(HEADER_SIZE + encoded.len() / 4);
+    data.extend_from_slice(MAGIC);
+    data.push(FORMAT_VERSION);
+    data.extend_from_slice(&T::VERSION.to_le_bytes());
+    zstd::stream::copy_encode(encoded.as_slice(), &mut data, ZSTD_LEVEL).map_err(CodecError::Io)?;
+    Ok(data)
+}
+
+pub fn decode<T: SnapshotSchema>(data: &[u8]) -> Result<T, CodecError> {
+    if is_json(data) {
+        return serde_json::from_slice(data).map_err(CodecError::Json);
+    }
+    let (version, body) = get_schema_version(data)?;
+    let encoded = zstd::decode_all(body).map_err(CodecError::Io)?;
+    match version {
+        version if version == T::VERSION => {
+            bincode::deserialize(&encoded).map_err(CodecError::Bincode)
+        }
+        version if version > T::VERSION => Err(CodecError::NewerSchema(version)),
+        version => T::migrate(version, &encoded),
+    }
+}
+
+// Decodes bincode of the frozen `Old` layout, for `SnapshotSchema::migrate`.
+// Versions older than `Old` are chained through its own `migrate`.
+pub fn migrate_from<Old, T>(version: u16, encoded: &[u8]) -> Result<T, CodecError>
+where
+    Old: SnapshotSchema + Into<T>,
+{
+    let old: Old = if version == Old::VERSION {
+        bincode::deserialize(encoded).map_err(CodecError::Bincode)?
+    } else {
+        Old::migrate(version, encoded)?
+    };
+    Ok(old.into())
+}
+
+// JSON or an older version, it's to be saved again in the current one
+pub fn is_outdated<T: SnapshotSchema>(data: &[u8]) -> bool {
+    is_json(data)
+        || data[MAGIC.len()] != FORMAT_VERSION
+        || !matches!(get_schema_version(data), Ok((version, _)) if version == T::VERSION)
+}
+
+fn is_json(data: &[u8]) -> bool {
+    data.len() < MAGIC.len() + 1 || !data.starts_with(MAGIC)
+}
+
+// The schema version and the compressed bincode
+fn get_schema_version(data: &[u8]) -> Result<(u16, &[u8]), CodecError> {
+    match data[MAGIC.len()] {
+        FORMAT_V1 => Ok((1, &data[MAGIC.len() + 1..])),
+        FORMAT_VERSION if data.len() >= HEADER_SIZE => {
+            let version = u16::from_le_bytes([data[MAGIC.len() + 1], data[MAGIC.len() + 2]]);
+            Ok((version, &data[HEADER_SIZE..]))
+        }
+        version => Err(CodecError::UnknownFormat(version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::CsfloatItemKind, tests::fixtures::get_listing};
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize)]
+    struct Listings(Vec<CsfloatListingStruct>);
+
+    impl SnapshotSchema for Listings {
+        const VERSION: u16 = 1;
+    }
+
+    // frozen layout of version 1
+    #[derive(Serialize, Deserialize)]
+    struct ItemV1 {
+        name: String,
+        price: u64,
+    }
+
+    impl SnapshotSchema for ItemV1 {
+        const VERSION: u16 = 1;
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        name: String,
+        price: u64,
+        float: Option<f64>,
+    }
+
+    impl SnapshotSchema for Item {
+        const VERSION: u16 = 2;
+
+        fn migrate(version: u16, encoded: &[u8]) -> Result<Self, CodecError> {
+            migrate_from::<ItemV1, _>(version, encoded)
+        }
+    }
+
+    impl From<ItemV1> for Item {
+        fn from(value: ItemV1) -> Self {
+            Item {
+                name: value.name,
+                price: value.price,
+                float: None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot_codec() {
+        let listings = Listings(
+            (0..100)
+                .map(|i| get_listing(&i.to_string(), "AK-47 | Redline (Field-Tested)", 10_00 + i))
+                .collect(),
+        );
+
+        let json = serde_json::to_vec(&listings).unwrap();
+        let encoded = encode(&listings).unwrap();
+        assert!(!is_outdated::<Listings>(&encoded));
+        assert!(encoded.len() * 4 < json.len());
+        let decoded: Listings = decode(&encoded).unwrap();
+        assert_eq!(serde_json::to_vec(&decoded).unwrap(), json);
+
+        // saved before the binary format
+        assert!(is_outdated::<Listings>(&json));
+        let decoded: Listings = decode(&json).unwrap();
+        assert_eq!(serde_json::to_vec(&decoded).unwrap(), json);
+
+        // saved before the schema version was written
+        let mut v1 = MAGIC.to_vec();
+        v1.push(FORMAT_V1);
+        v1.extend(zstd::encode_all(bincode::serialize(&listings).unwrap().as_slice(), 0).unwrap());
+        assert!(is_outdated::<Listings>(&v1));
+        let decoded: Listings = decode(&v1).unwrap();
+        assert_eq!(serde_json::to_vec(&decoded).unwrap(), json);
+
+        let mut newer = encoded.clone();
+        newer[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            decode::<Listings>(&newer),
+            Err(CodecError::UnknownFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_migration() {
+        let old = encode(&ItemV1 {
+            name: "Kilowatt Case".to_string(),
+            price: 1_00,
+        })
+        .unwrap();
+        assert!(is_outdated::<Item>(&old));
+        assert_eq!(
+            decode::<Item>(&old).unwrap(),
+            Item {
+                name: "Kilowatt Case".to_string(),
+                price: 1_00,
+                float: None,
+            }
+        );
+
+        // the previous version of the bot can't read it and keeps it as is
+        let new = encode(&Item {
+            name: "Kilowatt Case".to_string(),
+            price: 1_00,
+            float: Some(0.1),
+        })
+        .unwrap();
+        assert!(!is_outdated::<Item>(&new));
+        assert!(matches!(
+            decode::<ItemV1>(&new),
+            Err(CodecError::NewerSchema(2))
+        ));
+    }
+
+    #[test]
+    fn test_listing_migration() {
+        let old: CsfloatListingStructV2 = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "created_at": "2024-02-19T15:59:14.443752Z",
+            "price": 10_00,
+            "listing_type": "buy_now",
+            "state": "listed",
+            "item": {
+                "market_hash_name": "AK-47 | Redline (Field-Tested)",
+                "is_souvenir": false,
+                "is_stattrak": false,
+                "float_value": 0.25,
+                "stickers": [],
+                "keychains": [],
+                "patches": []
+            }
+        }))
+        .unwrap();
+        let encoded = encode(&old).unwrap();
+        assert!(is_outdated::<CsfloatListingStruct>(&encoded));
+        let listing = decode::<CsfloatListingStruct>(&encoded).unwrap();
+        assert_eq!(listing.item.float_value, Some(0.25));
+        assert_eq!(listing.item.rarity, None);
+        assert_eq!(listing.item.collection, None);
+        assert_eq!(listing.item.kind, None);
+        assert_eq!(listing.item.get_kind(), CsfloatItemKind::Skin);
+    }
+}
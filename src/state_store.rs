@@ -0,0 +1,1028 @@
+use std::{collections::HashMap, fmt, io::ErrorKind, path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Pool, Postgres, Row, Sqlite,
+};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{StateBackend, StateStoreConfig},
+    models::{CsfloatListingStruct, CsfloatListingStructV2, CsfloatListingStructV3},
+    prices::PriceValue,
+    sharding::Shard,
+    snapshot_codec::{self, CodecError, SnapshotSchema},
+    steam_analyzer::AnalysisResult,
+    steam_orders::SteamOrderBook,
+    types::{ListingId, MarketName},
+};
+
+#[derive(Debug)]
+pub enum StoreError {
+    Db(sqlx::Error),
+    Io(std::io::Error),
+    Codec(CodecError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Db(err) => write!(f, "db: {}", err),
+            StoreError::Io(err) => write!(f, "io: {}", err),
+            StoreError::Codec(err) => write!(f, "codec: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(err: sqlx::Error) -> Self {
+        StoreError::Db(err)
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(err: std::io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+impl From<CodecError> for StoreError {
+    fn from(err: CodecError) -> Self {
+        StoreError::Codec(err)
+    }
+}
+
+// Listing with the time of its last refresh
+pub type SavedListing = (CsfloatListingStruct, Option<DateTime<Utc>>);
+
+// Either of them may be missing, `updated_at` is the time of the save
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSteamEntry {
+    pub analysis: Option<AnalysisResult>,
+    pub order_book: Option<SteamOrderBook>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Where the engines are saved, see `DbSerializable`. Saves get only the entries
+// changed since the previous save, entries which failed to decode are skipped on load.
+// Only implemented in this crate, so `Send` bounds of the futures aren't needed.
+#[allow(async_fn_in_trait)]
+pub trait StateStore {
+    async fn load_csfloat_listings(&self) -> Result<Vec<SavedListing>, StoreError>;
+    async fn load_sticker_prices(&self) -> Result<Vec<(String, PriceValue)>, StoreError>;
+    async fn load_steam_entries(&self) -> Result<Vec<(MarketName, SavedSteamEntry)>, StoreError>;
+    async fn save_csfloat(
+        &self,
+        listings: &[SavedListing],
+        removed: &[ListingId],
+        sticker_prices: &[(String, PriceValue)],
+    ) -> Result<(), StoreError>;
+    async fn save_steam(
+        &self,
+        entries: &[(MarketName, Option<AnalysisResult>, Option<SteamOrderBook>)],
+        removed: &[MarketName],
+    ) -> Result<(), StoreError>;
+    // Whole-engine dumps of the old `rust_dump` table, only Postgres may have them
+    async fn load_legacy(&self, _key: &str) -> Option<String> {
+        None
+    }
+    async fn remove_legacy(&self, _key: &str) {}
+}
+
+type SteamChange = (MarketName, Option<AnalysisResult>, Option<SteamOrderBook>);
+
+// None when the value is broken or can't be migrated, the row is left as it is then.
+// The flag tells it's saved in an outdated format.
+fn decode_value<T: SnapshotSchema>(data: &[u8], what: &str, key: &str) -> Option<(T, bool)> {
+    match snapshot_codec::decode(data) {
+        Ok(value) => Some((value, snapshot_codec::is_outdated::<T>(data))),
+        Err(err) => {
+            error!("Failed to deserialize {} {}: {}", what, key, err);
+            None
+        }
+    }
+}
+
+// Listings of the shard and the ones of them saved in an outdated format, which are to be migrated
+fn decode_listings(
+    rows: impl Iterator<Item = (String, Option<Vec<u8>>, Option<DateTime<Utc>>)>,
+    shard: Shard,
+) -> (Vec<SavedListing>, Vec<SavedListing>) {
+    let mut listings = vec![];
+    let mut legacy = vec![];
+    for (id, data, updated_at) in rows {
+        if !shard.contains(&id) {
+            continue;
+        }
+        let Some((listing, is_legacy)) = data
+            .as_deref()
+            .and_then(|x| decode_value::<CsfloatListingStruct>(x, "csfloat listing", &id))
+        else {
+            continue;
+        };
+        if is_legacy {
+            legacy.push((listing.clone(), updated_at));
+        }
+        listings.push((listing, updated_at));
+    }
+    (listings, legacy)
+}
+
+// Same as `decode_listings`. Migrated analyses keep the time of the row as `analyzed_at`,
+// as the migration itself updates it.
+fn decode_steam_entries(
+    rows: impl Iterator<Item = (String, Option<Vec<u8>>, Option<Vec<u8>>, DateTime<Utc>)>,
+) -> (Vec<(MarketName, SavedSteamEntry)>, Vec<SteamChange>) {
+    let mut entries = vec![];
+    let mut legacy = vec![];
+    for (market_name, analysis, order_book, updated_at) in rows {
+        let analysis = analysis
+            .as_deref()
+            .and_then(|x| decode_value::<AnalysisResult>(x, "analysis", &market_name));
+        let order_book = order_book
+            .as_deref()
+            .and_then(|x| decode_value::<SteamOrderBook>(x, "order book", &market_name));
+        let is_legacy = analysis.as_ref().is_some_and(|(_, x)| *x)
+            || order_book.as_ref().is_some_and(|(_, x)| *x);
+        let entry = SavedSteamEntry {
+            analysis: analysis.map(|(x, _)| x),
+            order_book: order_book.map(|(x, _)| x),
+            updated_at,
+        };
+        let market_name = MarketName::from(market_name);
+        if is_legacy {
+            let mut analysis = entry.analysis.clone();
+            if let Some(analysis) = analysis.as_mut() {
+                analysis.analyzed_at.get_or_insert(updated_at);
+            }
+            legacy.push((market_name.clone(), analysis, entry.order_book.clone()));
+        }
+        entries.push((market_name, entry));
+    }
+    (entries, legacy)
+}
+
+async fn migrate_listings(store: &impl StateStore, legacy: &[SavedListing]) {
+    if legacy.is_empty() {
+        return;
+    }
+    info!(
+        "Migrating {} csfloat listings to the current format",
+        legacy.len()
+    );
+    if let Err(err) = store.save_csfloat(legacy, &[], &[]).await {
+        error!("Failed to migrate csfloat listings: {}", err);
+    }
+}
+
+async fn migrate_steam_entries(store: &impl StateStore, legacy: &[SteamChange]) {
+    if legacy.is_empty() {
+        return;
+    }
+    info!(
+        "Migrating {} steam entries to the current format",
+        legacy.len()
+    );
+    if let Err(err) = store.save_steam(legacy, &[]).await {
+        error!("Failed to migrate steam entries: {}", err);
+    }
+}
+
+fn encode_optional<T: SnapshotSchema>(value: &Option<T>) -> Result<Option<Vec<u8>>, CodecError> {
+    value.as_ref().map(snapshot_codec::encode).transpose()
+}
+
+// Tables are created by `storages::create_tables` together with the rest of the bot's ones.
+// Values are written to the `*_bin` columns, the JSON ones are left from the old format.
+// Sharded instances share the tables, each of them loads only the listings of its shard.
+pub struct PostgresStore {
+    db: Pool<Postgres>,
+    shard: Shard,
+}
+
+impl PostgresStore {
+    pub fn new(db: Pool<Postgres>, shard: Shard) -> Self {
+        PostgresStore { db, shard }
+    }
+}
+
+impl StateStore for PostgresStore {
+    async fn load_csfloat_listings(&self) -> Result<Vec<SavedListing>, StoreError> {
+        let rows = sqlx::query("SELECT id, data, data_bin, updated_at FROM csfloat_listings")
+            .fetch_all(&self.db)
+            .await?;
+        let (listings, legacy) = decode_listings(
+            rows.into_iter().map(|row| {
+                let data = row
+                    .get::<Option<Vec<u8>>, _>("data_bin")
+                    .or_else(|| row.get::<Option<String>, _>("data").map(String::into_bytes));
+                (row.get("id"), data, row.get("updated_at"))
+            }),
+            self.shard,
+        );
+        migrate_listings(self, &legacy).await;
+        Ok(listings)
+    }
+
+    async fn load_sticker_prices(&self) -> Result<Vec<(String, PriceValue)>, StoreError> {
+        let rows = sqlx::query("SELECT name, price FROM sticker_prices")
+            .fetch_all(&self.db)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get("name"), row.get::<i64, _>("price") as PriceValue))
+            .collect())
+    }
+
+    async fn load_steam_entries(&self) -> Result<Vec<(MarketName, SavedSteamEntry)>, StoreError> {
+        let rows = sqlx::query(
+            "SELECT market_name, analysis, order_book, analysis_bin, order_book_bin, updated_at
+            FROM steam_analysis",
+        )
+        .fetch_all(&self.db)
+        .await?;
+        let (entries, legacy) = decode_steam_entries(rows.into_iter().map(|row| {
+            let get_value = |column: &str| {
+                row.get::<Option<Vec<u8>>, _>(format!("{}_bin", column).as_str())
+                    .or_else(|| row.get::<Option<String>, _>(column).map(String::into_bytes))
+            };
+            (
+                row.get("market_name"),
+                get_value("analysis"),
+                get_value("order_book"),
+                row.get("updated_at"),
+            )
+        }));
+        migrate_steam_entries(self, &legacy).await;
+        Ok(entries)
+    }
+
+    async fn save_csfloat(
+        &self,
+        listings: &[SavedListing],
+        removed: &[ListingId],
+        sticker_prices: &[(String, PriceValue)],
+    ) -> Result<(), StoreError> {
+        if !listings.is_empty() {
+            sqlx::query(
+                "INSERT INTO csfloat_listings (id, market_hash_name, price, updated_at, data_bin)
+                SELECT * FROM UNNEST($1::text[], $2::text[], $3::bigint[], $4::timestamptz[], $5::bytea[])
+                ON CONFLICT (id) DO UPDATE SET market_hash_name = EXCLUDED.market_hash_name,
+                    price = EXCLUDED.price, updated_at = EXCLUDED.updated_at,
+                    data = NULL, data_bin = EXCLUDED.data_bin",
+            )
+            .bind(listings.iter().map(|(x, _)| x.id.clone()).collect::<Vec<_>>())
+            .bind(
+                listings
+                    .iter()
+                    .map(|(x, _)| x.item.market_hash_name.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind(listings.iter().map(|(x, _)| x.price as i64).collect::<Vec<_>>())
+            .bind(listings.iter().map(|(_, t)| *t).collect::<Vec<_>>())
+            .bind(
+                listings
+                    .iter()
+                    .map(|(x, _)| snapshot_codec::encode(x))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+            .execute(&self.db)
+            .await?;
+        }
+
+        if !removed.is_empty() {
+            sqlx::query("DELETE FROM csfloat_listings WHERE id = ANY($1)")
+                .bind(removed)
+                .execute(&self.db)
+                .await?;
+        }
+
+        if !sticker_prices.is_empty() {
+            let (names, prices): (Vec<String>, Vec<i64>) = sticker_prices
+                .iter()
+                .map(|(name, price)| (name.clone(), *price as i64))
+                .unzip();
+            sqlx::query(
+                "INSERT INTO sticker_prices (name, price)
+                SELECT * FROM UNNEST($1::text[], $2::bigint[])
+                ON CONFLICT (name) DO UPDATE SET price = EXCLUDED.price",
+            )
+            .bind(names)
+            .bind(prices)
+            .execute(&self.db)
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn save_steam(
+        &self,
+        entries: &[(MarketName, Option<AnalysisResult>, Option<SteamOrderBook>)],
+        removed: &[MarketName],
+    ) -> Result<(), StoreError> {
+        if !entries.is_empty() {
+            sqlx::query(
+                "INSERT INTO steam_analysis (market_name, analysis_bin, order_book_bin, updated_at)
+                SELECT *, now() FROM UNNEST($1::text[], $2::bytea[], $3::bytea[])
+                ON CONFLICT (market_name) DO UPDATE SET analysis = NULL, order_book = NULL,
+                    analysis_bin = EXCLUDED.analysis_bin, order_book_bin = EXCLUDED.order_book_bin,
+                    updated_at = EXCLUDED.updated_at",
+            )
+            .bind(
+                entries
+                    .iter()
+                    .map(|(name, _, _)| name.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind(
+                entries
+                    .iter()
+                    .map(|(_, x, _)| encode_optional(x))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+            .bind(
+                entries
+                    .iter()
+                    .map(|(_, _, x)| encode_optional(x))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+            .execute(&self.db)
+            .await?;
+        }
+
+        if !removed.is_empty() {
+            sqlx::query("DELETE FROM steam_analysis WHERE market_name = ANY($1)")
+                .bind(removed)
+                .execute(&self.db)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn load_legacy(&self, key: &str) -> Option<String> {
+        let key = self.shard.get_state_key(key);
+        match sqlx::query_scalar("SELECT value FROM rust_dump WHERE key = $1")
+            .bind(&key)
+            .fetch_one(&self.db)
+            .await
+        {
+            Ok(it) => it,
+            Err(err) => {
+                match err {
+                    sqlx::Error::RowNotFound => warn!("No saved state for {}", key),
+                    _ => {
+                        warn!("Failed to load state for {} {:?}", key, err);
+                    }
+                }
+
+                None
+            }
+        }
+    }
+
+    async fn remove_legacy(&self, key: &str) {
+        let key = self.shard.get_state_key(key);
+        if let Err(err) = sqlx::query("DELETE FROM rust_dump WHERE key = $1")
+            .bind(&key)
+            .execute(&self.db)
+            .await
+        {
+            error!("Failed to remove state for {}: {:?}", key, err);
+        }
+    }
+}
+
+// The same tables as in Postgres in a single file, e.g. `sqlite://state.db`. Columns aren't typed
+// in SQLite, so the values are written to the same ones as the JSON of the old format.
+pub struct SqliteStore {
+    db: Pool<Sqlite>,
+    shard: Shard,
+}
+
+impl SqliteStore {
+    pub async fn connect(url: &str, shard: Shard) -> Result<Self, StoreError> {
+        const QUERIES: [&str; 3] = [
+            "CREATE TABLE IF NOT EXISTS csfloat_listings (
+                id TEXT PRIMARY KEY,
+                market_hash_name TEXT NOT NULL,
+                price INTEGER NOT NULL,
+                updated_at TEXT,
+                data BLOB NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS steam_analysis (
+                market_name TEXT PRIMARY KEY,
+                analysis BLOB,
+                order_book BLOB,
+                updated_at TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS sticker_prices (
+                name TEXT PRIMARY KEY,
+                price INTEGER NOT NULL
+            )",
+        ];
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        // a single connection, so `sqlite::memory:` is the same database for all queries
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        for query in QUERIES {
+            sqlx::query(query).execute(&db).await?;
+        }
+        Ok(SqliteStore { db, shard })
+    }
+}
+
+impl StateStore for SqliteStore {
+    async fn load_csfloat_listings(&self) -> Result<Vec<SavedListing>, StoreError> {
+        let rows = sqlx::query("SELECT id, data, updated_at FROM csfloat_listings")
+            .fetch_all(&self.db)
+            .await?;
+        let (listings, legacy) = decode_listings(
+            rows.into_iter()
+                .map(|row| (row.get("id"), row.get("data"), row.get("updated_at"))),
+            self.shard,
+        );
+        migrate_listings(self, &legacy).await;
+        Ok(listings)
+    }
+
+    async fn load_sticker_prices(&self) -> Result<Vec<(String, PriceValue)>, StoreError> {
+        let rows = sqlx::query("SELECT name, price FROM sticker_prices")
+            .fetch_all(&self.db)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get("name"), row.get::<i64, _>("price") as PriceValue))
+            .collect())
+    }
+
+    async fn load_steam_entries(&self) -> Result<Vec<(MarketName, SavedSteamEntry)>, StoreError> {
+        let rows =
+            sqlx::query("SELECT market_name, analysis, order_book, updated_at FROM steam_analysis")
+                .fetch_all(&self.db)
+                .await?;
+        let (entries, legacy) = decode_steam_entries(rows.into_iter().map(|row| {
+            (
+                row.get("market_name"),
+                row.get("analysis"),
+                row.get("order_book"),
+                row.get("updated_at"),
+            )
+        }));
+        migrate_steam_entries(self, &legacy).await;
+        Ok(entries)
+    }
+
+    async fn save_csfloat(
+        &self,
+        listings: &[SavedListing],
+        removed: &[ListingId],
+        sticker_prices: &[(String, PriceValue)],
+    ) -> Result<(), StoreError> {
+        let mut tx = self.db.begin().await?;
+        for (listing, updated_at) in listings {
+            sqlx::query(
+                "INSERT INTO csfloat_listings (id, market_hash_name, price, updated_at, data)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (id) DO UPDATE SET market_hash_name = excluded.market_hash_name,
+                    price = excluded.price, updated_at = excluded.updated_at, data = excluded.data",
+            )
+            .bind(&*listing.id)
+            .bind(&*listing.item.market_hash_name)
+            .bind(listing.price as i64)
+            .bind(updated_at)
+            .bind(snapshot_codec::encode(listing)?)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for listing_id in removed {
+            sqlx::query("DELETE FROM csfloat_listings WHERE id = $1")
+                .bind(&**listing_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for (name, price) in sticker_prices {
+            sqlx::query(
+                "INSERT INTO sticker_prices (name, price) VALUES ($1, $2)
+                ON CONFLICT (name) DO UPDATE SET price = excluded.price",
+            )
+            .bind(name)
+            .bind(*price as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn save_steam(
+        &self,
+        entries: &[(MarketName, Option<AnalysisResult>, Option<SteamOrderBook>)],
+        removed: &[MarketName],
+    ) -> Result<(), StoreError> {
+        let now = Utc::now();
+        let mut tx = self.db.begin().await?;
+        for (market_name, analysis, order_book) in entries {
+            let analysis = encode_optional(analysis)?;
+            let order_book = encode_optional(order_book)?;
+            sqlx::query(
+                "INSERT INTO steam_analysis (market_name, analysis, order_book, updated_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (market_name) DO UPDATE SET analysis = excluded.analysis,
+                    order_book = excluded.order_book, updated_at = excluded.updated_at",
+            )
+            .bind(&**market_name)
+            .bind(analysis)
+            .bind(order_book)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for market_name in removed {
+            sqlx::query("DELETE FROM steam_analysis WHERE market_name = $1")
+                .bind(&**market_name)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CsfloatFile {
+    listings: HashMap<ListingId, SavedListing>,
+    sticker_prices: HashMap<String, PriceValue>,
+}
+
+// Frozen layout of `CsfloatFile` with `CsfloatListingStructV2`
+#[derive(Serialize, Deserialize)]
+struct CsfloatFileV1 {
+    listings: HashMap<ListingId, (CsfloatListingStructV2, Option<DateTime<Utc>>)>,
+    sticker_prices: HashMap<String, PriceValue>,
+}
+
+impl From<CsfloatFileV1> for CsfloatFileV2 {
+    fn from(value: CsfloatFileV1) -> Self {
+        CsfloatFileV2 {
+            listings: value
+                .listings
+                .into_iter()
+                .map(|(id, (listing, updated_at))| (id, (listing.into(), updated_at)))
+                .collect(),
+            sticker_prices: value.sticker_prices,
+        }
+    }
+}
+
+// Frozen layout of `CsfloatFile` with `CsfloatListingStructV3`
+#[derive(Serialize, Deserialize)]
+struct CsfloatFileV2 {
+    listings: HashMap<ListingId, (CsfloatListingStructV3, Option<DateTime<Utc>>)>,
+    sticker_prices: HashMap<String, PriceValue>,
+}
+
+impl From<CsfloatFileV2> for CsfloatFile {
+    fn from(value: CsfloatFileV2) -> Self {
+        CsfloatFile {
+            listings: value
+                .listings
+                .into_iter()
+                .map(|(id, (listing, updated_at))| (id, (listing.into(), updated_at)))
+                .collect(),
+            sticker_prices: value.sticker_prices,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SteamFile {
+    entries: HashMap<MarketName, SavedSteamEntry>,
+}
+
+// Bumped together with the saved types they contain, the migration decodes a frozen
+// copy of the file with the old layout of the changed type
+impl SnapshotSchema for CsfloatFileV1 {
+    const VERSION: u16 = 1;
+}
+
+impl SnapshotSchema for CsfloatFileV2 {
+    const VERSION: u16 = 2;
+
+    fn migrate(version: u16, encoded: &[u8]) -> Result<Self, CodecError> {
+        snapshot_codec::migrate_from::<CsfloatFileV1, _>(version, encoded)
+    }
+}
+
+impl SnapshotSchema for CsfloatFile {
+    const VERSION: u16 = 3;
+
+    fn migrate(version: u16, encoded: &[u8]) -> Result<Self, CodecError> {
+        snapshot_codec::migrate_from::<CsfloatFileV2, _>(version, encoded)
+    }
+}
+
+impl SnapshotSchema for SteamFile {
+    const VERSION: u16 = 1;
+}
+
+// Snapshot files in a directory, rewritten as a whole on each save. Fine for a few
+// thousand listings, it's meant for running without any database.
+// `<name>.json` files of the old format are read until the first save replaces them.
+// Names of the files of sharded instances end with the shard, e.g. `csfloat_shard_0.bin`.
+pub struct FileStore {
+    dir: PathBuf,
+    shard: Shard,
+    // saves read the file, apply the changes and write it back
+    lock: Mutex<()>,
+}
+
+const CSFLOAT_FILE: &str = "csfloat";
+const STEAM_FILE: &str = "steam";
+
+impl FileStore {
+    pub async fn open(dir: &str, shard: Shard) -> Result<Self, StoreError> {
+        tokio::fs::create_dir_all(dir).await?;
+        Ok(FileStore {
+            dir: PathBuf::from(dir),
+            shard,
+            lock: Mutex::new(()),
+        })
+    }
+
+    async fn read<T: SnapshotSchema + Default>(&self, name: &str) -> Result<T, StoreError> {
+        for path in [self.get_path(name), self.get_legacy_path(name)] {
+            let content = match tokio::fs::read(&path).await {
+                Ok(content) => content,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            return match snapshot_codec::decode(&content) {
+                Ok(value) => Ok(value),
+                Err(err) => {
+                    // kept for a manual recovery, the next save starts a new file
+                    let broken_path =
+                        path.with_extension(format!("{}.broken", Utc::now().timestamp()));
+                    tokio::fs::rename(&path, &broken_path).await?;
+                    error!(
+                        "Moved undecodable {} to {}",
+                        path.display(),
+                        broken_path.display()
+                    );
+                    Err(err.into())
+                }
+            };
+        }
+        Ok(T::default())
+    }
+
+    // Written next to the file first, so a crash in the middle leaves the old one
+    async fn write<T: SnapshotSchema>(&self, name: &str, value: &T) -> Result<(), StoreError> {
+        let path = self.get_path(name);
+        let tmp_path = path.with_extension("bin.tmp");
+        tokio::fs::write(&tmp_path, snapshot_codec::encode(value)?).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        match tokio::fs::remove_file(self.get_legacy_path(name)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn get_path(&self, name: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}.bin", self.shard.get_state_key(name)))
+    }
+
+    fn get_legacy_path(&self, name: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}.json", self.shard.get_state_key(name)))
+    }
+}
+
+impl StateStore for FileStore {
+    async fn load_csfloat_listings(&self) -> Result<Vec<SavedListing>, StoreError> {
+        let file: CsfloatFile = self.read(CSFLOAT_FILE).await?;
+        Ok(file
+            .listings
+            .into_values()
+            .filter(|(x, _)| self.shard.contains(&x.id))
+            .collect())
+    }
+
+    async fn load_sticker_prices(&self) -> Result<Vec<(String, PriceValue)>, StoreError> {
+        let file: CsfloatFile = self.read(CSFLOAT_FILE).await?;
+        Ok(file.sticker_prices.into_iter().collect())
+    }
+
+    async fn load_steam_entries(&self) -> Result<Vec<(MarketName, SavedSteamEntry)>, StoreError> {
+        let file: SteamFile = self.read(STEAM_FILE).await?;
+        Ok(file.entries.into_iter().collect())
+    }
+
+    async fn save_csfloat(
+        &self,
+        listings: &[SavedListing],
+        removed: &[ListingId],
+        sticker_prices: &[(String, PriceValue)],
+    ) -> Result<(), StoreError> {
+        if listings.is_empty() && removed.is_empty() && sticker_prices.is_empty() {
+            return Ok(());
+        }
+        let _lock = self.lock.lock().await;
+        let mut file: CsfloatFile = self.read(CSFLOAT_FILE).await?;
+        for (listing, updated_at) in listings {
+            file.listings
+                .insert(listing.id.clone(), (listing.clone(), *updated_at));
+        }
+        for listing_id in removed {
+            file.listings.remove(listing_id);
+        }
+        file.sticker_prices.extend(sticker_prices.iter().cloned());
+        self.write(CSFLOAT_FILE, &file).await
+    }
+
+    async fn save_steam(
+        &self,
+        entries: &[(MarketName, Option<AnalysisResult>, Option<SteamOrderBook>)],
+        removed: &[MarketName],
+    ) -> Result<(), StoreError> {
+        if entries.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
+        let _lock = self.lock.lock().await;
+        let now = Utc::now();
+        let mut file: SteamFile = self.read(STEAM_FILE).await?;
+        for (market_name, analysis, order_book) in entries {
+            let entry = SavedSteamEntry {
+                analysis: analysis.clone(),
+                order_book: order_book.clone(),
+                updated_at: now,
+            };
+            file.entries.insert(market_name.clone(), entry);
+        }
+        for market_name in removed {
+            file.entries.remove(market_name);
+        }
+        self.write(STEAM_FILE, &file).await
+    }
+}
+
+// The store selected by `state_store.backend`
+pub enum AnyStateStore {
+    Postgres(PostgresStore),
+    Sqlite(SqliteStore),
+    File(FileStore),
+}
+
+impl AnyStateStore {
+    // `db` is used by the Postgres backend, the rest of the bot keeps using it anyway
+    pub async fn connect(
+        config: &StateStoreConfig,
+        shard: Shard,
+        db: &Pool<Postgres>,
+    ) -> Result<Self, StoreError> {
+        Ok(match config.backend {
+            StateBackend::Postgres => {
+                AnyStateStore::Postgres(PostgresStore::new(db.clone(), shard))
+            }
+            StateBackend::Sqlite => {
+                AnyStateStore::Sqlite(SqliteStore::connect(&config.sqlite_url, shard).await?)
+            }
+            StateBackend::File => {
+                AnyStateStore::File(FileStore::open(&config.file_dir, shard).await?)
+            }
+        })
+    }
+}
+
+impl StateStore for AnyStateStore {
+    async fn load_csfloat_listings(&self) -> Result<Vec<SavedListing>, StoreError> {
+        match self {
+            AnyStateStore::Postgres(store) => store.load_csfloat_listings().await,
+            AnyStateStore::Sqlite(store) => store.load_csfloat_listings().await,
+            AnyStateStore::File(store) => store.load_csfloat_listings().await,
+        }
+    }
+
+    async fn load_sticker_prices(&self) -> Result<Vec<(String, PriceValue)>, StoreError> {
+        match self {
+            AnyStateStore::Postgres(store) => store.load_sticker_prices().await,
+            AnyStateStore::Sqlite(store) => store.load_sticker_prices().await,
+            AnyStateStore::File(store) => store.load_sticker_prices().await,
+        }
+    }
+
+    async fn load_steam_entries(&self) -> Result<Vec<(MarketName, SavedSteamEntry)>, StoreError> {
+        match self {
+            AnyStateStore::Postgres(store) => store.load_steam_entries().await,
+            AnyStateStore::Sqlite(store) => store.load_steam_entries().await,
+            AnyStateStore::File(store) => store.load_steam_entries().await,
+        }
+    }
+
+    async fn save_csfloat(
+        &self,
+        listings: &[SavedListing],
+        removed: &[ListingId],
+        sticker_prices: &[(String, PriceValue)],
+    ) -> Result<(), StoreError> {
+        match self {
+            AnyStateStore::Postgres(store) => {
+                store.save_csfloat(listings, removed, sticker_prices).await
+            }
+            AnyStateStore::Sqlite(store) => {
+                store.save_csfloat(listings, removed, sticker_prices).await
+            }
+            AnyStateStore::File(store) => {
+                store.save_csfloat(listings, removed, sticker_prices).await
+            }
+        }
+    }
+
+    async fn save_steam(
+        &self,
+        entries: &[(MarketName, Option<AnalysisResult>, Option<SteamOrderBook>)],
+        removed: &[MarketName],
+    ) -> Result<(), StoreError> {
+        match self {
+            AnyStateStore::Postgres(store) => store.save_steam(entries, removed).await,
+            AnyStateStore::Sqlite(store) => store.save_steam(entries, removed).await,
+            AnyStateStore::File(store) => store.save_steam(entries, removed).await,
+        }
+    }
+
+    async fn load_legacy(&self, key: &str) -> Option<String> {
+        match self {
+            AnyStateStore::Postgres(store) => store.load_legacy(key).await,
+            AnyStateStore::Sqlite(_) | AnyStateStore::File(_) => None,
+        }
+    }
+
+    async fn remove_legacy(&self, key: &str) {
+        match self {
+            AnyStateStore::Postgres(store) => store.remove_legacy(key).await,
+            AnyStateStore::Sqlite(_) | AnyStateStore::File(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        steam_analyzer::{Smoothing, Trend},
+        tests::fixtures::get_listing,
+    };
+
+    fn listing(id: &str, price: u64) -> CsfloatListingStruct {
+        get_listing(id, "AK-47 | Redline (Field-Tested)", price)
+    }
+
+    fn analysis() -> AnalysisResult {
+        AnalysisResult {
+            rsd: Some(0.01),
+            is_stable: Some(true),
+            sold_per_week: Some(500),
+            percentiles: vec![(60, 1_00)],
+            percentiles_no_fee: vec![(60, 87)],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: None,
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
+        }
+    }
+
+    async fn check_roundtrip(store: &impl StateStore) {
+        let updated_at = Some(Utc::now());
+        store
+            .save_csfloat(
+                &[
+                    (listing("1", 10_00), updated_at),
+                    (listing("2", 12_00), None),
+                ],
+                &[],
+                &[("Sticker | Crown (Foil)".to_string(), 50_000)],
+            )
+            .await
+            .unwrap();
+        store
+            .save_csfloat(
+                &[(listing("1", 9_00), updated_at)],
+                &["2".into()],
+                &[("Sticker | Crown (Foil)".to_string(), 45_000)],
+            )
+            .await
+            .unwrap();
+        let listings = store.load_csfloat_listings().await.unwrap();
+        assert_eq!(listings.len(), 1);
+        assert_eq!(listings[0].0.id, "1");
+        assert_eq!(listings[0].0.price, 9_00);
+        assert_eq!(
+            listings[0].1.map(|x| x.timestamp_millis()),
+            updated_at.map(|x| x.timestamp_millis())
+        );
+        assert_eq!(
+            store.load_sticker_prices().await.unwrap(),
+            vec![("Sticker | Crown (Foil)".to_string(), 45_000)]
+        );
+
+        store
+            .save_steam(
+                &[
+                    ("Kilowatt Case".into(), Some(analysis()), None),
+                    ("Revolution Case".into(), Some(analysis()), None),
+                ],
+                &[],
+            )
+            .await
+            .unwrap();
+        store
+            .save_steam(&[], &["Revolution Case".into()])
+            .await
+            .unwrap();
+        let entries = store.load_steam_entries().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, "Kilowatt Case");
+        assert_eq!(entries[0].1.analysis, Some(analysis()));
+        assert!(entries[0].1.order_book.is_none());
+
+        assert_eq!(store.load_legacy("csfloat_engine").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let store = SqliteStore::connect("sqlite::memory:", Shard::default())
+            .await
+            .unwrap();
+        check_roundtrip(&store).await;
+
+        // saved before the binary format
+        sqlx::query("INSERT INTO csfloat_listings (id, market_hash_name, price, data) VALUES ($1, $2, $3, $4)")
+            .bind("3")
+            .bind("AK-47 | Redline (Field-Tested)")
+            .bind(8_00)
+            .bind(serde_json::to_string(&listing("3", 8_00)).unwrap())
+            .execute(&store.db)
+            .await
+            .unwrap();
+        assert_eq!(store.load_csfloat_listings().await.unwrap().len(), 2);
+        let data: Vec<u8> = sqlx::query_scalar("SELECT data FROM csfloat_listings WHERE id = '3'")
+            .fetch_one(&store.db)
+            .await
+            .unwrap();
+        assert!(!snapshot_codec::is_outdated::<CsfloatListingStruct>(&data));
+
+        // instances sharing the tables split the listings
+        let mut loaded = vec![];
+        for index in 0..2 {
+            let sharded = SqliteStore {
+                db: store.db.clone(),
+                shard: Shard::new(index, 2).unwrap(),
+            };
+            for (listing, _) in sharded.load_csfloat_listings().await.unwrap() {
+                assert!(sharded.shard.contains(&listing.id));
+                loaded.push(listing.id);
+            }
+        }
+        loaded.sort();
+        assert_eq!(loaded, vec![ListingId::from("1"), ListingId::from("3")]);
+    }
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let dir = std::env::temp_dir().join(format!("state_store_test_{}", std::process::id()));
+        let store = FileStore::open(dir.to_str().unwrap(), Shard::default())
+            .await
+            .unwrap();
+        let legacy = serde_json::json!({
+            "listings": {},
+            "sticker_prices": {"Sticker | Crown (Foil)": 40_000}
+        });
+        std::fs::write(dir.join("csfloat.json"), legacy.to_string()).unwrap();
+        assert_eq!(store.load_sticker_prices().await.unwrap().len(), 1);
+        check_roundtrip(&store).await;
+        assert!(!dir.join("csfloat.json").exists());
+        // a new store sees what the previous one saved
+        let reopened = FileStore::open(dir.to_str().unwrap(), Shard::default())
+            .await
+            .unwrap();
+        assert_eq!(reopened.load_csfloat_listings().await.unwrap().len(), 1);
+
+        // written by a newer version, it's moved aside instead of being overwritten
+        std::fs::write(dir.join("steam.bin"), b"SCSB\x09").unwrap();
+        assert!(reopened.load_steam_entries().await.is_err());
+        assert!(!dir.join("steam.bin").exists());
+        assert!(reopened.load_steam_entries().await.unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,34 +1,218 @@
 use std::collections::HashMap;
 
 use circular_buffer::CircularBuffer;
+use serde::Serialize;
 use std::fmt::Write;
 use std::time::Duration;
 use tracing::info;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+use crate::{
+    events::{PrimEvent, SecEvent},
+    strategies::StrategyName,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum StatsKind {
     CsfloatOneListingResponse,
     CsfloatListingsResponse,
     SteamResponse,
     UpdatedCsfloatListings,
+    SkinportListingsResponse,
+    DmarketListingsResponse,
+    SteamOrdersResponse,
     ProfitableListing,
+    Alert,
+    PaperPurchase,
+    PaperPurchaseChecked,
+    AuctionOpportunity,
+    SteamAnalysisRequested,
+    SteamAnalysisReady,
+    PurchaseConfirmed,
+    BuyRequested,
+}
+
+impl From<&PrimEvent> for StatsKind {
+    fn from(event: &PrimEvent) -> Self {
+        match event {
+            PrimEvent::CsfloatOneListingResponse(_) => StatsKind::CsfloatOneListingResponse,
+            PrimEvent::CsfloatListingsResponse(_) => StatsKind::CsfloatListingsResponse,
+            PrimEvent::SteamResponse(_) => StatsKind::SteamResponse,
+            PrimEvent::UpdatedCsfloatListings(_) => StatsKind::UpdatedCsfloatListings,
+            PrimEvent::SkinportListingsResponse(_) => StatsKind::SkinportListingsResponse,
+            PrimEvent::DmarketListingsResponse(_) => StatsKind::DmarketListingsResponse,
+            PrimEvent::SteamOrdersResponse(_) => StatsKind::SteamOrdersResponse,
+            PrimEvent::PaperPurchase(_) => StatsKind::PaperPurchase,
+            PrimEvent::SteamAnalysisRequested(_) => StatsKind::SteamAnalysisRequested,
+            PrimEvent::SteamAnalysisReady(_) => StatsKind::SteamAnalysisReady,
+        }
+    }
+}
+
+impl From<&SecEvent> for StatsKind {
+    fn from(event: &SecEvent) -> Self {
+        match event {
+            SecEvent::ProfitableListing(_) => StatsKind::ProfitableListing,
+            SecEvent::Alert(_) => StatsKind::Alert,
+            SecEvent::PaperPurchaseChecked(_) => StatsKind::PaperPurchaseChecked,
+            SecEvent::AuctionOpportunity(_) => StatsKind::AuctionOpportunity,
+            SecEvent::PurchaseConfirmed(_) => StatsKind::PurchaseConfirmed,
+            SecEvent::BuyRequested(_) => StatsKind::BuyRequested,
+        }
+    }
+}
+
+// Events that are only counted
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum StatsCounter {
+    CsfloatForbidden,
+    CsfloatRateLimited,
+    // removed by `scheduler.listing_ttl_secs`
+    CsfloatListingExpired,
+    // the single listing endpoint returned 404
+    CsfloatListingNotFound,
+    // by the `code` of the error body
+    CsfloatApiError(i64),
+    // the queue was full
+    Dropped(StatsKind),
+    // skipped by the dispatcher while the queue was filling up
+    Shed(StatsKind),
+    // profitable listing events found by the strategy
+    StrategySignals(StrategyName),
+    // successful purchases, including paper ones
+    StrategyBuys(StrategyName),
+    // CSFloat listings skipped before the engine by the name of the check or prefilter rule
+    PrefilterRejected(String),
+}
+
+// Current values, sampled periodically and overwritten, see `spawn_stats_sampler`
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum StatsGauge {
+    // events waiting in the queue, by `QueueDepth::name`
+    QueueDepth(&'static str),
+    SchedulerSize,
+    CsfloatEngineSize,
+    SteamEngineSize,
+    SkinportEngineSize,
+    DmarketEngineSize,
+    // seconds since the newest imported response of the source, it also grows
+    // while nothing new is written by the fetcher
+    ImporterLagSecs(&'static str),
+    // limit of the next import of the source, see `AdaptiveBatch`
+    ImporterBatchSize(&'static str),
+    // 1 while the importer polls without the interval to catch up
+    ImporterCatchingUp,
 }
 
 const STATS_SIZE: usize = 1_000;
 
+// Processing time of the last events of a kind, in microseconds
+#[derive(Debug, Serialize)]
+pub struct DurationsSnapshot {
+    pub kind: String,
+    // events processed since the start, `records` are the last of them
+    pub total: u64,
+    pub records: usize,
+    pub mean_us: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    pub durations: Vec<DurationsSnapshot>,
+    pub counters: Vec<(String, u64)>,
+    pub gauges: Vec<(String, u64)>,
+}
+
 pub struct Stats {
     hm: HashMap<StatsKind, CircularBuffer<STATS_SIZE, Duration>>,
+    processed: HashMap<StatsKind, u64>,
+    counters: HashMap<StatsCounter, u64>,
+    gauges: HashMap<StatsGauge, u64>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new()
+    }
 }
 
 impl Stats {
     pub fn new() -> Stats {
-        Stats { hm: HashMap::new() }
+        Stats {
+            hm: HashMap::new(),
+            processed: HashMap::new(),
+            counters: HashMap::new(),
+            gauges: HashMap::new(),
+        }
     }
+
+    pub fn increment(&mut self, counter: StatsCounter) {
+        self.increment_by(counter, 1);
+    }
+
+    pub fn increment_by(&mut self, counter: StatsCounter, value: u64) {
+        *self.counters.entry(counter).or_default() += value;
+    }
+
+    pub fn set_gauge(&mut self, gauge: StatsGauge, value: u64) {
+        self.gauges.insert(gauge, value);
+    }
+
+    // Dropped events of all kinds since the start
+    pub fn get_dropped_total(&self) -> u64 {
+        self.counters
+            .iter()
+            .filter(|(counter, _)| matches!(counter, StatsCounter::Dropped(_)))
+            .map(|(_, value)| value)
+            .sum()
+    }
+
     pub fn register_duration(&mut self, kind: StatsKind, duration: Duration) {
+        *self.processed.entry(kind).or_default() += 1;
         let entry = self.hm.entry(kind).or_default();
         entry.push_back(duration)
     }
 
+    // The same as `print` logs, kinds and counters are named by their Debug output
+    pub fn get_snapshot(&self) -> StatsSnapshot {
+        let mut durations: Vec<DurationsSnapshot> = self
+            .hm
+            .iter()
+            .filter(|(_, durations)| !durations.is_empty())
+            .map(|(kind, durations)| DurationsSnapshot {
+                kind: format!("{:?}", kind),
+                total: self.processed.get(kind).copied().unwrap_or_default(),
+                records: durations.len(),
+                mean_us: (durations.iter().sum::<Duration>() / durations.len() as u32).as_micros()
+                    as u64,
+                p50_us: self.get_percentile(durations, 50).as_micros() as u64,
+                p99_us: self.get_percentile(durations, 99).as_micros() as u64,
+            })
+            .collect();
+        durations.sort_by(|a, b| a.kind.cmp(&b.kind));
+
+        let mut counters: Vec<(String, u64)> = self
+            .counters
+            .iter()
+            .map(|(counter, value)| (format!("{:?}", counter), *value))
+            .collect();
+        counters.sort();
+
+        let mut gauges: Vec<(String, u64)> = self
+            .gauges
+            .iter()
+            .map(|(gauge, value)| (format!("{:?}", gauge), *value))
+            .collect();
+        gauges.sort();
+
+        StatsSnapshot {
+            durations,
+            counters,
+            gauges,
+        }
+    }
+
     pub fn print(&self) {
         const PERCENTILES: [u32; 4] = [50, 90, 95, 99];
 
@@ -69,6 +253,14 @@ impl Stats {
             }
         }
 
+        for (counter, value) in &self.counters {
+            writeln!(buffer, "Counter {:?}: {}", counter, value).unwrap();
+        }
+
+        for (gauge, value) in &self.gauges {
+            writeln!(buffer, "Gauge {:?}: {}", gauge, value).unwrap();
+        }
+
         // Print all accumulated log messages at once
         info!("{}", buffer);
     }
@@ -93,3 +285,23 @@ impl Stats {
         *sorted_times[index]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gauges() {
+        let mut stats = Stats::new();
+        stats.set_gauge(StatsGauge::QueueDepth("primary"), 10);
+        stats.set_gauge(StatsGauge::SchedulerSize, 500);
+        stats.set_gauge(StatsGauge::QueueDepth("primary"), 3);
+        assert_eq!(
+            stats.get_snapshot().gauges,
+            vec![
+                ("QueueDepth(\"primary\")".to_string(), 3),
+                ("SchedulerSize".to_string(), 500),
+            ]
+        );
+    }
+}
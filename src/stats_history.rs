@@ -0,0 +1,273 @@
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{error, info};
+
+use crate::{
+    config::SharedConfig,
+    shutdown::ShutdownSignal,
+    stats::{Stats, StatsSnapshot},
+};
+
+// Metrics are named "<group>.<Debug name of the stat>":
+// processed - events processed since the previous flush,
+// p50_us, p99_us - processing time of the last events, only when some were processed,
+// counter - increments since the previous flush, gauge - the current value
+const SUMMED_GROUPS: [&str; 2] = ["processed.", "counter."];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatsPoint {
+    pub created_at: DateTime<Utc>,
+    pub name: String,
+    pub value: f64,
+}
+
+// Turns the cumulative values of `Stats` into the deltas since the previous flush.
+// The first flush after a start counts everything since the start.
+#[derive(Debug, Default)]
+pub struct StatsFlusher {
+    last: HashMap<String, u64>,
+}
+
+impl StatsFlusher {
+    pub fn new() -> Self {
+        StatsFlusher::default()
+    }
+
+    fn take_delta(&mut self, name: String, value: u64) -> Option<(String, u64)> {
+        let last = self.last.insert(name.clone(), value).unwrap_or_default();
+        // a smaller value means the stats were reset
+        let delta = value.checked_sub(last).unwrap_or(value);
+        (delta > 0).then_some((name, delta))
+    }
+
+    pub fn build_points(
+        &mut self,
+        snapshot: &StatsSnapshot,
+        now: DateTime<Utc>,
+    ) -> Vec<StatsPoint> {
+        let mut points = vec![];
+        let mut push = |name: String, value: f64| {
+            points.push(StatsPoint {
+                created_at: now,
+                name,
+                value,
+            })
+        };
+        for durations in snapshot.durations.iter() {
+            let name = format!("processed.{}", durations.kind);
+            let Some((name, delta)) = self.take_delta(name, durations.total) else {
+                continue;
+            };
+            push(name, delta as f64);
+            push(
+                format!("p50_us.{}", durations.kind),
+                durations.p50_us as f64,
+            );
+            push(
+                format!("p99_us.{}", durations.kind),
+                durations.p99_us as f64,
+            );
+        }
+        for (counter, value) in snapshot.counters.iter() {
+            if let Some((name, delta)) = self.take_delta(format!("counter.{}", counter), *value) {
+                push(name, delta as f64);
+            }
+        }
+        for (gauge, value) in snapshot.gauges.iter() {
+            push(format!("gauge.{}", gauge), *value as f64);
+        }
+        points
+    }
+}
+
+pub async fn record_points(
+    db: &Pool<Postgres>,
+    shard: &str,
+    points: &[StatsPoint],
+) -> Result<(), sqlx::Error> {
+    if points.is_empty() {
+        return Ok(());
+    }
+    let created_at: Vec<DateTime<Utc>> = points.iter().map(|x| x.created_at).collect();
+    let names: Vec<&str> = points.iter().map(|x| x.name.as_str()).collect();
+    let values: Vec<f64> = points.iter().map(|x| x.value).collect();
+    sqlx::query(
+        "INSERT INTO bot_stats (created_at, shard, name, value)
+        SELECT created_at, $2, name, value
+        FROM UNNEST($1::timestamptz[], $3::text[], $4::float8[]) AS x(created_at, name, value)",
+    )
+    .bind(&created_at)
+    .bind(shard)
+    .bind(&names)
+    .bind(&values)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_points_before(
+    db: &Pool<Postgres>,
+    before: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM bot_stats WHERE created_at < $1")
+        .bind(before)
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Bucket {
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Bucket::Hour => "hour",
+            Bucket::Day => "day",
+        }
+    }
+}
+
+// Metrics starting with `prefix` of all shards in buckets, oldest first. Processed events
+// and counters are summed, latencies and gauges are averaged.
+pub async fn load_buckets(
+    db: &Pool<Postgres>,
+    prefix: &str,
+    since: DateTime<Utc>,
+    bucket: Bucket,
+) -> Result<Vec<StatsPoint>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT date_trunc($1, created_at) AS bucket, name,
+            CASE WHEN name LIKE $4 OR name LIKE $5 THEN SUM(value) ELSE AVG(value) END AS value
+        FROM bot_stats
+        WHERE created_at >= $2 AND starts_with(name, $3)
+        GROUP BY bucket, name
+        ORDER BY bucket, name",
+    )
+    .bind(bucket.as_str())
+    .bind(since)
+    .bind(prefix)
+    .bind(format!("{}%", SUMMED_GROUPS[0]))
+    .bind(format!("{}%", SUMMED_GROUPS[1]))
+    .fetch_all(db)
+    .await?;
+
+    let points = rows
+        .into_iter()
+        .map(|row| StatsPoint {
+            created_at: row.get("bucket"),
+            name: row.get("name"),
+            value: row.get("value"),
+        })
+        .collect();
+    Ok(points)
+}
+
+async fn flush(
+    flusher: &mut StatsFlusher,
+    stats: &Mutex<Stats>,
+    pool: &Pool<Postgres>,
+    shard: &str,
+) {
+    let snapshot = stats.lock().await.get_snapshot();
+    let points = flusher.build_points(&snapshot, Utc::now());
+    if let Err(err) = record_points(pool, shard, &points).await {
+        error!("Failed to flush {} stats points: {:?}", points.len(), err);
+    }
+}
+
+// Flushes `Stats` every `stats_history.flush_interval_secs` and once more on shutdown,
+// so the history has no gap of a restart besides the downtime itself
+pub fn spawn_stats_flusher(
+    stats: Arc<Mutex<Stats>>,
+    pool: Pool<Postgres>,
+    shard: String,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut flusher = StatsFlusher::new();
+        let flush_interval = config.load().stats_history.flush_interval();
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(flush_interval) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            let current_config = config.load();
+            if !current_config.stats_history.enabled {
+                continue;
+            }
+            flush(&mut flusher, &stats, &pool, &shard).await;
+
+            let retention_days = current_config.stats_history.retention_days;
+            if retention_days > 0 {
+                let before = Utc::now() - Duration::days(retention_days);
+                match delete_points_before(&pool, before).await {
+                    Ok(0) => {}
+                    Ok(deleted) => info!("Deleted {} stats points older than {}", deleted, before),
+                    Err(err) => error!("Failed to delete old stats points: {:?}", err),
+                }
+            }
+        }
+        if config.load().stats_history.enabled {
+            flush(&mut flusher, &stats, &pool, &shard).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::{StatsCounter, StatsGauge, StatsKind};
+
+    #[test]
+    fn test_build_points() {
+        let now = Utc::now();
+        let mut stats = Stats::new();
+        let mut flusher = StatsFlusher::new();
+        assert_eq!(flusher.build_points(&stats.get_snapshot(), now), vec![]);
+
+        stats.register_duration(
+            StatsKind::SteamResponse,
+            std::time::Duration::from_millis(2),
+        );
+        stats.register_duration(
+            StatsKind::SteamResponse,
+            std::time::Duration::from_millis(4),
+        );
+        stats.increment(StatsCounter::CsfloatRateLimited);
+        stats.set_gauge(StatsGauge::SchedulerSize, 500);
+        let names = |points: Vec<StatsPoint>| -> Vec<(String, f64)> {
+            points.into_iter().map(|x| (x.name, x.value)).collect()
+        };
+        assert_eq!(
+            names(flusher.build_points(&stats.get_snapshot(), now)),
+            vec![
+                ("processed.SteamResponse".to_string(), 2.0),
+                ("p50_us.SteamResponse".to_string(), 4_000.0),
+                ("p99_us.SteamResponse".to_string(), 4_000.0),
+                ("counter.CsfloatRateLimited".to_string(), 1.0),
+                ("gauge.SchedulerSize".to_string(), 500.0),
+            ]
+        );
+
+        // only the increments since the previous flush
+        stats.increment_by(StatsCounter::CsfloatRateLimited, 3);
+        assert_eq!(
+            names(flusher.build_points(&stats.get_snapshot(), now)),
+            vec![
+                ("counter.CsfloatRateLimited".to_string(), 3.0),
+                ("gauge.SchedulerSize".to_string(), 500.0),
+            ]
+        );
+    }
+}
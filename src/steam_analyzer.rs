@@ -2,19 +2,22 @@
 
 use std::ops::Add;
 
-use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, Timelike, Utc};
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    config::SteamAnalyzerConfig,
     consts::PERCENTILES,
     prices::{PriceValue, PriceValueTrait},
 };
 
 const MEDIAN_LOWER_LIMIT_COEF: f64 = 0.9;
 const MEDIAN_UPPER_LIMIT_COEF: f64 = 1.1;
-const REL_STD_MAX: f64 = 0.03;
+pub const REL_STD_MAX: f64 = 0.03;
+// days with sales at the sell hour needed to tell its price from the noise
+const SELL_HOUR_MIN_POINTS: usize = 3;
 
 #[derive(Deserialize)]
 struct Point {
@@ -65,16 +68,20 @@ pub fn extract_sell_history(
     Vec::new()
 }
 
+// `usd_rate` converts the prices of the wallet currency of the response to USD
 pub fn analyze_steam_sell_history(
     response: &str,
     current_datetime: DateTime<Utc>,
+    usd_rate: f64,
+    config: &SteamAnalyzerConfig,
 ) -> Option<AnalysisResult> {
-    let days = 7;
+    let days = config.window_days.max(1);
     let date_range_start = current_datetime - Duration::days(days);
     let history_data = extract_sell_history(response, date_range_start);
     let filtered_data: Vec<_> = history_data
         .into_iter()
         .filter(|&(date, _, _)| date_range_start <= date && date <= current_datetime)
+        .map(|(date, price, amount)| (date, price * usd_rate, amount))
         .collect();
 
     let mut prices: Vec<f64> = filtered_data
@@ -85,7 +92,7 @@ pub fn analyze_steam_sell_history(
         return None;
     }
 
-    prices.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    prices.sort_unstable_by(|a, b| a.total_cmp(b));
     let mid = prices.len() / 2;
     let median = {
         if prices.len() % 2 == 0 {
@@ -97,7 +104,37 @@ pub fn analyze_steam_sell_history(
     let upper_limit = median * MEDIAN_UPPER_LIMIT_COEF;
     let lower_limit = median * MEDIAN_LOWER_LIMIT_COEF;
 
-    let sold_per_week = filtered_data.iter().map(|x| x.2).sum::<i32>();
+    // normalized to 7 days, so it's comparable between window lengths
+    let sold_per_week = (filtered_data.iter().map(|x| x.2 as i64).sum::<i64>() * 7 / days) as i32;
+
+    let points: Vec<(f64, i32)> = filtered_data.iter().map(|x| (x.1, x.2)).collect();
+    let (points, rejected_outliers) = reject_outliers_by_mad(points, config.mad_threshold);
+    let weighted_percentiles: Vec<(u8, PriceValue)> = PERCENTILES
+        .iter()
+        .filter_map(|(percentile_value, percentile)| {
+            calculate_weighted_percentile(&points, *percentile)
+                .map(|price| (*percentile_value, PriceValue::from_usd_f64(price)))
+        })
+        .collect();
+
+    // the same points as used for the stability, so a single spike doesn't make a trend
+    let trend_points: Vec<(f64, f64)> = filtered_data
+        .iter()
+        .filter(|x| lower_limit <= x.1 && x.1 <= upper_limit)
+        .map(|x| {
+            (
+                (x.0 - date_range_start).num_minutes() as f64 / (24.0 * 60.0),
+                x.1,
+            )
+        })
+        .collect();
+    let trend = detect_trend(&trend_points, config.flat_trend_pct_per_day);
+    let seasonality_points: Vec<(DateTime<Utc>, f64, i32)> = filtered_data
+        .iter()
+        .filter(|x| lower_limit <= x.1 && x.1 <= upper_limit)
+        .copied()
+        .collect();
+    let seasonality = calculate_seasonality(&seasonality_points, config.sell_hour);
 
     let mut prices: Vec<_> = filtered_data
         .into_iter()
@@ -105,22 +142,30 @@ pub fn analyze_steam_sell_history(
         .filter(|&p| lower_limit <= p && p <= upper_limit)
         .collect();
 
-    let sma = simple_moving_average(&prices, 3);
-    if sma.is_empty() {
+    let smoothed = smooth(&prices, config);
+    if smoothed.is_empty() {
         return Some(AnalysisResult {
             rsd: None,
             is_stable: None,
             sold_per_week: None,
             percentiles: vec![],
             percentiles_no_fee: vec![],
+            weighted_percentiles,
+            rejected_outliers,
+            trend,
+            analyzed_at: Some(current_datetime),
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: config.smoothing,
+            seasonality,
         });
     }
-    let sma_mean = mean(&sma).unwrap();
-    let sma_std = std_deviation(&sma, sma_mean).unwrap();
-    let sma_rel_std = sma_std / sma_mean;
+    let smoothed_mean = mean(&smoothed).unwrap();
+    let smoothed_std = std_deviation(&smoothed, smoothed_mean).unwrap();
+    let smoothed_rel_std = smoothed_std / smoothed_mean;
 
-    let is_stable = sma_rel_std < REL_STD_MAX;
-    prices.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    let is_stable = smoothed_rel_std < REL_STD_MAX;
+    prices.sort_unstable_by(|a, b| a.total_cmp(b));
 
     let percentiles: Vec<(u8, PriceValue)> = PERCENTILES
         .iter()
@@ -132,14 +177,147 @@ pub fn analyze_steam_sell_history(
         .collect();
 
     Some(AnalysisResult {
-        rsd: Some(sma_rel_std),
+        rsd: Some(smoothed_rel_std),
         is_stable: Some(is_stable),
         sold_per_week: Some(sold_per_week),
         percentiles,
         percentiles_no_fee: vec![],
+        weighted_percentiles,
+        rejected_outliers,
+        trend,
+        analyzed_at: Some(current_datetime),
+        sell_listings: None,
+        expected_days_to_sell: None,
+        smoothing: config.smoothing,
+        seasonality,
     })
 }
 
+// Least squares slope of (day, price) points
+fn linear_regression_slope(points: &[(f64, f64)]) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|x| x.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|x| x.1).sum::<f64>() / n;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    match variance > 0.0 {
+        true => Some(covariance / variance),
+        false => None,
+    }
+}
+
+// Weighted average price of each hour of the day (UTC) with sales, and how the price at
+// `sell_hour` differs from the average of the whole window. None without enough sales
+// at the sell hour.
+fn calculate_seasonality(
+    points: &[(DateTime<Utc>, f64, i32)],
+    sell_hour: u8,
+) -> Option<Seasonality> {
+    let mut hours = [(0.0, 0i64, 0usize); 24];
+    for (date, price, amount) in points.iter().filter(|x| x.2 > 0) {
+        let hour = &mut hours[date.hour() as usize];
+        hour.0 += price * *amount as f64;
+        hour.1 += *amount as i64;
+        hour.2 += 1;
+    }
+    let total_amount: i64 = hours.iter().map(|x| x.1).sum();
+    let (sell_hour_sum, sell_hour_amount, sell_hour_points) = *hours.get(sell_hour as usize)?;
+    if total_amount == 0 || sell_hour_points < SELL_HOUR_MIN_POINTS {
+        return None;
+    }
+
+    let average = hours.iter().map(|x| x.0).sum::<f64>() / total_amount as f64;
+    let sell_hour_average = sell_hour_sum / sell_hour_amount as f64;
+    Some(Seasonality {
+        hourly_prices: hours
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.1 > 0)
+            .map(|(hour, x)| (hour as u8, PriceValue::from_usd_f64(x.0 / x.1 as f64)))
+            .collect(),
+        sell_hour,
+        sell_hour_price: PriceValue::from_usd_f64(sell_hour_average),
+        sell_hour_factor: sell_hour_average / average,
+    })
+}
+
+// Slopes within `flat_pct_per_day` of the mean price per day are considered flat
+fn detect_trend(points: &[(f64, f64)], flat_pct_per_day: f64) -> Trend {
+    let Some(slope) = linear_regression_slope(points) else {
+        return Trend::Flat;
+    };
+    let Some(mean_price) = mean(&points.iter().map(|x| x.1).collect::<Vec<_>>()) else {
+        return Trend::Flat;
+    };
+    let pct_per_day = slope / mean_price * 100.0;
+    if pct_per_day > flat_pct_per_day {
+        Trend::Rising(pct_per_day)
+    } else if pct_per_day < -flat_pct_per_day {
+        Trend::Falling(-pct_per_day)
+    } else {
+        Trend::Flat
+    }
+}
+
+fn median(sorted: &[f64]) -> Option<f64> {
+    let mid = sorted.len() / 2;
+    match sorted.len() {
+        0 => None,
+        n if n % 2 == 0 => Some((sorted[mid - 1] + sorted[mid]) / 2.0),
+        _ => Some(sorted[mid]),
+    }
+}
+
+// Drops (price, amount) points with the robust z-score (median absolute deviation based)
+// above `threshold`. All points are kept when half of them have the same price.
+fn reject_outliers_by_mad(points: Vec<(f64, i32)>, threshold: f64) -> (Vec<(f64, i32)>, u32) {
+    let mut prices: Vec<f64> = points.iter().map(|x| x.0).collect();
+    prices.sort_unstable_by(|a, b| a.total_cmp(b));
+    let Some(median_price) = median(&prices) else {
+        return (points, 0);
+    };
+    let mut deviations: Vec<f64> = prices.iter().map(|x| (x - median_price).abs()).collect();
+    deviations.sort_unstable_by(|a, b| a.total_cmp(b));
+    let mad = median(&deviations).unwrap_or(0.0);
+    if mad == 0.0 {
+        return (points, 0);
+    }
+
+    let size = points.len();
+    let kept: Vec<(f64, i32)> = points
+        .into_iter()
+        .filter(|(price, _)| 0.6745 * (price - median_price).abs() / mad <= threshold)
+        .collect();
+    let rejected = (size - kept.len()) as u32;
+    (kept, rejected)
+}
+
+// Percentile of sold items rather than of hourly points, each point is weighted by its amount
+fn calculate_weighted_percentile(points: &[(f64, i32)], percentile: f64) -> Option<f64> {
+    let mut points: Vec<&(f64, i32)> = points.iter().filter(|x| x.1 > 0).collect();
+    points.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+    let total: i64 = points.iter().map(|x| x.1 as i64).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let target = percentile * total as f64;
+    let mut cumulative = 0;
+    for (price, amount) in points.iter() {
+        cumulative += *amount as i64;
+        if cumulative as f64 >= target {
+            return Some(*price);
+        }
+    }
+    points.last().map(|x| x.0)
+}
+
 fn calculate_percentile(data: &[f64], percentile: f64) -> Option<f64> {
     // Step 1: Calculate the index
     let n = data.len() as f64;
@@ -191,6 +369,54 @@ fn mean(data: &[f64]) -> Option<f64> {
     }
 }
 
+// Series the stability is measured on, SMA of 3 drops the first two points
+fn smooth(prices: &[f64], config: &SteamAnalyzerConfig) -> Vec<f64> {
+    match config.smoothing {
+        Smoothing::Sma => simple_moving_average(prices, 3),
+        Smoothing::Ewma => exponential_moving_average(prices, config.ewma_alpha),
+        Smoothing::Kalman => kalman_filter(prices, config.kalman_noise_ratio),
+    }
+}
+
+// `alpha` is the weight of the newest price, it's clamped to (0, 1]
+pub fn exponential_moving_average(prices: &[f64], alpha: f64) -> Vec<f64> {
+    let alpha = alpha.clamp(f64::EPSILON, 1.0);
+    let mut results = Vec::with_capacity(prices.len());
+    let mut last: Option<f64> = None;
+    for &price in prices {
+        let value = match last {
+            Some(last) => alpha * price + (1.0 - alpha) * last,
+            None => price,
+        };
+        results.push(value);
+        last = Some(value);
+    }
+    results
+}
+
+// One-dimensional Kalman filter of a random walk price. Only the ratio of the process
+// noise to the measurement noise matters: the lower it is, the less a single sale moves
+// the estimate.
+pub fn kalman_filter(prices: &[f64], noise_ratio: f64) -> Vec<f64> {
+    let process_noise = noise_ratio.max(0.0);
+    let measurement_noise = 1.0;
+    let mut results = Vec::with_capacity(prices.len());
+    let mut state: Option<(f64, f64)> = None;
+    for &price in prices {
+        let (estimate, error) = match state {
+            Some((estimate, error)) => {
+                let error = error + process_noise;
+                let gain = error / (error + measurement_noise);
+                (estimate + gain * (price - estimate), (1.0 - gain) * error)
+            }
+            None => (price, measurement_noise),
+        };
+        results.push(estimate);
+        state = Some((estimate, error));
+    }
+    results
+}
+
 pub fn simple_moving_average(array_prices: &[f64], window: u32) -> Vec<f64> {
     let interval = window as usize;
     let mut index = interval - 1;
@@ -211,16 +437,191 @@ pub fn simple_moving_average(array_prices: &[f64], window: u32) -> Vec<f64> {
     results
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Prices by the hour of the day (UTC), Steam prices dip at certain hours
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Seasonality {
+    pub hourly_prices: Vec<(u8, PriceValue)>,
+    // `steam_analyzer.sell_hour` at the time of the analysis
+    pub sell_hour: u8,
+    // expected price at the sell hour, with fee
+    pub sell_hour_price: PriceValue,
+    // the sell hour price to the average price of the window
+    pub sell_hour_factor: f64,
+}
+
+// Estimator of the price series `rsd` is calculated on
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Smoothing {
+    // moving average of 3 points
+    #[default]
+    Sma,
+    Ewma,
+    Kalman,
+}
+
+// Price change in % per day, by the linear regression of the analyzed window
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum Trend {
+    Rising(f64),
+    #[default]
+    Flat,
+    Falling(f64),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct AnalysisResult {
     pub rsd: Option<f64>,
     pub is_stable: Option<bool>,
     pub sold_per_week: Option<i32>,
     pub percentiles: Vec<(u8, PriceValue)>,
     pub percentiles_no_fee: Vec<(u8, PriceValue)>,
+    // weighted by the amount sold, after outlier rejection
+    #[serde(default)]
+    pub weighted_percentiles: Vec<(u8, PriceValue)>,
+    #[serde(default)]
+    pub rejected_outliers: u32,
+    #[serde(default)]
+    pub trend: Trend,
+    // time of the analyzed response, None for analyses saved before it was tracked
+    #[serde(default)]
+    pub analyzed_at: Option<DateTime<Utc>>,
+    // listings on the Steam market at the time of the analysis, see `set_sell_listings`
+    #[serde(default)]
+    pub sell_listings: Option<u32>,
+    #[serde(default)]
+    pub expected_days_to_sell: Option<f64>,
+    // the one `rsd` was calculated with
+    #[serde(default)]
+    pub smoothing: Smoothing,
+    #[serde(default)]
+    pub seasonality: Option<Seasonality>,
+}
+
+// Frozen layout of `AnalysisResult` saved before the seasonality was calculated
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnalysisResultV3 {
+    pub rsd: Option<f64>,
+    pub is_stable: Option<bool>,
+    pub sold_per_week: Option<i32>,
+    pub percentiles: Vec<(u8, PriceValue)>,
+    pub percentiles_no_fee: Vec<(u8, PriceValue)>,
+    pub weighted_percentiles: Vec<(u8, PriceValue)>,
+    pub rejected_outliers: u32,
+    pub trend: Trend,
+    pub analyzed_at: Option<DateTime<Utc>>,
+    pub sell_listings: Option<u32>,
+    pub expected_days_to_sell: Option<f64>,
+    pub smoothing: Smoothing,
+}
+
+impl From<AnalysisResultV3> for AnalysisResult {
+    fn from(value: AnalysisResultV3) -> Self {
+        AnalysisResult {
+            rsd: value.rsd,
+            is_stable: value.is_stable,
+            sold_per_week: value.sold_per_week,
+            percentiles: value.percentiles,
+            percentiles_no_fee: value.percentiles_no_fee,
+            weighted_percentiles: value.weighted_percentiles,
+            rejected_outliers: value.rejected_outliers,
+            trend: value.trend,
+            analyzed_at: value.analyzed_at,
+            sell_listings: value.sell_listings,
+            expected_days_to_sell: value.expected_days_to_sell,
+            smoothing: value.smoothing,
+            seasonality: None,
+        }
+    }
+}
+
+// Frozen layout of `AnalysisResult` saved before the smoothing was configurable
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnalysisResultV2 {
+    pub rsd: Option<f64>,
+    pub is_stable: Option<bool>,
+    pub sold_per_week: Option<i32>,
+    pub percentiles: Vec<(u8, PriceValue)>,
+    pub percentiles_no_fee: Vec<(u8, PriceValue)>,
+    pub weighted_percentiles: Vec<(u8, PriceValue)>,
+    pub rejected_outliers: u32,
+    pub trend: Trend,
+    pub analyzed_at: Option<DateTime<Utc>>,
+    pub sell_listings: Option<u32>,
+    pub expected_days_to_sell: Option<f64>,
+}
+
+impl From<AnalysisResultV2> for AnalysisResultV3 {
+    fn from(value: AnalysisResultV2) -> Self {
+        AnalysisResultV3 {
+            rsd: value.rsd,
+            is_stable: value.is_stable,
+            sold_per_week: value.sold_per_week,
+            percentiles: value.percentiles,
+            percentiles_no_fee: value.percentiles_no_fee,
+            weighted_percentiles: value.weighted_percentiles,
+            rejected_outliers: value.rejected_outliers,
+            trend: value.trend,
+            analyzed_at: value.analyzed_at,
+            sell_listings: value.sell_listings,
+            expected_days_to_sell: value.expected_days_to_sell,
+            smoothing: Smoothing::Sma,
+        }
+    }
+}
+
+// Frozen layout of `AnalysisResult` saved before the exit time was estimated
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnalysisResultV1 {
+    pub rsd: Option<f64>,
+    pub is_stable: Option<bool>,
+    pub sold_per_week: Option<i32>,
+    pub percentiles: Vec<(u8, PriceValue)>,
+    pub percentiles_no_fee: Vec<(u8, PriceValue)>,
+    pub weighted_percentiles: Vec<(u8, PriceValue)>,
+    pub rejected_outliers: u32,
+    pub trend: Trend,
+    pub analyzed_at: Option<DateTime<Utc>>,
+}
+
+impl From<AnalysisResultV1> for AnalysisResultV2 {
+    fn from(value: AnalysisResultV1) -> Self {
+        AnalysisResultV2 {
+            rsd: value.rsd,
+            is_stable: value.is_stable,
+            sold_per_week: value.sold_per_week,
+            percentiles: value.percentiles,
+            percentiles_no_fee: value.percentiles_no_fee,
+            weighted_percentiles: value.weighted_percentiles,
+            rejected_outliers: value.rejected_outliers,
+            trend: value.trend,
+            analyzed_at: value.analyzed_at,
+            sell_listings: None,
+            expected_days_to_sell: None,
+        }
+    }
+}
+
+// Days until a new listing is sold, when it has to wait for all the current listings
+// to be bought out at the pace of the sell history. None when nothing is sold.
+pub fn estimate_days_to_sell(sold_per_week: Option<i32>, sell_listings: u32) -> Option<f64> {
+    let sold_per_day = sold_per_week.filter(|&x| x > 0)? as f64 / 7.0;
+    Some((sell_listings + 1) as f64 / sold_per_day)
 }
 
 impl AnalysisResult {
+    pub fn set_sell_listings(&mut self, sell_listings: u32) {
+        self.sell_listings = Some(sell_listings);
+        self.expected_days_to_sell = estimate_days_to_sell(self.sold_per_week, sell_listings);
+    }
+
+    pub fn get_weighted_price_by_percentile(&self, desired_percentile: u8) -> Option<PriceValue> {
+        self.weighted_percentiles
+            .iter()
+            .find(|(percentile, _)| *percentile == desired_percentile)
+            .map(|(_, price)| *price)
+    }
+
     pub fn get_price_by_percentile(&self, desired_percentile: u8) -> Option<PriceValue> {
         if !self.percentiles.is_empty() {
             for &(percentile, price) in self.percentiles.iter() {
@@ -246,6 +647,14 @@ mod tests {
             sold_per_week: Some(10),
             percentiles: vec![(25, 10), (50, 20), (75, 30)],
             percentiles_no_fee: vec![],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: None,
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
         };
 
         // Test for an existing percentile (50th percentile)
@@ -266,6 +675,14 @@ mod tests {
             sold_per_week: Some(10),
             percentiles: vec![(25, 10), (50, 20), (75, 30)],
             percentiles_no_fee: vec![],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: None,
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
         };
 
         // Test for a non-existing percentile (80th percentile)
@@ -285,6 +702,14 @@ mod tests {
             sold_per_week: Some(10),
             percentiles: vec![],
             percentiles_no_fee: vec![],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: None,
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
         };
 
         // Test for any percentile on an empty set
@@ -295,6 +720,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_estimate_days_to_sell() {
+        assert_eq!(estimate_days_to_sell(Some(70), 19), Some(2.0));
+        assert_eq!(estimate_days_to_sell(Some(7), 0), Some(1.0));
+        assert_eq!(estimate_days_to_sell(Some(0), 5), None);
+        assert_eq!(estimate_days_to_sell(None, 5), None);
+    }
+
     #[test]
     fn test_mean_with_positive_values() {
         let data = vec![1.0, 2.0, 3.0, 4.0, 5.0];
@@ -353,6 +786,103 @@ mod tests {
         assert_eq!(calculate_percentile(&data, percentile), Some(42.0));
     }
 
+    #[test]
+    fn test_reject_outliers_by_mad() {
+        let points = vec![
+            (1.0, 1),
+            (1.02, 3),
+            (0.98, 2),
+            (1.01, 1),
+            (0.99, 1),
+            (5.0, 1),
+        ];
+        let (kept, rejected) = reject_outliers_by_mad(points, 3.5);
+        assert_eq!(rejected, 1);
+        assert!(kept.iter().all(|x| x.0 < 2.0));
+
+        // a NaN price doesn't panic the sort
+        let points = vec![(1.0, 1), (f64::NAN, 1), (1.02, 1), (0.98, 1)];
+        reject_outliers_by_mad(points, 3.5);
+    }
+
+    #[test]
+    fn test_calculate_weighted_percentile() {
+        // most items are sold for 2.0, though there are more hourly points for 1.0
+        let points = vec![(1.0, 1), (1.0, 1), (1.0, 1), (2.0, 10)];
+        assert_eq!(calculate_weighted_percentile(&points, 0.6), Some(2.0));
+        assert_eq!(calculate_weighted_percentile(&points, 0.2), Some(1.0));
+        assert_eq!(calculate_weighted_percentile(&[], 0.6), None);
+        let points = vec![(1.0, 1), (f64::NAN, 1), (2.0, 10)];
+        assert_eq!(calculate_weighted_percentile(&points, 0.6), Some(2.0));
+    }
+
+    #[test]
+    fn test_detect_trend() {
+        let falling: Vec<(f64, f64)> = (0..7)
+            .map(|day| (day as f64, 10.0 - day as f64 * 0.2))
+            .collect();
+        match detect_trend(&falling, 0.5) {
+            // $0.2 a day of the $9.4 mean
+            Trend::Falling(pct) => assert!((pct - 0.2 / 9.4 * 100.0).abs() < 0.01),
+            trend => panic!("unexpected trend {:?}", trend),
+        }
+
+        let flat: Vec<(f64, f64)> = (0..7)
+            .map(|day| (day as f64, 10.0 + (day % 2) as f64 * 0.01))
+            .collect();
+        assert_eq!(detect_trend(&flat, 0.5), Trend::Flat);
+        assert!(matches!(
+            detect_trend(&[(0.0, 10.0), (1.0, 11.0)], 0.5),
+            Trend::Rising(_)
+        ));
+        assert_eq!(detect_trend(&[(0.0, 10.0)], 0.5), Trend::Flat);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let prices = vec![10.0, 12.0, 10.0, 12.0];
+        assert_eq!(simple_moving_average(&prices, 3).len(), 2);
+        assert_eq!(
+            exponential_moving_average(&prices, 0.5),
+            vec![10.0, 11.0, 10.5, 11.25]
+        );
+        assert_eq!(exponential_moving_average(&prices, 1.0), prices);
+
+        // the estimate moves less than the prices and the lower ratio smooths more
+        let smooth = kalman_filter(&prices, 0.01);
+        let rough = kalman_filter(&prices, 1.0);
+        assert_eq!(smooth[0], 10.0);
+        assert!(smooth[1] > 10.0 && smooth[1] < rough[1] && rough[1] < 12.0);
+        assert!(std_deviation(&smooth, mean(&smooth).unwrap()) < std_deviation(&prices, 11.0));
+    }
+
+    #[test]
+    fn test_calculate_seasonality() {
+        let start = DateTime::parse_from_rfc3339("2024-02-12T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        // a dip at 03:00 every day, the rest is sold at $10
+        let points: Vec<(DateTime<Utc>, f64, i32)> = (0..7 * 24)
+            .map(|hour| {
+                let date = start + Duration::hours(hour);
+                let price = if date.hour() == 3 { 9.0 } else { 10.0 };
+                (date, price, 1)
+            })
+            .collect();
+
+        let seasonality = calculate_seasonality(&points, 3).unwrap();
+        assert_eq!(seasonality.hourly_prices.len(), 24);
+        assert_eq!(seasonality.sell_hour_price, 9_00);
+        assert!(seasonality.sell_hour_factor < 1.0);
+        let seasonality = calculate_seasonality(&points, 18).unwrap();
+        assert_eq!(seasonality.sell_hour_price, 10_00);
+        assert!(seasonality.sell_hour_factor > 1.0);
+
+        // too few sales at the sell hour
+        assert_eq!(calculate_seasonality(&points[..48], 18), None);
+        assert_eq!(calculate_seasonality(&points, 24), None);
+    }
+
     #[test]
     fn test_calculate_percentile_with_fractional_index() {
         let data = vec![10.0, 20.0, 30.0, 40.0];
@@ -0,0 +1,337 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::{cookie::Jar, Client, StatusCode, Url};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::SteamFetcherConfig, csfloat_fetcher::RateLimiter, steam_orders::extract_item_nameid,
+    storages::SteamEngine, types::MarketName,
+};
+
+const STEAM_URL: &str = "https://steamcommunity.com";
+const LISTINGS_URL: &str = "https://steamcommunity.com/market/listings/730/";
+const ORDERS_HISTOGRAM_URL: &str = "https://steamcommunity.com/market/itemordershistogram";
+const SEARCH_RENDER_URL: &str = "https://steamcommunity.com/market/search/render/";
+// sell history is rendered into the page only for logged in users
+const SELL_HISTORY_MARKER: &str = "var line1=";
+
+// Steam market page of the item
+pub fn get_listings_url(market_name: &MarketName) -> Url {
+    let mut url = LISTINGS_URL.parse::<Url>().unwrap();
+    url.path_segments_mut()
+        .unwrap()
+        .pop_if_empty()
+        .push(market_name);
+    url
+}
+
+// search/render with norender=1, the query matches other items too
+#[derive(Deserialize)]
+struct SearchRenderResponse {
+    success: bool,
+    #[serde(default)]
+    results: Vec<SearchRenderItem>,
+}
+
+#[derive(Deserialize)]
+struct SearchRenderItem {
+    hash_name: MarketName,
+    sell_listings: u32,
+}
+
+// Number of listings of `market_name` on the Steam market
+pub fn parse_sell_listings(response: &str, market_name: &MarketName) -> Option<u32> {
+    let parsed = serde_json::from_str::<SearchRenderResponse>(response).ok()?;
+    if !parsed.success {
+        return None;
+    }
+    parsed
+        .results
+        .into_iter()
+        .find(|x| &x.hash_name == market_name)
+        .map(|x| x.sell_listings)
+}
+
+pub struct SteamFetcher {
+    client: Client,
+    rate_limiter: RateLimiter,
+    // when each market name was fetched successfully last time, failed ones are retried
+    // by the next pick
+    last_fetched: HashMap<MarketName, Instant>,
+    // item_nameid never changes, so it's parsed from the listing page only once
+    item_nameids: HashMap<MarketName, u64>,
+}
+
+impl SteamFetcher {
+    // Session cookies are taken from STEAM_LOGIN_SECURE and STEAM_SESSION_ID,
+    // cookies refreshed by Steam are kept in the client's jar.
+    pub fn from_env(config: &SteamFetcherConfig) -> SteamFetcher {
+        let jar = Arc::new(Jar::default());
+        let steam_url = STEAM_URL.parse::<Url>().unwrap();
+        for (cookie_name, env_key) in [
+            ("steamLoginSecure", "STEAM_LOGIN_SECURE"),
+            ("sessionid", "STEAM_SESSION_ID"),
+        ] {
+            match env::var(env_key) {
+                Ok(value) => jar.add_cookie_str(
+                    &format!(
+                        "{}={}; Domain=steamcommunity.com; Path=/",
+                        cookie_name, value
+                    ),
+                    &steam_url,
+                ),
+                Err(e) => error!("{} is not set for steam fetcher! {:?}", env_key, e),
+            }
+        }
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(15))
+            .cookie_provider(jar)
+            .build()
+            .expect("Failed to build client for steam fetcher");
+
+        SteamFetcher {
+            client,
+            rate_limiter: RateLimiter::new(config.request_interval()),
+            last_fetched: HashMap::new(),
+            item_nameids: HashMap::new(),
+        }
+    }
+
+    // Names with a stale analysis requested to be refreshed go first, then the ones
+    // without analysis, then the ones fetched the longest time ago.
+    // Names requested less than `stale_after` ago are skipped.
+    pub fn pick_next(
+        &self,
+        market_names: &HashSet<MarketName>,
+        steam_engine: &SteamEngine,
+        stale_after: Duration,
+    ) -> Option<MarketName> {
+        market_names
+            .iter()
+            .filter(|name| match self.last_fetched.get(*name) {
+                Some(fetched_at) => fetched_at.elapsed() >= stale_after,
+                None => true,
+            })
+            .min_by_key(|name| {
+                (
+                    !steam_engine.refresh_requests.contains(*name),
+                    steam_engine.hm.contains_key(*name),
+                    self.last_fetched.get(*name),
+                )
+            })
+            .cloned()
+    }
+
+    pub async fn fetch(
+        &mut self,
+        market_name: &MarketName,
+        config: &SteamFetcherConfig,
+    ) -> Option<String> {
+        let url = get_listings_url(market_name);
+        let text = self.get(url, market_name, config).await?;
+        self.last_fetched
+            .insert(market_name.clone(), Instant::now());
+        if !text.contains(SELL_HISTORY_MARKER) {
+            info!(
+                "No sell history for {}, steam session may be expired",
+                market_name
+            );
+        }
+        if let Some(item_nameid) = extract_item_nameid(&text) {
+            self.item_nameids.insert(market_name.clone(), item_nameid);
+        }
+        Some(text)
+    }
+
+    pub async fn fetch_sell_listings(
+        &mut self,
+        market_name: &MarketName,
+        config: &SteamFetcherConfig,
+    ) -> Option<u32> {
+        let url = Url::parse_with_params(
+            SEARCH_RENDER_URL,
+            &[
+                ("appid", "730"),
+                ("norender", "1"),
+                ("count", "10"),
+                ("search_descriptions", "0"),
+                ("query", market_name.as_ref()),
+            ],
+        )
+        .unwrap();
+        let text = self.get(url, market_name, config).await?;
+        let sell_listings = parse_sell_listings(&text, market_name);
+        if sell_listings.is_none() {
+            warn!("No sell listings of {} in search results", market_name);
+        }
+        sell_listings
+    }
+
+    // Requires the listing page of `market_name` to be fetched before
+    pub async fn fetch_order_histogram(
+        &mut self,
+        market_name: &MarketName,
+        config: &SteamFetcherConfig,
+    ) -> Option<String> {
+        let Some(item_nameid) = self.item_nameids.get(market_name) else {
+            warn!("Unknown item_nameid for {}", market_name);
+            return None;
+        };
+
+        let url = Url::parse_with_params(
+            ORDERS_HISTOGRAM_URL,
+            &[
+                ("country", "US"),
+                ("language", "english"),
+                ("currency", "1"),
+                ("item_nameid", item_nameid.to_string().as_str()),
+            ],
+        )
+        .unwrap();
+        self.get(url, market_name, config).await
+    }
+
+    async fn get(
+        &mut self,
+        url: Url,
+        market_name: &MarketName,
+        config: &SteamFetcherConfig,
+    ) -> Option<String> {
+        self.rate_limiter.set_interval(config.request_interval());
+        self.rate_limiter.wait().await;
+
+        let response = match self.client.get(url).send().await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(
+                    "Steam listings request failed for {}: {:?}",
+                    market_name, err
+                );
+                return None;
+            }
+        };
+
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::TOO_MANY_REQUESTS => {
+                warn!(
+                    "Steam rate limited us, pausing the fetcher for {:?}",
+                    config.rate_limited_backoff()
+                );
+                self.rate_limiter.pause(config.rate_limited_backoff());
+                return None;
+            }
+            status => {
+                warn!(
+                    "Steam listings request failed for {}: {}",
+                    market_name, status
+                );
+                return None;
+            }
+        }
+
+        response.text().await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        steam_analyzer::{AnalysisResult, Smoothing, Trend},
+        storages::SteamEngineTrait,
+    };
+
+    fn get_fetcher() -> SteamFetcher {
+        SteamFetcher {
+            client: Client::new(),
+            rate_limiter: RateLimiter::new(Duration::ZERO),
+            last_fetched: HashMap::new(),
+            item_nameids: HashMap::new(),
+        }
+    }
+
+    fn get_analysis() -> AnalysisResult {
+        AnalysisResult {
+            rsd: None,
+            is_stable: None,
+            sold_per_week: None,
+            percentiles: vec![],
+            percentiles_no_fee: vec![],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: None,
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_sell_listings() {
+        let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let response = r#"{"success": true, "start": 0, "pagesize": 10, "total_count": 2, "results": [
+            {"name": "StatTrak™ AK-47 | Redline (Field-Tested)", "hash_name": "StatTrak™ AK-47 | Redline (Field-Tested)", "sell_listings": 45, "sell_price": 4012},
+            {"name": "AK-47 | Redline (Field-Tested)", "hash_name": "AK-47 | Redline (Field-Tested)", "sell_listings": 412, "sell_price": 1830}
+        ]}"#;
+        assert_eq!(parse_sell_listings(response, &market_name), Some(412));
+
+        let response = r#"{"success": true, "results": []}"#;
+        assert_eq!(parse_sell_listings(response, &market_name), None);
+        assert_eq!(parse_sell_listings("null", &market_name), None);
+    }
+
+    #[test]
+    fn test_pick_next_prefers_missing_analysis() {
+        let mut fetcher = get_fetcher();
+        let mut steam_engine = SteamEngine::new();
+        let analyzed = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let missing = MarketName::from("AWP | Asiimov (Field-Tested)");
+        steam_engine.update(&analyzed, get_analysis());
+        let names = HashSet::from([analyzed.clone(), missing.clone()]);
+        let stale_after = Duration::from_secs(60);
+
+        assert_eq!(
+            fetcher.pick_next(&names, &steam_engine, stale_after),
+            Some(missing.clone())
+        );
+
+        fetcher.last_fetched.insert(missing, Instant::now());
+        assert_eq!(
+            fetcher.pick_next(&names, &steam_engine, stale_after),
+            Some(analyzed.clone())
+        );
+
+        fetcher.last_fetched.insert(analyzed, Instant::now());
+        assert_eq!(fetcher.pick_next(&names, &steam_engine, stale_after), None);
+    }
+
+    #[tokio::test]
+    async fn test_failed_fetch_is_picked_again() {
+        let mut fetcher = get_fetcher();
+        // nothing listens there, so the request fails right away
+        fetcher.client = Client::builder()
+            .proxy(reqwest::Proxy::all("http://127.0.0.1:9").unwrap())
+            .build()
+            .unwrap();
+        let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let names = HashSet::from([market_name.clone()]);
+
+        let config = SteamFetcherConfig::default();
+        assert_eq!(fetcher.fetch(&market_name, &config).await, None);
+        assert_eq!(
+            fetcher.pick_next(&names, &SteamEngine::new(), Duration::from_secs(60)),
+            Some(market_name)
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use std::{collections::HashSet, fmt::Write};
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::{
+    business_logic::estimate_steam_sell_price,
+    config::AppConfig,
+    fee::SteamFee,
+    ledger::{load_unlisted_purchases, UnlistedPurchase},
+    prices::{PriceValue, PriceValueTrait},
+    steam_seller::{InventoryItem, SteamSeller, SteamSellerError},
+    storages::SteamEngine,
+    types::ListingId,
+};
+
+// Floats of the same item differ in the last digits between CSFloat and Steam
+const FLOAT_TOLERANCE: f64 = 1e-6;
+
+// Bought item which is still in the Steam inventory
+#[derive(Debug, Clone, PartialEq)]
+pub struct StockItem {
+    pub item: InventoryItem,
+    pub listing_id: ListingId,
+    pub paid_price: PriceValue,
+    // Steam price minus fee at the time of the poll, None without the Steam analysis
+    pub value: Option<PriceValue>,
+}
+
+impl StockItem {
+    pub fn is_on_hold(&self, now: DateTime<Utc>) -> bool {
+        match self.item.tradable_after {
+            Some(tradable_after) => tradable_after > now,
+            None => !self.item.is_tradable,
+        }
+    }
+}
+
+pub fn is_same_float(purchase: &UnlistedPurchase, item: &InventoryItem) -> bool {
+    match (purchase.float, item.float) {
+        (Some(purchase_float), Some(item_float)) => {
+            (purchase_float - item_float).abs() < FLOAT_TOLERANCE
+        }
+        _ => false,
+    }
+}
+
+// Purchases are matched to items of the same market name by float first,
+// the rest of them take any item of their market name which is left
+pub fn match_stock(
+    inventory: &[InventoryItem],
+    purchases: &[UnlistedPurchase],
+) -> Vec<(UnlistedPurchase, InventoryItem)> {
+    let mut taken: HashSet<&str> = HashSet::new();
+    let mut matched: Vec<Option<&InventoryItem>> = vec![None; purchases.len()];
+
+    for (idx, purchase) in purchases.iter().enumerate() {
+        let item = inventory.iter().find(|x| {
+            x.market_name == purchase.market_name
+                && is_same_float(purchase, x)
+                && !taken.contains(x.asset_id.as_str())
+        });
+        if let Some(item) = item {
+            taken.insert(&item.asset_id);
+            matched[idx] = Some(item);
+        }
+    }
+    for (idx, purchase) in purchases.iter().enumerate() {
+        if matched[idx].is_some() {
+            continue;
+        }
+        // an item with another float belongs to another purchase or was there before
+        let item = inventory.iter().find(|x| {
+            x.market_name == purchase.market_name
+                && (purchase.float.is_none() || x.float.is_none())
+                && !taken.contains(x.asset_id.as_str())
+        });
+        if let Some(item) = item {
+            taken.insert(&item.asset_id);
+            matched[idx] = Some(item);
+        }
+    }
+
+    purchases
+        .iter()
+        .zip(matched)
+        .filter_map(|(purchase, item)| Some((purchase.clone(), item?.clone())))
+        .collect()
+}
+
+// "2d 5h", "3h 20m", "15m"
+fn format_countdown(duration: Duration) -> String {
+    let minutes = duration.num_minutes().max(1);
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+// Unsold stock of the last inventory poll
+#[derive(Debug, Default)]
+pub struct InventoryTracker {
+    items: Vec<StockItem>,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl InventoryTracker {
+    pub fn new() -> Self {
+        InventoryTracker::default()
+    }
+
+    // Returns items whose trade hold has ended since the previous update
+    pub fn update(&mut self, items: Vec<StockItem>, now: DateTime<Utc>) -> Vec<StockItem> {
+        let released = match self.updated_at {
+            Some(updated_at) => items
+                .iter()
+                .filter(|item| {
+                    !item.is_on_hold(now)
+                        && self.items.iter().any(|x| {
+                            x.item.asset_id == item.item.asset_id && x.is_on_hold(updated_at)
+                        })
+                })
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        self.items = items;
+        self.updated_at = Some(now);
+        released
+    }
+
+    pub fn summary(&self, now: DateTime<Utc>) -> String {
+        let Some(updated_at) = self.updated_at else {
+            return "Steam inventory is not polled yet".to_string();
+        };
+        if self.items.is_empty() {
+            return "No unsold items in the Steam inventory".to_string();
+        }
+
+        let paid: PriceValue = self.items.iter().map(|x| x.paid_price).sum();
+        let priced: Vec<&StockItem> = self.items.iter().filter(|x| x.value.is_some()).collect();
+        let value: PriceValue = priced.iter().filter_map(|x| x.value).sum();
+        let priced_paid: PriceValue = priced.iter().map(|x| x.paid_price).sum();
+        let on_hold = self.items.iter().filter(|x| x.is_on_hold(now)).count();
+
+        let mut text = format!(
+            "Stock: {} items ({} on trade hold), paid ${:.2}\n",
+            self.items.len(),
+            on_hold,
+            paid.to_usd()
+        );
+        writeln!(
+            text,
+            "Value after fee: ${:.2}, unrealized P&L ${:.2} ({} without Steam price)",
+            value.to_usd(),
+            (value as i64 - priced_paid as i64) as f64 / 100.0,
+            self.items.len() - priced.len()
+        )
+        .unwrap();
+
+        for stock_item in self.items.iter() {
+            write!(
+                text,
+                "\n{}: paid ${:.2}",
+                stock_item.item.market_name,
+                stock_item.paid_price.to_usd()
+            )
+            .unwrap();
+            if let Some(value) = stock_item.value {
+                write!(text, ", worth ${:.2}", value.to_usd()).unwrap();
+            }
+            if stock_item.is_on_hold(now) {
+                match stock_item.item.tradable_after {
+                    Some(tradable_after) => write!(
+                        text,
+                        ", tradable in {}",
+                        format_countdown(tradable_after - now)
+                    ),
+                    None => write!(text, ", not tradable"),
+                }
+                .unwrap();
+            }
+        }
+        write!(
+            text,
+            "\n\nUpdated {} ago",
+            format_countdown(now - updated_at)
+        )
+        .unwrap();
+        text
+    }
+}
+
+// Polls the Steam inventory and updates the tracker.
+// Returns items whose trade hold has ended since the previous poll.
+pub async fn poll_inventory(
+    seller: &SteamSeller,
+    db: &Pool<Postgres>,
+    steam_engine: &Mutex<SteamEngine>,
+    tracker: &Mutex<InventoryTracker>,
+    config: &AppConfig,
+) -> Result<Vec<StockItem>, SteamSellerError> {
+    let since = Utc::now() - config.inventory.lookback();
+    let purchases = load_unlisted_purchases(db, since)
+        .await
+        .map_err(SteamSellerError::Db)?;
+    let inventory = seller.fetch_inventory().await?;
+    let matched = match_stock(&inventory, &purchases);
+
+    let items: Vec<StockItem> = {
+        let steam_engine_locked = steam_engine.lock().await;
+        matched
+            .into_iter()
+            .map(|(purchase, item)| {
+                let value =
+                    estimate_steam_sell_price(&item.market_name, &steam_engine_locked, config)
+                        .and_then(SteamFee::try_subtract_fee);
+                StockItem {
+                    item,
+                    listing_id: purchase.listing_id,
+                    paid_price: purchase.paid_price,
+                    value,
+                }
+            })
+            .collect()
+    };
+    info!(
+        "Steam inventory: {} items | {} unlisted purchases | {} in stock",
+        inventory.len(),
+        purchases.len(),
+        items.len()
+    );
+    Ok(tracker.lock().await.update(items, Utc::now()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::steam_seller::parse_inventory;
+
+    fn get_purchase(listing_id: &str, market_name: &str, float: Option<f64>) -> UnlistedPurchase {
+        UnlistedPurchase {
+            listing_id: listing_id.into(),
+            market_name: market_name.into(),
+            paid_price: 10_00,
+            float,
+        }
+    }
+
+    #[test]
+    fn test_match_stock_by_float() {
+        let inventory = parse_inventory(
+            r#"{
+                "assets": [
+                    {"appid": 730, "contextid": "2", "assetid": "1", "classid": "10", "instanceid": "0", "amount": "1"},
+                    {"appid": 730, "contextid": "2", "assetid": "2", "classid": "10", "instanceid": "0", "amount": "1"},
+                    {"appid": 730, "contextid": "2", "assetid": "3", "classid": "20", "instanceid": "0", "amount": "1"}
+                ],
+                "descriptions": [
+                    {"classid": "10", "instanceid": "0", "market_hash_name": "AK-47 | Redline (Field-Tested)", "tradable": 0, "marketable": 0, "cache_expiration": "2024-01-08T07:00:00Z"},
+                    {"classid": "20", "instanceid": "0", "market_hash_name": "Sticker | Crown (Foil)", "tradable": 1, "marketable": 1}
+                ],
+                "asset_properties": [
+                    {"appid": 730, "contextid": "2", "assetid": "1", "asset_properties": [
+                        {"propertyid": 1, "int_value": "661", "name": "Pattern Template"},
+                        {"propertyid": 2, "float_value": "0.2500000059604645", "name": "Wear Rating"}
+                    ]},
+                    {"appid": 730, "contextid": "2", "assetid": "2", "asset_properties": [
+                        {"propertyid": 2, "float_value": "0.1600000005960464", "name": "Wear Rating"}
+                    ]}
+                ],
+                "success": 1
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(inventory[0].float, Some(0.2500000059604645));
+        assert_eq!(
+            inventory[0].tradable_after,
+            Some("2024-01-08T07:00:00Z".parse().unwrap())
+        );
+        assert_eq!(inventory[2].float, None);
+        assert_eq!(inventory[2].tradable_after, None);
+
+        let purchases = vec![
+            get_purchase("a", "AK-47 | Redline (Field-Tested)", Some(0.16)),
+            get_purchase("b", "AK-47 | Redline (Field-Tested)", Some(0.25)),
+            // a third Redline is sold already
+            get_purchase("c", "AK-47 | Redline (Field-Tested)", Some(0.3)),
+            get_purchase("d", "Sticker | Crown (Foil)", None),
+        ];
+        let matched: Vec<_> = match_stock(&inventory, &purchases)
+            .into_iter()
+            .map(|(purchase, item)| (purchase.listing_id, item.asset_id))
+            .collect();
+        let expected = vec![("a", "2"), ("b", "1"), ("d", "3")];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(listing_id, asset_id)| (ListingId::from(listing_id), asset_id.to_string()))
+            .collect();
+        assert_eq!(matched, expected);
+    }
+
+    #[test]
+    fn test_inventory_tracker() {
+        let now: DateTime<Utc> = "2024-01-05T12:00:00Z".parse().unwrap();
+        let get_item = |asset_id: &str, tradable_after: Option<DateTime<Utc>>, value| StockItem {
+            item: InventoryItem {
+                asset_id: asset_id.to_string(),
+                market_name: format!("Item {}", asset_id).into(),
+                is_tradable: tradable_after.is_none(),
+                is_marketable: tradable_after.is_none(),
+                tradable_after,
+                float: None,
+            },
+            listing_id: asset_id.into(),
+            paid_price: 10_00,
+            value,
+        };
+        let hold_end = now + Duration::hours(50);
+        let items = vec![
+            get_item("1", Some(hold_end), Some(12_00)),
+            get_item("2", None, None),
+        ];
+
+        let mut tracker = InventoryTracker::new();
+        assert_eq!(tracker.summary(now), "Steam inventory is not polled yet");
+        assert!(tracker.update(items.clone(), now).is_empty());
+        assert_eq!(
+            tracker.summary(now + Duration::minutes(30)),
+            "Stock: 2 items (1 on trade hold), paid $20.00\n\
+            Value after fee: $12.00, unrealized P&L $2.00 (1 without Steam price)\n\
+            \nItem 1: paid $10.00, worth $12.00, tradable in 2d 1h\
+            \nItem 2: paid $10.00\
+            \n\nUpdated 30m ago"
+        );
+
+        let later = hold_end + Duration::minutes(10);
+        let released = tracker.update(items.clone(), later);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].item.asset_id, "1");
+        assert!(tracker.update(items, later).is_empty());
+        assert!(tracker.update(vec![], later).is_empty());
+        assert_eq!(
+            tracker.summary(later),
+            "No unsold items in the Steam inventory"
+        );
+    }
+}
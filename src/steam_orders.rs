@@ -0,0 +1,130 @@
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+
+use crate::prices::PriceValue;
+
+// how many price levels of each side are kept
+const ORDER_BOOK_DEPTH: usize = 5;
+
+lazy_static! {
+    static ref ITEM_NAMEID_REGEX: Regex =
+        Regex::new(r#"Market_LoadOrderSpread\(\s*(\d+)\s*\)"#).unwrap();
+}
+
+// item_nameid is required by itemordershistogram and is found only on the listing page
+pub fn extract_item_nameid(listing_page: &str) -> Option<u64> {
+    ITEM_NAMEID_REGEX
+        .captures(listing_page)
+        .and_then(|captures| captures[1].parse::<u64>().ok())
+}
+
+// Point of `buy_order_graph`/`sell_order_graph`: [price in USD, cumulative quantity, tooltip]
+#[derive(Deserialize)]
+struct GraphPoint(f64, u64, IgnoredAny);
+
+#[derive(Deserialize)]
+struct HistogramResponse {
+    success: u8,
+    // cents as a string, null when there are no orders
+    highest_buy_order: Option<String>,
+    lowest_sell_order: Option<String>,
+    #[serde(default)]
+    buy_order_graph: Vec<GraphPoint>,
+    #[serde(default)]
+    sell_order_graph: Vec<GraphPoint>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrderBookLevel {
+    pub price: PriceValue,
+    pub quantity: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SteamOrderBook {
+    // price we can sell for instantly
+    pub highest_buy_order: Option<PriceValue>,
+    pub lowest_sell_order: Option<PriceValue>,
+    // nearest levels to the spread, best price first
+    pub buy_walls: Vec<OrderBookLevel>,
+    pub sell_walls: Vec<OrderBookLevel>,
+    pub updated_at: DateTime<Utc>,
+}
+
+fn graph_to_levels(graph: &[GraphPoint]) -> Vec<OrderBookLevel> {
+    let mut prev_cumulative = 0;
+    graph
+        .iter()
+        .take(ORDER_BOOK_DEPTH)
+        .map(|point| {
+            let level = OrderBookLevel {
+                // round, $12.03 is 1202.99.. cents as f64
+                price: (point.0 * 100.0).round() as PriceValue,
+                quantity: point.1.saturating_sub(prev_cumulative),
+            };
+            prev_cumulative = point.1;
+            level
+        })
+        .collect()
+}
+
+pub fn parse_order_histogram(response: &str, timestamp: DateTime<Utc>) -> Option<SteamOrderBook> {
+    let parsed = serde_json::from_str::<HistogramResponse>(response).ok()?;
+    if parsed.success != 1 {
+        return None;
+    }
+
+    let parse_cents = |x: &Option<String>| x.as_ref().and_then(|x| x.parse::<PriceValue>().ok());
+    Some(SteamOrderBook {
+        highest_buy_order: parse_cents(&parsed.highest_buy_order),
+        lowest_sell_order: parse_cents(&parsed.lowest_sell_order),
+        buy_walls: graph_to_levels(&parsed.buy_order_graph),
+        sell_walls: graph_to_levels(&parsed.sell_order_graph),
+        updated_at: timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_item_nameid() {
+        let page = "\t\tMarket_LoadOrderSpread( 176118270 );\t// initial load";
+        assert_eq!(extract_item_nameid(page), Some(176118270));
+        assert_eq!(extract_item_nameid("<html></html>"), None);
+    }
+
+    #[test]
+    fn test_parse_order_histogram() {
+        let response = r#"{
+            "success": 1,
+            "highest_buy_order": "1203",
+            "lowest_sell_order": "1290",
+            "buy_order_graph": [[12.03, 4, "4 buy orders at $12.03 or higher"],
+                                [12.0, 54, "54 buy orders at $12.00 or higher"]],
+            "sell_order_graph": [[12.9, 2, "2 sell orders at $12.90 or lower"]]
+        }"#;
+        let order_book = parse_order_histogram(response, Utc::now()).unwrap();
+        assert_eq!(order_book.highest_buy_order, Some(1203));
+        assert_eq!(order_book.lowest_sell_order, Some(1290));
+        assert_eq!(
+            order_book.buy_walls,
+            vec![
+                OrderBookLevel {
+                    price: 1203,
+                    quantity: 4
+                },
+                OrderBookLevel {
+                    price: 1200,
+                    quantity: 50
+                },
+            ]
+        );
+        assert_eq!(order_book.sell_walls.len(), 1);
+
+        assert!(parse_order_histogram(r#"{"success": 16}"#, Utc::now()).is_none());
+    }
+}
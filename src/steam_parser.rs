@@ -0,0 +1,138 @@
+use std::{sync::Arc, time::Instant};
+
+use tokio::sync::{mpsc::Sender, Mutex, Semaphore};
+use tracing::error;
+
+use crate::{
+    config::AppConfig,
+    currency::SharedRates,
+    event_log::EventLog,
+    event_processors::parse_steam_response,
+    events::{PrimEvent, SteamAnalysisReadyEvent, SteamResponseEvent},
+    stats::{Stats, StatsKind},
+};
+
+// Parses Steam responses on blocking threads, so the steam pipeline only applies
+// the results. They come back to the primary queue as `SteamAnalysisReady`.
+pub struct SteamParserPool {
+    workers: Arc<Semaphore>,
+    prim_tx: Sender<PrimEvent>,
+    stats: Arc<Mutex<Stats>>,
+    event_log: Arc<EventLog>,
+    rates: SharedRates,
+}
+
+impl SteamParserPool {
+    pub fn new(
+        workers: usize,
+        prim_tx: Sender<PrimEvent>,
+        stats: Arc<Mutex<Stats>>,
+        event_log: Arc<EventLog>,
+        rates: SharedRates,
+    ) -> Self {
+        SteamParserPool {
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            prim_tx,
+            stats,
+            event_log,
+            rates,
+        }
+    }
+
+    // Waits for a free worker, so responses wait in the queue rather than in memory
+    pub async fn parse(&self, event: SteamResponseEvent, config: Arc<AppConfig>) {
+        let permit = self
+            .workers
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Semaphore of the steam parser is never closed");
+        let prim_tx = self.prim_tx.clone();
+        let stats = self.stats.clone();
+        let event_log = self.event_log.clone();
+        let rates = self.rates.load_full();
+
+        tokio::spawn(async move {
+            let _start = Instant::now();
+            let log_seq = event.log_seq;
+            let parsed =
+                tokio::task::spawn_blocking(move || parse_steam_response(&event, &rates, &config))
+                    .await;
+            drop(permit);
+            stats
+                .lock()
+                .await
+                .register_duration(StatsKind::SteamResponse, _start.elapsed());
+
+            let parsed = parsed.unwrap_or_else(|err| {
+                error!("Steam parser worker failed: {:?}", err);
+                None
+            });
+            let Some((market_name, result)) = parsed else {
+                event_log.mark_processed_seq(log_seq).await;
+                return;
+            };
+            let ready_event = PrimEvent::SteamAnalysisReady(SteamAnalysisReadyEvent {
+                market_name,
+                result,
+                log_seq,
+            });
+            // the result of a parsed response is never dropped, it waits for room in the queue
+            if let Err(err) = prim_tx.send(ready_event).await {
+                error!(
+                    "Queue is closed, {:?} event is dropped",
+                    StatsKind::SteamAnalysisReady
+                );
+                event_log.mark_processed(&err.0).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::*;
+    use crate::{currency::ExchangeRates, leadership::Leadership, sharding::Shard};
+
+    #[tokio::test]
+    async fn test_steam_parser_pool() {
+        // the second result waits for room in the full queue instead of being dropped
+        let (prim_tx, mut prim_rx) = tokio::sync::mpsc::channel(1);
+        // the event log only touches the db when events are appended
+        let db = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/test")
+            .unwrap();
+        let pool = SteamParserPool::new(
+            2,
+            prim_tx,
+            Arc::new(Mutex::new(Stats::new())),
+            Arc::new(EventLog::new(db, Shard::default(), Leadership::always())),
+            ExchangeRates::new().into_shared(),
+        );
+        let response = std::fs::read_to_string("src/test_data/Kilowatt Case.html").unwrap();
+        for log_seq in [3, 4] {
+            let event = SteamResponseEvent {
+                timestamp: Utc.with_ymd_and_hms(2024, 2, 19, 0, 0, 0).unwrap(),
+                response: response.clone(),
+                sell_listings: None,
+                log_seq: Some(log_seq),
+            };
+            pool.parse(event, Arc::new(AppConfig::default())).await;
+        }
+
+        let mut log_seqs = vec![];
+        for _ in 0..2 {
+            let Some(PrimEvent::SteamAnalysisReady(ready)) = prim_rx.recv().await else {
+                panic!("SteamAnalysisReady is expected");
+            };
+            assert_eq!(ready.market_name, "Kilowatt Case");
+            assert_eq!(ready.result.sold_per_week, Some(604_240));
+            log_seqs.push(ready.log_seq);
+        }
+        log_seqs.sort();
+        assert_eq!(log_seqs, vec![Some(3), Some(4)]);
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{AppConfig, RepricerConfig},
+    fee::SteamFee,
+    ledger::{clear_steam_listing, load_listed_cost_basis, update_steam_list_price},
+    notify::{NotificationKind, Notifications},
+    prices::{PriceValue, PriceValueTrait},
+    steam_orders::SteamOrderBook,
+    steam_seller::{SteamListing, SteamSeller, SteamSellerError},
+    storages::SteamEngine,
+};
+
+// Lowest ask of other sellers, our own listing at `own_price` (with fee) is skipped
+pub fn get_competing_ask(order_book: &SteamOrderBook, own_price: PriceValue) -> Option<PriceValue> {
+    if order_book.sell_walls.is_empty() {
+        return order_book.lowest_sell_order.filter(|x| *x != own_price);
+    }
+    order_book
+        .sell_walls
+        .iter()
+        .find(|x| x.price != own_price || x.quantity > 1)
+        .map(|x| x.price)
+}
+
+// `price` is the `steam_seller.percentile` price, all prices are with the Steam fee
+pub fn get_target_price(
+    price: PriceValue,
+    competing_ask: Option<PriceValue>,
+    config: &RepricerConfig,
+) -> PriceValue {
+    let Some(competing_ask) = competing_ask.filter(|_| config.undercut_cents > 0) else {
+        return price;
+    };
+    let min_price = (price as f64 * config.min_price_pct / 100.0).round() as PriceValue;
+    competing_ask
+        .saturating_sub(config.undercut_cents)
+        .max(min_price)
+}
+
+pub fn is_need_to_reprice(price: PriceValue, target: PriceValue, threshold_pct: f64) -> bool {
+    target > 0 && price.abs_diff(target) as f64 * 100.0 / target as f64 > threshold_pct
+}
+
+// Relists of the last 24 hours, it's reset on restart
+#[derive(Debug, Default)]
+pub struct RelistBudget {
+    relisted_at: VecDeque<DateTime<Utc>>,
+}
+
+impl RelistBudget {
+    pub fn new() -> Self {
+        RelistBudget::default()
+    }
+
+    pub fn try_take(&mut self, now: DateTime<Utc>, max_per_day: usize) -> bool {
+        while self
+            .relisted_at
+            .front()
+            .is_some_and(|x| now - *x >= Duration::days(1))
+        {
+            self.relisted_at.pop_front();
+        }
+        if self.relisted_at.len() >= max_per_day {
+            return false;
+        }
+        self.relisted_at.push_back(now);
+        true
+    }
+}
+
+// Target price after fee of each listing which should be repriced.
+// Items aren't relisted below what was paid for them, see `load_listed_cost_basis`.
+fn plan_repricing(
+    listings: Vec<SteamListing>,
+    cost_basis: &HashMap<String, PriceValue>,
+    steam_engine: &SteamEngine,
+    config: &AppConfig,
+) -> Vec<(SteamListing, PriceValue)> {
+    listings
+        .into_iter()
+        .filter_map(|listing| {
+            let cost = *cost_basis.get(&listing.asset_id)?;
+            let price = steam_engine
+                .hm
+                .get(&listing.market_name)?
+                .get_price_by_percentile(config.steam_seller.percentile)?;
+            let own_price = SteamFee::add_fee(listing.price);
+            let competing_ask = steam_engine
+                .order_books
+                .get(&listing.market_name)
+                .and_then(|x| get_competing_ask(x, own_price));
+            let target = get_target_price(price, competing_ask, &config.repricer);
+            let target = SteamFee::try_subtract_fee(target)?.max(cost);
+            is_need_to_reprice(listing.price, target, config.repricer.threshold_pct)
+                .then_some((listing, target))
+        })
+        .collect()
+}
+
+// Cancels and relists the listings of `steam_seller` whose price is off the target.
+// Returns the number of relisted items.
+pub async fn reprice_listings(
+    seller: &SteamSeller,
+    db: &Pool<Postgres>,
+    steam_engine: &Mutex<SteamEngine>,
+    budget: &mut RelistBudget,
+    notifications: &Notifications,
+    config: &AppConfig,
+) -> Result<usize, SteamSellerError> {
+    let cost_basis = load_listed_cost_basis(db)
+        .await
+        .map_err(SteamSellerError::Db)?;
+    if cost_basis.is_empty() {
+        return Ok(0);
+    }
+    // listings made manually are left alone
+    let listings: Vec<SteamListing> = seller
+        .fetch_my_listings()
+        .await?
+        .into_iter()
+        .filter(|x| cost_basis.contains_key(&x.asset_id))
+        .collect();
+    let listings_count = listings.len();
+    let plan = {
+        let steam_engine_locked = steam_engine.lock().await;
+        plan_repricing(listings, &cost_basis, &steam_engine_locked, config)
+    };
+
+    let mut relisted = 0;
+    for (listing, target) in plan {
+        if !budget.try_take(Utc::now(), config.repricer.max_relists_per_day) {
+            warn!(
+                "Relist budget is exhausted, {} is not repriced",
+                listing.market_name
+            );
+            break;
+        }
+        if let Err(err) = seller.remove_listing(&listing.listing_id).await {
+            warn!(
+                "Failed to cancel Steam listing of {}: {}",
+                listing.market_name, err
+            );
+            continue;
+        }
+
+        let text = match seller.sell_item(&listing.asset_id, target).await {
+            Ok(requires_confirmation) => {
+                relisted += 1;
+                if let Err(err) = update_steam_list_price(db, &listing.asset_id, target).await {
+                    error!(
+                        "Failed to save Steam price of {}: {:?}",
+                        listing.asset_id, err
+                    );
+                }
+                format!(
+                    "Repriced {} on Steam: ${} -> ${} after fee{}",
+                    listing.market_name,
+                    listing.price.to_usd(),
+                    target.to_usd(),
+                    match requires_confirmation {
+                        true => ", confirm it in the mobile app",
+                        false => "",
+                    }
+                )
+            }
+            Err(err) => {
+                warn!("Failed to relist {} on Steam: {}", listing.market_name, err);
+                // the steam seller lists it again on the next poll
+                if let Err(err) = clear_steam_listing(db, &listing.asset_id).await {
+                    error!(
+                        "Failed to clear Steam listing of {}: {:?}",
+                        listing.asset_id, err
+                    );
+                }
+                format!(
+                    "Cancelled {} on Steam but failed to relist it: {}",
+                    listing.market_name, err
+                )
+            }
+        };
+        notifications.notify(NotificationKind::SteamListing, text, config);
+    }
+    info!(
+        "Steam repricer: {} listings | {} relisted",
+        listings_count, relisted
+    );
+    Ok(relisted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::DESIRED_PERCENTILE,
+        steam_analyzer::{AnalysisResult, Smoothing, Trend},
+        steam_orders::OrderBookLevel,
+        steam_seller::parse_my_listings,
+        storages::SteamEngineTrait,
+    };
+
+    #[test]
+    fn test_parse_my_listings() {
+        let (listings, total_count) = parse_my_listings(
+            r#"{
+                "success": true,
+                "pagesize": 100,
+                "total_count": 2,
+                "start": 0,
+                "num_active_listings": 2,
+                "assets": {},
+                "listings": [
+                    {
+                        "listingid": "4001",
+                        "time_created": 1704441600,
+                        "asset": {"currency": 0, "appid": 730, "contextid": "2", "id": "101", "amount": "1", "market_hash_name": "AK-47 | Redline (Field-Tested)"},
+                        "price": 1000,
+                        "fee": 150
+                    }
+                ],
+                "listings_on_hold": [],
+                "listings_to_confirm": [],
+                "buy_orders": []
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(total_count, 2);
+        assert_eq!(
+            listings,
+            vec![SteamListing {
+                listing_id: "4001".to_string(),
+                asset_id: "101".to_string(),
+                market_name: "AK-47 | Redline (Field-Tested)".into(),
+                price: 10_00,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_get_target_price() {
+        let config = RepricerConfig::default();
+        assert_eq!(get_target_price(20_00, None, &config), 20_00);
+        assert_eq!(get_target_price(20_00, Some(21_00), &config), 20_99);
+        assert_eq!(get_target_price(20_00, Some(19_00), &config), 18_99);
+        // not below min_price_pct
+        assert_eq!(get_target_price(20_00, Some(15_00), &config), 18_00);
+
+        let config = RepricerConfig {
+            undercut_cents: 0,
+            ..RepricerConfig::default()
+        };
+        assert_eq!(get_target_price(20_00, Some(19_00), &config), 20_00);
+
+        let level = |price, quantity| OrderBookLevel { price, quantity };
+        let mut order_book = SteamOrderBook {
+            highest_buy_order: Some(18_00),
+            lowest_sell_order: Some(19_00),
+            buy_walls: vec![],
+            sell_walls: vec![level(19_00, 1), level(19_50, 3)],
+            updated_at: Utc::now(),
+        };
+        assert_eq!(get_competing_ask(&order_book, 19_00), Some(19_50));
+        assert_eq!(get_competing_ask(&order_book, 20_00), Some(19_00));
+        order_book.sell_walls.clear();
+        assert_eq!(get_competing_ask(&order_book, 19_00), None);
+    }
+
+    #[test]
+    fn test_is_need_to_reprice() {
+        assert!(!is_need_to_reprice(10_00, 10_40, 5.0));
+        assert!(is_need_to_reprice(10_00, 10_60, 5.0));
+        assert!(is_need_to_reprice(10_00, 9_40, 5.0));
+    }
+
+    #[test]
+    fn test_relist_budget() {
+        let now = Utc::now();
+        let mut budget = RelistBudget::new();
+        assert!(budget.try_take(now, 2));
+        assert!(budget.try_take(now + Duration::hours(1), 2));
+        assert!(!budget.try_take(now + Duration::hours(2), 2));
+        assert!(budget.try_take(now + Duration::hours(24), 2));
+        assert!(!budget.try_take(now + Duration::hours(24), 2));
+    }
+
+    #[test]
+    fn test_plan_repricing_keeps_cost_basis() {
+        let market_name = "AK-47 | Redline (Field-Tested)".into();
+        let mut steam_engine = SteamEngine::new();
+        steam_engine.update(
+            &market_name,
+            AnalysisResult {
+                rsd: Some(0.01),
+                is_stable: Some(true),
+                sold_per_week: Some(500),
+                percentiles: vec![(DESIRED_PERCENTILE, 13_00)],
+                percentiles_no_fee: vec![(DESIRED_PERCENTILE, 11_30)],
+                weighted_percentiles: vec![],
+                rejected_outliers: 0,
+                trend: Trend::Flat,
+                analyzed_at: Some(Utc::now()),
+                sell_listings: None,
+                expected_days_to_sell: None,
+                smoothing: Smoothing::Sma,
+                seasonality: None,
+            },
+        );
+        let listing = |asset_id: &str| SteamListing {
+            listing_id: format!("listing {}", asset_id),
+            asset_id: asset_id.to_string(),
+            market_name: market_name.clone(),
+            price: 15_00,
+        };
+        let cost_basis = HashMap::from([("1".to_string(), 10_00), ("2".to_string(), 12_50)]);
+        let config = AppConfig::default();
+
+        let plan = plan_repricing(
+            vec![listing("1"), listing("2"), listing("manual")],
+            &cost_basis,
+            &steam_engine,
+            &config,
+        );
+        let targets: Vec<(&str, PriceValue)> = plan
+            .iter()
+            .map(|(listing, target)| (listing.asset_id.as_str(), *target))
+            .collect();
+        // the percentile price after fee is below what was paid for the second item
+        assert_eq!(
+            targets,
+            vec![("1", SteamFee::subtract_fee(13_00)), ("2", 12_50)]
+        );
+    }
+}
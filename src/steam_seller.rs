@@ -0,0 +1,541 @@
+use std::{collections::HashSet, env, fmt, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use reqwest::{cookie::Jar, header::REFERER, Client, StatusCode, Url};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::AppConfig,
+    fee::SteamFee,
+    ledger::{load_listed_asset_ids, load_unlisted_purchases, set_steam_listing, UnlistedPurchase},
+    notify::{NotificationKind, Notifications},
+    prices::{PriceValue, PriceValueTrait},
+    steam_inventory::{is_same_float, match_stock},
+    storages::SteamEngine,
+    types::MarketName,
+};
+
+const STEAM_URL: &str = "https://steamcommunity.com";
+const SELL_ITEM_URL: &str = "https://steamcommunity.com/market/sellitem/";
+const MY_LISTINGS_URL: &str = "https://steamcommunity.com/market/mylistings";
+const REMOVE_LISTING_URL: &str = "https://steamcommunity.com/market/removelisting";
+const MY_LISTINGS_PAGE_SIZE: usize = 100;
+const CS2_APP_ID: &str = "730";
+const CS2_CONTEXT_ID: &str = "2";
+// propertyid of the "Wear Rating" asset property
+const WEAR_RATING_PROPERTY_ID: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InventoryItem {
+    pub asset_id: String,
+    pub market_name: MarketName,
+    // false during the trade protection after the purchase
+    pub is_tradable: bool,
+    pub is_marketable: bool,
+    // end of the trade protection, None for tradable items
+    pub tradable_after: Option<DateTime<Utc>>,
+    // "Wear Rating" asset property, None for items without wear
+    pub float: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct InventoryResponse {
+    #[serde(default)]
+    assets: Vec<InventoryAsset>,
+    #[serde(default)]
+    descriptions: Vec<InventoryDescription>,
+    #[serde(default)]
+    asset_properties: Vec<InventoryAssetProperties>,
+}
+
+#[derive(Deserialize)]
+struct InventoryAsset {
+    assetid: String,
+    classid: String,
+    instanceid: String,
+}
+
+#[derive(Deserialize)]
+struct InventoryDescription {
+    classid: String,
+    instanceid: String,
+    market_hash_name: MarketName,
+    #[serde(default)]
+    tradable: u8,
+    #[serde(default)]
+    marketable: u8,
+    // set for items under the trade protection
+    #[serde(default)]
+    cache_expiration: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct InventoryAssetProperties {
+    assetid: String,
+    #[serde(default)]
+    asset_properties: Vec<InventoryAssetProperty>,
+}
+
+#[derive(Deserialize)]
+struct InventoryAssetProperty {
+    propertyid: u32,
+    #[serde(default)]
+    float_value: Option<String>,
+}
+
+// Assets are joined with their descriptions by (classid, instanceid)
+// and with their properties by assetid
+pub fn parse_inventory(text: &str) -> Result<Vec<InventoryItem>, serde_json::Error> {
+    let response: InventoryResponse = serde_json::from_str(text)?;
+    let items = response
+        .assets
+        .into_iter()
+        .filter_map(|asset| {
+            let description = response
+                .descriptions
+                .iter()
+                .find(|x| x.classid == asset.classid && x.instanceid == asset.instanceid)?;
+            let float = response
+                .asset_properties
+                .iter()
+                .find(|x| x.assetid == asset.assetid)
+                .and_then(|x| {
+                    x.asset_properties
+                        .iter()
+                        .find(|x| x.propertyid == WEAR_RATING_PROPERTY_ID)
+                })
+                .and_then(|x| x.float_value.as_ref()?.parse().ok());
+            Some(InventoryItem {
+                asset_id: asset.assetid,
+                market_name: description.market_hash_name.clone(),
+                is_tradable: description.tradable == 1,
+                is_marketable: description.marketable == 1,
+                tradable_after: description.cache_expiration,
+                float,
+            })
+        })
+        .collect();
+    Ok(items)
+}
+
+// Purchases are matched to sellable items by float, see `match_stock`, so an item the user
+// owned before isn't listed in place of the bought one. Only items without a float, e.g. cases,
+// are matched by the market name alone, any of them is the same. Items listed before are skipped.
+pub fn match_purchases(
+    inventory: &[InventoryItem],
+    purchases: &[UnlistedPurchase],
+    listed_asset_ids: &HashSet<String>,
+) -> Vec<(UnlistedPurchase, InventoryItem)> {
+    let sellable: Vec<InventoryItem> = inventory
+        .iter()
+        .filter(|x| x.is_tradable && x.is_marketable && !listed_asset_ids.contains(&x.asset_id))
+        .cloned()
+        .collect();
+    match_stock(&sellable, purchases)
+        .into_iter()
+        .filter(|(purchase, item)| match (purchase.float, item.float) {
+            (None, None) => true,
+            _ => is_same_float(purchase, item),
+        })
+        .collect()
+}
+
+// Active sell listing on the Steam market
+#[derive(Debug, Clone, PartialEq)]
+pub struct SteamListing {
+    pub listing_id: String,
+    pub asset_id: String,
+    pub market_name: MarketName,
+    // what the seller receives, without the Steam fee
+    pub price: PriceValue,
+}
+
+#[derive(Deserialize)]
+struct MyListingsResponse {
+    #[serde(default)]
+    total_count: usize,
+    #[serde(default)]
+    listings: Vec<MyListing>,
+}
+
+#[derive(Deserialize)]
+struct MyListing {
+    listingid: String,
+    price: PriceValue,
+    asset: MyListingAsset,
+}
+
+#[derive(Deserialize)]
+struct MyListingAsset {
+    id: String,
+    market_hash_name: MarketName,
+}
+
+// One page of /market/mylistings, returns the listings and the total count of them.
+// Listings waiting for a confirmation or on hold come separately and are skipped.
+pub fn parse_my_listings(text: &str) -> Result<(Vec<SteamListing>, usize), serde_json::Error> {
+    let response: MyListingsResponse = serde_json::from_str(text)?;
+    let listings = response
+        .listings
+        .into_iter()
+        .map(|x| SteamListing {
+            listing_id: x.listingid,
+            asset_id: x.asset.id,
+            market_name: x.asset.market_hash_name,
+            price: x.price,
+        })
+        .collect();
+    Ok((listings, response.total_count))
+}
+
+#[derive(Debug)]
+pub enum SteamSellerError {
+    // STEAM_ID or STEAM_SESSION_ID is not set
+    NotConfigured,
+    Http(reqwest::Error),
+    Status(StatusCode),
+    Parse(serde_json::Error),
+    Db(sqlx::Error),
+    // Steam refused to list the item
+    Rejected(String),
+}
+
+impl fmt::Display for SteamSellerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SteamSellerError::NotConfigured => write!(f, "STEAM_ID or STEAM_SESSION_ID is not set"),
+            SteamSellerError::Http(err) => write!(f, "http: {}", err),
+            SteamSellerError::Status(status) => write!(f, "unexpected status {}", status),
+            SteamSellerError::Parse(err) => write!(f, "parse: {}", err),
+            SteamSellerError::Db(err) => write!(f, "db: {}", err),
+            SteamSellerError::Rejected(message) => write!(f, "rejected: {}", message),
+        }
+    }
+}
+
+// {"success": true, "requires_confirmation": 1, ...} or {"success": false, "message": "..."}.
+// Returns whether the listing waits for a mobile confirmation.
+pub fn parse_sell_response(text: &str) -> Result<bool, SteamSellerError> {
+    let json: serde_json::Value = serde_json::from_str(text).map_err(SteamSellerError::Parse)?;
+    if json["success"] != true {
+        let message = json["message"].as_str().unwrap_or(text).to_string();
+        return Err(SteamSellerError::Rejected(message));
+    }
+    Ok(json["requires_confirmation"].as_u64().unwrap_or(0) == 1)
+}
+
+pub struct SteamSeller {
+    client: Client,
+    steam_id: Option<String>,
+    session_id: Option<String>,
+}
+
+impl SteamSeller {
+    // Same session cookies as the steam fetcher, plus STEAM_ID (SteamID64) of the inventory
+    pub fn from_env() -> SteamSeller {
+        let jar = Arc::new(Jar::default());
+        let steam_url = STEAM_URL.parse::<Url>().unwrap();
+        for (cookie_name, env_key) in [
+            ("steamLoginSecure", "STEAM_LOGIN_SECURE"),
+            ("sessionid", "STEAM_SESSION_ID"),
+        ] {
+            if let Ok(value) = env::var(env_key) {
+                jar.add_cookie_str(
+                    &format!(
+                        "{}={}; Domain=steamcommunity.com; Path=/",
+                        cookie_name, value
+                    ),
+                    &steam_url,
+                );
+            }
+        }
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(15))
+            .cookie_provider(jar)
+            .build()
+            .expect("Failed to build client for steam seller");
+
+        SteamSeller {
+            client,
+            steam_id: env::var("STEAM_ID").ok(),
+            session_id: env::var("STEAM_SESSION_ID").ok(),
+        }
+    }
+
+    fn get_credentials(&self) -> Result<(&str, &str), SteamSellerError> {
+        match (&self.steam_id, &self.session_id) {
+            (Some(steam_id), Some(session_id)) => Ok((steam_id, session_id)),
+            _ => Err(SteamSellerError::NotConfigured),
+        }
+    }
+
+    pub async fn fetch_inventory(&self) -> Result<Vec<InventoryItem>, SteamSellerError> {
+        let (steam_id, _) = self.get_credentials()?;
+        let url = format!(
+            "{}/inventory/{}/{}/{}?l=english&count=2000",
+            STEAM_URL, steam_id, CS2_APP_ID, CS2_CONTEXT_ID
+        );
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(SteamSellerError::Http)?;
+        if !response.status().is_success() {
+            return Err(SteamSellerError::Status(response.status()));
+        }
+        let text = response.text().await.map_err(SteamSellerError::Http)?;
+        parse_inventory(&text).map_err(SteamSellerError::Parse)
+    }
+
+    pub async fn fetch_my_listings(&self) -> Result<Vec<SteamListing>, SteamSellerError> {
+        let mut listings = vec![];
+        loop {
+            let response = self
+                .client
+                .get(MY_LISTINGS_URL)
+                .query(&[
+                    ("norender", "1".to_string()),
+                    ("start", listings.len().to_string()),
+                    ("count", MY_LISTINGS_PAGE_SIZE.to_string()),
+                ])
+                .send()
+                .await
+                .map_err(SteamSellerError::Http)?;
+            if !response.status().is_success() {
+                return Err(SteamSellerError::Status(response.status()));
+            }
+            let text = response.text().await.map_err(SteamSellerError::Http)?;
+            let (page, total_count) = parse_my_listings(&text).map_err(SteamSellerError::Parse)?;
+            let is_last_page = page.is_empty() || listings.len() + page.len() >= total_count;
+            listings.extend(page);
+            if is_last_page {
+                return Ok(listings);
+            }
+        }
+    }
+
+    // The item goes back to the inventory
+    pub async fn remove_listing(&self, listing_id: &str) -> Result<(), SteamSellerError> {
+        let (steam_id, session_id) = self.get_credentials()?;
+        let response = self
+            .client
+            .post(format!("{}/{}", REMOVE_LISTING_URL, listing_id))
+            .header(
+                REFERER,
+                format!("{}/profiles/{}/inventory", STEAM_URL, steam_id),
+            )
+            .form(&[("sessionid", session_id)])
+            .send()
+            .await
+            .map_err(SteamSellerError::Http)?;
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(SteamSellerError::Status(response.status())),
+        }
+    }
+
+    // `price` is what the seller receives, Steam adds its fee on top
+    pub async fn sell_item(
+        &self,
+        asset_id: &str,
+        price: PriceValue,
+    ) -> Result<bool, SteamSellerError> {
+        let (steam_id, session_id) = self.get_credentials()?;
+        let price = price.to_string();
+        let form = [
+            ("sessionid", session_id),
+            ("appid", CS2_APP_ID),
+            ("contextid", CS2_CONTEXT_ID),
+            ("assetid", asset_id),
+            ("amount", "1"),
+            ("price", price.as_str()),
+        ];
+        let response = self
+            .client
+            .post(SELL_ITEM_URL)
+            .header(
+                REFERER,
+                format!("{}/profiles/{}/inventory", STEAM_URL, steam_id),
+            )
+            .form(&form)
+            .send()
+            .await
+            .map_err(SteamSellerError::Http)?;
+        let status = response.status();
+        let text = response.text().await.map_err(SteamSellerError::Http)?;
+        // rejections come with 502 and the message in the body
+        if !status.is_success() && !text.starts_with('{') {
+            return Err(SteamSellerError::Status(status));
+        }
+        parse_sell_response(&text)
+    }
+}
+
+// Lists delivered purchases at `steam_seller.percentile` of the Steam analysis.
+// Returns the number of listed items.
+pub async fn list_purchased_items(
+    seller: &SteamSeller,
+    db: &Pool<Postgres>,
+    steam_engine: &Mutex<SteamEngine>,
+    notifications: &Notifications,
+    config: &AppConfig,
+) -> Result<usize, SteamSellerError> {
+    let since = Utc::now() - config.steam_seller.lookback();
+    let purchases = load_unlisted_purchases(db, since)
+        .await
+        .map_err(SteamSellerError::Db)?;
+    if purchases.is_empty() {
+        return Ok(0);
+    }
+    let listed_asset_ids = load_listed_asset_ids(db)
+        .await
+        .map_err(SteamSellerError::Db)?;
+    let inventory = seller.fetch_inventory().await?;
+
+    let mut listed = 0;
+    for (purchase, item) in match_purchases(&inventory, &purchases, &listed_asset_ids) {
+        let price = steam_engine
+            .lock()
+            .await
+            .hm
+            .get(&item.market_name)
+            .and_then(|x| x.get_price_by_percentile(config.steam_seller.percentile));
+        let Some((price, price_no_fee)) =
+            price.and_then(|x| Some((x, SteamFee::try_subtract_fee(x)?)))
+        else {
+            warn!("No Steam price to list {}", item.market_name);
+            continue;
+        };
+
+        match seller.sell_item(&item.asset_id, price_no_fee).await {
+            Ok(requires_confirmation) => {
+                listed += 1;
+                if let Err(err) =
+                    set_steam_listing(db, &purchase.listing_id, &item.asset_id, price_no_fee).await
+                {
+                    error!(
+                        "Failed to save Steam listing of {}: {:?}",
+                        purchase.listing_id, err
+                    );
+                }
+                let text = format!(
+                    "Listed {} on Steam for ${} (${} after fee){}",
+                    item.market_name,
+                    price.to_usd(),
+                    price_no_fee.to_usd(),
+                    match requires_confirmation {
+                        true => ", confirm it in the mobile app",
+                        false => "",
+                    }
+                );
+                notifications.notify(NotificationKind::SteamListing, text, config);
+            }
+            Err(err) => {
+                warn!("Failed to list {} on Steam: {}", item.market_name, err);
+                let text = format!("Failed to list {} on Steam: {}", item.market_name, err);
+                notifications.notify(NotificationKind::SteamListing, text, config);
+            }
+        }
+    }
+    info!(
+        "Steam seller: {} unlisted purchases | {} listed",
+        purchases.len(),
+        listed
+    );
+    Ok(listed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_inventory_and_match_purchases() {
+        let inventory = parse_inventory(
+            r#"{
+                "assets": [
+                    {"appid": 730, "contextid": "2", "assetid": "1", "classid": "10", "instanceid": "0", "amount": "1"},
+                    {"appid": 730, "contextid": "2", "assetid": "2", "classid": "10", "instanceid": "0", "amount": "1"},
+                    {"appid": 730, "contextid": "2", "assetid": "3", "classid": "20", "instanceid": "0", "amount": "1"}
+                ],
+                "descriptions": [
+                    {"classid": "10", "instanceid": "0", "market_hash_name": "AK-47 | Redline (Field-Tested)", "tradable": 1, "marketable": 1},
+                    {"classid": "20", "instanceid": "0", "market_hash_name": "M4A4 | Howl (Field-Tested)", "tradable": 0, "marketable": 0}
+                ],
+                "total_inventory_count": 3,
+                "success": 1
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(inventory.len(), 3);
+        assert_eq!(inventory[2].market_name, "M4A4 | Howl (Field-Tested)");
+        assert!(!inventory[2].is_tradable);
+
+        let purchase = |listing_id: &str, market_name: &str| UnlistedPurchase {
+            listing_id: listing_id.into(),
+            market_name: market_name.into(),
+            paid_price: 10_00,
+            float: None,
+        };
+        let purchases = vec![
+            purchase("a", "AK-47 | Redline (Field-Tested)"),
+            purchase("b", "AK-47 | Redline (Field-Tested)"),
+            purchase("c", "M4A4 | Howl (Field-Tested)"),
+        ];
+        let listed = HashSet::from(["1".to_string()]);
+        let matched = match_purchases(&inventory, &purchases, &listed);
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].0.listing_id, "a");
+        assert_eq!(matched[0].1.asset_id, "2");
+
+        let matched = match_purchases(&inventory, &purchases, &HashSet::new());
+        let asset_ids: Vec<_> = matched.iter().map(|(_, x)| x.asset_id.as_str()).collect();
+        assert_eq!(asset_ids, vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_match_purchases_by_float() {
+        let item = |asset_id: &str, float: Option<f64>| InventoryItem {
+            asset_id: asset_id.to_string(),
+            market_name: "AK-47 | Redline (Field-Tested)".into(),
+            is_tradable: true,
+            is_marketable: true,
+            tradable_after: None,
+            float,
+        };
+        let purchase = |listing_id: &str, float: Option<f64>| UnlistedPurchase {
+            listing_id: listing_id.into(),
+            market_name: "AK-47 | Redline (Field-Tested)".into(),
+            paid_price: 10_00,
+            float,
+        };
+        // the user's own item comes first in the inventory
+        let inventory = vec![item("1", Some(0.25)), item("2", Some(0.2312))];
+        let purchases = vec![purchase("a", Some(0.231_200_4)), purchase("b", Some(0.3))];
+        let matched = match_purchases(&inventory, &purchases, &HashSet::new());
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].0.listing_id, "a");
+        assert_eq!(matched[0].1.asset_id, "2");
+
+        // an item can't be told apart without the float of either side
+        let matched = match_purchases(&inventory, &[purchase("c", None)], &HashSet::new());
+        assert_eq!(matched, vec![]);
+        let matched = match_purchases(&[item("3", None)], &purchases, &HashSet::new());
+        assert_eq!(matched, vec![]);
+    }
+
+    #[test]
+    fn test_parse_sell_response() {
+        assert!(parse_sell_response(r#"{"success": true, "requires_confirmation": 1}"#).unwrap());
+        assert!(!parse_sell_response(r#"{"success": true, "requires_confirmation": 0}"#).unwrap());
+        assert!(matches!(
+            parse_sell_response(r#"{"success": false, "message": "The item is not tradable"}"#),
+            Err(SteamSellerError::Rejected(message)) if message == "The item is not tradable"
+        ));
+    }
+}
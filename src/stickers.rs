@@ -0,0 +1,93 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{models::CsfloatListingItem, prices::PriceValue};
+
+// Latest known sticker prices, filled from `reference` blocks of CSFloat listings
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct StickerPriceTable {
+    hm: HashMap<String, PriceValue>,
+    // stickers with price changed since the last save
+    #[serde(skip)]
+    dirty: HashSet<String>,
+}
+
+impl StickerPriceTable {
+    pub fn new() -> Self {
+        StickerPriceTable {
+            hm: HashMap::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    pub fn update_from_item(&mut self, item: &CsfloatListingItem) {
+        let applied = item
+            .stickers
+            .iter()
+            .chain(item.keychains.iter())
+            .chain(item.patches.iter());
+        for sticker in applied {
+            if let Some(reference) = &sticker.reference {
+                let old_price = self.hm.insert(sticker.name.clone(), reference.price);
+                if old_price != Some(reference.price) {
+                    self.dirty.insert(sticker.name.clone());
+                }
+            }
+        }
+    }
+
+    pub fn get_price(&self, name: &str) -> Option<PriceValue> {
+        self.hm.get(name).copied()
+    }
+
+    // used on load, the price is not marked as changed
+    pub fn insert_saved(&mut self, name: String, price: PriceValue) {
+        self.hm.insert(name, price);
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = self.hm.keys().cloned().collect();
+    }
+
+    pub fn mark_dirty(&mut self, name: &str) {
+        self.dirty.insert(name.to_string());
+    }
+
+    // changed prices, the table is considered saved afterwards
+    pub fn take_dirty(&mut self) -> Vec<(String, PriceValue)> {
+        self.dirty
+            .drain()
+            .filter_map(|name| self.hm.get(&name).map(|price| (name, *price)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_changed_prices_are_dirty() {
+        let item: CsfloatListingItem = serde_json::from_str(
+            r#"{
+                "market_hash_name": "AK-47 | Redline (Field-Tested)",
+                "stickers": [{"name": "Sticker | Titan (Holo) | Katowice 2014", "reference": {"price": 100000}}]
+            }"#,
+        )
+        .unwrap();
+        let mut table = StickerPriceTable::new();
+        table.insert_saved("Sticker | Titan (Holo) | Katowice 2014".to_string(), 100000);
+
+        table.update_from_item(&item);
+        assert!(table.take_dirty().is_empty());
+
+        table.insert_saved("Sticker | Titan (Holo) | Katowice 2014".to_string(), 90000);
+        table.update_from_item(&item);
+        assert_eq!(
+            table.take_dirty(),
+            vec![("Sticker | Titan (Holo) | Katowice 2014".to_string(), 100000)]
+        );
+        assert!(table.take_dirty().is_empty());
+    }
+}
@@ -1,69 +1,356 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
-use tracing::{error, warn};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
 
 use crate::{
+    clock::{system_clock, SharedClock},
+    consts::{DESIRED_PERCENTILE, PAPER_CHECK_TTL},
+    market_aggregates::MarketAggregates,
     models::{CsfloatListingState, CsfloatListingStruct},
+    prefilter::PrefilterRejections,
+    price_history::{PriceChanges, PriceKind, PricePoint},
+    prices::PriceValue,
+    sharding::Shard,
+    state_store::{StateStore, StoreError},
     steam_analyzer::AnalysisResult,
+    steam_orders::SteamOrderBook,
+    stickers::StickerPriceTable,
     types::{ListingId, MarketName},
 };
 
+// Engines are stored in `csfloat_listings`, `steam_analysis` and `sticker_prices` tables
+// of the configured `StateStore`, only entries changed since the previous save are written.
+// `rust_dump` keeps only legacy whole-engine dumps, which are migrated into the tables on first load.
+// Only implemented by the engines of this crate, so `Send` bounds of the futures aren't needed.
+#[allow(async_fn_in_trait)]
 pub trait DbSerializable<T> {
-    async fn deserialize(db: &Pool<Postgres>) -> T;
-    async fn serialize(&self, db: &Pool<Postgres>);
-    async fn deserialize_load(db: &Pool<Postgres>, key: &str) -> Option<String> {
-        match sqlx::query_scalar("SELECT value FROM rust_dump WHERE key = $1")
-            .bind(key)
-            .fetch_one(db)
-            .await
-        {
-            Ok(it) => it,
-            Err(err) => {
-                match err {
-                    sqlx::Error::RowNotFound => warn!("No saved state for {}", key),
-                    _ => {
-                        warn!("Failed to load state for {} {:?}", key, err);
-                    }
-                }
+    type Snapshot: DbSnapshot;
 
-                None
-            }
+    async fn deserialize(store: &impl StateStore) -> T;
+    // Cheap, called under the engine lock: copies changed entries and resets the dirty set
+    fn take_snapshot(&mut self) -> Self::Snapshot;
+    // Marks entries of a failed save as changed again, so they're retried next time
+    fn restore_snapshot(&mut self, snapshot: &Self::Snapshot);
+    async fn serialize(&mut self, store: &impl StateStore) -> Result<(), StoreError> {
+        let snapshot = self.take_snapshot();
+        if let Err(err) = snapshot.save(store).await {
+            error!("Failed to save state: {}", err);
+            self.restore_snapshot(&snapshot);
+            return Err(err);
         }
+        Ok(())
     }
-    async fn serialize_to_db(db: &Pool<Postgres>, key: &str, serialized: String) {
-        match sqlx::query(
-            "INSERT INTO rust_dump (key, value) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET value = $2",
-        )
-        .bind(key)
-        .bind(serialized)
-        .execute(db)
-        .await
-        {
-            Ok(_) => {}
-            Err(err) => error!(
-                "Failed to serialize and save state for {}: {:?}",
-                key, err
-            ),
-        };
+}
+
+#[allow(async_fn_in_trait)]
+pub trait DbSnapshot {
+    fn get_size(&self) -> usize;
+    async fn save(&self, store: &impl StateStore) -> Result<(), StoreError>;
+}
+
+// Unlike `serialize`, holds the engine lock only to take the snapshot,
+// so event processing isn't paused while the DB is written.
+// Returns the number of saved entries.
+// Returns the number of saved entries
+pub async fn save_engine<T: DbSerializable<T>>(
+    engine: &Mutex<T>,
+    store: &impl StateStore,
+) -> Result<usize, StoreError> {
+    let snapshot = engine.lock().await.take_snapshot();
+    if let Err(err) = snapshot.save(store).await {
+        error!("Failed to save state: {}", err);
+        engine.lock().await.restore_snapshot(&snapshot);
+        return Err(err);
+    }
+    Ok(snapshot.get_size())
+}
+
+pub async fn create_tables(db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+    const QUERIES: [&str; 34] = [
+        "CREATE TABLE IF NOT EXISTS rust_dump (key TEXT PRIMARY KEY, value TEXT)",
+        "CREATE TABLE IF NOT EXISTS csfloat_listings (
+            id TEXT PRIMARY KEY,
+            market_hash_name TEXT NOT NULL,
+            price BIGINT NOT NULL,
+            updated_at TIMESTAMPTZ,
+            data TEXT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS steam_analysis (
+            market_name TEXT PRIMARY KEY,
+            analysis TEXT,
+            order_book TEXT,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+        // binary snapshots of `snapshot_codec`, the JSON columns are left from the old format
+        "ALTER TABLE csfloat_listings ADD COLUMN IF NOT EXISTS data_bin BYTEA",
+        "ALTER TABLE csfloat_listings ALTER COLUMN data DROP NOT NULL",
+        "ALTER TABLE steam_analysis ADD COLUMN IF NOT EXISTS analysis_bin BYTEA",
+        "ALTER TABLE steam_analysis ADD COLUMN IF NOT EXISTS order_book_bin BYTEA",
+        "CREATE TABLE IF NOT EXISTS sticker_prices (
+            name TEXT PRIMARY KEY,
+            price BIGINT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS purchases (
+            id BIGSERIAL PRIMARY KEY,
+            listing_id TEXT NOT NULL,
+            market_hash_name TEXT NOT NULL,
+            paid_price BIGINT NOT NULL,
+            expected_steam_price BIGINT NOT NULL,
+            expected_profit BIGINT NOT NULL,
+            profit_pct DOUBLE PRECISION NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL,
+            is_success BOOLEAN NOT NULL,
+            outcome TEXT NOT NULL
+        )",
+        "ALTER TABLE purchases ADD COLUMN IF NOT EXISTS is_paper BOOLEAN NOT NULL DEFAULT false",
+        "ALTER TABLE purchases ADD COLUMN IF NOT EXISTS is_available BOOLEAN",
+        "ALTER TABLE purchases ADD COLUMN IF NOT EXISTS steam_asset_id TEXT",
+        "ALTER TABLE purchases ADD COLUMN IF NOT EXISTS steam_list_price BIGINT",
+        "ALTER TABLE purchases ADD COLUMN IF NOT EXISTS steam_listed_at TIMESTAMPTZ",
+        "ALTER TABLE purchases ADD COLUMN IF NOT EXISTS float_value DOUBLE PRECISION",
+        "ALTER TABLE purchases ADD COLUMN IF NOT EXISTS strategy TEXT",
+        "CREATE TABLE IF NOT EXISTS sales (
+            id BIGSERIAL PRIMARY KEY,
+            market_hash_name TEXT NOT NULL,
+            price BIGINT NOT NULL,
+            sold_at TIMESTAMPTZ NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS watch_rules (
+            id BIGSERIAL PRIMARY KEY,
+            market_name TEXT NOT NULL,
+            max_price BIGINT NOT NULL,
+            max_float DOUBLE PRECISION
+        )",
+        "CREATE TABLE IF NOT EXISTS seller_blacklist (steam_id TEXT PRIMARY KEY)",
+        // see `ledger::save_kill_switch`
+        "CREATE TABLE IF NOT EXISTS autobuy_state (name TEXT PRIMARY KEY, value TEXT NOT NULL)",
+        // either market_name or listing_id is set, forever without until
+        "CREATE TABLE IF NOT EXISTS item_mutes (
+            id BIGSERIAL PRIMARY KEY,
+            market_name TEXT,
+            listing_id TEXT,
+            until TIMESTAMPTZ
+        )",
+        // see `RealtimeImporter::load`, timestamps of the responses tables are without a zone.
+        // Each shard imports on its own, its cursors were keyed by the source alone before.
+        "CREATE TABLE IF NOT EXISTS importer_cursors (
+            shard TEXT NOT NULL DEFAULT '0/1',
+            source TEXT NOT NULL,
+            last_ts TIMESTAMP NOT NULL
+        )",
+        "ALTER TABLE importer_cursors ADD COLUMN IF NOT EXISTS shard TEXT NOT NULL DEFAULT '0/1'",
+        "ALTER TABLE importer_cursors DROP CONSTRAINT IF EXISTS importer_cursors_pkey",
+        "CREATE UNIQUE INDEX IF NOT EXISTS importer_cursors_shard_source
+            ON importer_cursors (shard, source)",
+        // see `stats_history`, counters are deltas since the previous flush of the shard
+        "CREATE TABLE IF NOT EXISTS bot_stats (
+            created_at TIMESTAMPTZ NOT NULL,
+            shard TEXT NOT NULL,
+            name TEXT NOT NULL,
+            value DOUBLE PRECISION NOT NULL
+        )",
+        "CREATE INDEX IF NOT EXISTS bot_stats_name_created_at ON bot_stats (name, created_at)",
+        // see `response_archive`, partitions are created by the archiver
+        "CREATE TABLE IF NOT EXISTS csfloat_responses_archive (
+            timestamp TIMESTAMP NOT NULL,
+            response TEXT NOT NULL
+        ) PARTITION BY RANGE (timestamp)",
+        "CREATE TABLE IF NOT EXISTS steam_responses_archive (
+            timestamp TIMESTAMP NOT NULL,
+            response TEXT NOT NULL
+        ) PARTITION BY RANGE (timestamp)",
+        // see `price_history`, listing_id is set only for the prices of listings
+        "CREATE TABLE IF NOT EXISTS price_history (
+            created_at TIMESTAMPTZ NOT NULL,
+            market_name TEXT NOT NULL,
+            listing_id TEXT,
+            kind TEXT NOT NULL,
+            price BIGINT NOT NULL
+        )",
+        "CREATE INDEX IF NOT EXISTS price_history_market_name_created_at
+            ON price_history (market_name, created_at)",
+        // see `EventLog`, events are removed once the engines of their shard are saved
+        "CREATE TABLE IF NOT EXISTS event_log (
+            seq BIGSERIAL PRIMARY KEY,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            shard TEXT NOT NULL DEFAULT '0/1',
+            event TEXT NOT NULL
+        )",
+        "ALTER TABLE event_log ADD COLUMN IF NOT EXISTS shard TEXT NOT NULL DEFAULT '0/1'",
+        "CREATE INDEX IF NOT EXISTS event_log_shard_seq ON event_log (shard, seq)",
+    ];
+    for query in QUERIES {
+        sqlx::query(query).execute(db).await?;
     }
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CsfloatEngine {
     pub hm: HashMap<ListingId, CsfloatListingStruct>,
     pub listing_id_to_last_update_time: HashMap<ListingId, Option<DateTime<Utc>>>,
+    #[serde(default)]
+    pub sticker_prices: StickerPriceTable,
+    // listings added, updated or removed since the last save
+    #[serde(skip)]
+    dirty: HashSet<ListingId>,
+    #[serde(skip)]
+    is_loaded_from_blob: bool,
+    // paper purchases waiting for the next refresh of their listing, with the time they were made
+    #[serde(skip)]
+    pub paper_checks: HashMap<ListingId, DateTime<Utc>>,
+    // rebuilt from `hm` on load
+    #[serde(skip)]
+    pub aggregates: MarketAggregates,
+    // ids of the listings in `hm` by their market name, rebuilt on load
+    #[serde(skip)]
+    listing_ids_by_name: HashMap<MarketName, HashSet<ListingId>>,
+    // listings of other shards are ignored
+    #[serde(skip)]
+    pub shard: Shard,
+    #[serde(skip)]
+    pub price_changes: PriceChanges,
+    #[serde(skip)]
+    pub prefilter_rejections: PrefilterRejections,
+    // times of the updates and price changes
+    #[serde(skip, default = "system_clock")]
+    clock: SharedClock,
+}
+
+impl Default for CsfloatEngine {
+    fn default() -> Self {
+        CsfloatEngine::new()
+    }
 }
 
 impl CsfloatEngine {
     pub fn new() -> Self {
+        CsfloatEngine::with_clock(system_clock())
+    }
+
+    pub fn with_clock(clock: SharedClock) -> Self {
         CsfloatEngine {
             hm: HashMap::new(),
             listing_id_to_last_update_time: HashMap::new(),
+            sticker_prices: StickerPriceTable::new(),
+            dirty: HashSet::new(),
+            is_loaded_from_blob: false,
+            paper_checks: HashMap::new(),
+            aggregates: MarketAggregates::new(),
+            listing_ids_by_name: HashMap::new(),
+            shard: Shard::default(),
+            price_changes: PriceChanges::new(),
+            prefilter_rejections: PrefilterRejections::new(),
+            clock,
+        }
+    }
+
+    // the time of the engine's clock, the replayed time in the backtest
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    // checks past `PAPER_CHECK_TTL` are dropped, their listings may be never refreshed
+    pub fn add_paper_check(&mut self, listing_id: &ListingId) {
+        let now = self.clock.now();
+        let deadline = now - Duration::from_std(PAPER_CHECK_TTL).unwrap();
+        self.paper_checks
+            .retain(|_, created_at| *created_at >= deadline);
+        self.paper_checks.insert(listing_id.clone(), now);
+    }
+
+    fn apply_listing(
+        &mut self,
+        listing_struct: &CsfloatListingStruct,
+    ) -> CsfloatEngineListingDecision {
+        let listing_id = &listing_struct.id;
+        self.sticker_prices.update_from_item(&listing_struct.item);
+        self.aggregates.update(listing_struct);
+        self.index_listing(listing_struct);
+        match self.hm.insert(listing_id.clone(), listing_struct.clone()) {
+            Some(old_listing) => {
+                // names of older versions may be canonicalized differently
+                if old_listing.item.market_hash_name != listing_struct.item.market_hash_name {
+                    self.unindex_listing(&old_listing);
+                }
+                if listing_struct.state == CsfloatListingState::Delisted
+                    || listing_struct.state == CsfloatListingState::Sold
+                    || listing_struct.state == CsfloatListingState::Refunded
+                {
+                    self.remove_listing(listing_id);
+                    return CsfloatEngineListingDecision::Removed;
+                }
+                self.listing_id_to_last_update_time
+                    .insert(listing_id.clone(), Some(self.clock.now()));
+                // update time of not changed listings isn't saved, it only affects
+                // the refresh order after restart
+                let is_updated = old_listing.has_any_important_changes(listing_struct);
+                match is_updated {
+                    true => {
+                        self.dirty.insert(listing_id.clone());
+                        CsfloatEngineListingDecision::Updated
+                    }
+                    false => CsfloatEngineListingDecision::NotChanged,
+                }
+            }
+            None => {
+                self.listing_id_to_last_update_time
+                    .insert(listing_id.clone(), Some(self.clock.now()));
+                self.dirty.insert(listing_id.clone());
+                CsfloatEngineListingDecision::New
+            }
+        }
+    }
+
+    fn index_listing(&mut self, listing: &CsfloatListingStruct) {
+        self.listing_ids_by_name
+            .entry(listing.item.market_hash_name.clone())
+            .or_default()
+            .insert(listing.id.clone());
+    }
+
+    fn unindex_listing(&mut self, listing: &CsfloatListingStruct) {
+        let market_name = &listing.item.market_hash_name;
+        if let Some(listing_ids) = self.listing_ids_by_name.get_mut(market_name) {
+            listing_ids.remove(&listing.id);
+            if listing_ids.is_empty() {
+                self.listing_ids_by_name.remove(market_name);
+            }
         }
     }
+
+    // Listings of the market name in no particular order
+    pub fn get_listings_by_name(&self, market_name: &MarketName) -> Vec<&CsfloatListingStruct> {
+        self.listing_ids_by_name
+            .get(market_name)
+            .into_iter()
+            .flatten()
+            .filter_map(|listing_id| self.hm.get(listing_id))
+            .collect()
+    }
+
+    // Ids of the listings of the market name, sorted so the result doesn't depend on the hashing
+    pub fn get_listing_ids_by_name(&self, market_name: &MarketName) -> Vec<ListingId> {
+        let mut listing_ids: Vec<ListingId> = self
+            .listing_ids_by_name
+            .get(market_name)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        listing_ids.sort();
+        listing_ids
+    }
+
+    fn get_floor(&self, market_name: &MarketName) -> Option<PriceValue> {
+        self.aggregates
+            .get_floor_except(market_name, None)
+            .map(|(price, _)| price)
+    }
 }
 
 pub enum CsfloatEngineListingDecision {
@@ -77,6 +364,8 @@ pub trait CsfloatEngineTrait {
     fn get_size(&self) -> usize;
     fn get_listing_ids_by_update_time(&self) -> Vec<ListingId>;
     fn remove_listing(&mut self, listing_id: &ListingId);
+    // Removes listings not refreshed since `deadline` and returns their ids
+    fn expire_refreshed_before(&mut self, deadline: DateTime<Utc>) -> Vec<ListingId>;
     fn update_listing(
         &mut self,
         listing_struct: &CsfloatListingStruct,
@@ -89,127 +378,541 @@ impl CsfloatEngineTrait for CsfloatEngine {
     }
 
     fn get_listing_ids_by_update_time(&self) -> Vec<ListingId> {
-        let mut result: Vec<(&String, &Option<DateTime<Utc>>)> =
+        let mut result: Vec<(&ListingId, &Option<DateTime<Utc>>)> =
             self.listing_id_to_last_update_time.iter().collect();
         result.sort_unstable_by_key(|x| *x.1);
-        result.into_iter().map(|x| x.0.to_string()).collect()
+        result.into_iter().map(|x| x.0.clone()).collect()
     }
 
     fn update_listing(
         &mut self,
         listing_struct: &CsfloatListingStruct,
     ) -> CsfloatEngineListingDecision {
-        let listing_id = &listing_struct.id;
-        match self.hm.insert(listing_id.clone(), listing_struct.clone()) {
-            Some(old_listing) => {
-                if listing_struct.state == CsfloatListingState::Delisted
-                    || listing_struct.state == CsfloatListingState::Sold
-                    || listing_struct.state == CsfloatListingState::Refunded
-                {
-                    self.remove_listing(listing_id);
-                    return CsfloatEngineListingDecision::Removed;
-                }
-                self.listing_id_to_last_update_time
-                    .insert(listing_id.to_string(), Some(Utc::now()));
-                let is_updated = old_listing.has_any_important_changes(listing_struct);
-                match is_updated {
-                    true => CsfloatEngineListingDecision::Updated,
-                    false => CsfloatEngineListingDecision::NotChanged,
-                }
-            }
-            None => {
-                self.listing_id_to_last_update_time
-                    .insert(listing_id.to_string(), Some(Utc::now()));
-                CsfloatEngineListingDecision::New
-            }
+        let market_name = &listing_struct.item.market_hash_name;
+        let old_price = self.hm.get(&listing_struct.id).map(|x| x.get_price_value());
+        let old_floor = self.get_floor(market_name);
+        let decision = self.apply_listing(listing_struct);
+
+        let now = self.clock.now();
+        let price = listing_struct.get_price_value();
+        let is_listed = matches!(
+            decision,
+            CsfloatEngineListingDecision::New | CsfloatEngineListingDecision::Updated
+        );
+        if is_listed && old_price != Some(price) {
+            self.price_changes.push(PricePoint {
+                created_at: now,
+                market_name: market_name.clone(),
+                listing_id: Some(listing_struct.id.clone()),
+                kind: PriceKind::Listing,
+                price,
+            });
+        }
+        let floor = self.get_floor(market_name);
+        if let Some(floor) = floor.filter(|x| Some(*x) != old_floor) {
+            self.price_changes.push(PricePoint {
+                created_at: now,
+                market_name: market_name.clone(),
+                listing_id: None,
+                kind: PriceKind::Floor,
+                price: floor,
+            });
         }
+        decision
     }
 
     fn remove_listing(&mut self, listing_id: &ListingId) {
-        self.hm.remove(listing_id);
+        if let Some(listing) = self.hm.remove(listing_id) {
+            self.unindex_listing(&listing);
+        }
         self.listing_id_to_last_update_time.remove(listing_id);
+        self.aggregates.remove(listing_id);
+        self.dirty.insert(listing_id.clone());
+    }
+
+    fn expire_refreshed_before(&mut self, deadline: DateTime<Utc>) -> Vec<ListingId> {
+        let expired: Vec<ListingId> = self
+            .listing_id_to_last_update_time
+            .iter()
+            .filter(|(_, updated_at)| updated_at.is_some_and(|x| x < deadline))
+            .map(|(listing_id, _)| listing_id.clone())
+            .collect();
+        for listing_id in expired.iter() {
+            self.remove_listing(listing_id);
+            // it won't be refreshed anymore
+            self.paper_checks.remove(listing_id);
+        }
+        expired
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SteamEngine {
     pub hm: HashMap<MarketName, AnalysisResult>,
+    #[serde(default)]
+    pub order_books: HashMap<MarketName, SteamOrderBook>,
+    // market names with analysis or order book changed since the last save
+    #[serde(skip)]
+    dirty: HashSet<MarketName>,
+    #[serde(skip)]
+    is_loaded_from_blob: bool,
+    // stale analyses the steam fetcher should refresh first
+    #[serde(skip)]
+    pub refresh_requests: HashSet<MarketName>,
+    #[serde(skip)]
+    pub price_changes: PriceChanges,
+}
+
+impl Default for SteamEngine {
+    fn default() -> Self {
+        SteamEngine::new()
+    }
 }
 
 impl SteamEngine {
     pub fn new() -> Self {
-        SteamEngine { hm: HashMap::new() }
+        SteamEngine {
+            hm: HashMap::new(),
+            order_books: HashMap::new(),
+            dirty: HashSet::new(),
+            is_loaded_from_blob: false,
+            refresh_requests: HashSet::new(),
+            price_changes: PriceChanges::new(),
+        }
     }
 }
 
 pub trait SteamEngineTrait {
     fn update(&mut self, market_name: &MarketName, result: AnalysisResult);
+    fn update_order_book(&mut self, market_name: &MarketName, order_book: SteamOrderBook);
+    // Analysis exists, but it's older than `max_age` or of unknown age
+    fn is_stale(&self, market_name: &MarketName, now: DateTime<Utc>, max_age: Duration) -> bool;
+    fn request_refresh(&mut self, market_name: &MarketName);
+    // Removes analyses and order books of names not analyzed since `deadline`.
+    // Returns the number of removed names.
+    fn evict_analyzed_before(&mut self, deadline: DateTime<Utc>) -> usize;
 }
 
 impl SteamEngineTrait for SteamEngine {
     fn update(&mut self, market_name: &MarketName, result: AnalysisResult) {
-        self.hm.insert(market_name.to_string(), result);
+        let price = result.get_price_by_percentile(DESIRED_PERCENTILE);
+        let old_price = self
+            .hm
+            .get(market_name)
+            .and_then(|x| x.get_price_by_percentile(DESIRED_PERCENTILE));
+        if let Some(price) = price.filter(|x| Some(*x) != old_price) {
+            self.price_changes.push(PricePoint {
+                created_at: result.analyzed_at.unwrap_or_else(Utc::now),
+                market_name: market_name.clone(),
+                listing_id: None,
+                kind: PriceKind::Steam,
+                price,
+            });
+        }
+        self.hm.insert(market_name.clone(), result);
+        self.dirty.insert(market_name.clone());
+        self.refresh_requests.remove(market_name);
+    }
+
+    fn is_stale(&self, market_name: &MarketName, now: DateTime<Utc>, max_age: Duration) -> bool {
+        match self.hm.get(market_name) {
+            Some(analysis) => match analysis.analyzed_at {
+                Some(analyzed_at) => now - analyzed_at > max_age,
+                None => true,
+            },
+            None => false,
+        }
+    }
+
+    fn request_refresh(&mut self, market_name: &MarketName) {
+        self.refresh_requests.insert(market_name.clone());
+    }
+
+    fn evict_analyzed_before(&mut self, deadline: DateTime<Utc>) -> usize {
+        let evicted: Vec<MarketName> = self
+            .hm
+            .iter()
+            .filter(|(_, analysis)| analysis.analyzed_at.is_some_and(|x| x < deadline))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in evicted.iter() {
+            self.hm.remove(name);
+            self.order_books.remove(name);
+            self.refresh_requests.remove(name);
+            self.dirty.insert(name.clone());
+        }
+        evicted.len()
+    }
+
+    fn update_order_book(&mut self, market_name: &MarketName, order_book: SteamOrderBook) {
+        self.order_books.insert(market_name.clone(), order_book);
+        self.dirty.insert(market_name.clone());
     }
 }
 
+// legacy whole-engine dumps
 const CSFLOAT_KEY: &str = "csfloat_engine";
 const STEAM_KEY: &str = "steam_engine";
 
+impl CsfloatEngine {
+    async fn load_listings(&mut self, store: &impl StateStore) -> Result<(), StoreError> {
+        for (listing, updated_at) in store.load_csfloat_listings().await? {
+            self.aggregates.update(&listing);
+            self.index_listing(&listing);
+            self.listing_id_to_last_update_time
+                .insert(listing.id.clone(), updated_at);
+            self.hm.insert(listing.id.clone(), listing);
+        }
+        Ok(())
+    }
+
+    async fn load_sticker_prices(&mut self, store: &impl StateStore) -> Result<(), StoreError> {
+        for (name, price) in store.load_sticker_prices().await? {
+            self.sticker_prices.insert_saved(name, price);
+        }
+        Ok(())
+    }
+}
+
+pub struct CsfloatSnapshot {
+    listings: Vec<(CsfloatListingStruct, Option<DateTime<Utc>>)>,
+    removed: Vec<ListingId>,
+    sticker_prices: Vec<(String, PriceValue)>,
+    is_loaded_from_blob: bool,
+}
+
+impl DbSnapshot for CsfloatSnapshot {
+    fn get_size(&self) -> usize {
+        self.listings.len() + self.removed.len() + self.sticker_prices.len()
+    }
+
+    async fn save(&self, store: &impl StateStore) -> Result<(), StoreError> {
+        store
+            .save_csfloat(&self.listings, &self.removed, &self.sticker_prices)
+            .await?;
+
+        if self.is_loaded_from_blob {
+            store.remove_legacy(CSFLOAT_KEY).await;
+            info!("CsfloatEngine is migrated from rust_dump");
+        }
+        Ok(())
+    }
+}
+
 impl DbSerializable<CsfloatEngine> for CsfloatEngine {
-    async fn deserialize(db: &Pool<Postgres>) -> CsfloatEngine {
-        let value =
-            <CsfloatEngine as DbSerializable<CsfloatEngine>>::deserialize_load(db, CSFLOAT_KEY)
-                .await;
-        if let Some(encoded) = value {
-            let engine = match serde_json::from_str::<CsfloatEngine>(&encoded) {
-                Ok(engine) => Some(engine),
-                Err(err) => {
-                    error!("Failed to deserialize state for CsfloatEngine: {}", err);
-                    None
+    type Snapshot = CsfloatSnapshot;
+
+    async fn deserialize(store: &impl StateStore) -> CsfloatEngine {
+        let mut engine = CsfloatEngine::new();
+        if let Err(err) = engine.load_listings(store).await {
+            error!("Failed to load csfloat listings: {}", err);
+        }
+
+        if let Err(err) = engine.load_sticker_prices(store).await {
+            error!("Failed to load sticker prices: {}", err);
+        }
+
+        if !engine.hm.is_empty() {
+            return engine;
+        }
+
+        if let Some(encoded) = store.load_legacy(CSFLOAT_KEY).await {
+            match serde_json::from_str::<CsfloatEngine>(&encoded) {
+                Ok(mut legacy) => {
+                    warn!(
+                        "Migrating {} csfloat listings from rust_dump",
+                        legacy.hm.len()
+                    );
+                    legacy.dirty = legacy.hm.keys().cloned().collect();
+                    legacy.sticker_prices.mark_all_dirty();
+                    legacy.is_loaded_from_blob = true;
+                    let listings = std::mem::take(&mut legacy.hm);
+                    for listing in listings.values() {
+                        legacy.aggregates.update(listing);
+                        legacy.index_listing(listing);
+                    }
+                    legacy.hm = listings;
+                    engine = legacy;
+                }
+                Err(err) => error!("Failed to deserialize state for CsfloatEngine: {}", err),
+            }
+        }
+        engine
+    }
+
+    fn take_snapshot(&mut self) -> CsfloatSnapshot {
+        let mut listings = vec![];
+        let mut removed = vec![];
+        for listing_id in self.dirty.drain() {
+            match self.hm.get(&listing_id) {
+                Some(listing) => {
+                    let update_time = self
+                        .listing_id_to_last_update_time
+                        .get(&listing_id)
+                        .cloned()
+                        .flatten();
+                    listings.push((listing.clone(), update_time));
                 }
-            };
-            return match engine {
-                Some(engine) => engine,
-                None => CsfloatEngine::new(),
-            };
+                None => removed.push(listing_id),
+            }
+        }
+
+        let is_loaded_from_blob = self.is_loaded_from_blob;
+        self.is_loaded_from_blob = false;
+        CsfloatSnapshot {
+            listings,
+            removed,
+            sticker_prices: self.sticker_prices.take_dirty(),
+            is_loaded_from_blob,
         }
-        CsfloatEngine::new()
     }
 
-    async fn serialize(&self, db: &Pool<Postgres>) {
-        let serialized = serde_json::to_string(self).unwrap();
-        <CsfloatEngine as DbSerializable<CsfloatEngine>>::serialize_to_db(
-            db,
-            CSFLOAT_KEY,
-            serialized,
-        )
-        .await;
+    fn restore_snapshot(&mut self, snapshot: &CsfloatSnapshot) {
+        self.dirty
+            .extend(snapshot.listings.iter().map(|(x, _)| x.id.clone()));
+        self.dirty.extend(snapshot.removed.iter().cloned());
+        for (name, _) in snapshot.sticker_prices.iter() {
+            self.sticker_prices.mark_dirty(name);
+        }
+        self.is_loaded_from_blob |= snapshot.is_loaded_from_blob;
+    }
+}
+
+impl SteamEngine {
+    async fn load_analysis(&mut self, store: &impl StateStore) -> Result<(), StoreError> {
+        for (market_name, entry) in store.load_steam_entries().await? {
+            if let Some(mut analysis) = entry.analysis {
+                // saved before analyzed_at was tracked, it's not older than the row
+                analysis.analyzed_at.get_or_insert(entry.updated_at);
+                self.hm.insert(market_name.clone(), analysis);
+            }
+            if let Some(order_book) = entry.order_book {
+                self.order_books.insert(market_name, order_book);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct SteamSnapshot {
+    entries: Vec<(MarketName, Option<AnalysisResult>, Option<SteamOrderBook>)>,
+    removed: Vec<MarketName>,
+    is_loaded_from_blob: bool,
+}
+
+impl DbSnapshot for SteamSnapshot {
+    fn get_size(&self) -> usize {
+        self.entries.len() + self.removed.len()
+    }
+
+    async fn save(&self, store: &impl StateStore) -> Result<(), StoreError> {
+        store.save_steam(&self.entries, &self.removed).await?;
+
+        if self.is_loaded_from_blob {
+            store.remove_legacy(STEAM_KEY).await;
+            info!("SteamEngine is migrated from rust_dump");
+        }
+        Ok(())
     }
 }
 
 impl DbSerializable<SteamEngine> for SteamEngine {
-    async fn deserialize(db: &Pool<Postgres>) -> SteamEngine {
-        let value =
-            <SteamEngine as DbSerializable<SteamEngine>>::deserialize_load(db, STEAM_KEY).await;
-        if let Some(encoded) = value {
-            let engine = match serde_json::from_str::<SteamEngine>(&encoded) {
-                Ok(engine) => Some(engine),
-                Err(err) => {
-                    error!("Failed to deserialize state for SteamEngine: {}", err);
-                    None
+    type Snapshot = SteamSnapshot;
+
+    async fn deserialize(store: &impl StateStore) -> SteamEngine {
+        let mut engine = SteamEngine::new();
+        if let Err(err) = engine.load_analysis(store).await {
+            error!("Failed to load steam analysis: {}", err);
+        }
+        if !engine.hm.is_empty() {
+            return engine;
+        }
+
+        if let Some(encoded) = store.load_legacy(STEAM_KEY).await {
+            match serde_json::from_str::<SteamEngine>(&encoded) {
+                Ok(mut legacy) => {
+                    warn!(
+                        "Migrating {} steam analysis from rust_dump",
+                        legacy.hm.len()
+                    );
+                    legacy.dirty = legacy
+                        .hm
+                        .keys()
+                        .chain(legacy.order_books.keys())
+                        .cloned()
+                        .collect();
+                    legacy.is_loaded_from_blob = true;
+                    engine = legacy;
                 }
-            };
-            return match engine {
-                Some(engine) => engine,
-                None => SteamEngine::new(),
-            };
+                Err(err) => error!("Failed to deserialize state for SteamEngine: {}", err),
+            }
         }
-        SteamEngine::new()
+        engine
     }
 
-    async fn serialize(&self, db: &Pool<Postgres>) {
-        let serialized = serde_json::to_string(self).unwrap();
-        <SteamEngine as DbSerializable<SteamEngine>>::serialize_to_db(db, STEAM_KEY, serialized)
-            .await;
+    fn take_snapshot(&mut self) -> SteamSnapshot {
+        let mut entries = vec![];
+        let mut removed = vec![];
+        for name in self.dirty.drain() {
+            let analysis = self.hm.get(&name).cloned();
+            let order_book = self.order_books.get(&name).cloned();
+            match (analysis, order_book) {
+                (None, None) => removed.push(name),
+                (analysis, order_book) => entries.push((name, analysis, order_book)),
+            }
+        }
+
+        let is_loaded_from_blob = self.is_loaded_from_blob;
+        self.is_loaded_from_blob = false;
+        SteamSnapshot {
+            entries,
+            removed,
+            is_loaded_from_blob,
+        }
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &SteamSnapshot) {
+        self.dirty
+            .extend(snapshot.entries.iter().map(|(name, _, _)| name.clone()));
+        self.dirty.extend(snapshot.removed.iter().cloned());
+        self.is_loaded_from_blob |= snapshot.is_loaded_from_blob;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, MockClock},
+        tests::fixtures::{get_listing, get_listing_json},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_expire_refreshed_before() {
+        let now = Utc::now();
+        let mut csfloat_engine = CsfloatEngine::new();
+        for (listing_id, updated_at) in [
+            ("old", Some(now - Duration::hours(30))),
+            ("fresh", Some(now - Duration::hours(1))),
+            ("unknown", None),
+        ] {
+            csfloat_engine
+                .listing_id_to_last_update_time
+                .insert(listing_id.into(), updated_at);
+        }
+        csfloat_engine.paper_checks.insert("old".into(), now);
+
+        let expired = csfloat_engine.expire_refreshed_before(now - Duration::hours(24));
+        assert_eq!(expired, vec![ListingId::from("old")]);
+        assert!(csfloat_engine.paper_checks.is_empty());
+        assert_eq!(csfloat_engine.listing_id_to_last_update_time.len(), 2);
+        assert!(csfloat_engine.dirty.contains("old"));
+    }
+
+    #[test]
+    fn test_update_time_follows_clock() {
+        let listing = |id: &str| get_listing(id, "AK-47 | Redline (Field-Tested)", 10_00);
+        let start = Utc::now();
+        let clock = Arc::new(MockClock::new(start));
+        let mut csfloat_engine = CsfloatEngine::with_clock(clock.clone());
+        csfloat_engine.update_listing(&listing("old"));
+        clock.advance(Duration::hours(30));
+        csfloat_engine.update_listing(&listing("fresh"));
+        assert_eq!(
+            csfloat_engine.listing_id_to_last_update_time[&ListingId::from("fresh")],
+            Some(start + Duration::hours(30))
+        );
+        assert_eq!(csfloat_engine.price_changes.take()[0].created_at, start);
+
+        let expired = csfloat_engine.expire_refreshed_before(clock.now() - Duration::hours(24));
+        assert_eq!(expired, vec![ListingId::from("old")]);
+    }
+
+    #[test]
+    fn test_paper_checks_expire() {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let mut csfloat_engine = CsfloatEngine::with_clock(clock.clone());
+        csfloat_engine.add_paper_check(&"other shard".into());
+        clock.advance(Duration::minutes(30));
+        csfloat_engine.add_paper_check(&"1".into());
+        assert_eq!(csfloat_engine.paper_checks.len(), 2);
+
+        clock.advance(Duration::minutes(31));
+        csfloat_engine.add_paper_check(&"2".into());
+        let mut listing_ids: Vec<&ListingId> = csfloat_engine.paper_checks.keys().collect();
+        listing_ids.sort();
+        assert_eq!(listing_ids, vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_price_changes() {
+        let listing = |id: &str, price: PriceValue, state: &str| -> CsfloatListingStruct {
+            let mut listing = get_listing_json(id, "AK-47 | Redline (Field-Tested)", price);
+            listing["state"] = state.into();
+            serde_json::from_value(listing).unwrap()
+        };
+        let mut csfloat_engine = CsfloatEngine::new();
+        csfloat_engine.update_listing(&listing("1", 12_00, "listed"));
+        csfloat_engine.update_listing(&listing("2", 15_00, "listed"));
+        // the same price isn't a change
+        csfloat_engine.update_listing(&listing("2", 15_00, "listed"));
+        csfloat_engine.update_listing(&listing("1", 13_00, "listed"));
+        csfloat_engine.update_listing(&listing("1", 13_00, "sold"));
+
+        let changes: Vec<(Option<String>, PriceKind, PriceValue)> = csfloat_engine
+            .price_changes
+            .take()
+            .into_iter()
+            .map(|x| (x.listing_id.map(|id| id.to_string()), x.kind, x.price))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (Some("1".to_string()), PriceKind::Listing, 12_00),
+                (None, PriceKind::Floor, 12_00),
+                (Some("2".to_string()), PriceKind::Listing, 15_00),
+                (Some("1".to_string()), PriceKind::Listing, 13_00),
+                (None, PriceKind::Floor, 13_00),
+                (None, PriceKind::Floor, 15_00),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listing_ids_by_name() {
+        let listing = |id: &str, market_name: &str, state: &str| -> CsfloatListingStruct {
+            let mut listing = get_listing_json(id, market_name, 10_00);
+            listing["state"] = state.into();
+            serde_json::from_value(listing).unwrap()
+        };
+        let redline = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let case = MarketName::from("Recoil Case");
+        let mut csfloat_engine = CsfloatEngine::new();
+        csfloat_engine.update_listing(&listing("2", &redline, "listed"));
+        csfloat_engine.update_listing(&listing("1", &redline, "listed"));
+        csfloat_engine.update_listing(&listing("3", &case, "listed"));
+        assert_eq!(
+            csfloat_engine.get_listing_ids_by_name(&redline),
+            vec![ListingId::from("1"), ListingId::from("2")]
+        );
+        assert_eq!(csfloat_engine.get_listings_by_name(&case).len(), 1);
+
+        csfloat_engine.update_listing(&listing("1", &redline, "sold"));
+        csfloat_engine.remove_listing(&"3".into());
+        assert_eq!(
+            csfloat_engine.get_listing_ids_by_name(&redline),
+            vec![ListingId::from("2")]
+        );
+        assert!(csfloat_engine.get_listing_ids_by_name(&case).is_empty());
+        assert!(!csfloat_engine.listing_ids_by_name.contains_key(&case));
+
+        // a renamed listing moves to its new name
+        csfloat_engine.update_listing(&listing("2", &case, "listed"));
+        assert!(csfloat_engine.get_listing_ids_by_name(&redline).is_empty());
+        assert_eq!(
+            csfloat_engine.get_listing_ids_by_name(&case),
+            vec![ListingId::from("2")]
+        );
     }
 }
@@ -0,0 +1,899 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    business_logic::{
+        apply_trade_hold_decay, estimate_applied_value, estimate_fallback_sell_price,
+        estimate_raw_stickers_value, estimate_steam_sell_price, find_phase_deal,
+        find_rare_pattern_deal, get_buy_cost,
+    },
+    config::{AppConfig, CommodityStrategyConfig},
+    events::{AppliedValue, PriceSource, ProfitableListingEvent, ProfitableListingKind, Venue},
+    fee::SteamFee,
+    market_aggregates::MarketAggregates,
+    models::{CsfloatItemKind, CsfloatListingStruct},
+    prices::PriceValue,
+    pricing::apply_float_premium,
+    steam_analyzer::{AnalysisResult, Trend},
+    stickers::StickerPriceTable,
+    storages::SteamEngine,
+    types::{ListingId, MarketName},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StrategyName {
+    SteamArb,
+    Phase,
+    RarePattern,
+    LowFloat,
+    Sticker,
+    Commodity,
+}
+
+impl StrategyName {
+    pub const ALL: [StrategyName; 6] = [
+        StrategyName::SteamArb,
+        StrategyName::Phase,
+        StrategyName::RarePattern,
+        StrategyName::LowFloat,
+        StrategyName::Sticker,
+        StrategyName::Commodity,
+    ];
+
+    // Same as the `strategies` config section, also stored in the ledger
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StrategyName::SteamArb => "steam_arb",
+            StrategyName::Phase => "phase",
+            StrategyName::RarePattern => "rare_pattern",
+            StrategyName::LowFloat => "low_float",
+            StrategyName::Sticker => "sticker",
+            StrategyName::Commodity => "commodity",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<StrategyName> {
+        StrategyName::ALL.into_iter().find(|x| x.as_str() == value)
+    }
+}
+
+impl fmt::Display for StrategyName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// A deal found by a strategy
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub strategy: StrategyName,
+    pub event: ProfitableListingEvent,
+}
+
+// Engines a strategy may look at besides the listing itself
+pub struct StrategyContext<'a> {
+    pub steam_engine: &'a SteamEngine,
+    pub sticker_prices: &'a StickerPriceTable,
+    pub aggregates: &'a MarketAggregates,
+    // the time the listings are evaluated at, the replayed time in the backtest
+    pub now: DateTime<Utc>,
+    pub config: &'a AppConfig,
+}
+
+// A source of deals among CSFloat listings, enabled and weighted by `strategies` config.
+// New signals are added by implementing it and registering in `STRATEGIES`.
+pub trait Strategy: Send + Sync {
+    fn name(&self) -> StrategyName;
+
+    // Such strategies aren't evaluated while the Steam analysis is stale
+    fn needs_steam_price(&self) -> bool {
+        true
+    }
+
+    // Kinds of items the strategy looks at, see `CsfloatListingItem::get_kind`, all when empty
+    fn kinds(&self) -> &'static [CsfloatItemKind] {
+        &[]
+    }
+
+    // `analysis` is the Steam analysis of the listing's market name
+    fn evaluate(
+        &self,
+        listing: &CsfloatListingStruct,
+        analysis: Option<&AnalysisResult>,
+        ctx: &StrategyContext,
+    ) -> Vec<Signal>;
+}
+
+lazy_static! {
+    static ref STRATEGIES: Vec<Box<dyn Strategy>> = vec![
+        Box::new(SteamArbStrategy),
+        Box::new(PhaseStrategy),
+        Box::new(RarePatternStrategy),
+        Box::new(LowFloatStrategy),
+        Box::new(StickerStrategy),
+        Box::new(CommodityStrategy),
+    ];
+}
+
+// Signals of all enabled strategies of the item kind, the highest `weight` first.
+// Steam priced strategies are skipped when `is_stale_analysis`.
+pub fn evaluate_strategies(
+    listing: &CsfloatListingStruct,
+    ctx: &StrategyContext,
+    is_stale_analysis: bool,
+) -> Vec<Signal> {
+    let analysis = ctx.steam_engine.hm.get(&listing.item.market_hash_name);
+    let kind = listing.item.get_kind();
+    let mut signals: Vec<(f64, Signal)> = vec![];
+    for strategy in STRATEGIES.iter() {
+        let toggle = ctx.config.strategies.get_toggle(strategy.name());
+        if !toggle.enabled || (is_stale_analysis && strategy.needs_steam_price()) {
+            continue;
+        }
+        if !strategy.kinds().is_empty() && !strategy.kinds().contains(&kind) {
+            continue;
+        }
+        signals.extend(
+            strategy
+                .evaluate(listing, analysis, ctx)
+                .into_iter()
+                .map(|mut signal| {
+                    signal.event.strategy = Some(signal.strategy);
+                    (toggle.weight, signal)
+                }),
+        );
+    }
+    // stable, so equal weights keep the registration order
+    signals.sort_by(|a, b| b.0.total_cmp(&a.0));
+    signals.into_iter().map(|(_, signal)| signal).collect()
+}
+
+// Steam sell price with all premiums applied
+pub fn estimate_steam_price(
+    steam_engine: &SteamEngine,
+    market_name: &MarketName,
+    float: Option<f64>,
+    predicted_price: Option<PriceValue>,
+    applied_value: AppliedValue,
+    trade_hold_days: u32,
+    config: &AppConfig,
+) -> Option<(PriceValue, PriceSource)> {
+    let (steam_price, price_source) =
+        match estimate_steam_sell_price(market_name, steam_engine, config) {
+            Some(steam_price) => (steam_price, PriceSource::Steam),
+            None => {
+                estimate_fallback_sell_price(market_name, predicted_price, steam_engine, config)?
+            }
+        };
+    let steam_price = apply_float_premium(steam_price, market_name, float, &config.pricing);
+    let steam_price =
+        apply_trade_hold_decay(steam_price + applied_value.total(), trade_hold_days, config);
+    // a long trade hold can leave less than the Steam fee
+    if steam_price < SteamFee::get_min_total() {
+        return None;
+    }
+    Some((steam_price, price_source))
+}
+
+// Compares a buy price from any venue with the Steam sell price (minus fee)
+#[allow(clippy::too_many_arguments)]
+pub fn build_profitable_listing_event(
+    steam_engine: &SteamEngine,
+    venue: Venue,
+    market_name: &MarketName,
+    listing_id: &ListingId,
+    price: PriceValue,
+    float: Option<f64>,
+    predicted_price: Option<PriceValue>,
+    applied_value: AppliedValue,
+    trade_hold_days: u32,
+    seller_id: Option<String>,
+    floor_undercut_pct: Option<f64>,
+    config: &AppConfig,
+) -> Option<ProfitableListingEvent> {
+    let (steam_price, price_source) = estimate_steam_price(
+        steam_engine,
+        market_name,
+        float,
+        predicted_price,
+        applied_value,
+        trade_hold_days,
+        config,
+    )?;
+    let steam_no_fee = SteamFee::subtract_fee(steam_price);
+    let cost = get_buy_cost(venue, price, config);
+    if cost >= steam_no_fee {
+        return None;
+    }
+
+    let profit_pct = ((steam_no_fee as f64 / cost as f64) - 1.0) * 100.0;
+    let steam_analysis = steam_engine.hm.get(market_name);
+    Some(ProfitableListingEvent {
+        kind: ProfitableListingKind::Profitable,
+        venue,
+        market_name: market_name.clone(),
+        listing_id: listing_id.clone(),
+        csfloat_price: price,
+        steam_price,
+        steam_no_fee,
+        price_source,
+        predicted_price,
+        applied_value,
+        sold_per_week: steam_analysis.and_then(|x| x.sold_per_week).unwrap_or(0) as u64,
+        is_stable: steam_analysis.and_then(|x| x.is_stable).unwrap_or(false),
+        trend: steam_analysis.map(|x| x.trend).unwrap_or_default(),
+        expected_days_to_sell: steam_analysis.and_then(|x| x.expected_days_to_sell),
+        profit_pct,
+        float,
+        trade_hold_days,
+        seller_id,
+        strategy: None,
+        floor_undercut_pct,
+        batch: vec![],
+    })
+}
+
+fn get_floor_undercut_pct(listing: &CsfloatListingStruct, ctx: &StrategyContext) -> Option<f64> {
+    ctx.aggregates.get_floor_undercut_pct(
+        &listing.item.market_hash_name,
+        &listing.id,
+        listing.get_price_value(),
+    )
+}
+
+// Event of a listing priced by the regular Steam price, `profit_pct` is negative
+// when it's sold with a premium
+fn build_regular_price_event(
+    listing: &CsfloatListingStruct,
+    kind: ProfitableListingKind,
+    analysis: Option<&AnalysisResult>,
+    steam_price: PriceValue,
+    applied_value: AppliedValue,
+    ctx: &StrategyContext,
+) -> ProfitableListingEvent {
+    let csfloat_price = listing.get_price_value();
+    let cost = get_buy_cost(Venue::Csfloat, csfloat_price, ctx.config);
+    let steam_no_fee = SteamFee::subtract_fee(steam_price);
+    ProfitableListingEvent {
+        kind,
+        venue: Venue::Csfloat,
+        market_name: listing.item.market_hash_name.clone(),
+        listing_id: listing.id.clone(),
+        csfloat_price,
+        steam_price,
+        steam_no_fee,
+        price_source: PriceSource::Steam,
+        predicted_price: listing.get_predicted_price(),
+        applied_value,
+        sold_per_week: analysis.and_then(|x| x.sold_per_week).unwrap_or(0) as u64,
+        is_stable: analysis.and_then(|x| x.is_stable).unwrap_or(false),
+        trend: analysis.map(|x| x.trend).unwrap_or_default(),
+        expected_days_to_sell: analysis.and_then(|x| x.expected_days_to_sell),
+        profit_pct: ((steam_no_fee as f64 / cost.max(1) as f64) - 1.0) * 100.0,
+        float: listing.item.float_value,
+        trade_hold_days: listing.item.get_days_until_tradable(ctx.now),
+        seller_id: listing.get_seller_id(),
+        strategy: None,
+        floor_undercut_pct: get_floor_undercut_pct(listing, ctx),
+        batch: vec![],
+    }
+}
+
+// Buy now listings below the Steam price minus fee, with all premiums applied
+pub struct SteamArbStrategy;
+
+impl Strategy for SteamArbStrategy {
+    fn name(&self) -> StrategyName {
+        StrategyName::SteamArb
+    }
+
+    fn evaluate(
+        &self,
+        listing: &CsfloatListingStruct,
+        _analysis: Option<&AnalysisResult>,
+        ctx: &StrategyContext,
+    ) -> Vec<Signal> {
+        // auctions can't be bought right away
+        if listing.is_auction() {
+            return vec![];
+        }
+        // containers are priced by their buy orders then
+        let is_commodity = ctx.config.strategies.commodity.enabled
+            && CommodityStrategy.kinds().contains(&listing.item.get_kind());
+        if is_commodity {
+            return vec![];
+        }
+        let event = build_profitable_listing_event(
+            ctx.steam_engine,
+            Venue::Csfloat,
+            &listing.item.market_hash_name,
+            &listing.id,
+            listing.get_price_value(),
+            listing.item.float_value,
+            listing.get_predicted_price(),
+            estimate_applied_value(&listing.item, ctx.sticker_prices, ctx.config),
+            listing.item.get_days_until_tradable(ctx.now),
+            listing.get_seller_id(),
+            get_floor_undercut_pct(listing, ctx),
+            ctx.config,
+        );
+        event
+            .into_iter()
+            .map(|event| Signal {
+                strategy: self.name(),
+                event,
+            })
+            .collect()
+    }
+}
+
+// `phases.prices`, priced by the table, not by Steam
+pub struct PhaseStrategy;
+
+impl Strategy for PhaseStrategy {
+    fn name(&self) -> StrategyName {
+        StrategyName::Phase
+    }
+
+    fn kinds(&self) -> &'static [CsfloatItemKind] {
+        &[CsfloatItemKind::Skin]
+    }
+
+    fn needs_steam_price(&self) -> bool {
+        false
+    }
+
+    fn evaluate(
+        &self,
+        listing: &CsfloatListingStruct,
+        _analysis: Option<&AnalysisResult>,
+        ctx: &StrategyContext,
+    ) -> Vec<Signal> {
+        let Some(phase_price) = find_phase_deal(listing, ctx.config) else {
+            return vec![];
+        };
+        let csfloat_price = listing.get_price_value();
+        let steam_price = phase_price.target_sell_price.unwrap_or(0);
+        let steam_no_fee = phase_price
+            .target_sell_price
+            .map(SteamFee::subtract_fee)
+            .unwrap_or(0);
+        let cost = get_buy_cost(Venue::Csfloat, csfloat_price, ctx.config);
+        let profit_pct = match steam_no_fee > cost {
+            true => ((steam_no_fee as f64 / cost as f64) - 1.0) * 100.0,
+            false => 0.0,
+        };
+
+        vec![Signal {
+            strategy: self.name(),
+            event: ProfitableListingEvent {
+                kind: ProfitableListingKind::Phase(phase_price.clone()),
+                venue: Venue::Csfloat,
+                market_name: listing.item.market_hash_name.clone(),
+                listing_id: listing.id.clone(),
+                csfloat_price,
+                steam_price,
+                steam_no_fee,
+                price_source: PriceSource::Steam,
+                predicted_price: listing.get_predicted_price(),
+                applied_value: AppliedValue::default(),
+                sold_per_week: 0,
+                is_stable: false,
+                trend: Trend::Flat,
+                expected_days_to_sell: None,
+                profit_pct,
+                float: listing.item.float_value,
+                trade_hold_days: listing.item.get_days_until_tradable(ctx.now),
+                seller_id: listing.get_seller_id(),
+                strategy: None,
+                floor_undercut_pct: get_floor_undercut_pct(listing, ctx),
+                batch: vec![],
+            },
+        }]
+    }
+}
+
+// `patterns.tiers`, rare patterns priced below the tier premium over the regular item
+pub struct RarePatternStrategy;
+
+impl Strategy for RarePatternStrategy {
+    fn name(&self) -> StrategyName {
+        StrategyName::RarePattern
+    }
+
+    fn kinds(&self) -> &'static [CsfloatItemKind] {
+        &[CsfloatItemKind::Skin]
+    }
+
+    fn evaluate(
+        &self,
+        listing: &CsfloatListingStruct,
+        analysis: Option<&AnalysisResult>,
+        ctx: &StrategyContext,
+    ) -> Vec<Signal> {
+        let Some(pattern_tier) = find_rare_pattern_deal(listing, ctx.steam_engine, ctx.config)
+        else {
+            return vec![];
+        };
+        if analysis.is_none() {
+            return vec![];
+        }
+        let market_name = &listing.item.market_hash_name;
+        let Some(steam_price) =
+            estimate_steam_sell_price(market_name, ctx.steam_engine, ctx.config)
+        else {
+            return vec![];
+        };
+        let kind = ProfitableListingKind::RarePattern(pattern_tier.clone());
+        vec![Signal {
+            strategy: self.name(),
+            event: build_regular_price_event(
+                listing,
+                kind,
+                analysis,
+                steam_price,
+                AppliedValue::default(),
+                ctx,
+            ),
+        }]
+    }
+}
+
+// See `strategies.low_float`
+pub struct LowFloatStrategy;
+
+impl Strategy for LowFloatStrategy {
+    fn name(&self) -> StrategyName {
+        StrategyName::LowFloat
+    }
+
+    fn kinds(&self) -> &'static [CsfloatItemKind] {
+        &[CsfloatItemKind::Skin]
+    }
+
+    fn evaluate(
+        &self,
+        listing: &CsfloatListingStruct,
+        analysis: Option<&AnalysisResult>,
+        ctx: &StrategyContext,
+    ) -> Vec<Signal> {
+        let config = &ctx.config.strategies.low_float;
+        let is_low_float = listing
+            .item
+            .float_value
+            .is_some_and(|x| x <= config.max_float);
+        if !is_low_float || listing.is_auction() || analysis.is_none() {
+            return vec![];
+        }
+        let market_name = &listing.item.market_hash_name;
+        let Some(steam_price) =
+            estimate_steam_sell_price(market_name, ctx.steam_engine, ctx.config)
+        else {
+            return vec![];
+        };
+        let event = build_regular_price_event(
+            listing,
+            ProfitableListingKind::LowFloat,
+            analysis,
+            steam_price,
+            AppliedValue::default(),
+            ctx,
+        );
+        if event.profit_pct < -config.max_premium_pct {
+            return vec![];
+        }
+        vec![Signal {
+            strategy: self.name(),
+            event,
+        }]
+    }
+}
+
+// See `strategies.sticker`
+pub struct StickerStrategy;
+
+impl Strategy for StickerStrategy {
+    fn name(&self) -> StrategyName {
+        StrategyName::Sticker
+    }
+
+    fn kinds(&self) -> &'static [CsfloatItemKind] {
+        &[CsfloatItemKind::Skin]
+    }
+
+    fn evaluate(
+        &self,
+        listing: &CsfloatListingStruct,
+        analysis: Option<&AnalysisResult>,
+        ctx: &StrategyContext,
+    ) -> Vec<Signal> {
+        let config = &ctx.config.strategies.sticker;
+        if listing.is_auction() || analysis.is_none() {
+            return vec![];
+        }
+        let stickers_value =
+            estimate_raw_stickers_value(&listing.item, ctx.sticker_prices, ctx.config);
+        if stickers_value < config.min_stickers_value.max(1) {
+            return vec![];
+        }
+        let market_name = &listing.item.market_hash_name;
+        let Some(steam_price) =
+            estimate_steam_sell_price(market_name, ctx.steam_engine, ctx.config)
+        else {
+            return vec![];
+        };
+        let premium = listing
+            .get_price_value()
+            .saturating_sub(SteamFee::subtract_fee(steam_price));
+        if premium as f64 > stickers_value as f64 * config.max_premium_pct / 100.0 {
+            return vec![];
+        }
+        let applied_value = AppliedValue {
+            stickers: stickers_value,
+            ..AppliedValue::default()
+        };
+        vec![Signal {
+            strategy: self.name(),
+            event: build_regular_price_event(
+                listing,
+                ProfitableListingKind::Sticker,
+                analysis,
+                steam_price,
+                applied_value,
+                ctx,
+            ),
+        }]
+    }
+}
+
+// Highest Steam buy order, None when the order book is older than `max_order_book_age_secs`
+fn get_buy_order_price(
+    steam_engine: &SteamEngine,
+    market_name: &MarketName,
+    config: &CommodityStrategyConfig,
+    now: DateTime<Utc>,
+) -> Option<PriceValue> {
+    let order_book = steam_engine.order_books.get(market_name)?;
+    if now - order_book.updated_at > config.max_order_book_age() {
+        return None;
+    }
+    order_book.highest_buy_order
+}
+
+// See `strategies.commodity`
+pub struct CommodityStrategy;
+
+impl Strategy for CommodityStrategy {
+    fn name(&self) -> StrategyName {
+        StrategyName::Commodity
+    }
+
+    fn kinds(&self) -> &'static [CsfloatItemKind] {
+        &[CsfloatItemKind::Container]
+    }
+
+    fn evaluate(
+        &self,
+        listing: &CsfloatListingStruct,
+        analysis: Option<&AnalysisResult>,
+        ctx: &StrategyContext,
+    ) -> Vec<Signal> {
+        let config = &ctx.config.strategies.commodity;
+        let is_liquid = analysis
+            .and_then(|x| x.sold_per_week)
+            .is_some_and(|x| x as u64 >= config.min_sold_per_week);
+        if listing.is_auction() || !is_liquid {
+            return vec![];
+        }
+        let market_name = &listing.item.market_hash_name;
+        let Some(buy_order) = get_buy_order_price(ctx.steam_engine, market_name, config, ctx.now)
+        else {
+            return vec![];
+        };
+        let mut event = build_regular_price_event(
+            listing,
+            ProfitableListingKind::Profitable,
+            analysis,
+            buy_order,
+            AppliedValue::default(),
+            ctx,
+        );
+        if event.profit_pct <= 0.0 {
+            return vec![];
+        }
+        event.batch = ctx
+            .aggregates
+            .iter_listings(market_name)
+            .filter(|(price, id)| *id != &listing.id && *price <= event.csfloat_price)
+            .take(config.batch_size.saturating_sub(1))
+            .map(|(price, id)| (id.clone(), price))
+            .collect();
+        vec![Signal {
+            strategy: self.name(),
+            event,
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        steam_analyzer::Smoothing, steam_orders::SteamOrderBook, storages::SteamEngineTrait,
+        tests::fixtures::get_listing_json,
+    };
+
+    fn get_steam_engine(
+        market_name: &MarketName,
+        analyzed_at: chrono::DateTime<Utc>,
+    ) -> SteamEngine {
+        let mut steam_engine = SteamEngine::new();
+        steam_engine.update(
+            market_name,
+            AnalysisResult {
+                rsd: Some(0.01),
+                is_stable: Some(true),
+                sold_per_week: Some(500),
+                percentiles: vec![(60, 13_00)],
+                percentiles_no_fee: vec![(60, 11_30)],
+                weighted_percentiles: vec![],
+                rejected_outliers: 0,
+                trend: Trend::Flat,
+                analyzed_at: Some(analyzed_at),
+                sell_listings: None,
+                expected_days_to_sell: None,
+                smoothing: Smoothing::Sma,
+                seasonality: None,
+            },
+        );
+        steam_engine
+    }
+
+    fn get_listing(
+        price: PriceValue,
+        float: f64,
+        sticker_price: PriceValue,
+    ) -> CsfloatListingStruct {
+        let mut listing = get_listing_json("1", "AK-47 | Redline (Field-Tested)", price);
+        listing["item"]["float_value"] = float.into();
+        listing["item"]["stickers"] = serde_json::json!([
+            {"name": "Sticker | Crown (Foil)", "reference": {"price": sticker_price}}
+        ]);
+        serde_json::from_value(listing).unwrap()
+    }
+
+    fn get_strategies(signals: &[Signal]) -> Vec<StrategyName> {
+        signals.iter().map(|x| x.strategy).collect()
+    }
+
+    #[test]
+    fn test_trade_hold_is_counted_from_context_time() {
+        let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let now = Utc::now();
+        let steam_engine = get_steam_engine(&market_name, now);
+        let sticker_prices = StickerPriceTable::new();
+        let aggregates = MarketAggregates::new();
+        let mut listing = get_listing(10_00, 0.005, 0);
+        listing.item.tradable_after = Some(now + chrono::Duration::days(3));
+        let config = AppConfig::default();
+        let evaluate = |now: DateTime<Utc>| {
+            let ctx = StrategyContext {
+                steam_engine: &steam_engine,
+                sticker_prices: &sticker_prices,
+                aggregates: &aggregates,
+                now,
+                config: &config,
+            };
+            evaluate_strategies(&listing, &ctx, false)
+        };
+
+        assert_eq!(evaluate(now)[0].event.trade_hold_days, 3);
+        // a replayed listing is evaluated at the replayed time, not the wall clock
+        let signals = evaluate(now + chrono::Duration::days(2));
+        assert_eq!(signals[0].event.trade_hold_days, 1);
+    }
+
+    #[test]
+    fn test_strategies_are_weighted() {
+        let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let steam_engine = get_steam_engine(&market_name, Utc::now());
+        let sticker_prices = StickerPriceTable::new();
+        let aggregates = MarketAggregates::new();
+        let listing = get_listing(10_00, 0.005, 0);
+        let evaluate = |config: &AppConfig, is_stale: bool| {
+            let ctx = StrategyContext {
+                steam_engine: &steam_engine,
+                sticker_prices: &sticker_prices,
+                aggregates: &aggregates,
+                now: Utc::now(),
+                config,
+            };
+            evaluate_strategies(&listing, &ctx, is_stale)
+        };
+        let mut config = AppConfig::default();
+        config.strategy.desired_percentile = 60;
+        config.strategies.low_float.enabled = true;
+
+        let signals = evaluate(&config, false);
+        assert_eq!(
+            get_strategies(&signals),
+            vec![StrategyName::SteamArb, StrategyName::LowFloat]
+        );
+        assert_eq!(signals[1].event.kind, ProfitableListingKind::LowFloat);
+        assert_eq!(signals[1].event.strategy, Some(StrategyName::LowFloat));
+        // Steam priced strategies wait for a fresh analysis
+        assert!(evaluate(&config, true).is_empty());
+
+        config.strategies.low_float.weight = 2.0;
+        assert_eq!(
+            get_strategies(&evaluate(&config, false)),
+            vec![StrategyName::LowFloat, StrategyName::SteamArb]
+        );
+
+        config.strategies.steam_arb.enabled = false;
+        assert_eq!(
+            get_strategies(&evaluate(&config, false)),
+            vec![StrategyName::LowFloat]
+        );
+    }
+
+    #[test]
+    fn test_unpriceable_fallback_is_skipped() {
+        let market_name = MarketName::from("Sticker | Unknown");
+        let steam_engine = SteamEngine::new();
+        let config = AppConfig::default();
+        // a predicted price of a few cents is below the Steam fee after the haircut
+        for predicted_price in [0, 3] {
+            let event = build_profitable_listing_event(
+                &steam_engine,
+                Venue::Csfloat,
+                &market_name,
+                &"1".into(),
+                1,
+                None,
+                Some(predicted_price),
+                AppliedValue::default(),
+                0,
+                None,
+                None,
+                &config,
+            );
+            assert!(event.is_none());
+        }
+    }
+
+    #[test]
+    fn test_decayed_price_below_fee_is_skipped() {
+        let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let steam_engine = get_steam_engine(&market_name, Utc::now());
+        let mut config = AppConfig::default();
+        config.strategy.desired_percentile = 60;
+        config.trade_hold.decay_per_day_pct = 90.0;
+        let estimate = |trade_hold_days| {
+            estimate_steam_price(
+                &steam_engine,
+                &market_name,
+                None,
+                None,
+                AppliedValue::default(),
+                trade_hold_days,
+                &config,
+            )
+        };
+        // 13_00 loses 90% a day, truncated
+        assert_eq!(estimate(1), Some((1_29, PriceSource::Steam)));
+        assert_eq!(estimate(7), None);
+    }
+
+    #[test]
+    fn test_low_float_and_sticker_strategies() {
+        let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let steam_engine = get_steam_engine(&market_name, Utc::now());
+        let sticker_prices = StickerPriceTable::new();
+        let aggregates = MarketAggregates::new();
+        let mut config = AppConfig::default();
+        config.strategy.desired_percentile = 60;
+        config.strategies.steam_arb.enabled = false;
+        config.strategies.low_float.enabled = true;
+        config.strategies.sticker.enabled = true;
+        let ctx = StrategyContext {
+            steam_engine: &steam_engine,
+            sticker_prices: &sticker_prices,
+            aggregates: &aggregates,
+            now: Utc::now(),
+            config: &config,
+        };
+        let steam_no_fee = SteamFee::subtract_fee(13_00);
+
+        // not low enough float, stickers below `min_stickers_value`
+        assert!(evaluate_strategies(&get_listing(10_00, 0.2, 10_00), &ctx, false).is_empty());
+
+        // a premium below `max_premium_pct` is still reported
+        let signals = evaluate_strategies(&get_listing(steam_no_fee + 50, 0.005, 0), &ctx, false);
+        assert_eq!(get_strategies(&signals), vec![StrategyName::LowFloat]);
+        assert!(signals[0].event.profit_pct < 0.0);
+        assert!(
+            evaluate_strategies(&get_listing(steam_no_fee * 2, 0.005, 0), &ctx, false).is_empty()
+        );
+
+        // the premium is up to 10% of the stickers value
+        let signals =
+            evaluate_strategies(&get_listing(steam_no_fee + 5_00, 0.2, 80_00), &ctx, false);
+        assert_eq!(get_strategies(&signals), vec![StrategyName::Sticker]);
+        assert_eq!(signals[0].event.applied_value.stickers, 80_00);
+        assert!(
+            evaluate_strategies(&get_listing(steam_no_fee + 20_00, 0.2, 80_00), &ctx, false)
+                .is_empty()
+        );
+
+        // both look at skins only
+        let mut listing = get_listing(steam_no_fee + 50, 0.005, 80_00);
+        listing.item.kind = Some(CsfloatItemKind::Container);
+        assert!(evaluate_strategies(&listing, &ctx, false).is_empty());
+    }
+
+    #[test]
+    fn test_commodity_strategy() {
+        let market_name = MarketName::from("Recoil Case");
+        let mut steam_engine = get_steam_engine(&market_name, Utc::now());
+        let mut order_book = SteamOrderBook {
+            highest_buy_order: Some(3_00),
+            lowest_sell_order: Some(3_20),
+            buy_walls: vec![],
+            sell_walls: vec![],
+            updated_at: Utc::now(),
+        };
+        steam_engine.update_order_book(&market_name, order_book.clone());
+        let sticker_prices = StickerPriceTable::new();
+        let case = |id: &str, price: PriceValue| -> CsfloatListingStruct {
+            let mut listing = get_listing_json(id, "Recoil Case", price);
+            listing["item"]["type"] = "container".into();
+            serde_json::from_value(listing).unwrap()
+        };
+        let mut aggregates = MarketAggregates::new();
+        for (id, price) in [("1", 2_00), ("2", 1_90), ("3", 2_00), ("4", 2_50)] {
+            aggregates.update(&case(id, price));
+        }
+        let mut config = AppConfig::default();
+        config.strategy.desired_percentile = 60;
+        config.strategies.commodity.enabled = true;
+        config.strategies.commodity.batch_size = 3;
+
+        let evaluate = |steam_engine: &SteamEngine, config: &AppConfig| {
+            let ctx = StrategyContext {
+                steam_engine,
+                sticker_prices: &sticker_prices,
+                aggregates: &aggregates,
+                now: Utc::now(),
+                config,
+            };
+            evaluate_strategies(&case("1", 2_00), &ctx, false)
+        };
+
+        // priced by the buy order, not by the 13$ percentile, so steam arb stays away
+        let signals = evaluate(&steam_engine, &config);
+        assert_eq!(get_strategies(&signals), vec![StrategyName::Commodity]);
+        let event = &signals[0].event;
+        assert_eq!(event.steam_price, 3_00);
+        assert_eq!(
+            event.batch,
+            vec![(ListingId::from("2"), 1_90), (ListingId::from("3"), 2_00)]
+        );
+        assert_eq!(event.get_total_price(), 5_90);
+
+        // a stale order book isn't trusted
+        order_book.updated_at = Utc::now() - chrono::Duration::hours(2);
+        steam_engine.update_order_book(&market_name, order_book);
+        assert!(evaluate(&steam_engine, &config).is_empty());
+
+        config.strategies.commodity.enabled = false;
+        assert_eq!(
+            get_strategies(&evaluate(&steam_engine, &config)),
+            vec![StrategyName::SteamArb]
+        );
+    }
+}
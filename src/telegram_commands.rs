@@ -0,0 +1,629 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{Duration as ChronoDuration, Utc};
+use sqlx::{Pool, Postgres};
+use teloxide::{
+    payloads::{GetUpdatesSetters, SendPhotoSetters},
+    requests::{Request, Requester},
+    types::{CallbackQuery, ChatId, InputFile, Recipient, UpdateKind},
+    utils::command::BotCommands,
+    Bot,
+};
+use tokio::{
+    sync::{mpsc::Sender, Mutex},
+    task::JoinHandle,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    chart::render_price_chart,
+    config::{AppConfig, SharedConfig},
+    csfloat_autobuy::CsfloatAutobuy,
+    events::{BuyRequestedEvent, PurchaseConfirmedEvent, SecEvent},
+    filters::{
+        delete_item_mute, insert_blacklisted_seller, insert_item_mute, parse_mute_args, ItemMute,
+        ListingFilters, MuteTarget,
+    },
+    leadership::Leadership,
+    ledger::{record_sale, save_kill_switch, SaleRecord},
+    names::to_market_name,
+    price_history::{load_points, summarize},
+    prices::{PriceValue, PriceValueTrait},
+    risk::RiskManager,
+    shutdown::ShutdownSignal,
+    steam_inventory::InventoryTracker,
+    types::ListingId,
+    watchlist::{delete_watch_rule, insert_watch_rule, parse_watch_args, Watchlist},
+};
+
+const LONG_POLL_TIMEOUT_SECS: u32 = 10;
+const FAILED_POLL_PAUSE: Duration = Duration::from_secs(5);
+
+#[derive(BotCommands, Clone, Debug, PartialEq)]
+#[command(rename_rule = "lowercase", description = "Supported commands:")]
+pub enum Command {
+    #[command(description = "show this text")]
+    Help,
+    #[command(description = "turn the autobuy kill-switch on")]
+    Stop,
+    #[command(description = "turn the autobuy kill-switch off")]
+    Resume,
+    #[command(description = "show spend and open positions")]
+    Risk,
+    #[command(
+        rename = "reset_autobuy",
+        description = "resume the autobuy held by the circuit breaker"
+    )]
+    ResetAutobuy,
+    #[command(description = "close a position: /sold [price_usd] <market_hash_name>")]
+    Sold(String),
+    #[command(
+        description = "notify about listings: /watch <max_price_usd> [max_float] <market name>"
+    )]
+    Watch(String),
+    #[command(description = "show watchlist rules")]
+    Watchlist,
+    #[command(description = "remove a watchlist rule: /unwatch <id>")]
+    Unwatch(i64),
+    #[command(
+        description = "suppress an item: /mute [12h, 90m or 7d] <market name or listing id>"
+    )]
+    Mute(String),
+    #[command(description = "show muted items")]
+    Mutes,
+    #[command(description = "unmute an item: /unmute <id>")]
+    Unmute(i64),
+    #[command(description = "show unsold items in the Steam inventory")]
+    Inventory,
+    #[command(description = "draw CSFloat and Steam prices: /chart <market name>")]
+    Chart(String),
+}
+
+// Inline buttons of listing notifications, see `ListingFilters`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListingAction {
+    Buy(ListingId),
+    // semi-auto purchase, bought by the secondary dispatcher
+    ConfirmBuy(ListingId),
+    Snooze(ListingId),
+    BlacklistSeller(ListingId),
+}
+
+impl ListingAction {
+    // Telegram limits callback data to 64 bytes, so only the listing id is passed
+    pub fn to_callback_data(&self) -> String {
+        match self {
+            ListingAction::Buy(listing_id) => format!("buy:{}", listing_id),
+            ListingAction::ConfirmBuy(listing_id) => format!("confirm:{}", listing_id),
+            ListingAction::Snooze(listing_id) => format!("snooze:{}", listing_id),
+            ListingAction::BlacklistSeller(listing_id) => format!("blacklist:{}", listing_id),
+        }
+    }
+
+    pub fn from_callback_data(data: &str) -> Option<ListingAction> {
+        let (action, listing_id) = data.split_once(':')?;
+        let listing_id = ListingId::from(listing_id);
+        match action {
+            "buy" => Some(ListingAction::Buy(listing_id)),
+            "confirm" => Some(ListingAction::ConfirmBuy(listing_id)),
+            "snooze" => Some(ListingAction::Snooze(listing_id)),
+            "blacklist" => Some(ListingAction::BlacklistSeller(listing_id)),
+            _ => None,
+        }
+    }
+}
+
+// "12.34 AK-47 | Redline (Field-Tested)" -> sold for $12.34 after fee, the price is optional
+pub fn parse_sold_args(args: &str) -> (Option<PriceValue>, &str) {
+    let args = args.trim();
+    if let Some((first, rest)) = args.split_once(' ') {
+        if let Ok(price) = first.trim_start_matches('$').parse::<f64>() {
+            return (Some((price * 100.0).round() as PriceValue), rest.trim());
+        }
+    }
+    (None, args)
+}
+
+pub async fn handle_command(
+    command: Command,
+    risk_manager: &Mutex<RiskManager>,
+    watchlist: &Mutex<Watchlist>,
+    filters: &Mutex<ListingFilters>,
+    inventory: &Mutex<InventoryTracker>,
+    db: &Pool<Postgres>,
+) -> String {
+    let mut risk_manager_locked = risk_manager.lock().await;
+    match command {
+        Command::Help => Command::descriptions().to_string(),
+        Command::Stop => {
+            risk_manager_locked.set_kill_switch(true);
+            warn!("Autobuy kill-switch is turned on via Telegram");
+            match save_kill_switch(db, true).await {
+                Ok(()) => "Autobuy is stopped".to_string(),
+                Err(err) => {
+                    error!("Failed to save the kill-switch: {:?}", err);
+                    "Autobuy is stopped until a restart, failed to save it".to_string()
+                }
+            }
+        }
+        Command::Resume => {
+            risk_manager_locked.set_kill_switch(false);
+            warn!("Autobuy kill-switch is turned off via Telegram");
+            match save_kill_switch(db, false).await {
+                Ok(()) => "Autobuy is resumed".to_string(),
+                Err(err) => {
+                    error!("Failed to save the kill-switch: {:?}", err);
+                    "Autobuy is resumed until a restart, failed to save it".to_string()
+                }
+            }
+        }
+        Command::Risk => risk_manager_locked.summary(Utc::now()),
+        Command::Sold(args) => {
+            let (price, market_name) = parse_sold_args(&args);
+            let mut answer = match risk_manager_locked.register_sale(market_name) {
+                true => format!("Position of {} is closed", market_name),
+                false => format!("No open position of {}", market_name),
+            };
+            if let Some(price) = price {
+                let record = SaleRecord {
+                    market_name: market_name.into(),
+                    price,
+                    timestamp: Utc::now(),
+                };
+                match record_sale(db, &record).await {
+                    Ok(()) => answer += &format!(", sale for ${} is recorded", price.to_usd()),
+                    Err(err) => {
+                        error!("Failed to record sale {:?}: {:?}", record, err);
+                        answer += ", failed to record the sale";
+                    }
+                }
+            }
+            answer
+        }
+        Command::Watch(args) => {
+            let rule = match parse_watch_args(&args) {
+                Ok(rule) => rule,
+                Err(err) => return err,
+            };
+            match insert_watch_rule(db, &rule).await {
+                Ok(rule) => {
+                    let answer = format!("Rule #{} is added", rule.id);
+                    watchlist.lock().await.add(rule);
+                    answer
+                }
+                Err(err) => {
+                    error!("Failed to save watch rule {:?}: {:?}", rule, err);
+                    "Failed to save the rule".to_string()
+                }
+            }
+        }
+        Command::Watchlist => watchlist.lock().await.summary(),
+        Command::Unwatch(id) => {
+            if !watchlist.lock().await.remove(id) {
+                return format!("No rule #{}", id);
+            }
+            match delete_watch_rule(db, id).await {
+                Ok(()) => format!("Rule #{} is removed", id),
+                Err(err) => {
+                    error!("Failed to delete watch rule #{}: {:?}", id, err);
+                    format!("Rule #{} is removed until restart, failed to delete it", id)
+                }
+            }
+        }
+        Command::Mute(args) => {
+            let mute = match parse_mute_args(&args, Utc::now()) {
+                Ok(mute) => mute,
+                Err(err) => return err,
+            };
+            add_item_mute(filters, db, mute).await
+        }
+        Command::Mutes => filters.lock().await.summary(Utc::now()),
+        Command::Unmute(id) => {
+            if !filters.lock().await.remove_mute(id) {
+                return format!("No mute #{}", id);
+            }
+            match delete_item_mute(db, id).await {
+                Ok(()) => format!("Mute #{} is removed", id),
+                Err(err) => {
+                    error!("Failed to delete item mute #{}: {:?}", id, err);
+                    format!("Mute #{} is removed until restart, failed to delete it", id)
+                }
+            }
+        }
+        Command::Inventory => inventory.lock().await.summary(Utc::now()),
+        // sent as a photo, see `send_price_chart`
+        Command::Chart(_) => String::new(),
+        // needs the autobuy, see `reset_autobuy`
+        Command::ResetAutobuy => String::new(),
+    }
+}
+
+// The chart of `price_history.chart_days` with the price ranges in the caption,
+// only the caption when nothing is recorded
+async fn send_price_chart(
+    bot: &Bot,
+    chat_id: ChatId,
+    db: &Pool<Postgres>,
+    market_name: &str,
+    days: i64,
+) {
+    let market_name = to_market_name(market_name);
+    let since = Utc::now() - ChronoDuration::days(days);
+    let points = match load_points(db, &market_name, since).await {
+        Ok(points) => points,
+        Err(err) => {
+            error!("Failed to load price history of {}: {:?}", market_name, err);
+            let answer = format!("Failed to load price history of {}", market_name);
+            let _ = bot.send_message(Recipient::Id(chat_id), answer).await;
+            return;
+        }
+    };
+    let caption = summarize(&market_name, &points, days);
+    let result = match render_price_chart(&points) {
+        Some(png) => bot
+            .send_photo(
+                Recipient::Id(chat_id),
+                InputFile::memory(png).file_name("chart.png"),
+            )
+            .caption(caption)
+            .await
+            .map(|_| ()),
+        None => bot
+            .send_message(Recipient::Id(chat_id), caption)
+            .await
+            .map(|_| ()),
+    };
+    if let Err(err) = result {
+        warn!("Failed to send chart of {}: {:?}", market_name, err);
+    }
+}
+
+async fn add_item_mute(
+    filters: &Mutex<ListingFilters>,
+    db: &Pool<Postgres>,
+    mute: ItemMute,
+) -> String {
+    match insert_item_mute(db, &mute).await {
+        Ok(mute) => {
+            let answer = format!("Mute #{} is added", mute.id);
+            filters.lock().await.add_mute(mute);
+            answer
+        }
+        Err(err) => {
+            error!("Failed to save item mute {:?}: {:?}", mute, err);
+            "Failed to save the mute".to_string()
+        }
+    }
+}
+
+// Only listings of the recent notifications can be acted on, see `ListingFilters::remember`
+pub async fn handle_listing_action(
+    action: ListingAction,
+    filters: &Mutex<ListingFilters>,
+    sec_tx: &Sender<SecEvent>,
+    db: &Pool<Postgres>,
+    config: &AppConfig,
+) -> String {
+    let listing_id = match &action {
+        ListingAction::Buy(listing_id)
+        | ListingAction::ConfirmBuy(listing_id)
+        | ListingAction::Snooze(listing_id)
+        | ListingAction::BlacklistSeller(listing_id) => listing_id,
+    };
+    let Some(event) = filters.lock().await.find_notified(listing_id).cloned() else {
+        return format!("Listing {} is too old", listing_id);
+    };
+
+    match action {
+        // the expiry is checked by the secondary dispatcher
+        ListingAction::ConfirmBuy(_) => {
+            let confirmed = SecEvent::PurchaseConfirmed(PurchaseConfirmedEvent {
+                listing_id: listing_id.clone(),
+            });
+            match sec_tx.try_send(confirmed) {
+                Ok(()) => format!("Buying {}", event.market_name),
+                Err(err) => {
+                    error!("Failed to send confirmation of {}: {:?}", listing_id, err);
+                    format!("Failed to confirm {}", listing_id)
+                }
+            }
+        }
+        // bought by the secondary dispatcher with the autobuy checks, the result is notified
+        ListingAction::Buy(_) => {
+            let market_name = event.market_name.clone();
+            let requested = SecEvent::BuyRequested(BuyRequestedEvent { listing: event });
+            match sec_tx.try_send(requested) {
+                Ok(()) => format!("Buying {}", market_name),
+                Err(err) => {
+                    error!("Failed to request purchase of {}: {:?}", listing_id, err);
+                    format!("Failed to buy {}", listing_id)
+                }
+            }
+        }
+        ListingAction::Snooze(_) => {
+            let mute = ItemMute {
+                id: 0,
+                target: MuteTarget::MarketName(event.market_name.clone()),
+                until: Some(Utc::now() + ChronoDuration::hours(config.telegram.snooze_hours)),
+            };
+            add_item_mute(filters, db, mute).await
+        }
+        ListingAction::BlacklistSeller(_) => {
+            let Some(seller_id) = event.seller_id else {
+                return format!("Seller of {} is unknown", listing_id);
+            };
+            if !filters.lock().await.blacklist_seller(&seller_id) {
+                return format!("Seller {} is already blacklisted", seller_id);
+            }
+            match insert_blacklisted_seller(db, &seller_id).await {
+                Ok(()) => format!("Seller {} is blacklisted", seller_id),
+                Err(err) => {
+                    error!("Failed to blacklist seller {}: {:?}", seller_id, err);
+                    format!(
+                        "Seller {} is blacklisted until restart, failed to save it",
+                        seller_id
+                    )
+                }
+            }
+        }
+    }
+}
+
+// Long polls Telegram for commands and notification buttons, only updates
+// from `telegram.chat_id` are handled.
+pub async fn reset_autobuy(csfloat_autobuy: &Mutex<CsfloatAutobuy>) -> String {
+    match csfloat_autobuy.lock().await.reset_breaker() {
+        true => {
+            warn!("Autobuy circuit breaker is reset via Telegram");
+            "Autobuy is resumed".to_string()
+        }
+        false => "Autobuy isn't held by the circuit breaker".to_string(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_telegram_commands(
+    bot: Bot,
+    csfloat_autobuy: Arc<Mutex<CsfloatAutobuy>>,
+    risk_manager: Arc<Mutex<RiskManager>>,
+    watchlist: Arc<Mutex<Watchlist>>,
+    filters: Arc<Mutex<ListingFilters>>,
+    inventory: Arc<Mutex<InventoryTracker>>,
+    sec_tx: Sender<SecEvent>,
+    pool: Pool<Postgres>,
+    leadership: Leadership,
+    config: SharedConfig,
+    mut shutdown: ShutdownSignal,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let bot_name = match bot.get_me().await {
+            Ok(me) => me.username().to_string(),
+            Err(err) => {
+                warn!("Failed to get bot name: {:?}", err);
+                String::new()
+            }
+        };
+        let mut offset = 0;
+
+        loop {
+            // only the leader takes the updates, Telegram doesn't allow concurrent polling
+            if !leadership.is_leader() {
+                tokio::select! {
+                    _ = tokio::time::sleep(FAILED_POLL_PAUSE) => {}
+                    _ = shutdown.changed() => break,
+                }
+                continue;
+            }
+            let request = bot
+                .get_updates()
+                .offset(offset)
+                .timeout(LONG_POLL_TIMEOUT_SECS);
+            let updates = tokio::select! {
+                updates = request.send() => updates,
+                _ = shutdown.changed() => break,
+            };
+            let updates = match updates {
+                Ok(updates) => updates,
+                Err(err) => {
+                    warn!("Failed to get Telegram updates: {:?}", err);
+                    tokio::select! {
+                        _ = tokio::time::sleep(FAILED_POLL_PAUSE) => {}
+                        _ = shutdown.changed() => break,
+                    }
+                    continue;
+                }
+            };
+
+            let config = config.load();
+            let chat_id = config.telegram.chat_id();
+            for update in updates {
+                offset = offset.max(update.id + 1);
+                let message = match update.kind {
+                    UpdateKind::Message(message) => message,
+                    UpdateKind::CallbackQuery(query) => {
+                        let CallbackQuery {
+                            id, message, data, ..
+                        } = query;
+                        let is_allowed = message.is_some_and(|x| x.chat.id == chat_id);
+                        let action = data.as_deref().and_then(ListingAction::from_callback_data);
+                        let _ = bot.answer_callback_query(id).await;
+                        let (true, Some(action)) = (is_allowed, action) else {
+                            continue;
+                        };
+
+                        info!("Telegram listing action: {:?}", action);
+                        let answer =
+                            handle_listing_action(action, &filters, &sec_tx, &pool, &config).await;
+                        let _ = bot.send_message(Recipient::Id(chat_id), answer).await;
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if message.chat.id != chat_id {
+                    continue;
+                }
+                let Some(command) = message
+                    .text()
+                    .and_then(|text| Command::parse(text, &bot_name).ok())
+                else {
+                    continue;
+                };
+
+                info!("Telegram command: {:?}", command);
+                if command == Command::ResetAutobuy {
+                    let answer = reset_autobuy(&csfloat_autobuy).await;
+                    let _ = bot.send_message(Recipient::Id(chat_id), answer).await;
+                    continue;
+                }
+                if let Command::Chart(market_name) = &command {
+                    let days = config.price_history.chart_days;
+                    send_price_chart(&bot, chat_id, &pool, market_name, days).await;
+                    continue;
+                }
+                let answer = handle_command(
+                    command,
+                    &risk_manager,
+                    &watchlist,
+                    &filters,
+                    &inventory,
+                    &pool,
+                )
+                .await;
+                let _ = bot.send_message(Recipient::Id(chat_id), answer).await;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::AutobuyConfig, risk::RiskRejection, tests::fixtures::get_profitable_listing_event,
+    };
+    use sqlx::postgres::PgPoolOptions;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse("/stop", "bot").unwrap(), Command::Stop);
+        assert_eq!(
+            Command::parse("/resume@bot", "bot").unwrap(),
+            Command::Resume
+        );
+        assert_eq!(
+            Command::parse("/sold AK-47 | Redline (Field-Tested)", "bot").unwrap(),
+            Command::Sold("AK-47 | Redline (Field-Tested)".to_string())
+        );
+        assert_eq!(
+            Command::parse("/unwatch 3", "bot").unwrap(),
+            Command::Unwatch(3)
+        );
+        assert_eq!(
+            Command::parse("/chart AK-47 | Redline (Field-Tested)", "bot").unwrap(),
+            Command::Chart("AK-47 | Redline (Field-Tested)".to_string())
+        );
+        assert_eq!(
+            Command::parse("/reset_autobuy", "bot").unwrap(),
+            Command::ResetAutobuy
+        );
+        assert!(Command::parse("stop", "bot").is_err());
+    }
+
+    #[test]
+    fn test_parse_sold_args() {
+        assert_eq!(
+            parse_sold_args(" 12.03 AK-47 | Redline (Field-Tested) "),
+            (Some(12_03), "AK-47 | Redline (Field-Tested)")
+        );
+        assert_eq!(
+            parse_sold_args("$5 Sticker | Titan"),
+            (Some(5_00), "Sticker | Titan")
+        );
+        assert_eq!(
+            parse_sold_args("AK-47 | Redline (Field-Tested)"),
+            (None, "AK-47 | Redline (Field-Tested)")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kill_switch_command() {
+        let risk_manager = Mutex::new(RiskManager::new());
+        let watchlist = Mutex::new(Watchlist::new());
+        let filters = Mutex::new(ListingFilters::new());
+        let inventory = Mutex::new(InventoryTracker::new());
+        // the kill-switch isn't saved without the DB, it still applies until a restart
+        let db = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://localhost:1/test")
+            .unwrap();
+        let config = AutobuyConfig::default();
+        let check = |risk_manager: &RiskManager| risk_manager.check("A", 1_00, &config, Utc::now());
+
+        let answer = handle_command(
+            Command::Stop,
+            &risk_manager,
+            &watchlist,
+            &filters,
+            &inventory,
+            &db,
+        )
+        .await;
+        assert_eq!(
+            answer,
+            "Autobuy is stopped until a restart, failed to save it"
+        );
+        assert_eq!(
+            check(&*risk_manager.lock().await),
+            Err(RiskRejection::KillSwitch)
+        );
+        handle_command(
+            Command::Resume,
+            &risk_manager,
+            &watchlist,
+            &filters,
+            &inventory,
+            &db,
+        )
+        .await;
+        assert_eq!(check(&*risk_manager.lock().await), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_buy_action() {
+        let filters = Mutex::new(ListingFilters::new());
+        let (sec_tx, mut sec_rx) = mpsc::channel(1);
+        // the buy action doesn't touch the db
+        let db = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/test")
+            .unwrap();
+        let config = AppConfig::default();
+
+        let answer = handle_listing_action(
+            ListingAction::Buy("1".into()),
+            &filters,
+            &sec_tx,
+            &db,
+            &config,
+        )
+        .await;
+        assert_eq!(answer, "Listing 1 is too old");
+        assert!(sec_rx.try_recv().is_err());
+
+        let event = get_profitable_listing_event();
+        filters.lock().await.remember(&event);
+        let answer = handle_listing_action(
+            ListingAction::Buy("1".into()),
+            &filters,
+            &sec_tx,
+            &db,
+            &config,
+        )
+        .await;
+        assert_eq!(answer, "Buying AK-47 | Redline (Field-Tested)");
+        match sec_rx.try_recv() {
+            Ok(SecEvent::BuyRequested(requested)) => assert_eq!(requested.listing, event),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,561 @@
+use chrono::Utc;
+
+use crate::{
+    business_logic::{
+        apply_trade_hold_decay, check_autobuy, estimate_applied_value,
+        estimate_fallback_sell_price, estimate_steam_sell_price, estimate_stickers_value,
+        get_min_profit_abs, get_refresh_tier, get_sell_percentile, is_below_predicted_price,
+        is_need_notify_via_telegram, is_need_to_autobuy, is_need_to_confirm_buy, is_price_in_band,
+        is_reliable_seller, AutobuyRejection,
+    },
+    config::{AppConfig, CategoryPriceBand, LiquidityTier, ProfitBand, SellPriceSource},
+    csfloat::PriorityTier,
+    events::{PriceSource, ProfitableListingEvent, Venue},
+    models::{CsfloatListingItem, CsfloatSeller},
+    pricing::ItemCategory,
+    risk::{RiskManager, RiskRejection},
+    steam_analyzer::{AnalysisResult, Smoothing, Trend},
+    steam_orders::SteamOrderBook,
+    stickers::StickerPriceTable,
+    storages::{SteamEngine, SteamEngineTrait},
+    tests::fixtures::get_profitable_listing_event,
+    types::MarketName,
+};
+
+fn get_stickered_item() -> CsfloatListingItem {
+    serde_json::from_str(
+        r#"{
+            "market_hash_name": "AK-47 | Redline (Field-Tested)",
+            "stickers": [
+                {"name": "Sticker | Titan (Holo) | Katowice 2014", "slot": 0, "reference": {"price": 100000}},
+                {"name": "Sticker | Titan (Holo) | Katowice 2014", "slot": 1, "wear": 0.5},
+                {"name": "Sticker | Unknown", "slot": 2}
+            ]
+        }"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_estimate_stickers_value() {
+    let item = get_stickered_item();
+    let mut sticker_prices = StickerPriceTable::new();
+    sticker_prices.update_from_item(&item);
+
+    let mut config = AppConfig::default();
+    assert_eq!(estimate_stickers_value(&item, &sticker_prices, &config), 0);
+
+    config.stickers.value_multiplier = 0.05;
+    assert_eq!(
+        estimate_stickers_value(&item, &sticker_prices, &config),
+        5000
+    );
+
+    // scraped sticker is priced from the table
+    config.stickers.max_wear = 0.5;
+    assert_eq!(
+        estimate_stickers_value(&item, &sticker_prices, &config),
+        10000
+    );
+}
+
+#[test]
+fn test_estimate_applied_value() {
+    let item: CsfloatListingItem = serde_json::from_str(
+        r#"{
+            "market_hash_name": "AK-47 | Redline (Field-Tested)",
+            "keychains": [{"name": "Charm | Lil' Squirt", "slot": 0, "reference": {"price": 2000}}],
+            "patches": [{"name": "Patch | Phoenix", "slot": 0}]
+        }"#,
+    )
+    .unwrap();
+    let mut sticker_prices = StickerPriceTable::new();
+    sticker_prices.insert_saved("Patch | Phoenix".to_string(), 500);
+
+    let mut config = AppConfig::default();
+    config.stickers.keychain_multiplier = 0.5;
+    config.stickers.patch_multiplier = 0.2;
+    let value = estimate_applied_value(&item, &sticker_prices, &config);
+    assert_eq!(
+        (value.stickers, value.keychains, value.patches),
+        (0, 1000, 100)
+    );
+    assert_eq!(value.total(), 1100);
+}
+
+#[test]
+fn test_estimate_steam_sell_price_by_source() {
+    let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+    let mut steam_engine = SteamEngine::new();
+    steam_engine.update(
+        &market_name,
+        AnalysisResult {
+            rsd: Some(0.01),
+            is_stable: Some(true),
+            sold_per_week: Some(500),
+            percentiles: vec![(60, 1300)],
+            percentiles_no_fee: vec![(60, 1130)],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: None,
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
+        },
+    );
+
+    let mut config = AppConfig::default();
+    config.strategy.desired_percentile = 60;
+    assert_eq!(
+        estimate_steam_sell_price(&market_name, &steam_engine, &config),
+        Some(1300)
+    );
+
+    config.strategy.sell_price_source = SellPriceSource::HighestBuyOrder;
+    assert_eq!(
+        estimate_steam_sell_price(&market_name, &steam_engine, &config),
+        None
+    );
+
+    steam_engine.update_order_book(
+        &market_name,
+        SteamOrderBook {
+            highest_buy_order: Some(1203),
+            lowest_sell_order: Some(1290),
+            buy_walls: vec![],
+            sell_walls: vec![],
+            updated_at: Utc::now(),
+        },
+    );
+    assert_eq!(
+        estimate_steam_sell_price(&market_name, &steam_engine, &config),
+        Some(1203)
+    );
+}
+
+#[test]
+fn test_get_refresh_tier() {
+    let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+    let mut config = AppConfig::default();
+
+    assert_eq!(
+        get_refresh_tier(&market_name, 50, None, &config),
+        PriorityTier::Low
+    );
+    assert_eq!(
+        get_refresh_tier(&market_name, 1000, None, &config),
+        PriorityTier::Normal
+    );
+    assert_eq!(
+        get_refresh_tier(&market_name, 1000, Some(500), &config),
+        PriorityTier::Normal
+    );
+    // needs less than 10% drop to become profitable
+    assert_eq!(
+        get_refresh_tier(&market_name, 1000, Some(950), &config),
+        PriorityTier::High
+    );
+    assert_eq!(
+        get_refresh_tier(&market_name, 5000, None, &config),
+        PriorityTier::High
+    );
+
+    config.scheduler.watched_market_names = vec![market_name.clone()];
+    assert_eq!(
+        get_refresh_tier(&market_name, 50, None, &config),
+        PriorityTier::Watched
+    );
+}
+
+#[test]
+fn test_get_sell_percentile() {
+    let mut config = AppConfig::default();
+    config.strategy.desired_percentile = 65;
+    assert_eq!(get_sell_percentile(None, &config), Some(65));
+
+    config.strategy.liquidity_tiers = vec![
+        LiquidityTier {
+            min_sold_per_week: 50,
+            percentile: 75,
+        },
+        LiquidityTier {
+            min_sold_per_week: 500,
+            percentile: 60,
+        },
+    ];
+    assert_eq!(get_sell_percentile(Some(1000), &config), Some(60));
+    assert_eq!(get_sell_percentile(Some(500), &config), Some(60));
+    assert_eq!(get_sell_percentile(Some(120), &config), Some(75));
+    assert_eq!(get_sell_percentile(Some(49), &config), None);
+    assert_eq!(get_sell_percentile(None, &config), None);
+}
+
+#[test]
+fn test_is_reliable_seller() {
+    let get_seller = |verified: u64, failed: u64| -> CsfloatSeller {
+        serde_json::from_str(&format!(
+            r#"{{
+                "statistics": {{"total_verified_trades": {}, "total_failed_trades": {}}},
+                "verification_mode": "key"
+            }}"#,
+            verified, failed
+        ))
+        .unwrap()
+    };
+    let mut config = AppConfig::default();
+
+    assert!(is_reliable_seller(&get_seller(95, 5), &config));
+    assert!(!is_reliable_seller(&get_seller(94, 6), &config));
+    assert!(!is_reliable_seller(&get_seller(0, 0), &config));
+
+    config.seller.enabled = false;
+    assert!(is_reliable_seller(&get_seller(0, 0), &config));
+}
+
+#[test]
+fn test_trade_hold_decay() {
+    let item: CsfloatListingItem = serde_json::from_str(
+        r#"{
+            "market_hash_name": "AK-47 | Redline (Field-Tested)",
+            "tradable": 0,
+            "tradable_after": "2024-02-20T12:00:00Z"
+        }"#,
+    )
+    .unwrap();
+    let now = "2024-02-18T00:00:00Z".parse().unwrap();
+    assert_eq!(item.get_days_until_tradable(now), 3);
+    let now = "2024-02-21T00:00:00Z".parse().unwrap();
+    assert_eq!(item.get_days_until_tradable(now), 0);
+    let now = "2024-02-20T11:30:00Z".parse().unwrap();
+    assert_eq!(item.get_days_until_tradable(now), 1);
+    let now = "2024-02-19T11:59:59Z".parse().unwrap();
+    assert_eq!(item.get_days_until_tradable(now), 2);
+
+    let mut config = AppConfig::default();
+    config.trade_hold.decay_per_day_pct = 50.0;
+    assert_eq!(apply_trade_hold_decay(10_00, 0, &config), 10_00);
+    assert_eq!(apply_trade_hold_decay(10_00, 2, &config), 2_50);
+}
+
+#[test]
+fn test_estimate_fallback_sell_price() {
+    let market_name = MarketName::from("AK-47 | Redline (Field-Tested)");
+    let mut steam_engine = SteamEngine::new();
+    let config = AppConfig::default();
+
+    assert_eq!(
+        estimate_fallback_sell_price(&market_name, Some(10_00), &steam_engine, &config),
+        Some((8_00, PriceSource::PredictedPriceFallback))
+    );
+    // nothing would be left after the Steam fee
+    assert_eq!(
+        estimate_fallback_sell_price(&market_name, Some(3), &steam_engine, &config),
+        None
+    );
+    assert_eq!(
+        estimate_fallback_sell_price(&market_name, Some(0), &steam_engine, &config),
+        None
+    );
+
+    steam_engine.update_order_book(
+        &market_name,
+        SteamOrderBook {
+            highest_buy_order: Some(20_00),
+            lowest_sell_order: None,
+            buy_walls: vec![],
+            sell_walls: vec![],
+            updated_at: Utc::now(),
+        },
+    );
+    assert_eq!(
+        estimate_fallback_sell_price(&market_name, Some(10_00), &steam_engine, &config),
+        Some((19_00, PriceSource::BuyOrderFallback))
+    );
+
+    // analyzed items are priced by the strategy only
+    steam_engine.update(
+        &market_name,
+        AnalysisResult {
+            rsd: Some(0.01),
+            is_stable: Some(true),
+            sold_per_week: Some(1),
+            percentiles: vec![],
+            percentiles_no_fee: vec![],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: None,
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
+        },
+    );
+    assert_eq!(
+        estimate_fallback_sell_price(&market_name, Some(10_00), &steam_engine, &config),
+        None
+    );
+}
+
+#[test]
+fn test_is_below_predicted_price() {
+    let mut event = ProfitableListingEvent {
+        steam_price: 20_00,
+        steam_no_fee: 17_40,
+        predicted_price: Some(10_50),
+        profit_pct: 74.0,
+        ..get_profitable_listing_event()
+    };
+    let mut config = AppConfig::default();
+    assert!(is_below_predicted_price(&event, &config));
+
+    config.autobuy.max_predicted_price_ratio = 0.9;
+    assert!(!is_below_predicted_price(&event, &config));
+
+    event.predicted_price = None;
+    assert!(is_below_predicted_price(&event, &config));
+}
+
+#[test]
+fn test_falling_trend_needs_higher_profit() {
+    let mut event = ProfitableListingEvent {
+        trend: Trend::Rising(1.0),
+        profit_pct: 35.0,
+        ..get_profitable_listing_event()
+    };
+    let config = AppConfig::default();
+    assert!(is_need_notify_via_telegram(&event, &config));
+
+    event.trend = Trend::Falling(1.0);
+    assert!(!is_need_notify_via_telegram(&event, &config));
+
+    event.profit_pct = 45.0;
+    assert!(is_need_notify_via_telegram(&event, &config));
+}
+
+#[test]
+fn test_max_days_to_sell() {
+    let mut event = ProfitableListingEvent {
+        expected_days_to_sell: Some(12.5),
+        ..get_profitable_listing_event()
+    };
+    let mut config = AppConfig::default();
+    assert!(is_need_notify_via_telegram(&event, &config));
+
+    config.strategy.max_days_to_sell = 7.0;
+    assert!(!is_need_notify_via_telegram(&event, &config));
+
+    event.expected_days_to_sell = Some(3.0);
+    assert!(is_need_notify_via_telegram(&event, &config));
+
+    // not estimated without the number of Steam listings
+    event.expected_days_to_sell = None;
+    assert!(is_need_notify_via_telegram(&event, &config));
+}
+
+#[test]
+fn test_notify_only_below_csfloat_floor() {
+    let mut event = ProfitableListingEvent {
+        floor_undercut_pct: Some(2.0),
+        ..get_profitable_listing_event()
+    };
+    let mut config = AppConfig::default();
+    assert!(is_need_notify_via_telegram(&event, &config));
+
+    config.strategy.notify_min_floor_undercut_pct = 5.0;
+    assert!(!is_need_notify_via_telegram(&event, &config));
+
+    event.floor_undercut_pct = Some(7.5);
+    assert!(is_need_notify_via_telegram(&event, &config));
+
+    // the only listing of the item
+    event.floor_undercut_pct = None;
+    assert!(is_need_notify_via_telegram(&event, &config));
+}
+
+#[test]
+fn test_min_profit_abs() {
+    let mut event = ProfitableListingEvent {
+        market_name: "P250 | Sand Dune (Field-Tested)".into(),
+        csfloat_price: 60,
+        steam_price: 100,
+        steam_no_fee: 87,
+        profit_pct: 45.0,
+        ..get_profitable_listing_event()
+    };
+    let mut config = AppConfig::default();
+    assert!(is_need_notify_via_telegram(&event, &config));
+
+    config.strategy.min_profit_abs = 50;
+    config.strategy.min_profit_abs_bands = vec![
+        ProfitBand {
+            min_price: 5_00,
+            min_profit_abs: 1_00,
+        },
+        ProfitBand {
+            min_price: 20_00,
+            min_profit_abs: 3_00,
+        },
+    ];
+    assert_eq!(get_min_profit_abs(60, &config), 50);
+    assert_eq!(get_min_profit_abs(10_00, &config), 1_00);
+    assert_eq!(get_min_profit_abs(50_00, &config), 3_00);
+    // 27 cents of profit
+    assert!(!is_need_notify_via_telegram(&event, &config));
+
+    event.csfloat_price = 10_00;
+    event.steam_no_fee = 14_50;
+    assert!(is_need_notify_via_telegram(&event, &config));
+}
+
+#[test]
+fn test_confirm_buy_range() {
+    let mut event = ProfitableListingEvent {
+        profit_pct: 35.0,
+        ..get_profitable_listing_event()
+    };
+    let mut config = AppConfig::default();
+    config.strategy.tg_notify_min_profit_pct = 30.0;
+    config.autobuy.from_profit_pct = 45.0;
+    assert!(!is_need_to_confirm_buy(&event, &config));
+
+    config.autobuy.confirm_enabled = true;
+    assert!(is_need_to_confirm_buy(&event, &config));
+
+    event.profit_pct = 50.0;
+    assert!(!is_need_to_confirm_buy(&event, &config));
+
+    event.profit_pct = 35.0;
+    event.venue = Venue::Skinport;
+    assert!(!is_need_to_confirm_buy(&event, &config));
+}
+
+fn get_autobuy_event() -> ProfitableListingEvent {
+    ProfitableListingEvent {
+        listing_id: "123".into(),
+        steam_price: 20_00,
+        steam_no_fee: 17_40,
+        profit_pct: 60.0,
+        ..get_profitable_listing_event()
+    }
+}
+
+#[test]
+fn test_autobuy_venues() {
+    let mut event = get_autobuy_event();
+    let mut config = AppConfig::default();
+    config.autobuy.enabled = true;
+    let risk_manager = RiskManager::new();
+    let now = Utc::now();
+    assert!(is_need_to_autobuy(
+        &event,
+        &config,
+        &risk_manager,
+        None,
+        now
+    ));
+
+    event.venue = Venue::Dmarket;
+    assert!(!is_need_to_autobuy(
+        &event,
+        &config,
+        &risk_manager,
+        None,
+        now
+    ));
+    config.dmarket.autobuy = true;
+    assert!(is_need_to_autobuy(
+        &event,
+        &config,
+        &risk_manager,
+        None,
+        now
+    ));
+
+    event.venue = Venue::Skinport;
+    assert!(!is_need_to_autobuy(
+        &event,
+        &config,
+        &risk_manager,
+        None,
+        now
+    ));
+}
+
+#[test]
+fn test_autobuy_balance_includes_buyer_fee() {
+    let event = get_autobuy_event();
+    let mut config = AppConfig::default();
+    config.autobuy.enabled = true;
+    let risk_manager = RiskManager::new();
+    let now = Utc::now();
+
+    // $10 plus 2.8% of the buyer fee
+    let result = check_autobuy(&event, &config, &risk_manager, Some(10_27), now);
+    assert_eq!(result, Err(AutobuyRejection::Rule("balance is too low")));
+    assert!(is_need_to_autobuy(
+        &event,
+        &config,
+        &risk_manager,
+        Some(10_28),
+        now
+    ));
+}
+
+#[test]
+fn test_autobuy_market_cooldown() {
+    let event = get_autobuy_event();
+    let mut config = AppConfig::default();
+    config.autobuy.enabled = true;
+    config.autobuy.max_positions_per_market = 3;
+    config.autobuy.market_cooldown_secs = 3600;
+    let now = Utc::now();
+    let mut risk_manager = RiskManager::new();
+    risk_manager.register_purchase(&event.market_name, 10_00, now);
+
+    let result = check_autobuy(&event, &config, &risk_manager, None, now);
+    assert!(matches!(
+        result,
+        Err(AutobuyRejection::Risk(RiskRejection::MarketCooldown { .. }))
+    ));
+    let later = now + chrono::Duration::hours(1);
+    assert!(is_need_to_autobuy(
+        &event,
+        &config,
+        &risk_manager,
+        None,
+        later
+    ));
+
+    // open positions hold it after the cooldown
+    risk_manager.register_purchase(&event.market_name, 10_00, now);
+    risk_manager.register_purchase(&event.market_name, 10_00, now);
+    let result = check_autobuy(&event, &config, &risk_manager, None, later);
+    assert!(matches!(
+        result,
+        Err(AutobuyRejection::Risk(
+            RiskRejection::TooManyPositions { .. }
+        ))
+    ));
+}
+
+#[test]
+fn test_category_price_bands() {
+    let mut config = AppConfig::default();
+    config.strategy.category_price_bands = vec![CategoryPriceBand {
+        category: ItemCategory::Souvenir,
+        min_price: 50_00,
+        max_price: 50_000,
+    }];
+    assert!(is_price_in_band(10_00, ItemCategory::Normal, &config));
+    assert!(is_price_in_band(10_00, ItemCategory::Stattrak, &config));
+    assert!(!is_price_in_band(10_000, ItemCategory::Stattrak, &config));
+    // souvenirs are skipped until enabled
+    assert!(!is_price_in_band(10_000, ItemCategory::Souvenir, &config));
+
+    config.strategy.souvenirs_enabled = true;
+    assert!(is_price_in_band(10_000, ItemCategory::Souvenir, &config));
+    assert!(!is_price_in_band(10_00, ItemCategory::Souvenir, &config));
+}
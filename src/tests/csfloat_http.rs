@@ -0,0 +1,616 @@
+use std::{sync::Arc, time::Duration};
+
+use mockito::{Matcher, Server};
+use reqwest::StatusCode;
+use tokio::{
+    net::TcpListener,
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
+};
+
+use crate::{
+    config::{AppConfig, AutobuyConfig},
+    csfloat_autobuy::{BalanceError, BuyOutcome, CsfloatAutobuy, CsfloatBuyError, VerifyError},
+    csfloat_client::{ApiKeys, CsfloatClient},
+    csfloat_fetcher::{fetch_listing, ListingFetch},
+    events::SecEvent,
+    models::CsfloatListingState,
+    stats::Stats,
+    tests::fixtures::get_listing_json,
+};
+
+fn new_autobuy(base_url: &str, config: &AutobuyConfig) -> (CsfloatAutobuy, Receiver<SecEvent>) {
+    let (tx, rx) = mpsc::channel(10);
+    let stats = Arc::new(Mutex::new(Stats::new()));
+    let mut autobuy = CsfloatAutobuy::new(
+        ApiKeys::new(vec!["key".to_string()]),
+        None,
+        AppConfig {
+            autobuy: config.clone(),
+            ..AppConfig::default()
+        }
+        .into_shared(),
+        stats,
+        tx,
+    );
+    autobuy.client.set_base_url(base_url);
+    (autobuy, rx)
+}
+
+fn new_client(base_url: &str) -> (CsfloatClient, Arc<Mutex<Stats>>) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(500))
+        .build()
+        .unwrap();
+    let (tx, _rx) = mpsc::channel(10);
+    let stats = Arc::new(Mutex::new(Stats::new()));
+    let mut client = CsfloatClient::new(client, stats.clone(), tx);
+    client.set_base_url(base_url);
+    (client, stats)
+}
+
+// Accepts connections but never answers, the requests run into their timeout
+async fn silent_server() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    (listener, url)
+}
+
+fn listing_json(id: &str, price: u64, state: &str) -> String {
+    let mut listing = get_listing_json(id, "AK-47 | Redline (Field-Tested)", price);
+    listing["state"] = state.into();
+    listing.to_string()
+}
+
+fn unverified_config() -> AutobuyConfig {
+    AutobuyConfig {
+        verify_before_buy: false,
+        ..AutobuyConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn test_buy_listings_success() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/listings/buy")
+        .match_header("authorization", "key")
+        .match_body(Matcher::Json(serde_json::json!({
+            "total_price": 25_00,
+            "contract_ids": ["1", "2"]
+        })))
+        .with_status(200)
+        .with_body(r#"{"message": "all listings purchased"}"#)
+        .create_async()
+        .await;
+    let (mut autobuy, _rx) = new_autobuy(&server.url(), &unverified_config());
+    autobuy.set_balance(10_000, 0);
+
+    let outcome = autobuy
+        .buy_listings(&[("1".into(), 10_00), ("2".into(), 15_00)])
+        .await;
+    assert_eq!(
+        outcome,
+        Ok(BuyOutcome {
+            is_success: true,
+            status: Some(200),
+            response: serde_json::json!({"message": "all listings purchased"}),
+        })
+    );
+    assert_eq!(autobuy.get_cached_balance(), Some(75_00));
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_buy_listing_failures() {
+    let mut server = Server::new_async().await;
+    let (mut autobuy, mut rx) = new_autobuy(&server.url(), &unverified_config());
+
+    let mock = server
+        .mock("POST", "/listings/buy")
+        .with_status(400)
+        .with_body(r#"{"code": 4, "message": "the listing is no longer available"}"#)
+        .create_async()
+        .await;
+    assert_eq!(
+        autobuy.buy_listing(&"1".into(), 10_00).await,
+        Err(CsfloatBuyError::AlreadySold)
+    );
+    mock.remove_async().await;
+
+    // an unexpected success body isn't a purchase
+    autobuy.next_call = chrono::Utc::now();
+    let mock = server
+        .mock("POST", "/listings/buy")
+        .with_status(200)
+        .with_body(r#"{"code": 20, "message": "listing is reserved"}"#)
+        .create_async()
+        .await;
+    assert_eq!(
+        autobuy.buy_listing(&"1".into(), 10_00).await,
+        Err(CsfloatBuyError::Api {
+            code: 20,
+            message: "listing is reserved".to_string()
+        })
+    );
+    mock.remove_async().await;
+
+    autobuy.next_call = chrono::Utc::now();
+    server
+        .mock("POST", "/listings/buy")
+        .with_status(401)
+        .with_body(r#"{"message": "Invalid API key"}"#)
+        .create_async()
+        .await;
+    let err = autobuy.buy_listing(&"1".into(), 10_00).await.unwrap_err();
+    assert_eq!(err, CsfloatBuyError::AuthExpired);
+    assert!(err.is_fatal());
+    // the rejected key is alerted
+    assert!(matches!(rx.try_recv(), Ok(SecEvent::Alert(_))));
+}
+
+#[tokio::test]
+async fn test_buy_listing_rate_limited() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/listings/buy")
+        .with_status(429)
+        .with_header("x-ratelimit-remaining", "0")
+        .with_header("x-ratelimit-reset", "30")
+        .with_body(r#"{"message": "slow down"}"#)
+        .expect(1)
+        .create_async()
+        .await;
+    let (mut autobuy, _rx) = new_autobuy(&server.url(), &unverified_config());
+
+    assert_eq!(
+        autobuy.buy_listing(&"1".into(), 10_00).await,
+        Err(CsfloatBuyError::RateLimited {
+            retry_after: Some(30)
+        })
+    );
+    // held locally until the limit resets, CSFloat isn't asked again
+    let err = autobuy.buy_listing(&"1".into(), 10_00).await.unwrap_err();
+    assert!(matches!(
+        err,
+        CsfloatBuyError::RateLimited {
+            retry_after: Some(secs)
+        } if secs > 20
+    ));
+    assert_eq!(autobuy.client.get_rate_limit().remaining, Some(0));
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_buy_listing_timeout() {
+    let (_listener, url) = silent_server().await;
+    let config = AutobuyConfig {
+        request_timeout_secs: 1,
+        ..unverified_config()
+    };
+    let (mut autobuy, _rx) = new_autobuy(&url, &config);
+
+    // CSFloat could have executed it
+    let err = autobuy.buy_listing(&"1".into(), 10_00).await.unwrap_err();
+    assert!(matches!(err, CsfloatBuyError::Unknown(_)));
+    assert!(!err.is_retryable());
+}
+
+#[tokio::test]
+async fn test_buy_listing_not_executed() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let (mut autobuy, _rx) = new_autobuy(&url, &unverified_config());
+
+    let err = autobuy.buy_listing(&"1".into(), 10_00).await.unwrap_err();
+    assert!(matches!(err, CsfloatBuyError::Request(_)));
+    assert!(err.is_retryable());
+    // nothing was answered, so the retry isn't held by the cooldown
+    assert!(autobuy.next_call <= chrono::Utc::now());
+
+    let mut server = Server::new_async().await;
+    autobuy.client.set_base_url(&server.url());
+    server
+        .mock("POST", "/listings/buy")
+        .with_status(502)
+        .create_async()
+        .await;
+    let err = autobuy.buy_listing(&"1".into(), 10_00).await.unwrap_err();
+    assert!(err.is_retryable());
+}
+
+#[tokio::test]
+async fn test_reconcile_purchase() {
+    let mut server = Server::new_async().await;
+    let config = AutobuyConfig {
+        buy_cooldown_secs: 0,
+        ..unverified_config()
+    };
+    let (mut autobuy, _rx) = new_autobuy(&server.url(), &config);
+    let buy = server
+        .mock("POST", "/listings/buy")
+        .with_status(200)
+        .with_body("<html>")
+        .create_async()
+        .await;
+    let listings = [("1".into(), 10_00)];
+    let err = autobuy.buy_listings(&listings).await.unwrap_err();
+    assert!(matches!(err, CsfloatBuyError::Unknown(_)));
+    buy.remove_async().await;
+
+    let listing = server
+        .mock("GET", "/listings/1")
+        .with_status(200)
+        .with_body(listing_json("1", 10_00, "listed"))
+        .create_async()
+        .await;
+    let result = autobuy
+        .reconcile_purchase(&listings, Some(50_00), err.clone())
+        .await;
+    assert!(matches!(result, Err(CsfloatBuyError::Request(_))));
+    listing.remove_async().await;
+
+    server
+        .mock("GET", "/listings/1")
+        .with_status(200)
+        .with_body(listing_json("1", 10_00, "sold"))
+        .create_async()
+        .await;
+    let balance = server
+        .mock("GET", "/me")
+        .with_status(200)
+        .with_body(r#"{"user": {"balance": 3972}}"#)
+        .create_async()
+        .await;
+    let outcome = autobuy
+        .reconcile_purchase(&listings, Some(50_00), err.clone())
+        .await
+        .unwrap();
+    assert!(outcome.is_success);
+    assert_eq!(autobuy.get_cached_balance(), Some(39_72));
+    balance.remove_async().await;
+
+    // sold to someone else or the balance was topped up meanwhile
+    server
+        .mock("GET", "/me")
+        .with_status(200)
+        .with_body(r#"{"user": {"balance": 5000}}"#)
+        .create_async()
+        .await;
+    let result = autobuy
+        .reconcile_purchase(&listings, Some(50_00), err.clone())
+        .await;
+    assert_eq!(result, Err(err));
+}
+
+#[tokio::test]
+async fn test_circuit_breaker() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/listings/buy")
+        .with_status(500)
+        .with_body(r#"{"code": 1, "message": "internal error"}"#)
+        .expect(3)
+        .create_async()
+        .await;
+    let config = AutobuyConfig {
+        buy_cooldown_secs: 0,
+        ..unverified_config()
+    };
+    let (mut autobuy, mut rx) = new_autobuy(&server.url(), &config);
+
+    for _ in 0..3 {
+        assert!(matches!(
+            autobuy.buy_listing(&"1".into(), 10_00).await,
+            Err(CsfloatBuyError::Api { code: 1, .. })
+        ));
+    }
+    assert!(matches!(rx.try_recv(), Ok(SecEvent::Alert(_))));
+    // held without asking CSFloat
+    assert!(matches!(
+        autobuy.buy_listing(&"1".into(), 10_00).await,
+        Err(CsfloatBuyError::CircuitOpen { .. })
+    ));
+    mock.assert_async().await;
+    mock.remove_async().await;
+
+    server
+        .mock("POST", "/listings/buy")
+        .with_status(200)
+        .with_body(r#"{"message": "all listings purchased"}"#)
+        .create_async()
+        .await;
+    assert!(autobuy.reset_breaker());
+    assert!(autobuy.buy_listing(&"1".into(), 10_00).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_api_key_rotation() {
+    let mut server = Server::new_async().await;
+    let rejected = server
+        .mock("POST", "/listings/buy")
+        .match_header("authorization", "first-key")
+        .with_status(401)
+        .with_body(r#"{"message": "Invalid API key"}"#)
+        .expect(1)
+        .create_async()
+        .await;
+    server
+        .mock("POST", "/listings/buy")
+        .match_header("authorization", "second-key")
+        .with_status(200)
+        .with_body(r#"{"message": "all listings purchased"}"#)
+        .create_async()
+        .await;
+    let (tx, mut rx) = mpsc::channel(10);
+    let stats = Arc::new(Mutex::new(Stats::new()));
+    let mut autobuy = CsfloatAutobuy::new(
+        ApiKeys::parse("first-key,second-key"),
+        None,
+        AppConfig {
+            autobuy: unverified_config(),
+            ..AppConfig::default()
+        }
+        .into_shared(),
+        stats,
+        tx,
+    );
+    autobuy.client.set_base_url(&server.url());
+
+    // the purchase goes through with the next key
+    assert!(autobuy.buy_listing(&"1".into(), 10_00).await.is_ok());
+    rejected.assert_async().await;
+    assert_eq!(autobuy.client.get_key_counts(), (1, 1, 2));
+    let Ok(SecEvent::Alert(alert)) = rx.try_recv() else {
+        panic!("the rejected key isn't alerted");
+    };
+    assert!(alert.text.contains("#1 (...-key)"));
+    assert!(rx.try_recv().is_err());
+
+    // the probe revives the key once it works again
+    server
+        .mock("GET", "/me")
+        .with_status(200)
+        .with_body(r#"{"user": {"balance": 100}}"#)
+        .create_async()
+        .await;
+    autobuy.client.probe_keys().await;
+    assert_eq!(autobuy.client.get_key_counts(), (1, 2, 2));
+    assert!(
+        matches!(rx.try_recv(), Ok(SecEvent::Alert(alert)) if alert.text.contains("works again"))
+    );
+}
+
+#[tokio::test]
+async fn test_forbidden_purchase_is_retried() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("POST", "/listings/buy")
+        .match_header("authorization", "first-key")
+        .with_status(403)
+        .with_body(r#"{"code": 1, "message": "forbidden"}"#)
+        .create_async()
+        .await;
+    server
+        .mock("POST", "/listings/buy")
+        .match_header("authorization", "second-key")
+        .with_status(200)
+        .with_body(r#"{"message": "all listings purchased"}"#)
+        .create_async()
+        .await;
+    let (mut autobuy, _rx) = new_autobuy(&server.url(), &unverified_config());
+    autobuy
+        .client
+        .set_api_keys(ApiKeys::parse("first-key,second-key"));
+
+    assert!(autobuy.buy_listing(&"1".into(), 10_00).await.is_ok());
+    assert_eq!(autobuy.client.get_key_counts(), (1, 1, 2));
+    assert_eq!(autobuy.get_breaker_open_until(), None);
+}
+
+#[tokio::test]
+async fn test_forbidden_page_keeps_the_key() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/me")
+        .with_status(403)
+        .with_body("<html>Attention Required! | Cloudflare</html>")
+        .create_async()
+        .await;
+    let (autobuy, mut rx) = new_autobuy(&server.url(), &AutobuyConfig::default());
+    autobuy
+        .client
+        .set_api_keys(ApiKeys::parse("first-key,second-key"));
+
+    // the caller still gets the body
+    let response = autobuy
+        .client
+        .send(autobuy.client.get(autobuy.client.url("/me")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+    assert!(response.text().await.unwrap().contains("Cloudflare"));
+    assert_eq!(autobuy.client.get_key_counts(), (0, 2, 2));
+    assert!(matches!(rx.try_recv(), Ok(SecEvent::Alert(alert)) if alert.text.contains("403")));
+    // the next ones are only counted until the alert interval passes
+    autobuy
+        .client
+        .send(autobuy.client.get(autobuy.client.url("/me")))
+        .await
+        .unwrap();
+    assert!(rx.try_recv().is_err());
+    mock.remove_async().await;
+
+    server
+        .mock("GET", "/me")
+        .with_status(403)
+        .with_body(r#"{"code": 1, "message": "forbidden"}"#)
+        .create_async()
+        .await;
+    autobuy
+        .client
+        .send(autobuy.client.get(autobuy.client.url("/me")))
+        .await
+        .unwrap();
+    assert_eq!(autobuy.client.get_key_counts(), (1, 1, 2));
+    // a clone, e.g. of the auth probe, shares the keys
+    let probe = autobuy.client.clone();
+    probe.set_api_keys(ApiKeys::parse("third-key"));
+    assert_eq!(autobuy.client.get_key_counts(), (0, 1, 1));
+}
+
+#[tokio::test]
+async fn test_verify_before_buy() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/listings/1")
+        .with_status(200)
+        .with_body(listing_json("1", 10_00, "sold"))
+        .create_async()
+        .await;
+    let buy = server
+        .mock("POST", "/listings/buy")
+        .expect(0)
+        .create_async()
+        .await;
+    let (mut autobuy, _rx) = new_autobuy(&server.url(), &AutobuyConfig::default());
+
+    assert_eq!(
+        autobuy.buy_listing(&"1".into(), 10_00).await,
+        Err(CsfloatBuyError::Aborted(VerifyError::NotListed(
+            CsfloatListingState::Sold
+        )))
+    );
+    buy.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_place_bid_once() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/listings/1/bid")
+        .match_body(Matcher::Json(serde_json::json!({"max_price": 12_00})))
+        .with_status(200)
+        .with_body(r#"{"id": "bid"}"#)
+        .create_async()
+        .await;
+    let rejected = server
+        .mock("POST", "/listings/2/bid")
+        .with_status(400)
+        .with_body(r#"{"code": 4, "message": "listing is sold"}"#)
+        .create_async()
+        .await;
+    let (mut autobuy, _rx) = new_autobuy(&server.url(), &AutobuyConfig::default());
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(1);
+
+    assert!(!autobuy.has_bid(&"1".into()));
+    let outcome = autobuy
+        .place_bid(&"1".into(), 12_00, expires_at)
+        .await
+        .unwrap();
+    assert!(outcome.is_success);
+    assert!(autobuy.has_bid(&"1".into()));
+    mock.assert_async().await;
+
+    // a rejected bid isn't sent again either
+    assert!(autobuy
+        .place_bid(&"2".into(), 12_00, expires_at)
+        .await
+        .is_err());
+    assert!(autobuy.has_bid(&"2".into()));
+    rejected.assert_async().await;
+
+    // an ended auction is forgotten
+    autobuy
+        .place_bid(&"1".into(), 12_00, chrono::Utc::now())
+        .await
+        .unwrap();
+    assert!(!autobuy.has_bid(&"1".into()));
+}
+
+#[tokio::test]
+async fn test_get_balance() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/me")
+        .with_status(200)
+        .with_body(r#"{"user": {"balance": 12345}}"#)
+        .create_async()
+        .await;
+    let (mut autobuy, _rx) = new_autobuy(&server.url(), &AutobuyConfig::default());
+    assert_eq!(autobuy.get_balance().await.unwrap(), 12345);
+    mock.remove_async().await;
+
+    // a failed request must not look like an empty balance
+    let mock = server
+        .mock("GET", "/me")
+        .with_status(503)
+        .create_async()
+        .await;
+    assert!(autobuy.get_balance().await.is_err());
+    mock.remove_async().await;
+
+    // nor a response without it
+    server
+        .mock("GET", "/me")
+        .with_status(200)
+        .with_body(r#"{"user": {}}"#)
+        .create_async()
+        .await;
+    assert!(matches!(
+        autobuy.get_balance().await,
+        Err(BalanceError::Malformed(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_fetch_listing() {
+    let mut server = Server::new_async().await;
+    let (client, stats) = new_client(&server.url());
+
+    let body = listing_json("1", 10_00, "listed");
+    server
+        .mock("GET", "/listings/1")
+        .with_status(200)
+        .with_header("x-ratelimit-remaining", "99")
+        .with_body(&body)
+        .create_async()
+        .await;
+    assert_eq!(
+        fetch_listing(&client, &"1".into()).await,
+        ListingFetch::Fetched(body)
+    );
+    assert_eq!(client.get_rate_limit().remaining, Some(99));
+
+    for (id, status, expected) in [
+        ("2", 404, ListingFetch::NotFound),
+        ("3", 429, ListingFetch::RateLimited),
+        ("4", 502, ListingFetch::Failed),
+        ("5", 400, ListingFetch::Rejected(StatusCode::BAD_REQUEST)),
+    ] {
+        server
+            .mock("GET", format!("/listings/{}", id).as_str())
+            .with_status(status)
+            .with_body(r#"{"code": 3, "message": "nope"}"#)
+            .create_async()
+            .await;
+        assert_eq!(fetch_listing(&client, &id.into()).await, expected);
+    }
+
+    let counters = stats.lock().await.get_snapshot().counters;
+    assert!(counters.contains(&("CsfloatRateLimited".to_string(), 1)));
+    assert!(counters.contains(&("CsfloatApiError(3)".to_string(), 4)));
+}
+
+#[tokio::test]
+async fn test_fetch_listing_timeout() {
+    let (_listener, url) = silent_server().await;
+    let (client, _) = new_client(&url);
+    assert_eq!(
+        fetch_listing(&client, &"1".into()).await,
+        ListingFetch::Failed
+    );
+}
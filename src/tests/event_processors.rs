@@ -1,18 +1,31 @@
-use std::time::Instant;
+use std::{collections::HashMap, time::Instant};
 
-use chrono::{DateTime, NaiveDate, Utc};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 
 use crate::{
+    config::AppConfig,
     csfloat::CsfloatScheduler,
-    event_processors::{process_csfloat_one_listing_response, process_steam_response},
+    currency::{Currency, ExchangeRates},
+    event_processors::{
+        build_listing_notification, parse_steam_response, process_csfloat_one_listing_response,
+        process_paper_purchase, process_steam_analysis_ready, process_steam_analysis_requested,
+        process_steam_response, process_updated_csfloat_listing,
+    },
     events::{
-        CsfloatOneListingResponseEvent, Event, PrimEvent, SteamResponseEvent,
-        UpdatedCsfloatListingsEvent,
+        CsfloatOneListingResponseEvent, Event, PaperPurchaseCheckedEvent, PaperPurchaseEvent,
+        PriceSource, PrimEvent, ProfitableListingEvent, ProfitableListingKind, SecEvent,
+        SteamAnalysisReadyEvent, SteamAnalysisRequestedEvent, SteamResponseEvent,
+        UpdatedCsfloatListingsEvent, Venue,
     },
-    models::CsfloatListingState,
+    filters::ListingFilters,
+    models::{CsfloatItemKind, CsfloatListingState, CsfloatListingStruct},
     prices::PriceValue,
-    storages::{CsfloatEngine, CsfloatEngineTrait, SteamEngine},
-    types::ListingId,
+    steam_analyzer::{AnalysisResult, Smoothing, Trend},
+    storages::{CsfloatEngine, CsfloatEngineTrait, SteamEngine, SteamEngineTrait},
+    telegram_commands::ListingAction,
+    tests::fixtures::{get_listing, get_listing_json, get_profitable_listing_event},
+    types::{ListingId, MarketName},
+    watchlist::{WatchRule, Watchlist},
 };
 
 #[tokio::test]
@@ -28,18 +41,120 @@ async fn test_process_steam_response() {
     let event = SteamResponseEvent {
         response: input,
         timestamp: DateTime::from_naive_utc_and_offset(faked_datetime, Utc),
+        sell_listings: Some(86_319),
+        log_seq: None,
     };
 
-    let result = process_steam_response(&mut steam_engine, &event).await;
+    let result = process_steam_response(
+        &mut steam_engine,
+        &CsfloatEngine::new(),
+        &event,
+        &ExchangeRates::new(),
+        &AppConfig::default(),
+    )
+    .await;
     let analysis_result = steam_engine.hm.get("Kilowatt Case").unwrap();
 
     assert_eq!(analysis_result.is_stable, Some(false));
     assert_eq!(analysis_result.sold_per_week, Some(604_240));
     assert_eq!(analysis_result.rsd, Some(0.04770835480294064));
+    // a day of sales is needed to sell out the listings before ours
+    assert_eq!(analysis_result.expected_days_to_sell, Some(1.0));
 
     assert_eq!(result.len(), 0);
 }
 
+#[test]
+fn test_parse_steam_response_in_wallet_currency() {
+    let input = std::fs::read_to_string("src/test_data/Kilowatt Case.html").unwrap();
+    let event = SteamResponseEvent {
+        response: input,
+        timestamp: Utc.with_ymd_and_hms(2024, 2, 19, 0, 0, 0).unwrap(),
+        sell_listings: None,
+        log_seq: None,
+    };
+    let mut config = AppConfig::default();
+    let (_, usd) = parse_steam_response(&event, &ExchangeRates::new(), &config).unwrap();
+
+    config.currency.steam_wallet_currency = Currency::Eur;
+    // skipped until the rate is known
+    assert!(parse_steam_response(&event, &ExchangeRates::new(), &config).is_none());
+
+    let rates = ExchangeRates {
+        rates: HashMap::from([(Currency::Eur, 0.5)]),
+        updated_at: None,
+    };
+    let (_, eur) = parse_steam_response(&event, &rates, &config).unwrap();
+    assert_eq!(eur.sold_per_week, usd.sold_per_week);
+    assert_eq!(eur.percentiles.len(), usd.percentiles.len());
+    for ((_, eur_price), (_, usd_price)) in eur.percentiles.iter().zip(&usd.percentiles) {
+        assert!(eur_price.abs_diff(usd_price * 2) <= 1);
+    }
+}
+
+#[tokio::test]
+async fn test_process_steam_analysis_ready() {
+    let mut steam_engine = SteamEngine::new();
+    let now = Utc::now();
+    let ready = |sold_per_week, analyzed_at| SteamAnalysisReadyEvent {
+        market_name: "Kilowatt Case".into(),
+        result: AnalysisResult {
+            rsd: Some(0.01),
+            is_stable: Some(true),
+            sold_per_week: Some(sold_per_week),
+            percentiles: vec![(60, 1_00)],
+            percentiles_no_fee: vec![(60, 87)],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: Some(analyzed_at),
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
+        },
+        log_seq: None,
+    };
+
+    let mut csfloat_engine = CsfloatEngine::new();
+    let listing = |id: &str, market_name: &str| get_listing(id, market_name, 1_00);
+    csfloat_engine.update_listing(&listing("2", "Kilowatt Case"));
+    csfloat_engine.update_listing(&listing("1", "Kilowatt Case"));
+    csfloat_engine.update_listing(&listing("3", "Recoil Case"));
+
+    // stored listings of the item are evaluated again
+    assert_eq!(
+        process_steam_analysis_ready(&mut steam_engine, &csfloat_engine, &ready(500, now)).await,
+        vec![Event::Primary(PrimEvent::UpdatedCsfloatListings(
+            UpdatedCsfloatListingsEvent {
+                listing_ids: vec!["1".into(), "2".into()]
+            }
+        ))]
+    );
+    // an older response parsed last is skipped
+    assert!(process_steam_analysis_ready(
+        &mut steam_engine,
+        &csfloat_engine,
+        &ready(400, now - Duration::hours(1))
+    )
+    .await
+    .is_empty());
+    let analysis_result = steam_engine.hm.get("Kilowatt Case").unwrap();
+    assert_eq!(analysis_result.sold_per_week, Some(500));
+
+    csfloat_engine.remove_listing(&"1".into());
+    csfloat_engine.remove_listing(&"2".into());
+    assert!(process_steam_analysis_ready(
+        &mut steam_engine,
+        &csfloat_engine,
+        &ready(600, now + Duration::hours(1))
+    )
+    .await
+    .is_empty());
+    let analysis_result = steam_engine.hm.get("Kilowatt Case").unwrap();
+    assert_eq!(analysis_result.sold_per_week, Some(600));
+}
+
 #[tokio::test]
 async fn test_process_csfloat_one_listing_response() {
     // Prepare your test data
@@ -108,17 +223,22 @@ async fn test_process_csfloat_one_listing_response() {
         }
     "#;
     let event = CsfloatOneListingResponseEvent {
-        timestamp: Instant::now(), // Set the timestamp to the current time
+        timestamp: Instant::now(),      // Set the timestamp to the current time
         response: response.to_string(), // Provide your test JSON response
-                                   // You might need to provide other fields if they're required by your implementation
+        // You might need to provide other fields if they're required by your implementation
+        log_seq: None,
     };
 
     // Call the function being tested
-    let result =
-        process_csfloat_one_listing_response(&mut csfloat_engine, &mut csfloat_scheduler, &event)
-            .await;
+    let result = process_csfloat_one_listing_response(
+        &mut csfloat_engine,
+        &mut csfloat_scheduler,
+        &event,
+        &AppConfig::default(),
+    )
+    .await;
 
-    let listing_id: ListingId = "679718648830624407".to_string();
+    let listing_id: ListingId = "679718648830624407".into();
 
     // Assert the result against your expectations
     assert_eq!(result.len(), 1);
@@ -143,4 +263,298 @@ async fn test_process_csfloat_one_listing_response() {
         stored_item.item.market_hash_name,
         "Glock-18 | Wasteland Rebel (Minimal Wear)".to_string()
     );
+    assert_eq!(stored_item.item.rarity, Some(6));
+    assert_eq!(
+        stored_item.item.collection.as_deref(),
+        Some("The Gamma Collection")
+    );
+    assert_eq!(stored_item.item.kind, Some(CsfloatItemKind::Skin));
+    assert_eq!(stored_item.item.wear_name.as_deref(), Some("Minimal Wear"));
+}
+
+#[tokio::test]
+async fn test_paper_purchase_is_checked_by_next_refresh() {
+    let mut csfloat_engine = CsfloatEngine::new();
+    let mut csfloat_scheduler = CsfloatScheduler::new();
+    let listing_id: ListingId = "679718648830624407".into();
+    let event = PaperPurchaseEvent {
+        listing_id: listing_id.clone(),
+    };
+    process_paper_purchase(&mut csfloat_engine, &mut csfloat_scheduler, &event).await;
+
+    let mut response = get_listing_json(
+        &listing_id,
+        "Glock-18 | Wasteland Rebel (Minimal Wear)",
+        3_55,
+    );
+    response["state"] = "sold".into();
+    let event = CsfloatOneListingResponseEvent {
+        timestamp: Instant::now(),
+        response: response.to_string(),
+        log_seq: None,
+    };
+    let result = process_csfloat_one_listing_response(
+        &mut csfloat_engine,
+        &mut csfloat_scheduler,
+        &event,
+        &AppConfig::default(),
+    )
+    .await;
+
+    assert_eq!(
+        result[0],
+        Event::Secondary(SecEvent::PaperPurchaseChecked(PaperPurchaseCheckedEvent {
+            listing_id,
+            is_available: false,
+        }))
+    );
+    assert!(csfloat_engine.paper_checks.is_empty());
+}
+
+#[tokio::test]
+async fn test_auction_is_not_bought_as_buy_now() {
+    let market_name = MarketName::from("Glock-18 | Wasteland Rebel (Minimal Wear)");
+    let listing_id: ListingId = "679718648830624407".into();
+    let mut steam_engine = SteamEngine::new();
+    steam_engine.update(
+        &market_name,
+        AnalysisResult {
+            rsd: Some(0.01),
+            is_stable: Some(true),
+            sold_per_week: Some(500),
+            percentiles: vec![(60, 13_00)],
+            percentiles_no_fee: vec![(60, 11_30)],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: Some(Utc::now()),
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
+        },
+    );
+    let mut listing = get_listing_json(&listing_id, &market_name, 1_00);
+    listing["type"] = "auction".into();
+    listing["auction_details"] = serde_json::json!({
+        "reserve_price": 100,
+        "top_bid": {"price": 450},
+        "min_next_bid": 500,
+        "expires_at": "2099-01-01T00:00:00Z"
+    });
+    let listing: CsfloatListingStruct = serde_json::from_value(listing).unwrap();
+    let mut csfloat_engine = CsfloatEngine::new();
+    csfloat_engine.hm.insert(listing_id.clone(), listing);
+    let mut config = AppConfig::default();
+    config.strategy.desired_percentile = 60;
+
+    let result = process_updated_csfloat_listing(
+        &mut steam_engine,
+        &mut csfloat_engine,
+        &mut CsfloatScheduler::new(),
+        &Watchlist::new(),
+        &mut ListingFilters::new(),
+        &UpdatedCsfloatListingsEvent {
+            listing_ids: vec![listing_id],
+        },
+        &config,
+    )
+    .await;
+
+    assert_eq!(result.len(), 1);
+    let Event::Secondary(SecEvent::AuctionOpportunity(auction)) = &result[0] else {
+        panic!("Unexpected event {:?}", result[0]);
+    };
+    assert_eq!(auction.next_bid, 5_00);
+    assert!(auction.max_bid > auction.next_bid && auction.max_bid < auction.steam_no_fee);
+}
+
+#[tokio::test]
+async fn test_stale_analysis_is_refreshed_instead_of_used() {
+    let market_name = MarketName::from("Glock-18 | Wasteland Rebel (Minimal Wear)");
+    let listing_id: ListingId = "679718648830624407".into();
+    let mut steam_engine = SteamEngine::new();
+    steam_engine.update(
+        &market_name,
+        AnalysisResult {
+            rsd: Some(0.01),
+            is_stable: Some(true),
+            sold_per_week: Some(500),
+            percentiles: vec![(60, 13_00)],
+            percentiles_no_fee: vec![(60, 11_30)],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: Some(Utc::now() - Duration::days(21)),
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
+        },
+    );
+    let listing = get_listing(&listing_id, &market_name, 1_00);
+    let mut csfloat_engine = CsfloatEngine::new();
+    csfloat_engine.hm.insert(listing_id.clone(), listing);
+    let mut config = AppConfig::default();
+    config.strategy.desired_percentile = 60;
+    let event = UpdatedCsfloatListingsEvent {
+        listing_ids: vec![listing_id],
+    };
+
+    let result = process_updated_csfloat_listing(
+        &mut steam_engine,
+        &mut csfloat_engine,
+        &mut CsfloatScheduler::new(),
+        &Watchlist::new(),
+        &mut ListingFilters::new(),
+        &event,
+        &config,
+    )
+    .await;
+    assert_eq!(
+        result,
+        vec![Event::Primary(PrimEvent::SteamAnalysisRequested(
+            SteamAnalysisRequestedEvent {
+                market_name: market_name.clone(),
+            }
+        ))]
+    );
+    let Event::Primary(PrimEvent::SteamAnalysisRequested(requested)) = &result[0] else {
+        unreachable!();
+    };
+    process_steam_analysis_requested(&mut steam_engine, requested).await;
+    assert!(steam_engine.refresh_requests.contains(&market_name));
+
+    config.steam_analyzer.max_age_secs = 0;
+    let result = process_updated_csfloat_listing(
+        &mut steam_engine,
+        &mut csfloat_engine,
+        &mut CsfloatScheduler::new(),
+        &Watchlist::new(),
+        &mut ListingFilters::new(),
+        &event,
+        &config,
+    )
+    .await;
+    assert_eq!(result.len(), 1);
+}
+
+#[tokio::test]
+async fn test_unpriced_watchlist_listing_is_skipped() {
+    let market_name = MarketName::from("Glock-18 | Wasteland Rebel (Minimal Wear)");
+    let listing_id: ListingId = "679718648830624407".into();
+    let listing = get_listing(&listing_id, &market_name, 1_00);
+    let mut csfloat_engine = CsfloatEngine::new();
+    csfloat_engine.hm.insert(listing_id.clone(), listing);
+    let mut watchlist = Watchlist::new();
+    watchlist.add(WatchRule {
+        id: 1,
+        market_name: "wasteland rebel".to_string(),
+        max_price: 5_00,
+        max_float: None,
+    });
+    let mut config = AppConfig::default();
+    config.strategy.desired_percentile = 60;
+    let event = UpdatedCsfloatListingsEvent {
+        listing_ids: vec![listing_id],
+    };
+    let get_watchlist_events = |result: Vec<Event>| -> Vec<ProfitableListingEvent> {
+        result
+            .into_iter()
+            .filter_map(|x| match x {
+                Event::Secondary(SecEvent::ProfitableListing(e))
+                    if matches!(e.kind, ProfitableListingKind::Watchlist(_)) =>
+                {
+                    Some(e)
+                }
+                _ => None,
+            })
+            .collect()
+    };
+
+    // neither the Steam analysis nor CSFloat predicted price is known
+    let mut steam_engine = SteamEngine::new();
+    let result = process_updated_csfloat_listing(
+        &mut steam_engine,
+        &mut csfloat_engine,
+        &mut CsfloatScheduler::new(),
+        &watchlist,
+        &mut ListingFilters::new(),
+        &event,
+        &config,
+    )
+    .await;
+    assert_eq!(get_watchlist_events(result), vec![]);
+
+    steam_engine.update(
+        &market_name,
+        AnalysisResult {
+            rsd: Some(0.01),
+            is_stable: Some(true),
+            sold_per_week: Some(500),
+            percentiles: vec![(60, 13_00)],
+            percentiles_no_fee: vec![(60, 11_30)],
+            weighted_percentiles: vec![],
+            rejected_outliers: 0,
+            trend: Trend::Flat,
+            analyzed_at: Some(Utc::now()),
+            sell_listings: None,
+            expected_days_to_sell: None,
+            smoothing: Smoothing::Sma,
+            seasonality: None,
+        },
+    );
+    let result = process_updated_csfloat_listing(
+        &mut steam_engine,
+        &mut csfloat_engine,
+        &mut CsfloatScheduler::new(),
+        &watchlist,
+        &mut ListingFilters::new(),
+        &event,
+        &config,
+    )
+    .await;
+    let events = get_watchlist_events(result);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].steam_price, 13_00);
+    assert_eq!(events[0].price_source, PriceSource::Steam);
+    assert_eq!(events[0].sold_per_week, 500);
+}
+
+#[test]
+fn test_build_listing_notification() {
+    let mut event = ProfitableListingEvent {
+        listing_id: "123".into(),
+        float: Some(0.15),
+        seller_id: Some("76561198000000000".to_string()),
+        ..get_profitable_listing_event()
+    };
+    let notification = build_listing_notification(&event, "profitable", "plain".to_string());
+    assert_eq!(notification.text, "plain");
+    let markdown = notification.markdown.unwrap();
+    assert!(markdown.starts_with("*39\\.20%* AK\\-47 \\| Redline \\(Field\\-Tested\\)\n"));
+    assert!(markdown.contains("float: 0\\.15"));
+    assert!(markdown.contains("[CSFloat](https://csfloat.com/item/123)"));
+    let actions: Vec<_> = notification
+        .buttons
+        .iter()
+        .map(|(_, data)| ListingAction::from_callback_data(data).unwrap())
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            ListingAction::Buy("123".into()),
+            ListingAction::Snooze("123".into()),
+            ListingAction::BlacklistSeller("123".into()),
+        ]
+    );
+
+    event.venue = Venue::Skinport;
+    event.seller_id = None;
+    let notification = build_listing_notification(&event, "profitable", "plain".to_string());
+    assert!(!notification.markdown.unwrap().contains("CSFloat"));
+    assert_eq!(
+        notification.buttons,
+        vec![("Snooze item".to_string(), "snooze:123".to_string())]
+    );
 }
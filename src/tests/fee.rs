@@ -1,4 +1,7 @@
-use crate::fee::SteamFee;
+use crate::{
+    config::CsfloatFeeConfig,
+    fee::{CsfloatFee, FeeSchedule, SteamFee, DOTA2_APP_ID, TF2_APP_ID},
+};
 
 #[test]
 fn test_add_fee() {
@@ -20,6 +23,11 @@ fn test_add_fee() {
 
 #[test]
 fn test_subtract_fee() {
+    // nothing is left of totals below the minimum fee
+    assert_eq!(SteamFee::try_subtract_fee(2), None);
+    assert_eq!(SteamFee::subtract_fee(2), 0);
+    assert_eq!(SteamFee::subtract_fee(0), 0);
+    assert_eq!(SteamFee::try_subtract_fee(3), Some(1));
     assert_eq!(SteamFee::subtract_fee(3), 1);
     assert_eq!(SteamFee::subtract_fee(4), 2);
     assert_eq!(SteamFee::subtract_fee(23), 20);
@@ -38,3 +46,49 @@ fn test_subtract_fee() {
     assert_eq!(SteamFee::subtract_fee(14884), 12943);
     assert_eq!(SteamFee::subtract_fee(200000), 173914);
 }
+
+#[test]
+fn test_csfloat_add_fee() {
+    let config = CsfloatFeeConfig::default();
+    assert_eq!(CsfloatFee::add_fee(1, &config), 2);
+    assert_eq!(CsfloatFee::add_fee(10, &config), 11);
+    assert_eq!(CsfloatFee::add_fee(50, &config), 51);
+    assert_eq!(CsfloatFee::add_fee(60, &config), 62);
+    assert_eq!(CsfloatFee::add_fee(500, &config), 514);
+    assert_eq!(CsfloatFee::add_fee(1000, &config), 1028);
+    assert_eq!(CsfloatFee::add_fee(1243, &config), 1278);
+    assert_eq!(CsfloatFee::add_fee(12943, &config), 13305);
+}
+
+#[test]
+fn test_csfloat_fee_disabled() {
+    let config = CsfloatFeeConfig {
+        buyer_fee_pct: 0.0,
+        min_buyer_fee: 1,
+    };
+    assert_eq!(CsfloatFee::get_fee(1000, &config), 0);
+    assert_eq!(CsfloatFee::add_fee(1000, &config), 1000);
+}
+
+#[test]
+fn test_fee_schedule() {
+    assert_eq!(FeeSchedule::for_appid(DOTA2_APP_ID), FeeSchedule::VALVE);
+    assert_eq!(
+        FeeSchedule::for_appid(TF2_APP_ID).add_fee(1243),
+        SteamFee::add_fee(1243)
+    );
+
+    let schedule = FeeSchedule {
+        publisher_pct: 15.0,
+        min_publisher_fee: 2,
+        ..FeeSchedule::VALVE
+    };
+    assert_eq!(schedule.add_fee(1), 4);
+    assert_eq!(schedule.add_fee(100), 120);
+    assert_eq!(schedule.add_fee(1243), 1491);
+    assert_eq!(schedule.try_subtract_fee(3), None);
+    assert_eq!(schedule.subtract_fee(4), 1);
+    assert_eq!(schedule.subtract_fee(120), 100);
+    assert_eq!(schedule.subtract_fee(1490), 1242);
+    assert_eq!(schedule.subtract_fee(1491), 1243);
+}
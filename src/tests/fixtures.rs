@@ -0,0 +1,53 @@
+use crate::{
+    events::{AppliedValue, PriceSource, ProfitableListingEvent, ProfitableListingKind, Venue},
+    models::CsfloatListingStruct,
+    prices::PriceValue,
+    steam_analyzer::Trend,
+};
+
+// Listed buy now listing as CSFloat returns it, tests add the fields they need
+pub(crate) fn get_listing_json(
+    id: &str,
+    market_name: &str,
+    price: PriceValue,
+) -> serde_json::Value {
+    serde_json::json!({
+        "id": id,
+        "created_at": "2024-02-19T15:59:14.443752Z",
+        "type": "buy_now",
+        "price": price,
+        "state": "listed",
+        "item": {"market_hash_name": market_name}
+    })
+}
+
+pub(crate) fn get_listing(id: &str, market_name: &str, price: PriceValue) -> CsfloatListingStruct {
+    serde_json::from_value(get_listing_json(id, market_name, price)).unwrap()
+}
+
+// 10$ CSFloat listing worth 16$ on Steam, tests override the fields they check
+pub(crate) fn get_profitable_listing_event() -> ProfitableListingEvent {
+    ProfitableListingEvent {
+        kind: ProfitableListingKind::Profitable,
+        venue: Venue::Csfloat,
+        market_name: "AK-47 | Redline (Field-Tested)".into(),
+        listing_id: "1".into(),
+        csfloat_price: 10_00,
+        steam_price: 16_00,
+        steam_no_fee: 13_92,
+        price_source: PriceSource::Steam,
+        predicted_price: None,
+        applied_value: AppliedValue::default(),
+        sold_per_week: 500,
+        is_stable: true,
+        trend: Trend::Flat,
+        expected_days_to_sell: None,
+        profit_pct: 39.2,
+        float: None,
+        trade_hold_days: 0,
+        seller_id: None,
+        strategy: None,
+        floor_undercut_pct: None,
+        batch: vec![],
+    }
+}
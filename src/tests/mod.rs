@@ -1,2 +1,5 @@
+mod business_logic;
+mod csfloat_http;
 mod event_processors;
 mod fee;
+pub(crate) mod fixtures;
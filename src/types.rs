@@ -1,2 +1,233 @@
-pub type MarketName = String;
-pub type ListingId = String;
+use std::{
+    borrow::Borrow,
+    collections::HashSet,
+    fmt,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef},
+    Decode, Encode, Postgres, Type,
+};
+
+// Ids are cloned into the engines, the scheduler and most of the events, so they're
+// shared `Arc<str>` instead of `String`. They're (de)serialized as plain strings,
+// so saved snapshots and DB columns stay the same.
+macro_rules! shared_str {
+    ($name:ident) => {
+        #[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(Arc<str>);
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        // HashMaps keyed by it can be looked up by `&str`
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&*self.0, f)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&*self.0, f)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(value: &str) -> Self {
+                $name::new(value)
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(value: String) -> Self {
+                $name::new(&value)
+            }
+        }
+
+        impl From<&String> for $name {
+            fn from(value: &String) -> Self {
+                $name::new(value)
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                &*self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                &*self.0 == *other
+            }
+        }
+
+        impl PartialEq<String> for $name {
+            fn eq(&self, other: &String) -> bool {
+                *self.0 == **other
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+                Ok($name::new(&value))
+            }
+        }
+
+        impl Type<Postgres> for $name {
+            fn type_info() -> PgTypeInfo {
+                <&str as Type<Postgres>>::type_info()
+            }
+
+            fn compatible(ty: &PgTypeInfo) -> bool {
+                <&str as Type<Postgres>>::compatible(ty)
+            }
+        }
+
+        impl PgHasArrayType for $name {
+            fn array_type_info() -> PgTypeInfo {
+                <&str as PgHasArrayType>::array_type_info()
+            }
+        }
+
+        impl<'q> Encode<'q, Postgres> for $name {
+            fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+                <&str as Encode<Postgres>>::encode(&*self.0, buf)
+            }
+        }
+
+        impl<'r> Decode<'r, Postgres> for $name {
+            fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+                Ok($name::new(<&str as Decode<Postgres>>::decode(value)?))
+            }
+        }
+    };
+}
+
+shared_str!(MarketName);
+shared_str!(ListingId);
+
+// There are a few thousand tradable items, but each of them is listed many times
+const MIN_PRUNED_NAMES: usize = 10_000;
+
+// Names only the table holds are dropped once it doubles since the last pruning,
+// e.g. of sources' misspellings or items not listed anymore
+#[derive(Debug)]
+struct NameTable {
+    names: HashSet<Arc<str>>,
+    min_pruned: usize,
+    prune_at: usize,
+}
+
+impl NameTable {
+    fn new(min_pruned: usize) -> Self {
+        NameTable {
+            names: HashSet::new(),
+            min_pruned,
+            prune_at: min_pruned,
+        }
+    }
+
+    fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(name) = self.names.get(value) {
+            return name.clone();
+        }
+        if self.names.len() >= self.prune_at {
+            // the table is locked, so an unused name can't be cloned meanwhile
+            self.names.retain(|x| Arc::strong_count(x) > 1);
+            self.prune_at = (self.names.len() * 2).max(self.min_pruned);
+        }
+        let name: Arc<str> = Arc::from(value);
+        self.names.insert(name.clone());
+        name
+    }
+}
+
+lazy_static! {
+    static ref MARKET_NAMES: Mutex<NameTable> = Mutex::new(NameTable::new(MIN_PRUNED_NAMES));
+}
+
+impl MarketName {
+    // Interned, so all listings of an item share the name
+    pub fn new(value: &str) -> Self {
+        let name = MARKET_NAMES.lock().unwrap().intern(value);
+        MarketName(name)
+    }
+}
+
+impl ListingId {
+    pub fn new(value: &str) -> Self {
+        ListingId(Arc::from(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_shared_str() {
+        let a = MarketName::from("AK-47 | Redline (Field-Tested)");
+        let b = MarketName::from("AK-47 | Redline (Field-Tested)".to_string());
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, "AK-47 | Redline (Field-Tested)");
+
+        let hm = HashMap::from([(ListingId::from("1"), 10)]);
+        assert_eq!(hm.get("1"), Some(&10));
+
+        let text = serde_json::to_string(&vec![ListingId::from("1")]).unwrap();
+        assert_eq!(text, r#"["1"]"#);
+        let parsed: Vec<ListingId> = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed, vec![ListingId::from("1")]);
+    }
+
+    #[test]
+    fn test_name_table_drops_unused_names() {
+        let mut table = NameTable::new(2);
+        let kept = table.intern("A");
+        assert!(Arc::ptr_eq(&kept, &table.intern("A")));
+        table.intern("B");
+        assert_eq!(table.names.len(), 2);
+
+        // "B" isn't held outside of the table anymore
+        table.intern("C");
+        let mut names: Vec<&str> = table.names.iter().map(|x| &**x).collect();
+        names.sort();
+        assert_eq!(names, vec!["A", "C"]);
+        assert_eq!(table.prune_at, 2);
+        assert!(Arc::ptr_eq(&kept, &table.intern("A")));
+    }
+}
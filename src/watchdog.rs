@@ -0,0 +1,344 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+
+use crate::{
+    config::{AppConfig, SharedConfig, WatchdogConfig},
+    notify::{NotificationKind, Notifications},
+    queues::{QueueDepth, QueueSenders},
+    shutdown::ShutdownSignal,
+};
+
+// Tasks whose progress is watched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Component {
+    Importer,
+    // the CSFloat listing refresher, a success or an empty scheduler is a progress
+    Refresher,
+    // dispatchers are watched only while their queue has events
+    PrimaryDispatcher,
+    CsfloatPipeline,
+    SteamPipeline,
+    SecondaryDispatcher,
+    Database,
+}
+
+impl Component {
+    // `QueueDepth::name` of the queue the component reads
+    fn queue_name(&self) -> Option<&'static str> {
+        match self {
+            Component::PrimaryDispatcher => Some("primary"),
+            Component::CsfloatPipeline => Some("csfloat"),
+            Component::SteamPipeline => Some("steam"),
+            Component::SecondaryDispatcher => Some("secondary"),
+            Component::Importer | Component::Refresher | Component::Database => None,
+        }
+    }
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Component::Importer => write!(f, "importer"),
+            Component::Refresher => write!(f, "csfloat refresher"),
+            Component::PrimaryDispatcher => write!(f, "primary dispatcher"),
+            Component::CsfloatPipeline => write!(f, "csfloat pipeline"),
+            Component::SteamPipeline => write!(f, "steam pipeline"),
+            Component::SecondaryDispatcher => write!(f, "secondary dispatcher"),
+            Component::Database => write!(f, "database"),
+        }
+    }
+}
+
+// Time of the last progress of each component, the tasks hold clones
+#[derive(Debug, Clone, Default)]
+pub struct Heartbeats {
+    beats: Arc<Mutex<HashMap<Component, DateTime<Utc>>>>,
+}
+
+impl Heartbeats {
+    pub fn new() -> Self {
+        Heartbeats::default()
+    }
+
+    pub fn beat(&self, component: Component) {
+        self.beat_at(component, Utc::now());
+    }
+
+    pub fn beat_at(&self, component: Component, at: DateTime<Utc>) {
+        self.beats.lock().unwrap().insert(component, at);
+    }
+
+    pub fn get_last(&self, component: Component) -> Option<DateTime<Utc>> {
+        self.beats.lock().unwrap().get(&component).copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stall {
+    pub component: Component,
+    // None when there was no progress since the watchdog started
+    pub last_progress: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.last_progress {
+            Some(at) => write!(f, "{} has made no progress since {}", self.component, at),
+            None => write!(f, "{} has made no progress since the start", self.component),
+        }
+    }
+}
+
+fn get_threshold(component: Component, config: &WatchdogConfig) -> Duration {
+    match component {
+        Component::Importer => config.importer_stall(),
+        Component::Refresher => config.refresher_stall(),
+        Component::PrimaryDispatcher
+        | Component::CsfloatPipeline
+        | Component::SteamPipeline
+        | Component::SecondaryDispatcher => config.dispatcher_stall(),
+        Component::Database => config.db_stall(),
+    }
+}
+
+// Components without a progress for longer than their threshold. `watched` are the running
+// ones, `started_at` stands for the last progress of those which made none yet.
+pub fn find_stalls(
+    heartbeats: &Heartbeats,
+    watched: &[Component],
+    depths: &[QueueDepth],
+    started_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    config: &WatchdogConfig,
+) -> Vec<Stall> {
+    watched
+        .iter()
+        .filter(|component| {
+            // an idle dispatcher waits for events, it isn't stalled
+            component
+                .queue_name()
+                .is_none_or(|name| depths.iter().any(|x| x.name == name && x.queued > 0))
+        })
+        .filter_map(|&component| {
+            let last_progress = heartbeats.get_last(component);
+            let since = last_progress.unwrap_or(started_at).max(started_at);
+            let elapsed = (now - since).to_std().unwrap_or_default();
+            (elapsed > get_threshold(component, config)).then_some(Stall {
+                component,
+                last_progress,
+            })
+        })
+        .collect()
+}
+
+async fn ping_db(pool: &Pool<Postgres>, timeout: Duration) -> Result<(), String> {
+    match tokio::time::timeout(timeout, sqlx::query("SELECT 1").execute(pool)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(format!("no answer in {:?}", timeout)),
+    }
+}
+
+type SpawnTask = Box<dyn Fn() -> JoinHandle<()> + Send + Sync>;
+
+// A task the watchdog can restart, it's spawned again by `spawn`
+struct SupervisedTask {
+    component: Component,
+    spawn: SpawnTask,
+    handle: JoinHandle<()>,
+}
+
+// Alerts once when a component stalls and once when it recovers. With
+// `watchdog.restart_stalled` the supervised tasks are aborted and spawned again,
+// the dispatchers own their queues, so they are only alerted about.
+pub struct Watchdog {
+    heartbeats: Heartbeats,
+    watched: Vec<Component>,
+    tasks: Vec<SupervisedTask>,
+}
+
+impl Watchdog {
+    pub fn new(heartbeats: Heartbeats, watched: Vec<Component>) -> Self {
+        Watchdog {
+            heartbeats,
+            watched,
+            tasks: vec![],
+        }
+    }
+
+    // The task is spawned right away
+    pub fn supervise(
+        &mut self,
+        component: Component,
+        spawn: impl Fn() -> JoinHandle<()> + Send + Sync + 'static,
+    ) {
+        let handle = spawn();
+        self.watched.push(component);
+        self.tasks.push(SupervisedTask {
+            component,
+            spawn: Box::new(spawn),
+            handle,
+        });
+    }
+
+    fn restart(&mut self, component: Component) -> bool {
+        let Some(task) = self.tasks.iter_mut().find(|x| x.component == component) else {
+            return false;
+        };
+        task.handle.abort();
+        task.handle = (task.spawn)();
+        // the new task gets the full threshold
+        self.heartbeats.beat(component);
+        true
+    }
+
+    // Runs until the shutdown, then waits for the supervised tasks to stop
+    pub fn spawn(
+        mut self,
+        notifications: Notifications,
+        pool: Pool<Postgres>,
+        queues: QueueSenders,
+        config: SharedConfig,
+        mut shutdown: ShutdownSignal,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let started_at = Utc::now();
+            let mut stalled: HashSet<Component> = HashSet::new();
+            loop {
+                let check_interval = config.load().watchdog.check_interval();
+                tokio::select! {
+                    _ = tokio::time::sleep(check_interval) => {}
+                    _ = shutdown.changed() => break,
+                }
+
+                let current_config = config.load();
+                let watchdog_config = &current_config.watchdog;
+                if !watchdog_config.enabled {
+                    continue;
+                }
+                match ping_db(&pool, watchdog_config.db_timeout()).await {
+                    Ok(()) => self.heartbeats.beat(Component::Database),
+                    Err(err) => warn!("Database is unreachable: {}", err),
+                }
+
+                let stalls = find_stalls(
+                    &self.heartbeats,
+                    &self.watched,
+                    &queues.get_depths(),
+                    started_at,
+                    Utc::now(),
+                    watchdog_config,
+                );
+                self.report(&stalls, &mut stalled, &notifications, &current_config);
+            }
+
+            for task in self.tasks {
+                if let Err(err) = task.handle.await {
+                    if !err.is_cancelled() {
+                        error!("{} failed during shutdown: {:?}", task.component, err);
+                    }
+                }
+            }
+        })
+    }
+
+    fn report(
+        &mut self,
+        stalls: &[Stall],
+        stalled: &mut HashSet<Component>,
+        notifications: &Notifications,
+        config: &AppConfig,
+    ) {
+        for stall in stalls {
+            let is_new = stalled.insert(stall.component);
+            // a finished task, e.g. after a panic, is restarted even if the stall isn't new
+            let is_restarted = config.watchdog.restart_stalled && self.restart(stall.component);
+            if !is_new && !is_restarted {
+                continue;
+            }
+            let mut text = format!("Watchdog: {}", stall);
+            if is_restarted {
+                text.push_str(", restarted");
+            }
+            warn!("{}", text);
+            notifications.notify(NotificationKind::Alert, text, config);
+        }
+        let recovered: Vec<Component> = stalled
+            .iter()
+            .filter(|x| !stalls.iter().any(|stall| stall.component == **x))
+            .copied()
+            .collect();
+        for component in recovered {
+            stalled.remove(&component);
+            let text = format!("Watchdog: {} has recovered", component);
+            warn!("{}", text);
+            notifications.notify(NotificationKind::Alert, text, config);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_find_stalls() {
+        let config = WatchdogConfig::default();
+        let started_at = Utc::now();
+        let heartbeats = Heartbeats::new();
+        let watched = [Component::Importer, Component::SteamPipeline];
+        let depth = |queued| {
+            vec![QueueDepth {
+                name: "steam",
+                queued,
+                capacity: 100,
+            }]
+        };
+        let stalled = |stalls: Vec<Stall>| -> Vec<Component> {
+            stalls.into_iter().map(|x| x.component).collect()
+        };
+
+        // nothing is expected right after the start
+        let now = started_at + Duration::seconds(10);
+        assert!(find_stalls(&heartbeats, &watched, &depth(5), started_at, now, &config).is_empty());
+
+        let now = started_at + Duration::hours(1);
+        assert_eq!(
+            stalled(find_stalls(
+                &heartbeats,
+                &watched,
+                &depth(5),
+                started_at,
+                now,
+                &config
+            )),
+            vec![Component::Importer, Component::SteamPipeline]
+        );
+        // an idle pipeline isn't stalled
+        assert_eq!(
+            stalled(find_stalls(
+                &heartbeats,
+                &watched,
+                &depth(0),
+                started_at,
+                now,
+                &config
+            )),
+            vec![Component::Importer]
+        );
+
+        heartbeats.beat_at(Component::Importer, now - Duration::seconds(10));
+        heartbeats.beat_at(Component::SteamPipeline, now - Duration::seconds(10));
+        assert!(find_stalls(&heartbeats, &watched, &depth(5), started_at, now, &config).is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+use std::fmt::Write;
+
+use sqlx::{Pool, Postgres, Row};
+
+use crate::{
+    models::CsfloatListingStruct,
+    prices::{PriceValue, PriceValueTrait},
+};
+
+// User-defined notification rule, e.g. any "AK-47 | Redline (Field-Tested)" under $20 with float < 0.20
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchRule {
+    pub id: i64,
+    // part of the market name, case-insensitive
+    pub market_name: String,
+    pub max_price: PriceValue,
+    pub max_float: Option<f64>,
+}
+
+impl WatchRule {
+    pub fn is_matching(&self, listing: &CsfloatListingStruct) -> bool {
+        if listing.get_price_value() > self.max_price {
+            return false;
+        }
+        if let Some(max_float) = self.max_float {
+            match listing.item.float_value {
+                Some(float) if float < max_float => {}
+                _ => return false,
+            }
+        }
+        listing
+            .item
+            .market_hash_name
+            .to_lowercase()
+            .contains(&self.market_name.to_lowercase())
+    }
+}
+
+// Rules are edited via Telegram and kept in the watch_rules table
+#[derive(Debug, Default)]
+pub struct Watchlist {
+    rules: Vec<WatchRule>,
+}
+
+impl Watchlist {
+    pub fn new() -> Self {
+        Watchlist { rules: vec![] }
+    }
+
+    pub fn add(&mut self, rule: WatchRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn remove(&mut self, id: i64) -> bool {
+        let size = self.rules.len();
+        self.rules.retain(|x| x.id != id);
+        self.rules.len() != size
+    }
+
+    pub fn find_matching(&self, listing: &CsfloatListingStruct) -> Option<&WatchRule> {
+        self.rules.iter().find(|x| x.is_matching(listing))
+    }
+
+    pub fn summary(&self) -> String {
+        if self.rules.is_empty() {
+            return "Watchlist is empty".to_string();
+        }
+
+        let mut text = String::from("Watchlist:");
+        for rule in self.rules.iter() {
+            write!(
+                text,
+                "\n#{} {} under ${}",
+                rule.id,
+                rule.market_name,
+                rule.max_price.to_usd()
+            )
+            .unwrap();
+            if let Some(max_float) = rule.max_float {
+                write!(text, " with float < {}", max_float).unwrap();
+            }
+        }
+        text
+    }
+}
+
+// "20 0.2 AK-47 | Redline (Field-Tested)" -> under $20 with float < 0.2, the float is optional.
+// Returns a rule without id.
+pub fn parse_watch_args(args: &str) -> Result<WatchRule, String> {
+    const USAGE: &str = "Usage: /watch <max_price_usd> [max_float] <market name>";
+
+    let (price, rest) = args.trim().split_once(' ').ok_or(USAGE)?;
+    let price = price
+        .trim_start_matches('$')
+        .parse::<f64>()
+        .map_err(|_| USAGE)?;
+    let rest = rest.trim();
+
+    let (max_float, market_name) = match rest.split_once(' ') {
+        Some((first, name)) => match first.parse::<f64>() {
+            Ok(float) => (Some(float), name.trim()),
+            Err(_) => (None, rest),
+        },
+        None => (None, rest),
+    };
+    if let Some(float) = max_float {
+        if !(0.0..=1.0).contains(&float) {
+            return Err("Float should be between 0 and 1".to_string());
+        }
+    }
+    Ok(WatchRule {
+        id: 0,
+        market_name: market_name.to_string(),
+        max_price: (price * 100.0).round() as PriceValue,
+        max_float,
+    })
+}
+
+pub async fn load_watch_rules(db: &Pool<Postgres>) -> Result<Watchlist, sqlx::Error> {
+    let rows =
+        sqlx::query("SELECT id, market_name, max_price, max_float FROM watch_rules ORDER BY id")
+            .fetch_all(db)
+            .await?;
+
+    let rules = rows
+        .into_iter()
+        .map(|row| WatchRule {
+            id: row.get("id"),
+            market_name: row.get("market_name"),
+            max_price: row.get::<i64, _>("max_price") as PriceValue,
+            max_float: row.get("max_float"),
+        })
+        .collect();
+    Ok(Watchlist { rules })
+}
+
+// Saves the rule and returns it with the assigned id
+pub async fn insert_watch_rule(
+    db: &Pool<Postgres>,
+    rule: &WatchRule,
+) -> Result<WatchRule, sqlx::Error> {
+    let row = sqlx::query(
+        "INSERT INTO watch_rules (market_name, max_price, max_float) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(&rule.market_name)
+    .bind(rule.max_price as i64)
+    .bind(rule.max_float)
+    .fetch_one(db)
+    .await?;
+    Ok(WatchRule {
+        id: row.get("id"),
+        ..rule.clone()
+    })
+}
+
+pub async fn delete_watch_rule(db: &Pool<Postgres>, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM watch_rules WHERE id = $1")
+        .bind(id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+
+    fn get_listing(price: u64, float: Option<f64>) -> CsfloatListingStruct {
+        let mut listing =
+            fixtures::get_listing("1", "StatTrak™ AK-47 | Redline (Field-Tested)", price);
+        listing.item.float_value = float;
+        listing
+    }
+
+    #[test]
+    fn test_parse_watch_args() {
+        let rule = parse_watch_args("20 0.2 AK-47 | Redline (Field-Tested)").unwrap();
+        assert_eq!(rule.max_price, 20_00);
+        assert_eq!(rule.max_float, Some(0.2));
+        assert_eq!(rule.market_name, "AK-47 | Redline (Field-Tested)");
+
+        let rule = parse_watch_args("$5.5 Kilowatt Case").unwrap();
+        assert_eq!((rule.max_price, rule.max_float), (5_50, None));
+        assert_eq!(rule.market_name, "Kilowatt Case");
+
+        assert!(parse_watch_args("AK-47 | Redline").is_err());
+        assert!(parse_watch_args("20 1.5 AK-47 | Redline").is_err());
+    }
+
+    #[test]
+    fn test_rule_matching() {
+        let mut rule = parse_watch_args("20 0.2 ak-47 | redline").unwrap();
+        rule.id = 1;
+        let mut watchlist = Watchlist::new();
+        watchlist.add(rule);
+
+        assert!(watchlist
+            .find_matching(&get_listing(19_00, Some(0.15)))
+            .is_some());
+        assert!(watchlist
+            .find_matching(&get_listing(21_00, Some(0.15)))
+            .is_none());
+        assert!(watchlist
+            .find_matching(&get_listing(19_00, Some(0.25)))
+            .is_none());
+        assert!(watchlist.find_matching(&get_listing(19_00, None)).is_none());
+
+        assert!(watchlist.remove(1));
+        assert!(!watchlist.remove(1));
+    }
+}
@@ -0,0 +1,29 @@
+// The analyzer and fees used from outside of the bot, as other tools would
+use chrono::{TimeZone, Utc};
+
+use steam_csfloat_rust::{
+    config::AppConfig,
+    fee::{FeeSchedule, SteamFee, CS2_APP_ID},
+    steam_analyzer::analyze_steam_sell_history,
+};
+
+#[test]
+fn test_analyze_steam_sell_history() {
+    let response = std::fs::read_to_string("src/test_data/Kilowatt Case.html").unwrap();
+    let now = Utc.with_ymd_and_hms(2024, 2, 19, 0, 0, 0).unwrap();
+    let config = AppConfig::default();
+
+    let analysis = analyze_steam_sell_history(&response, now, 1.0, &config.steam_analyzer).unwrap();
+    assert_eq!(analysis.sold_per_week, Some(604_240));
+    assert_eq!(analysis.is_stable, Some(false));
+
+    let price = analysis
+        .get_price_by_percentile(config.strategy.desired_percentile)
+        .unwrap();
+    let no_fee = SteamFee::subtract_fee(price);
+    assert!(no_fee < price);
+    assert_eq!(
+        no_fee,
+        FeeSchedule::for_appid(CS2_APP_ID).subtract_fee(price)
+    );
+}